@@ -14,12 +14,15 @@
 // limitations under the License.
 
 use clap::Parser;
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use dynamo_llm::{
+    auth::ApiKeys,
     http::service::{
         discovery::{model_watcher, ModelWatchState},
         service_v2::HttpService,
+        tls::TlsConfig,
     },
     model_type::ModelType,
 };
@@ -45,6 +48,24 @@ struct Args {
     /// Component name for the service
     #[arg(long, default_value = "http")]
     component: String,
+
+    /// JSON file of the API keys clients must send. Defaults to the `DYN_API_KEYS_FILE` and
+    /// `DYN_API_KEYS` environment variables, no authentication if neither is set.
+    #[arg(long)]
+    api_keys: Option<PathBuf>,
+
+    /// PEM certificate chain to serve HTTPS with
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM private key of --tls-cert
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// PEM certificates of the CAs that sign client certificates, which clients must then
+    /// present
+    #[arg(long, requires = "tls_cert")]
+    tls_client_ca: Option<PathBuf>,
 }
 
 fn main() -> Result<()> {
@@ -57,9 +78,23 @@ async fn app(runtime: Runtime) -> Result<()> {
     let distributed = DistributedRuntime::from_settings(runtime.clone()).await?;
     let args = Args::parse();
 
+    let api_keys = match &args.api_keys {
+        Some(path) => Some(ApiKeys::from_file(path)?),
+        None => ApiKeys::from_env()?,
+    };
+
+    let tls = args.tls_cert.map(|cert| TlsConfig {
+        cert,
+        key: args.tls_key.unwrap_or_default(),
+        client_ca: args.tls_client_ca,
+    });
+
     let http_service = HttpService::builder()
         .port(args.port)
         .host(args.host)
+        .api_keys(api_keys.map(Arc::new))
+        .tls(tls)
+        .drain(Some(runtime.drain()))
         .build()?;
     let manager = http_service.model_manager().clone();
 
@@ -79,15 +114,20 @@ async fn app(runtime: Runtime) -> Result<()> {
     // TODO: A single watcher already watches all model types and does the right thing.
     // The paths need change here and in llmctl to not include the model_type
 
-    // Create watchers for `Chat` and `Completion` model types
-    for model_type in [ModelType::Chat, ModelType::Completion] {
+    // Create watchers for `Chat`, `Completion`, `Embedding` and `Transcription` model types
+    for model_type in [
+        ModelType::Chat,
+        ModelType::Completion,
+        ModelType::Embedding,
+        ModelType::Transcription,
+    ] {
         let etcd_path = format!("{}/models/{}/", etcd_root, model_type.as_str());
 
-        let state = Arc::new(ModelWatchState {
-            prefix: etcd_path.clone(),
-            manager: manager.clone(),
-            drt: distributed.clone(),
-        });
+        let state = Arc::new(ModelWatchState::new(
+            &etcd_path,
+            manager.clone(),
+            distributed.clone(),
+        ));
 
         if let Some(etcd_client) = distributed.etcd_client() {
             let models_watcher: PrefixWatcher =
@@ -0,0 +1,439 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Files of dynamo-run arguments, for `--config <file>` and `dynamo-run lint`.
+//!
+//! A file is JSON, TOML or YAML, told apart by its extension. It holds either the arguments as
+//! they would be on the command line:
+//!
+//! ```json
+//! ["in=http", "out=vllm", "--model-path", "/models/Qwen2.5-3B-Instruct"]
+//! ```
+//!
+//! or `in`, `out` and flags, named with or without the leading `--`:
+//!
+//! ```toml
+//! in = "http"
+//! out = "vllm"
+//! model-path = "/models/Qwen2.5-3B-Instruct"
+//! http-port = 8000
+//! api-keys = "${KEYS_DIR}/keys.json"
+//! ```
+//!
+//! `${VAR}` in a value is replaced by the environment variable, `${VAR:-default}` falls back
+//! to `default` when it is unset or empty, and `$$` is a `$`.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context as _;
+use clap::{CommandFactory, Parser as _};
+
+use crate::{router, Flags};
+
+const CONFIG_FLAG: &str = "config";
+
+/// The command line with the arguments of its `--config` file merged in
+pub struct Merged {
+    pub args: Vec<String>,
+
+    /// The file, and the flags (`--http-port`, `in`, ...) the arguments got from it
+    pub file: Option<(PathBuf, Vec<String>)>,
+}
+
+/// Merge the arguments of the `--config` file in `args`, if there is one. The command line wins:
+/// a flag set there replaces all of the file's values for it, as do `in=`, `out=`, a model path
+/// and the arguments after `--`.
+pub fn merge(args: Vec<String>) -> anyhow::Result<Merged> {
+    let command = Flags::command();
+    let cli = Parts::split(&args, &command);
+    let Some(path) = cli.config_path() else {
+        return Ok(Merged { args, file: None });
+    };
+    let mut file_args = load_args(&path)?;
+    if file_args.first().is_some_and(|arg| arg == "router") {
+        file_args = router::expand(&file_args)?;
+    }
+    let file = Parts::split(&file_args, &command);
+
+    let mut merged = vec![];
+    let mut from_file = vec![];
+    // in= and out= must come first
+    for key in ["in", "out"] {
+        let prefix = format!("{key}=");
+        if let Some(arg) = cli.in_out.iter().find(|arg| arg.starts_with(&prefix)) {
+            merged.push(arg.clone());
+        } else if let Some(arg) = file.in_out.iter().find(|arg| arg.starts_with(&prefix)) {
+            merged.push(arg.clone());
+            from_file.push(key.to_string());
+        }
+    }
+    if cli.positional.is_empty() {
+        merged.extend(file.positional);
+    } else {
+        merged.extend(cli.positional);
+    }
+    for (name, written) in file.flags {
+        if !cli.flags.iter().any(|(cli_name, _)| *cli_name == name) {
+            merged.extend(written);
+            from_file.push(name);
+        }
+    }
+    for (_, written) in cli.flags {
+        merged.extend(written);
+    }
+    if let Some(last) = cli.last.or(file.last) {
+        merged.push("--".to_string());
+        merged.extend(last);
+    }
+    Ok(Merged {
+        args: merged,
+        file: Some((path, from_file)),
+    })
+}
+
+/// This process's flags parsed again, with its `--config` file as it is now. `in=http` applies
+/// the ones it can change while running when it gets a SIGHUP.
+pub fn reread_flags() -> anyhow::Result<Flags> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args = match args.first().map(String::as_str) {
+        Some("router") => router::expand(&args)?,
+        _ => args,
+    };
+    let flag_args = merge(args)?
+        .args
+        .into_iter()
+        .enumerate()
+        .filter(|(i, arg)| *i >= 2 || !(arg.starts_with("in=") || arg.starts_with("out=")))
+        .map(|(_, arg)| arg);
+    Ok(Flags::try_parse_from(
+        std::iter::once("dynamo-run".to_string()).chain(flag_args),
+    )?)
+}
+
+/// Arguments sorted out so the command line's can replace the file's
+#[derive(Default, Debug)]
+struct Parts {
+    in_out: Vec<String>,
+    positional: Vec<String>,
+    /// The flag as `--long` or `-v`, and the arguments it was written as
+    flags: Vec<(String, Vec<String>)>,
+    /// After `--`
+    last: Option<Vec<String>>,
+}
+
+impl Parts {
+    fn split(args: &[String], command: &clap::Command) -> Parts {
+        let mut parts = Parts::default();
+        let mut args = args.iter().cloned();
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                parts.last = Some(args.by_ref().collect());
+                break;
+            }
+            if arg.starts_with("in=") || arg.starts_with("out=") {
+                parts.in_out.push(arg);
+            } else if let Some(flag) = arg.strip_prefix("--") {
+                let (long, inline_value) = match flag.split_once('=') {
+                    Some((long, _)) => (long, true),
+                    None => (flag, false),
+                };
+                let takes_value = command
+                    .get_arguments()
+                    .find(|a| a.get_long() == Some(long))
+                    .is_some_and(|a| a.get_action().takes_values());
+                let name = format!("--{long}");
+                let mut written = vec![arg.clone()];
+                if takes_value && !inline_value {
+                    written.extend(args.next());
+                }
+                parts.flags.push((name, written));
+            } else if arg.starts_with('-') && arg.len() > 1 {
+                parts.flags.push((arg.clone(), vec![arg]));
+            } else {
+                parts.positional.push(arg);
+            }
+        }
+        parts
+    }
+
+    fn config_path(&self) -> Option<PathBuf> {
+        let flag = format!("--{CONFIG_FLAG}");
+        let (_, written) = self.flags.iter().rev().find(|(name, _)| *name == flag)?;
+        let value = match written.as_slice() {
+            [arg] => arg.split_once('=')?.1,
+            [_, value] => value.as_str(),
+            _ => return None,
+        };
+        Some(PathBuf::from(value))
+    }
+}
+
+/// The arguments in the file, as they would be on the command line
+pub fn load_args(path: &Path) -> anyhow::Result<Vec<String>> {
+    match load_value(path)? {
+        serde_json::Value::Array(items) => Ok(items.into_iter().map(arg_string).collect()),
+        serde_json::Value::Object(map) => {
+            let mut in_out = vec![];
+            let mut flags = vec![];
+            let mut last = vec![];
+            for (key, value) in map {
+                let name = key.trim_start_matches('-').replace('_', "-");
+                match name.as_str() {
+                    "in" | "out" => in_out.push(format!("{name}={}", arg_string(value))),
+                    // Everything after `--`
+                    "" => match value {
+                        serde_json::Value::Array(items) => {
+                            last.extend(items.into_iter().map(arg_string))
+                        }
+                        other => last.push(arg_string(other)),
+                    },
+                    _ => push_flag(&mut flags, &key, value)?,
+                }
+            }
+            // in= and out= must come first
+            in_out.sort();
+            let mut args = in_out;
+            args.extend(flags);
+            if !last.is_empty() {
+                args.push("--".to_string());
+                args.extend(last);
+            }
+            Ok(args)
+        }
+        _ => anyhow::bail!(
+            "{}: expected an array of arguments or an object of flags",
+            path.display()
+        ),
+    }
+}
+
+/// The JSON, TOML or YAML of the file, with environment variables interpolated
+pub fn load_value(path: &Path) -> anyhow::Result<serde_json::Value> {
+    let content =
+        std::fs::read_to_string(path).with_context(|| format!("Reading {}", path.display()))?;
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase());
+    let config: serde_json::Value = match extension.as_deref() {
+        Some("toml") => toml::from_str(&content)
+            .with_context(|| format!("{} is not valid TOML", path.display()))?,
+        Some("yaml" | "yml") => serde_yaml::from_str(&content)
+            .with_context(|| format!("{} is not valid YAML", path.display()))?,
+        _ => serde_json::from_str(&content)
+            .with_context(|| format!("{} is not valid JSON", path.display()))?,
+    };
+    interpolate_value(config).with_context(|| path.display().to_string())
+}
+
+/// Add the flag `key` with `value` to `args`: `--key value` for each value of a list, `--key`
+/// for true, nothing for false or null
+pub fn push_flag(
+    args: &mut Vec<String>,
+    key: &str,
+    value: serde_json::Value,
+) -> anyhow::Result<()> {
+    let name = key.trim_start_matches('-').replace('_', "-");
+    let values = match value {
+        serde_json::Value::Array(items) => items,
+        other => vec![other],
+    };
+    for value in values {
+        match value {
+            serde_json::Value::Null | serde_json::Value::Bool(false) => {}
+            serde_json::Value::Bool(true) => args.push(format!("--{name}")),
+            serde_json::Value::Object(_) => {
+                anyhow::bail!("{key}: expected a string, number or boolean")
+            }
+            other => {
+                args.push(format!("--{name}"));
+                args.push(arg_string(other));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn arg_string(value: serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s,
+        other => other.to_string(),
+    }
+}
+
+/// Environment variables in the strings of `value`
+fn interpolate_value(value: serde_json::Value) -> anyhow::Result<serde_json::Value> {
+    Ok(match value {
+        serde_json::Value::String(s) => serde_json::Value::String(interpolate(&s)?),
+        serde_json::Value::Array(items) => serde_json::Value::Array(
+            items
+                .into_iter()
+                .map(interpolate_value)
+                .collect::<anyhow::Result<_>>()?,
+        ),
+        serde_json::Value::Object(map) => serde_json::Value::Object(
+            map.into_iter()
+                .map(|(key, value)| Ok((key, interpolate_value(value)?)))
+                .collect::<anyhow::Result<_>>()?,
+        ),
+        other => other,
+    })
+}
+
+fn interpolate(s: &str) -> anyhow::Result<String> {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('$') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        if let Some(after) = after.strip_prefix('$') {
+            out.push('$');
+            rest = after;
+            continue;
+        }
+        let Some(body) = after.strip_prefix('{') else {
+            out.push('$');
+            rest = after;
+            continue;
+        };
+        let end = body
+            .find('}')
+            .with_context(|| format!("'{s}': ${{ without a closing }}"))?;
+        let (name, default) = match body[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&body[..end], None),
+        };
+        match (std::env::var(name), default) {
+            (Ok(value), Some(default)) if value.is_empty() => out.push_str(default),
+            (Ok(value), _) => out.push_str(&value),
+            (Err(_), Some(default)) => out.push_str(default),
+            (Err(_), None) => anyhow::bail!("${{{name}}}: environment variable not set"),
+        }
+        rest = &body[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_load_args() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        std::fs::write(
+            &path,
+            r#"{"out": "echo_full", "--http-port": 8000, "in": "http", "tls_cert": "c.pem",
+                "--": ["--foo"]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            load_args(&path).unwrap(),
+            vec![
+                "in=http",
+                "out=echo_full",
+                "--http-port",
+                "8000",
+                "--tls-cert",
+                "c.pem",
+                "--",
+                "--foo"
+            ]
+        );
+
+        std::fs::write(&path, r#"["in=http", "out=echo_full"]"#).unwrap();
+        assert_eq!(load_args(&path).unwrap(), vec!["in=http", "out=echo_full"]);
+
+        std::fs::write(&path, "in=http").unwrap();
+        assert!(load_args(&path).is_err());
+
+        let path = dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            "in = \"http\"\nhttp-port = 8000\nmodel-name = \"${DYN_TEST_UNSET_VAR:-qwen}\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            load_args(&path).unwrap(),
+            vec!["in=http", "--http-port", "8000", "--model-name", "qwen"]
+        );
+
+        let path = dir.path().join("config.yaml");
+        std::fs::write(&path, "out: echo_full\nrouter-mode: round-robin\n").unwrap();
+        assert_eq!(
+            load_args(&path).unwrap(),
+            vec!["out=echo_full", "--router-mode", "round-robin"]
+        );
+    }
+
+    #[test]
+    fn test_interpolate() {
+        std::env::set_var("DYN_TEST_CONFIG_VAR", "8000");
+        assert_eq!(interpolate("${DYN_TEST_CONFIG_VAR}").unwrap(), "8000");
+        assert_eq!(interpolate("$$5 and $HOME").unwrap(), "$5 and $HOME");
+        assert_eq!(interpolate("${DYN_TEST_UNSET_VAR:-a}/b").unwrap(), "a/b");
+        assert!(interpolate("${DYN_TEST_UNSET_VAR}").is_err());
+        assert!(interpolate("${DYN_TEST_CONFIG_VAR").is_err());
+    }
+
+    #[test]
+    fn test_merge() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dynamo.toml");
+        std::fs::write(
+            &path,
+            "in = \"http\"\nout = \"echo_full\"\nhttp-port = 8000\nstop = [\"a\", \"b\"]\n\
+             router-mode = \"random\"\n",
+        )
+        .unwrap();
+        let config = path.display().to_string();
+
+        let merged = merge(args(&[
+            "out=echo_core",
+            "--config",
+            &config,
+            "--http-port=9000",
+            "--stop",
+            "c",
+        ]))
+        .unwrap();
+        assert_eq!(
+            merged.args,
+            args(&[
+                "in=http",
+                "out=echo_core",
+                "--router-mode",
+                "random",
+                "--config",
+                &config,
+                "--http-port=9000",
+                "--stop",
+                "c",
+            ])
+        );
+        let (file, from_file) = merged.file.unwrap();
+        assert_eq!(file, path);
+        assert_eq!(from_file, args(&["in", "--router-mode"]));
+
+        let merged = merge(args(&["in=text", "--http-port", "8000"])).unwrap();
+        assert_eq!(merged.args, args(&["in=text", "--http-port", "8000"]));
+        assert!(merged.file.is_none());
+    }
+}
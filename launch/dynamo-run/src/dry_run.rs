@@ -0,0 +1,281 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `dynamo-run ... --dry-run`
+//!
+//! Goes as far as dynamo-run can without loading a model: the options as `lint` checks them,
+//! the model's config and tokenizer, downloaded from Hugging Face if needed but without the
+//! weights, what the engine needs at runtime, and etcd and NATS when the run uses them. Prints
+//! what would run, and fails if anything would stop it from starting.
+
+use std::time::Duration;
+
+use clap::Parser as _;
+use dynamo_llm::LocalModel;
+use dynamo_runtime::{DistributedRuntime, Runtime};
+
+use crate::probe::{self, NVIDIA_DRIVER};
+use crate::{lint, multi, Flags, Input, Output};
+
+/// How long to wait for etcd and NATS
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Default)]
+struct Checks {
+    problems: usize,
+}
+
+impl Checks {
+    fn ok(&self, message: impl AsRef<str>) {
+        println!("ok: {}", message.as_ref());
+    }
+
+    fn problem(&mut self, message: impl AsRef<str>) {
+        println!("error: {}", message.as_ref());
+        self.problems += 1;
+    }
+}
+
+/// Entry point for `--dry-run`. `args` are those of the run, without the binary name.
+pub async fn run(runtime: Runtime, args: &[String]) -> anyhow::Result<()> {
+    let mut checks = Checks::default();
+
+    let (errors, warnings) = lint::check(args);
+    for warning in &warnings {
+        println!("warning: {warning}");
+    }
+    for error in &errors {
+        checks.problem(error);
+    }
+    // Without options that parse there is nothing more to check
+    if errors.is_empty() {
+        let (in_opt, out_opt, flags) = parse(args)?;
+        // As the user wrote them, Display drops the dyn:// of an endpoint
+        let given = |prefix: &str| args.iter().take(2).find(|arg| arg.starts_with(prefix));
+        match (given("in="), given("out=")) {
+            (Some(in_arg), Some(out_arg)) => println!("plan: {in_arg} {out_arg}"),
+            (Some(in_arg), None) => println!("plan: {in_arg} out={out_opt}"),
+            (None, Some(out_arg)) => println!("plan: in={in_opt} {out_arg}"),
+            (None, None) => println!("plan: in={in_opt} out={out_opt}"),
+        }
+        resolve_model(&flags, &out_opt, &mut checks).await;
+        for out_opt in engines(&out_opt) {
+            check_engine(&out_opt, &mut checks).await;
+        }
+        if let Input::Arena(other) | Input::BenchCompare(other) = &in_opt {
+            check_engine(&Output::try_from(other.as_str())?, &mut checks).await;
+        }
+        if uses_network(&in_opt, &out_opt) {
+            check_network(runtime, &mut checks).await;
+        }
+    }
+
+    if checks.problems > 0 {
+        anyhow::bail!("dry run found {} problems", checks.problems);
+    }
+    println!("dry run passed");
+    Ok(())
+}
+
+/// in= and out= as main reads them, and the flags after them
+fn parse(args: &[String]) -> anyhow::Result<(Input, Output, Flags)> {
+    let mut in_opt = None;
+    let mut out_opt = None;
+    let mut skip = 0;
+    for arg in args.iter().take(2) {
+        match arg.split_once('=') {
+            Some(("in", value)) => in_opt = Some(Input::try_from(value)?),
+            Some(("out", value)) => out_opt = Some(Output::try_from(value)?),
+            _ => continue,
+        }
+        skip += 1;
+    }
+    let flags = Flags::try_parse_from(
+        ["dynamo-run".to_string()]
+            .into_iter()
+            .chain(args.iter().skip(skip).cloned()),
+    )?;
+    Ok((
+        in_opt.unwrap_or_default(),
+        out_opt.unwrap_or_default(),
+        flags,
+    ))
+}
+
+/// Load the model card the way the run would, without the weights
+async fn resolve_model(flags: &Flags, out_opt: &Output, checks: &mut Checks) {
+    if matches!(out_opt, Output::Endpoint(_)) {
+        println!("plan: model from the workers of dyn://{out_opt}");
+        return;
+    }
+    if let Output::Multi(path) = out_opt {
+        // lint has already checked the file
+        for spec in multi::load(path).unwrap_or_default() {
+            match &spec.fallback {
+                Some(fallback) => println!(
+                    "plan: model {} on out={}, falling back to {fallback}",
+                    spec.model, spec.out
+                ),
+                None => println!("plan: model {} on out={}", spec.model, spec.out),
+            }
+        }
+        return;
+    }
+    let Some(model_path) = flags.model_paths().into_iter().next() else {
+        // lint has already rejected engines that need one
+        println!("plan: no model");
+        return;
+    };
+    let Some(model_path) = model_path.to_str() else {
+        checks.problem("Invalid UTF-8 in model path");
+        return;
+    };
+    let mut model = match LocalModel::inspect(
+        model_path,
+        flags.model_config.as_deref(),
+        flags.model_name.clone(),
+        flags.tokenizer_path.as_deref(),
+    )
+    .await
+    {
+        Ok(model) => model,
+        Err(err) => {
+            checks.problem(format!("model {model_path}: {err:#}"));
+            return;
+        }
+    };
+    if let Some(chat_template) = &flags.chat_template {
+        if let Err(err) = model.set_chat_template(chat_template) {
+            checks.problem(format!("--chat-template: {err:#}"));
+        }
+    }
+    if let Some(context_length) = flags.context_length {
+        model.set_context_length(context_length);
+    }
+    let card = model.card();
+    let info = match &card.model_info {
+        Some(info) => info.get_model_info().await.ok(),
+        None => None,
+    };
+    let details = match info {
+        Some(info) => format!(
+            "{}, context length {}",
+            info.model_type(),
+            card.context_length
+                .map_or(info.max_position_embeddings(), |n| n as usize)
+        ),
+        None => "no model config".to_string(),
+    };
+    let tokenizer = if card.has_tokenizer() {
+        "tokenizer"
+    } else {
+        "no tokenizer"
+    };
+    let chat_template = if card.has_chat_template() {
+        "chat template"
+    } else {
+        "no chat template"
+    };
+    println!(
+        "plan: model {} from {}, {details}, {tokenizer}, {chat_template}",
+        model.display_name(),
+        model.path().display()
+    );
+}
+
+/// What vllm and sglang need on this machine, the other engines are compiled in
+async fn check_engine(out_opt: &Output, checks: &mut Checks) {
+    let module = match out_opt {
+        Output::Vllm => "vllm",
+        Output::SgLang => "sglang",
+        _ => return,
+    };
+
+    // The sub-process script imports both
+    let import = format!("import dynamo.runtime, {module}");
+    match tokio::process::Command::new("python3")
+        .args(["-c", &import])
+        .output()
+        .await
+    {
+        Ok(output) if output.status.success() => {
+            checks.ok(format!("python3 can import dynamo and {module}"))
+        }
+        Ok(output) => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let reason = stderr.lines().last().unwrap_or("failed");
+            checks.problem(format!("out={out_opt}: python3 -c '{import}': {reason}"));
+        }
+        Err(err) => checks.problem(format!("out={out_opt}: cannot run python3: {err}")),
+    }
+
+    match probe::driver_version() {
+        Some(version) => checks.ok(format!("NVIDIA driver {version}")),
+        None => checks.problem(format!(
+            "out={out_opt} needs CUDA, but no NVIDIA driver is loaded ({NVIDIA_DRIVER})"
+        )),
+    }
+}
+
+/// The engines `out_opt` runs, those of its file for out=multi
+fn engines(out_opt: &Output) -> Vec<Output> {
+    match out_opt {
+        Output::Multi(path) => multi::load(path)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|spec| Output::try_from(spec.out.as_str()).ok())
+            .collect(),
+        other => vec![other.clone()],
+    }
+}
+
+/// Runs that discover or register models through etcd and NATS
+fn uses_network(in_opt: &Input, out_opt: &Output) -> bool {
+    matches!(in_opt, Input::Endpoint(_))
+        || engines(out_opt)
+            .iter()
+            .any(|out| matches!(out, Output::Endpoint(_)) || out.is_subprocess())
+}
+
+async fn check_network(runtime: Runtime, checks: &mut Checks) {
+    match tokio::time::timeout(CONNECT_TIMEOUT, DistributedRuntime::from_settings(runtime)).await {
+        Ok(Ok(_)) => checks.ok("connected to etcd and NATS"),
+        Ok(Err(err)) => checks.problem(format!("etcd and NATS: {err:#}")),
+        Err(_) => checks.problem(format!(
+            "etcd and NATS: no connection after {}s",
+            CONNECT_TIMEOUT.as_secs()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uses_network() {
+        let args = |args: &[&str]| -> Vec<String> { args.iter().map(|a| a.to_string()).collect() };
+        let (in_opt, out_opt, flags) = parse(&args(&["in=http", "out=echo_full"])).unwrap();
+        assert!(!uses_network(&in_opt, &out_opt));
+        assert!(flags.model_path_pos.is_none());
+
+        let (in_opt, out_opt, _) = parse(&args(&["out=dyn://ns.backend.generate"])).unwrap();
+        assert!(uses_network(&in_opt, &out_opt));
+
+        let (in_opt, out_opt, _) =
+            parse(&args(&["in=dyn://ns.backend.generate", "out=vllm"])).unwrap();
+        assert!(uses_network(&in_opt, &out_opt));
+    }
+}
@@ -16,9 +16,25 @@
 use std::collections::HashMap;
 use std::path::PathBuf;
 
-use clap::ValueEnum;
+use clap::{parser::ValueSource, ArgAction, CommandFactory, FromArgMatches, ValueEnum};
+use dynamo_llm::engines::load::{DType, Device, LoadOptions, Quantization};
+use dynamo_llm::engines::mock::MockProfile;
+use dynamo_llm::gguf::RopeScaling;
+use dynamo_llm::http::service::access_log::PromptLogging;
+use dynamo_llm::http::service::forwarded::TrustedProxies;
+use dynamo_llm::lora::LoraAdapter;
+use dynamo_llm::model_alias::{AliasTarget, ModelAliases};
+use dynamo_llm::preprocessor::media::ImageFetch;
+use dynamo_llm::preprocessor::truncation::Truncation;
+use dynamo_llm::protocols::common::sampling::OutOfRange;
+use dynamo_runtime::config::{ConfigSetting, ConfigSource};
+use dynamo_runtime::pipeline::network::codec::PayloadEncoding;
 use dynamo_runtime::pipeline::RouterMode as RuntimeRouterMode;
 
+use crate::input::bench::Lengths;
+use crate::subprocess;
+use crate::Output;
+
 /// Required options depend on the in and out choices
 #[derive(clap::Parser, Debug, Clone)]
 #[command(version, about, long_about = None)]
@@ -33,11 +49,17 @@ pub struct Flags {
     #[arg(index = 1)]
     pub model_path_pos: Option<PathBuf>,
 
-    // `--model-path`. The one above is `dynamo-run <positional-model-path>`
+    // `--model-path`. The one above is `dynamo-run <positional-model-path>`. in=http serves
+    // every one given, see `crate::model_pool`.
     #[arg(long = "model-path")]
-    pub model_path_flag: Option<PathBuf>,
+    pub model_path_flag: Vec<PathBuf>,
+
+    /// JSON, TOML or YAML file of arguments, `in`, `out` and flags. `${VAR}` in a value is read
+    /// from the environment. Flags on the command line win over the file.
+    #[arg(long)]
+    pub config: Option<PathBuf>,
 
-    /// HTTP port. `in=http` only
+    /// HTTP port. in=http and in=mcp only
     #[arg(long, default_value = "8080")]
     pub http_port: u16,
 
@@ -45,6 +67,20 @@ pub struct Flags {
     #[arg(long)]
     pub model_name: Option<String>,
 
+    /// Branch, tag or commit of the Hugging Face models to download, `main` by default. Same
+    /// as setting `DYN_HF_REVISION`.
+    #[arg(long)]
+    pub revision: Option<String>,
+
+    /// in=dyn only
+    ///
+    /// Copy the model from a worker that has the same revision of it instead of downloading
+    /// it, and once it is loaded offer it to the workers that start later. Much quicker to
+    /// scale up a large model across a fast network. Downloads as usual if no worker has it.
+    /// Same as setting `DYN_WEIGHTS_FROM_PEERS`.
+    #[arg(long)]
+    pub weights_from_peers: bool,
+
     /// Verbose output (-v for debug, -vv for trace)
     #[arg(short = 'v', action = clap::ArgAction::Count, default_value_t = 0)]
     pub verbosity: u8,
@@ -58,13 +94,118 @@ pub struct Flags {
     #[arg(long)]
     pub model_config: Option<PathBuf>,
 
-    /// sglang, vllm
+    /// Tokenizer to use instead of the model's own, for example a tokenizer.json with a GGUF
+    /// whose embedded vocabulary we can't read. A Hugging Face `.json`, a SentencePiece
+    /// `.model` or a tiktoken file. SentencePiece and tiktoken need dynamo-run built with the
+    /// `sentencepiece` and `tiktoken` features.
+    #[arg(long)]
+    pub tokenizer_path: Option<PathBuf>,
+
+    /// Jinja chat template to render prompts with instead of the model's own, for models
+    /// whose tokenizer_config.json has none or a broken one. A GGUF with `--model-config`
+    /// falls back to the template embedded in the GGUF if the config has none.
+    #[arg(long)]
+    pub chat_template: Option<PathBuf>,
+
+    /// Log every prompt after the chat template is applied, to debug templates. Logs what
+    /// users send. Same as setting `DYN_PRINT_PROMPT=1`.
+    #[arg(long)]
+    pub print_prompt: bool,
+
+    /// llamacpp only
+    ///
+    /// Most requests to decode together. New requests join the running batch at the next
+    /// decode step instead of waiting for it to drain, until the batch is this big.
+    #[arg(long, default_value = "3", value_parser = clap::value_parser!(u32).range(1..1024))]
+    pub max_batch_size: u32,
+
+    /// llamacpp, vllm and sglang
+    ///
+    /// Most prompt tokens prefilled in one step. Longer prompts are prefilled a chunk at a
+    /// time, between the decode steps of the running requests, so they don't stall them.
+    /// vllm and sglang count the decoding requests' tokens in it too.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_prefill_chunk: Option<u32>,
+
+    /// llamacpp only
+    ///
+    /// While requests are decoding, prefill at most this many prompt tokens per step for each
+    /// of them, e.g. 64 with 3 requests decoding is 192 prompt tokens a step. Lower keeps their
+    /// token rate steadier, higher starts new requests sooner.
+    #[arg(long)]
+    pub prefill_decode_ratio: Option<f32>,
+
+    /// llamacpp only
+    ///
+    /// When all --max-batch-size slots are taken, a request of a higher priority (nvext.priority
+    /// or the x-dynamo-priority header) preempts the running request of the lowest priority,
+    /// which resumes once a slot frees up.
+    #[arg(long)]
+    pub preemption: bool,
+
+    /// Most tokens a request can have, prompt and completion. Longer prompts are truncated or
+    /// rejected as `--truncation` says. Defaults to the model's context length, from
+    /// config.json or the GGUF. llamacpp sizes each sequence's context to this, by default the
+    /// GGUF's context length but at most 8192.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub context_length: Option<u32>,
+
+    /// llamacpp only
+    ///
+    /// RoPE scaling instead of the GGUF's: `none`, `linear:<factor>` or `yarn:<factor>`. With
+    /// `--context-length` it runs the model past the context it was trained on.
+    #[arg(long)]
+    pub rope_scaling: Option<RopeScaling>,
+
+    /// llamacpp only
+    ///
+    /// RoPE base frequency instead of the GGUF's `rope.freq_base`
+    #[arg(long)]
+    pub rope_freq_base: Option<f32>,
+
+    /// mistralrs, sglang and vllm
+    ///
+    /// Quantize the weights as the model loads: `fp8`, `int8` or `int4`. mistralrs takes all
+    /// three, with ISQ. vllm takes fp8 and int4, with bitsandbytes, and sglang takes fp8. Leave
+    /// it out for GGUF files and checkpoints that are already quantized.
+    #[arg(long)]
+    pub quantization: Option<Quantization>,
+
+    /// mistralrs, sglang and vllm
+    ///
+    /// Type of the weights that aren't quantized, and of the activations: `auto`, `bf16`,
+    /// `f16` or `f32`. Defaults to what the model's config.json says.
+    #[arg(long)]
+    pub dtype: Option<DType>,
+
+    /// sglang, vllm and llamacpp
     ///
     /// How many GPUs to use at once, total across all nodes.
     /// This must divide by num_nodes, and each node must use the same number of GPUs.
-    #[arg(long, default_value = "1", value_parser = clap::value_parser!(u32).range(1..256))]
+    /// llamacpp splits the model's layers over every GPU it sees, also when `--device` is one.
+    #[arg(
+        long,
+        visible_alias = "tensor-parallel",
+        default_value = "1",
+        value_parser = clap::value_parser!(u32).range(1..256)
+    )]
     pub tensor_parallel_size: u32,
 
+    /// sglang and vllm
+    ///
+    /// Share of each GPU's memory the engine takes for the weights and the KV cache, more than
+    /// 0 and at most 1.
+    #[arg(long)]
+    pub gpu_memory_fraction: Option<f32>,
+
+    /// mistralrs, llamacpp, sglang and vllm
+    ///
+    /// Where to run the model: `cpu`, `cuda:<index>` or `metal`. With tensor parallelism the
+    /// index is the first GPU. sglang takes `cuda:<index>` as --base-gpu-id. vllm, and llamacpp
+    /// with tensor parallelism, only take `cuda:0`, set CUDA_VISIBLE_DEVICES to pick other GPUs.
+    #[arg(long)]
+    pub device: Option<Device>,
+
     /// sglang only
     /// vllm uses CUDA_VISIBLE_DEVICES env var
     ///
@@ -98,13 +239,82 @@ pub struct Flags {
     #[arg(long)]
     pub leader_addr: Option<String>,
 
+    /// vllm and sglang only
+    ///
+    /// Small model that drafts tokens for the served model to verify, several per decode step.
+    /// A local path or a Hugging Face repo. sglang needs an EAGLE draft head for the model.
+    #[arg(long)]
+    pub draft_model: Option<String>,
+
+    /// vllm and sglang only
+    ///
+    /// How many tokens the draft model proposes per step, with --draft-model
+    #[arg(long, default_value = "5", value_parser = clap::value_parser!(u32).range(1..64))]
+    pub num_speculative_tokens: u32,
+
     /// If using `out=dyn://..` with multiple backends, this says how to route the requests.
     ///
-    /// Mostly interesting for KV-aware routing.
-    /// Defaults to RouterMode::RoundRobin
+    /// - random, round-robin: spread requests evenly.
+    /// - least-outstanding: the worker with the fewest requests in flight from this node.
+    /// - power-of-two: the less busy of two random workers. For pools of GPUs of different
+    ///   speeds, like least-outstanding, with less herding when there are several frontends.
+    /// - kv: the worker with most of the prompt in its KV cache, from the KV events the workers
+    ///   publish. Only for workers that take pre-processed requests, the others and requests
+    ///   the KV router can't place yet go round-robin.
+    ///
+    /// Defaults to round-robin.
     #[arg(long, default_value = "round-robin")]
     pub router_mode: RouterMode,
 
+    /// With `--router-mode kv`, the KV cache block size of the workers, in tokens. It must be
+    /// the one they publish their KV events with.
+    #[arg(long, default_value = "16", value_parser = clap::value_parser!(u32).range(1..))]
+    pub kv_block_size: u32,
+
+    /// in=http only
+    ///
+    /// With several `--model-path` or `--model-admin`, the GPU memory the models may use
+    /// together, in GB, estimated from the size of their weights. Loading a model unloads the least recently used idle ones until it fits.
+    /// Without it, models stay loaded once they are.
+    #[arg(long)]
+    pub vram_budget_gb: Option<f64>,
+
+    /// in=http only
+    ///
+    /// Serve `POST /admin/models/load` and `POST /admin/models/unload`, to load a model or new
+    /// weights of a served one while serving, and to unload one. Requests running on the old
+    /// weights finish on them. Not with engines in a sub-process or out=dyn://.
+    #[arg(long)]
+    pub model_admin: bool,
+
+    /// in=http only
+    ///
+    /// Serve the operator API under `/admin`: the config, workers and sticky sessions, and
+    /// draining workers, unpinning sessions and flushing the prefix cache. Use it with
+    /// --api-keys, without them anyone who reaches the port can call it.
+    #[arg(long)]
+    pub admin_api: bool,
+
+    /// Before serving, generate a few tokens for a prompt of each of these lengths, in tokens,
+    /// comma separated. Takes the latency of the first requests out of the way, before the
+    /// HTTP service is ready or the worker registers. Off by default.
+    #[arg(long, value_delimiter = ',')]
+    pub warmup: Vec<u32>,
+
+    /// out=mock only
+    ///
+    /// How fast the mock engine generates and how often it fails, as comma separated
+    /// `key=value`: `tps` (tokens per second, default 100), `ttft_ms` (time to first token,
+    /// default 50), `jitter` (0 to 1, how much each delay varies), `failure_rate` (0 to 1) and
+    /// `max_tokens` (for requests without one, default 128).
+    #[arg(long)]
+    pub mock_profile: Option<MockProfile>,
+
+    /// Append every request the engine finishes to this JSONL trace, to serve it back later
+    /// with `out=replay:<trace>`. Same as setting `DYN_RECORD`.
+    #[arg(long)]
+    pub record: Option<PathBuf>,
+
     /// Additional engine-specific arguments from a JSON file.
     /// Contains a mapping of parameter names to values.
     #[arg(long)]
@@ -121,6 +331,676 @@ pub struct Flags {
     #[arg(long)]
     pub request_template: Option<PathBuf>,
 
+    /// JSON file of named prompt presets, each a `system` prompt and few-shot `examples` of
+    /// `user` and `assistant` messages. Chat requests pick one with `nvext.preset`, in=text
+    /// with `/preset <name>`. Edits to the file apply to the next request.
+    #[arg(long)]
+    pub presets: Option<PathBuf>,
+
+    /// in=text, in=stdin and in=batch only
+    ///
+    /// System prompt before the conversation, or before each prompt of a batch.
+    #[arg(long)]
+    pub system_prompt: Option<String>,
+
+    /// in=text, in=stdin and in=batch only
+    ///
+    /// Sampling temperature. Defaults to the one of --request-template, or 0.7.
+    #[arg(long)]
+    pub temperature: Option<f32>,
+
+    /// in=text, in=stdin and in=batch only
+    ///
+    /// Nucleus sampling, the most likely tokens adding up to this probability. Defaults to
+    /// the engine's.
+    #[arg(long)]
+    pub top_p: Option<f32>,
+
+    /// in=text, in=stdin and in=batch only
+    ///
+    /// Most tokens in each response. Defaults to the max_completion_tokens of
+    /// --request-template, or 8192.
+    #[arg(long)]
+    pub max_tokens: Option<u32>,
+
+    /// in=text, in=stdin and in=batch only
+    ///
+    /// Stop the response at this text. Repeat for several.
+    #[arg(long)]
+    pub stop: Vec<String>,
+
+    /// in=text only
+    ///
+    /// Continue a conversation saved with `/save`: its messages, system prompt and sampling
+    /// settings.
+    #[arg(long)]
+    pub resume: Option<PathBuf>,
+
+    /// in=http only
+    ///
+    /// Cache responses to deterministic (temperature 0) non-streaming chat completion
+    /// requests for this many seconds.
+    #[arg(long)]
+    pub response_cache_ttl: Option<u64>,
+
+    /// in=http only
+    ///
+    /// Cached responses older than this many seconds are still served, and re-generated in
+    /// the background so the next client gets a fresh one. Requires --response-cache-ttl.
+    #[arg(long, requires = "response_cache_ttl")]
+    pub response_cache_stale_after: Option<u64>,
+
+    /// in=http only
+    ///
+    /// Most responses to keep cached, the least recently used makes room. Default 1024.
+    #[arg(long, requires = "response_cache_ttl")]
+    pub response_cache_size: Option<usize>,
+
+    /// in=http only
+    ///
+    /// Comma separated models to cache the responses of. All models by default.
+    #[arg(long, value_delimiter = ',', requires = "response_cache_ttl")]
+    pub response_cache_models: Vec<String>,
+
+    /// in=http only
+    ///
+    /// Redis URL of a response cache shared with other frontends, behind the in-memory one.
+    /// Needs dynamo-run built with the 'redis' feature.
+    #[arg(long, requires = "response_cache_ttl")]
+    pub response_cache_redis: Option<String>,
+
+    /// in=http only
+    ///
+    /// Keep the conversations of chat requests with `nvext.session_id` for this many seconds
+    /// after their last turn, so clients only send the new messages. Without it such requests
+    /// are refused.
+    #[arg(long)]
+    pub conversation_ttl: Option<u64>,
+
+    /// in=http only
+    ///
+    /// Most conversations to keep in memory, the one closest to expiring makes room.
+    /// Default 10000.
+    #[arg(long, requires = "conversation_ttl")]
+    pub conversation_max: Option<usize>,
+
+    /// in=http only
+    ///
+    /// Redis URL to keep conversations in instead, shared with other frontends. Needs
+    /// dynamo-run built with the 'redis' feature.
+    #[arg(long, requires = "conversation_ttl")]
+    pub conversation_redis: Option<String>,
+
+    /// in=http only
+    ///
+    /// Serve `/v1/prefetch`, which prefills a prompt in the background so that the requests
+    /// starting with it get their first token sooner. A prefetched prompt is not prefetched
+    /// again for this many seconds.
+    #[arg(long)]
+    pub prefetch_ttl: Option<u64>,
+
+    /// in=http only
+    ///
+    /// Most prompts prefetched within --prefetch-ttl, further prefetches are refused with a
+    /// 429. Default 64.
+    #[arg(long, requires = "prefetch_ttl")]
+    pub prefetch_capacity: Option<usize>,
+
+    /// in=http only
+    ///
+    /// JSON file of content filter rules, each a `name`, a regex `pattern` or `keywords`, an
+    /// `action` (`block` or `redact`) and the `stages` it checks (`prompt`, `response`).
+    /// Blocked prompts get a 400, flagged responses stop with finish reason `content_filter`.
+    #[arg(long)]
+    pub content_filter_rules: Option<PathBuf>,
+
+    /// in=http only
+    ///
+    /// URL of a content classifier answering like OpenAI's `/v1/moderations`. Prompts and
+    /// responses it flags are blocked, and if it can't be reached requests fail with a 503.
+    #[arg(long)]
+    pub content_filter_url: Option<String>,
+
+    /// in=http only
+    ///
+    /// `model` to send the classifier of --content-filter-url.
+    #[arg(long, requires = "content_filter_url")]
+    pub content_filter_model: Option<String>,
+
+    /// in=http only
+    ///
+    /// Check streaming responses each time they grew by this many characters. Until then
+    /// their text is held back. Default 100.
+    #[arg(long)]
+    pub content_filter_scan_chars: Option<usize>,
+
+    /// in=http only
+    ///
+    /// Send the chunks of streaming responses that arrive within this many milliseconds of
+    /// each other in one write, to save CPU with fast models. The first chunk is sent at once.
+    /// Requests can change it with `nvext.stream_coalesce_ms`.
+    #[arg(long)]
+    pub stream_coalesce_ms: Option<u64>,
+
+    /// in=http only
+    ///
+    /// Most chunks sent in one write. Default 16. Requires --stream-coalesce-ms.
+    #[arg(long, requires = "stream_coalesce_ms", value_parser = clap::value_parser!(u32).range(1..))]
+    pub stream_coalesce_max: Option<u32>,
+
+    /// in=http only
+    ///
+    /// Most completion tokens a request can ask for. Requests asking for more get a 400,
+    /// requests that don't say get this many.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_output_tokens: Option<u32>,
+
+    /// in=http only
+    ///
+    /// Most tokens a prompt can have, after the chat template. Longer prompts get a 400.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_prompt_tokens: Option<u32>,
+
+    /// in=http only
+    ///
+    /// Seconds a request can take, waiting in queues included. Past that its generation is
+    /// stopped: a non-streaming request gets a 504, a streaming one an error event.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub request_timeout: Option<u64>,
+
+    /// in=http only
+    ///
+    /// Comma separated origins browser pages may call the API from, `*` for any. Turns on
+    /// CORS, which is off by default.
+    #[arg(long, value_delimiter = ',')]
+    pub cors_origins: Vec<String>,
+
+    /// in=http only
+    ///
+    /// Comma separated request headers pages may send. Default `Authorization`,
+    /// `Content-Type`, `traceparent` and the `x-dynamo-*` request headers.
+    #[arg(long, value_delimiter = ',', requires = "cors_origins")]
+    pub cors_headers: Vec<String>,
+
+    /// in=http only
+    ///
+    /// Let pages send cookies and `Authorization` with their requests. Needs a list of
+    /// origins, not `*`.
+    #[arg(long, requires = "cors_origins")]
+    pub cors_credentials: bool,
+
+    /// in=http only
+    ///
+    /// Comma separated addresses and CIDR networks of the load balancers and proxies in front
+    /// of us. For requests from them the client's address, which rate limits are keyed on, is
+    /// read from `X-Forwarded-For`. The header is ignored by default.
+    #[arg(long)]
+    pub trusted_proxies: Option<TrustedProxies>,
+
+    /// in=http only
+    ///
+    /// Send a keep-alive comment on streaming responses that have been idle for this many
+    /// seconds, so proxies don't time them out while a long prompt is prefilled.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub sse_keep_alive: Option<u64>,
+
+    /// in=http only
+    ///
+    /// Seconds to keep the response of a request with an `Idempotency-Key` header. Requests
+    /// repeating the key get that response instead of generating another. The header is
+    /// ignored by default.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub idempotency_ttl: Option<u64>,
+
+    /// in=http only
+    ///
+    /// Most idempotency keys remembered. Requests with new keys are not recorded while full.
+    /// Default 10000.
+    #[arg(long, requires = "idempotency_ttl")]
+    pub idempotency_max_keys: Option<usize>,
+
+    /// in=http only
+    ///
+    /// JSON file of the API keys clients must send as `Authorization: Bearer <key>`, each
+    /// with a `name` and optionally the `models` it may use and the `namespace` it belongs to.
+    /// Defaults to the `DYN_API_KEYS_FILE` and `DYN_API_KEYS` environment variables, no
+    /// authentication if neither is set.
+    #[arg(long)]
+    pub api_keys: Option<PathBuf>,
+
+    /// in=http only
+    ///
+    /// JSON file of the namespaces to serve models from, besides the one of `out=dyn://`, and
+    /// their `token_quota` per `quota_period_secs`. API keys with a `namespace` only see its
+    /// models. Usage is at `/admin/namespaces`.
+    #[arg(long)]
+    pub tenants: Option<PathBuf>,
+
+    /// in=http only
+    ///
+    /// Another name clients may use for a model, as `alias=model`, for example
+    /// `gpt-4o=Qwen2.5-3B-Instruct`. `model@namespace.component.endpoint` only goes to the
+    /// model while it is served from that endpoint, and `alias=model1,model2` spreads the
+    /// requests over those being served. Repeat for several. Like the API keys, rate limits
+    /// and router mode, re-read on SIGHUP.
+    #[arg(long)]
+    pub model_alias: Vec<ModelAlias>,
+
+    /// in=http only
+    ///
+    /// A LoRA adapter of the model, as `name=path`, where path is a directory or a Hugging
+    /// Face repo. Clients use it with the model name `<model>:<name>`. Repeat for several.
+    /// More can be added while running with --lora-admin. Needs out=vllm.
+    #[arg(long)]
+    pub lora: Vec<LoraAdapter>,
+
+    /// in=http only
+    ///
+    /// Serve `/admin/lora`, to add LoRA adapters from a path or Hugging Face repo and remove
+    /// them while serving. Use it with --api-keys, without them anyone who reaches the port
+    /// can load adapters.
+    #[arg(long)]
+    pub lora_admin: bool,
+
+    /// vllm only
+    ///
+    /// How many LoRA adapters a batch can use at once. Setting it, or giving --lora, enables
+    /// LoRA in the engine. Defaults to the number of --lora adapters.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..1024))]
+    pub max_loras: Option<u32>,
+
+    /// in=batch and in=http only
+    ///
+    /// Prompts sent to the engine at once. The next one goes as soon as one finishes. Output
+    /// is written in input order either way. Defaults to all of them with in=batch, and to 8
+    /// for each batch of in=http's `/v1/batches`.
+    #[arg(long)]
+    pub batch_concurrency: Option<u32>,
+
+    /// in=batch only
+    ///
+    /// Continue an interrupted run. The prompts already in output.jsonl are skipped and the
+    /// new responses are appended to it.
+    #[arg(long)]
+    pub batch_resume: bool,
+
+    /// in=batch only
+    ///
+    /// Write the end of run summary to this file as JSON: prompts done and failed, tokens,
+    /// and latency and time to first token percentiles.
+    #[arg(long)]
+    pub batch_report: Option<PathBuf>,
+
+    /// in=batch only
+    ///
+    /// File to write the results to. Defaults to output.<format> next to the input.
+    #[arg(long)]
+    pub batch_output: Option<PathBuf>,
+
+    /// in=batch only
+    ///
+    /// Format of the results. Defaults to the extension of --batch-output, jsonl if that
+    /// doesn't say. parquet needs the 'parquet' feature.
+    #[arg(long, value_enum)]
+    pub batch_format: Option<BatchFormat>,
+
+    /// in=batch only
+    ///
+    /// Run one shard of the prompts, `i/N`: those whose request_id divided by N leaves i.
+    /// Writes output.i-of-N.<format> by default, so the shards can share a folder.
+    #[arg(long, conflicts_with = "batch_shards")]
+    pub batch_shard: Option<BatchShard>,
+
+    /// in=batch only
+    ///
+    /// Start this many dynamo-run processes, one per --batch-shard, with the same flags, and
+    /// merge their results into --batch-output. With out=dyn:// that spreads the batch over
+    /// the workers serving the endpoint.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub batch_shards: Option<u32>,
+
+    /// in=kafka only
+    ///
+    /// Consumer group. Every dynamo-run in the group gets a share of the topic's partitions,
+    /// so start more of them to go faster. Defaults to dynamo-run.
+    #[arg(long)]
+    pub kafka_group: Option<String>,
+
+    /// in=kafka only
+    ///
+    /// Topic to send the responses to when a request has no `reply_to` header. Defaults to
+    /// the request topic with `-replies` appended.
+    #[arg(long)]
+    pub kafka_reply_topic: Option<String>,
+
+    /// in=kafka only
+    ///
+    /// Requests sent to the engine at once. The next message is read as soon as one finishes.
+    #[arg(long, default_value = "16", value_parser = clap::value_parser!(u32).range(1..))]
+    pub kafka_concurrency: u32,
+
+    /// in=kafka only
+    ///
+    /// A librdkafka setting for the consumer and the producer, as `key=value`, e.g.
+    /// `security.protocol=SASL_SSL`. Repeat for several.
+    #[arg(long)]
+    pub kafka_config: Vec<KafkaSetting>,
+
+    /// in=redis only
+    ///
+    /// The queue is a stream, read with a consumer group, instead of a list. Not needed if
+    /// the key is a stream already.
+    #[arg(long)]
+    pub redis_stream: bool,
+
+    /// in=redis only
+    ///
+    /// Consumer group when the queue is a stream. Defaults to dynamo-run.
+    #[arg(long)]
+    pub redis_group: Option<String>,
+
+    /// in=redis only
+    ///
+    /// Key the results are added to, a list or a stream like the queue. Defaults to the queue
+    /// with `:results` appended.
+    #[arg(long)]
+    pub redis_results: Option<String>,
+
+    /// in=redis only
+    ///
+    /// Seconds without a sign of life after which another dynamo-run takes back the requests
+    /// a crashed one was running.
+    #[arg(long, default_value = "300", value_parser = clap::value_parser!(u64).range(3..))]
+    pub redis_visibility_timeout: u64,
+
+    /// in=redis only
+    ///
+    /// Requests sent to the engine at once. The next one is popped as soon as one finishes.
+    #[arg(long, default_value = "16", value_parser = clap::value_parser!(u32).range(1..))]
+    pub redis_concurrency: u32,
+
+    /// in=watch only
+    ///
+    /// Requests sent to the engine at once, across the prompt files being run.
+    #[arg(long, default_value = "16", value_parser = clap::value_parser!(u32).range(1..))]
+    pub watch_concurrency: u32,
+
+    /// in=bench only
+    ///
+    /// With a second engine: JSON Lines file of prompts to run one at a time on both,
+    /// `{"text": "..."}` as for in=batch. Without it, both get the synthetic load of the
+    /// other --bench-* flags in turn.
+    #[arg(long)]
+    pub bench_prompts: Option<PathBuf>,
+
+    /// in=bench only
+    ///
+    /// Where to write the report, JSON. Defaults to bench.json in the current directory, or
+    /// bench-compare.json with a second engine.
+    #[arg(long)]
+    pub bench_report: Option<PathBuf>,
+
+    /// in=bench only
+    ///
+    /// With a second engine: percent change of a metric, in the bad direction, that counts as a regression when
+    /// the 95% confidence interval confirms it. Defaults to 5.
+    #[arg(long)]
+    pub bench_threshold: Option<f64>,
+
+    /// in=bench only
+    ///
+    /// Without --bench-prompts: clients sending requests at once, each waiting for its
+    /// response before the next. Default 8.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub bench_concurrency: Option<u32>,
+
+    /// in=bench only
+    ///
+    /// Without --bench-prompts: seconds to keep sending requests for. Default 60.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub bench_duration: Option<u64>,
+
+    /// in=bench only
+    ///
+    /// Without --bench-prompts: prompt length in tokens, `<n>` or uniform in `<min>-<max>`.
+    /// Default 512.
+    #[arg(long)]
+    pub bench_prompt_tokens: Option<Lengths>,
+
+    /// in=bench only
+    ///
+    /// Without --bench-prompts: response length in tokens, `<n>` or uniform in
+    /// `<min>-<max>`. Default 128.
+    #[arg(long)]
+    pub bench_output_tokens: Option<Lengths>,
+
+    /// in=arena only
+    ///
+    /// JSON Lines file the votes are appended to. Defaults to arena.jsonl in the current
+    /// directory.
+    #[arg(long)]
+    pub arena_results: Option<PathBuf>,
+
+    /// in=http only
+    ///
+    /// PEM certificate chain to serve HTTPS with. Requires --tls-key.
+    #[arg(long, requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// in=http only
+    ///
+    /// PEM private key of --tls-cert.
+    #[arg(long, requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// in=http only
+    ///
+    /// PEM certificates of the CAs that sign client certificates. Clients must then present
+    /// one (mutual TLS). Requires --tls-cert.
+    #[arg(long, requires = "tls_cert")]
+    pub tls_client_ca: Option<PathBuf>,
+
+    /// in=http only
+    ///
+    /// Requests per minute each client may send. A client is its API key with --api-keys,
+    /// its IP address otherwise.
+    #[arg(long)]
+    pub rate_limit_rpm: Option<u32>,
+
+    /// in=http only
+    ///
+    /// Prompt and generated tokens per minute each client may use.
+    #[arg(long)]
+    pub rate_limit_tpm: Option<u32>,
+
+    /// in=http only
+    ///
+    /// Requests each client may have in flight at once.
+    #[arg(long)]
+    pub max_concurrent_requests: Option<u32>,
+
+    /// in=http only
+    ///
+    /// Append a JSON line per request to this file, `-` for stdout: the request id, model,
+    /// API key name, token counts, time to first token, latency and status.
+    #[arg(long)]
+    pub access_log: Option<PathBuf>,
+
+    /// in=http only
+    ///
+    /// Fraction of successful requests to write to the access log, from 0 to 1. Failed
+    /// requests are always written. Default 1.
+    #[arg(long, requires = "access_log")]
+    pub access_log_sample_rate: Option<f64>,
+
+    /// in=http only
+    ///
+    /// Prompts in the access log: `omit` them (default), a `hash` to spot the same prompt
+    /// again, or `full`.
+    #[arg(long, requires = "access_log")]
+    pub access_log_prompts: Option<PromptLogging>,
+
+    /// in=http only
+    ///
+    /// Time to first token objective in milliseconds. While the p99 over the last 30 seconds
+    /// is higher, new requests to the model are shed, see --slo-degrade-max-tokens.
+    #[arg(long)]
+    pub slo_ttft_ms: Option<u64>,
+
+    /// in=http only
+    ///
+    /// Queue delay objective in milliseconds, the time until a worker accepts a request.
+    /// Shed load like --slo-ttft-ms.
+    #[arg(long)]
+    pub slo_queue_delay_ms: Option<u64>,
+
+    /// in=http only
+    ///
+    /// Instead of rejecting requests with a 503 while a model misses its SLO, lower their
+    /// max tokens to this.
+    #[arg(long)]
+    pub slo_degrade_max_tokens: Option<u32>,
+
+    /// in=http only
+    ///
+    /// Requests of each model running at once. Further requests wait in a queue of
+    /// --max-queued-per-model, and get a 429 once it is full. No limit if not set.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_running_per_model: Option<u32>,
+
+    /// in=http only
+    ///
+    /// Requests of each model waiting for one of its --max-running-per-model slots.
+    #[arg(long, default_value = "64", requires = "max_running_per_model")]
+    pub max_queued_per_model: u32,
+
+    /// in=http only
+    ///
+    /// Milliseconds a queued request waits before it goes ahead of higher priority requests
+    /// that waited less. Keeps low priority requests from starving.
+    #[arg(long, default_value = "5000", requires = "max_running_per_model")]
+    pub starvation_timeout_ms: u64,
+
+    /// Serve Prometheus metrics on this port at `/metrics`, and `/live`, `/ready` and `/health`
+    /// for probes. For inputs other than `in=http`, which serves them on its own port.
+    #[arg(long)]
+    pub metrics_port: Option<u16>,
+
+    /// What to do with sampling options (temperature, top_p, ...) outside of the range the
+    /// API and the engine allow: `reject` the request (default), or `clamp` them and return
+    /// a warning in the response's `nvext`. Same as setting `DYN_SAMPLING_OUT_OF_RANGE`.
+    #[arg(long)]
+    pub sampling_out_of_range: Option<OutOfRange>,
+
+    /// File of words the model must never generate, one per line. Each is banned with a
+    /// `logit_bias` of -100 on its token, so the engine has to support `logit_bias`. Words
+    /// that are more than one token of the model are skipped with a warning. Same as setting
+    /// `DYN_BANNED_WORDS`.
+    #[arg(long)]
+    pub banned_words: Option<PathBuf>,
+
+    /// Sample requests that don't set a `seed` with this one, for reproducible output. The
+    /// response's `nvext.seed` says which seed was used. Ignored by engines that don't take a
+    /// seed. Same as setting `DYN_SEED`.
+    #[arg(long, allow_negative_numbers = true)]
+    pub seed: Option<i64>,
+
+    /// What to do with a prompt that doesn't leave room for `max_tokens` in the context:
+    /// fail the request with a 400 (`reject`, default), send it anyway (`off`), or cut it down
+    /// and return a warning in the response's `nvext`. `messages` drops the oldest chat
+    /// messages, `left` the oldest tokens and `middle` tokens from the middle. Same as setting
+    /// `DYN_CONTEXT_OVERFLOW_POLICY`.
+    #[arg(long, alias = "truncation")]
+    pub context_overflow_policy: Option<Truncation>,
+
+    /// Which `http(s)` image URLs of chat requests to fetch: none (`off`, default, only
+    /// `data:` URLs), those of hosts on public addresses (`public`), or any (`any`, for
+    /// trusted clients only, they can make the server reach internal services). Same as
+    /// setting `DYN_IMAGE_FETCH`.
+    #[arg(long)]
+    pub image_fetch: Option<ImageFetch>,
+
+    /// When the engine rejects a prompt as too long for its context, send the request once
+    /// more with the prompt cut by a quarter using the `--context-overflow-policy`. Same as
+    /// setting `DYN_TRUNCATION_RETRY=1`.
+    #[arg(long)]
+    pub truncation_retry: bool,
+
+    /// Threads that apply the chat template and tokenize prompts, away from the threads
+    /// serving requests. Half the CPUs by default. Same as setting `DYN_TOKENIZER_THREADS`.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub tokenizer_threads: Option<u32>,
+
+    /// How many prompts can wait for a tokenizer thread, 256 by default. More requests wait
+    /// for a place. Same as setting `DYN_TOKENIZER_QUEUE`.
+    #[arg(long)]
+    pub tokenizer_queue: Option<u32>,
+
+    /// Export OpenTelemetry traces to this OTLP/HTTP collector, e.g. `http://localhost:4318`.
+    /// Same as setting `OTEL_EXPORTER_OTLP_ENDPOINT`.
+    #[arg(long)]
+    pub otlp_endpoint: Option<String>,
+
+    /// Seconds our etcd lease outlives us if we stop renewing it, after which our models and
+    /// endpoints are deregistered. Lower notices dead workers sooner, but a longer etcd hiccup
+    /// is needed to take out a healthy one. Same as setting `DYN_LEASE_TTL`. Default 10.
+    #[arg(long, value_parser = clap::value_parser!(i64).range(1..))]
+    pub lease_ttl: Option<i64>,
+
+    /// How the requests this node sends to workers, and their responses, are encoded: `json`
+    /// (default) or `msgpack`, smaller and quicker to parse for long prompts. Each request
+    /// says which it uses, so only the sending node needs it, but every worker must be new
+    /// enough to understand msgpack. Same as setting `DYN_PAYLOAD_ENCODING`.
+    #[arg(long)]
+    pub payload_encoding: Option<PayloadEncoding>,
+
+    /// Encrypt the response streams and model copies between nodes with TLS, with this PEM
+    /// certificate chain. Every node needs one signed by --transport-tls-ca, for the name
+    /// `dynamo` unless `DYN_TRANSPORT_TLS_SERVER_NAME` says otherwise. Set it on every node.
+    /// KV blocks, which NIXL can't encrypt, are then not transferred between workers.
+    /// Same as setting `DYN_TRANSPORT_TLS_CERT`, which can also hold the PEM itself.
+    #[arg(long, requires_all = ["transport_tls_key", "transport_tls_ca"])]
+    pub transport_tls_cert: Option<PathBuf>,
+
+    /// PEM private key of --transport-tls-cert. Same as setting `DYN_TRANSPORT_TLS_KEY`.
+    #[arg(long, requires = "transport_tls_cert")]
+    pub transport_tls_key: Option<PathBuf>,
+
+    /// PEM certificates of the CAs that sign the nodes' certificates. Same as setting
+    /// `DYN_TRANSPORT_TLS_CA`.
+    #[arg(long, requires = "transport_tls_cert")]
+    pub transport_tls_ca: Option<PathBuf>,
+
+    /// Connect to NATS over TLS, trusting the server certificates these PEM CAs sign. Same as
+    /// setting `NATS_TLS_CA`.
+    #[arg(long)]
+    pub nats_tls_ca: Option<PathBuf>,
+
+    /// PEM client certificate to show NATS, for a server that verifies clients. Same as
+    /// setting `NATS_TLS_CERT`.
+    #[arg(long, requires = "nats_tls_key")]
+    pub nats_tls_cert: Option<PathBuf>,
+
+    /// PEM private key of --nats-tls-cert. Same as setting `NATS_TLS_KEY`.
+    #[arg(long, requires = "nats_tls_cert")]
+    pub nats_tls_key: Option<PathBuf>,
+
+    /// in=dyn only
+    ///
+    /// Sample the utilization and memory of our GPUs, and the engine's KV cache use, every this
+    /// many seconds. They are exported as metrics and advertised to the routers, whose
+    /// `least-outstanding` and `power-of-two` modes prefer the less busy GPUs. The model and
+    /// memory of the GPUs are advertised too, for routers to weight workers with other GPUs.
+    /// Needs dynamo-run built with `--features nvml`.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub gpu_telemetry: Option<u64>,
+
+    /// Check the options, the model's config and tokenizer, what the engine needs and the
+    /// connection to etcd and NATS, print what would run and exit, without loading the model.
+    /// Exits non-zero if anything would stop the run.
+    #[arg(long)]
+    pub dry_run: bool,
+
     /// Everything after a `--`.
     /// These are the command line arguments to the python engine when using `pystr` or `pytok`.
     #[arg(index = 2, last = true, hide = true, allow_hyphen_values = true)]
@@ -128,6 +1008,85 @@ pub struct Flags {
 }
 
 impl Flags {
+    /// Parse the flags like `try_parse_from`, and also list the settings that differ from
+    /// the defaults: flags given on the command line, then the `--extra-engine-args` file.
+    pub fn parse_with_settings<I, T>(args: I) -> anyhow::Result<(Flags, Vec<ConfigSetting>)>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<std::ffi::OsString> + Clone,
+    {
+        let command = Flags::command();
+        let matches = command.clone().try_get_matches_from(args)?;
+        let flags = Flags::from_arg_matches(&matches)?;
+
+        let mut settings = vec![];
+        for arg in command.get_arguments() {
+            let id = arg.get_id().as_str();
+            if matches.value_source(id) != Some(ValueSource::CommandLine) {
+                continue;
+            }
+            let value = if matches!(arg.get_action(), ArgAction::Count) {
+                matches.get_count(id).to_string()
+            } else {
+                let Some(raw) = matches.get_raw(id) else {
+                    continue;
+                };
+                let values: Vec<_> = raw.map(|v| v.to_string_lossy().into_owned()).collect();
+                let defaults: Vec<_> = arg
+                    .get_default_values()
+                    .iter()
+                    .map(|v| v.to_string_lossy().into_owned())
+                    .collect();
+                if values == defaults {
+                    continue;
+                }
+                values.join(" ")
+            };
+            let key = match (arg.get_long(), arg.get_short()) {
+                (Some(long), _) => format!("--{long}"),
+                (None, Some(short)) => format!("-{short}"),
+                (None, None) if id == "last" => "--".to_string(),
+                (None, None) => id.to_string(),
+            };
+            settings.push(ConfigSetting {
+                key,
+                value,
+                source: ConfigSource::Cli,
+                origin: None,
+            });
+        }
+
+        if let Some(args) = flags.load_extra_engine_args()? {
+            let origin = flags
+                .extra_engine_args
+                .as_ref()
+                .map(|path| path.display().to_string());
+            let mut args: Vec<_> = args.into_iter().collect();
+            args.sort_by(|a, b| a.0.cmp(&b.0));
+            for (key, value) in args {
+                settings.push(ConfigSetting {
+                    key,
+                    value: match value {
+                        serde_json::Value::String(s) => s,
+                        other => other.to_string(),
+                    },
+                    source: ConfigSource::File,
+                    origin: origin.clone(),
+                });
+            }
+        }
+        Ok((flags, settings))
+    }
+
+    /// The models to serve, the positional one first
+    pub fn model_paths(&self) -> Vec<PathBuf> {
+        self.model_path_pos
+            .iter()
+            .chain(&self.model_path_flag)
+            .cloned()
+            .collect()
+    }
+
     /// Convert the flags back to a command line. Including only the non-null values, but
     /// include the defaults. Includes the canonicalized model path and normalized model name.
     ///
@@ -165,10 +1124,101 @@ impl Flags {
             out.push("--extra-engine-args".to_string());
             out.push(extra_engine_args.display().to_string());
         }
+        if let Some(quantization) = self.quantization {
+            out.push("--quantization".to_string());
+            out.push(quantization.to_string());
+        }
+        if let Some(dtype) = self.dtype {
+            out.push("--dtype".to_string());
+            out.push(dtype.to_string());
+        }
+        if let Some(fraction) = self.gpu_memory_fraction {
+            out.push("--gpu-memory-fraction".to_string());
+            out.push(fraction.to_string());
+        }
+        if let Some(device) = self.device {
+            out.push("--device".to_string());
+            out.push(device.to_string());
+        }
         out.extend(self.last.clone());
         out
     }
 
+    /// The draft model for speculative decoding, if there is one
+    pub fn draft_config(&self) -> Option<subprocess::DraftConfig> {
+        self.draft_model
+            .as_ref()
+            .map(|model| subprocess::DraftConfig {
+                model: model.clone(),
+                num_speculative_tokens: self.num_speculative_tokens,
+            })
+    }
+
+    /// How `out_opt` loads the model, from `--quantization`, `--dtype`,
+    /// `--tensor-parallel-size`, `--gpu-memory-fraction` and `--device`. Errors on the ones
+    /// the engine can't do. Engines that don't load a model ignore them.
+    pub fn load_options(&self, out_opt: &Output) -> anyhow::Result<LoadOptions> {
+        let options = LoadOptions {
+            quantization: self.quantization,
+            dtype: self.dtype,
+            tensor_parallel_size: self.tensor_parallel_size,
+            gpu_memory_fraction: self.gpu_memory_fraction,
+            device: self.device,
+        };
+        if let Some(fraction) = options.gpu_memory_fraction {
+            if !(fraction > 0.0 && fraction <= 1.0) {
+                anyhow::bail!("--gpu-memory-fraction {fraction} must be more than 0 and at most 1");
+            }
+        }
+        let unsupported: &[&str] = match out_opt {
+            #[cfg(feature = "mistralrs")]
+            Output::MistralRs => &["--tensor-parallel-size", "--gpu-memory-fraction"],
+            #[cfg(feature = "llamacpp")]
+            Output::LlamaCpp => &["--quantization", "--dtype", "--gpu-memory-fraction"],
+            Output::SgLang | Output::Vllm => &[],
+            _ => return Ok(options),
+        };
+        if let Some(flag) = options.first_set(unsupported) {
+            anyhow::bail!("{flag} is not supported by {out_opt}");
+        }
+        match (out_opt, options.quantization, options.device) {
+            (Output::Vllm, Some(quantization @ Quantization::Int8), _)
+            | (Output::SgLang, Some(quantization @ (Quantization::Int8 | Quantization::Int4)), _) =>
+            {
+                anyhow::bail!(
+                    "{out_opt} can't quantize to {quantization} as it loads, use a quantized checkpoint"
+                );
+            }
+            (Output::Vllm | Output::SgLang, _, Some(Device::Metal)) => {
+                anyhow::bail!("{out_opt} does not run on metal");
+            }
+            (Output::Vllm, _, Some(Device::Cuda(index))) if index != 0 => {
+                anyhow::bail!(
+                    "vllm does not support --device cuda:{index}. Set environment variable CUDA_VISIBLE_DEVICES instead."
+                );
+            }
+            (Output::SgLang, _, Some(Device::Cuda(_))) if self.base_gpu_id != 0 => {
+                anyhow::bail!("Pass one of --device and --base-gpu-id");
+            }
+            #[cfg(feature = "llamacpp")]
+            (Output::LlamaCpp, _, Some(Device::Cuda(index)))
+                if index != 0 && options.tensor_parallel_size > 1 =>
+            {
+                anyhow::bail!(
+                    "llamacpp splits the model over every GPU it sees, it can't start at cuda:{index}. Set environment variable CUDA_VISIBLE_DEVICES instead."
+                );
+            }
+            _ => {}
+        }
+        Ok(options)
+    }
+
+    /// The `--max-loras` of the engine, None to leave LoRA off
+    pub fn max_loras(&self) -> Option<u32> {
+        self.max_loras
+            .or((!self.lora.is_empty()).then_some(self.lora.len() as u32))
+    }
+
     /// Load extra engine arguments from a JSON file
     /// Returns a HashMap of parameter names to values
     pub fn load_extra_engine_args(
@@ -184,12 +1234,115 @@ impl Flags {
     }
 }
 
+#[derive(Default, PartialEq, Eq, ValueEnum, Clone, Copy, Debug)]
+pub enum BatchFormat {
+    #[default]
+    Jsonl,
+    Csv,
+    Parquet,
+}
+
+impl std::fmt::Display for BatchFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = self.to_possible_value().expect("no skipped variants");
+        write!(f, "{}", name.get_name())
+    }
+}
+
+/// One of N slices of a batch file, `i/N`
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct BatchShard {
+    pub index: u32,
+    pub count: u32,
+}
+
+impl BatchShard {
+    pub fn includes(&self, request_id: usize) -> bool {
+        request_id % self.count as usize == self.index as usize
+    }
+}
+
+impl std::str::FromStr for BatchShard {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || format!("'{s}' is not i/N, with i from 0 to N-1");
+        let (index, count) = s.split_once('/').ok_or_else(bad)?;
+        let index: u32 = index.trim().parse().map_err(|_| bad())?;
+        let count: u32 = count.trim().parse().map_err(|_| bad())?;
+        if index >= count {
+            return Err(bad());
+        }
+        Ok(BatchShard { index, count })
+    }
+}
+
+impl std::fmt::Display for BatchShard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.index, self.count)
+    }
+}
+
+/// `key=value`, a librdkafka setting
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct KafkaSetting {
+    pub key: String,
+    pub value: String,
+}
+
+impl std::str::FromStr for KafkaSetting {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => Ok(KafkaSetting {
+                key: key.trim().to_string(),
+                value: value.trim().to_string(),
+            }),
+            _ => Err(format!("'{s}' is not key=value")),
+        }
+    }
+}
+
+impl std::fmt::Display for KafkaSetting {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}={}", self.key, self.value)
+    }
+}
+
+/// `alias=target,target...`, see [`dynamo_llm::model_alias`]
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct ModelAlias {
+    pub alias: String,
+    pub targets: Vec<AliasTarget>,
+}
+
+impl std::str::FromStr for ModelAlias {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (alias, targets) = ModelAliases::parse_entry(s)?;
+        Ok(ModelAlias { alias, targets })
+    }
+}
+
+impl std::fmt::Display for ModelAlias {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let targets: Vec<String> = self.targets.iter().map(|t| t.to_string()).collect();
+        write!(f, "{}={}", self.alias, targets.join(","))
+    }
+}
+
 #[derive(Default, PartialEq, Eq, ValueEnum, Clone, Debug)]
 pub enum RouterMode {
     #[default]
     Random,
     #[value(name = "round-robin")]
     RoundRobin,
+    #[value(name = "least-outstanding")]
+    LeastOutstanding,
+    #[value(name = "power-of-two")]
+    PowerOfTwo,
     #[value(name = "kv")]
     KV,
 }
@@ -204,7 +1357,10 @@ impl From<RouterMode> for RuntimeRouterMode {
     fn from(r: RouterMode) -> RuntimeRouterMode {
         match r {
             RouterMode::RoundRobin => RuntimeRouterMode::RoundRobin,
-            RouterMode::KV => todo!("KV not implemented yet"),
+            RouterMode::LeastOutstanding => RuntimeRouterMode::LeastOutstanding,
+            RouterMode::PowerOfTwo => RuntimeRouterMode::PowerOfTwo,
+            // For the requests the KV router doesn't place itself
+            RouterMode::KV => RuntimeRouterMode::RoundRobin,
             _ => RuntimeRouterMode::Random,
         }
     }
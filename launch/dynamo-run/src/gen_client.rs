@@ -0,0 +1,481 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `dynamo-run gen-client python|typescript --out <dir>`
+//!
+//! Writes a thin client for the HTTP API that `in=http` serves. The routes come from the
+//! [`RouteDoc`]s of an HTTP service built exactly like `in=http` builds it, so a client
+//! generated by a binary has a method for every route that binary serves, and no others.
+//!
+//! Request and response bodies are left as JSON objects. The clients only know the routes,
+//! their path parameters and whether they take JSON or a multipart upload.
+
+use std::fmt::Write as _;
+use std::path::PathBuf;
+
+use anyhow::Context as _;
+use clap::ValueEnum;
+use dynamo_llm::http::service::{service_v2::HttpService, RouteDoc};
+
+#[derive(clap::Parser, Debug)]
+#[command(name = "dynamo-run gen-client")]
+struct GenClientArgs {
+    /// Language of the client
+    #[arg(value_enum)]
+    language: Language,
+
+    /// Directory to write the client to. Created if missing.
+    #[arg(long)]
+    out: PathBuf,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Language {
+    Python,
+    #[value(alias = "ts")]
+    Typescript,
+}
+
+impl Language {
+    fn file_name(&self) -> &'static str {
+        match self {
+            Language::Python => "dynamo_client.py",
+            Language::Typescript => "dynamo_client.ts",
+        }
+    }
+}
+
+/// Entry point for `dynamo-run gen-client ...`. `args` starts at `gen-client`.
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = <GenClientArgs as clap::Parser>::parse_from(args);
+    let (service, _batch_dir) = http_service()?;
+
+    let source = generate(args.language, service.route_docs());
+    std::fs::create_dir_all(&args.out)
+        .with_context(|| format!("Creating {}", args.out.display()))?;
+    let path = args.out.join(args.language.file_name());
+    std::fs::write(&path, source).with_context(|| format!("Writing {}", path.display()))?;
+    println!(
+        "Wrote {} with {} routes",
+        path.display(),
+        service.route_docs().len()
+    );
+    Ok(())
+}
+
+/// The HTTP service `in=http` would run. It is never started.
+fn http_service() -> anyhow::Result<(HttpService, tempfile::TempDir)> {
+    // The batches endpoints need somewhere to put files. Nothing is uploaded here.
+    let batch_dir = tempfile::tempdir()?;
+    let service = crate::input::http::service_builder()
+        .batch_dir(Some(batch_dir.path().to_path_buf()))
+        .build()?;
+    Ok((service, batch_dir))
+}
+
+pub fn generate(language: Language, routes: &[RouteDoc]) -> String {
+    let mut routes: Vec<Route> = routes.iter().map(Route::new).collect();
+    routes.sort_by(|a, b| a.path.cmp(&b.path).then(a.method.cmp(&b.method)));
+    match language {
+        Language::Python => python(&routes),
+        Language::Typescript => typescript(&routes),
+    }
+}
+
+struct Route {
+    method: String,
+    path: String,
+    /// Method name, split into words
+    words: Vec<String>,
+    /// Names of the `{param}` path segments
+    params: Vec<String>,
+    multipart: bool,
+}
+
+impl Route {
+    fn new(doc: &RouteDoc) -> Self {
+        let method = doc.method().as_str().to_string();
+        let mut words = vec![method.to_lowercase()];
+        let mut params = Vec::new();
+        for segment in doc.path().split('/').filter(|s| !s.is_empty()) {
+            if let Some(param) = segment.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+                params.push(param.to_string());
+            } else if segment != "v1" {
+                words.extend(
+                    segment
+                        .split(|c: char| !c.is_ascii_alphanumeric())
+                        .filter(|w| !w.is_empty())
+                        .map(str::to_lowercase),
+                );
+            }
+        }
+        Route {
+            method,
+            path: doc.path().to_string(),
+            words,
+            params,
+            multipart: doc.is_multipart(),
+        }
+    }
+
+    fn has_body(&self) -> bool {
+        self.method != "GET"
+    }
+}
+
+fn camel_case(words: &[String]) -> String {
+    let mut out = String::new();
+    for (i, word) in words.iter().enumerate() {
+        let mut chars = word.chars();
+        match chars.next() {
+            Some(first) if i > 0 => {
+                out.push(first.to_ascii_uppercase());
+                out.extend(chars);
+            }
+            _ => out.push_str(word),
+        }
+    }
+    out
+}
+
+fn python(routes: &[Route]) -> String {
+    let mut out = PYTHON_HEADER.replace("VERSION", env!("CARGO_PKG_VERSION"));
+    for route in routes {
+        let name = route.words.join("_");
+        let mut args = vec!["self".to_string()];
+        args.extend(route.params.iter().map(|p| format!("{p}: str")));
+        let call = if route.multipart {
+            args.push("fields: Dict[str, str]".to_string());
+            args.push("files: Dict[str, File]".to_string());
+            ", fields=fields, files=files"
+        } else if route.has_body() {
+            args.push("body: Optional[Json] = None".to_string());
+            ", body=body"
+        } else {
+            ""
+        };
+        let path = if route.params.is_empty() {
+            format!("\"{}\"", route.path)
+        } else {
+            let mut path = route.path.clone();
+            for param in &route.params {
+                path = path.replace(&format!("{{{param}}}"), &format!("{{_quote({param})}}"));
+            }
+            format!("f\"{path}\"")
+        };
+        let _ = write!(
+            out,
+            "\n    def {name}({}) -> Any:\n        \"\"\"{} {}\"\"\"\n        return self._request(\"{}\", {path}{call})\n",
+            args.join(", "),
+            route.method,
+            route.path,
+            route.method,
+        );
+    }
+    out.push_str(PYTHON_FOOTER);
+    out
+}
+
+fn typescript(routes: &[Route]) -> String {
+    let mut out = TYPESCRIPT_HEADER.replace("VERSION", env!("CARGO_PKG_VERSION"));
+    for route in routes {
+        let name = camel_case(&route.words);
+        let param_names: Vec<String> = route
+            .params
+            .iter()
+            .map(|p| camel_case(&p.split('_').map(str::to_string).collect::<Vec<_>>()))
+            .collect();
+        let mut args: Vec<String> = param_names.iter().map(|p| format!("{p}: string")).collect();
+        let call = if route.multipart {
+            args.push("form: FormData".to_string());
+            ", undefined, form"
+        } else if route.has_body() {
+            args.push("body?: Json".to_string());
+            ", body"
+        } else {
+            ""
+        };
+        let path = if route.params.is_empty() {
+            format!("\"{}\"", route.path)
+        } else {
+            let mut path = route.path.clone();
+            for (param, ts_name) in route.params.iter().zip(&param_names) {
+                path = path.replace(
+                    &format!("{{{param}}}"),
+                    &format!("${{encodeURIComponent({ts_name})}}"),
+                );
+            }
+            format!("`{path}`")
+        };
+        let _ = write!(
+            out,
+            "\n  /** {} {} */\n  async {name}({}): Promise<unknown> {{\n    return this.request(\"{}\", {path}{call});\n  }}\n",
+            route.method,
+            route.path,
+            args.join(", "),
+            route.method,
+        );
+    }
+    out.push_str(TYPESCRIPT_FOOTER);
+    out
+}
+
+const PYTHON_HEADER: &str = r#"# Generated by `dynamo-run gen-client python` from dynamo-run VERSION. Do not edit.
+"""Thin client for the Dynamo HTTP API.
+
+Standard library only. Bodies are plain dicts, shaped as in the OpenAI API reference, with
+Dynamo's request extensions under `nvext`. A streaming response (`"stream": True`) is returned
+as an iterator over the decoded events.
+"""
+
+import json
+import urllib.error
+import urllib.parse
+import urllib.request
+import uuid
+from typing import Any, Dict, Iterator, Optional, Tuple
+
+Json = Dict[str, Any]
+# (filename, contents) of an uploaded file
+File = Tuple[str, bytes]
+
+
+class DynamoError(Exception):
+    def __init__(self, status: int, message: str):
+        super().__init__(f"HTTP {status}: {message}")
+        self.status = status
+        self.message = message
+
+
+class DynamoClient:
+    def __init__(
+        self,
+        base_url: str = "http://localhost:8080",
+        headers: Optional[Dict[str, str]] = None,
+        timeout: float = 600.0,
+    ):
+        self.base_url = base_url.rstrip("/")
+        self.headers = dict(headers or {})
+        self.timeout = timeout
+
+    def _request(
+        self,
+        method: str,
+        path: str,
+        body: Optional[Json] = None,
+        fields: Optional[Dict[str, str]] = None,
+        files: Optional[Dict[str, File]] = None,
+    ) -> Any:
+        headers = dict(self.headers)
+        data = None
+        if files is not None or fields is not None:
+            boundary = uuid.uuid4().hex
+            data = _multipart(boundary, fields or {}, files or {})
+            headers["Content-Type"] = f"multipart/form-data; boundary={boundary}"
+        elif body is not None:
+            data = json.dumps(body).encode()
+            headers["Content-Type"] = "application/json"
+        request = urllib.request.Request(
+            self.base_url + path, data=data, headers=headers, method=method
+        )
+        try:
+            response = urllib.request.urlopen(request, timeout=self.timeout)
+        except urllib.error.HTTPError as e:
+            message = e.read().decode(errors="replace")
+            try:
+                message = json.loads(message)["error"]
+            except (ValueError, KeyError, TypeError):
+                pass
+            raise DynamoError(e.code, message) from None
+        content_type = response.headers.get("Content-Type", "")
+        if content_type.startswith("text/event-stream"):
+            return _events(response)
+        with response:
+            payload = response.read()
+        if content_type.startswith("application/json"):
+            return json.loads(payload)
+        if content_type.startswith("text/"):
+            return payload.decode()
+        return payload
+"#;
+
+const PYTHON_FOOTER: &str = r#"
+
+def _quote(param: str) -> str:
+    return urllib.parse.quote(param, safe="")
+
+
+def _events(response) -> Iterator[Json]:
+    with response:
+        for line in response:
+            line = line.decode().strip()
+            if not line.startswith("data:"):
+                continue
+            data = line[len("data:") :].strip()
+            if data == "[DONE]":
+                return
+            yield json.loads(data)
+
+
+def _multipart(boundary: str, fields: Dict[str, str], files: Dict[str, File]) -> bytes:
+    out = bytearray()
+    for name, value in fields.items():
+        out += (
+            f"--{boundary}\r\n"
+            f'Content-Disposition: form-data; name="{name}"\r\n\r\n'
+            f"{value}\r\n"
+        ).encode()
+    for name, (filename, contents) in files.items():
+        out += (
+            f"--{boundary}\r\n"
+            f'Content-Disposition: form-data; name="{name}"; filename="{filename}"\r\n'
+            "Content-Type: application/octet-stream\r\n\r\n"
+        ).encode()
+        out += contents + b"\r\n"
+    out += f"--{boundary}--\r\n".encode()
+    return bytes(out)
+"#;
+
+const TYPESCRIPT_HEADER: &str = r#"// Generated by `dynamo-run gen-client typescript` from dynamo-run VERSION. Do not edit.
+
+/**
+ * Thin client for the Dynamo HTTP API, using `fetch`.
+ *
+ * Bodies are plain objects, shaped as in the OpenAI API reference, with Dynamo's request
+ * extensions under `nvext`. A streaming response (`stream: true`) is returned as an async
+ * iterator over the decoded events.
+ */
+
+export type Json = Record<string, unknown>;
+
+export class DynamoError extends Error {
+  constructor(
+    public readonly status: number,
+    message: string,
+  ) {
+    super(`HTTP ${status}: ${message}`);
+  }
+}
+
+export interface DynamoClientOptions {
+  baseUrl?: string;
+  headers?: Record<string, string>;
+}
+
+export class DynamoClient {
+  private readonly baseUrl: string;
+  private readonly headers: Record<string, string>;
+
+  constructor(options: DynamoClientOptions = {}) {
+    this.baseUrl = (options.baseUrl ?? "http://localhost:8080").replace(/\/+$/, "");
+    this.headers = options.headers ?? {};
+  }
+
+  private async request(
+    method: string,
+    path: string,
+    body?: Json,
+    form?: FormData,
+  ): Promise<unknown> {
+    const headers: Record<string, string> = { ...this.headers };
+    let payload: BodyInit | undefined;
+    if (form !== undefined) {
+      payload = form;
+    } else if (body !== undefined) {
+      payload = JSON.stringify(body);
+      headers["Content-Type"] = "application/json";
+    }
+    const response = await fetch(this.baseUrl + path, { method, headers, body: payload });
+    if (!response.ok) {
+      const text = await response.text();
+      let message = text;
+      try {
+        message = JSON.parse(text).error ?? text;
+      } catch {
+        // not JSON, keep the text
+      }
+      throw new DynamoError(response.status, message);
+    }
+    const contentType = response.headers.get("Content-Type") ?? "";
+    if (contentType.startsWith("text/event-stream")) {
+      return events(response);
+    }
+    if (contentType.startsWith("application/json")) {
+      return response.json();
+    }
+    if (contentType.startsWith("text/")) {
+      return response.text();
+    }
+    return response.arrayBuffer();
+  }
+"#;
+
+const TYPESCRIPT_FOOTER: &str = r#"}
+
+async function* events(response: Response): AsyncGenerator<Json> {
+  const reader = response.body!.pipeThrough(new TextDecoderStream()).getReader();
+  let buffer = "";
+  for (;;) {
+    const { value, done } = await reader.read();
+    if (done) {
+      return;
+    }
+    buffer += value;
+    let newline: number;
+    while ((newline = buffer.indexOf("\n")) >= 0) {
+      const line = buffer.slice(0, newline).trim();
+      buffer = buffer.slice(newline + 1);
+      if (!line.startsWith("data:")) {
+        continue;
+      }
+      const data = line.slice("data:".length).trim();
+      if (data === "[DONE]") {
+        return;
+      }
+      yield JSON.parse(data);
+    }
+  }
+}
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_python() {
+        let (service, _dir) = http_service().unwrap();
+        let source = generate(Language::Python, service.route_docs());
+        assert!(
+            source.contains("def post_chat_completions(self, body: Optional[Json] = None) -> Any:")
+        );
+        assert!(source
+            .contains(r#"return self._request("GET", f"/v1/files/{_quote(file_id)}/content")"#));
+        assert!(source.contains(
+            "def post_audio_transcriptions(self, fields: Dict[str, str], files: Dict[str, File])"
+        ));
+    }
+
+    #[test]
+    fn test_typescript() {
+        let (service, _dir) = http_service().unwrap();
+        let source = generate(Language::Typescript, service.route_docs());
+        assert!(source.contains("async postChatCompletions(body?: Json): Promise<unknown>"));
+        assert!(source.contains(
+            "return this.request(\"GET\", `/v1/files/${encodeURIComponent(fileId)}/content`);"
+        ));
+        assert!(source.contains("async postAudioTranscriptions(form: FormData)"));
+    }
+}
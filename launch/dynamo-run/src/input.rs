@@ -13,8 +13,17 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod arena;
 pub mod batch;
+pub mod bench;
+pub mod bench_compare;
 mod common;
 pub mod endpoint;
 pub mod http;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod mcp;
+#[cfg(feature = "redis")]
+pub mod redis;
 pub mod text;
+pub mod watch;
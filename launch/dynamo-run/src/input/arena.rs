@@ -0,0 +1,359 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `in=arena:<out>` sends each prompt to the `out=` engine and to a second one, shows the two
+//! answers side by side, and asks which is better. Votes and latencies are appended to a JSON
+//! Lines file.
+
+use std::io::{ErrorKind, Write as _};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use dynamo_llm::types::openai::chat_completions::{
+    NvCreateChatCompletionRequest, OpenAIChatCompletionsStreamingEngine,
+};
+use dynamo_runtime::{pipeline::Context, Runtime};
+use futures::StreamExt;
+use serde::Serialize;
+
+use crate::input::common;
+use crate::{EngineConfig, Flags, RequestTemplate};
+
+/// Max response tokens for each answer
+const MAX_TOKENS: u32 = 8192;
+
+const DEFAULT_RESULTS_FILE: &str = "arena.jsonl";
+
+/// Between the two columns
+const GUTTER: &str = " │ ";
+
+const CHOICES: &[(&str, Option<Winner>)] = &[
+    ("A is better", Some(Winner::A)),
+    ("B is better", Some(Winner::B)),
+    ("Tie", Some(Winner::Tie)),
+    ("Both are bad", Some(Winner::BothBad)),
+    ("Skip", None),
+];
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum Winner {
+    A,
+    B,
+    Tie,
+    BothBad,
+}
+
+/// One engine's answer to a prompt. Also what `in=bench:<engine>` measures.
+#[derive(Serialize, Default, Debug)]
+pub(super) struct Answer {
+    pub output: String,
+    pub response: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Time to first token
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttft_ms: Option<u64>,
+    pub elapsed_ms: u64,
+    /// Streamed chunks with content, about one per token
+    pub tokens_out: usize,
+}
+
+/// A line of the results file
+#[derive(Serialize, Debug)]
+struct Vote<'a> {
+    prompt: &'a str,
+    a: &'a Answer,
+    b: &'a Answer,
+    winner: Winner,
+}
+
+pub(super) struct Contender {
+    /// The out= value
+    pub output: String,
+    pub service_name: String,
+    pub engine: OpenAIChatCompletionsStreamingEngine,
+}
+
+/// `contenders` are the out= value and engine of A then B
+pub async fn run(
+    runtime: Runtime,
+    flags: Flags,
+    contenders: [(String, EngineConfig); 2],
+    template: Option<RequestTemplate>,
+) -> anyhow::Result<()> {
+    let cancel_token = runtime.primary_token();
+    let results_path = flags
+        .arena_results
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_RESULTS_FILE));
+
+    let [(output_a, config_a), (output_b, config_b)] = contenders;
+    let prepared_a = common::prepare_engine(runtime.clone(), flags.clone(), config_a).await?;
+    let prepared_b = common::prepare_engine(runtime, flags, config_b).await?;
+    let a = Contender {
+        output: output_a,
+        service_name: prepared_a.service_name,
+        engine: prepared_a.engine,
+    };
+    let b = Contender {
+        output: output_b,
+        service_name: prepared_b.service_name,
+        engine: prepared_b.engine,
+    };
+
+    let mut results = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&results_path)
+        .map_err(|err| anyhow::anyhow!("{}: {err}", results_path.display()))?;
+    tracing::info!(
+        "A is {}, B is {}. Votes go to {}. Ctrl-c to exit",
+        a.output,
+        b.output,
+        results_path.display()
+    );
+
+    let theme = dialoguer::theme::ColorfulTheme::default();
+    let mut history = dialoguer::BasicHistory::default();
+    let mut tally = [0usize; 4];
+    while !cancel_token.is_cancelled() {
+        let input_ui = dialoguer::Input::<String>::with_theme(&theme)
+            .history_with(&mut history)
+            .with_prompt("User");
+        let prompt = match input_ui.interact_text() {
+            Ok(prompt) => prompt,
+            Err(dialoguer::Error::IO(err)) => {
+                if err.kind() != ErrorKind::Interrupted {
+                    tracing::info!("IO error: {}", err.kind());
+                }
+                break;
+            }
+        };
+
+        // Both at once, with a progress line until they are done
+        let progress = [AtomicUsize::new(0), AtomicUsize::new(0)];
+        let answers = futures::future::join(
+            answer(&a, &prompt, template.as_ref(), &progress[0]),
+            answer(&b, &prompt, template.as_ref(), &progress[1]),
+        );
+        tokio::pin!(answers);
+        let mut ticker = tokio::time::interval(Duration::from_millis(200));
+        let (answer_a, answer_b) = loop {
+            tokio::select! {
+                answers = &mut answers => break answers,
+                _ = ticker.tick() => {
+                    eprint!(
+                        "\rA: {} tokens, B: {} tokens",
+                        progress[0].load(Ordering::Relaxed),
+                        progress[1].load(Ordering::Relaxed)
+                    );
+                }
+                _ = cancel_token.cancelled() => return Ok(()),
+            }
+        };
+        // Clear the progress line
+        eprint!("\r\x1b[2K");
+
+        print_side_by_side(&answer_a, &answer_b);
+
+        let selection = dialoguer::Select::with_theme(&theme)
+            .with_prompt("Which answer is better?")
+            .items(&CHOICES.iter().map(|(label, _)| *label).collect::<Vec<_>>())
+            .default(0)
+            .interact_opt();
+        let winner = match selection {
+            Ok(Some(i)) => CHOICES[i].1,
+            Ok(None) => None,
+            Err(dialoguer::Error::IO(_)) => break,
+        };
+        let Some(winner) = winner else {
+            continue;
+        };
+        tally[winner as usize] += 1;
+        let vote = Vote {
+            prompt: &prompt,
+            a: &answer_a,
+            b: &answer_b,
+            winner,
+        };
+        writeln!(results, "{}", serde_json::to_string(&vote)?)?;
+        results.flush()?;
+    }
+
+    let [a_wins, b_wins, ties, both_bad] = tally;
+    if a_wins + b_wins + ties + both_bad > 0 {
+        println!(
+            "{} won {a_wins}, {} won {b_wins}, {ties} ties, {both_bad} both bad",
+            a.output, b.output
+        );
+    }
+    cancel_token.cancel(); // stop everything else
+    Ok(())
+}
+
+/// Run `prompt` on one engine. Failures are part of the answer, the other engine may still
+/// have done fine.
+pub(super) async fn answer(
+    contender: &Contender,
+    prompt: &str,
+    template: Option<&RequestTemplate>,
+    tokens_out: &AtomicUsize,
+) -> Answer {
+    let mut answer = Answer {
+        output: contender.output.clone(),
+        ..Default::default()
+    };
+    let start = Instant::now();
+    if let Err(err) = generate(contender, prompt, template, tokens_out, start, &mut answer).await {
+        answer.error = Some(err.to_string());
+    }
+    answer.elapsed_ms = start.elapsed().as_millis() as u64;
+    answer.tokens_out = tokens_out.load(Ordering::Relaxed);
+    answer
+}
+
+async fn generate(
+    contender: &Contender,
+    prompt: &str,
+    template: Option<&RequestTemplate>,
+    tokens_out: &AtomicUsize,
+    start: Instant,
+    answer: &mut Answer,
+) -> anyhow::Result<()> {
+    let user_message = async_openai::types::ChatCompletionRequestMessage::User(
+        async_openai::types::ChatCompletionRequestUserMessage {
+            content: async_openai::types::ChatCompletionRequestUserMessageContent::Text(
+                prompt.to_string(),
+            ),
+            name: None,
+        },
+    );
+    let inner = async_openai::types::CreateChatCompletionRequestArgs::default()
+        .messages(vec![user_message])
+        .model(template.map_or_else(|| contender.service_name.clone(), |t| t.model.clone()))
+        .stream(true)
+        .max_completion_tokens(template.map_or(MAX_TOKENS, |t| t.max_completion_tokens))
+        .temperature(template.map_or(0.7, |t| t.temperature))
+        .n(1)
+        .build()?;
+    let request = NvCreateChatCompletionRequest { inner, nvext: None };
+
+    let mut stream = contender.engine.generate(Context::new(request)).await?;
+    while let Some(item) = stream.next().await {
+        match (item.data.as_ref(), item.event.as_deref()) {
+            (Some(data), _) => {
+                let Some(choice) = data.inner.choices.first() else {
+                    continue;
+                };
+                if let Some(content) = &choice.delta.content {
+                    answer
+                        .ttft_ms
+                        .get_or_insert(start.elapsed().as_millis() as u64);
+                    answer.response += content;
+                    tokens_out.fetch_add(1, Ordering::Relaxed);
+                }
+                if choice.finish_reason.is_some() {
+                    break;
+                }
+            }
+            (None, Some("error")) => {
+                anyhow::bail!(item.comment.unwrap_or_default().join(", "));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn print_side_by_side(a: &Answer, b: &Answer) {
+    let (_, columns) = dialoguer::console::Term::stdout().size();
+    let width = (columns as usize).saturating_sub(GUTTER.chars().count()) / 2;
+    let width = width.max(20);
+
+    let left = column("A", a, width);
+    let right = column("B", b, width);
+    let mut stdout = std::io::stdout().lock();
+    for i in 0..left.len().max(right.len()) {
+        let l = left.get(i).map(String::as_str).unwrap_or("");
+        let r = right.get(i).map(String::as_str).unwrap_or("");
+        let padding = width - l.chars().count();
+        let _ = writeln!(stdout, "{l}{:padding$}{GUTTER}{r}", "");
+    }
+    let _ = writeln!(stdout);
+}
+
+/// The lines of one side: a header, the answer wrapped to `width`, and its timings
+fn column(label: &str, answer: &Answer, width: usize) -> Vec<String> {
+    let mut lines = wrap(&format!("{label}: {}", answer.output), width);
+    lines.push("─".repeat(width));
+    match &answer.error {
+        Some(err) => lines.extend(wrap(&format!("Error: {err}"), width)),
+        None => lines.extend(wrap(answer.response.trim(), width)),
+    }
+    lines.push("─".repeat(width));
+    let ttft = answer
+        .ttft_ms
+        .map(|ms| format!(", first token {ms}ms"))
+        .unwrap_or_default();
+    lines.extend(wrap(
+        &format!(
+            "{} tokens in {}ms{ttft}",
+            answer.tokens_out, answer.elapsed_ms
+        ),
+        width,
+    ));
+    lines
+}
+
+/// Split `text` into lines of at most `width` characters, breaking at spaces when possible
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = vec![];
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split(' ') {
+            let needed =
+                line.chars().count() + usize::from(!line.is_empty()) + word.chars().count();
+            if needed > width && !line.is_empty() {
+                lines.push(std::mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(word);
+            // A word longer than the column
+            while line.chars().count() > width {
+                let split = line.char_indices().nth(width).map(|(i, _)| i).unwrap();
+                lines.push(line[..split].to_string());
+                line = line[split..].to_string();
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(wrap("one two three", 7), vec!["one two", "three"]);
+        assert_eq!(wrap("abcdefghij", 4), vec!["abcd", "efgh", "ij"]);
+        assert_eq!(wrap("a\n\nb", 4), vec!["a", "", "b"]);
+    }
+}
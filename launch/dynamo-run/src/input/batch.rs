@@ -14,7 +14,7 @@
 // limitations under the License.
 
 use anyhow::Context as _;
-use async_openai::types::FinishReason;
+use async_openai::types::{FinishReason, Stop};
 use dynamo_llm::model_card::model::ModelDeploymentCard;
 use dynamo_llm::preprocessor::OpenAIPreprocessor;
 use dynamo_llm::request_template::RequestTemplate;
@@ -24,27 +24,78 @@ use dynamo_llm::types::openai::chat_completions::{
 use dynamo_runtime::{pipeline::Context, runtime::CancellationToken, Runtime};
 use futures::StreamExt;
 use serde::{Deserialize, Serialize};
-use std::cmp;
+use std::collections::{BTreeMap, HashSet};
+use std::io::IsTerminal as _;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+use tokio::io::AsyncBufReadExt;
+use tokio::sync::Semaphore;
 
-use crate::input::common;
+use crate::input::common::{self, Percentiles, Sampling};
 use crate::{EngineConfig, Flags};
 
+mod format;
+use format::Sink;
+mod shard;
+pub use shard::coordinate;
+
 /// Max tokens in each response.
 /// TODO: For batch mode this should be the full context size of the model
 const MAX_TOKENS: u32 = 8192;
 
-const OUTPUT_FILENAME: &str = "output.jsonl";
+/// Updated by the prompts in flight, read by the progress line and the summary
+#[derive(Default)]
+struct Stats {
+    succeeded: AtomicU64,
+    failed: AtomicU64,
+    tokens_in: AtomicU64,
+    tokens_out: AtomicU64,
+    /// Total time and time to first token of each prompt that succeeded
+    latencies: Mutex<Vec<(Duration, Option<Duration>)>>,
+}
+
+impl Stats {
+    fn finished(&self) -> u64 {
+        self.succeeded.load(Ordering::Relaxed) + self.failed.load(Ordering::Relaxed)
+    }
+}
+
+/// End of run numbers, printed and written to --batch-report
+#[derive(Serialize, Debug)]
+struct Summary {
+    succeeded: u64,
+    failed: u64,
+    /// Done by an earlier run, with --batch-resume
+    skipped: u64,
+    elapsed_secs: f64,
+    tokens_in: u64,
+    tokens_out: u64,
+    tokens_out_per_sec: f64,
+    latency_ms: Option<Percentiles>,
+    ttft_ms: Option<Percentiles>,
+}
 
 #[derive(Serialize, Deserialize, Default, Debug)]
 struct Entry {
-    // The input files only have this
+    // The input files only have this, and optionally the sampling fields
     text: String,
 
+    // Sampling for this prompt, instead of the flags, --request-template or the defaults.
+    // Echoed in the output so a sweep can be told apart.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    stop: Option<Stop>,
+
     response: Option<String>,
 
     #[serde(default)]
@@ -59,7 +110,9 @@ struct Entry {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     finish_reason: Option<FinishReason>,
 
-    #[serde(skip, default)]
+    // Line of the prompt in the input, not counting empty lines. Resuming skips those in the
+    // output already.
+    #[serde(default)]
     request_id: usize,
 }
 
@@ -80,6 +133,35 @@ pub async fn run(
         );
     }
 
+    let concurrency = flags.batch_concurrency.map(|n| n.max(1) as usize);
+    let (output_file, format) = format::resolve(
+        &input_jsonl,
+        flags.batch_output.as_deref(),
+        flags.batch_format,
+        flags.batch_shard,
+    )?;
+    let completed = if flags.batch_resume {
+        let completed = load_completed(&output_file).await?;
+        tracing::info!(
+            "Resuming, {} prompts done already in {}",
+            completed.len(),
+            output_file.display()
+        );
+        completed
+    } else {
+        HashSet::new()
+    };
+    // Before the engine, so a bad --batch-format fails fast
+    let sink = Sink::open(&output_file, format, flags.batch_resume).await?;
+    let report_path = flags.batch_report.clone();
+    let sampling = Arc::new(Sampling::from_flags(&flags));
+    let shard = flags.batch_shard;
+    let in_shard = move |request_id: usize| shard.is_none_or(|shard| shard.includes(request_id));
+    let total = (0..count_prompts(&input_jsonl).await?)
+        .filter(|request_id| in_shard(*request_id) && !completed.contains(request_id))
+        .count();
+    // Shards run side by side, one progress line each would garble the terminal
+    let draw_progress = shard.is_none() && std::io::stderr().is_terminal();
     let prepared_engine = common::prepare_engine(runtime, flags, engine_config).await?;
     let service_name_ref = Arc::new(prepared_engine.service_name);
 
@@ -90,18 +172,19 @@ pub async fn run(
     };
     let (done_entries_tx, done_entries_rx) = tokio::sync::mpsc::channel(64);
     let dw_cancel_token = cancel_token.clone();
-    let mut output_file = input_jsonl.clone();
-    output_file.set_file_name(OUTPUT_FILENAME);
-    tokio::spawn(async move {
-        if let Err(err) = output_writer(dw_cancel_token, done_entries_rx, &output_file).await {
+    let writer = tokio::spawn(async move {
+        if let Err(err) = output_writer(dw_cancel_token, done_entries_rx, sink).await {
             tracing::error!(%err, "Failed writing output to {}", output_file.display());
         }
     });
+    let in_flight = Arc::new(Semaphore::new(
+        concurrency.unwrap_or(Semaphore::MAX_PERMITS),
+    ));
 
-    let tokens_in = Arc::new(AtomicU64::new(0));
-    let tokens_out = Arc::new(AtomicU64::new(0));
+    let stats = Arc::new(Stats::default());
     let mut handles = vec![];
     let mut num_entries = 0;
+    let mut num_skipped = 0;
     let input_file = tokio::fs::File::open(&input_jsonl)
         .await
         .with_context(|| input_jsonl.display().to_string())?;
@@ -109,6 +192,7 @@ pub async fn run(
 
     tracing::info!("Timer start.");
     let start = Instant::now();
+    let progress = tokio::spawn(show_progress(stats.clone(), total, start, draw_progress));
     let mut lines = buffered_input.lines();
     let template: Option<Arc<RequestTemplate>> = template.map(Arc::new);
     while let Ok(Some(line)) = lines.next_line().await {
@@ -120,6 +204,17 @@ pub async fn run(
         }
         let request_id = num_entries;
         num_entries += 1;
+        if !in_shard(request_id) {
+            // Another shard's
+            let _ = done_entries_tx.send((request_id, None)).await;
+            continue;
+        }
+        if completed.contains(&request_id) {
+            num_skipped += 1;
+            // Nothing to write, but the writer waits for each request_id in turn
+            let _ = done_entries_tx.send((request_id, None)).await;
+            continue;
+        }
         let mut entry: Entry = match serde_json::from_str(&line) {
             Ok(entry) => entry,
             Err(err) => {
@@ -128,32 +223,44 @@ pub async fn run(
         };
         entry.request_id = request_id;
 
+        // Keep reading the input while the engine works, up to the concurrency limit
+        let permit = tokio::select! {
+            _ = cancel_token.cancelled() => break,
+            permit = in_flight.clone().acquire_owned() => permit?,
+        };
         let engine = prepared_engine.engine.clone();
         let pre_processor = pre_processor.clone();
-        let tokens_in = tokens_in.clone();
-        let tokens_out = tokens_out.clone();
+        let stats = stats.clone();
         let done_entries_tx = done_entries_tx.clone();
         let service_name_ref = service_name_ref.clone();
         let template_clone = template.clone();
+        let sampling = sampling.clone();
         let handle = tokio::spawn(async move {
+            let _permit = permit;
             let local_start = Instant::now();
-            let response = match evaluate(
+            let (response, first_token) = match evaluate(
                 request_id,
                 service_name_ref.as_str(),
                 engine,
                 &mut entry,
                 template_clone,
+                &sampling,
             )
             .await
             {
                 Ok(r) => r,
                 Err(err) => {
                     tracing::error!(%err, entry.text, "Failed evaluating prompt");
+                    stats.failed.fetch_add(1, Ordering::Relaxed);
+                    // The writer needs to know not to wait for it
+                    let _ = done_entries_tx.send((request_id, None)).await;
                     return;
                 }
             };
             let local_elapsed = Instant::now() - local_start;
             entry.elapsed_ms = local_elapsed.as_millis() as usize;
+            let ttft = first_token.map(|t| t - local_start);
+            stats.latencies.lock().unwrap().push((local_elapsed, ttft));
 
             if let Some(pre) = pre_processor {
                 // Note this does not include the prompt template. Probably TODO
@@ -171,50 +278,160 @@ pub async fn run(
                         0
                     }
                 };
-                tokens_in.fetch_add(entry.tokens_in as u64, Ordering::Relaxed);
-                tokens_out.fetch_add(entry.tokens_out as u64, Ordering::Relaxed);
+                stats
+                    .tokens_in
+                    .fetch_add(entry.tokens_in as u64, Ordering::Relaxed);
+                stats
+                    .tokens_out
+                    .fetch_add(entry.tokens_out as u64, Ordering::Relaxed);
             }
             entry.response = Some(response);
+            stats.succeeded.fetch_add(1, Ordering::Relaxed);
 
-            let _ = done_entries_tx.send(entry).await;
+            let _ = done_entries_tx.send((request_id, Some(entry))).await;
         });
         handles.push(handle);
     }
     tokio::select! {
         _ = cancel_token.cancelled() => {
-            // Don't print stats
+            // Don't print stats, but keep what's done for --batch-resume
+            progress.abort();
+            let _ = writer.await;
             return Ok(());
         }
         _ = futures::future::join_all(handles) => {
         }
     }
+    // The writer stops once it has all the entries
+    drop(done_entries_tx);
+    let _ = writer.await;
+    progress.abort();
+    if draw_progress {
+        // Clear the progress line
+        eprint!("\r\x1b[2K");
+    }
+
     let elapsed = Instant::now() - start;
+    let summary = summarize(&stats, num_skipped, elapsed);
+    print_summary(&summary, elapsed);
+    if let Some(path) = report_path {
+        std::fs::write(&path, serde_json::to_string_pretty(&summary)?)
+            .with_context(|| path.display().to_string())?;
+        tracing::info!("Report written to {}", path.display());
+    }
+    cancel_token.cancel(); // stop everything else
+
+    Ok(())
+}
+
+/// Prompts to run, the non-empty lines of the input
+async fn count_prompts(input_jsonl: &Path) -> anyhow::Result<usize> {
+    let input_file = tokio::fs::File::open(input_jsonl)
+        .await
+        .with_context(|| input_jsonl.display().to_string())?;
+    let mut lines = tokio::io::BufReader::new(input_file).lines();
+    let mut count = 0;
+    while let Some(line) = lines.next_line().await? {
+        if !line.is_empty() {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// Until aborted, a line on stderr with how far along we are. Logged every 30s instead unless
+/// `draw`.
+async fn show_progress(stats: Arc<Stats>, total: usize, start: Instant, draw: bool) {
+    let tick = if draw { 1 } else { 30 };
+    let mut ticker = tokio::time::interval(Duration::from_secs(tick));
+    ticker.tick().await; // the first one is immediate
+    loop {
+        ticker.tick().await;
+        let finished = stats.finished();
+        let elapsed = start.elapsed();
+        let tokens_per_sec = stats.tokens_out.load(Ordering::Relaxed) / elapsed.as_secs().max(1);
+        let eta = match finished {
+            0 => "?".to_string(),
+            _ => {
+                let left = total.saturating_sub(finished as usize) as u32;
+                let eta = elapsed / finished as u32 * left;
+                humantime::format_duration(Duration::from_secs(eta.as_secs())).to_string()
+            }
+        };
+        let failed = stats.failed.load(Ordering::Relaxed);
+        let line = format!(
+            "{finished}/{total} prompts, {failed} failed, {tokens_per_sec} tokens/s, ETA {eta}"
+        );
+        if draw {
+            eprint!("\r\x1b[2K{line}");
+        } else {
+            tracing::info!("{line}");
+        }
+    }
+}
+
+fn summarize(stats: &Stats, skipped: u64, elapsed: Duration) -> Summary {
+    let latencies = stats.latencies.lock().unwrap();
+    let ms = |d: &Duration| d.as_secs_f64() * 1000.0;
+    let tokens_out = stats.tokens_out.load(Ordering::Relaxed);
+    Summary {
+        succeeded: stats.succeeded.load(Ordering::Relaxed),
+        failed: stats.failed.load(Ordering::Relaxed),
+        skipped,
+        elapsed_secs: elapsed.as_secs_f64(),
+        tokens_in: stats.tokens_in.load(Ordering::Relaxed),
+        tokens_out,
+        tokens_out_per_sec: tokens_out as f64 / elapsed.as_secs_f64().max(0.001),
+        latency_ms: Percentiles::of(latencies.iter().map(|(total, _)| ms(total)).collect()),
+        ttft_ms: Percentiles::of(
+            latencies
+                .iter()
+                .filter_map(|(_, t)| t.as_ref().map(ms))
+                .collect(),
+        ),
+    }
+}
+
+fn print_summary(summary: &Summary, elapsed: Duration) {
     let elapsed_clean = Duration::from_millis(elapsed.as_millis() as u64);
-    let tokens_in = Arc::into_inner(tokens_in).unwrap().into_inner();
-    let tokens_out = Arc::into_inner(tokens_out).unwrap().into_inner();
-    tokio::time::sleep(Duration::from_millis(1)).await; // Let output_writer finish stdout write
     tracing::info!(
-        "Ran {} files in {}. Tokens in: {} ({}/s). Tokens out: {} ({}/s)",
-        num_entries,
+        "Ran {} prompts in {}, {} failed{}. Tokens in: {}. Tokens out: {} ({:.0}/s)",
+        summary.succeeded + summary.failed,
         humantime::format_duration(elapsed_clean),
-        tokens_in,
-        tokens_in / cmp::max(elapsed.as_secs(), 1),
-        tokens_out,
-        tokens_out / cmp::max(elapsed.as_secs(), 1),
+        summary.failed,
+        match summary.skipped {
+            0 => String::new(),
+            skipped => format!(", {skipped} done before"),
+        },
+        summary.tokens_in,
+        summary.tokens_out,
+        summary.tokens_out_per_sec,
     );
-    cancel_token.cancel(); // stop everything else
-
-    Ok(())
+    for (name, p) in [
+        ("Latency", &summary.latency_ms),
+        ("Time to first token", &summary.ttft_ms),
+    ] {
+        if let Some(p) = p {
+            tracing::info!(
+                "{name} ms: mean {:.0}, p50 {:.0}, p90 {:.0}, p99 {:.0}",
+                p.mean,
+                p.p50,
+                p.p90,
+                p.p99
+            );
+        }
+    }
 }
 
-// Run a single prompt through the engine
+/// Run a single prompt through the engine. Returns the response and when its first token came.
 async fn evaluate(
     request_id: usize,
     service_name: &str,
     engine: OpenAIChatCompletionsStreamingEngine,
     entry: &mut Entry,
     template: Option<Arc<RequestTemplate>>,
-) -> anyhow::Result<String> {
+    sampling: &Sampling,
+) -> anyhow::Result<(String, Option<Instant>)> {
     let user_message = async_openai::types::ChatCompletionRequestMessage::User(
         async_openai::types::ChatCompletionRequestUserMessage {
             content: async_openai::types::ChatCompletionRequestUserMessageContent::Text(
@@ -223,24 +440,47 @@ async fn evaluate(
             name: None,
         },
     );
-    let inner = async_openai::types::CreateChatCompletionRequestArgs::default()
-        .messages(vec![user_message])
+    let system_message = sampling.system_prompt.as_ref().map(|prompt| {
+        async_openai::types::ChatCompletionRequestMessage::System(
+            async_openai::types::ChatCompletionRequestSystemMessage {
+                content: async_openai::types::ChatCompletionRequestSystemMessageContent::Text(
+                    prompt.clone(),
+                ),
+                name: None,
+            },
+        )
+    });
+    let messages = system_message.into_iter().chain([user_message]).collect();
+    let mut args = async_openai::types::CreateChatCompletionRequestArgs::default();
+    args.messages(messages)
         .model(
             template
                 .as_ref()
                 .map_or_else(|| service_name.to_string(), |t| t.model.clone()),
         )
         .stream(true)
-        .max_completion_tokens(
+        .max_completion_tokens(entry.max_tokens.or(sampling.max_tokens).unwrap_or_else(|| {
             template
                 .as_ref()
-                .map_or(MAX_TOKENS, |t| t.max_completion_tokens),
-        )
-        .temperature(template.as_ref().map_or(0.7, |t| t.temperature))
-        .build()?;
+                .map_or(MAX_TOKENS, |t| t.max_completion_tokens)
+        }))
+        .temperature(
+            entry
+                .temperature
+                .or(sampling.temperature)
+                .unwrap_or_else(|| template.as_ref().map_or(0.7, |t| t.temperature)),
+        );
+    if let Some(top_p) = entry.top_p.or(sampling.top_p) {
+        args.top_p(top_p);
+    }
+    if let Some(stop) = entry.stop.clone().or_else(|| sampling.stop.clone()) {
+        args.stop(stop);
+    }
+    let inner = args.build()?;
     let req = NvCreateChatCompletionRequest { inner, nvext: None };
     let mut stream = engine.generate(Context::new(req)).await?;
     let mut output = String::new();
+    let mut first_token = None;
     while let Some(item) = stream.next().await {
         match (item.data.as_ref(), item.event.as_deref()) {
             (Some(data), _) => {
@@ -248,6 +488,7 @@ async fn evaluate(
                 let choice = data.inner.choices.first();
                 let chat_comp = choice.as_ref().unwrap();
                 if let Some(c) = &chat_comp.delta.content {
+                    first_token.get_or_insert_with(Instant::now);
                     output += c;
                 }
                 entry.finish_reason = chat_comp.finish_reason;
@@ -275,18 +516,52 @@ async fn evaluate(
             }
         }
     }
-    Ok(output)
+    Ok((output, first_token))
+}
+
+/// The request_id of the entries in an earlier run's output. A line cut short when that run was
+/// killed is dropped from the file, its prompt runs again.
+async fn load_completed(output_file: &Path) -> anyhow::Result<HashSet<usize>> {
+    let contents = match tokio::fs::read_to_string(output_file).await {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(HashSet::new()),
+        Err(err) => return Err(err).with_context(|| output_file.display().to_string()),
+    };
+    let mut completed = HashSet::new();
+    let mut valid = String::new();
+    for line in contents.lines() {
+        match serde_json::from_str::<Entry>(line) {
+            Ok(entry) => {
+                completed.insert(entry.request_id);
+                valid.push_str(line);
+                valid.push('\n');
+            }
+            Err(err) => {
+                tracing::warn!(%err, "Dropping unreadable line from {}", output_file.display());
+            }
+        }
+    }
+    if valid.len() != contents.len() {
+        tokio::fs::write(output_file, valid)
+            .await
+            .with_context(|| output_file.display().to_string())?;
+    }
+    Ok(completed)
 }
 
+/// Write the entries in input order. They finish in any order, so the ones that come early wait
+/// in `pending` for those before them. `None` is a prompt that failed or was done before, it's
+/// skipped.
 async fn output_writer(
     cancel_token: CancellationToken,
-    mut entries_rx: tokio::sync::mpsc::Receiver<Entry>,
-    output_file: &Path,
+    mut entries_rx: tokio::sync::mpsc::Receiver<(usize, Option<Entry>)>,
+    mut sink: Sink,
 ) -> anyhow::Result<()> {
     let mut num_completed = 0;
-    let mut f = tokio::fs::File::create(output_file).await?;
+    let mut next_id = 0;
+    let mut pending = BTreeMap::new();
     loop {
-        let entry = tokio::select! {
+        let (request_id, maybe_entry) = tokio::select! {
             _ = cancel_token.cancelled() => {
                 break;
             }
@@ -297,14 +572,43 @@ async fn output_writer(
                 }
             }
         };
-        let mut s = serde_json::to_string(&entry)?;
-        s.push('\n');
-        f.write_all(s.as_bytes()).await?;
-
-        num_completed += 1;
-        // TODO: Progress bar. We'd have to count the lines in the input first,
-        // and the input maybe be large
-        tracing::info!(entry.request_id, entry.tokens_out, "Saved {num_completed}");
+        pending.insert(request_id, maybe_entry);
+        while let Some(maybe_entry) = pending.remove(&next_id) {
+            next_id += 1;
+            let Some(entry) = maybe_entry else {
+                continue;
+            };
+            sink.write(&entry).await?;
+
+            num_completed += 1;
+            // The progress line says how far along we are
+            tracing::debug!(entry.request_id, entry.tokens_out, "Saved {num_completed}");
+        }
     }
+    sink.finish().await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_load_completed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("output.jsonl");
+        assert!(load_completed(&path).await.unwrap().is_empty());
+
+        let done = concat!(
+            r#"{"text":"a","response":"A","request_id":0}"#,
+            "\n",
+            r#"{"text":"c","response":"C","request_id":2}"#,
+            "\n",
+        );
+        // Killed halfway through the third line
+        std::fs::write(&path, format!(r#"{done}{{"text":"d","resp"#)).unwrap();
+        let completed = load_completed(&path).await.unwrap();
+        assert_eq!(completed, HashSet::from([0, 2]));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), done);
+    }
+}
@@ -0,0 +1,330 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The batch output file. JSON Lines has the entries as they are. CSV and Parquet have one
+//! column per field, always all of them and in the same order: request_id, text, response,
+//! tokens_in, tokens_out, elapsed_ms, finish_reason, then the sampling fields of the input,
+//! temperature, top_p, max_tokens and stop. They are empty where the input doesn't set them,
+//! stop is a JSON array.
+
+use std::path::Path;
+
+use anyhow::Context as _;
+use async_openai::types::Stop;
+use clap::ValueEnum as _;
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+
+use super::Entry;
+use crate::flags::{BatchFormat, BatchShard};
+
+/// A row of the CSV and Parquet files
+#[derive(Serialize)]
+struct Row<'a> {
+    request_id: u64,
+    text: &'a str,
+    response: Option<&'a str>,
+    tokens_in: u64,
+    tokens_out: u64,
+    elapsed_ms: u64,
+    finish_reason: Option<String>,
+    temperature: Option<f32>,
+    top_p: Option<f32>,
+    max_tokens: Option<u32>,
+    stop: Option<String>,
+}
+
+impl<'a> From<&'a Entry> for Row<'a> {
+    fn from(entry: &'a Entry) -> Self {
+        // As the OpenAI API spells it, "stop", "length" and so on
+        let finish_reason = entry
+            .finish_reason
+            .and_then(|reason| serde_json::to_value(reason).ok())
+            .and_then(|value| value.as_str().map(str::to_string));
+        let stop = entry.stop.as_ref().map(|stop| {
+            let stop = match stop {
+                Stop::String(s) => std::slice::from_ref(s),
+                Stop::StringArray(list) => list.as_slice(),
+            };
+            serde_json::to_string(stop).unwrap_or_default()
+        });
+        Row {
+            request_id: entry.request_id as u64,
+            text: &entry.text,
+            response: entry.response.as_deref(),
+            tokens_in: entry.tokens_in as u64,
+            tokens_out: entry.tokens_out as u64,
+            elapsed_ms: entry.elapsed_ms as u64,
+            finish_reason,
+            temperature: entry.temperature,
+            top_p: entry.top_p,
+            max_tokens: entry.max_tokens,
+            stop,
+        }
+    }
+}
+
+pub(super) enum Sink {
+    Jsonl(tokio::fs::File),
+    Csv(csv::Writer<std::fs::File>),
+    #[cfg(feature = "parquet")]
+    Parquet(parquet_sink::ParquetSink),
+}
+
+impl Sink {
+    /// Only JSON Lines can be appended to
+    pub async fn open(path: &Path, format: BatchFormat, append: bool) -> anyhow::Result<Self> {
+        if append && format != BatchFormat::Jsonl {
+            anyhow::bail!("Only {} output can be resumed", BatchFormat::Jsonl);
+        }
+        let sink = match format {
+            BatchFormat::Jsonl => {
+                let f = tokio::fs::OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .append(append)
+                    .truncate(!append)
+                    .open(path)
+                    .await?;
+                Sink::Jsonl(f)
+            }
+            BatchFormat::Csv => Sink::Csv(csv::Writer::from_path(path)?),
+            #[cfg(feature = "parquet")]
+            BatchFormat::Parquet => Sink::Parquet(parquet_sink::ParquetSink::create(path)?),
+            #[cfg(not(feature = "parquet"))]
+            BatchFormat::Parquet => {
+                anyhow::bail!("Parquet output needs dynamo-run built with the 'parquet' feature")
+            }
+        };
+        Ok(sink)
+    }
+
+    pub async fn write(&mut self, entry: &Entry) -> anyhow::Result<()> {
+        match self {
+            Sink::Jsonl(f) => {
+                let mut s = serde_json::to_string(entry)?;
+                s.push('\n');
+                f.write_all(s.as_bytes()).await?;
+            }
+            Sink::Csv(writer) => {
+                writer.serialize(Row::from(entry))?;
+            }
+            #[cfg(feature = "parquet")]
+            Sink::Parquet(sink) => sink.write(Row::from(entry))?,
+        }
+        Ok(())
+    }
+
+    /// Flush, and for Parquet write the footer. Without it the file can't be read.
+    pub async fn finish(self) -> anyhow::Result<()> {
+        match self {
+            Sink::Jsonl(mut f) => f.flush().await?,
+            Sink::Csv(mut writer) => writer.flush()?,
+            #[cfg(feature = "parquet")]
+            Sink::Parquet(sink) => sink.finish()?,
+        }
+        Ok(())
+    }
+}
+
+/// Where the output goes and in which format. `--batch-format` wins over the extension of
+/// `--batch-output`, JSON Lines if neither says.
+pub(super) fn resolve(
+    input_jsonl: &Path,
+    output: Option<&Path>,
+    format: Option<BatchFormat>,
+    shard: Option<BatchShard>,
+) -> anyhow::Result<(std::path::PathBuf, BatchFormat)> {
+    let from_extension = match output.and_then(|p| p.extension()) {
+        Some(ext) => Some(
+            BatchFormat::from_str(&ext.to_string_lossy(), true)
+                .map_err(anyhow::Error::msg)
+                .with_context(|| format!("--batch-output {}", output.unwrap().display()))?,
+        ),
+        None => None,
+    };
+    let format = format.or(from_extension).unwrap_or_default();
+    let path = match output {
+        Some(path) => path.to_path_buf(),
+        None => {
+            let mut path = input_jsonl.to_path_buf();
+            match shard {
+                Some(BatchShard { index, count }) => {
+                    path.set_file_name(format!("output.{index}-of-{count}.{format}"))
+                }
+                None => path.set_file_name(format!("output.{format}")),
+            }
+            path
+        }
+    };
+    Ok((path, format))
+}
+
+#[cfg(feature = "parquet")]
+mod parquet_sink {
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use arrow_array::{ArrayRef, Float32Array, RecordBatch, StringArray, UInt32Array, UInt64Array};
+    use arrow_schema::{DataType, Field, Schema, SchemaRef};
+    use parquet::arrow::ArrowWriter;
+
+    use super::Row;
+
+    /// Rows per row group
+    const BATCH_ROWS: usize = 1024;
+
+    #[derive(Default)]
+    struct Columns {
+        request_id: Vec<u64>,
+        text: Vec<String>,
+        response: Vec<Option<String>>,
+        tokens_in: Vec<u64>,
+        tokens_out: Vec<u64>,
+        elapsed_ms: Vec<u64>,
+        finish_reason: Vec<Option<String>>,
+        temperature: Vec<Option<f32>>,
+        top_p: Vec<Option<f32>>,
+        max_tokens: Vec<Option<u32>>,
+        stop: Vec<Option<String>>,
+    }
+
+    pub struct ParquetSink {
+        schema: SchemaRef,
+        writer: ArrowWriter<std::fs::File>,
+        columns: Columns,
+    }
+
+    impl ParquetSink {
+        pub fn create(path: &Path) -> anyhow::Result<Self> {
+            let schema = Arc::new(Schema::new(vec![
+                Field::new("request_id", DataType::UInt64, false),
+                Field::new("text", DataType::Utf8, false),
+                Field::new("response", DataType::Utf8, true),
+                Field::new("tokens_in", DataType::UInt64, false),
+                Field::new("tokens_out", DataType::UInt64, false),
+                Field::new("elapsed_ms", DataType::UInt64, false),
+                Field::new("finish_reason", DataType::Utf8, true),
+                Field::new("temperature", DataType::Float32, true),
+                Field::new("top_p", DataType::Float32, true),
+                Field::new("max_tokens", DataType::UInt32, true),
+                Field::new("stop", DataType::Utf8, true),
+            ]));
+            let f = std::fs::File::create(path)?;
+            let writer = ArrowWriter::try_new(f, schema.clone(), None)?;
+            Ok(ParquetSink {
+                schema,
+                writer,
+                columns: Columns::default(),
+            })
+        }
+
+        pub fn write(&mut self, row: Row<'_>) -> anyhow::Result<()> {
+            let c = &mut self.columns;
+            c.request_id.push(row.request_id);
+            c.text.push(row.text.to_string());
+            c.response.push(row.response.map(str::to_string));
+            c.tokens_in.push(row.tokens_in);
+            c.tokens_out.push(row.tokens_out);
+            c.elapsed_ms.push(row.elapsed_ms);
+            c.finish_reason.push(row.finish_reason);
+            c.temperature.push(row.temperature);
+            c.top_p.push(row.top_p);
+            c.max_tokens.push(row.max_tokens);
+            c.stop.push(row.stop);
+            if c.request_id.len() >= BATCH_ROWS {
+                self.flush()?;
+            }
+            Ok(())
+        }
+
+        pub fn finish(mut self) -> anyhow::Result<()> {
+            self.flush()?;
+            self.writer.close()?;
+            Ok(())
+        }
+
+        fn flush(&mut self) -> anyhow::Result<()> {
+            if self.columns.request_id.is_empty() {
+                return Ok(());
+            }
+            let c = std::mem::take(&mut self.columns);
+            let arrays: Vec<ArrayRef> = vec![
+                Arc::new(UInt64Array::from(c.request_id)),
+                Arc::new(StringArray::from(c.text)),
+                Arc::new(StringArray::from(c.response)),
+                Arc::new(UInt64Array::from(c.tokens_in)),
+                Arc::new(UInt64Array::from(c.tokens_out)),
+                Arc::new(UInt64Array::from(c.elapsed_ms)),
+                Arc::new(StringArray::from(c.finish_reason)),
+                Arc::new(Float32Array::from(c.temperature)),
+                Arc::new(Float32Array::from(c.top_p)),
+                Arc::new(UInt32Array::from(c.max_tokens)),
+                Arc::new(StringArray::from(c.stop)),
+            ];
+            let batch = RecordBatch::try_new(self.schema.clone(), arrays)?;
+            self.writer.write(&batch)?;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_csv() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path, format) = resolve(
+            &dir.path().join("prompts.jsonl"),
+            Some(&dir.path().join("out.csv")),
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(format, BatchFormat::Csv);
+
+        let mut sink = Sink::open(&path, format, false).await.unwrap();
+        let entry = Entry {
+            text: "Hello, \"world\"".to_string(),
+            response: Some("Hi".to_string()),
+            tokens_in: 4,
+            tokens_out: 1,
+            elapsed_ms: 12,
+            finish_reason: Some(async_openai::types::FinishReason::Stop),
+            request_id: 3,
+            top_p: Some(0.5),
+            stop: Some(Stop::String("\n".to_string())),
+            ..Default::default()
+        };
+        sink.write(&entry).await.unwrap();
+        sink.finish().await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "request_id,text,response,tokens_in,tokens_out,elapsed_ms,finish_reason,\
+             temperature,top_p,max_tokens,stop\n\
+             3,\"Hello, \"\"world\"\"\",Hi,4,1,12,stop,,0.5,,\"[\"\"\\n\"\"]\"\n"
+        );
+
+        let prompts = dir.path().join("prompts.jsonl");
+        let (path, format) = resolve(&prompts, None, None, None).unwrap();
+        assert_eq!(format, BatchFormat::Jsonl);
+        assert_eq!(path, dir.path().join("output.jsonl"));
+        let (path, _) = resolve(&prompts, None, None, "2/4".parse().ok()).unwrap();
+        assert_eq!(path, dir.path().join("output.2-of-4.jsonl"));
+        assert!(resolve(&path, Some(Path::new("out.xlsx")), None, None).is_err());
+    }
+}
@@ -0,0 +1,266 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `--batch-shards N`: run the batch as N dynamo-run processes, each with `--batch-shard i/N`,
+//! and merge what they wrote. The shards always write JSON Lines next to the final output, so
+//! they can be resumed and read back. They are deleted once merged.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context as _;
+use dynamo_runtime::CancellationToken;
+
+use super::format::{self, Sink};
+use super::Entry;
+use crate::flags::{BatchFormat, BatchShard};
+use crate::Flags;
+
+/// Flags the coordinator sets differently for each shard, or keeps for itself. Each takes a
+/// value.
+const COORDINATOR_FLAGS: [&str; 4] = [
+    "--batch-shards",
+    "--batch-output",
+    "--batch-format",
+    "--batch-report",
+];
+
+pub async fn coordinate(
+    cancel_token: CancellationToken,
+    input_jsonl: &Path,
+    flags: &Flags,
+    shards: u32,
+) -> anyhow::Result<()> {
+    let (output_file, format) = format::resolve(
+        input_jsonl,
+        flags.batch_output.as_deref(),
+        flags.batch_format,
+        None,
+    )?;
+    if flags.batch_report.is_some() {
+        tracing::warn!("--batch-report is ignored with --batch-shards, each shard logs a summary");
+    }
+    let exe = std::env::current_exe().context("Finding the dynamo-run binary")?;
+    let args = shard_args(std::env::args().skip(1).collect());
+
+    let mut children = Vec::with_capacity(shards as usize);
+    let mut shard_files = Vec::with_capacity(shards as usize);
+    for index in 0..shards {
+        let shard = BatchShard {
+            index,
+            count: shards,
+        };
+        let shard_file = shard_path(&output_file, shard);
+        let mut cmd = tokio::process::Command::new(&exe);
+        let extra = [
+            "--batch-shard".to_string(),
+            shard.to_string(),
+            "--batch-output".to_string(),
+            shard_file.display().to_string(),
+        ];
+        cmd.args(with_flags(&args, &extra)).kill_on_drop(true);
+        tracing::info!("Starting shard {shard}, writing {}", shard_file.display());
+        let child = cmd
+            .spawn()
+            .with_context(|| format!("Starting shard {shard}"))?;
+        children.push((shard, child));
+        shard_files.push(shard_file);
+    }
+
+    let mut failed = vec![];
+    for (shard, mut child) in children {
+        let status = child.wait().await?;
+        if !status.success() {
+            tracing::error!("Shard {shard} failed: {status}");
+            failed.push(shard.to_string());
+        }
+    }
+    if cancel_token.is_cancelled() {
+        // The shards got the signal too, what they wrote is kept for --batch-resume
+        tracing::info!("Interrupted, shard outputs are not merged");
+        return Ok(());
+    }
+    if !failed.is_empty() {
+        anyhow::bail!(
+            "Shards {} failed. Their output is kept, run again with --batch-resume to finish them.",
+            failed.join(", ")
+        );
+    }
+
+    let merged = merge(&shard_files, &output_file, format).await?;
+    for shard_file in &shard_files {
+        if let Err(err) = tokio::fs::remove_file(shard_file).await {
+            tracing::warn!(%err, "Could not remove {}", shard_file.display());
+        }
+    }
+    tracing::info!(
+        "Merged {merged} results from {shards} shards into {}",
+        output_file.display()
+    );
+    Ok(())
+}
+
+/// Where a shard writes when the coordinator's output is `output`: `output.jsonl` becomes
+/// `output.0-of-4.jsonl`.
+fn shard_path(output: &Path, shard: BatchShard) -> PathBuf {
+    let stem = output
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_else(|| "output".to_string());
+    output.with_file_name(format!(
+        "{stem}.{}-of-{}.{}",
+        shard.index,
+        shard.count,
+        BatchFormat::Jsonl
+    ))
+}
+
+/// The coordinator's arguments without the flags it sets for the shards
+fn shard_args(args: Vec<String>) -> Vec<String> {
+    let mut kept = Vec::with_capacity(args.len());
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            kept.push(arg);
+            kept.extend(args.by_ref());
+            break;
+        }
+        if COORDINATOR_FLAGS.contains(&arg.as_str()) {
+            args.next(); // its value
+            continue;
+        }
+        let is_coordinator_flag = arg
+            .split_once('=')
+            .is_some_and(|(name, _)| COORDINATOR_FLAGS.contains(&name));
+        if !is_coordinator_flag {
+            kept.push(arg);
+        }
+    }
+    kept
+}
+
+/// `args` with `extra` added before any `--`, after which they would be engine arguments
+fn with_flags(args: &[String], extra: &[String]) -> Vec<String> {
+    let split = args
+        .iter()
+        .position(|arg| arg == "--")
+        .unwrap_or(args.len());
+    let mut all = args[..split].to_vec();
+    all.extend_from_slice(extra);
+    all.extend_from_slice(&args[split..]);
+    all
+}
+
+/// Write the entries of the shard files to `output` in request_id order. Returns how many.
+async fn merge(
+    shard_files: &[PathBuf],
+    output: &Path,
+    format: BatchFormat,
+) -> anyhow::Result<usize> {
+    let mut entries = vec![];
+    for shard_file in shard_files {
+        let contents = tokio::fs::read_to_string(shard_file)
+            .await
+            .with_context(|| shard_file.display().to_string())?;
+        for line in contents.lines().filter(|line| !line.is_empty()) {
+            let entry: Entry = serde_json::from_str(line)
+                .with_context(|| format!("Reading {}", shard_file.display()))?;
+            entries.push(entry);
+        }
+    }
+    entries.sort_by_key(|entry| entry.request_id);
+
+    let mut sink = Sink::open(output, format, false).await?;
+    for entry in &entries {
+        sink.write(entry).await?;
+    }
+    sink.finish().await?;
+    Ok(entries.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_shard_args() {
+        let coordinator = args(&[
+            "in=batch:prompts.jsonl",
+            "out=dyn://ns.backend.generate",
+            "--batch-shards",
+            "4",
+            "--batch-output=results.csv",
+            "--batch-concurrency",
+            "8",
+            "--",
+            "--batch-report",
+            "engine-arg",
+        ]);
+        let shard = with_flags(&shard_args(coordinator), &args(&["--batch-shard", "1/4"]));
+        assert_eq!(
+            shard,
+            args(&[
+                "in=batch:prompts.jsonl",
+                "out=dyn://ns.backend.generate",
+                "--batch-concurrency",
+                "8",
+                "--batch-shard",
+                "1/4",
+                "--",
+                "--batch-report",
+                "engine-arg",
+            ])
+        );
+        assert_eq!(
+            shard_path(Path::new("/data/results.csv"), "1/4".parse().unwrap()),
+            Path::new("/data/results.1-of-4.jsonl")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_merge() {
+        let dir = tempfile::tempdir().unwrap();
+        let shards = [dir.path().join("a.jsonl"), dir.path().join("b.jsonl")];
+        std::fs::write(
+            &shards[0],
+            concat!(
+                r#"{"text":"a","response":"A","request_id":0}"#,
+                "\n",
+                r#"{"text":"c","response":"C","request_id":2}"#,
+                "\n",
+            ),
+        )
+        .unwrap();
+        std::fs::write(
+            &shards[1],
+            concat!(r#"{"text":"b","response":"B","request_id":1}"#, "\n"),
+        )
+        .unwrap();
+        let output = dir.path().join("output.jsonl");
+        assert_eq!(
+            merge(&shards, &output, BatchFormat::Jsonl).await.unwrap(),
+            3
+        );
+        let ids: Vec<usize> = std::fs::read_to_string(&output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<Entry>(line).unwrap().request_id)
+            .collect();
+        assert_eq!(ids, [0, 1, 2]);
+    }
+}
@@ -0,0 +1,390 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `in=bench`, without a second engine, puts synthetic load on the `out=` engine for a while
+//! and reports its throughput and latencies.
+//!
+//! `--bench-concurrency` clients each send a request, wait for the whole response, and send the
+//! next one, until `--bench-duration` is up. Prompts are random common words, about a token
+//! each, so the engine's prefix cache doesn't make them cheaper than real ones. Lengths are
+//! drawn from `--bench-prompt-tokens` and `--bench-output-tokens`, and the engine is asked to
+//! ignore EOS so that each response is as long as drawn. The requests are the server's own
+//! chat completion requests, through the same pre-processor as `in=http`.
+//!
+//! `in=bench:<engine>` runs the same load on both engines when it isn't given prompts, see
+//! [`super::bench_compare`].
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use anyhow::Context as _;
+use dynamo_llm::protocols::openai::nvext::NvExt;
+use dynamo_llm::types::openai::chat_completions::{
+    NvCreateChatCompletionRequest, OpenAIChatCompletionsStreamingEngine,
+};
+use dynamo_runtime::{pipeline::Context, CancellationToken, Runtime};
+use futures::StreamExt;
+use rand::seq::IndexedRandom as _;
+use rand::Rng as _;
+use serde::Serialize;
+
+use crate::input::common::{self, Percentiles};
+use crate::{EngineConfig, Flags, RequestTemplate};
+
+const DEFAULT_REPORT_FILE: &str = "bench.json";
+
+const DEFAULT_CONCURRENCY: u32 = 8;
+
+const DEFAULT_DURATION: Duration = Duration::from_secs(60);
+
+const DEFAULT_PROMPT_TOKENS: Lengths = Lengths { min: 512, max: 512 };
+
+const DEFAULT_OUTPUT_TOKENS: Lengths = Lengths { min: 128, max: 128 };
+
+/// Common words most tokenizers have as a single token
+const WORDS: &[&str] = &[
+    "the", "of", "and", "to", "in", "is", "that", "for", "it", "as", "was", "with", "be", "by",
+    "on", "not", "he", "this", "are", "or", "his", "from", "at", "which", "but", "have", "an",
+    "had", "they", "you", "were", "their", "one", "all", "we", "can", "her", "has", "there",
+    "been", "if", "more", "when", "will", "would", "who", "so", "no", "time", "people", "way",
+    "water", "day", "part", "sound", "work", "place", "year", "back", "thing", "name", "house",
+];
+
+/// A length in tokens, the same every time or uniform between two bounds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lengths {
+    min: u32,
+    max: u32,
+}
+
+impl FromStr for Lengths {
+    type Err = String;
+
+    /// `<n>` or `<min>-<max>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |n: &str| {
+            n.trim()
+                .parse::<u32>()
+                .ok()
+                .filter(|n| *n > 0)
+                .ok_or_else(|| format!("'{s}' is not <tokens> or <min>-<max>"))
+        };
+        let (min, max) = match s.split_once('-') {
+            Some((min, max)) => (parse(min)?, parse(max)?),
+            None => (parse(s)?, parse(s)?),
+        };
+        if min > max {
+            return Err(format!("'{s}': the minimum is more than the maximum"));
+        }
+        Ok(Lengths { min, max })
+    }
+}
+
+impl Lengths {
+    fn draw(&self) -> u32 {
+        rand::rng().random_range(self.min..=self.max)
+    }
+}
+
+/// The load put on an engine, from the `--bench-*` flags
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Load {
+    pub concurrency: u32,
+    pub duration: Duration,
+    prompt_tokens: Lengths,
+    output_tokens: Lengths,
+}
+
+impl Load {
+    pub(super) fn from_flags(flags: &Flags) -> Self {
+        Load {
+            concurrency: flags.bench_concurrency.unwrap_or(DEFAULT_CONCURRENCY),
+            duration: flags
+                .bench_duration
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_DURATION),
+            prompt_tokens: flags.bench_prompt_tokens.unwrap_or(DEFAULT_PROMPT_TOKENS),
+            output_tokens: flags.bench_output_tokens.unwrap_or(DEFAULT_OUTPUT_TOKENS),
+        }
+    }
+}
+
+/// One request of the load
+#[derive(Debug, Default)]
+pub(super) struct Sample {
+    pub error: bool,
+    pub ttft: Option<Duration>,
+    pub elapsed: Duration,
+    /// Streamed chunks with content, about one per token
+    pub tokens_out: usize,
+}
+
+#[derive(Serialize, Debug)]
+struct LoadReport {
+    /// Always `bench`, tells this report from those of `in=bench:<engine>`
+    mode: &'static str,
+    output: String,
+    concurrency: u32,
+    duration_s: f64,
+    requests: usize,
+    errors: usize,
+    tokens_out: usize,
+    requests_per_sec: f64,
+    output_tokens_per_sec: f64,
+    metrics: BTreeMap<&'static str, Percentiles>,
+}
+
+impl LoadReport {
+    fn new(output: &str, concurrency: u32, duration: Duration, samples: &[Sample]) -> Self {
+        let secs = duration.as_secs_f64().max(f64::EPSILON);
+        let tokens_out = samples.iter().map(|s| s.tokens_out).sum();
+        let ok: Vec<&Sample> = samples.iter().filter(|s| !s.error).collect();
+        let millis = |d: Duration| d.as_secs_f64() * 1000.0;
+        let series: [(&'static str, Vec<f64>); 3] = [
+            (
+                "ttft_ms",
+                ok.iter().filter_map(|s| s.ttft).map(millis).collect(),
+            ),
+            (
+                "itl_ms",
+                ok.iter()
+                    .filter(|s| s.tokens_out > 1)
+                    .filter_map(|s| {
+                        let ttft = s.ttft?;
+                        Some(millis(s.elapsed - ttft) / (s.tokens_out - 1) as f64)
+                    })
+                    .collect(),
+            ),
+            ("latency_ms", ok.iter().map(|s| millis(s.elapsed)).collect()),
+        ];
+        LoadReport {
+            mode: "bench",
+            output: output.to_string(),
+            concurrency,
+            duration_s: duration.as_secs_f64(),
+            requests: samples.len(),
+            errors: samples.len() - ok.len(),
+            tokens_out,
+            requests_per_sec: samples.len() as f64 / secs,
+            output_tokens_per_sec: tokens_out as f64 / secs,
+            metrics: series
+                .into_iter()
+                .filter_map(|(name, values)| Some((name, Percentiles::of(values)?)))
+                .collect(),
+        }
+    }
+}
+
+pub async fn run(
+    runtime: Runtime,
+    flags: Flags,
+    output: String,
+    engine_config: EngineConfig,
+    template: Option<RequestTemplate>,
+) -> anyhow::Result<()> {
+    let cancel_token = runtime.primary_token();
+    let load = Load::from_flags(&flags);
+    let report_path = flags
+        .bench_report
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_REPORT_FILE));
+
+    let prepared = common::prepare_engine(runtime, flags, engine_config).await?;
+    let model = template
+        .as_ref()
+        .map_or(prepared.service_name.clone(), |t| t.model.clone());
+    let temperature = template.as_ref().map_or(0.7, |t| t.temperature);
+    tracing::info!(
+        "{} clients for {}s on {output}",
+        load.concurrency,
+        load.duration.as_secs()
+    );
+
+    let (samples, elapsed) =
+        generate(&load, &prepared.engine, &model, temperature, &cancel_token).await;
+    if cancel_token.is_cancelled() {
+        tracing::warn!("Interrupted, reporting on {} requests", samples.len());
+    }
+
+    let report = LoadReport::new(&output, load.concurrency, elapsed, &samples);
+    std::fs::write(&report_path, serde_json::to_string_pretty(&report)?)
+        .with_context(|| report_path.display().to_string())?;
+    print_report(&report);
+    println!("Report written to {}", report_path.display());
+    cancel_token.cancel(); // stop everything else
+    Ok(())
+}
+
+/// Puts `load` on `engine` until its duration is up or `cancel_token` is cancelled. Returns
+/// a sample per request and how long it took.
+pub(super) async fn generate(
+    load: &Load,
+    engine: &OpenAIChatCompletionsStreamingEngine,
+    model: &str,
+    temperature: f32,
+    cancel_token: &CancellationToken,
+) -> (Vec<Sample>, Duration) {
+    let start = Instant::now();
+    let end = start + load.duration;
+    let clients = (0..load.concurrency).map(|_| async move {
+        let mut samples = vec![];
+        while Instant::now() < end && !cancel_token.is_cancelled() {
+            let request = make_request(
+                model,
+                temperature,
+                load.prompt_tokens.draw(),
+                load.output_tokens.draw(),
+            );
+            let sample = tokio::select! {
+                sample = measure(engine, request) => sample,
+                _ = cancel_token.cancelled() => break,
+            };
+            samples.push(sample);
+        }
+        samples
+    });
+    let samples = futures::future::join_all(clients)
+        .await
+        .into_iter()
+        .flatten()
+        .collect();
+    (samples, start.elapsed())
+}
+
+fn make_request(
+    model: &str,
+    temperature: f32,
+    prompt_tokens: u32,
+    max_tokens: u32,
+) -> anyhow::Result<NvCreateChatCompletionRequest> {
+    let mut rng = rand::rng();
+    let prompt: Vec<&str> = (0..prompt_tokens)
+        .filter_map(|_| WORDS.choose(&mut rng).copied())
+        .collect();
+    let user_message = async_openai::types::ChatCompletionRequestMessage::User(
+        async_openai::types::ChatCompletionRequestUserMessage {
+            content: async_openai::types::ChatCompletionRequestUserMessageContent::Text(
+                prompt.join(" "),
+            ),
+            name: None,
+        },
+    );
+    let inner = async_openai::types::CreateChatCompletionRequestArgs::default()
+        .messages(vec![user_message])
+        .model(model)
+        .stream(true)
+        .max_completion_tokens(max_tokens)
+        .temperature(temperature)
+        .n(1)
+        .build()?;
+    let nvext = NvExt::builder().ignore_eos(true).build()?;
+    Ok(NvCreateChatCompletionRequest {
+        inner,
+        nvext: Some(nvext),
+    })
+}
+
+/// Send one request and read its whole response
+async fn measure(
+    engine: &OpenAIChatCompletionsStreamingEngine,
+    request: anyhow::Result<NvCreateChatCompletionRequest>,
+) -> Sample {
+    let start = Instant::now();
+    let mut sample = Sample::default();
+    let result = async {
+        let mut stream = engine.generate(Context::new(request?)).await?;
+        while let Some(item) = stream.next().await {
+            if item.is_error() {
+                anyhow::bail!(item.comment.unwrap_or_default().join(", "));
+            }
+            let Some(choice) = item.data.as_ref().and_then(|d| d.inner.choices.first()) else {
+                continue;
+            };
+            if choice.delta.content.is_some() {
+                sample.ttft.get_or_insert(start.elapsed());
+                sample.tokens_out += 1;
+            }
+            if choice.finish_reason.is_some() {
+                break;
+            }
+        }
+        anyhow::Ok(())
+    }
+    .await;
+    if let Err(err) = result {
+        tracing::debug!(%err, "Request failed");
+        sample.error = true;
+    }
+    sample.elapsed = start.elapsed();
+    sample
+}
+
+fn print_report(report: &LoadReport) {
+    println!(
+        "{}: {} requests in {:.1}s from {} clients, {} errors",
+        report.output, report.requests, report.duration_s, report.concurrency, report.errors
+    );
+    println!(
+        "  {:.2} requests/s, {:.1} output tokens/s, {} tokens",
+        report.requests_per_sec, report.output_tokens_per_sec, report.tokens_out
+    );
+    for (metric, p) in &report.metrics {
+        println!(
+            "  {metric:<15} mean {:>9.1}  p50 {:>9.1}  p90 {:>9.1}  p99 {:>9.1}",
+            p.mean, p.p50, p.p90, p.p99
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lengths() {
+        let fixed: Lengths = "512".parse().unwrap();
+        assert_eq!(fixed.draw(), 512);
+
+        let range: Lengths = "100-200".parse().unwrap();
+        for _ in 0..100 {
+            assert!((100..=200).contains(&range.draw()));
+        }
+
+        assert!("200-100".parse::<Lengths>().is_err());
+        assert!("0".parse::<Lengths>().is_err());
+        assert!("many".parse::<Lengths>().is_err());
+    }
+
+    #[test]
+    fn test_report() {
+        let sample = |ms: u64, error: bool| Sample {
+            error,
+            ttft: Some(Duration::from_millis(ms / 10)),
+            elapsed: Duration::from_millis(ms),
+            tokens_out: 10,
+        };
+        let samples = [sample(1000, false), sample(2000, false), sample(500, true)];
+        let report = LoadReport::new("echo_full", 2, Duration::from_secs(3), &samples);
+        assert_eq!(report.requests, 3);
+        assert_eq!(report.errors, 1);
+        assert_eq!(report.tokens_out, 30);
+        assert_eq!(report.requests_per_sec, 1.0);
+        assert_eq!(report.output_tokens_per_sec, 10.0);
+        assert_eq!(report.metrics["latency_ms"].p99, 2000.0);
+        assert_eq!(report.metrics["ttft_ms"].mean, 150.0);
+    }
+}
@@ -0,0 +1,592 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `in=bench:<out>` measures the `out=` engine (A, the baseline) and a second one (B, the
+//! candidate), and reports how B compares. For each metric that's the percentiles on both
+//! sides, and the mean of B minus A with a 95% confidence interval. B regressed on a metric if
+//! it is worse by more than `--bench-threshold` percent and the interval is all on the bad side
+//! of zero. The command fails when something regressed, so an engine upgrade can be gated on
+//! it.
+//!
+//! With `--bench-prompts`, the prompts run one at a time, each on both engines, alternating
+//! which goes first so neither one always gets the warmer machine. Every difference is between
+//! the two answers to the same prompt.
+//!
+//! Without, the synthetic load of `in=bench` runs on A and then on B, see [`super::bench`].
+//! The requests of the two runs are not paired, the interval is of the difference of the two
+//! means.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::time::Duration;
+
+use anyhow::Context as _;
+use dynamo_runtime::{CancellationToken, Runtime};
+use serde::{Deserialize, Serialize};
+
+use super::arena::{answer, Answer, Contender};
+use super::bench::{self, Load, Sample};
+use crate::input::common::{self, Percentiles};
+use crate::{EngineConfig, Flags, RequestTemplate};
+
+const DEFAULT_REPORT_FILE: &str = "bench-compare.json";
+
+const DEFAULT_THRESHOLD_PCT: f64 = 5.0;
+
+/// Two-sided 95%, normal approximation of the mean difference
+const Z_95: f64 = 1.96;
+
+/// A line of the prompts file
+#[derive(Deserialize)]
+struct Prompt {
+    text: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Metric {
+    /// Generated tokens over the request's time
+    TokensPerSec,
+    /// Time to first token
+    TtftMs,
+    /// Mean time between tokens after the first
+    ItlMs,
+}
+
+const METRICS: [Metric; 3] = [Metric::TokensPerSec, Metric::TtftMs, Metric::ItlMs];
+
+impl Metric {
+    fn name(&self) -> &'static str {
+        match self {
+            Metric::TokensPerSec => "tokens_per_sec",
+            Metric::TtftMs => "ttft_ms",
+            Metric::ItlMs => "itl_ms",
+        }
+    }
+
+    fn higher_is_better(&self) -> bool {
+        matches!(self, Metric::TokensPerSec)
+    }
+
+    /// None if the answer failed, or has too few tokens to say
+    fn of(&self, answer: &Answer) -> Option<f64> {
+        if answer.error.is_some() || answer.tokens_out == 0 {
+            return None;
+        }
+        let ttft_ms = answer.ttft_ms? as f64;
+        let elapsed_ms = answer.elapsed_ms as f64;
+        match self {
+            Metric::TokensPerSec if elapsed_ms > 0.0 => {
+                Some(answer.tokens_out as f64 * 1000.0 / elapsed_ms)
+            }
+            Metric::TokensPerSec => None,
+            Metric::TtftMs => Some(ttft_ms),
+            Metric::ItlMs if answer.tokens_out > 1 => {
+                Some((elapsed_ms - ttft_ms) / (answer.tokens_out - 1) as f64)
+            }
+            Metric::ItlMs => None,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct EngineReport {
+    output: String,
+    errors: usize,
+    tokens_out: usize,
+    /// All the generated tokens over the time spent generating them
+    throughput_tps: f64,
+    metrics: BTreeMap<&'static str, Percentiles>,
+}
+
+impl EngineReport {
+    fn new(output: &str, answers: &[&Answer]) -> Self {
+        let tokens_out = answers.iter().map(|a| a.tokens_out).sum();
+        let elapsed_ms: u64 = answers.iter().map(|a| a.elapsed_ms).sum();
+        let metrics = METRICS
+            .iter()
+            .filter_map(|metric| {
+                let values = answers.iter().filter_map(|a| metric.of(a)).collect();
+                Some((metric.name(), Percentiles::of(values)?))
+            })
+            .collect();
+        EngineReport {
+            output: output.to_string(),
+            errors: answers.iter().filter(|a| a.error.is_some()).count(),
+            tokens_out,
+            throughput_tps: tokens_out as f64 * 1000.0 / (elapsed_ms.max(1) as f64),
+            metrics,
+        }
+    }
+}
+
+/// B against A on one metric, over the prompts or requests that have it
+#[derive(Serialize, Debug)]
+struct Comparison {
+    metric: &'static str,
+    /// Prompts where both have it, or the requests of the side with fewer
+    samples: usize,
+    mean_a: f64,
+    mean_b: f64,
+    /// Mean of B - A
+    mean_diff: f64,
+    ci95: [f64; 2],
+    /// Of the mean, relative to A
+    change_pct: f64,
+    regression: bool,
+}
+
+impl Comparison {
+    /// `pairs` are (A, B). None with fewer than two, there is no interval then.
+    fn new(metric: Metric, pairs: &[(f64, f64)], threshold_pct: f64) -> Option<Self> {
+        if pairs.len() < 2 {
+            return None;
+        }
+        let n = pairs.len() as f64;
+        let diffs: Vec<f64> = pairs.iter().map(|(a, b)| b - a).collect();
+        let half_width = Z_95 * (variance(&diffs) / n).sqrt();
+        let mean_a = pairs.iter().map(|(a, _)| a).sum::<f64>() / n;
+        let mean_b = pairs.iter().map(|(_, b)| b).sum::<f64>() / n;
+        Some(Self::with_interval(
+            metric,
+            pairs.len(),
+            mean_a,
+            mean_b,
+            half_width,
+            threshold_pct,
+        ))
+    }
+
+    /// Unpaired values of A and B. None with fewer than two on a side.
+    fn unpaired(metric: Metric, a: &[f64], b: &[f64], threshold_pct: f64) -> Option<Self> {
+        if a.len() < 2 || b.len() < 2 {
+            return None;
+        }
+        let half_width =
+            Z_95 * (variance(a) / a.len() as f64 + variance(b) / b.len() as f64).sqrt();
+        Some(Self::with_interval(
+            metric,
+            a.len().min(b.len()),
+            mean(a),
+            mean(b),
+            half_width,
+            threshold_pct,
+        ))
+    }
+
+    /// The interval of B - A is its mean give or take `half_width`
+    fn with_interval(
+        metric: Metric,
+        samples: usize,
+        mean_a: f64,
+        mean_b: f64,
+        half_width: f64,
+        threshold_pct: f64,
+    ) -> Self {
+        let mean_diff = mean_b - mean_a;
+        let ci95 = [mean_diff - half_width, mean_diff + half_width];
+        let change_pct = if mean_a == 0.0 {
+            0.0
+        } else {
+            mean_diff / mean_a * 100.0
+        };
+        let regression = if metric.higher_is_better() {
+            ci95[1] < 0.0 && change_pct < -threshold_pct
+        } else {
+            ci95[0] > 0.0 && change_pct > threshold_pct
+        };
+        Comparison {
+            metric: metric.name(),
+            samples,
+            mean_a,
+            mean_b,
+            mean_diff,
+            ci95,
+            change_pct,
+            regression,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct Report {
+    /// Always `bench_compare`, tells this report from those of `in=bench`
+    mode: &'static str,
+    /// `prompts` with `--bench-prompts`, else `load`
+    method: &'static str,
+    /// Prompts run on both engines, or requests sent to the two together
+    requests: usize,
+    threshold_pct: f64,
+    a: EngineReport,
+    b: EngineReport,
+    comparisons: Vec<Comparison>,
+}
+
+/// `contenders` are the out= value and engine of A then B
+pub async fn run(
+    runtime: Runtime,
+    flags: Flags,
+    contenders: [(String, EngineConfig); 2],
+    template: Option<RequestTemplate>,
+) -> anyhow::Result<()> {
+    let cancel_token = runtime.primary_token();
+    let prompts = match &flags.bench_prompts {
+        Some(path) => Some((path.clone(), load_prompts(path)?)),
+        None => None,
+    };
+    let report_path = flags
+        .bench_report
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_REPORT_FILE));
+    let threshold_pct = flags.bench_threshold.unwrap_or(DEFAULT_THRESHOLD_PCT);
+    let load = Load::from_flags(&flags);
+
+    let [(output_a, config_a), (output_b, config_b)] = contenders;
+    let prepared_a = common::prepare_engine(runtime.clone(), flags.clone(), config_a).await?;
+    let prepared_b = common::prepare_engine(runtime, flags, config_b).await?;
+    let a = Contender {
+        output: output_a,
+        service_name: prepared_a.service_name,
+        engine: prepared_a.engine,
+    };
+    let b = Contender {
+        output: output_b,
+        service_name: prepared_b.service_name,
+        engine: prepared_b.engine,
+    };
+
+    let report = match prompts {
+        Some((prompts_path, prompts)) => {
+            tracing::info!(
+                "A is {}, B is {}. {} prompts from {}",
+                a.output,
+                b.output,
+                prompts.len(),
+                prompts_path.display()
+            );
+            let answers = run_prompts(&a, &b, &prompts, template.as_ref(), &cancel_token).await;
+            make_report(&a.output, &b.output, &answers, threshold_pct)
+        }
+        None => {
+            tracing::info!(
+                "A is {}, B is {}. {} clients for {}s on each",
+                a.output,
+                b.output,
+                load.concurrency,
+                load.duration.as_secs()
+            );
+            let mut runs = vec![];
+            for contender in [&a, &b] {
+                if cancel_token.is_cancelled() {
+                    break;
+                }
+                let model = template
+                    .as_ref()
+                    .map_or(contender.service_name.clone(), |t| t.model.clone());
+                let temperature = template.as_ref().map_or(0.7, |t| t.temperature);
+                let (samples, elapsed) =
+                    bench::generate(&load, &contender.engine, &model, temperature, &cancel_token)
+                        .await;
+                let answers = samples
+                    .iter()
+                    .map(|sample| answer_of(&contender.output, sample))
+                    .collect();
+                runs.push((answers, elapsed));
+            }
+            let [(answers_a, elapsed_a), (answers_b, elapsed_b)] = <[_; 2]>::try_from(runs)
+                .map_err(|_| anyhow::anyhow!("Interrupted before both engines ran"))?;
+            make_load_report(
+                (&a.output, &answers_a, elapsed_a),
+                (&b.output, &answers_b, elapsed_b),
+                threshold_pct,
+            )
+        }
+    };
+    std::fs::write(&report_path, serde_json::to_string_pretty(&report)?)
+        .with_context(|| report_path.display().to_string())?;
+    print_report(&report);
+    println!("Report written to {}", report_path.display());
+    cancel_token.cancel(); // stop everything else
+
+    let regressions: Vec<&str> = report
+        .comparisons
+        .iter()
+        .filter(|c| c.regression)
+        .map(|c| c.metric)
+        .collect();
+    if !regressions.is_empty() {
+        anyhow::bail!("{} regressed on {}", b.output, regressions.join(", "));
+    }
+    Ok(())
+}
+
+/// Each prompt on both engines, in turn, until done or cancelled
+async fn run_prompts(
+    a: &Contender,
+    b: &Contender,
+    prompts: &[String],
+    template: Option<&RequestTemplate>,
+    cancel_token: &CancellationToken,
+) -> Vec<(Answer, Answer)> {
+    let mut answers = Vec::with_capacity(prompts.len());
+    for (i, prompt) in prompts.iter().enumerate() {
+        let pair = async {
+            if i % 2 == 0 {
+                let answer_a = measure(a, prompt, template).await;
+                (answer_a, measure(b, prompt, template).await)
+            } else {
+                let answer_b = measure(b, prompt, template).await;
+                (measure(a, prompt, template).await, answer_b)
+            }
+        };
+        let (answer_a, answer_b) = tokio::select! {
+            pair = pair => pair,
+            _ = cancel_token.cancelled() => break,
+        };
+        tracing::info!(
+            "{}/{}: A {}ms, B {}ms",
+            i + 1,
+            prompts.len(),
+            answer_a.elapsed_ms,
+            answer_b.elapsed_ms
+        );
+        answers.push((answer_a, answer_b));
+    }
+    if answers.len() < prompts.len() {
+        tracing::warn!(
+            "Interrupted, reporting on the first {} prompts",
+            answers.len()
+        );
+    }
+    answers
+}
+
+/// A request of the load as if it were a prompt's answer, to measure both the same way
+fn answer_of(output: &str, sample: &Sample) -> Answer {
+    Answer {
+        output: output.to_string(),
+        error: sample.error.then(|| "Request failed".to_string()),
+        ttft_ms: sample.ttft.map(|ttft| ttft.as_millis() as u64),
+        elapsed_ms: sample.elapsed.as_millis() as u64,
+        tokens_out: sample.tokens_out,
+        ..Default::default()
+    }
+}
+
+async fn measure(
+    contender: &Contender,
+    prompt: &str,
+    template: Option<&RequestTemplate>,
+) -> Answer {
+    let tokens_out = AtomicUsize::new(0);
+    answer(contender, prompt, template, &tokens_out).await
+}
+
+fn load_prompts(path: &std::path::Path) -> anyhow::Result<Vec<String>> {
+    let contents = std::fs::read_to_string(path).with_context(|| path.display().to_string())?;
+    let mut prompts = vec![];
+    for (i, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let prompt: Prompt = serde_json::from_str(line)
+            .with_context(|| format!("{} line {}", path.display(), i + 1))?;
+        prompts.push(prompt.text);
+    }
+    if prompts.is_empty() {
+        anyhow::bail!("{}: no prompts", path.display());
+    }
+    Ok(prompts)
+}
+
+fn make_report(
+    output_a: &str,
+    output_b: &str,
+    answers: &[(Answer, Answer)],
+    threshold_pct: f64,
+) -> Report {
+    let answers_a: Vec<&Answer> = answers.iter().map(|(a, _)| a).collect();
+    let answers_b: Vec<&Answer> = answers.iter().map(|(_, b)| b).collect();
+    let comparisons = METRICS
+        .iter()
+        .filter_map(|metric| {
+            let pairs: Vec<(f64, f64)> = answers
+                .iter()
+                .filter_map(|(a, b)| Some((metric.of(a)?, metric.of(b)?)))
+                .collect();
+            Comparison::new(*metric, &pairs, threshold_pct)
+        })
+        .collect();
+    Report {
+        mode: "bench_compare",
+        method: "prompts",
+        requests: answers.len(),
+        threshold_pct,
+        a: EngineReport::new(output_a, &answers_a),
+        b: EngineReport::new(output_b, &answers_b),
+        comparisons,
+    }
+}
+
+/// `a` and `b` are the out= value, answers and time of the load run on each engine
+fn make_load_report(
+    a: (&str, &[Answer], Duration),
+    b: (&str, &[Answer], Duration),
+    threshold_pct: f64,
+) -> Report {
+    let engine_report = |(output, answers, elapsed): (&str, &[Answer], Duration)| {
+        let answers: Vec<&Answer> = answers.iter().collect();
+        let mut report = EngineReport::new(output, &answers);
+        // The requests overlap, the throughput is over the time of the whole run
+        report.throughput_tps = report.tokens_out as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+        report
+    };
+    let comparisons = METRICS
+        .iter()
+        .filter_map(|metric| {
+            let values_a: Vec<f64> = a.1.iter().filter_map(|x| metric.of(x)).collect();
+            let values_b: Vec<f64> = b.1.iter().filter_map(|x| metric.of(x)).collect();
+            Comparison::unpaired(*metric, &values_a, &values_b, threshold_pct)
+        })
+        .collect();
+    Report {
+        mode: "bench_compare",
+        method: "load",
+        requests: a.1.len() + b.1.len(),
+        threshold_pct,
+        a: engine_report(a),
+        b: engine_report(b),
+        comparisons,
+    }
+}
+
+fn print_report(report: &Report) {
+    for (label, engine) in [("A", &report.a), ("B", &report.b)] {
+        println!(
+            "{label} {}: {:.1} tokens/s overall, {} tokens, {} errors",
+            engine.output, engine.throughput_tps, engine.tokens_out, engine.errors
+        );
+        for (metric, p) in &engine.metrics {
+            println!(
+                "  {metric:<15} mean {:>9.1}  p50 {:>9.1}  p90 {:>9.1}  p99 {:>9.1}",
+                p.mean, p.p50, p.p90, p.p99
+            );
+        }
+    }
+    match report.method {
+        "prompts" => println!("B - A over {} prompts:", report.requests),
+        _ => println!("B - A over {} requests:", report.requests),
+    }
+    for c in &report.comparisons {
+        println!(
+            "  {:<15} {:>+7.1}%  95% CI [{:.1}, {:.1}]{}",
+            c.metric,
+            c.change_pct,
+            c.ci95[0],
+            c.ci95[1],
+            if c.regression { "  REGRESSION" } else { "" }
+        );
+    }
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+/// Sample variance, of at least two values
+fn variance(values: &[f64]) -> f64 {
+    let mean = mean(values);
+    values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (values.len() - 1) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_comparison() {
+        // B is consistently 20% slower to the first token
+        let pairs: Vec<(f64, f64)> = (0..20)
+            .map(|i| {
+                let a = 100.0 + f64::from(i % 5);
+                (a, a * 1.2)
+            })
+            .collect();
+        let slower = Comparison::new(Metric::TtftMs, &pairs, 5.0).unwrap();
+        assert!(slower.regression, "{slower:?}");
+        assert!((slower.change_pct - 20.0).abs() < 1e-9);
+
+        // The same numbers are an improvement where higher is better
+        let faster = Comparison::new(Metric::TokensPerSec, &pairs, 5.0).unwrap();
+        assert!(!faster.regression);
+
+        // Noise around no change
+        let noisy: Vec<(f64, f64)> = (0..20)
+            .map(|i| (100.0, if i % 2 == 0 { 80.0 } else { 125.0 }))
+            .collect();
+        assert!(
+            !Comparison::new(Metric::TtftMs, &noisy, 5.0)
+                .unwrap()
+                .regression
+        );
+
+        assert!(Comparison::new(Metric::TtftMs, &pairs[..1], 5.0).is_none());
+    }
+
+    #[test]
+    fn test_unpaired() {
+        let a: Vec<f64> = (0..50).map(|i| 100.0 + f64::from(i % 5)).collect();
+        let b: Vec<f64> = (0..40).map(|i| 130.0 + f64::from(i % 5)).collect();
+        let slower = Comparison::unpaired(Metric::TtftMs, &a, &b, 5.0).unwrap();
+        assert!(slower.regression, "{slower:?}");
+        assert_eq!(slower.samples, 40);
+        assert!((slower.mean_diff - 30.0).abs() < 1e-9);
+        assert!(slower.ci95[0] > 0.0);
+
+        let same = Comparison::unpaired(Metric::TtftMs, &a, &a, 5.0).unwrap();
+        assert!(!same.regression);
+        assert!(Comparison::unpaired(Metric::TtftMs, &a, &b[..1], 5.0).is_none());
+    }
+
+    #[test]
+    fn test_load_report() {
+        let sample = |ms: u64| Sample {
+            error: false,
+            ttft: Some(Duration::from_millis(ms / 10)),
+            elapsed: Duration::from_millis(ms),
+            tokens_out: 10,
+        };
+        let answers_a: Vec<Answer> = (0..10).map(|_| answer_of("a", &sample(1000))).collect();
+        let answers_b: Vec<Answer> = (0..10).map(|_| answer_of("b", &sample(2000))).collect();
+        let report = make_load_report(
+            ("a", &answers_a, Duration::from_secs(5)),
+            ("b", &answers_b, Duration::from_secs(10)),
+            5.0,
+        );
+        assert_eq!(report.mode, "bench_compare");
+        assert_eq!(report.method, "load");
+        assert_eq!(report.requests, 20);
+        // 100 tokens over each run
+        assert_eq!(report.a.throughput_tps, 20.0);
+        assert_eq!(report.b.throughput_tps, 10.0);
+        let regressions: Vec<&str> = report
+            .comparisons
+            .iter()
+            .filter(|c| c.regression)
+            .map(|c| c.metric)
+            .collect();
+        assert_eq!(regressions, ["tokens_per_sec", "ttft_ms", "itl_ms"]);
+    }
+}
@@ -15,10 +15,15 @@
 
 use std::pin::Pin;
 
+use async_openai::types::Stop;
 use dynamo_llm::{
     backend::{Backend, ExecutionContext},
-    engines::StreamingEngineAdapter,
+    engines::{
+        fan_out::{FanOut, FanOutRequest, FanOutResponse},
+        StreamingEngineAdapter,
+    },
     http::service::discovery::ModelNetworkName,
+    kv_router::{KvPushRouter, KvRouter},
     model_card::ModelDeploymentCard,
     model_type::ModelType,
     preprocessor::OpenAIPreprocessor,
@@ -41,7 +46,7 @@ use dynamo_runtime::{
 };
 use std::sync::Arc;
 
-use crate::{flags::RouterMode, EngineConfig, Flags};
+use crate::{EngineConfig, Flags};
 
 pub struct PreparedEngine {
     pub service_name: String,
@@ -67,55 +72,69 @@ pub async fn prepare_engine(
 
             let client = endpoint.client().await?;
             let mut cache_dir = None;
-            let engine: OpenAIChatCompletionsStreamingEngine = match &flags.router_mode {
-                RouterMode::Random | RouterMode::RoundRobin => {
-                    tracing::info!("Waiting for remote model..");
-
-                    let remote_endpoints = client.wait_for_endpoints().await?;
-                    debug_assert!(!remote_endpoints.is_empty());
-                    tracing::info!(count = remote_endpoints.len(), "Model(s) discovered");
-
-                    let network_name: ModelNetworkName = (&remote_endpoints[0]).into();
-                    let Some(etcd_client) = distributed_runtime.etcd_client() else {
-                        anyhow::bail!("Cannot run distributed components without etcd");
-                    };
-                    let network_entry = network_name.load_entry(etcd_client.clone()).await?;
-                    let mut card = network_entry.load_mdc(endpoint_id, etcd_client).await?;
-
-                    match network_entry.model_type {
-                        ModelType::Backend => {
-                            // Download tokenizer.json etc to local disk
-                            cache_dir = Some(
-                                card.move_from_nats(distributed_runtime.nats_client())
-                                    .await?,
-                            );
-
-                            // The backend doesn't mind what we expose to the user (chat or
-                            // completions), and this function is only used by text and batch input so
-                            // the user doesn't see the HTTP request. So use Chat.
-                            let frontend = SegmentSource::<
-                                SingleIn<NvCreateChatCompletionRequest>,
-                                ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
-                            >::new();
-                            let preprocessor =
-                                OpenAIPreprocessor::new(card.clone()).await?.into_operator();
-                            let backend = Backend::from_mdc(card.clone()).await?.into_operator();
-                            let router =
-                                PushRouter::<BackendInput, Annotated<LLMEngineOutput>>::from_client(
-                                    client,
-                                    flags.router_mode.into(),
-                                )
-                                .await?;
-
-                            frontend
-                                .link(preprocessor.forward_edge())?
-                                .link(backend.forward_edge())?
-                                .link(ServiceBackend::from_engine(Arc::new(router)))?
-                                .link(backend.backward_edge())?
-                                .link(preprocessor.backward_edge())?
-                                .link(frontend)?
+            let engine: OpenAIChatCompletionsStreamingEngine = {
+                tracing::info!("Waiting for remote model..");
+
+                let remote_endpoints = client.wait_for_endpoints().await?;
+                debug_assert!(!remote_endpoints.is_empty());
+                tracing::info!(count = remote_endpoints.len(), "Model(s) discovered");
+
+                let network_name: ModelNetworkName = (&remote_endpoints[0]).into();
+                let Some(etcd_client) = distributed_runtime.etcd_client() else {
+                    anyhow::bail!("Cannot run distributed components without etcd");
+                };
+                let network_entry = network_name.load_entry(etcd_client.clone()).await?;
+                let mut card = network_entry.load_mdc(endpoint_id, etcd_client).await?;
+
+                match network_entry.model_type {
+                    ModelType::Backend => {
+                        // Download tokenizer.json etc to local disk
+                        cache_dir = Some(
+                            card.move_from_nats(distributed_runtime.nats_client())
+                                .await?,
+                        );
+
+                        // The backend doesn't mind what we expose to the user (chat or
+                        // completions), and this function is only used by text and batch input so
+                        // the user doesn't see the HTTP request. So use Chat.
+                        let frontend = SegmentSource::<
+                            SingleIn<NvCreateChatCompletionRequest>,
+                            ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
+                        >::new();
+                        let preprocessor =
+                            OpenAIPreprocessor::new(card.clone()).await?.into_operator();
+                        let backend = Backend::from_mdc(card.clone()).await?.into_operator();
+                        let router =
+                            PushRouter::<BackendInput, Annotated<LLMEngineOutput>>::from_client(
+                                client.clone(),
+                                flags.router_mode.clone().into(),
+                            )
+                            .await?;
+                        let router: ExecutionContext = if flags.router_mode.is_kv_routing() {
+                            let kv_router = KvRouter::new(
+                                client.endpoint.component().clone(),
+                                flags.kv_block_size as usize,
+                                None,
+                            )
+                            .await?;
+                            Arc::new(KvPushRouter::new(router, kv_router))
+                        } else {
+                            Arc::new(router)
+                        };
+
+                        frontend
+                            .link(preprocessor.forward_edge())?
+                            .link(backend.forward_edge())?
+                            .link(ServiceBackend::from_engine(router))?
+                            .link(backend.backward_edge())?
+                            .link(preprocessor.backward_edge())?
+                            .link(frontend)?
+                    }
+                    ModelType::Chat => {
+                        if flags.router_mode.is_kv_routing() {
+                            tracing::warn!("The workers pre-process requests themselves, so there are no tokens to route them by KV cache with, routing round-robin");
                         }
-                        ModelType::Chat => Arc::new(
+                        Arc::new(
                             PushRouter::<
                                 NvCreateChatCompletionRequest,
                                 Annotated<NvCreateChatCompletionStreamResponse>,
@@ -123,24 +142,33 @@ pub async fn prepare_engine(
                                 client, flags.router_mode.into()
                             )
                             .await?,
-                        ),
-                        ModelType::Completion => {
-                            anyhow::bail!("text and batch input only accept remote Chat models, not Completion");
-                            /*
-                            Arc::new(
-                                PushRouter::<
-                                    CompletionRequest,
-                                    Annotated<CompletionResponse>,
-                                >::from_client(
-                                    client, flags.router_mode.into()
-                                )
-                                .await?,
+                        )
+                    }
+                    ModelType::Completion => {
+                        anyhow::bail!(
+                            "text and batch input only accept remote Chat models, not Completion"
+                        );
+                        /*
+                        Arc::new(
+                            PushRouter::<
+                                CompletionRequest,
+                                Annotated<CompletionResponse>,
+                            >::from_client(
+                                client, flags.router_mode.into()
                             )
-                            */
-                        }
+                            .await?,
+                        )
+                        */
+                    }
+                    ModelType::Embedding => {
+                        anyhow::bail!(
+                            "text and batch input only accept remote Chat models, not Embedding"
+                        );
+                    }
+                    ModelType::Transcription => {
+                        anyhow::bail!("text and batch input only accept remote Chat models, not Transcription");
                     }
                 }
-                RouterMode::KV => todo!(),
             };
 
             // The service_name isn't used for text chat outside of logs,
@@ -164,6 +192,17 @@ pub async fn prepare_engine(
                 _cache_dir: None,
             })
         }
+        EngineConfig::Pool(_) => {
+            anyhow::bail!(
+                "text and batch input serve one model, several --model-path need in=http"
+            );
+        }
+        EngineConfig::Multi(multi) => Ok(PreparedEngine {
+            service_name: multi.default_model().to_string(),
+            engine: multi.chat_engine(None),
+            inspect_template: false,
+            _cache_dir: None,
+        }),
         EngineConfig::StaticCore {
             engine: inner_engine,
             model,
@@ -186,13 +225,15 @@ pub async fn prepare_engine(
     }
 }
 
+/// Pre-process requests for `engine`, which samples one choice per request. Requests for
+/// more are fanned out.
 pub async fn build_pipeline<Req, Resp>(
     card: &ModelDeploymentCard,
     engine: ExecutionContext,
-) -> anyhow::Result<Arc<ServiceFrontend<SingleIn<Req>, ManyOut<Annotated<Resp>>>>>
+) -> anyhow::Result<Arc<FanOut<Req, Resp>>>
 where
-    Req: Data,
-    Resp: Data,
+    Req: Data + FanOutRequest,
+    Resp: Data + FanOutResponse,
     OpenAIPreprocessor: Operator<
         Context<Req>,
         Pin<Box<dyn AsyncEngineStream<Annotated<Resp>>>>,
@@ -207,18 +248,114 @@ where
     let backend = Backend::from_mdc((*card).clone()).await?.into_operator();
     let engine = ServiceBackend::from_engine(engine);
 
-    Ok(frontend
+    let pipeline = frontend
         .link(preprocessor.forward_edge())?
         .link(backend.forward_edge())?
         .link(engine)?
         .link(backend.backward_edge())?
         .link(preprocessor.backward_edge())?
-        .link(frontend)?)
+        .link(frontend)?;
+    Ok(Arc::new(FanOut::new(pipeline)))
+}
+
+/// Run a chat completion request, JSON from a queue or an MCP client, through the engine.
+/// Returns all of the response at once. `model` defaults to the served model.
+pub async fn complete(
+    engine: OpenAIChatCompletionsStreamingEngine,
+    service_name: &str,
+    mut request: serde_json::Value,
+) -> anyhow::Result<dynamo_llm::types::openai::chat_completions::NvCreateChatCompletionResponse> {
+    use anyhow::Context as _;
+    use dynamo_llm::types::openai::chat_completions::NvCreateChatCompletionResponse;
+
+    let Some(fields) = request.as_object_mut() else {
+        anyhow::bail!("Request must be a JSON object");
+    };
+    fields
+        .entry("model")
+        .or_insert_with(|| service_name.to_string().into());
+    let request: NvCreateChatCompletionRequest =
+        serde_json::from_value(request).context("Request is not a chat completion request")?;
+    let stream = engine.generate(Context::new(request)).await?;
+    NvCreateChatCompletionResponse::from_annotated_stream(stream.into())
+        .await
+        .map_err(|err| anyhow::anyhow!(err))
+}
+
+/// What --system-prompt and the sampling flags set for in=text, in=stdin and in=batch. Unset
+/// fields fall back to --request-template, then to the defaults of each input.
+#[derive(Clone, Debug, Default)]
+pub struct Sampling {
+    pub system_prompt: Option<String>,
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub max_tokens: Option<u32>,
+    pub stop: Option<Stop>,
+}
+
+impl Sampling {
+    pub fn from_flags(flags: &Flags) -> Self {
+        let stop = match flags.stop.as_slice() {
+            [] => None,
+            [stop] => Some(Stop::String(stop.clone())),
+            stops => Some(Stop::StringArray(stops.to_vec())),
+        };
+        Sampling {
+            system_prompt: flags.system_prompt.clone(),
+            temperature: flags.temperature,
+            top_p: flags.top_p,
+            max_tokens: flags.max_tokens,
+            stop,
+        }
+    }
+}
+
+/// Summary of latencies and the like, for the reports of in=batch and in=bench
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub struct Percentiles {
+    pub mean: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+}
+
+impl Percentiles {
+    /// None without values
+    pub fn of(mut values: Vec<f64>) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_by(f64::total_cmp);
+        // Nearest rank
+        let at = |p: f64| values[((p / 100.0 * values.len() as f64).ceil() as usize).max(1) - 1];
+        Some(Percentiles {
+            mean: values.iter().sum::<f64>() / values.len() as f64,
+            p50: at(50.0),
+            p90: at(90.0),
+            p99: at(99.0),
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    #[test]
+    fn test_percentiles() {
+        let values: Vec<f64> = (1..=100).map(f64::from).collect();
+        assert_eq!(
+            Percentiles::of(values),
+            Some(Percentiles {
+                mean: 50.5,
+                p50: 50.0,
+                p90: 90.0,
+                p99: 99.0
+            })
+        );
+        assert_eq!(Percentiles::of(vec![]), None);
+    }
+
     use dynamo_llm::types::openai::{
         chat_completions::{NvCreateChatCompletionRequest, NvCreateChatCompletionStreamResponse},
         completions::{CompletionRequest, CompletionResponse},
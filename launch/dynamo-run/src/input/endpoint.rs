@@ -13,11 +13,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{pin::Pin, sync::Arc};
+use std::{pin::Pin, sync::Arc, time::Duration};
 
 use dynamo_llm::{
     backend::Backend,
     engines::StreamingEngineAdapter,
+    gpu_telemetry,
     model_type::ModelType,
     preprocessor::{BackendInput, BackendOutput},
     types::{
@@ -27,6 +28,7 @@ use dynamo_llm::{
         Annotated,
     },
 };
+use dynamo_runtime::component::WorkerCapabilities;
 use dynamo_runtime::engine::AsyncEngineStream;
 use dynamo_runtime::pipeline::{
     network::Ingress, Context, ManyOut, Operator, SegmentSource, ServiceBackend, SingleIn, Source,
@@ -39,8 +41,25 @@ pub async fn run(
     distributed_runtime: DistributedRuntime,
     path: String,
     engine_config: EngineConfig,
+    gpu_telemetry: Option<Duration>,
+    tokens_per_sec: Option<f32>,
 ) -> anyhow::Result<()> {
     let cancel_token = distributed_runtime.primary_token().clone();
+    let gpu_load = match gpu_telemetry {
+        Some(interval) => Some(gpu_telemetry::start(interval, cancel_token.clone())?),
+        None => None,
+    };
+    // Advertised for routers to weight us against workers with other GPUs
+    let mut capabilities = WorkerCapabilities {
+        tokens_per_sec,
+        ..Default::default()
+    };
+    if gpu_telemetry.is_some() {
+        match gpu_telemetry::capabilities() {
+            Ok(gpus) => capabilities = capabilities.or(gpus),
+            Err(err) => tracing::warn!(%err, "Failed detecting the GPUs"),
+        }
+    }
     let endpoint_id: EndpointId = path.parse()?;
 
     let component = distributed_runtime
@@ -61,7 +80,14 @@ pub async fn run(
             >::for_engine(engine)?;
 
             model.attach(&endpoint, ModelType::Chat).await?;
-            let fut_chat = endpoint.endpoint_builder().handler(ingress_chat).start();
+            let mut builder = endpoint
+                .endpoint_builder()
+                .handler(ingress_chat)
+                .capabilities(capabilities);
+            if let Some(gpu_load) = gpu_load {
+                builder = builder.gpu_load(gpu_load);
+            }
+            let fut_chat = builder.start();
 
             (fut_chat, model.card().clone())
         }
@@ -84,13 +110,26 @@ pub async fn run(
             let ingress = Ingress::for_pipeline(pipeline)?;
 
             model.attach(&endpoint, ModelType::Backend).await?;
-            let fut = endpoint.endpoint_builder().handler(ingress).start();
+            let mut builder = endpoint
+                .endpoint_builder()
+                .handler(ingress)
+                .capabilities(capabilities);
+            if let Some(gpu_load) = gpu_load {
+                builder = builder.gpu_load(gpu_load);
+            }
+            let fut = builder.start();
 
             (fut, model.card().clone())
         }
         EngineConfig::Dynamic(_) => {
             anyhow::bail!("Cannot use endpoint for both in and out");
         }
+        EngineConfig::Pool(_) => {
+            anyhow::bail!("in=dyn:// serves one model, several --model-path need in=http");
+        }
+        EngineConfig::Multi(_) => {
+            anyhow::bail!("in=dyn:// serves one model, it cannot take out=multi");
+        }
     };
 
     tokio::select! {
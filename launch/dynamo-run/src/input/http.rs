@@ -13,15 +13,35 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use crate::input::common;
 use crate::{EngineConfig, Flags};
 use dynamo_llm::http::service::ModelManager;
 use dynamo_llm::{
+    auth::ApiKeys,
     engines::StreamingEngineAdapter,
-    http::service::{discovery, service_v2},
+    http::service::{
+        access_log::AccessLogConfig,
+        admission::AdmissionConfig,
+        coalesce::StreamCoalescing,
+        conversations::{ConversationConfig, ConversationStore},
+        cors::CorsConfig,
+        discovery,
+        idempotency::IdempotencyConfig,
+        limits::RequestLimits,
+        moderation::{Classifier, ContentFilter, Moderator, Rules},
+        prefetch::PrefetchConfig,
+        rate_limit::RateLimitConfig,
+        response_cache::{ResponseCacheConfig, SharedResponseCache},
+        service_v2::{self, HttpService, ReloadConfig},
+        shedding::{ShedAction, SloConfig},
+        tls::TlsConfig,
+    },
+    preprocessor::OpenAIPreprocessor,
+    presets::PresetLibrary,
     request_template::RequestTemplate,
+    tenancy::Tenants,
     types::{
         openai::chat_completions::{
             NvCreateChatCompletionRequest, NvCreateChatCompletionStreamResponse,
@@ -29,42 +49,229 @@ use dynamo_llm::{
         openai::completions::{CompletionRequest, CompletionResponse},
     },
 };
+use dynamo_runtime::pipeline::SharedRouterMode;
 use dynamo_runtime::transports::etcd;
-use dynamo_runtime::{DistributedRuntime, Runtime};
+use dynamo_runtime::{CancellationToken, DistributedRuntime, Runtime};
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+
+#[cfg(feature = "redis")]
+mod redis_cache;
+#[cfg(feature = "redis")]
+mod redis_conversations;
+
+/// How long a request waits for the content classifier before failing
+const CONTENT_CLASSIFIER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The endpoints `in=http` serves. `gen-client` uses this too, so that generated clients
+/// match the server.
+pub fn service_builder() -> service_v2::HttpServiceConfigBuilder {
+    service_v2::HttpService::builder()
+        .enable_chat_endpoints(true)
+        .enable_cmpl_endpoints(true)
+        .enable_batches_endpoints(true)
+}
 
 /// Build and run an HTTP service
 pub async fn run(
     runtime: Runtime,
     flags: Flags,
     engine_config: EngineConfig,
+    // The model dynamo-run loaded, empty with out=dyn://
+    served_model: &str,
     template: Option<RequestTemplate>,
 ) -> anyhow::Result<()> {
-    let http_service = service_v2::HttpService::builder()
+    let response_cache = flags.response_cache_ttl.map(|ttl| {
+        let defaults = ResponseCacheConfig::default();
+        ResponseCacheConfig {
+            ttl: Duration::from_secs(ttl),
+            stale_after: flags.response_cache_stale_after.map(Duration::from_secs),
+            capacity: flags.response_cache_size.unwrap_or(defaults.capacity),
+            models: (!flags.response_cache_models.is_empty())
+                .then(|| flags.response_cache_models.iter().cloned().collect()),
+        }
+    });
+    let response_cache_shared: Option<Arc<dyn SharedResponseCache>> =
+        match &flags.response_cache_redis {
+            #[cfg(feature = "redis")]
+            Some(url) => Some(Arc::new(
+                redis_cache::RedisResponseCache::connect(url).await?,
+            )),
+            #[cfg(not(feature = "redis"))]
+            Some(_) => anyhow::bail!(
+                "--response-cache-redis needs dynamo-run built with the 'redis' feature"
+            ),
+            None => None,
+        };
+    let conversations = flags.conversation_ttl.map(|ttl| {
+        let defaults = ConversationConfig::default();
+        ConversationConfig {
+            ttl: Duration::from_secs(ttl),
+            max_conversations: flags.conversation_max.unwrap_or(defaults.max_conversations),
+        }
+    });
+    let conversation_store: Option<Arc<dyn ConversationStore>> = match &flags.conversation_redis {
+        #[cfg(feature = "redis")]
+        Some(url) => Some(Arc::new(
+            redis_conversations::RedisConversationStore::connect(url).await?,
+        )),
+        #[cfg(not(feature = "redis"))]
+        Some(_) => {
+            anyhow::bail!("--conversation-redis needs dynamo-run built with the 'redis' feature")
+        }
+        None => None,
+    };
+    let prefetch = flags.prefetch_ttl.map(|ttl| PrefetchConfig {
+        ttl: Duration::from_secs(ttl),
+        capacity: flags
+            .prefetch_capacity
+            .unwrap_or(PrefetchConfig::default().capacity),
+    });
+    let content_filter = content_filter(&flags)?;
+    let stream_coalescing = flags.stream_coalesce_ms.filter(|ms| *ms > 0).map(|ms| {
+        let mut coalescing = StreamCoalescing::new(Duration::from_millis(ms));
+        if let Some(max) = flags.stream_coalesce_max {
+            coalescing.max_events = max as usize;
+        }
+        coalescing
+    });
+    let request_limits = RequestLimits {
+        max_output_tokens: flags.max_output_tokens,
+        max_prompt_tokens: flags.max_prompt_tokens,
+        timeout: flags.request_timeout.map(Duration::from_secs),
+    };
+    let cors = (!flags.cors_origins.is_empty()).then(|| CorsConfig {
+        origins: flags.cors_origins.clone(),
+        headers: flags.cors_headers.clone(),
+        credentials: flags.cors_credentials,
+    });
+    let idempotency = flags.idempotency_ttl.map(|ttl| {
+        let mut idempotency = IdempotencyConfig::new(Duration::from_secs(ttl));
+        if let Some(max_keys) = flags.idempotency_max_keys {
+            idempotency.max_keys = max_keys;
+        }
+        idempotency
+    });
+    let reloadable = reload_config(&flags)?;
+    let slo = SloConfig {
+        ttft_p99: flags.slo_ttft_ms.map(Duration::from_millis),
+        queue_delay_p99: flags.slo_queue_delay_ms.map(Duration::from_millis),
+        action: match flags.slo_degrade_max_tokens {
+            Some(max_tokens) => ShedAction::Degrade { max_tokens },
+            None => ShedAction::Reject,
+        },
+    };
+    let admission = flags
+        .max_running_per_model
+        .map(|max_running| AdmissionConfig {
+            max_running,
+            max_queued: flags.max_queued_per_model,
+            starvation_timeout: Duration::from_millis(flags.starvation_timeout_ms),
+        });
+    let presets = flags
+        .presets
+        .as_deref()
+        .map(PresetLibrary::load)
+        .transpose()?;
+    let tenants = flags
+        .tenants
+        .as_deref()
+        .map(Tenants::from_file)
+        .transpose()?
+        .map(Arc::new);
+    let access_log = flags.access_log.as_ref().map(|path| AccessLogConfig {
+        path: (path.as_os_str() != "-").then(|| path.clone()),
+        sample_rate: flags.access_log_sample_rate.unwrap_or(1.0),
+        prompts: flags.access_log_prompts.unwrap_or_default(),
+    });
+    // clap makes sure the key comes with the certificate
+    let tls = flags.tls_cert.clone().map(|cert| TlsConfig {
+        cert,
+        key: flags.tls_key.clone().unwrap_or_default(),
+        client_ca: flags.tls_client_ca.clone(),
+    });
+    let model_loader = match &engine_config {
+        EngineConfig::Pool(pool) if flags.model_admin => Some(pool.loader()),
+        _ => None,
+    };
+    let http_service = service_builder()
         .port(flags.http_port)
-        .enable_chat_endpoints(true)
-        .enable_cmpl_endpoints(true)
         .with_request_template(template)
+        .batch_concurrency(flags.batch_concurrency.map(|n| n as usize))
+        .response_cache(response_cache)
+        .response_cache_shared(response_cache_shared)
+        .stream_coalescing(stream_coalescing)
+        .request_limits(request_limits)
+        .sse_keep_alive(flags.sse_keep_alive.map(Duration::from_secs))
+        .cors(cors)
+        .trusted_proxies(flags.trusted_proxies.clone())
+        .idempotency(idempotency)
+        .presets(presets.map(Arc::new))
+        .conversations(conversations)
+        .conversation_store(conversation_store)
+        .prefetch(prefetch)
+        .content_filter(content_filter)
+        .api_keys(reloadable.api_keys)
+        .tenants(tenants.clone())
+        .rate_limit(reloadable.rate_limit)
+        .model_aliases(reloadable.model_aliases)
+        .tls(tls)
+        .slo(Some(slo))
+        .admission(admission)
+        .drain(Some(runtime.drain()))
+        .access_log(access_log)
+        .model_loader(model_loader)
+        .enable_admin_endpoints(flags.admin_api)
+        .enable_lora_admin_endpoints(flags.lora_admin)
         .build()?;
+    for adapter in &flags.lora {
+        if served_model.is_empty() {
+            anyhow::bail!(
+                "--lora needs the model dynamo-run loads. With out=dyn:// add adapters with --lora-admin and POST /admin/lora."
+            );
+        }
+        http_service
+            .model_manager()
+            .add_lora_adapter(served_model, adapter.clone())?;
+    }
+    // Set once we know the engine is remote
+    let router_mode = SharedRouterMode::default();
     match engine_config {
         EngineConfig::Dynamic(endpoint) => {
             let distributed_runtime = DistributedRuntime::from_settings(runtime.clone()).await?;
             match distributed_runtime.etcd_client() {
                 Some(etcd_client) => {
+                    router_mode.set(flags.router_mode.clone().into());
+                    let kv_block_size = flags
+                        .router_mode
+                        .is_kv_routing()
+                        .then_some(flags.kv_block_size as usize);
                     // This will attempt to connect to NATS and etcd
 
-                    let component = distributed_runtime
-                        .namespace(endpoint.namespace)?
-                        .component(endpoint.component)?;
-                    let network_prefix = component.service_name();
-
-                    // Listen for models registering themselves in etcd, add them to HTTP service
-                    run_watcher(
-                        distributed_runtime.clone(),
-                        http_service.model_manager().clone(),
-                        etcd_client.clone(),
-                        &network_prefix,
-                    )
-                    .await?;
+                    // The same component in each tenant's namespace
+                    let mut namespaces = vec![endpoint.namespace];
+                    for namespace in tenants.iter().flat_map(|t| t.namespaces()) {
+                        if !namespaces.contains(&namespace) {
+                            namespaces.push(namespace);
+                        }
+                    }
+                    for namespace in namespaces {
+                        let component = distributed_runtime
+                            .namespace(namespace)?
+                            .component(&endpoint.component)?;
+                        let network_prefix = component.service_name();
+
+                        // Listen for models registering themselves in etcd, add them to HTTP service
+                        run_watcher(
+                            distributed_runtime.clone(),
+                            http_service.model_manager().clone(),
+                            etcd_client.clone(),
+                            &network_prefix,
+                            router_mode.clone(),
+                            kv_block_size,
+                        )
+                        .await?;
+                    }
                 }
                 None => {
                     // Static endpoints don't need discovery
@@ -72,8 +279,15 @@ pub async fn run(
             }
         }
         EngineConfig::StaticFull { engine, model } => {
+            let embeddings = engine.embeddings();
             let engine = Arc::new(StreamingEngineAdapter::new(engine));
             let manager = http_service.model_manager();
+            if let Some(engine_name) = &model.card().engine {
+                manager.set_engine_name(model.service_name(), engine_name);
+            }
+            if let Some(embeddings) = embeddings {
+                manager.add_embeddings_model(model.service_name(), embeddings)?;
+            }
             manager.add_completions_model(model.service_name(), engine.clone())?;
             manager.add_chat_completions_model(model.service_name(), engine)?;
         }
@@ -82,6 +296,9 @@ pub async fn run(
             model,
         } => {
             let manager = http_service.model_manager();
+            if let Some(engine_name) = &model.card().engine {
+                manager.set_engine_name(model.service_name(), engine_name);
+            }
 
             let chat_pipeline = common::build_pipeline::<
                 NvCreateChatCompletionRequest,
@@ -96,26 +313,156 @@ pub async fn run(
             )
             .await?;
             manager.add_completions_model(model.service_name(), cmpl_pipeline)?;
+
+            let preprocessor = OpenAIPreprocessor::new(model.card().clone()).await?;
+            manager.add_preprocessor(model.service_name(), preprocessor)?;
+        }
+        EngineConfig::Pool(pool) => {
+            let manager = http_service.model_manager();
+            for card in pool.cards() {
+                let name = &card.service_name;
+                if let Some(engine_name) = &card.engine {
+                    manager.set_engine_name(name, engine_name);
+                }
+                manager.add_chat_completions_model(name, pool.chat_engine(name))?;
+                manager.add_completions_model(name, pool.completions_engine(name))?;
+                if card.has_tokenizer() {
+                    let preprocessor = OpenAIPreprocessor::new(card.clone()).await?;
+                    manager.add_preprocessor(name, preprocessor)?;
+                }
+            }
+        }
+        EngineConfig::Multi(multi) => {
+            let manager = http_service.model_manager();
+            for model in multi.models() {
+                manager.add_chat_completions_model(model, multi.chat_engine(Some(model)))?;
+            }
         }
     }
+    reload_on_hangup(
+        http_service.clone(),
+        router_mode,
+        flags.router_mode.is_kv_routing(),
+        runtime.primary_token(),
+    )?;
     http_service.run(runtime.primary_token()).await?;
     runtime.shutdown(); // Cancel primary token
     Ok(())
 }
 
+/// What the service can change while it runs, as `flags` set it
+fn reload_config(flags: &Flags) -> anyhow::Result<ReloadConfig> {
+    let api_keys = match &flags.api_keys {
+        Some(path) => Some(ApiKeys::from_file(path)?),
+        None => ApiKeys::from_env()?,
+    };
+    Ok(ReloadConfig {
+        api_keys: api_keys.map(Arc::new),
+        rate_limit: Some(RateLimitConfig {
+            requests_per_minute: flags.rate_limit_rpm,
+            tokens_per_minute: flags.rate_limit_tpm,
+            max_concurrent_requests: flags.max_concurrent_requests,
+        }),
+        model_aliases: flags
+            .model_alias
+            .iter()
+            .map(|alias| (alias.alias.clone(), alias.targets.clone()))
+            .collect(),
+    })
+}
+
+/// The rules, then the classifier, of the --content-filter-* flags. None without either.
+fn content_filter(flags: &Flags) -> anyhow::Result<Option<Arc<ContentFilter>>> {
+    let mut moderators: Vec<Arc<dyn Moderator>> = Vec::new();
+    if let Some(path) = &flags.content_filter_rules {
+        moderators.push(Arc::new(Rules::load(path)?));
+    }
+    if let Some(url) = &flags.content_filter_url {
+        moderators.push(Arc::new(Classifier::new(
+            url,
+            flags.content_filter_model.clone(),
+            CONTENT_CLASSIFIER_TIMEOUT,
+        )?));
+    }
+    if moderators.is_empty() {
+        return Ok(None);
+    }
+    let scan_chars = flags.content_filter_scan_chars.unwrap_or(100);
+    Ok(Some(Arc::new(ContentFilter::new(moderators, scan_chars))))
+}
+
+/// On each SIGHUP read the flags again, `--config` file and `--api-keys` file included, and
+/// apply their API keys, rate limits, model aliases and router mode. Requests already running
+/// are not affected. If anything is wrong none of it is applied. Models are KV routed or not
+/// from the start, `kv_routing`, so `--router-mode kv` can't be switched to or from.
+#[cfg(unix)]
+fn reload_on_hangup(
+    http_service: HttpService,
+    router_mode: SharedRouterMode,
+    kv_routing: bool,
+    cancel_token: CancellationToken,
+) -> anyhow::Result<()> {
+    let mut hangup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = cancel_token.cancelled() => break,
+                received = hangup.recv() => {
+                    if received.is_none() {
+                        break;
+                    }
+                }
+            }
+            let reloaded = crate::config::reread_flags().and_then(|flags| {
+                if flags.router_mode.is_kv_routing() != kv_routing {
+                    anyhow::bail!("--router-mode kv can only be turned on or off with a restart");
+                }
+                http_service.reload(reload_config(&flags)?)?;
+                router_mode.set(flags.router_mode.into());
+                Ok(())
+            });
+            match reloaded {
+                Ok(()) => tracing::info!(
+                    "SIGHUP: reloaded API keys, rate limits, model aliases and router mode"
+                ),
+                Err(err) => {
+                    tracing::error!("SIGHUP: reload failed, keeping the current settings: {err:#}")
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Windows has no SIGHUP, settings are only read at start
+#[cfg(windows)]
+fn reload_on_hangup(
+    _http_service: HttpService,
+    _router_mode: SharedRouterMode,
+    _kv_routing: bool,
+    _cancel_token: CancellationToken,
+) -> anyhow::Result<()> {
+    Ok(())
+}
+
 /// Spawns a task that watches for new models in etcd at network_prefix,
 /// and registers them with the ModelManager so that the HTTP service can use them.
+/// With a `kv_block_size` pre-processed requests are routed by KV cache.
 async fn run_watcher(
     distributed_runtime: DistributedRuntime,
     model_manager: ModelManager,
     etcd_client: etcd::Client,
     network_prefix: &str,
+    router_mode: SharedRouterMode,
+    kv_block_size: Option<usize>,
 ) -> anyhow::Result<()> {
-    let state = Arc::new(discovery::ModelWatchState {
-        prefix: network_prefix.to_string(),
-        manager: model_manager,
-        drt: distributed_runtime.clone(),
-    });
+    let mut state =
+        discovery::ModelWatchState::new(network_prefix, model_manager, distributed_runtime.clone())
+            .with_shared_router_mode(router_mode);
+    if let Some(block_size) = kv_block_size {
+        state = state.with_kv_routing(block_size);
+    }
+    let state = Arc::new(state);
     tracing::info!("Watching for remote model at {network_prefix}");
     let models_watcher = etcd_client.kv_get_and_watch_prefix(network_prefix).await?;
     let (_prefix, _watcher, receiver) = models_watcher.dissolve();
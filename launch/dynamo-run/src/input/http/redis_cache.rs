@@ -0,0 +1,71 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `--response-cache-redis`: a response cache the frontends using the same Redis share. Each
+//! response is a JSON string that expires with the cache's TTL.
+
+use std::time::Duration;
+
+use ::redis::aio::MultiplexedConnection;
+use ::redis::AsyncCommands as _;
+use anyhow::Context as _;
+use async_trait::async_trait;
+use dynamo_llm::http::service::response_cache::SharedResponseCache;
+use dynamo_llm::types::openai::chat_completions::NvCreateChatCompletionResponse;
+
+const KEY_PREFIX: &str = "dynamo:response-cache:";
+
+pub struct RedisResponseCache {
+    connection: MultiplexedConnection,
+}
+
+impl RedisResponseCache {
+    pub async fn connect(url: &str) -> anyhow::Result<Self> {
+        let client =
+            ::redis::Client::open(url).with_context(|| format!("Invalid Redis URL {url}"))?;
+        let connection = client
+            .get_multiplexed_async_connection()
+            .await
+            .with_context(|| format!("Failed connecting to Redis at {url}"))?;
+        tracing::info!(url, "Sharing the response cache through Redis");
+        Ok(RedisResponseCache { connection })
+    }
+}
+
+#[async_trait]
+impl SharedResponseCache for RedisResponseCache {
+    async fn get(&self, key: &str) -> anyhow::Result<Option<NvCreateChatCompletionResponse>> {
+        let mut connection = self.connection.clone();
+        let value: Option<Vec<u8>> = connection.get(format!("{KEY_PREFIX}{key}")).await?;
+        value
+            .map(|value| serde_json::from_slice(&value))
+            .transpose()
+            .context("Cached response is not a chat completion")
+    }
+
+    async fn put(
+        &self,
+        key: &str,
+        response: &NvCreateChatCompletionResponse,
+        ttl: Duration,
+    ) -> anyhow::Result<()> {
+        let mut connection = self.connection.clone();
+        let value = serde_json::to_vec(response)?;
+        let _: () = connection
+            .set_ex(format!("{KEY_PREFIX}{key}"), value, ttl.as_secs().max(1))
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,71 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `--conversation-redis`: conversations kept in Redis, so that any frontend using it can
+//! continue them. Each conversation is a JSON array of messages, expiring with its TTL.
+
+use std::time::Duration;
+
+use ::redis::aio::MultiplexedConnection;
+use ::redis::AsyncCommands as _;
+use anyhow::Context as _;
+use async_openai::types::ChatCompletionRequestMessage;
+use async_trait::async_trait;
+use dynamo_llm::http::service::conversations::ConversationStore;
+
+const KEY_PREFIX: &str = "dynamo:conversation:";
+
+pub struct RedisConversationStore {
+    connection: MultiplexedConnection,
+}
+
+impl RedisConversationStore {
+    pub async fn connect(url: &str) -> anyhow::Result<Self> {
+        let client =
+            ::redis::Client::open(url).with_context(|| format!("Invalid Redis URL {url}"))?;
+        let connection = client
+            .get_multiplexed_async_connection()
+            .await
+            .with_context(|| format!("Failed connecting to Redis at {url}"))?;
+        tracing::info!(url, "Keeping conversations in Redis");
+        Ok(RedisConversationStore { connection })
+    }
+}
+
+#[async_trait]
+impl ConversationStore for RedisConversationStore {
+    async fn load(&self, key: &str) -> anyhow::Result<Option<Vec<ChatCompletionRequestMessage>>> {
+        let mut connection = self.connection.clone();
+        let value: Option<Vec<u8>> = connection.get(format!("{KEY_PREFIX}{key}")).await?;
+        value
+            .map(|value| serde_json::from_slice(&value))
+            .transpose()
+            .context("Stored conversation is not a list of chat messages")
+    }
+
+    async fn save(
+        &self,
+        key: &str,
+        messages: &[ChatCompletionRequestMessage],
+        ttl: Duration,
+    ) -> anyhow::Result<()> {
+        let mut connection = self.connection.clone();
+        let value = serde_json::to_vec(messages)?;
+        let _: () = connection
+            .set_ex(format!("{KEY_PREFIX}{key}"), value, ttl.as_secs().max(1))
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,329 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `in=kafka:<brokers>/<topic>` consumes requests from a Kafka topic and produces the
+//! responses to a reply topic, to drive bulk generation from an event pipeline.
+//!
+//! A request message is an OpenAI chat completion request as JSON, `model` defaults to the
+//! served model. The response is the chat completion, or `{"error": {"message": ...}}`, sent
+//! to the topic in the request's `reply_to` header, else to `--kafka-reply-topic`. It has the
+//! request's key and a `correlation_id` header: the request's own, or its key if it has none.
+//!
+//! Every dynamo-run in a `--kafka-group` gets some of the topic's partitions. A request's
+//! offset is committed once its response is delivered and every earlier request of the
+//! partition is too, so a restart runs again what was in flight: delivery is at least once.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Context as _;
+use async_trait::async_trait;
+use dynamo_llm::types::openai::chat_completions::{
+    NvCreateChatCompletionResponse, OpenAIChatCompletionsStreamingEngine,
+};
+use dynamo_runtime::Runtime;
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{CommitMode, Consumer, StreamConsumer};
+use rdkafka::message::{Header, Headers, Message, OwnedHeaders};
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::{Offset, TopicPartitionList};
+use tokio::sync::Semaphore;
+
+use crate::input::common;
+use crate::plugin::InputProvider;
+use crate::{EngineConfig, Flags};
+
+const DEFAULT_GROUP: &str = "dynamo-run";
+const REPLY_TO_HEADER: &str = "reply_to";
+const CORRELATION_ID_HEADER: &str = "correlation_id";
+
+/// How long the producer keeps trying to deliver a response
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A request message, copied out of the consumer's buffer
+struct Request {
+    partition: i32,
+    offset: i64,
+    key: Option<Vec<u8>>,
+    correlation_id: Option<Vec<u8>>,
+    reply_to: String,
+    payload: Vec<u8>,
+}
+
+/// `in=kafka`, registered by [`crate::plugin::register_features`]
+pub struct KafkaInput;
+
+#[async_trait]
+impl InputProvider for KafkaInput {
+    fn name(&self) -> &'static str {
+        "kafka"
+    }
+
+    fn parse(&self, arg: &str) -> anyhow::Result<()> {
+        brokers_and_topic(arg).map(|_| ())
+    }
+
+    async fn run(
+        &self,
+        runtime: Runtime,
+        flags: Flags,
+        arg: &str,
+        engine: EngineConfig,
+    ) -> anyhow::Result<()> {
+        let (brokers, topic) = brokers_and_topic(arg)?;
+        run(runtime, flags, brokers, topic, engine).await
+    }
+}
+
+/// `<brokers>/<topic>`, the brokers a comma separated list of `host:port`
+fn brokers_and_topic(arg: &str) -> anyhow::Result<(&str, &str)> {
+    match arg.rsplit_once('/') {
+        Some((brokers, topic)) if !brokers.is_empty() && !topic.is_empty() => Ok((brokers, topic)),
+        _ => anyhow::bail!(
+            "Invalid in=kafka option 'kafka:{arg}', expected kafka:<brokers>/<topic>, \
+             e.g. kafka:localhost:9092/requests"
+        ),
+    }
+}
+
+async fn run(
+    runtime: Runtime,
+    flags: Flags,
+    brokers: &str,
+    topic: &str,
+    engine_config: EngineConfig,
+) -> anyhow::Result<()> {
+    let cancel_token = runtime.primary_token();
+    let group = flags
+        .kafka_group
+        .as_deref()
+        .unwrap_or(DEFAULT_GROUP)
+        .to_string();
+    let reply_topic = flags
+        .kafka_reply_topic
+        .clone()
+        .unwrap_or_else(|| format!("{topic}-replies"));
+
+    let mut producer_config = ClientConfig::new();
+    producer_config.set("bootstrap.servers", brokers);
+    let mut consumer_config = producer_config.clone();
+    consumer_config
+        .set("group.id", &group)
+        .set("auto.offset.reset", "earliest")
+        // We store each offset once its response is delivered, see OffsetTracker
+        .set("enable.auto.offset.store", "false");
+    for setting in &flags.kafka_config {
+        producer_config.set(&setting.key, &setting.value);
+        consumer_config.set(&setting.key, &setting.value);
+    }
+    let consumer: StreamConsumer = consumer_config
+        .create()
+        .context("Failed creating the Kafka consumer")?;
+    let producer: FutureProducer = producer_config
+        .create()
+        .context("Failed creating the Kafka producer")?;
+    consumer
+        .subscribe(&[topic])
+        .with_context(|| format!("Failed subscribing to Kafka topic {topic}"))?;
+    let consumer = Arc::new(consumer);
+
+    let in_flight = Arc::new(Semaphore::new(flags.kafka_concurrency as usize));
+    let prepared_engine = common::prepare_engine(runtime, flags, engine_config).await?;
+    let service_name = Arc::new(prepared_engine.service_name);
+    let tracker = Arc::new(Mutex::new(OffsetTracker::default()));
+    let topic = Arc::new(topic.to_string());
+    tracing::info!(%brokers, %topic, %group, %reply_topic, "Consuming requests from Kafka");
+
+    let mut handles = vec![];
+    loop {
+        let permit = tokio::select! {
+            _ = cancel_token.cancelled() => break,
+            permit = in_flight.clone().acquire_owned() => permit?,
+        };
+        let message = tokio::select! {
+            _ = cancel_token.cancelled() => break,
+            message = consumer.recv() => message,
+        };
+        let message = match message {
+            Ok(message) => message,
+            Err(err) => {
+                tracing::warn!(%err, "Kafka consumer error");
+                continue;
+            }
+        };
+        let header = |name: &str| {
+            let headers = message.headers()?;
+            headers
+                .iter()
+                .find(|header| header.key == name)
+                .and_then(|header| header.value.map(|value| value.to_vec()))
+        };
+        let request = Request {
+            partition: message.partition(),
+            offset: message.offset(),
+            key: message.key().map(|key| key.to_vec()),
+            correlation_id: header(CORRELATION_ID_HEADER),
+            reply_to: header(REPLY_TO_HEADER)
+                .and_then(|topic| String::from_utf8(topic).ok())
+                .unwrap_or_else(|| reply_topic.clone()),
+            payload: message.payload().unwrap_or_default().to_vec(),
+        };
+        tracker
+            .lock()
+            .unwrap()
+            .start(request.partition, request.offset);
+
+        let engine = prepared_engine.engine.clone();
+        let service_name = service_name.clone();
+        let producer = producer.clone();
+        let consumer = consumer.clone();
+        let tracker = tracker.clone();
+        let topic = topic.clone();
+        handles.push(tokio::spawn(async move {
+            let _permit = permit;
+            let (partition, offset) = (request.partition, request.offset);
+            let reply = match respond(engine, &service_name, &request.payload).await {
+                Ok(response) => serde_json::to_vec(&response),
+                Err(err) => {
+                    tracing::warn!(partition, offset, "Request failed: {err:#}");
+                    serde_json::to_vec(&serde_json::json!({
+                        "error": {"message": format!("{err:#}")}
+                    }))
+                }
+            };
+            let reply = match reply {
+                Ok(reply) => reply,
+                Err(err) => {
+                    tracing::error!(partition, offset, %err, "Failed serializing response");
+                    return;
+                }
+            };
+            if let Err(err) = send_reply(&producer, &request, &reply).await {
+                // Not committed, so it runs again after a restart or rebalance
+                tracing::error!(partition, offset, reply_to = request.reply_to, "{err:#}");
+                return;
+            }
+            let next = tracker.lock().unwrap().finish(partition, offset);
+            if let Some(next) = next {
+                let mut offsets = TopicPartitionList::new();
+                let stored = offsets
+                    .add_partition_offset(&topic, partition, Offset::Offset(next))
+                    .and_then(|_| consumer.store_offsets(&offsets));
+                if let Err(err) = stored {
+                    // The partition may have moved to another consumer in the group
+                    tracing::debug!(partition, next, %err, "Failed storing Kafka offset");
+                }
+            }
+        }));
+        handles.retain(|handle| !handle.is_finished());
+    }
+
+    // Let what's in flight finish, then commit it
+    futures::future::join_all(handles).await;
+    if let Err(err) = consumer.commit_consumer_state(CommitMode::Sync) {
+        tracing::warn!(%err, "Failed committing Kafka offsets");
+    }
+    Ok(())
+}
+
+/// Run a request through the engine, all of the response at once
+async fn respond(
+    engine: OpenAIChatCompletionsStreamingEngine,
+    service_name: &str,
+    payload: &[u8],
+) -> anyhow::Result<NvCreateChatCompletionResponse> {
+    let request = serde_json::from_slice(payload).context("Request is not JSON")?;
+    common::complete(engine, service_name, request).await
+}
+
+async fn send_reply(
+    producer: &FutureProducer,
+    request: &Request,
+    reply: &[u8],
+) -> anyhow::Result<()> {
+    let mut record: FutureRecord<'_, Vec<u8>, [u8]> =
+        FutureRecord::to(&request.reply_to).payload(reply);
+    if let Some(key) = &request.key {
+        record = record.key(key);
+    }
+    if let Some(correlation_id) = request.correlation_id.as_ref().or(request.key.as_ref()) {
+        record = record.headers(OwnedHeaders::new().insert(Header {
+            key: CORRELATION_ID_HEADER,
+            value: Some(correlation_id),
+        }));
+    }
+    producer
+        .send(record, DELIVERY_TIMEOUT)
+        .await
+        .map_err(|(err, _)| anyhow::anyhow!("Failed delivering response: {err}"))?;
+    Ok(())
+}
+
+/// The offsets of each partition being worked on, to commit only past those all done
+#[derive(Default)]
+struct OffsetTracker {
+    /// By partition, each offset and whether it is done
+    partitions: HashMap<i32, BTreeMap<i64, bool>>,
+}
+
+impl OffsetTracker {
+    fn start(&mut self, partition: i32, offset: i64) {
+        self.partitions
+            .entry(partition)
+            .or_default()
+            .insert(offset, false);
+    }
+
+    /// The offset to commit now that `offset` is done, if that moved it: one past the
+    /// highest offset that has nothing unfinished before it.
+    fn finish(&mut self, partition: i32, offset: i64) -> Option<i64> {
+        let pending = self.partitions.get_mut(&partition)?;
+        *pending.get_mut(&offset)? = true;
+        let mut next = None;
+        while let Some(first) = pending.first_entry() {
+            if !*first.get() {
+                break;
+            }
+            next = Some(*first.key() + 1);
+            first.remove();
+        }
+        next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offset_tracker() {
+        let mut tracker = OffsetTracker::default();
+        for offset in 10..13 {
+            tracker.start(0, offset);
+        }
+        tracker.start(1, 5);
+
+        // 10 is still running
+        assert_eq!(tracker.finish(0, 11), None);
+        assert_eq!(tracker.finish(0, 10), Some(12));
+        assert_eq!(tracker.finish(1, 5), Some(6));
+        assert_eq!(tracker.finish(0, 12), Some(13));
+
+        // Not started, or finished already
+        assert_eq!(tracker.finish(0, 12), None);
+        assert_eq!(tracker.finish(2, 0), None);
+    }
+}
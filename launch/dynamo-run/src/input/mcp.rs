@@ -0,0 +1,502 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `in=mcp` serves the model to [Model Context Protocol](https://modelcontextprotocol.io)
+//! clients, such as IDEs and agent tools, over stdio. `in=mcp:sse` does it over HTTP with
+//! server-sent events on `--http-port`: `GET /sse` opens a session, whose first event is the
+//! endpoint to `POST` its messages to.
+//!
+//! Clients can use it two ways:
+//!
+//! - `sampling/createMessage`, with the parameters and result of MCP sampling, as a sampling
+//!   provider.
+//! - The `generate` tool, `tools/call` with a prompt, for clients that only call tools.
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+
+use async_openai::types::FinishReason;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::IntoResponse;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use dynamo_llm::types::openai::chat_completions::OpenAIChatCompletionsStreamingEngine;
+use dynamo_runtime::Runtime;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+
+use crate::input::common;
+use crate::opt::McpTransport;
+use crate::{EngineConfig, Flags};
+
+/// The MCP revision whose HTTP with SSE transport we speak
+const PROTOCOL_VERSION: &str = "2024-11-05";
+
+const TOOL_NAME: &str = "generate";
+
+// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+
+pub async fn run(
+    runtime: Runtime,
+    flags: Flags,
+    transport: McpTransport,
+    engine_config: EngineConfig,
+) -> anyhow::Result<()> {
+    let cancel_token = runtime.primary_token();
+    let http_port = flags.http_port;
+    let prepared_engine = common::prepare_engine(runtime, flags, engine_config).await?;
+    let server = Arc::new(Server {
+        engine: prepared_engine.engine,
+        service_name: prepared_engine.service_name,
+    });
+    match transport {
+        McpTransport::Stdio => serve_stdio(server, cancel_token).await,
+        McpTransport::Sse => serve_sse(server, http_port, cancel_token).await,
+    }
+}
+
+/// One JSON-RPC message per line on stdin, the responses on stdout
+async fn serve_stdio(
+    server: Arc<Server>,
+    cancel_token: dynamo_runtime::CancellationToken,
+) -> anyhow::Result<()> {
+    tracing::info!("MCP server on stdio");
+    // Requests run side by side, their responses are written one at a time
+    let (responses_tx, mut responses_rx) = tokio::sync::mpsc::channel::<Value>(64);
+    let writer = tokio::spawn(async move {
+        let mut stdout = tokio::io::stdout();
+        while let Some(response) = responses_rx.recv().await {
+            let mut line = response.to_string();
+            line.push('\n');
+            if stdout.write_all(line.as_bytes()).await.is_err() || stdout.flush().await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut lines = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+    let mut handles = vec![];
+    loop {
+        let line = tokio::select! {
+            _ = cancel_token.cancelled() => break,
+            line = lines.next_line() => line?,
+        };
+        // The client closed stdin, it's done with us
+        let Some(line) = line else {
+            break;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let server = server.clone();
+        let responses_tx = responses_tx.clone();
+        handles.push(tokio::spawn(async move {
+            if let Some(response) = server.handle_text(&line).await {
+                let _ = responses_tx.send(response).await;
+            }
+        }));
+        handles.retain(|handle| !handle.is_finished());
+    }
+    futures::future::join_all(handles).await;
+    drop(responses_tx);
+    let _ = writer.await;
+    Ok(())
+}
+
+/// Each SSE session's channel to its event stream
+type Sessions = Arc<Mutex<HashMap<String, tokio::sync::mpsc::Sender<Value>>>>;
+
+#[derive(Clone)]
+struct SseState {
+    server: Arc<Server>,
+    sessions: Sessions,
+}
+
+#[derive(Deserialize)]
+struct SessionQuery {
+    session_id: String,
+}
+
+/// Forgets the session once its event stream is dropped, when the client disconnects
+struct SessionGuard {
+    sessions: Sessions,
+    id: String,
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        self.sessions.lock().unwrap().remove(&self.id);
+    }
+}
+
+async fn serve_sse(
+    server: Arc<Server>,
+    port: u16,
+    cancel_token: dynamo_runtime::CancellationToken,
+) -> anyhow::Result<()> {
+    let state = SseState {
+        server,
+        sessions: Default::default(),
+    };
+    let router = Router::new()
+        .route("/sse", get(open_session))
+        .route("/messages", post(post_message))
+        .with_state(state);
+    let listener = tokio::net::TcpListener::bind(("0.0.0.0", port)).await?;
+    tracing::info!("MCP server on http://0.0.0.0:{port}/sse");
+    axum::serve(listener, router)
+        .with_graceful_shutdown(cancel_token.cancelled_owned())
+        .await?;
+    Ok(())
+}
+
+async fn open_session(State(state): State<SseState>) -> impl IntoResponse {
+    let id = uuid::Uuid::new_v4().simple().to_string();
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Value>(64);
+    state.sessions.lock().unwrap().insert(id.clone(), tx);
+    let guard = SessionGuard {
+        sessions: state.sessions.clone(),
+        id: id.clone(),
+    };
+    tracing::debug!(session_id = id, "MCP session opened");
+    let events = async_stream::stream! {
+        let _guard = guard;
+        yield Ok::<_, Infallible>(
+            Event::default()
+                .event("endpoint")
+                .data(format!("/messages?session_id={id}")),
+        );
+        while let Some(message) = rx.recv().await {
+            yield Ok(Event::default().event("message").data(message.to_string()));
+        }
+    };
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+/// Accepted at once, the response goes out on the session's event stream
+async fn post_message(
+    State(state): State<SseState>,
+    Query(query): Query<SessionQuery>,
+    body: String,
+) -> StatusCode {
+    let Some(tx) = state
+        .sessions
+        .lock()
+        .unwrap()
+        .get(&query.session_id)
+        .cloned()
+    else {
+        return StatusCode::NOT_FOUND;
+    };
+    tokio::spawn(async move {
+        if let Some(response) = state.server.handle_text(&body).await {
+            let _ = tx.send(response).await;
+        }
+    });
+    StatusCode::ACCEPTED
+}
+
+struct Server {
+    engine: OpenAIChatCompletionsStreamingEngine,
+    service_name: String,
+}
+
+#[derive(Deserialize)]
+struct RpcRequest {
+    /// None for notifications, which get no response
+    #[serde(default)]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// `sampling/createMessage` parameters, those we use
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct CreateMessageParams {
+    messages: Vec<SamplingMessage>,
+    #[serde(default)]
+    system_prompt: Option<String>,
+    #[serde(default)]
+    temperature: Option<f32>,
+    #[serde(default)]
+    max_tokens: Option<u32>,
+    #[serde(default)]
+    stop_sequences: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SamplingMessage {
+    role: String,
+    content: Content,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Content {
+    Text {
+        text: String,
+    },
+    Image {
+        data: String,
+        #[serde(rename = "mimeType")]
+        mime_type: String,
+    },
+}
+
+/// Arguments of the `generate` tool
+#[derive(Deserialize)]
+struct GenerateArgs {
+    prompt: String,
+    #[serde(default)]
+    system_prompt: Option<String>,
+    #[serde(default)]
+    max_tokens: Option<u32>,
+    #[serde(default)]
+    temperature: Option<f32>,
+}
+
+impl Server {
+    /// The response to a message, None if it is a notification
+    async fn handle_text(&self, text: &str) -> Option<Value> {
+        match serde_json::from_str(text) {
+            Ok(message) => self.handle(message).await,
+            Err(err) => Some(error_response(
+                Value::Null,
+                RpcError::new(PARSE_ERROR, err.to_string()),
+            )),
+        }
+    }
+
+    async fn handle(&self, message: Value) -> Option<Value> {
+        let request: RpcRequest = match serde_json::from_value(message) {
+            Ok(request) => request,
+            Err(err) => {
+                return Some(error_response(
+                    Value::Null,
+                    RpcError::new(INVALID_REQUEST, err.to_string()),
+                ));
+            }
+        };
+        // Notifications, such as notifications/initialized, need nothing from us
+        let id = request.id?;
+        let result = match request.method.as_str() {
+            "initialize" => Ok(self.initialize()),
+            "ping" => Ok(json!({})),
+            "tools/list" => Ok(tools()),
+            "tools/call" => self.call_tool(request.params).await,
+            "sampling/createMessage" => self.create_message(request.params).await,
+            method => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("Method not found: {method}"),
+            )),
+        };
+        Some(match result {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+            Err(err) => error_response(id, err),
+        })
+    }
+
+    fn initialize(&self) -> Value {
+        json!({
+            "protocolVersion": PROTOCOL_VERSION,
+            "capabilities": {"tools": {}},
+            "serverInfo": {"name": "dynamo-run", "version": env!("CARGO_PKG_VERSION")},
+            "instructions": format!(
+                "Generates text with {}. Call sampling/createMessage or the {TOOL_NAME} tool.",
+                self.service_name
+            ),
+        })
+    }
+
+    async fn create_message(&self, params: Value) -> Result<Value, RpcError> {
+        let params: CreateMessageParams = serde_json::from_value(params)
+            .map_err(|err| RpcError::new(INVALID_PARAMS, err.to_string()))?;
+        self.sample(&params)
+            .await
+            .map_err(|err| RpcError::new(INTERNAL_ERROR, format!("{err:#}")))
+    }
+
+    /// Engine failures are the tool's result, for the model calling it to see
+    async fn call_tool(&self, params: Value) -> Result<Value, RpcError> {
+        let name = params
+            .get("name")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        if name != TOOL_NAME {
+            return Err(RpcError::new(
+                INVALID_PARAMS,
+                format!("Unknown tool: {name}"),
+            ));
+        }
+        let args: GenerateArgs =
+            serde_json::from_value(params.get("arguments").cloned().unwrap_or_default())
+                .map_err(|err| RpcError::new(INVALID_PARAMS, err.to_string()))?;
+        let params = CreateMessageParams {
+            messages: vec![SamplingMessage {
+                role: "user".to_string(),
+                content: Content::Text { text: args.prompt },
+            }],
+            system_prompt: args.system_prompt,
+            temperature: args.temperature,
+            max_tokens: args.max_tokens,
+            stop_sequences: vec![],
+        };
+        Ok(match self.sample(&params).await {
+            Ok(message) => json!({"content": [message["content"]], "isError": false}),
+            Err(err) => json!({
+                "content": [{"type": "text", "text": format!("{err:#}")}],
+                "isError": true,
+            }),
+        })
+    }
+
+    /// Run the messages through the engine, the reply as the result of sampling/createMessage
+    async fn sample(&self, params: &CreateMessageParams) -> anyhow::Result<Value> {
+        let response = common::complete(
+            self.engine.clone(),
+            &self.service_name,
+            chat_request(params),
+        )
+        .await?;
+        let Some(choice) = response.inner.choices.first() else {
+            anyhow::bail!("The engine returned no choices");
+        };
+        let stop_reason = match choice.finish_reason {
+            Some(FinishReason::Length) => "maxTokens",
+            _ => "endTurn",
+        };
+        Ok(json!({
+            "role": "assistant",
+            "content": {"type": "text", "text": choice.message.content.clone().unwrap_or_default()},
+            "model": response.inner.model,
+            "stopReason": stop_reason,
+        }))
+    }
+}
+
+/// The OpenAI chat completion request for MCP sampling parameters
+fn chat_request(params: &CreateMessageParams) -> Value {
+    let system = params
+        .system_prompt
+        .iter()
+        .map(|prompt| json!({"role": "system", "content": prompt}));
+    let messages = params.messages.iter().map(|message| {
+        let content = match &message.content {
+            Content::Text { text } => json!(text),
+            Content::Image { data, mime_type } => json!([{
+                "type": "image_url",
+                "image_url": {"url": format!("data:{mime_type};base64,{data}")},
+            }]),
+        };
+        json!({"role": message.role, "content": content})
+    });
+    let mut request = json!({"messages": system.chain(messages).collect::<Vec<_>>()});
+    if let Some(max_tokens) = params.max_tokens {
+        request["max_tokens"] = json!(max_tokens);
+    }
+    if let Some(temperature) = params.temperature {
+        request["temperature"] = json!(temperature);
+    }
+    if !params.stop_sequences.is_empty() {
+        request["stop"] = json!(params.stop_sequences);
+    }
+    request
+}
+
+fn error_response(id: Value, err: RpcError) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": {"code": err.code, "message": err.message},
+    })
+}
+
+fn tools() -> Value {
+    json!({
+        "tools": [{
+            "name": TOOL_NAME,
+            "description": "Generate a reply to a prompt with the served model",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "prompt": {"type": "string", "description": "What to reply to"},
+                    "system_prompt": {"type": "string"},
+                    "max_tokens": {"type": "integer", "minimum": 1},
+                    "temperature": {"type": "number", "minimum": 0},
+                },
+                "required": ["prompt"],
+            },
+        }]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chat_request() {
+        let params: CreateMessageParams = serde_json::from_value(json!({
+            "messages": [
+                {"role": "user", "content": {"type": "text", "text": "What is this?"}},
+                {"role": "user", "content": {"type": "image", "data": "aGk=", "mimeType": "image/png"}},
+            ],
+            "systemPrompt": "Be brief.",
+            "maxTokens": 100,
+            "stopSequences": ["\n\n"],
+            "includeContext": "none",
+        }))
+        .unwrap();
+        assert_eq!(
+            chat_request(&params),
+            json!({
+                "messages": [
+                    {"role": "system", "content": "Be brief."},
+                    {"role": "user", "content": "What is this?"},
+                    {"role": "user", "content": [
+                        {"type": "image_url", "image_url": {"url": "data:image/png;base64,aGk="}}
+                    ]},
+                ],
+                "max_tokens": 100,
+                "stop": ["\n\n"],
+            })
+        );
+    }
+}
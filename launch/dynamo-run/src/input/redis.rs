@@ -0,0 +1,604 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `in=redis:<url>/<key>` pops requests from a Redis list or stream and adds the results to
+//! another one, a job queue for when there is no NATS or Kafka.
+//!
+//! A request is an OpenAI chat completion request as JSON, with an optional `id` to find its
+//! result by. `model` defaults to the served model. A result is
+//! `{"id": ..., "response": <chat completion>}`, or `{"id": ..., "error": {"message": ...}}`.
+//!
+//! - A list is a queue producers `LPUSH` to. Each dynamo-run moves what it pops to a
+//!   processing list of its own and removes it from there once the result is in. It also
+//!   keeps a key alive that expires after `--redis-visibility-timeout`. When that key is gone
+//!   the others push what was left in its processing list back on the queue. Results are
+//!   pushed on the left of the results list.
+//! - A stream is read with a consumer group. Results are added to the results stream with
+//!   the fields `id` and `result`, the entry ID is the `id` of requests without one. A
+//!   request pending longer than the visibility timeout is claimed by another dynamo-run,
+//!   so each one touches the entries it is running well before that.
+//!
+//! Either way a request runs again if its dynamo-run stops before the result is in.
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use ::redis::aio::MultiplexedConnection;
+use ::redis::streams::{StreamAutoClaimReply, StreamId, StreamReadReply};
+use anyhow::Context as _;
+use async_trait::async_trait;
+use dynamo_runtime::Runtime;
+use tokio::sync::Semaphore;
+
+use crate::input::common;
+use crate::plugin::InputProvider;
+use crate::{EngineConfig, Flags};
+
+const DEFAULT_GROUP: &str = "dynamo-run";
+
+/// Field of a stream entry with the request
+const REQUEST_FIELD: &str = "request";
+
+/// Seconds a blocking pop waits, so we notice being stopped
+const BLOCK_SECS: u64 = 1;
+
+/// The keys of the queue, and this dynamo-run's name in it
+struct Keys {
+    queue: String,
+    results: String,
+    worker: String,
+    /// Consumer group, None if the queue is a list
+    group: Option<String>,
+    visibility: Duration,
+}
+
+impl Keys {
+    fn processing(&self, worker: &str) -> String {
+        format!("{}:processing:{worker}", self.queue)
+    }
+
+    fn alive(&self, worker: &str) -> String {
+        format!("{}:alive:{worker}", self.queue)
+    }
+
+    fn workers(&self) -> String {
+        format!("{}:workers", self.queue)
+    }
+}
+
+/// Where a request is until its result is in
+enum Handle {
+    /// The list item, removed from the processing list by value
+    Item(Vec<u8>),
+    /// The stream entry ID, to acknowledge
+    Entry(String),
+}
+
+struct Job {
+    handle: Handle,
+    /// The request's `id`, else the stream entry ID, else null
+    id: serde_json::Value,
+    request: anyhow::Result<serde_json::Value>,
+}
+
+/// Where the next sweep for stream entries other workers left pending is
+struct Reclaim {
+    cursor: String,
+    next: Instant,
+}
+
+/// `in=redis`, registered by [`crate::plugin::register_features`]
+pub struct RedisInput;
+
+#[async_trait]
+impl InputProvider for RedisInput {
+    fn name(&self) -> &'static str {
+        "redis"
+    }
+
+    fn parse(&self, arg: &str) -> anyhow::Result<()> {
+        url_and_queue(arg).map(|_| ())
+    }
+
+    async fn run(
+        &self,
+        runtime: Runtime,
+        flags: Flags,
+        arg: &str,
+        engine: EngineConfig,
+    ) -> anyhow::Result<()> {
+        let (url, queue) = url_and_queue(arg)?;
+        run(runtime, flags, url, queue, engine).await
+    }
+}
+
+/// `<url>/<key>`, the key the last part of the path, after the host and the database
+fn url_and_queue(arg: &str) -> anyhow::Result<(&str, &str)> {
+    let host_start = arg.find("://").map(|i| i + 3).unwrap_or(0);
+    match arg.rsplit_once('/') {
+        Some((url, queue)) if url.len() > host_start && !queue.is_empty() && host_start > 0 => {
+            Ok((url, queue))
+        }
+        _ => anyhow::bail!(
+            "Invalid in=redis option 'redis:{arg}', expected redis:<url>/<key>, \
+             e.g. redis:redis://localhost:6379/requests"
+        ),
+    }
+}
+
+async fn run(
+    runtime: Runtime,
+    flags: Flags,
+    url: &str,
+    queue: &str,
+    engine_config: EngineConfig,
+) -> anyhow::Result<()> {
+    let cancel_token = runtime.primary_token();
+    let client = ::redis::Client::open(url).with_context(|| format!("Invalid Redis URL {url}"))?;
+    // Blocking pops get a connection of their own so they don't hold up the writes
+    let mut reader = client
+        .get_multiplexed_async_connection()
+        .await
+        .with_context(|| format!("Failed connecting to Redis at {url}"))?;
+    let writer = client.get_multiplexed_async_connection().await?;
+
+    let key_type: String = ::redis::cmd("TYPE")
+        .arg(queue)
+        .query_async(&mut reader)
+        .await?;
+    let is_stream = flags.redis_stream || key_type == "stream";
+    if !is_stream && key_type != "list" && key_type != "none" {
+        anyhow::bail!("Redis key {queue} is a {key_type}, in=redis needs a list or a stream");
+    }
+    let keys = Arc::new(Keys {
+        queue: queue.to_string(),
+        results: flags
+            .redis_results
+            .clone()
+            .unwrap_or_else(|| format!("{queue}:results")),
+        worker: worker_name(),
+        group: is_stream.then(|| {
+            flags
+                .redis_group
+                .clone()
+                .unwrap_or_else(|| DEFAULT_GROUP.to_string())
+        }),
+        visibility: Duration::from_secs(flags.redis_visibility_timeout),
+    });
+    join(&keys, &mut reader).await?;
+
+    let in_flight = Arc::new(Semaphore::new(flags.redis_concurrency as usize));
+    let prepared_engine = common::prepare_engine(runtime, flags, engine_config).await?;
+    let service_name = Arc::new(prepared_engine.service_name);
+    tracing::info!(
+        queue = keys.queue,
+        results = keys.results,
+        worker = keys.worker,
+        group = keys.group,
+        "Taking requests from Redis"
+    );
+
+    // Stream entries being run, touched so no one else claims them
+    let running = Arc::new(Mutex::new(HashSet::new()));
+    let heartbeat_token = cancel_token.child_token();
+    let heartbeat = tokio::spawn(heartbeat(
+        keys.clone(),
+        writer.clone(),
+        running.clone(),
+        heartbeat_token.clone(),
+    ));
+
+    let mut reclaim = Reclaim {
+        cursor: "0-0".to_string(),
+        next: Instant::now(),
+    };
+    let mut handles = vec![];
+    loop {
+        let permit = tokio::select! {
+            _ = cancel_token.cancelled() => break,
+            permit = in_flight.clone().acquire_owned() => permit?,
+        };
+        if cancel_token.is_cancelled() {
+            break;
+        }
+        let job = match next_job(&keys, &mut reader, &mut reclaim).await {
+            Ok(Some(job)) => job,
+            Ok(None) => continue,
+            Err(err) => {
+                tracing::warn!("Failed reading from Redis: {err:#}");
+                tokio::time::sleep(Duration::from_secs(BLOCK_SECS)).await;
+                continue;
+            }
+        };
+        if let Handle::Entry(entry) = &job.handle {
+            running.lock().unwrap().insert(entry.clone());
+        }
+
+        let engine = prepared_engine.engine.clone();
+        let service_name = service_name.clone();
+        let keys = keys.clone();
+        let mut writer = writer.clone();
+        let running = running.clone();
+        handles.push(tokio::spawn(async move {
+            let _permit = permit;
+            let response = match job.request {
+                Ok(request) => common::complete(engine, &service_name, request).await,
+                Err(err) => Err(err),
+            };
+            let result = match response {
+                Ok(response) => serde_json::json!({"id": job.id, "response": response}),
+                Err(err) => {
+                    tracing::warn!(id = %job.id, "Request failed: {err:#}");
+                    serde_json::json!({"id": job.id, "error": {"message": format!("{err:#}")}})
+                }
+            };
+            if let Err(err) = finish(&keys, &mut writer, &job.handle, &job.id, &result).await {
+                // Left in the queue's care, it runs again once reclaimed
+                tracing::error!(id = %job.id, "Failed writing the result: {err:#}");
+            }
+            if let Handle::Entry(entry) = &job.handle {
+                running.lock().unwrap().remove(entry);
+            }
+        }));
+        handles.retain(|handle| !handle.is_finished());
+    }
+
+    futures::future::join_all(handles).await;
+    heartbeat_token.cancel();
+    let _ = heartbeat.await;
+    let mut writer = writer;
+    if let Err(err) = leave(&keys, &mut writer).await {
+        tracing::warn!("Failed leaving the Redis queue: {err:#}");
+    }
+    Ok(())
+}
+
+/// Unique enough between the dynamo-run of a queue
+fn worker_name() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or_default();
+    format!("dynamo-run-{}-{nanos:08x}", std::process::id())
+}
+
+/// Register with the queue: for a list our alive key, for a stream the consumer group
+async fn join(keys: &Keys, conn: &mut MultiplexedConnection) -> anyhow::Result<()> {
+    match &keys.group {
+        None => {
+            ::redis::pipe()
+                .sadd(keys.workers(), &keys.worker)
+                .ignore()
+                .set_ex(keys.alive(&keys.worker), 1, keys.visibility.as_secs())
+                .ignore()
+                .query_async::<()>(conn)
+                .await?;
+        }
+        Some(group) => {
+            let created = ::redis::cmd("XGROUP")
+                .arg("CREATE")
+                .arg(&keys.queue)
+                .arg(group)
+                .arg("0")
+                .arg("MKSTREAM")
+                .query_async::<()>(conn)
+                .await;
+            match created {
+                Ok(()) => tracing::info!(group, "Created Redis consumer group"),
+                Err(err) if err.code() == Some("BUSYGROUP") => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Put back what we popped but didn't finish, and stop being a worker of the list
+async fn leave(keys: &Keys, conn: &mut MultiplexedConnection) -> anyhow::Result<()> {
+    if keys.group.is_some() {
+        // Our pending entries, if any, are claimed once idle long enough
+        return Ok(());
+    }
+    let returned = return_items(keys, conn, &keys.worker).await?;
+    if returned > 0 {
+        tracing::info!(returned, "Put unfinished requests back on the Redis queue");
+    }
+    ::redis::pipe()
+        .del(keys.alive(&keys.worker))
+        .ignore()
+        .srem(keys.workers(), &keys.worker)
+        .ignore()
+        .query_async::<()>(conn)
+        .await?;
+    Ok(())
+}
+
+/// Move what `worker` was running back to the queue, where the next pop takes it
+async fn return_items(
+    keys: &Keys,
+    conn: &mut MultiplexedConnection,
+    worker: &str,
+) -> anyhow::Result<usize> {
+    let processing = keys.processing(worker);
+    let mut returned = 0;
+    loop {
+        let moved: Option<Vec<u8>> = ::redis::cmd("LMOVE")
+            .arg(&processing)
+            .arg(&keys.queue)
+            .arg("RIGHT")
+            .arg("RIGHT")
+            .query_async(conn)
+            .await?;
+        if moved.is_none() {
+            return Ok(returned);
+        }
+        returned += 1;
+    }
+}
+
+/// Until cancelled, show we are alive: refresh our key of a list, touch the stream entries
+/// we are running. Also take back the items of list workers that stopped.
+async fn heartbeat(
+    keys: Arc<Keys>,
+    mut conn: MultiplexedConnection,
+    running: Arc<Mutex<HashSet<String>>>,
+    cancel_token: dynamo_runtime::CancellationToken,
+) {
+    let mut ticker = tokio::time::interval(keys.visibility / 3);
+    loop {
+        tokio::select! {
+            _ = cancel_token.cancelled() => return,
+            _ = ticker.tick() => {}
+        }
+        let beat = match &keys.group {
+            None => keep_list_alive(&keys, &mut conn).await,
+            Some(group) => {
+                let entries: Vec<String> = running.lock().unwrap().iter().cloned().collect();
+                touch_entries(&keys, group, &entries, &mut conn).await
+            }
+        };
+        if let Err(err) = beat {
+            tracing::warn!("Redis heartbeat failed: {err:#}");
+        }
+    }
+}
+
+async fn keep_list_alive(keys: &Keys, conn: &mut MultiplexedConnection) -> anyhow::Result<()> {
+    ::redis::cmd("SET")
+        .arg(keys.alive(&keys.worker))
+        .arg(1)
+        .arg("EX")
+        .arg(keys.visibility.as_secs())
+        .query_async::<()>(conn)
+        .await?;
+    // A worker still registered whose alive key expired has stopped
+    let workers: Vec<String> = ::redis::cmd("SMEMBERS")
+        .arg(keys.workers())
+        .query_async(conn)
+        .await?;
+    for worker in workers.iter().filter(|worker| **worker != keys.worker) {
+        let alive: bool = ::redis::cmd("EXISTS")
+            .arg(keys.alive(worker))
+            .query_async(conn)
+            .await?;
+        if alive {
+            continue;
+        }
+        let returned = return_items(keys, conn, worker).await?;
+        ::redis::cmd("SREM")
+            .arg(keys.workers())
+            .arg(worker)
+            .query_async::<()>(conn)
+            .await?;
+        tracing::info!(
+            worker,
+            returned,
+            "Took back the requests of a stopped worker"
+        );
+    }
+    Ok(())
+}
+
+/// Reset how long the entries have been idle, so the group doesn't hand them to another
+async fn touch_entries(
+    keys: &Keys,
+    group: &str,
+    entries: &[String],
+    conn: &mut MultiplexedConnection,
+) -> anyhow::Result<()> {
+    if entries.is_empty() {
+        return Ok(());
+    }
+    ::redis::cmd("XCLAIM")
+        .arg(&keys.queue)
+        .arg(group)
+        .arg(&keys.worker)
+        .arg(0)
+        .arg(entries)
+        .arg("JUSTID")
+        .query_async::<()>(conn)
+        .await?;
+    Ok(())
+}
+
+/// The next request, None if there was none for a second
+async fn next_job(
+    keys: &Keys,
+    conn: &mut MultiplexedConnection,
+    reclaim: &mut Reclaim,
+) -> anyhow::Result<Option<Job>> {
+    let Some(group) = &keys.group else {
+        let item: Option<Vec<u8>> = ::redis::cmd("BLMOVE")
+            .arg(&keys.queue)
+            .arg(keys.processing(&keys.worker))
+            .arg("RIGHT")
+            .arg("LEFT")
+            .arg(BLOCK_SECS)
+            .query_async(conn)
+            .await?;
+        return Ok(item.map(|item| {
+            let (id, request) = parse_request(&item);
+            Job {
+                handle: Handle::Item(item),
+                id,
+                request,
+            }
+        }));
+    };
+
+    // Entries a stopped worker left pending go first
+    if Instant::now() >= reclaim.next {
+        let claimed: StreamAutoClaimReply = ::redis::cmd("XAUTOCLAIM")
+            .arg(&keys.queue)
+            .arg(group)
+            .arg(&keys.worker)
+            .arg(keys.visibility.as_millis() as u64)
+            .arg(&reclaim.cursor)
+            .arg("COUNT")
+            .arg(1)
+            .query_async(conn)
+            .await?;
+        reclaim.cursor = claimed.next_stream_id;
+        if reclaim.cursor == "0-0" {
+            // Swept the whole stream
+            reclaim.next = Instant::now() + keys.visibility / 3;
+        }
+        if let Some(entry) = claimed.claimed.into_iter().next() {
+            tracing::info!(entry = entry.id, "Took back a request of a stopped worker");
+            return Ok(Some(stream_job(entry)));
+        }
+    }
+
+    let read: Option<StreamReadReply> = ::redis::cmd("XREADGROUP")
+        .arg("GROUP")
+        .arg(group)
+        .arg(&keys.worker)
+        .arg("COUNT")
+        .arg(1)
+        .arg("BLOCK")
+        .arg(BLOCK_SECS * 1000)
+        .arg("STREAMS")
+        .arg(&keys.queue)
+        .arg(">")
+        .query_async(conn)
+        .await?;
+    let entry = read
+        .into_iter()
+        .flat_map(|read| read.keys)
+        .flat_map(|key| key.ids)
+        .next();
+    Ok(entry.map(stream_job))
+}
+
+fn stream_job(entry: StreamId) -> Job {
+    let (id, request) = match entry.get::<Vec<u8>>(REQUEST_FIELD) {
+        Some(payload) => parse_request(&payload),
+        None => (
+            serde_json::Value::Null,
+            Err(anyhow::anyhow!(
+                "Stream entry has no '{REQUEST_FIELD}' field"
+            )),
+        ),
+    };
+    Job {
+        id: match id {
+            serde_json::Value::Null => entry.id.clone().into(),
+            id => id,
+        },
+        handle: Handle::Entry(entry.id),
+        request,
+    }
+}
+
+/// The request's `id`, taken out of it, and the request
+fn parse_request(payload: &[u8]) -> (serde_json::Value, anyhow::Result<serde_json::Value>) {
+    match serde_json::from_slice::<serde_json::Value>(payload) {
+        Ok(mut request) => {
+            let id = request
+                .as_object_mut()
+                .and_then(|fields| fields.remove("id"))
+                .unwrap_or_default();
+            (id, Ok(request))
+        }
+        Err(err) => (
+            serde_json::Value::Null,
+            Err(anyhow::anyhow!("Request is not JSON: {err}")),
+        ),
+    }
+}
+
+/// Add the result and take the request off the queue, both or neither
+async fn finish(
+    keys: &Keys,
+    conn: &mut MultiplexedConnection,
+    handle: &Handle,
+    id: &serde_json::Value,
+    result: &serde_json::Value,
+) -> anyhow::Result<()> {
+    let result = serde_json::to_string(result)?;
+    let mut pipe = ::redis::pipe();
+    pipe.atomic();
+    match (handle, &keys.group) {
+        (Handle::Item(item), _) => {
+            pipe.lpush(&keys.results, result)
+                .ignore()
+                .lrem(keys.processing(&keys.worker), 1, item.as_slice())
+                .ignore();
+        }
+        (Handle::Entry(entry), Some(group)) => {
+            let id = match id {
+                serde_json::Value::String(id) => id.clone(),
+                id => id.to_string(),
+            };
+            pipe.cmd("XADD")
+                .arg(&keys.results)
+                .arg("*")
+                .arg("id")
+                .arg(id)
+                .arg("result")
+                .arg(result)
+                .ignore()
+                .xack(&keys.queue, group, &[entry])
+                .ignore();
+        }
+        (Handle::Entry(_), None) => unreachable!("Stream entries come with a consumer group"),
+    }
+    pipe.query_async::<()>(conn).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_request() {
+        let (id, request) =
+            parse_request(br#"{"id": "job-1", "messages": [{"role": "user", "content": "Hi"}]}"#);
+        assert_eq!(id, serde_json::json!("job-1"));
+        assert_eq!(
+            request.unwrap(),
+            serde_json::json!({"messages": [{"role": "user", "content": "Hi"}]})
+        );
+
+        let (id, request) = parse_request(br#"{"messages": []}"#);
+        assert_eq!(id, serde_json::Value::Null);
+        assert!(request.is_ok());
+
+        let (_, request) = parse_request(b"not json");
+        assert!(request.is_err());
+    }
+}
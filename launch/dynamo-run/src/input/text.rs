@@ -13,17 +13,25 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use dynamo_llm::presets::PresetLibrary;
 use dynamo_llm::protocols::openai::nvext::NvExt;
 use dynamo_llm::types::openai::chat_completions::{
     NvCreateChatCompletionRequest, OpenAIChatCompletionsStreamingEngine,
 };
 use dynamo_runtime::{pipeline::Context, runtime::CancellationToken, Runtime};
 use futures::StreamExt;
-use std::io::{ErrorKind, Write};
+use std::io::Write;
 
 use crate::input::common;
 use crate::{EngineConfig, Flags, RequestTemplate};
 
+mod command;
+use command::{Command, Line};
+mod editor;
+use editor::Editor;
+mod session;
+pub(crate) use session::Session;
+
 /// Max response tokens for each single query. Must be less than model context size.
 /// TODO: Cmd line flag to overwrite this
 const MAX_TOKENS: u32 = 8192;
@@ -36,6 +44,27 @@ pub async fn run(
     template: Option<RequestTemplate>,
 ) -> anyhow::Result<()> {
     let cancel_token = runtime.primary_token();
+    let presets = flags
+        .presets
+        .as_deref()
+        .map(PresetLibrary::load)
+        .transpose()?;
+    let mut session = match flags.resume.as_deref() {
+        Some(path) => {
+            let session = Session::load(path)?;
+            tracing::info!(
+                "Resuming {} messages from {}",
+                session.messages.len(),
+                path.display()
+            );
+            session
+        }
+        None => Session::default(),
+    };
+    let sampling = common::Sampling::from_flags(&flags);
+    if session.system.is_none() {
+        session.system = sampling.system_prompt.clone();
+    }
     let prepared_engine = common::prepare_engine(runtime, flags, engine_config).await?;
     main_loop(
         cancel_token,
@@ -44,6 +73,9 @@ pub async fn run(
         single_prompt,
         prepared_engine.inspect_template,
         template,
+        presets,
+        session,
+        sampling,
     )
     .await
 }
@@ -55,38 +87,41 @@ async fn main_loop(
     mut initial_prompt: Option<String>,
     _inspect_template: bool,
     template: Option<RequestTemplate>,
+    presets: Option<PresetLibrary>,
+    mut session: Session,
+    sampling: common::Sampling,
 ) -> anyhow::Result<()> {
-    if initial_prompt.is_none() {
-        tracing::info!("Ctrl-c to exit");
-    }
-    let theme = dialoguer::theme::ColorfulTheme::default();
-
     // Initial prompt is the pipe case: `echo "Hello" | dynamo-run ..`
     // We run that single prompt and exit
     let single = initial_prompt.is_some();
-    let mut history = dialoguer::BasicHistory::default();
-    let mut messages = vec![];
+    let mut editor = if single {
+        None
+    } else {
+        tracing::info!("Ctrl-d to exit, /help for commands");
+        Some(Editor::new()?)
+    };
     while !cancel_token.is_cancelled() {
         // User input
         let prompt = match initial_prompt.take() {
             Some(p) => p,
             None => {
-                let input_ui = dialoguer::Input::<String>::with_theme(&theme)
-                    .history_with(&mut history)
-                    .with_prompt("User");
-                match input_ui.interact_text() {
-                    Ok(prompt) => prompt,
-                    Err(dialoguer::Error::IO(err)) => {
-                        match err.kind() {
-                            ErrorKind::Interrupted => {
-                                // Ctrl-C
-                                // Unfortunately I could not make dialoguer handle Ctrl-d
-                            }
-                            k => {
-                                tracing::info!("IO error: {k}");
-                            }
-                        }
-                        break;
+                let editor = editor.as_mut().expect("an editor unless piped");
+                let Some(text) = editor.read()? else {
+                    break;
+                };
+                if text.trim().is_empty() {
+                    continue;
+                }
+                match Line::parse(text) {
+                    Ok(Line::Prompt(prompt)) => prompt,
+                    Ok(Line::Command(Command::Exit)) => break,
+                    Ok(Line::Command(command)) => {
+                        session.run(command, presets.as_ref());
+                        continue;
+                    }
+                    Err(err) => {
+                        println!("{err}");
+                        continue;
                     }
                 }
             }
@@ -99,26 +134,40 @@ async fn main_loop(
                 name: None,
             },
         );
-        messages.push(user_message);
+        session.messages.push(user_message);
         // Request
-        let inner = async_openai::types::CreateChatCompletionRequestArgs::default()
-            .messages(messages.clone())
+        let mut args = async_openai::types::CreateChatCompletionRequestArgs::default();
+        args.messages(session.conversation())
             .model(
                 template
                     .as_ref()
                     .map_or_else(|| service_name.to_string(), |t| t.model.clone()),
             )
             .stream(true)
-            .max_completion_tokens(
-                template
-                    .as_ref()
-                    .map_or(MAX_TOKENS, |t| t.max_completion_tokens),
+            .max_completion_tokens(session.max_tokens.or(sampling.max_tokens).unwrap_or_else(
+                || {
+                    template
+                        .as_ref()
+                        .map_or(MAX_TOKENS, |t| t.max_completion_tokens)
+                },
+            ))
+            .temperature(
+                session
+                    .temperature
+                    .or(sampling.temperature)
+                    .unwrap_or_else(|| template.as_ref().map_or(0.7, |t| t.temperature)),
             )
-            .temperature(template.as_ref().map_or(0.7, |t| t.temperature))
-            .n(1) // only generate one response
-            .build()?;
+            .n(1); // only generate one response
+        if let Some(top_p) = sampling.top_p {
+            args.top_p(top_p);
+        }
+        if let Some(stop) = sampling.stop.clone() {
+            args.stop(stop);
+        }
+        let inner = args.build()?;
         let nvext = NvExt {
             ignore_eos: Some(true),
+            preset: session.preset.clone(),
             ..Default::default()
         };
 
@@ -128,10 +177,16 @@ async fn main_loop(
         //     req_builder.min_tokens(8192);
         // }
 
-        let req = NvCreateChatCompletionRequest {
+        let mut req = NvCreateChatCompletionRequest {
             inner,
             nvext: Some(nvext),
         };
+        if let Some(library) = &presets {
+            if let Err(err) = library.apply(&mut req) {
+                // The preset was removed from the file since we selected it
+                tracing::warn!("{err}");
+            }
+        }
 
         // Call the model
         let mut stream = engine.generate(Context::new(req)).await?;
@@ -185,7 +240,7 @@ async fn main_loop(
                 ..Default::default()
             },
         );
-        messages.push(assistant_message);
+        session.messages.push(assistant_message);
 
         if single {
             break;
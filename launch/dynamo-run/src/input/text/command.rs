@@ -0,0 +1,135 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The slash commands of in=text. A line starting with `/` is a command, `//` sends the rest of
+//! the line as a prompt starting with `/`.
+
+use std::path::PathBuf;
+
+/// Where `/save` writes without a path
+const DEFAULT_SAVE_FILE: &str = "conversation.json";
+
+pub(super) const HELP: &str = "\
+/system <prompt>      Set the system prompt, /system alone removes it
+/reset                Forget the conversation, keep the system prompt
+/temperature <value>  Sampling temperature, /temperature alone goes back to the default
+/max_tokens <n>       Most tokens in a response, /max_tokens alone goes back to the default
+/preset <name>        Use a preset from --presets, /preset alone stops
+/save [file]          Save the conversation and settings, to conversation.json by default.
+                      Continue it later with --resume <file>
+/help                 This list
+/exit                 Quit, as does Ctrl-d
+
+Start a line with ``` to write over several lines, up to a line with ``` alone, or use Alt-Enter
+for a new line.";
+
+#[derive(Debug, PartialEq)]
+pub(super) enum Command {
+    System(Option<String>),
+    Reset,
+    Temperature(Option<f32>),
+    MaxTokens(Option<u32>),
+    Preset(Option<String>),
+    Save(PathBuf),
+    Help,
+    Exit,
+}
+
+/// What the user typed
+#[derive(Debug, PartialEq)]
+pub(super) enum Line {
+    Prompt(String),
+    Command(Command),
+}
+
+impl Line {
+    pub(super) fn parse(line: String) -> Result<Line, String> {
+        if let Some(escaped) = line.strip_prefix("//") {
+            return Ok(Line::Prompt(format!("/{escaped}")));
+        }
+        let Some(command) = line.strip_prefix('/') else {
+            return Ok(Line::Prompt(line));
+        };
+        let (name, arg) = match command.split_once(char::is_whitespace) {
+            Some((name, arg)) => (name, Some(arg.trim()).filter(|arg| !arg.is_empty())),
+            None => (command.trim_end(), None),
+        };
+        let command = match name {
+            "system" => Command::System(arg.map(str::to_string)),
+            "reset" => Command::Reset,
+            "temperature" => Command::Temperature(
+                arg.map(|value| {
+                    value
+                        .parse()
+                        .map_err(|_| format!("'{value}' is not a temperature"))
+                })
+                .transpose()?,
+            ),
+            "max_tokens" => Command::MaxTokens(
+                arg.map(|value| {
+                    value
+                        .parse()
+                        .map_err(|_| format!("'{value}' is not a number of tokens"))
+                })
+                .transpose()?,
+            ),
+            "preset" => Command::Preset(arg.map(str::to_string)),
+            "save" => Command::Save(PathBuf::from(arg.unwrap_or(DEFAULT_SAVE_FILE))),
+            "help" => Command::Help,
+            "exit" | "quit" => Command::Exit,
+            _ => {
+                return Err(format!(
+                    "Unknown command /{name}, /help lists them. Start the line with // to send \
+                     it as a prompt."
+                ))
+            }
+        };
+        Ok(Line::Command(command))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Result<Line, String> {
+        Line::parse(line.to_string())
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("Hello"), Ok(Line::Prompt("Hello".to_string())));
+        assert_eq!(parse("//etc"), Ok(Line::Prompt("/etc".to_string())));
+        assert_eq!(
+            parse("/system You are a pirate. "),
+            Ok(Line::Command(Command::System(Some(
+                "You are a pirate.".to_string()
+            ))))
+        );
+        assert_eq!(parse("/system"), Ok(Line::Command(Command::System(None))));
+        assert_eq!(
+            parse("/temperature 0.2"),
+            Ok(Line::Command(Command::Temperature(Some(0.2))))
+        );
+        assert!(parse("/temperature hot").is_err());
+        assert_eq!(
+            parse("/save"),
+            Ok(Line::Command(Command::Save(PathBuf::from(
+                "conversation.json"
+            ))))
+        );
+        assert!(parse("/nope").is_err());
+    }
+}
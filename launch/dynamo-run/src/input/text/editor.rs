@@ -0,0 +1,91 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::path::PathBuf;
+
+use rustyline::error::ReadlineError;
+use rustyline::{Cmd, DefaultEditor, EventHandler, KeyCode, KeyEvent, Modifiers};
+
+/// In the home directory, the prompts of earlier sessions
+const HISTORY_FILE: &str = ".dynamo_run_history";
+
+const FENCE: &str = "```";
+
+/// Line editing with the usual readline keys, and history kept across sessions
+pub(super) struct Editor {
+    rl: DefaultEditor,
+    history: Option<PathBuf>,
+}
+
+impl Editor {
+    pub(super) fn new() -> anyhow::Result<Self> {
+        let mut rl = DefaultEditor::new()?;
+        rl.bind_sequence(
+            KeyEvent(KeyCode::Enter, Modifiers::ALT),
+            EventHandler::Simple(Cmd::Newline),
+        );
+        let history = std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE));
+        if let Some(path) = &history {
+            // There isn't one the first time
+            let _ = rl.load_history(path);
+        }
+        Ok(Editor { rl, history })
+    }
+
+    /// What the user typed, `None` once they leave with Ctrl-d or Ctrl-c. A first line starting
+    /// with ``` continues up to a line that is only ```. The fences are dropped when the first
+    /// one is alone on its line, kept when it names a language.
+    pub(super) fn read(&mut self) -> anyhow::Result<Option<String>> {
+        let Some(first) = self.read_line("User> ")? else {
+            return Ok(None);
+        };
+        let text = if first.trim_start().starts_with(FENCE) {
+            let bare = first.trim() == FENCE;
+            let mut lines = if bare { vec![] } else { vec![first] };
+            loop {
+                let Some(line) = self.read_line("... ")? else {
+                    return Ok(None);
+                };
+                if line.trim() == FENCE {
+                    if !bare {
+                        lines.push(line);
+                    }
+                    break;
+                }
+                lines.push(line);
+            }
+            lines.join("\n")
+        } else {
+            first
+        };
+        if !text.trim().is_empty() {
+            let _ = self.rl.add_history_entry(text.as_str());
+            if let Some(path) = &self.history {
+                if let Err(err) = self.rl.append_history(path) {
+                    tracing::debug!(%err, "Could not save history to {}", path.display());
+                }
+            }
+        }
+        Ok(Some(text))
+    }
+
+    fn read_line(&mut self, prompt: &str) -> anyhow::Result<Option<String>> {
+        match self.rl.readline(prompt) {
+            Ok(line) => Ok(Some(line)),
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
@@ -0,0 +1,170 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::path::Path;
+
+use anyhow::Context as _;
+use async_openai::types::{
+    ChatCompletionRequestMessage, ChatCompletionRequestSystemMessage,
+    ChatCompletionRequestSystemMessageContent,
+};
+use dynamo_llm::presets::PresetLibrary;
+use serde::{Deserialize, Serialize};
+
+use super::command::{Command, HELP};
+
+/// The conversation so far and what the slash commands set
+#[derive(Default, Debug, PartialEq)]
+pub(crate) struct Session {
+    pub(super) system: Option<String>,
+    pub(super) messages: Vec<ChatCompletionRequestMessage>,
+    pub(super) temperature: Option<f32>,
+    pub(super) max_tokens: Option<u32>,
+    pub(super) preset: Option<String>,
+}
+
+/// What `/save` writes and `--resume` reads. `messages` are those of a chat completion request,
+/// the system prompt first, so a saved session can also be sent to the HTTP API as it is.
+#[derive(Serialize, Deserialize)]
+struct SessionFile {
+    messages: Vec<ChatCompletionRequestMessage>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    preset: Option<String>,
+}
+
+impl Session {
+    pub(crate) fn load(path: &Path) -> anyhow::Result<Session> {
+        let json = std::fs::read_to_string(path).with_context(|| path.display().to_string())?;
+        let file: SessionFile = serde_json::from_str(&json)
+            .with_context(|| format!("{} is not a saved session", path.display()))?;
+        let mut messages = file.messages;
+        let system = match messages.first() {
+            Some(ChatCompletionRequestMessage::System(system)) => match &system.content {
+                ChatCompletionRequestSystemMessageContent::Text(text) => Some(text.clone()),
+                ChatCompletionRequestSystemMessageContent::Array(_) => {
+                    anyhow::bail!("{}: only text system prompts", path.display());
+                }
+            },
+            _ => None,
+        };
+        if system.is_some() {
+            messages.remove(0);
+        }
+        Ok(Session {
+            system,
+            messages,
+            temperature: file.temperature,
+            max_tokens: file.max_tokens,
+            preset: file.preset,
+        })
+    }
+
+    fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let file = SessionFile {
+            messages: self.conversation(),
+            temperature: self.temperature,
+            max_tokens: self.max_tokens,
+            preset: self.preset.clone(),
+        };
+        std::fs::write(path, serde_json::to_string_pretty(&file)?)?;
+        Ok(())
+    }
+
+    /// Any command but /exit, which the main loop handles
+    pub(super) fn run(&mut self, command: Command, presets: Option<&PresetLibrary>) {
+        match command {
+            Command::System(prompt) => self.system = prompt,
+            Command::Reset => {
+                self.messages.clear();
+                println!("Conversation cleared");
+            }
+            Command::Temperature(value) => self.temperature = value,
+            Command::MaxTokens(value) => self.max_tokens = value,
+            Command::Preset(name) => match (presets, name) {
+                (None, _) => println!("No presets, start with --presets <file>"),
+                (Some(_), None) => self.preset = None,
+                (Some(library), Some(name)) => {
+                    let names = library.names();
+                    if names.contains(&name) {
+                        self.preset = Some(name);
+                    } else {
+                        println!("Unknown preset '{name}'. Presets: {}", names.join(", "));
+                    }
+                }
+            },
+            Command::Save(path) => match self.save(&path) {
+                Ok(()) => println!("Saved to {}", path.display()),
+                Err(err) => println!("Could not save to {}: {err:#}", path.display()),
+            },
+            Command::Help => println!("{HELP}"),
+            Command::Exit => {}
+        }
+    }
+
+    /// The conversation as sent to the engine, the system prompt first
+    pub(super) fn conversation(&self) -> Vec<ChatCompletionRequestMessage> {
+        let system = self.system.as_ref().map(|prompt| {
+            ChatCompletionRequestMessage::System(ChatCompletionRequestSystemMessage {
+                content: ChatCompletionRequestSystemMessageContent::Text(prompt.clone()),
+                name: None,
+            })
+        });
+        system
+            .into_iter()
+            .chain(self.messages.iter().cloned())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_openai::types::{
+        ChatCompletionRequestUserMessage, ChatCompletionRequestUserMessageContent,
+    };
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.json");
+        let session = Session {
+            system: Some("You are a pirate.".to_string()),
+            messages: vec![ChatCompletionRequestMessage::User(
+                ChatCompletionRequestUserMessage {
+                    content: ChatCompletionRequestUserMessageContent::Text("Ahoy".to_string()),
+                    name: None,
+                },
+            )],
+            temperature: Some(0.2),
+            ..Default::default()
+        };
+        session.save(&path).unwrap();
+        let saved: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved["messages"][0]["role"], "system");
+        assert!(saved.get("max_tokens").is_none());
+        assert_eq!(Session::load(&path).unwrap(), session);
+
+        std::fs::write(&path, "[]").unwrap();
+        assert!(Session::load(&path).is_err());
+    }
+}
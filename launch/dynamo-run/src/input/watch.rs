@@ -0,0 +1,443 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `in=watch:<dir>` runs the prompt files dropped in a directory and writes each one's result
+//! next to it, for pipelines that can only exchange files.
+//!
+//! - `<name>.txt` is one prompt, the whole file. Its result `<name>.txt.out` is the text of
+//!   the reply.
+//! - `<name>.jsonl` has a request per line: an OpenAI chat completion request, or an in=batch
+//!   entry, `{"text": ...}` with optionally `temperature`, `top_p`, `max_tokens` and `stop`.
+//!   `model` defaults to the served model. Line N of `<name>.jsonl.out` is the chat completion
+//!   of the Nth request, or `{"error": {"message": ...}}`.
+//!
+//! A file is picked up once it has gone a second without changing. It is renamed to
+//! `<file>.processing` while it runs, then to `<file>.done` once its `.out` is written, or to
+//! `<file>.failed`, with the reason in `<file>.error`, if it or any of its requests failed.
+//! The `.out` is written under another name and renamed, so it is complete when it appears.
+//!
+//! Delivery is at least once: a dynamo-run starting runs again the files left `.processing`
+//! by one that stopped, so a directory has one dynamo-run watching it.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Context as _;
+use async_trait::async_trait;
+use dynamo_llm::types::openai::chat_completions::OpenAIChatCompletionsStreamingEngine;
+use dynamo_runtime::Runtime;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use crate::input::common;
+use crate::plugin::InputProvider;
+use crate::{EngineConfig, Flags};
+
+/// How often the directory is listed
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long a file goes unchanged before it is picked up, so that it isn't read half written
+const SETTLE: Duration = Duration::from_secs(1);
+
+const PROCESSING: &str = "processing";
+const DONE: &str = "done";
+const FAILED: &str = "failed";
+const ERROR: &str = "error";
+const OUT: &str = "out";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    /// One prompt
+    Text,
+    /// A request per line
+    Lines,
+}
+
+impl Kind {
+    fn of(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "txt" => Some(Kind::Text),
+            "jsonl" => Some(Kind::Lines),
+            _ => None,
+        }
+    }
+}
+
+/// What a file left to write
+struct Outcome {
+    /// The `.out`, None if the file couldn't run at all
+    output: Option<String>,
+    /// Why the file is `.failed`
+    failure: Option<String>,
+}
+
+/// `in=watch`, registered by [`crate::plugin::register_features`]
+pub struct WatchInput;
+
+#[async_trait]
+impl InputProvider for WatchInput {
+    fn name(&self) -> &'static str {
+        "watch"
+    }
+
+    fn parse(&self, arg: &str) -> anyhow::Result<()> {
+        watched_dir(arg).map(|_| ())
+    }
+
+    async fn run(
+        &self,
+        runtime: Runtime,
+        flags: Flags,
+        arg: &str,
+        engine: EngineConfig,
+    ) -> anyhow::Result<()> {
+        let dir = watched_dir(arg)?;
+        run(runtime, flags, dir, engine).await
+    }
+}
+
+/// The directory of `watch:<dir>`, which must exist
+fn watched_dir(arg: &str) -> anyhow::Result<PathBuf> {
+    if arg.is_empty() {
+        anyhow::bail!("in=watch needs a directory, in=watch:<dir>");
+    }
+    let dir = PathBuf::from(arg);
+    if !dir.is_dir() {
+        anyhow::bail!("in=watch:{arg}: no such directory");
+    }
+    Ok(dir)
+}
+
+async fn run(
+    runtime: Runtime,
+    flags: Flags,
+    dir: PathBuf,
+    engine_config: EngineConfig,
+) -> anyhow::Result<()> {
+    let cancel_token = runtime.primary_token();
+    let in_flight = Arc::new(Semaphore::new(flags.watch_concurrency as usize));
+    let prepared_engine = common::prepare_engine(runtime, flags, engine_config).await?;
+    let engine = prepared_engine.engine;
+    let service_name: Arc<str> = prepared_engine.service_name.into();
+
+    recover(&dir).await?;
+    tracing::info!(dir = %dir.display(), "Watching for prompt files");
+
+    let mut files = JoinSet::new();
+    let mut poll = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = cancel_token.cancelled() => break,
+            _ = poll.tick() => {}
+        }
+        while files.try_join_next().is_some() {}
+        let ready = match ready_files(&dir, SystemTime::now()).await {
+            Ok(ready) => ready,
+            Err(err) => {
+                tracing::warn!(%err, dir = %dir.display(), "Failed listing the directory");
+                continue;
+            }
+        };
+        for (path, kind) in ready {
+            let processing = with_suffix(&path, PROCESSING);
+            if let Err(err) = tokio::fs::rename(&path, &processing).await {
+                tracing::warn!(%err, file = %path.display(), "Failed claiming prompt file");
+                continue;
+            }
+            tracing::debug!(file = %path.display(), "Running prompt file");
+            files.spawn(process(
+                path,
+                kind,
+                engine.clone(),
+                service_name.clone(),
+                in_flight.clone(),
+            ));
+        }
+    }
+    // What is still running stays `.processing`, the next start runs it again
+    files.shutdown().await;
+    Ok(())
+}
+
+/// `<path>.<suffix>`
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Puts back the files a dynamo-run that stopped left `.processing`, to run them again
+async fn recover(dir: &Path) -> anyhow::Result<()> {
+    let mut entries = tokio::fs::read_dir(dir)
+        .await
+        .with_context(|| dir.display().to_string())?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.extension().is_none_or(|ext| ext != PROCESSING) {
+            continue;
+        }
+        let original = path.with_extension("");
+        if Kind::of(&original).is_none() {
+            continue;
+        }
+        tracing::info!(
+            file = %original.display(),
+            "Running again a prompt file an earlier run did not finish"
+        );
+        tokio::fs::rename(&path, &original)
+            .await
+            .with_context(|| path.display().to_string())?;
+    }
+    Ok(())
+}
+
+/// The prompt files of `dir` that haven't changed for [`SETTLE`] at `now`, oldest first
+async fn ready_files(dir: &Path, now: SystemTime) -> anyhow::Result<Vec<(PathBuf, Kind)>> {
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    let mut ready = vec![];
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let Some(kind) = Kind::of(&path) else {
+            continue;
+        };
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let Ok(metadata) = entry.metadata().await else {
+            // Gone already
+            continue;
+        };
+        if !metadata.is_file() {
+            continue;
+        }
+        let modified = metadata.modified()?;
+        if now.duration_since(modified).unwrap_or_default() >= SETTLE {
+            ready.push((modified, path, kind));
+        }
+    }
+    ready.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(ready
+        .into_iter()
+        .map(|(_, path, kind)| (path, kind))
+        .collect())
+}
+
+/// Runs a claimed file, writes its `.out` and leaves it `.done` or `.failed`
+async fn process(
+    path: PathBuf,
+    kind: Kind,
+    engine: OpenAIChatCompletionsStreamingEngine,
+    service_name: Arc<str>,
+    in_flight: Arc<Semaphore>,
+) {
+    let processing = with_suffix(&path, PROCESSING);
+    let mut outcome = match kind {
+        Kind::Text => run_text(&processing, engine, &service_name, &in_flight).await,
+        Kind::Lines => run_lines(&processing, engine, &service_name, &in_flight).await,
+    }
+    .unwrap_or_else(|err| Outcome {
+        output: None,
+        failure: Some(format!("{err:#}")),
+    });
+    if let Some(output) = &outcome.output {
+        if let Err(err) = write_whole(&with_suffix(&path, OUT), output).await {
+            outcome.failure = Some(format!("Failed writing the result: {err:#}"));
+        }
+    }
+    let state = match &outcome.failure {
+        None => DONE,
+        Some(failure) => {
+            tracing::warn!(file = %path.display(), "Prompt file failed: {failure}");
+            if let Err(err) = write_whole(&with_suffix(&path, ERROR), failure).await {
+                tracing::warn!(%err, file = %path.display(), "Failed writing the error");
+            }
+            FAILED
+        }
+    };
+    if let Err(err) = tokio::fs::rename(&processing, with_suffix(&path, state)).await {
+        tracing::warn!(%err, file = %processing.display(), "Failed renaming prompt file");
+    }
+}
+
+/// The whole file is the prompt, the reply's text the output
+async fn run_text(
+    file: &Path,
+    engine: OpenAIChatCompletionsStreamingEngine,
+    service_name: &str,
+    in_flight: &Semaphore,
+) -> anyhow::Result<Outcome> {
+    let prompt = tokio::fs::read_to_string(file)
+        .await
+        .context("Failed reading the prompt")?;
+    let request = serde_json::json!({"messages": [{"role": "user", "content": prompt}]});
+    let _permit = in_flight.acquire().await?;
+    let response = common::complete(engine, service_name, request).await?;
+    let Some(choice) = response.inner.choices.first() else {
+        anyhow::bail!("The engine returned no choices");
+    };
+    Ok(Outcome {
+        output: Some(choice.message.content.clone().unwrap_or_default()),
+        failure: None,
+    })
+}
+
+/// A request per line, run together up to `--watch-concurrency`, a response per line
+async fn run_lines(
+    file: &Path,
+    engine: OpenAIChatCompletionsStreamingEngine,
+    service_name: &str,
+    in_flight: &Semaphore,
+) -> anyhow::Result<Outcome> {
+    let contents = tokio::fs::read_to_string(file)
+        .await
+        .context("Failed reading the requests")?;
+    let requests = contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let engine = engine.clone();
+            async move {
+                let request = line_request(line)?;
+                let _permit = in_flight.acquire().await?;
+                common::complete(engine, service_name, request).await
+            }
+        });
+    let responses = futures::future::join_all(requests).await;
+    let mut output = String::new();
+    let mut failed = 0;
+    for response in &responses {
+        let line = match response {
+            Ok(response) => serde_json::to_string(response)?,
+            Err(err) => {
+                failed += 1;
+                serde_json::json!({"error": {"message": format!("{err:#}")}}).to_string()
+            }
+        };
+        output.push_str(&line);
+        output.push('\n');
+    }
+    let failure = (failed > 0).then(|| {
+        format!(
+            "{failed} of {} requests failed, their lines of the .{OUT} have the errors",
+            responses.len()
+        )
+    });
+    Ok(Outcome {
+        output: Some(output),
+        failure,
+    })
+}
+
+/// The chat completion request of a line: a request as it is, or an in=batch entry
+fn line_request(line: &str) -> anyhow::Result<serde_json::Value> {
+    let mut request: serde_json::Value =
+        serde_json::from_str(line).context("Request is not JSON")?;
+    let Some(fields) = request.as_object_mut() else {
+        anyhow::bail!("Request must be a JSON object");
+    };
+    if let Some(text) = fields.remove("text") {
+        if fields.contains_key("messages") {
+            anyhow::bail!("A request has text or messages, not both");
+        }
+        fields.insert(
+            "messages".to_string(),
+            serde_json::json!([{"role": "user", "content": text}]),
+        );
+    }
+    Ok(request)
+}
+
+/// Writes `contents` under a temporary name and renames it to `path`, so that `path` only
+/// ever has all of it
+async fn write_whole(path: &Path, contents: &str) -> anyhow::Result<()> {
+    let partial = with_suffix(path, "partial");
+    tokio::fs::write(&partial, contents)
+        .await
+        .with_context(|| partial.display().to_string())?;
+    tokio::fs::rename(&partial, path)
+        .await
+        .with_context(|| path.display().to_string())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watched_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let arg = dir.path().to_str().unwrap();
+        assert_eq!(watched_dir(arg).unwrap(), dir.path());
+        assert!(watched_dir("").is_err());
+        let missing = dir.path().join("missing");
+        assert!(watched_dir(missing.to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_line_request() {
+        let request = line_request(r#"{"text": "Hi", "max_tokens": 8}"#).unwrap();
+        assert_eq!(
+            request,
+            serde_json::json!({"messages": [{"role": "user", "content": "Hi"}], "max_tokens": 8})
+        );
+        let chat = r#"{"messages": [{"role": "user", "content": "Hi"}], "model": "m"}"#;
+        assert_eq!(
+            line_request(chat).unwrap(),
+            serde_json::from_str::<serde_json::Value>(chat).unwrap()
+        );
+        assert!(line_request(r#"{"text": "Hi", "messages": []}"#).is_err());
+        assert!(line_request("[1]").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_ready_files() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in [
+            "a.txt",
+            "b.jsonl",
+            "c.json",
+            ".d.txt",
+            "e.txt.done",
+            "f.txt.out",
+        ] {
+            std::fs::write(dir.path().join(name), "Hi").unwrap();
+        }
+        std::fs::write(dir.path().join("g.jsonl.processing"), "{}").unwrap();
+
+        // Just written, not settled yet
+        let ready = ready_files(dir.path(), SystemTime::now()).await.unwrap();
+        assert!(ready.is_empty());
+
+        recover(dir.path()).await.unwrap();
+        let later = SystemTime::now() + SETTLE;
+        let mut ready = ready_files(dir.path(), later).await.unwrap();
+        ready.sort();
+        let names: Vec<_> = ready
+            .iter()
+            .map(|(path, kind)| (path.file_name().unwrap().to_str().unwrap(), *kind))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("a.txt", Kind::Text),
+                ("b.jsonl", Kind::Lines),
+                ("g.jsonl", Kind::Lines)
+            ]
+        );
+    }
+}
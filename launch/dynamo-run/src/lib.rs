@@ -5,16 +5,33 @@ use std::{future::Future, pin::Pin};
 use std::{io::Read, sync::Arc, time::Duration};
 
 use anyhow::Context;
-use dynamo_llm::{backend::ExecutionContext, engines::StreamingEngine, LocalModel};
+use dynamo_llm::{
+    backend::ExecutionContext,
+    engines::{load::Device, StreamingEngine},
+    model_card::ModelDeploymentCard,
+    LocalModel,
+};
 use dynamo_runtime::{protocols::Endpoint, CancellationToken, DistributedRuntime};
 
+pub mod config;
+pub mod dry_run;
 mod flags;
 pub use flags::Flags;
+pub mod gen_client;
 mod input;
+mod model_pool;
+use model_pool::ModelPool;
+mod multi;
+use multi::MultiEngine;
+pub mod lint;
 mod opt;
+pub mod plugin;
 pub use dynamo_llm::request_template::RequestTemplate;
 pub use opt::{Input, Output};
+pub mod probe;
+pub mod router;
 mod subprocess;
+mod warmup;
 
 const CHILD_STOP_TIMEOUT: Duration = Duration::from_secs(2);
 
@@ -22,6 +39,10 @@ const CHILD_STOP_TIMEOUT: Duration = Duration::from_secs(2);
 #[cfg(feature = "python")]
 const PYTHON_STR_SCHEME: &str = "pystr:";
 
+/// How we identify a python token endpoint
+#[cfg(feature = "python")]
+const PYTHON_TOK_SCHEME: &str = "pytok:";
+
 pub enum EngineConfig {
     /// An remote networked engine we don't know about yet
     Dynamic(Endpoint),
@@ -37,6 +58,12 @@ pub enum EngineConfig {
         engine: ExecutionContext,
         model: Box<LocalModel>,
     },
+
+    /// Several models, each loaded on its first request. in=http only.
+    Pool(Arc<ModelPool>),
+
+    /// Several engines, each serving a model, see [`multi`]. Chat completions only.
+    Multi(Arc<MultiEngine>),
 }
 
 pub async fn run(
@@ -46,12 +73,190 @@ pub async fn run(
     flags: Flags,
 ) -> anyhow::Result<()> {
     let cancel_token = runtime.primary_token();
-    let maybe_path = flags
-        .model_path_pos
-        .clone()
-        .or(flags.model_path_flag.clone());
 
-    let local_model: LocalModel = match out_opt {
+    let template = if let Some(path) = flags.request_template.as_ref() {
+        let template = RequestTemplate::load(path)?;
+        tracing::debug!("Using request template: {template:?}");
+        Some(template)
+    } else {
+        None
+    };
+
+    let out_name = out_opt.to_string();
+    let arena_out = match &in_opt {
+        Input::Arena(other) | Input::BenchCompare(other) => {
+            let other = Output::try_from(other.as_str())?;
+            if out_opt.is_subprocess() && other.is_subprocess() {
+                let mode = if matches!(in_opt, Input::Arena(_)) {
+                    "arena"
+                } else {
+                    "bench"
+                };
+                anyhow::bail!("in={mode} can only run one of vllm and sglang");
+            }
+            Some(other)
+        }
+        _ => None,
+    };
+
+    if let (Input::Batch(path), Some(shards)) = (&in_opt, flags.batch_shards) {
+        // Each shard is a dynamo-run of its own, with its own engine
+        return crate::input::batch::coordinate(cancel_token, path, &flags, shards).await;
+    }
+
+    let pooled =
+        flags.model_paths().len() > 1 || (flags.model_admin && matches!(in_opt, Input::Http));
+    let (engine_config, card, extra) = if let Output::Multi(path) = &out_opt {
+        if !flags.model_paths().is_empty() {
+            anyhow::bail!(
+                "out=multi takes its models from {}, not --model-path",
+                path.display()
+            );
+        }
+        let (multi, stops) =
+            MultiEngine::start(&runtime, path, &flags, cancel_token.clone()).await?;
+        let card = ModelDeploymentCard::with_name_only(multi.default_model());
+        let extra: Option<StopFuture> = (!stops.is_empty()).then(|| {
+            Box::pin(async move {
+                for stop in stops {
+                    stop.await;
+                }
+            }) as StopFuture
+        });
+        (EngineConfig::Multi(multi), card, extra)
+    } else if pooled {
+        if !matches!(in_opt, Input::Http) {
+            anyhow::bail!("Several --model-path need in=http");
+        }
+        if out_opt.is_subprocess() || matches!(out_opt, Output::Endpoint(_)) {
+            anyhow::bail!(
+                "out={out_name} serves one model, it cannot take several --model-path or --model-admin"
+            );
+        }
+        let pool = ModelPool::new(out_opt, &flags, cancel_token.clone()).await?;
+        let card = pool.first_card();
+        (EngineConfig::Pool(pool), card, None)
+    } else {
+        make_engine(out_opt, &flags, cancel_token.clone()).await?
+    };
+    let tokens_per_sec = warmup::run(&engine_config, &flags.warmup).await;
+    // The engine is up. A worker is ready once its endpoint is registered too.
+    if !matches!(in_opt, Input::Endpoint(_)) {
+        dynamo_runtime::readiness::set_ready(true);
+    }
+
+    if let Some(port) = flags.metrics_port {
+        if matches!(in_opt, Input::Http) {
+            tracing::warn!("--metrics-port is ignored with in=http, metrics are on the HTTP port");
+        } else {
+            let cancel_token = cancel_token.clone();
+            tokio::spawn(async move {
+                if let Err(err) = dynamo_runtime::metrics::serve(port, cancel_token).await {
+                    tracing::error!(%err, "Metrics server failed");
+                }
+            });
+        }
+    }
+
+    match in_opt {
+        Input::Http => {
+            crate::input::http::run(
+                runtime.clone(),
+                flags,
+                engine_config,
+                &card.service_name,
+                template,
+            )
+            .await?;
+        }
+        Input::Text => {
+            crate::input::text::run(runtime.clone(), flags, None, engine_config, template).await?;
+        }
+        Input::Stdin => {
+            let mut prompt = String::new();
+            std::io::stdin().read_to_string(&mut prompt).unwrap();
+            crate::input::text::run(
+                runtime.clone(),
+                flags,
+                Some(prompt),
+                engine_config,
+                template,
+            )
+            .await?;
+        }
+        Input::Batch(path) => {
+            crate::input::batch::run(runtime.clone(), flags, card, path, engine_config, template)
+                .await?;
+        }
+        Input::Mcp(transport) => {
+            crate::input::mcp::run(runtime.clone(), flags, transport, engine_config).await?;
+        }
+        Input::Plugin { name, arg } => {
+            let provider =
+                plugin::input(&name).with_context(|| format!("in={name} is not registered"))?;
+            provider
+                .run(runtime.clone(), flags, &arg, engine_config)
+                .await?;
+        }
+        Input::Endpoint(path) => {
+            let distributed_runtime = DistributedRuntime::from_settings(runtime.clone()).await?;
+            let gpu_telemetry = flags.gpu_telemetry.map(Duration::from_secs);
+            crate::input::endpoint::run(
+                distributed_runtime,
+                path,
+                engine_config,
+                gpu_telemetry,
+                tokens_per_sec,
+            )
+            .await?;
+        }
+        Input::Bench => {
+            crate::input::bench::run(runtime.clone(), flags, out_name, engine_config, template)
+                .await?;
+        }
+        Input::Arena(_) | Input::BenchCompare(_) => {
+            let other_out = arena_out.expect("in=arena and in=bench have a second engine");
+            let other_name = other_out.to_string();
+            let (other_config, _, other_extra) =
+                make_engine(other_out, &flags, cancel_token.clone()).await?;
+            let contenders = [(out_name, engine_config), (other_name, other_config)];
+            if matches!(in_opt, Input::Arena(_)) {
+                crate::input::arena::run(runtime.clone(), flags, contenders, template).await?;
+            } else {
+                crate::input::bench_compare::run(runtime.clone(), flags, contenders, template)
+                    .await?;
+            }
+            if let Some(other_extra) = other_extra {
+                other_extra.await;
+            }
+        }
+    }
+
+    // Allow engines to ask main thread to wait on an extra future.
+    // We use this to stop the vllm and sglang sub-process
+    if let Some(extra) = extra {
+        extra.await;
+    }
+
+    Ok(())
+}
+
+/// Waits for a vllm or sglang sub-process to stop
+type StopFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Create the engine matching `out`. Also returns the model card, and for vllm and sglang a
+/// future that stops their sub-process once `cancel_token` is cancelled.
+async fn make_engine(
+    out_opt: Output,
+    flags: &Flags,
+    cancel_token: CancellationToken,
+) -> anyhow::Result<(EngineConfig, ModelDeploymentCard, Option<StopFuture>)> {
+    if matches!(out_opt, Output::Multi(_)) {
+        anyhow::bail!("out=multi runs the engines of its file, it cannot be one of them");
+    }
+    let maybe_path = flags.model_paths().into_iter().next();
+
+    let mut local_model: LocalModel = match out_opt {
         // If output is an endpoint we are ingress and don't have a local model, but making an
         // empty one cleans up the code.
         Output::Endpoint(_) => Default::default(),
@@ -60,12 +265,17 @@ pub async fn run(
         _ => {
             match &maybe_path {
                 Some(model_path) => {
-                    LocalModel::prepare(
+                    let mut local_model = LocalModel::prepare(
                         model_path.to_str().context("Invalid UTF-8 in model path")?,
                         flags.model_config.as_deref(),
                         flags.model_name.clone(),
+                        flags.tokenizer_path.as_deref(),
                     )
-                    .await?
+                    .await?;
+                    if let Some(chat_template) = &flags.chat_template {
+                        local_model.set_chat_template(chat_template)?;
+                    }
+                    local_model
                 }
                 None => {
                     // echo_full engine doesn't need a path
@@ -75,19 +285,33 @@ pub async fn run(
         }
     };
 
-    let mut extra: Option<Pin<Box<dyn Future<Output = ()> + Send>>> = None; // vllm and sglang sub-process
+    let mut extra: Option<StopFuture> = None; // vllm and sglang sub-process
 
-    let template = if let Some(path) = flags.request_template.as_ref() {
-        let template = RequestTemplate::load(path)?;
-        tracing::debug!("Using request template: {template:?}");
-        Some(template)
+    if let Some(engine) = out_opt.card_engine() {
+        local_model.set_engine(engine);
+    }
+
+    // llamacpp picks its own context length, the pre-processor must truncate to the same
+    #[cfg(feature = "llamacpp")]
+    let context_length = if matches!(out_opt, Output::LlamaCpp) {
+        Some(dynamo_engine_llamacpp::context_length(
+            local_model.path(),
+            flags.context_length,
+        ))
     } else {
-        None
+        flags.context_length
     };
+    #[cfg(not(feature = "llamacpp"))]
+    let context_length = flags.context_length;
+    if let Some(context_length) = context_length {
+        local_model.set_context_length(context_length);
+    }
 
     // We may need it later
     let card = local_model.card().clone();
 
+    let load_options = flags.load_options(&out_opt)?;
+
     // Create the engine matching `out`
     let engine_config = match out_opt {
         Output::Endpoint(path) => {
@@ -110,9 +334,37 @@ pub async fn run(
                 model: Box::new(local_model),
             }
         }
+        Output::Mock => {
+            let Some(tokenizer) = &local_model.card().tokenizer else {
+                anyhow::bail!(
+                    "out=mock needs to find the tokenizer. Pass flag --model-path <path>"
+                );
+            };
+            let profile = flags.mock_profile.unwrap_or_default();
+            tracing::info!(%profile, "Mock engine");
+            EngineConfig::StaticCore {
+                engine: dynamo_llm::engines::mock::make_engine_mock(
+                    profile,
+                    tokenizer.load()?.as_ref(),
+                )?,
+                model: Box::new(local_model),
+            }
+        }
+        Output::Multi(_) => unreachable!("out=multi is refused above"),
+        Output::Replay(path) => {
+            if !local_model.card().has_tokenizer() {
+                anyhow::bail!(
+                    "out=replay needs the tokenizer the trace was recorded with. Pass flag --model-path <path>"
+                );
+            }
+            EngineConfig::StaticCore {
+                engine: dynamo_llm::engines::replay::make_engine_replay(&path)?,
+                model: Box::new(local_model),
+            }
+        }
         #[cfg(feature = "mistralrs")]
         Output::MistralRs => EngineConfig::StaticFull {
-            engine: dynamo_engine_mistralrs::make_engine(&local_model).await?,
+            engine: dynamo_engine_mistralrs::make_engine(&local_model, &load_options).await?,
             model: Box::new(local_model),
         },
         Output::SgLang => {
@@ -120,19 +372,26 @@ pub async fn run(
                 // TODO Does sglang support GGUF? Can we make it work?
                 anyhow::bail!("`--model-path should point at a HuggingFace repo checkout");
             }
+            if !flags.lora.is_empty() || flags.max_loras.is_some() {
+                anyhow::bail!("sglang does not support LoRA adapters, use vllm");
+            }
             let multi_node_conf = dynamo_llm::engines::MultiNodeConfig {
                 num_nodes: flags.num_nodes,
                 node_rank: flags.node_rank,
                 leader_addr: flags.leader_addr.clone().unwrap_or_default(),
             };
+            let base_gpu_id = match load_options.device {
+                Some(Device::Cuda(index)) => index,
+                _ => flags.base_gpu_id,
+            };
             let (py_script, child) = match subprocess::start(
                 subprocess::sglang::PY,
                 &local_model,
-                flags.tensor_parallel_size,
-                if flags.base_gpu_id == 0 {
+                &load_options,
+                if base_gpu_id == 0 {
                     None
                 } else {
-                    Some(flags.base_gpu_id)
+                    Some(base_gpu_id)
                 },
                 if flags.num_nodes <= 1 {
                     None
@@ -140,6 +399,11 @@ pub async fn run(
                     Some(multi_node_conf)
                 },
                 flags.extra_engine_args.as_deref(),
+                flags.draft_config(),
+                None, // max_loras, LoRA is vllm only
+                flags.max_prefill_chunk,
+                flags.chat_template.as_deref(),
+                &flags.warmup,
             )
             .await
             {
@@ -164,10 +428,15 @@ pub async fn run(
             let (py_script, child) = match subprocess::start(
                 subprocess::vllm::PY,
                 &local_model,
-                flags.tensor_parallel_size,
+                &load_options,
                 None, // base_gpu_id. vllm uses CUDA_VISIBLE_DEVICES instead
                 None, // multi-node config. vllm uses `ray`, see guide
                 flags.extra_engine_args.as_deref(),
+                flags.draft_config(),
+                flags.max_loras(),
+                flags.max_prefill_chunk,
+                flags.chat_template.as_deref(),
+                &flags.warmup,
             )
             .await
             {
@@ -191,9 +460,24 @@ pub async fn run(
             if !local_model.path().is_file() {
                 anyhow::bail!("--model-path should refer to a GGUF file. llama_cpp does not support safetensors.");
             }
-            let engine =
-                dynamo_engine_llamacpp::make_engine(cancel_token.clone(), local_model.path())
-                    .await?;
+            let options = dynamo_engine_llamacpp::EngineOptions {
+                max_batch_size: flags.max_batch_size,
+                context_length: context_length
+                    .unwrap_or(dynamo_engine_llamacpp::DEFAULT_CONTEXT_LENGTH),
+                rope_scaling: flags.rope_scaling,
+                rope_freq_base: flags.rope_freq_base,
+                max_prefill_chunk: flags.max_prefill_chunk,
+                prefill_decode_ratio: flags.prefill_decode_ratio,
+                preemption: flags.preemption,
+                device: load_options.device,
+                tensor_parallel_size: load_options.tensor_parallel_size,
+            };
+            let engine = dynamo_engine_llamacpp::make_engine(
+                cancel_token.clone(),
+                local_model.path(),
+                options,
+            )
+            .await?;
             EngineConfig::StaticCore {
                 engine,
                 model: Box::new(local_model),
@@ -211,44 +495,33 @@ pub async fn run(
                 model: Box::new(local_model),
             }
         }
-    };
-
-    match in_opt {
-        Input::Http => {
-            crate::input::http::run(runtime.clone(), flags, engine_config, template).await?;
-        }
-        Input::Text => {
-            crate::input::text::run(runtime.clone(), flags, None, engine_config, template).await?;
+        Output::Plugin { name, arg } => {
+            let provider =
+                plugin::engine(&name).with_context(|| format!("out={name} is not registered"))?;
+            provider
+                .make_engine(&arg, local_model, flags, cancel_token.clone())
+                .await?
         }
-        Input::Stdin => {
-            let mut prompt = String::new();
-            std::io::stdin().read_to_string(&mut prompt).unwrap();
-            crate::input::text::run(
-                runtime.clone(),
-                flags,
-                Some(prompt),
-                engine_config,
-                template,
-            )
-            .await?;
-        }
-        Input::Batch(path) => {
-            crate::input::batch::run(runtime.clone(), flags, card, path, engine_config, template)
-                .await?;
-        }
-        Input::Endpoint(path) => {
-            let distributed_runtime = DistributedRuntime::from_settings(runtime.clone()).await?;
-            crate::input::endpoint::run(distributed_runtime, path, engine_config).await?;
+        #[cfg(feature = "python")]
+        Output::PythonTok(path_str) => {
+            let card = local_model.card();
+            if !card.has_tokenizer() {
+                anyhow::bail!(
+                    "out=pytok needs to find the tokenizer. Pass flag --model-path <path>"
+                );
+            }
+            let py_args = flags.as_vec(&path_str, &card.service_name);
+            let p = std::path::PathBuf::from(path_str);
+            let engine =
+                dynamo_engine_python::make_token_engine(cancel_token.clone(), &p, py_args).await?;
+            EngineConfig::StaticCore {
+                engine,
+                model: Box::new(local_model),
+            }
         }
-    }
-
-    // Allow engines to ask main thread to wait on an extra future.
-    // We use this to stop the vllm and sglang sub-process
-    if let Some(extra) = extra {
-        extra.await;
-    }
+    };
 
-    Ok(())
+    Ok((engine_config, card, extra))
 }
 
 /// Wait for cancel_token to be cancelled, then stop the child as gracefully as possible.
@@ -261,9 +534,13 @@ async fn stopper(
     cancel_token.cancelled().await;
 
     // Ask subprocess to stop gracefully
+    #[cfg(unix)]
     if let Some(pid) = child.id() {
         unsafe { libc::kill(pid as i32, libc::SIGTERM) };
     }
+    // There is no asking on Windows, but the sub-process engines don't run there anyway
+    #[cfg(windows)]
+    let _ = child.start_kill();
 
     tokio::select! {
         exit = child.wait() => {
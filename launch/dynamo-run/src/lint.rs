@@ -0,0 +1,766 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `dynamo-run lint --config <file>`
+//!
+//! Checks a dynamo-run configuration without running it, for CI on deployment manifests. The
+//! file is one `--config` takes, see [`crate::config`]. An array of arguments can also start
+//! with `router`, as `dynamo-run router` takes them.
+//!
+//! Errors are what would stop dynamo-run from starting: unknown flags or bad values, missing
+//! files, engines this binary was built without. Warnings are options that would be ignored
+//! or contradict each other.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+use anyhow::Context as _;
+use clap::{parser::ValueSource, CommandFactory, FromArgMatches};
+use dynamo_llm::auth::{self, ApiKeys};
+use dynamo_llm::presets::PresetLibrary;
+use dynamo_llm::tenancy::Tenants;
+use regex::Regex;
+
+use crate::flags::BatchFormat;
+use crate::input::text::Session;
+use crate::{config, plugin, router, Flags, Input, Output, RequestTemplate};
+
+/// Prefix of the python engine, also when this binary was built without it
+const PYTHON_STR_PREFIX: &str = "pystr:";
+const PYTHON_TOK_PREFIX: &str = "pytok:";
+
+/// A model path that is always downloaded from Hugging Face
+const HF_SCHEME: &str = "hf://";
+
+/// `in=http only`, `in=text and in=batch only` and the like in a flag's help
+static INPUT_SCOPE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\b(in=\w+(?:(?:,\s*|\s+and\s+)in=\w+)*)`?\s+only\b").unwrap());
+
+/// `llamacpp only`, `vllm and sglang only`, `sglang, vllm` at the start of a flag's help
+static ENGINE_SCOPE: LazyLock<Regex> = LazyLock::new(|| {
+    let engine = "(?:mistralrs|llamacpp|sglang|vllm)";
+    Regex::new(&format!(
+        r"^({engine}(?:(?:,\s*|\s+and\s+){engine})*)(?:\s+only)?(?:\s|$)"
+    ))
+    .unwrap()
+});
+
+#[derive(clap::Parser, Debug)]
+#[command(name = "dynamo-run lint")]
+struct LintArgs {
+    /// JSON, TOML or YAML file of dynamo-run arguments: an array as on the command line, or
+    /// `in`, `out` and flags.
+    #[arg(long)]
+    config: PathBuf,
+
+    /// Fail on warnings too
+    #[arg(long)]
+    deny_warnings: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Report {
+    findings: Vec<(Severity, String)>,
+}
+
+impl Report {
+    fn error(&mut self, message: impl Into<String>) {
+        self.findings.push((Severity::Error, message.into()));
+    }
+
+    fn warning(&mut self, message: impl Into<String>) {
+        self.findings.push((Severity::Warning, message.into()));
+    }
+
+    fn count(&self, severity: Severity) -> usize {
+        self.findings.iter().filter(|(s, _)| *s == severity).count()
+    }
+}
+
+/// Entry point for `dynamo-run lint ...`. `args` starts at `lint`.
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = <LintArgs as clap::Parser>::parse_from(args);
+    let report = match config::load_args(&args.config) {
+        Ok(run_args) => lint(&run_args),
+        Err(err) => {
+            let mut report = Report::default();
+            report.error(format!("{err:#}"));
+            report
+        }
+    };
+
+    for (severity, message) in &report.findings {
+        println!("{severity}: {message}");
+    }
+    let errors = report.count(Severity::Error);
+    let warnings = report.count(Severity::Warning);
+    println!(
+        "{}: {errors} errors, {warnings} warnings",
+        args.config.display()
+    );
+    if errors > 0 || (args.deny_warnings && warnings > 0) {
+        anyhow::bail!("{} failed lint", args.config.display());
+    }
+    Ok(())
+}
+
+/// The errors and the warnings for the arguments of a run that is about to start
+pub(crate) fn check(args: &[String]) -> (Vec<String>, Vec<String>) {
+    let mut errors = vec![];
+    let mut warnings = vec![];
+    for (severity, message) in lint(args).findings {
+        match severity {
+            Severity::Error => errors.push(message),
+            Severity::Warning => warnings.push(message),
+        }
+    }
+    (errors, warnings)
+}
+
+/// Check dynamo-run arguments, without the binary name
+fn lint(args: &[String]) -> Report {
+    let mut report = Report::default();
+
+    // `router ...` is a shorthand
+    let expanded;
+    let args = if args.first().is_some_and(|arg| arg == "router") {
+        match router::expand(args) {
+            Ok(router_args) => {
+                expanded = router_args;
+                &expanded
+            }
+            Err(err) => {
+                report.error(err.to_string());
+                return report;
+            }
+        }
+    } else {
+        args
+    };
+
+    // Like main: in= and out= are the first two arguments, if given
+    let mut in_value = None;
+    let mut out_value = None;
+    let mut skip = 0;
+    for arg in args.iter().take(2) {
+        match arg.split_once('=') {
+            Some(("in", value)) => in_value = Some(value.to_string()),
+            Some(("out", value)) => out_value = Some(value.to_string()),
+            _ => continue,
+        }
+        skip += 1;
+    }
+
+    let in_opt = match in_value.as_deref().map(Input::try_from) {
+        Some(Ok(in_opt)) => Some(in_opt),
+        Some(Err(err)) => {
+            report.error(err.to_string());
+            None
+        }
+        None => Some(Input::Text),
+    };
+    let out_opt = match out_value.as_deref() {
+        Some(value) => match Output::try_from(value) {
+            Ok(out_opt) => Some(out_opt),
+            Err(err) => {
+                match missing_feature(value) {
+                    Some(feature) => report.error(format!(
+                        "out={value} is not available, this dynamo-run was built without the \
+                         '{feature}' feature"
+                    )),
+                    None => report.error(err.to_string()),
+                }
+                None
+            }
+        },
+        None => Some(Output::default()),
+    };
+    if let Some(out) = out_value.as_deref() {
+        let python_path = out
+            .strip_prefix(PYTHON_STR_PREFIX)
+            .or_else(|| out.strip_prefix(PYTHON_TOK_PREFIX));
+        if python_path.is_some_and(|path| !Path::new(path).is_file()) {
+            report.error(format!("out={out}: no such file"));
+        }
+    }
+
+    let command = Flags::command();
+    let matches = match command.clone().try_get_matches_from(
+        ["dynamo-run".to_string()]
+            .into_iter()
+            .chain(args.iter().skip(skip).cloned()),
+    ) {
+        Ok(matches) => matches,
+        Err(err) => {
+            report.error(clap_message(&err));
+            return report;
+        }
+    };
+    let flags = match Flags::from_arg_matches(&matches) {
+        Ok(flags) => flags,
+        Err(err) => {
+            report.error(clap_message(&err));
+            return report;
+        }
+    };
+
+    check_files(&flags, in_opt.as_ref(), &mut report);
+
+    let (Some(in_opt), Some(out_opt)) = (in_opt, out_opt) else {
+        return report;
+    };
+    let is_set = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+    // As the user wrote it, Display drops the dyn:// of an endpoint
+    let out_label = out_value.clone().unwrap_or_else(|| out_opt.to_string());
+
+    // Flags already reported, the scope check below would only repeat them
+    let mut reported = vec![];
+
+    check_model_path(&flags, &out_opt, &mut report);
+
+    let is_endpoint = |out: &Output| matches!(out, Output::Endpoint(_));
+    if matches!(in_opt, Input::Endpoint(_)) && is_endpoint(&out_opt) {
+        report.error("in=dyn:// and out=dyn:// cannot be used together");
+    }
+    if matches!(in_opt, Input::Endpoint(_)) && matches!(out_opt, Output::Multi(_)) {
+        report.error("in=dyn:// serves one model, it cannot take out=multi");
+    }
+    if is_endpoint(&out_opt) && flags.chat_template.is_some() {
+        report.warning("--chat-template is ignored with out=dyn://, pass it to the workers");
+    }
+    if is_endpoint(&out_opt) && flags.context_length.is_some() {
+        report.warning("--context-length is ignored with out=dyn://, pass it to the workers");
+    }
+    if is_endpoint(&out_opt) && !flags.warmup.is_empty() {
+        report.warning("--warmup is ignored with out=dyn://, pass it to the workers");
+    }
+    if let Input::Arena(other) | Input::BenchCompare(other) = &in_opt {
+        if let Ok(other) = Output::try_from(other.as_str()) {
+            if out_opt.is_subprocess() && other.is_subprocess() {
+                report.error(format!(
+                    "in={} can only run one of vllm and sglang",
+                    input_name(&in_opt)
+                ));
+            }
+        }
+    }
+    if matches!(out_opt, Output::Vllm) && flags.base_gpu_id != 0 {
+        report.error("--base-gpu-id is not supported by vllm, set CUDA_VISIBLE_DEVICES instead");
+        reported.push("base_gpu_id");
+    }
+    if let Err(err) = flags.load_options(&out_opt) {
+        report.error(format!("{err:#}"));
+        reported.extend([
+            "quantization",
+            "dtype",
+            "tensor_parallel_size",
+            "gpu_memory_fraction",
+            "device",
+        ]);
+    }
+    if flags.node_rank >= flags.num_nodes {
+        report.error(format!(
+            "--node-rank {} must be less than --num-nodes {}",
+            flags.node_rank, flags.num_nodes
+        ));
+    }
+    if flags.tensor_parallel_size % flags.num_nodes != 0 {
+        report.error(format!(
+            "--tensor-parallel-size {} must divide by --num-nodes {}",
+            flags.tensor_parallel_size, flags.num_nodes
+        ));
+    }
+    if matches!(out_opt, Output::SgLang) && flags.num_nodes > 1 && flags.leader_addr.is_none() {
+        report.error("sglang on more than one node needs --leader-addr");
+    }
+    if is_set("kv_block_size") && !flags.router_mode.is_kv_routing() {
+        report.warning("--kv-block-size only applies with --router-mode kv");
+    }
+    if is_set("router_mode") && !is_endpoint(&out_opt) {
+        report.warning(format!(
+            "--router-mode only applies to out=dyn://, it is ignored with out={out_label}"
+        ));
+    }
+    if flags.batch_shards.is_some() && matches!(in_opt, Input::Batch(_)) && !is_endpoint(&out_opt) {
+        report.warning(format!(
+            "--batch-shards starts out={out_label} once per shard, use out=dyn:// to share \
+             the workers of an endpoint"
+        ));
+    }
+    if flags.metrics_port.is_some() && matches!(in_opt, Input::Http) {
+        report.warning("--metrics-port is ignored with in=http, metrics are on the HTTP port");
+    }
+    if flags.response_cache_redis.is_some() && !cfg!(feature = "redis") {
+        report.error(
+            "--response-cache-redis is not available, this dynamo-run was built without the \
+             'redis' feature",
+        );
+    }
+    if flags.model_paths().len() > 1 && !matches!(in_opt, Input::Http) {
+        report.error(format!(
+            "in={} serves one model, several --model-path need in=http",
+            input_name(&in_opt)
+        ));
+    }
+    if flags.vram_budget_gb.is_some() && flags.model_paths().len() < 2 && !flags.model_admin {
+        report.warning(
+            "--vram-budget-gb does nothing with fewer than two --model-path, without --model-admin",
+        );
+        reported.push("vram_budget_gb");
+    }
+    if flags.model_admin && (out_opt.is_subprocess() || is_endpoint(&out_opt)) {
+        report.error(format!(
+            "--model-admin loads models in dynamo-run, out={out_label} cannot"
+        ));
+    }
+    if flags.admin_api && flags.api_keys.is_none() && !api_keys_in_env() {
+        report.warning(
+            "--admin-api without --api-keys lets anyone who reaches the port drain workers and \
+             read the config",
+        );
+    }
+    if flags.lora_admin && flags.api_keys.is_none() && !api_keys_in_env() {
+        report.warning(
+            "--lora-admin without --api-keys lets anyone who reaches the port load adapters \
+             from any path",
+        );
+    }
+    if flags.slo_degrade_max_tokens.is_some()
+        && flags.slo_ttft_ms.is_none()
+        && flags.slo_queue_delay_ms.is_none()
+    {
+        report.warning(
+            "--slo-degrade-max-tokens does nothing without --slo-ttft-ms or --slo-queue-delay-ms",
+        );
+        reported.push("slo_degrade_max_tokens");
+    }
+    if !flags.last.is_empty() && !out_value.as_deref().is_some_and(is_python) {
+        report.warning("Arguments after `--` are only passed to pystr and pytok engines");
+    }
+
+    // Flags documented as only applying to some inputs or engines
+    let input_name = input_name(&in_opt);
+    let output_name = out_opt.to_string();
+    for arg in command.get_arguments() {
+        let id = arg.get_id().as_str();
+        if !is_set(id) || reported.contains(&id) {
+            continue;
+        }
+        let Some(help) = arg.get_help().map(|help| help.to_string()) else {
+            continue;
+        };
+        let flag = match arg.get_long() {
+            Some(long) => format!("--{long}"),
+            None => id.to_string(),
+        };
+        if let Some(scope) = INPUT_SCOPE.captures(&help) {
+            let inputs: Vec<&str> = scope[1]
+                .split([',', ' '])
+                .filter_map(|word| word.strip_prefix("in="))
+                .collect();
+            if !inputs.contains(&input_name) {
+                report.warning(format!(
+                    "{flag} only applies to {}, it is ignored with in={input_name}",
+                    &scope[1]
+                ));
+            }
+        }
+        // The engines of out=multi take the command line's flags as well as their own
+        let engine_scope = ENGINE_SCOPE
+            .captures(&help)
+            .filter(|_| !matches!(out_opt, Output::Multi(_)));
+        if let Some(scope) = engine_scope {
+            let engines: Vec<&str> = scope[1]
+                .split([',', ' '])
+                .filter(|word| !word.is_empty() && *word != "and")
+                .collect();
+            if !engines.contains(&output_name.as_str()) {
+                report.warning(format!(
+                    "{flag} only applies to {}, it is ignored with out={out_label}",
+                    engines.join(" and ")
+                ));
+            }
+        }
+    }
+
+    report
+}
+
+/// Whether the service would get API keys from the environment without --api-keys
+fn api_keys_in_env() -> bool {
+    [auth::API_KEYS_ENV, auth::API_KEYS_FILE_ENV]
+        .iter()
+        .any(|name| std::env::var(name).is_ok_and(|val| !val.is_empty()))
+}
+
+/// The cargo feature an engine known to dynamo-run needs, if this binary doesn't have it
+fn missing_feature(out: &str) -> Option<&'static str> {
+    match out {
+        "mistralrs" if !cfg!(feature = "mistralrs") => Some("mistralrs"),
+        "llamacpp" | "llama_cpp" if !cfg!(feature = "llamacpp") => Some("llamacpp"),
+        _ if is_python(out) && !cfg!(feature = "python") => Some("python"),
+        _ => None,
+    }
+}
+
+fn is_python(out: &str) -> bool {
+    out.starts_with(PYTHON_STR_PREFIX) || out.starts_with(PYTHON_TOK_PREFIX)
+}
+
+/// The input as it's named in flag help, `in=<name> only`
+fn input_name(in_opt: &Input) -> &'static str {
+    match in_opt {
+        Input::Http => "http",
+        Input::Text => "text",
+        Input::Stdin => "stdin",
+        Input::Endpoint(_) => "dyn",
+        Input::Batch(_) => "batch",
+        Input::Arena(_) => "arena",
+        Input::Bench | Input::BenchCompare(_) => "bench",
+        Input::Mcp(_) => "mcp",
+        Input::Plugin { name, .. } => plugin::input(name).map_or("plugin", |input| input.name()),
+    }
+}
+
+/// The first line of a clap error, without its `error: ` prefix
+fn clap_message(err: &clap::Error) -> String {
+    let rendered = err.to_string();
+    let first = rendered.lines().next().unwrap_or_default();
+    first.trim_start_matches("error: ").to_string()
+}
+
+/// Files the flags point at must exist, and parse if we read them at startup
+fn check_files(flags: &Flags, in_opt: Option<&Input>, report: &mut Report) {
+    let files = [
+        ("--model-config", &flags.model_config),
+        ("--tokenizer-path", &flags.tokenizer_path),
+        ("--chat-template", &flags.chat_template),
+        ("--banned-words", &flags.banned_words),
+        ("--tls-cert", &flags.tls_cert),
+        ("--tls-key", &flags.tls_key),
+        ("--tls-client-ca", &flags.tls_client_ca),
+    ];
+    for (flag, path) in files {
+        if let Some(path) = path {
+            if !path.exists() {
+                report.error(format!("{flag} {}: no such file", path.display()));
+            }
+        }
+    }
+
+    if let Some(path) = &flags.extra_engine_args {
+        if let Err(err) = flags.load_extra_engine_args() {
+            report.error(format!("--extra-engine-args {}: {err}", path.display()));
+        }
+    }
+    if let Some(path) = &flags.request_template {
+        if let Err(err) = RequestTemplate::load(path) {
+            report.error(format!("--request-template {}: {err:#}", path.display()));
+        }
+    }
+    if let Some(path) = &flags.presets {
+        if let Err(err) = PresetLibrary::load(path) {
+            report.error(format!("--presets {}: {err:#}", path.display()));
+        }
+    }
+    if let Some(path) = &flags.resume {
+        if let Err(err) = Session::load(path) {
+            report.error(format!("--resume {}: {err:#}", path.display()));
+        }
+    }
+    if let Some(path) = &flags.tenants {
+        if let Err(err) = Tenants::from_file(path) {
+            report.error(format!("--tenants {}: {err:#}", path.display()));
+        }
+    }
+    if let Some(path) = &flags.api_keys {
+        if let Err(err) = ApiKeys::from_file(path) {
+            report.error(format!("--api-keys {}: {err:#}", path.display()));
+        }
+    }
+    let outputs = [
+        ("--arena-results", &flags.arena_results),
+        ("--bench-report", &flags.bench_report),
+        ("--batch-output", &flags.batch_output),
+        ("--batch-report", &flags.batch_report),
+    ];
+    for (flag, path) in outputs {
+        let Some(path) = path else {
+            continue;
+        };
+        let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+        if dir.is_some_and(|dir| !dir.is_dir()) {
+            report.error(format!("{flag} {}: no such directory", path.display()));
+        }
+    }
+    if let Some(path) = &flags.bench_prompts {
+        if !path.exists() {
+            report.error(format!("--bench-prompts {}: no such file", path.display()));
+        }
+    }
+    if matches!(in_opt, Some(Input::Bench)) && flags.bench_prompts.is_some() {
+        report.warning("--bench-prompts only applies with a second engine, in=bench:<engine>");
+    }
+    if let Some(Input::Batch(path)) = in_opt {
+        if !path.exists() {
+            report.error(format!("in=batch:{}: no such file", path.display()));
+        }
+        let parquet_output = flags
+            .batch_output
+            .as_ref()
+            .and_then(|path| path.extension())
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("parquet"));
+        let parquet = match flags.batch_format {
+            Some(format) => format == BatchFormat::Parquet,
+            None => parquet_output,
+        };
+        if parquet && !cfg!(feature = "parquet") {
+            report.error(
+                "Parquet output is not available, this dynamo-run was built without the \
+                 'parquet' feature",
+            );
+        }
+    }
+}
+
+/// Engines running a local model need one they can load
+fn check_model_path(flags: &Flags, out_opt: &Output, report: &mut Report) {
+    let model_paths = flags.model_paths();
+    if let Output::Multi(path) = out_opt {
+        if !model_paths.is_empty() {
+            report.error(format!(
+                "out=multi takes its models from {}, not --model-path",
+                path.display()
+            ));
+        }
+        if let Err(err) = crate::multi::load(path) {
+            report.error(format!("out=multi: {err:#}"));
+        }
+        return;
+    }
+    if model_paths.is_empty() {
+        if !matches!(out_opt, Output::Endpoint(_) | Output::EchoFull) {
+            report.error(format!("out={out_opt} needs a model, pass --model-path"));
+        }
+        return;
+    };
+    if matches!(out_opt, Output::Endpoint(_)) {
+        report.warning("--model-path is ignored with out=dyn://, the workers load the model");
+        return;
+    }
+    if model_paths.len() > 1 && out_opt.is_subprocess() {
+        report.error(format!(
+            "out={out_opt} serves one model, it cannot take several --model-path"
+        ));
+    }
+    for model_path in &model_paths {
+        check_one_model_path(model_path, out_opt, report);
+    }
+}
+
+fn check_one_model_path(model_path: &Path, out_opt: &Output, report: &mut Report) {
+    let path_str = model_path.to_string_lossy();
+    // Downloaded when dynamo-run starts
+    if path_str.starts_with(HF_SCHEME) || dynamo_llm::model_source::is_remote(&path_str) {
+        return;
+    }
+    if !model_path.exists() {
+        // Not on disk, so dynamo-run will download it from Hugging Face
+        let is_repo_name = !model_path.is_absolute()
+            && !path_str.starts_with('.')
+            && path_str.split('/').count() == 2;
+        if !is_repo_name {
+            report.error(format!(
+                "--model-path {path_str}: no such file, and not a Hugging Face repo name"
+            ));
+        }
+        return;
+    }
+    if out_opt.is_subprocess() && !model_path.is_dir() {
+        report.error(format!(
+            "--model-path {path_str}: out={out_opt} needs a Hugging Face repo checkout, not a file"
+        ));
+    }
+    if out_opt.to_string() == "llamacpp" && !model_path.is_file() {
+        report.error(format!(
+            "--model-path {path_str}: out=llamacpp needs a GGUF file, not a directory"
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn findings(args: &[&str]) -> Vec<(Severity, String)> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        lint(&args).findings
+    }
+
+    #[test]
+    fn test_lint() {
+        assert!(findings(&["in=http", "out=echo_full"]).is_empty());
+
+        let found = findings(&["in=http", "out=echo_full", "--no-such-flag"]);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, Severity::Error);
+        assert!(found[0].1.contains("--no-such-flag"), "{found:?}");
+
+        let found = findings(&["in=text", "out=echo_full", "--rate-limit-rpm", "10"]);
+        assert_eq!(
+            found,
+            vec![(
+                Severity::Warning,
+                "--rate-limit-rpm only applies to in=http, it is ignored with in=text".to_string()
+            )]
+        );
+
+        assert!(findings(&["in=text", "out=echo_full", "--temperature", "0.2"]).is_empty());
+        let found = findings(&["in=http", "out=echo_full", "--temperature", "0.2"]);
+        assert_eq!(
+            found,
+            vec![(
+                Severity::Warning,
+                "--temperature only applies to in=text, in=stdin and in=batch, it is ignored \
+                 with in=http"
+                    .to_string()
+            )]
+        );
+
+        let found = findings(&[
+            "in=http",
+            "out=dyn://ns.backend.generate",
+            "--max-batch-size",
+            "8",
+            "--tls-cert",
+            "/nonexistent/cert.pem",
+            "--tls-key",
+            "/nonexistent/key.pem",
+        ]);
+        let messages: Vec<&str> = found.iter().map(|(_, message)| message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "--tls-cert /nonexistent/cert.pem: no such file",
+                "--tls-key /nonexistent/key.pem: no such file",
+                "--max-batch-size only applies to llamacpp, it is ignored with out=dyn://ns.backend.generate",
+            ]
+        );
+
+        let found = findings(&[
+            "in=http",
+            "out=dyn://ns.backend.generate",
+            "--chat-template",
+            "/nonexistent/template.jinja",
+            "--context-length",
+            "4096",
+        ]);
+        let messages: Vec<&str> = found.iter().map(|(_, message)| message.as_str()).collect();
+        assert!(messages.contains(&"--chat-template /nonexistent/template.jinja: no such file"));
+        assert!(messages
+            .contains(&"--chat-template is ignored with out=dyn://, pass it to the workers"));
+        assert!(messages
+            .contains(&"--context-length is ignored with out=dyn://, pass it to the workers"));
+
+        let several = |input| {
+            findings(&[
+                input,
+                "out=echo_core",
+                "--model-path",
+                "Qwen/Qwen3-0.6B",
+                "--model-path",
+                "Qwen/Qwen3-4B",
+            ])
+        };
+        assert!(several("in=http").is_empty());
+        let found = several("in=text");
+        assert_eq!(
+            found,
+            vec![(
+                Severity::Error,
+                "in=text serves one model, several --model-path need in=http".to_string()
+            )]
+        );
+
+        let found = findings(&["in=http", "out=dyn://a.b.c", "--model-admin"]);
+        assert_eq!(
+            found,
+            vec![(
+                Severity::Error,
+                "--model-admin loads models in dynamo-run, out=dyn://a.b.c cannot".to_string()
+            )]
+        );
+
+        let found = findings(&["in=http", "out=multi:/nonexistent/engines.toml"]);
+        assert_eq!(found.len(), 1);
+        assert!(
+            found[0].1.contains("/nonexistent/engines.toml"),
+            "{found:?}"
+        );
+
+        let found = findings(&["in=dyn://a.b.c", "out=dyn://a.b.d"]);
+        assert_eq!(found[0].0, Severity::Error);
+
+        let found = findings(&["in=http", "out=vllm", "--device", "cuda:1"]);
+        let messages: Vec<&str> = found.iter().map(|(_, message)| message.as_str()).collect();
+        assert!(messages.contains(
+            &"vllm does not support --device cuda:1. Set environment variable CUDA_VISIBLE_DEVICES instead."
+        ));
+        let found = findings(&["in=http", "out=sglang", "--quantization", "int4"]);
+        let messages: Vec<&str> = found.iter().map(|(_, message)| message.as_str()).collect();
+        assert!(messages
+            .contains(&"sglang can't quantize to int4 as it loads, use a quantized checkpoint"));
+        let found = findings(&["in=http", "out=echo_full", "--gpu-memory-fraction", "0.9"]);
+        let message = &found[0].1;
+        assert!(message.starts_with("--gpu-memory-fraction only applies to sglang and vllm"));
+
+        let found = findings(&["in=http", "out=echo_full", "--admin-api"]);
+        assert!(found[0].1.starts_with("--admin-api without --api-keys"));
+        let found = findings(&["in=http", "out=dyn://a.b.c", "--lora-admin"]);
+        assert!(found[0].1.starts_with("--lora-admin without --api-keys"));
+
+        assert!(findings(&["router", "dyn://a.b.c", "--router-mode", "kv"]).is_empty());
+        assert!(findings(&[
+            "router",
+            "dyn://a.b.c",
+            "--router-mode",
+            "kv",
+            "--kv-block-size",
+            "64"
+        ])
+        .is_empty());
+        let found = findings(&["router", "dyn://a.b.c", "--kv-block-size", "64"]);
+        assert_eq!(
+            found[0].1,
+            "--kv-block-size only applies with --router-mode kv"
+        );
+    }
+}
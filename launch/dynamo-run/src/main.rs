@@ -17,8 +17,23 @@ use std::env;
 
 use clap::Parser;
 
+use dynamo_llm::engines::replay::RECORD_ENV;
+use dynamo_llm::hub::REVISION_ENV;
+use dynamo_llm::model_source::peer::PEERS_ENV;
+use dynamo_llm::preprocessor::media::IMAGE_FETCH_ENV;
+use dynamo_llm::preprocessor::truncation::{CONTEXT_OVERFLOW_POLICY_ENV, TRUNCATION_RETRY_ENV};
+use dynamo_llm::preprocessor::PRINT_PROMPT_ENV;
+use dynamo_llm::protocols::common::sampling::{
+    BANNED_WORDS_ENV, DEFAULT_SEED_ENV, OUT_OF_RANGE_ENV,
+};
+use dynamo_llm::tokenizers::pool::{TOKENIZER_QUEUE_ENV, TOKENIZER_THREADS_ENV};
+use dynamo_run::config::Merged;
 use dynamo_run::{Input, Output};
-use dynamo_runtime::logging;
+use dynamo_runtime::config::{self, ConfigSetting, ConfigSource, WorkerConfig};
+use dynamo_runtime::pipeline::network::codec::PAYLOAD_ENCODING_ENV;
+use dynamo_runtime::transports::etcd::LEASE_TTL_ENV;
+use dynamo_runtime::transports::tls;
+use dynamo_runtime::{logging, RuntimeConfig};
 
 const HELP: &str = r#"
 dynamo-run is a single binary that wires together the various inputs (http, text, network) and workers (network, engine), that runs the services. It is the simplest way to use dynamo locally.
@@ -28,13 +43,41 @@ Example:
 - cd target/debug
 - ./dynamo-run Qwen/Qwen2.5-3B-Instruct
 - OR: ./dynamo-run /data/models/Llama-3.2-1B-Instruct-Q4_K_M.gguf
+
+Generate a client for the HTTP API of this build:
+- ./dynamo-run gen-client python|typescript --out <dir>
+
+Run with the arguments of a JSON, TOML or YAML file, the command line overrides it:
+- ./dynamo-run --config <dynamo.toml> [flags]
+
+Check a configuration without running it:
+- ./dynamo-run lint --config <file> [--deny-warnings]
+
+Report the GPUs, engines, etcd and NATS, and RDMA devices of this machine:
+- ./dynamo-run probe [--json]
+
+Check that a run could start, model and engine included, without loading the model:
+- ./dynamo-run in=http out=vllm <model> --dry-run
+
+Run a gateway, the HTTP frontend and router without a local engine:
+- ./dynamo-run router [dyn://<namespace.component.endpoint>] [--router-mode kv]
 "#;
 
-const USAGE: &str = "USAGE: dynamo-run in=[http|text|dyn://<path>|batch:<folder>] out=ENGINE_LIST|dyn://<path> [--http-port 8080] [--model-path <path>] [--model-name <served-model-name>] [--model-config <hf-repo>] [--tensor-parallel-size=1] [--num-nodes=1] [--node-rank=0] [--leader-addr=127.0.0.1:9876] [--base-gpu-id=0] [--extra-engine-args=args.json] [--router-mode random|round-robin]";
+const USAGE: &str = "USAGE: dynamo-run in=[http|text|dyn://<path>|batch:<folder>|arena:<engine>|bench:<engine>|kafka:<brokers>/<topic>|redis:<url>/<key>|watch:<dir>|mcp|mcp:sse] out=ENGINE_LIST|dyn://<path> [--http-port 8080] [--model-path <path>] [--model-name <served-model-name>] [--model-config <hf-repo>] [--tensor-parallel-size=1] [--num-nodes=1] [--node-rank=0] [--leader-addr=127.0.0.1:9876] [--base-gpu-id=0] [--extra-engine-args=args.json] [--router-mode random|round-robin|least-outstanding|power-of-two|kv]";
 
 fn main() -> anyhow::Result<()> {
-    // Set log level based on verbosity flag
-    let log_level = match dynamo_run::Flags::try_parse() {
+    // Before any in= is parsed
+    dynamo_run::plugin::register_features()?;
+    let args = cli_args()?.args;
+    // Set log level based on verbosity flag. in= and out= are not flags.
+    let flag_args = args
+        .iter()
+        .enumerate()
+        .filter(|(i, arg)| *i >= 2 || !(arg.starts_with("in=") || arg.starts_with("out=")))
+        .map(|(_, arg)| arg.clone());
+    let parsed_flags =
+        dynamo_run::Flags::try_parse_from(["dynamo-run".to_string()].into_iter().chain(flag_args));
+    let log_level = match &parsed_flags {
         Ok(flags) => match flags.verbosity {
             0 => "info",
             1 => "debug",
@@ -52,6 +95,82 @@ fn main() -> anyhow::Result<()> {
         std::env::set_var("DYN_LOG", log_level);
     }
 
+    let parsed_flags = parsed_flags.ok();
+
+    // Logging sets up the trace exporter
+    if let Some(endpoint) = parsed_flags.as_ref().and_then(|f| f.otlp_endpoint.as_ref()) {
+        std::env::set_var("OTEL_EXPORTER_OTLP_ENDPOINT", endpoint);
+    }
+
+    // Read by every pre-processor, including those made for discovered models
+    if let Some(out_of_range) = parsed_flags.as_ref().and_then(|f| f.sampling_out_of_range) {
+        std::env::set_var(OUT_OF_RANGE_ENV, out_of_range.to_string());
+    }
+    if let Some(path) = parsed_flags.as_ref().and_then(|f| f.banned_words.as_ref()) {
+        std::env::set_var(BANNED_WORDS_ENV, path);
+    }
+    if let Some(seed) = parsed_flags.as_ref().and_then(|f| f.seed) {
+        std::env::set_var(DEFAULT_SEED_ENV, seed.to_string());
+    }
+    if let Some(policy) = parsed_flags
+        .as_ref()
+        .and_then(|f| f.context_overflow_policy)
+    {
+        std::env::set_var(CONTEXT_OVERFLOW_POLICY_ENV, policy.to_string());
+    }
+    if let Some(fetch) = parsed_flags.as_ref().and_then(|f| f.image_fetch) {
+        std::env::set_var(IMAGE_FETCH_ENV, fetch.to_string());
+    }
+    if parsed_flags.as_ref().is_some_and(|f| f.truncation_retry) {
+        std::env::set_var(TRUNCATION_RETRY_ENV, "1");
+    }
+    if parsed_flags.as_ref().is_some_and(|f| f.print_prompt) {
+        std::env::set_var(PRINT_PROMPT_ENV, "1");
+    }
+    if let Some(threads) = parsed_flags.as_ref().and_then(|f| f.tokenizer_threads) {
+        std::env::set_var(TOKENIZER_THREADS_ENV, threads.to_string());
+    }
+    if let Some(queue) = parsed_flags.as_ref().and_then(|f| f.tokenizer_queue) {
+        std::env::set_var(TOKENIZER_QUEUE_ENV, queue.to_string());
+    }
+    // Read by every backend
+    if let Some(path) = parsed_flags.as_ref().and_then(|f| f.record.as_ref()) {
+        std::env::set_var(RECORD_ENV, path);
+    }
+
+    // Read when downloading a model
+    if let Some(revision) = parsed_flags.as_ref().and_then(|f| f.revision.as_ref()) {
+        std::env::set_var(REVISION_ENV, revision);
+    }
+    if parsed_flags.as_ref().is_some_and(|f| f.weights_from_peers) {
+        std::env::set_var(PEERS_ENV, "1");
+    }
+
+    // Read when connecting to etcd
+    if let Some(ttl) = parsed_flags.as_ref().and_then(|f| f.lease_ttl) {
+        std::env::set_var(LEASE_TTL_ENV, ttl.to_string());
+    }
+    // Read when sending a request to a worker
+    if let Some(encoding) = parsed_flags.as_ref().and_then(|f| f.payload_encoding) {
+        std::env::set_var(PAYLOAD_ENCODING_ENV, encoding.to_string());
+    }
+    // Read when connecting to another node, and to NATS
+    if let Some(flags) = parsed_flags.as_ref() {
+        let tls_paths = [
+            (tls::CERT_ENV, &flags.transport_tls_cert),
+            (tls::KEY_ENV, &flags.transport_tls_key),
+            (tls::CA_ENV, &flags.transport_tls_ca),
+            ("NATS_TLS_CA", &flags.nats_tls_ca),
+            ("NATS_TLS_CERT", &flags.nats_tls_cert),
+            ("NATS_TLS_KEY", &flags.nats_tls_key),
+        ];
+        for (name, path) in tls_paths {
+            if let Some(path) = path {
+                std::env::set_var(name, path);
+            }
+        }
+    }
+
     logging::init();
 
     // max_worker_threads and max_blocking_threads from env vars or config file.
@@ -63,10 +182,26 @@ fn main() -> anyhow::Result<()> {
     worker.execute(wrapper)
 }
 
+/// The arguments without the binary name, `router ...` expanded to what it stands for and the
+/// `--config` file merged in
+fn cli_args() -> anyhow::Result<Merged> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("router") => dynamo_run::config::merge(dynamo_run::router::expand(&args)?),
+        // Their own flags
+        Some("gen-client" | "lint" | "probe") => Ok(Merged { args, file: None }),
+        _ => dynamo_run::config::merge(args),
+    }
+}
+
 async fn wrapper(runtime: dynamo_runtime::Runtime) -> anyhow::Result<()> {
     let mut in_opt = None;
     let mut out_opt = None;
-    let args: Vec<String> = env::args().skip(1).collect();
+    let is_router = env::args().nth(1).is_some_and(|arg| arg == "router");
+    let Merged {
+        args,
+        file: config_file,
+    } = cli_args()?;
     if args.is_empty()
         || args[0] == "-h"
         || args[0] == "--help"
@@ -78,7 +213,27 @@ async fn wrapper(runtime: dynamo_runtime::Runtime) -> anyhow::Result<()> {
         println!("{HELP}");
         return Ok(());
     }
-    for arg in env::args().skip(1).take(2) {
+    if args[0] == "gen-client" {
+        return dynamo_run::gen_client::run(&args);
+    }
+    if args[0] == "lint" {
+        return dynamo_run::lint::run(&args);
+    }
+    if args[0] == "probe" {
+        return dynamo_run::probe::run(runtime, &args).await;
+    }
+    // Before in= and out= are parsed, so an engine this build lacks is reported like the rest
+    if args
+        .iter()
+        .take_while(|arg| *arg != "--")
+        .any(|arg| arg == "--dry-run")
+    {
+        return dynamo_run::dry_run::run(runtime, &args).await;
+    }
+    if is_router {
+        dynamo_run::router::check(&args)?;
+    }
+    for arg in args.iter().take(2) {
         let Some((in_out, val)) = arg.split_once('=') else {
             // Probably we're defaulting in and/or out, and this is a flag
             continue;
@@ -121,12 +276,39 @@ async fn wrapper(runtime: dynamo_runtime::Runtime) -> anyhow::Result<()> {
 
     // Clap skips the first argument expecting it to be the binary name, so add it back
     // Note `--model-path` has index=1 (in lib.rs) so that doesn't need a flag.
-    let flags = dynamo_run::Flags::try_parse_from(
+    let (flags, cli_settings) = dynamo_run::Flags::parse_with_settings(
         ["dynamo-run".to_string()]
             .into_iter()
-            .chain(env::args().skip(non_flag_params)),
+            .chain(args.iter().skip(non_flag_params - 1).cloned()),
     )?;
 
+    // What this process is actually running with: in/out, then flags, then env and files
+    let mut settings: Vec<ConfigSetting> = args
+        .iter()
+        .take(non_flag_params - 1)
+        .filter_map(|arg| {
+            let (key, value) = arg.split_once('=')?;
+            Some(ConfigSetting {
+                key: key.to_string(),
+                value: value.to_string(),
+                source: ConfigSource::Cli,
+                origin: None,
+            })
+        })
+        .collect();
+    settings.extend(cli_settings);
+    if let Some((path, from_file)) = &config_file {
+        for setting in &mut settings {
+            if from_file.contains(&setting.key) {
+                setting.source = ConfigSource::File;
+                setting.origin = Some(path.display().to_string());
+            }
+        }
+    }
+    settings.extend(RuntimeConfig::non_default_settings());
+    settings.extend(WorkerConfig::non_default_settings());
+    config::log_settings("dynamo-run", &settings);
+
     dynamo_run::run(runtime, in_opt, out_opt, flags).await
 }
 
@@ -0,0 +1,680 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Several models in one dynamo-run, each loaded on its first request
+//!
+//! With more than one `--model-path`, `in=http` serves every model under its own name and the
+//! `model` of a request picks one. The first model loads at startup, the others when a
+//! request first asks for them. With `--vram-budget-gb`, loading a model first unloads the
+//! least recently used models that have no request running, until the weights of the loaded
+//! models fit the budget. A model's size is that of its weight files, which is less than the
+//! engine needs with its KV cache, so leave headroom.
+//!
+//! `--model-name`, `--model-config`, `--tokenizer-path` and `--chat-template` apply to the
+//! first model. The others are named after their path.
+//!
+//! With `--model-admin` the pool serves even one model, and the HTTP service's
+//! `/admin/models` routes load more through [`ModelPool::loader`]. Loading a model under the
+//! name of one already served swaps it: the new weights load and warm up next to the old
+//! ones, then new requests go to them. Each response holds on to the engines it started on,
+//! so requests already running finish on the old weights, which are freed after the last.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use anyhow::Context as _;
+use async_trait::async_trait;
+use dynamo_llm::engines::StreamingEngineAdapter;
+use dynamo_llm::http::service::model_admin::{LoadedModel, ModelLoader};
+use dynamo_llm::model_card::ModelDeploymentCard;
+use dynamo_llm::protocols::error::{ErrorKind, RequestError};
+use dynamo_llm::types::openai::chat_completions::{
+    NvCreateChatCompletionRequest, NvCreateChatCompletionStreamResponse,
+    OpenAIChatCompletionsStreamingEngine,
+};
+use dynamo_llm::types::openai::completions::{
+    CompletionRequest, CompletionResponse, OpenAICompletionsStreamingEngine,
+};
+use dynamo_llm::LocalModel;
+use dynamo_runtime::engine::{AsyncEngine, AsyncEngineContextProvider, Data, ResponseStream};
+use dynamo_runtime::pipeline::{Error, ManyOut, ServerStreamingEngine, SingleIn};
+use dynamo_runtime::CancellationToken;
+use futures::StreamExt;
+
+use crate::input::common;
+use crate::{EngineConfig, Flags, Output};
+
+const BYTES_PER_GB: f64 = 1_000_000_000.0;
+
+/// Files that hold weights, to size a model
+const WEIGHT_EXTENSIONS: &[&str] = &["safetensors", "gguf", "bin", "pt", "pth"];
+
+pub struct ModelPool {
+    out_opt: Output,
+    /// Models loaded through the admin routes take these, less the first model's own
+    flags: Flags,
+    cancel_token: CancellationToken,
+    budget: Option<u64>,
+    /// The `--model-path` models, in the order of the flags
+    names: Vec<String>,
+    models: Mutex<HashMap<String, PoolModel>>,
+    /// One model loads at a time, so that two loads don't both count on the same free memory
+    loading: tokio::sync::Mutex<()>,
+}
+
+struct PoolModel {
+    /// The flags to load this model with
+    flags: Flags,
+    card: ModelDeploymentCard,
+    /// Of the weights, in bytes
+    size: u64,
+    loaded: Option<Loaded>,
+    last_used: Instant,
+}
+
+#[derive(Clone)]
+struct Loaded {
+    chat: OpenAIChatCompletionsStreamingEngine,
+    completions: OpenAICompletionsStreamingEngine,
+    /// Requests streaming from the model, it is only unloaded at 0
+    running: Arc<AtomicUsize>,
+}
+
+impl ModelPool {
+    /// Find every model of `flags` and load the first one
+    pub async fn new(
+        out_opt: Output,
+        flags: &Flags,
+        cancel_token: CancellationToken,
+    ) -> anyhow::Result<Arc<Self>> {
+        let mut names = vec![];
+        let mut models = HashMap::new();
+        for (index, path) in flags.model_paths().into_iter().enumerate() {
+            let mut model_flags = flags.clone();
+            model_flags.model_path_pos = Some(path.clone());
+            model_flags.model_path_flag = vec![];
+            if index > 0 {
+                model_flags.model_name = None;
+                model_flags.model_config = None;
+                model_flags.tokenizer_path = None;
+                model_flags.chat_template = None;
+            }
+            let (name, model) = prepare(&out_opt, model_flags).await?;
+            if models.contains_key(&name) {
+                anyhow::bail!("Two --model-path are both served as '{name}'");
+            }
+            tracing::info!(
+                model = name,
+                path = %path.display(),
+                size_gb = model.size as f64 / BYTES_PER_GB,
+                "Serving model"
+            );
+            names.push(name.clone());
+            models.insert(name, model);
+        }
+        if names.is_empty() {
+            anyhow::bail!("No model to serve, pass --model-path");
+        }
+
+        let pool = Arc::new(ModelPool {
+            out_opt,
+            flags: flags.clone(),
+            cancel_token,
+            budget: flags
+                .vram_budget_gb
+                .map(|budget| (budget * BYTES_PER_GB) as u64),
+            names,
+            models: Mutex::new(models),
+            loading: tokio::sync::Mutex::new(()),
+        });
+        pool.get(&pool.names[0]).await?;
+        Ok(pool)
+    }
+
+    /// The cards of the `--model-path` models still served, the first one first
+    pub fn cards(&self) -> Vec<ModelDeploymentCard> {
+        let models = self.models.lock().unwrap();
+        self.names
+            .iter()
+            .filter_map(|name| Some(models.get(name)?.card.clone()))
+            .collect()
+    }
+
+    pub fn first_card(&self) -> ModelDeploymentCard {
+        self.models.lock().unwrap()[&self.names[0]].card.clone()
+    }
+
+    pub fn chat_engine(self: &Arc<Self>, model: &str) -> OpenAIChatCompletionsStreamingEngine {
+        Arc::new(PooledEngine {
+            pool: self.clone(),
+            model: model.to_string(),
+            select: |loaded| loaded.chat.clone(),
+        })
+    }
+
+    pub fn completions_engine(self: &Arc<Self>, model: &str) -> OpenAICompletionsStreamingEngine {
+        Arc::new(PooledEngine {
+            pool: self.clone(),
+            model: model.to_string(),
+            select: |loaded| loaded.completions.clone(),
+        })
+    }
+
+    /// For the HTTP service's `/admin/models` routes
+    pub fn loader(self: &Arc<Self>) -> Arc<dyn ModelLoader> {
+        Arc::new(PoolLoader(self.clone()))
+    }
+
+    /// The engines of `model`, loading it if it isn't
+    async fn get(&self, model: &str) -> anyhow::Result<Loaded> {
+        if let Some(loaded) = self.touch(model)? {
+            return Ok(loaded);
+        }
+        let _loading = self.loading.lock().await;
+        // Another request may have loaded it while we waited
+        if let Some(loaded) = self.touch(model)? {
+            return Ok(loaded);
+        }
+        let (flags, size) = {
+            let models = self.models.lock().unwrap();
+            let Some(entry) = models.get(model) else {
+                anyhow::bail!("Model {model} is not served");
+            };
+            (entry.flags.clone(), entry.size)
+        };
+        self.make_room(model, size)?;
+        let loaded = self.start(model, &flags).await?;
+
+        let mut models = self.models.lock().unwrap();
+        let Some(entry) = models.get_mut(model) else {
+            anyhow::bail!("Model {model} was unloaded while it loaded");
+        };
+        entry.loaded = Some(loaded.clone());
+        entry.last_used = Instant::now();
+        Ok(loaded)
+    }
+
+    /// Load the model at `path` as `name`, in place of the model of that name if there is one
+    async fn load(self: &Arc<Self>, path: &str, name: Option<&str>) -> anyhow::Result<LoadedModel> {
+        let mut flags = self.flags.clone();
+        flags.model_path_pos = Some(PathBuf::from(path));
+        flags.model_path_flag = vec![];
+        flags.model_name = name.map(str::to_string);
+        flags.model_config = None;
+        flags.tokenizer_path = None;
+        flags.chat_template = None;
+        let (name, mut model) = prepare(&self.out_opt, flags).await?;
+
+        let _loading = self.loading.lock().await;
+        self.make_room(&name, model.size)?;
+        let loaded = self.start(&name, &model.flags).await?;
+        model.loaded = Some(loaded);
+        let card = model.card.clone();
+        self.install(&name, model);
+        Ok(LoadedModel {
+            card,
+            chat: self.chat_engine(&name),
+            completions: self.completions_engine(&name),
+        })
+    }
+
+    /// Serve `model` as `name`, in place of the model of that name if there is one. New
+    /// requests go to it, those running keep the engines they started on.
+    fn install(&self, name: &str, model: PoolModel) {
+        let old = self.models.lock().unwrap().insert(name.to_string(), model);
+        if let Some(old) = old.and_then(|old| old.loaded) {
+            tracing::info!(
+                model = name,
+                running = old.running.load(Ordering::Relaxed),
+                "Swapped in new weights, running requests finish on the old ones"
+            );
+        }
+    }
+
+    /// Stop loading `model` for requests. Those running keep its engines until they finish.
+    fn unload(&self, model: &str) -> anyhow::Result<()> {
+        if self.models.lock().unwrap().remove(model).is_none() {
+            return Err(RequestError::new(
+                ErrorKind::NotFound,
+                format!("Model {model} is not served"),
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Start an engine for `model` and warm it up. The caller holds `loading`.
+    async fn start(&self, model: &str, flags: &Flags) -> anyhow::Result<Loaded> {
+        tracing::info!(model, "Loading model");
+        let start = Instant::now();
+        let (engine_config, _card, _extra) =
+            crate::make_engine(self.out_opt.clone(), flags, self.cancel_token.clone()).await?;
+        crate::warmup::run(&engine_config, &flags.warmup).await;
+        let loaded = Loaded::new(engine_config).await?;
+        tracing::info!(
+            model,
+            elapsed_ms = start.elapsed().as_millis(),
+            "Loaded model"
+        );
+        Ok(loaded)
+    }
+
+    /// The engines of `model` if it is loaded, marking it used
+    fn touch(&self, model: &str) -> anyhow::Result<Option<Loaded>> {
+        let mut models = self.models.lock().unwrap();
+        let Some(entry) = models.get_mut(model) else {
+            anyhow::bail!("Model {model} is not served");
+        };
+        entry.last_used = Instant::now();
+        Ok(entry.loaded.clone())
+    }
+
+    /// Unload models until `model`, of `size` bytes, fits the budget
+    fn make_room(&self, model: &str, size: u64) -> anyhow::Result<()> {
+        let mut models = self.models.lock().unwrap();
+        if let Some(budget) = self.budget {
+            let candidates: Vec<Candidate> = models
+                .iter()
+                .filter(|(_, entry)| entry.loaded.is_some())
+                .map(|(name, entry)| Candidate {
+                    name: name.clone(),
+                    size: entry.size,
+                    last_used: entry.last_used,
+                    idle: entry
+                        .loaded
+                        .as_ref()
+                        .is_some_and(|loaded| loaded.running.load(Ordering::Relaxed) == 0),
+                })
+                .collect();
+            let Some(evict) = evictions(candidates, size, budget) else {
+                return Err(RequestError::new(
+                    ErrorKind::Unavailable,
+                    format!(
+                        "Model {model} doesn't fit in the GPU memory budget next to the models \
+                         serving requests, try again later"
+                    ),
+                )
+                .into());
+            };
+            for name in evict {
+                tracing::info!(model = name, "Unloading model to make room for {model}");
+                models.get_mut(&name).unwrap().loaded = None;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Loaded {
+    async fn new(engine_config: EngineConfig) -> anyhow::Result<Self> {
+        let (chat, completions) = match engine_config {
+            EngineConfig::StaticFull { engine, .. } => {
+                let engine = Arc::new(StreamingEngineAdapter::new(engine));
+                (
+                    engine.clone() as OpenAIChatCompletionsStreamingEngine,
+                    engine as OpenAICompletionsStreamingEngine,
+                )
+            }
+            EngineConfig::StaticCore { engine, model } => (
+                common::build_pipeline::<
+                    NvCreateChatCompletionRequest,
+                    NvCreateChatCompletionStreamResponse,
+                >(model.card(), engine.clone())
+                .await? as OpenAIChatCompletionsStreamingEngine,
+                common::build_pipeline::<CompletionRequest, CompletionResponse>(
+                    model.card(),
+                    engine,
+                )
+                .await? as OpenAICompletionsStreamingEngine,
+            ),
+            EngineConfig::Dynamic(_) | EngineConfig::Pool(_) | EngineConfig::Multi(_) => {
+                anyhow::bail!("Only engines running in dynamo-run can serve several models");
+            }
+        };
+        Ok(Loaded {
+            chat,
+            completions,
+            running: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+}
+
+/// Find the model of `flags`, to be loaded when it is first used
+async fn prepare(out_opt: &Output, flags: Flags) -> anyhow::Result<(String, PoolModel)> {
+    let Some(path) = &flags.model_path_pos else {
+        anyhow::bail!("No model path");
+    };
+    let mut local_model = LocalModel::prepare(
+        path.to_str().context("Invalid UTF-8 in model path")?,
+        flags.model_config.as_deref(),
+        flags.model_name.clone(),
+        flags.tokenizer_path.as_deref(),
+    )
+    .await?;
+    if let Some(chat_template) = &flags.chat_template {
+        local_model.set_chat_template(chat_template)?;
+    }
+    if let Some(engine) = out_opt.card_engine() {
+        local_model.set_engine(engine);
+    }
+    let name = local_model.service_name().to_string();
+    let model = PoolModel {
+        size: weights_size(local_model.path()),
+        card: local_model.card().clone(),
+        flags,
+        loaded: None,
+        last_used: Instant::now(),
+    };
+    Ok((name, model))
+}
+
+/// A loaded model, as [`evictions`] sees it
+struct Candidate {
+    name: String,
+    size: u64,
+    last_used: Instant,
+    idle: bool,
+}
+
+/// The models to unload, least recently used first, so that one of `size` bytes fits in
+/// `budget` next to the others. None if unloading every idle model isn't enough.
+fn evictions(mut loaded: Vec<Candidate>, size: u64, budget: u64) -> Option<Vec<String>> {
+    let mut used: u64 = loaded.iter().map(|model| model.size).sum();
+    loaded.sort_by_key(|model| model.last_used);
+    let mut evict = vec![];
+    for model in loaded.into_iter().filter(|model| model.idle) {
+        if used + size <= budget {
+            break;
+        }
+        used -= model.size;
+        evict.push(model.name);
+    }
+    (used + size <= budget).then_some(evict)
+}
+
+/// Bytes of the weight files of a model, a file or a directory
+fn weights_size(path: &Path) -> u64 {
+    if path.is_file() {
+        return path.metadata().map(|m| m.len()).unwrap_or_default();
+    }
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|ext| WEIGHT_EXTENSIONS.iter().any(|w| ext == *w))
+        })
+        .filter_map(|path: PathBuf| path.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// The engine the HTTP service has for a model of the pool. It loads the model if it must,
+/// and counts the requests running on it.
+struct PooledEngine<Req, Resp> {
+    pool: Arc<ModelPool>,
+    model: String,
+    select: fn(&Loaded) -> ServerStreamingEngine<Req, Resp>,
+}
+
+/// Holds a request on a model while its response streams
+struct Running(Arc<AtomicUsize>);
+
+impl Running {
+    fn start(running: Arc<AtomicUsize>) -> Self {
+        running.fetch_add(1, Ordering::Relaxed);
+        Running(running)
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[async_trait]
+impl<Req: Data, Resp: Data> AsyncEngine<SingleIn<Req>, ManyOut<Resp>, Error>
+    for PooledEngine<Req, Resp>
+{
+    async fn generate(&self, request: SingleIn<Req>) -> Result<ManyOut<Resp>, Error> {
+        let loaded = self.pool.get(&self.model).await?;
+        let running = Running::start(loaded.running.clone());
+        let stream = (self.select)(&loaded).generate(request).await?;
+        let context = stream.context();
+        // Unloading or swapping the model leaves this response its engines
+        let stream = stream.inspect(move |_| {
+            let _ = (&running, &loaded);
+        });
+        Ok(ResponseStream::new(Box::pin(stream), context))
+    }
+}
+
+/// The pool as the HTTP service sees it
+struct PoolLoader(Arc<ModelPool>);
+
+#[async_trait]
+impl ModelLoader for PoolLoader {
+    async fn load(&self, path: &str, name: Option<&str>) -> anyhow::Result<LoadedModel> {
+        self.0.load(path, name).await
+    }
+
+    async fn unload(&self, name: &str) -> anyhow::Result<()> {
+        self.0.unload(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser as _;
+    use dynamo_llm::engines::make_engine_full;
+    use dynamo_llm::protocols::Annotated;
+    use dynamo_runtime::pipeline::Context;
+    use std::time::Duration;
+
+    /// Answers every chat request with its tag
+    struct TagEngine(&'static str);
+
+    #[async_trait]
+    impl
+        AsyncEngine<
+            SingleIn<NvCreateChatCompletionRequest>,
+            ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
+            Error,
+        > for TagEngine
+    {
+        async fn generate(
+            &self,
+            request: SingleIn<NvCreateChatCompletionRequest>,
+        ) -> Result<ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>, Error> {
+            let (request, context) = request.transfer(());
+            let inner =
+                request
+                    .response_generator()
+                    .create_choice(0, Some(self.0.to_string()), None, None);
+            let output = NvCreateChatCompletionStreamResponse { inner, nvext: None };
+            let stream = futures::stream::iter(vec![Annotated::from_data(output)]);
+            Ok(ResponseStream::new(Box::pin(stream), context.context()))
+        }
+    }
+
+    fn loaded(tag: &'static str) -> Loaded {
+        Loaded {
+            chat: Arc::new(TagEngine(tag)),
+            completions: Arc::new(StreamingEngineAdapter::new(make_engine_full())),
+            running: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    fn pool_model(name: &str, size: u64, loaded: Option<Loaded>) -> PoolModel {
+        PoolModel {
+            flags: Flags::try_parse_from(["dynamo-run"]).unwrap(),
+            card: ModelDeploymentCard::with_name_only(name),
+            size,
+            loaded,
+            last_used: Instant::now(),
+        }
+    }
+
+    fn pool(budget: Option<u64>, models: Vec<(&str, PoolModel)>) -> Arc<ModelPool> {
+        let names = models.iter().map(|(name, _)| name.to_string()).collect();
+        Arc::new(ModelPool {
+            out_opt: Output::EchoFull,
+            flags: Flags::try_parse_from(["dynamo-run"]).unwrap(),
+            cancel_token: CancellationToken::new(),
+            budget,
+            names,
+            models: Mutex::new(
+                models
+                    .into_iter()
+                    .map(|(name, model)| (name.to_string(), model))
+                    .collect(),
+            ),
+            loading: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    fn chat_request(model: &str) -> SingleIn<NvCreateChatCompletionRequest> {
+        let request = serde_json::from_value(serde_json::json!({
+            "model": model,
+            "messages": [{"role": "user", "content": "Hi"}],
+        }))
+        .unwrap();
+        Context::new(request)
+    }
+
+    /// The text of a chat response stream
+    async fn text(stream: ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>) -> String {
+        stream
+            .filter_map(|annotated| async move {
+                annotated.data?.inner.choices.first()?.delta.content.clone()
+            })
+            .collect::<Vec<_>>()
+            .await
+            .concat()
+    }
+
+    fn running(pool: &ModelPool, model: &str) -> usize {
+        pool.models.lock().unwrap()[model]
+            .loaded
+            .as_ref()
+            .unwrap()
+            .running
+            .load(Ordering::Relaxed)
+    }
+
+    #[tokio::test]
+    async fn test_swap_keeps_running_requests() {
+        let pool = pool(None, vec![("m", pool_model("m", 4, Some(loaded("old"))))]);
+        let engine = pool.chat_engine("m");
+
+        let in_flight = engine.generate(chat_request("m")).await.unwrap();
+        assert_eq!(running(&pool, "m"), 1);
+        let old_running = pool.models.lock().unwrap()["m"]
+            .loaded
+            .as_ref()
+            .unwrap()
+            .running
+            .clone();
+
+        pool.install("m", pool_model("m", 4, Some(loaded("new"))));
+        let new = engine.generate(chat_request("m")).await.unwrap();
+        assert_eq!(text(new).await, "new");
+        // Started before the swap, it finishes on the old weights
+        assert_eq!(text(in_flight).await, "old");
+        assert_eq!(old_running.load(Ordering::Relaxed), 0);
+
+        // Unloading doesn't cut running requests short either
+        let in_flight = engine.generate(chat_request("m")).await.unwrap();
+        pool.unload("m").unwrap();
+        assert_eq!(text(in_flight).await, "new");
+        assert!(engine.generate(chat_request("m")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_make_room_skips_running() {
+        let pool = pool(
+            Some(10),
+            vec![
+                ("busy", pool_model("busy", 4, Some(loaded("busy")))),
+                ("idle", pool_model("idle", 4, Some(loaded("idle")))),
+            ],
+        );
+        let in_flight = pool
+            .chat_engine("busy")
+            .generate(chat_request("busy"))
+            .await
+            .unwrap();
+        // busy is the least recently used, but it has a request running
+        let mut models = pool.models.lock().unwrap();
+        models.get_mut("busy").unwrap().last_used = Instant::now() - Duration::from_secs(60);
+        drop(models);
+
+        // Room for 7 needs both unloaded, and busy can't be
+        let err = pool.make_room("new", 7).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<RequestError>().unwrap().kind,
+            ErrorKind::Unavailable
+        );
+        assert!(pool.models.lock().unwrap()["idle"].loaded.is_some());
+
+        pool.make_room("new", 4).unwrap();
+        {
+            let models = pool.models.lock().unwrap();
+            assert!(models["busy"].loaded.is_some());
+            assert!(models["idle"].loaded.is_none());
+        }
+
+        // Once its request is done it can go too
+        assert_eq!(text(in_flight).await, "busy");
+        pool.make_room("new", 10).unwrap();
+        assert!(pool.models.lock().unwrap()["busy"].loaded.is_none());
+    }
+
+    #[test]
+    fn test_evictions() {
+        let now = Instant::now();
+        let model = |name: &str, size, age, idle| Candidate {
+            name: name.to_string(),
+            size,
+            last_used: now - Duration::from_secs(age),
+            idle,
+        };
+        let loaded = || {
+            vec![
+                model("recent", 4, 1, true),
+                model("oldest", 4, 30, true),
+                model("busy", 4, 60, false),
+            ]
+        };
+
+        // Fits without unloading anything
+        assert_eq!(evictions(loaded(), 4, 16), Some(vec![]));
+        // The least recently used idle model goes first, busy ones stay
+        assert_eq!(evictions(loaded(), 4, 12), Some(vec!["oldest".to_string()]));
+        assert_eq!(
+            evictions(loaded(), 8, 12),
+            Some(vec!["oldest".to_string(), "recent".to_string()])
+        );
+        assert_eq!(evictions(loaded(), 10, 12), None);
+    }
+}
@@ -0,0 +1,406 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `out=multi:<file>`: several engines in one dynamo-run, each serving a model
+//!
+//! The file is JSON, TOML or YAML, like a `--config` file. It lists the engines, each with the
+//! model it serves, its `out=` and the flags it takes in place of those of the command line:
+//!
+//! ```toml
+//! [[engines]]
+//! model = "qwen3-0.6b"
+//! out = "mistralrs"
+//! model-path = "/models/Qwen3-0.6B-Q8_0.gguf"
+//! fallback = "qwen3-32b"
+//! timeout-secs = 10
+//!
+//! [[engines]]
+//! model = "qwen3-32b"
+//! out = "dyn://dynamo.backend.generate"
+//! ```
+//!
+//! A request goes to the engine of its `model`, the first engine's if the input has no model
+//! of its own. When an engine with a `fallback` fails before its first response, or sends none
+//! within `timeout-secs`, the request goes to the fallback engine, and on to its fallback.
+//! Once a response has started there is no going back, an error after that ends it. Only chat
+//! completions are served.
+
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context as _;
+use async_trait::async_trait;
+use clap::Parser as _;
+use dynamo_llm::types::openai::chat_completions::{
+    NvCreateChatCompletionRequest, NvCreateChatCompletionStreamResponse,
+    OpenAIChatCompletionsStreamingEngine,
+};
+use dynamo_llm::types::Annotated;
+use dynamo_runtime::engine::{AsyncEngine, AsyncEngineContextProvider, ResponseStream};
+use dynamo_runtime::pipeline::{Error, ManyOut, SingleIn};
+use dynamo_runtime::{CancellationToken, Runtime};
+use futures::{stream, StreamExt};
+
+use crate::input::common;
+use crate::{config, Flags, Output, StopFuture};
+
+/// An engine of the file
+#[derive(Debug, Clone, PartialEq)]
+pub struct EngineSpec {
+    /// The model it serves, and the name clients ask for
+    pub model: String,
+
+    /// As `out=` takes it
+    pub out: String,
+
+    /// The model to send a request to when this engine fails it
+    pub fallback: Option<String>,
+
+    /// How long to wait for the first response before trying the fallback
+    pub timeout: Option<Duration>,
+
+    /// The flags of the engine, as on the command line
+    pub args: Vec<String>,
+}
+
+/// Read and check the engines of the file
+pub fn load(path: &Path) -> anyhow::Result<Vec<EngineSpec>> {
+    parse(config::load_value(path)?).with_context(|| path.display().to_string())
+}
+
+fn parse(value: serde_json::Value) -> anyhow::Result<Vec<EngineSpec>> {
+    let Some(serde_json::Value::Array(entries)) = value.get("engines").cloned() else {
+        anyhow::bail!("expected a list of engines under 'engines'");
+    };
+    let mut specs = vec![];
+    for (index, entry) in entries.into_iter().enumerate() {
+        let serde_json::Value::Object(entry) = entry else {
+            anyhow::bail!("engine {index}: expected an object");
+        };
+        let mut spec = EngineSpec {
+            model: String::new(),
+            out: String::new(),
+            fallback: None,
+            timeout: None,
+            args: vec![],
+        };
+        for (key, value) in entry {
+            let text = || match &value {
+                serde_json::Value::String(s) => Ok(s.clone()),
+                _ => Err(anyhow::anyhow!("engine {index}: {key} must be a string")),
+            };
+            match key.trim_start_matches('-').replace('_', "-").as_str() {
+                "model" => spec.model = text()?,
+                "out" => spec.out = text()?,
+                "fallback" => spec.fallback = Some(text()?),
+                "timeout-secs" => {
+                    let secs = value.as_f64().filter(|secs| *secs > 0.0).with_context(|| {
+                        format!("engine {index}: timeout-secs must be a positive number")
+                    })?;
+                    spec.timeout = Some(Duration::from_secs_f64(secs));
+                }
+                _ => config::push_flag(&mut spec.args, &key, value)?,
+            }
+        }
+        if spec.model.is_empty() {
+            anyhow::bail!("engine {index} has no model");
+        }
+        if spec.out.is_empty() {
+            anyhow::bail!("engine {} has no out", spec.model);
+        }
+        specs.push(spec);
+    }
+    check(&specs)?;
+    Ok(specs)
+}
+
+fn check(specs: &[EngineSpec]) -> anyhow::Result<()> {
+    if specs.is_empty() {
+        anyhow::bail!("no engines");
+    }
+    let mut models = HashSet::new();
+    let mut subprocess = None;
+    for spec in specs {
+        if !models.insert(spec.model.as_str()) {
+            anyhow::bail!("two engines serve {}", spec.model);
+        }
+        let out = Output::try_from(spec.out.as_str())
+            .with_context(|| format!("engine of {}", spec.model))?;
+        if matches!(out, Output::Multi(_)) {
+            anyhow::bail!("engine of {}: out=multi cannot be nested", spec.model);
+        }
+        if out.is_subprocess() {
+            if let Some(other) = subprocess.replace(&spec.model) {
+                anyhow::bail!(
+                    "the engines of {other} and {} both run in a sub-process, only one of vllm \
+                     and sglang can",
+                    spec.model
+                );
+            }
+        }
+    }
+    for spec in specs {
+        if let Some(fallback) = &spec.fallback {
+            if !models.contains(fallback.as_str()) {
+                anyhow::bail!(
+                    "{} falls back to {fallback}, which no engine serves",
+                    spec.model
+                );
+            }
+            if fallback == &spec.model {
+                anyhow::bail!("{} cannot fall back to itself", spec.model);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The engines of `model` and of its fallbacks, in the order to try them. Stops before an
+/// engine already in the list, so two models can fall back to each other.
+fn chain<'a>(specs: &'a [EngineSpec], model: &str) -> Vec<&'a EngineSpec> {
+    let mut chain: Vec<&EngineSpec> = vec![];
+    let mut next = Some(model);
+    while let Some(model) = next {
+        let Some(spec) = specs.iter().find(|spec| spec.model == model) else {
+            break;
+        };
+        if chain.iter().any(|seen| seen.model == spec.model) {
+            break;
+        }
+        chain.push(spec);
+        next = spec.fallback.as_deref();
+    }
+    chain
+}
+
+pub struct MultiEngine {
+    /// In the order of the file
+    specs: Vec<EngineSpec>,
+    /// Of each spec
+    engines: Vec<OpenAIChatCompletionsStreamingEngine>,
+    /// Hold the tokenizers of remote models
+    _cache_dirs: Vec<tempfile::TempDir>,
+}
+
+impl MultiEngine {
+    /// Start the engines of the file at `path`, one after the other. Also returns the futures
+    /// that stop the sub-process of vllm or sglang once `cancel_token` is cancelled.
+    pub async fn start(
+        runtime: &Runtime,
+        path: &Path,
+        flags: &Flags,
+        cancel_token: CancellationToken,
+    ) -> anyhow::Result<(Arc<Self>, Vec<StopFuture>)> {
+        let specs = load(path)?;
+        let mut engines = vec![];
+        let mut cache_dirs = vec![];
+        let mut stops = vec![];
+        for spec in &specs {
+            let out = Output::try_from(spec.out.as_str())?;
+            let engine_flags = engine_flags(flags, spec)?;
+            tracing::info!(model = spec.model, out = spec.out, "Starting engine");
+            let (engine_config, _card, extra) =
+                crate::make_engine(out, &engine_flags, cancel_token.clone())
+                    .await
+                    .with_context(|| format!("Engine of {}", spec.model))?;
+            crate::warmup::run(&engine_config, &engine_flags.warmup).await;
+            let prepared =
+                common::prepare_engine(runtime.clone(), engine_flags, engine_config).await?;
+            engines.push(prepared.engine);
+            cache_dirs.extend(prepared._cache_dir);
+            stops.extend(extra);
+        }
+        let multi = MultiEngine {
+            specs,
+            engines,
+            _cache_dirs: cache_dirs,
+        };
+        Ok((Arc::new(multi), stops))
+    }
+
+    /// The models served, the first engine's first
+    pub fn models(&self) -> impl Iterator<Item = &str> {
+        self.specs.iter().map(|spec| spec.model.as_str())
+    }
+
+    /// For inputs that don't say which model they want
+    pub fn default_model(&self) -> &str {
+        &self.specs[0].model
+    }
+
+    /// The engine for requests to `model`, with its fallbacks. Without a model, each request
+    /// goes to the engine of the model it asks for.
+    pub fn chat_engine(
+        self: &Arc<Self>,
+        model: Option<&str>,
+    ) -> OpenAIChatCompletionsStreamingEngine {
+        Arc::new(RoutedEngine {
+            multi: self.clone(),
+            model: model.map(str::to_string),
+        })
+    }
+
+    fn engine(&self, spec: &EngineSpec) -> &OpenAIChatCompletionsStreamingEngine {
+        let index = self
+            .specs
+            .iter()
+            .position(|s| s.model == spec.model)
+            .expect("spec of this engine");
+        &self.engines[index]
+    }
+
+    /// The stream of `spec`'s engine, once its first response came back without an error
+    async fn first_response(
+        &self,
+        spec: &EngineSpec,
+        request: SingleIn<NvCreateChatCompletionRequest>,
+    ) -> anyhow::Result<ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>> {
+        let attempt = async {
+            let mut stream = self.engine(spec).generate(request).await?;
+            let context = stream.context();
+            match stream.next().await {
+                Some(first) if first.is_error() => {
+                    anyhow::bail!(first.comment.unwrap_or_default().join(", "))
+                }
+                Some(first) => {
+                    let stream = stream::iter(Some(first)).chain(stream);
+                    Ok(ResponseStream::new(Box::pin(stream), context))
+                }
+                None => anyhow::bail!("the engine closed the response stream without responding"),
+            }
+        };
+        match spec.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, attempt)
+                .await
+                .map_err(|_| anyhow::anyhow!("no response in {}s", timeout.as_secs_f64()))?,
+            None => attempt.await,
+        }
+    }
+}
+
+/// The flags of the command line, with those of `spec` in place of them. The model comes
+/// from the spec alone.
+fn engine_flags(flags: &Flags, spec: &EngineSpec) -> anyhow::Result<Flags> {
+    let mut engine_flags = flags.clone();
+    engine_flags.model_path_pos = None;
+    engine_flags.model_path_flag = vec![];
+    engine_flags.model_config = None;
+    engine_flags.tokenizer_path = None;
+    engine_flags.chat_template = None;
+    engine_flags
+        .try_update_from(["dynamo-run".to_string()].iter().chain(&spec.args))
+        .with_context(|| format!("Flags of the engine of {}", spec.model))?;
+    engine_flags.model_name = Some(spec.model.clone());
+    Ok(engine_flags)
+}
+
+/// Sends each request to the engine of its model, then to the fallbacks
+struct RoutedEngine {
+    multi: Arc<MultiEngine>,
+    /// The model of every request, for a service that has an engine per model
+    model: Option<String>,
+}
+
+#[async_trait]
+impl
+    AsyncEngine<
+        SingleIn<NvCreateChatCompletionRequest>,
+        ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
+        Error,
+    > for RoutedEngine
+{
+    async fn generate(
+        &self,
+        request: SingleIn<NvCreateChatCompletionRequest>,
+    ) -> Result<ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>, Error> {
+        let model = self.model.as_deref().unwrap_or(&request.inner.model);
+        let chain = chain(&self.multi.specs, model);
+        let Some((last, first)) = chain.split_last() else {
+            anyhow::bail!("Model {model} is not served");
+        };
+        for (spec, next) in first.iter().zip(&chain[1..]) {
+            match self
+                .multi
+                .first_response(spec, request.fork((*request).clone()))
+                .await
+            {
+                Ok(stream) => return Ok(stream),
+                Err(err) => tracing::warn!(
+                    model = spec.model,
+                    fallback = next.model,
+                    "Engine failed, trying the fallback: {err:#}"
+                ),
+            }
+        }
+        self.multi.engine(last).generate(request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let specs = parse(serde_json::json!({"engines": [
+            {
+                "model": "small",
+                "out": "echo_full",
+                "fallback": "big",
+                "timeout-secs": 2.5,
+                "max-tokens": 64,
+                "warmup": [128, 512],
+            },
+            {"model": "big", "out": "dyn://dynamo.backend.generate", "fallback": "small"},
+        ]}))
+        .unwrap();
+        assert_eq!(specs[0].timeout, Some(Duration::from_millis(2500)));
+        assert_eq!(
+            specs[0].args,
+            ["--max-tokens", "64", "--warmup", "128", "--warmup", "512"]
+        );
+        assert!(specs[1].args.is_empty());
+
+        let models = |model| -> Vec<&str> {
+            chain(&specs, model)
+                .iter()
+                .map(|spec| spec.model.as_str())
+                .collect()
+        };
+        // Falling back to each other tries each once
+        assert_eq!(models("small"), ["small", "big"]);
+        assert_eq!(models("big"), ["big", "small"]);
+        assert!(models("other").is_empty());
+
+        let one = |engine: serde_json::Value| parse(serde_json::json!({ "engines": [engine] }));
+        assert!(one(serde_json::json!({"model": "a"})).is_err());
+        assert!(one(serde_json::json!({"model": "a", "out": "nope"})).is_err());
+        assert!(one(serde_json::json!({"model": "a", "out": "multi:x.toml"})).is_err());
+        assert!(
+            one(serde_json::json!({"model": "a", "out": "echo_full", "fallback": "b"})).is_err()
+        );
+        assert!(
+            one(serde_json::json!({"model": "a", "out": "echo_full", "fallback": "a"})).is_err()
+        );
+        assert!(parse(serde_json::json!({"engines": [
+            {"model": "a", "out": "vllm"},
+            {"model": "b", "out": "sglang"},
+        ]}))
+        .is_err());
+        assert!(parse(serde_json::json!({"engines": []})).is_err());
+    }
+}
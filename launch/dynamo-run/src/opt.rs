@@ -17,7 +17,14 @@ use std::{fmt, io::IsTerminal as _, path::PathBuf};
 
 use dynamo_runtime::protocols::ENDPOINT_SCHEME;
 
+use crate::plugin;
+
 const BATCH_PREFIX: &str = "batch:";
+const ARENA_PREFIX: &str = "arena:";
+const BENCH_PREFIX: &str = "bench:";
+const REPLAY_PREFIX: &str = "replay:";
+
+const MULTI_PREFIX: &str = "multi:";
 
 #[derive(PartialEq)]
 pub enum Input {
@@ -35,6 +42,32 @@ pub enum Input {
 
     /// Batch mode. Run all the prompts, write the outputs, exit.
     Batch(PathBuf),
+
+    /// Interactive comparison of the out= engine with this second one, an out= value
+    Arena(String),
+
+    /// Synthetic load on the out= engine, then its throughput and latencies
+    Bench,
+
+    /// Latency and throughput of the out= engine against this second one, an out= value
+    BenchCompare(String),
+
+    /// Model Context Protocol server, for IDEs and agent tools
+    Mcp(McpTransport),
+
+    /// An input registered with [`plugin::register_input`], such as `in=watch`, `in=kafka`
+    /// and `in=redis`. `arg` is what follows `<name>:`, if anything.
+    Plugin { name: String, arg: String },
+}
+
+/// How `in=mcp` talks to its clients
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum McpTransport {
+    /// JSON-RPC messages on stdin and stdout, for a client that starts dynamo-run itself
+    Stdio,
+
+    /// HTTP with server-sent events on `--http-port`
+    Sse,
 }
 
 impl TryFrom<&str> for Input {
@@ -45,6 +78,9 @@ impl TryFrom<&str> for Input {
             "http" => Ok(Input::Http),
             "text" => Ok(Input::Text),
             "stdin" => Ok(Input::Stdin),
+            "bench" => Ok(Input::Bench),
+            "mcp" | "mcp:stdio" => Ok(Input::Mcp(McpTransport::Stdio)),
+            "mcp:sse" => Ok(Input::Mcp(McpTransport::Sse)),
             endpoint_path if endpoint_path.starts_with(ENDPOINT_SCHEME) => {
                 Ok(Input::Endpoint(endpoint_path.to_string()))
             }
@@ -52,7 +88,36 @@ impl TryFrom<&str> for Input {
                 let path = batch_patch.strip_prefix(BATCH_PREFIX).unwrap();
                 Ok(Input::Batch(PathBuf::from(path)))
             }
-            e => Err(anyhow::anyhow!("Invalid in= option '{e}'")),
+            arena if arena.starts_with(ARENA_PREFIX) => {
+                let other = arena.strip_prefix(ARENA_PREFIX).unwrap();
+                // Fail early on a bad engine name
+                Output::try_from(other)?;
+                Ok(Input::Arena(other.to_string()))
+            }
+            bench if bench.starts_with(BENCH_PREFIX) => {
+                let other = bench.strip_prefix(BENCH_PREFIX).unwrap();
+                Output::try_from(other)?;
+                Ok(Input::BenchCompare(other.to_string()))
+            }
+            other => {
+                let (name, arg) = plugin::split(other);
+                let Some(provider) = plugin::input(name) else {
+                    if (name == "kafka" && !cfg!(feature = "kafka"))
+                        || (name == "redis" && !cfg!(feature = "redis"))
+                    {
+                        anyhow::bail!(
+                            "in={name} is not available, this dynamo-run was built without the \
+                             '{name}' feature"
+                        );
+                    }
+                    anyhow::bail!("Invalid in= option '{other}'");
+                };
+                provider.parse(arg)?;
+                Ok(Input::Plugin {
+                    name: name.to_string(),
+                    arg: arg.to_string(),
+                })
+            }
         }
     }
 }
@@ -65,11 +130,32 @@ impl fmt::Display for Input {
             Input::Stdin => "stdin",
             Input::Endpoint(path) => path,
             Input::Batch(path) => &path.display().to_string(),
+            Input::Arena(other) => &format!("{ARENA_PREFIX}{other}"),
+            Input::Bench => "bench",
+            Input::BenchCompare(other) => &format!("{BENCH_PREFIX}{other}"),
+            Input::Mcp(McpTransport::Stdio) => "mcp",
+            Input::Mcp(McpTransport::Sse) => "mcp:sse",
+            Input::Plugin { name, arg } => return plugin_fmt(f, name, arg),
         };
         write!(f, "{s}")
     }
 }
 
+impl Input {
+    /// Names an input registered with [`plugin::register_input`] can't take
+    pub const BUILT_IN: &[&str] = &[
+        "http", "text", "stdin", "bench", "mcp", "dyn", "batch", "arena",
+    ];
+}
+
+fn plugin_fmt(f: &mut fmt::Formatter, name: &str, arg: &str) -> fmt::Result {
+    if arg.is_empty() {
+        write!(f, "{name}")
+    } else {
+        write!(f, "{name}:{arg}")
+    }
+}
+
 impl Default for Input {
     fn default() -> Self {
         if std::io::stdin().is_terminal() {
@@ -80,6 +166,7 @@ impl Default for Input {
     }
 }
 
+#[derive(Clone)]
 pub enum Output {
     /// Accept un-preprocessed requests, echo the prompt back as the response
     EchoFull,
@@ -87,9 +174,20 @@ pub enum Output {
     /// Accept preprocessed requests, echo the tokens back as the response
     EchoCore,
 
+    /// Accept preprocessed requests, answer with lorem ipsum at the pace of `--mock-profile`
+    Mock,
+
+    /// Accept preprocessed requests, answer with what a trace recorded with `--record` has for
+    /// the prompt
+    Replay(PathBuf),
+
     /// Publish requests to a namespace/component/endpoint path.
     Endpoint(String),
 
+    /// Several of the other engines, each serving a model, as the file lists them. A request
+    /// goes to the engine of its model, and to that engine's fallback if it fails.
+    Multi(PathBuf),
+
     #[cfg(feature = "mistralrs")]
     /// Run inference on a model in a GGUF file using mistralrs w/ candle
     MistralRs,
@@ -109,6 +207,18 @@ pub enum Output {
     /// strings. It does it's own pre-processing.
     #[cfg(feature = "python")]
     PythonStr(String),
+
+    /// Run inference using a user supplied python file that accepts and returns tokens.
+    /// Dynamo does the pre-processing, detokenizing and stop conditions.
+    #[cfg(feature = "python")]
+    PythonTok(String),
+
+    /// An engine registered with [`plugin::register_engine`]. `arg` is what follows
+    /// `<name>:`, if anything.
+    Plugin {
+        name: String,
+        arg: String,
+    },
     // DEVELOPER NOTE
     // If you add an engine add it to `available_engines` below, and to Default if it makes sense
 }
@@ -129,6 +239,15 @@ impl TryFrom<&str> for Output {
 
             "echo_full" => Ok(Output::EchoFull),
             "echo_core" => Ok(Output::EchoCore),
+            "mock" => Ok(Output::Mock),
+            replay if replay.starts_with(REPLAY_PREFIX) => {
+                let path = replay.strip_prefix(REPLAY_PREFIX).unwrap();
+                Ok(Output::Replay(PathBuf::from(path)))
+            }
+            multi if multi.starts_with(MULTI_PREFIX) => {
+                let path = multi.strip_prefix(MULTI_PREFIX).unwrap();
+                Ok(Output::Multi(PathBuf::from(path)))
+            }
 
             endpoint_path if endpoint_path.starts_with(ENDPOINT_SCHEME) => {
                 let path = endpoint_path.strip_prefix(ENDPOINT_SCHEME).unwrap();
@@ -143,7 +262,25 @@ impl TryFrom<&str> for Output {
                 Ok(Output::PythonStr(path.to_string()))
             }
 
-            e => Err(anyhow::anyhow!("Invalid out= option '{e}'")),
+            #[cfg(feature = "python")]
+            python_tok_gen if python_tok_gen.starts_with(crate::PYTHON_TOK_SCHEME) => {
+                let path = python_tok_gen
+                    .strip_prefix(crate::PYTHON_TOK_SCHEME)
+                    .unwrap();
+                Ok(Output::PythonTok(path.to_string()))
+            }
+
+            other => {
+                let (name, arg) = plugin::split(other);
+                let Some(provider) = plugin::engine(name) else {
+                    anyhow::bail!("Invalid out= option '{other}'");
+                };
+                provider.parse(arg)?;
+                Ok(Output::Plugin {
+                    name: name.to_string(),
+                    arg: arg.to_string(),
+                })
+            }
         }
     }
 }
@@ -162,11 +299,19 @@ impl fmt::Display for Output {
 
             Output::EchoFull => "echo_full",
             Output::EchoCore => "echo_core",
+            Output::Mock => "mock",
+            Output::Replay(_) => "replay",
+            Output::Multi(_) => "multi",
 
             Output::Endpoint(path) => path,
 
             #[cfg(feature = "python")]
             Output::PythonStr(_) => "pystr",
+
+            #[cfg(feature = "python")]
+            Output::PythonTok(_) => "pytok",
+
+            Output::Plugin { name, arg } => return plugin_fmt(f, name, arg),
         };
         write!(f, "{s}")
     }
@@ -190,9 +335,50 @@ impl Default for Output {
 }
 
 impl Output {
+    /// Names an engine registered with [`plugin::register_engine`] can't take, also those of
+    /// engines this binary was built without
+    pub const BUILT_IN: &[&str] = &[
+        "mistralrs",
+        "llamacpp",
+        "llama_cpp",
+        "sglang",
+        "vllm",
+        "echo_full",
+        "echo_core",
+        "mock",
+        "replay",
+        "multi",
+        "dyn",
+        "pystr",
+        "pytok",
+    ];
+
+    /// The engines the pre-processor knows the sampling capabilities of. vllm and sglang
+    /// register their own model card.
+    pub fn card_engine(&self) -> Option<&'static str> {
+        match self {
+            #[cfg(feature = "mistralrs")]
+            Output::MistralRs => Some("mistralrs"),
+            #[cfg(feature = "llamacpp")]
+            Output::LlamaCpp => Some("llamacpp"),
+            _ => None,
+        }
+    }
+
+    /// vllm and sglang run in a sub-process, at most one at a time
+    pub fn is_subprocess(&self) -> bool {
+        matches!(self, Output::SgLang | Output::Vllm)
+    }
+
     #[allow(unused_mut)]
     pub fn available_engines() -> Vec<String> {
-        let mut out = vec!["echo_core".to_string(), "echo_full".to_string()];
+        let mut out = vec![
+            "echo_core".to_string(),
+            "echo_full".to_string(),
+            Output::Mock.to_string(),
+            Output::Replay(PathBuf::from("trace.jsonl")).to_string(),
+            Output::Multi(PathBuf::from("engines.toml")).to_string(),
+        ];
         #[cfg(feature = "mistralrs")]
         {
             out.push(Output::MistralRs.to_string());
@@ -209,8 +395,10 @@ impl Output {
         #[cfg(feature = "python")]
         {
             out.push(Output::PythonStr("file.py".to_string()).to_string());
+            out.push(Output::PythonTok("file.py".to_string()).to_string());
         }
 
+        out.extend(plugin::engine_names().into_iter().map(str::to_string));
         out
     }
 }
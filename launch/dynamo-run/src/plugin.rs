@@ -0,0 +1,232 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Inputs and engines added at startup, so that a fork or a feature can add an `in=` or an
+//! `out=` without changing [`Input`] and [`Output`].
+//!
+//! A provider is registered under a name before the command line is parsed: `in=<name>` and
+//! `in=<name>:<arg>` then become [`Input::Plugin`], `out=<name>` and `out=<name>:<arg>`
+//! become [`Output::Plugin`]. The built-in names can't be taken. A binary of its own does
+//! it like this:
+//! ```ignore
+//! dynamo_run::plugin::register_engine(MyEngine)?;
+//! let in_opt = Input::try_from("http")?;
+//! let out_opt = Output::try_from("my-engine:/etc/my-engine.toml")?;
+//! dynamo_run::run(runtime, in_opt, out_opt, flags).await
+//! ```
+//! dynamo-run's own `main` registers `in=watch`, and the inputs of the `kafka` and `redis`
+//! features, with [`register_features`].
+//!
+//! Those are the only built-ins that are providers. The other inputs, and every built-in
+//! engine, are still variants of [`Input`] and [`Output`] matched in `opt.rs` and run by
+//! [`crate::run`]: they use more than a provider is given, such as the model card, the request
+//! template or the router, so changing one of them still means changing those matches.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, LazyLock, RwLock};
+
+use async_trait::async_trait;
+use dynamo_llm::LocalModel;
+use dynamo_runtime::{CancellationToken, Runtime};
+
+use crate::{EngineConfig, Flags, Input, Output};
+
+/// An `in=`, what sends the engine its requests
+#[async_trait]
+pub trait InputProvider: Send + Sync {
+    /// `in=<name>`, without the `:<arg>`
+    fn name(&self) -> &'static str;
+
+    /// Checks the part of `in=` after `<name>:`, empty if there is none, before the engine
+    /// loads
+    fn parse(&self, arg: &str) -> anyhow::Result<()> {
+        if arg.is_empty() {
+            Ok(())
+        } else {
+            anyhow::bail!("in={} takes no argument, got '{arg}'", self.name())
+        }
+    }
+
+    /// Send `engine` requests until the runtime is cancelled or the input is done
+    async fn run(
+        &self,
+        runtime: Runtime,
+        flags: Flags,
+        arg: &str,
+        engine: EngineConfig,
+    ) -> anyhow::Result<()>;
+}
+
+/// An `out=`, the engine that serves the model
+#[async_trait]
+pub trait EngineProvider: Send + Sync {
+    /// `out=<name>`, without the `:<arg>`
+    fn name(&self) -> &'static str;
+
+    /// Checks the part of `out=` after `<name>:`, empty if there is none
+    fn parse(&self, arg: &str) -> anyhow::Result<()> {
+        if arg.is_empty() {
+            Ok(())
+        } else {
+            anyhow::bail!("out={} takes no argument, got '{arg}'", self.name())
+        }
+    }
+
+    /// Load the engine. `model` is `--model-path` prepared as for the built-in engines, with
+    /// `--context-length` and `--chat-template` applied, or an empty model without a path.
+    /// The engine stops when `cancel_token` is cancelled.
+    async fn make_engine(
+        &self,
+        arg: &str,
+        model: LocalModel,
+        flags: &Flags,
+        cancel_token: CancellationToken,
+    ) -> anyhow::Result<EngineConfig>;
+}
+
+#[derive(Default)]
+struct Registry {
+    inputs: BTreeMap<&'static str, Arc<dyn InputProvider>>,
+    engines: BTreeMap<&'static str, Arc<dyn EngineProvider>>,
+}
+
+static REGISTRY: LazyLock<RwLock<Registry>> = LazyLock::new(Default::default);
+
+/// Serve `in=<name>` with `provider`. Fails if the name is taken.
+pub fn register_input(provider: impl InputProvider + 'static) -> anyhow::Result<()> {
+    let name = provider.name();
+    if Input::BUILT_IN.contains(&name) {
+        anyhow::bail!("in={name} is built in, pick another name");
+    }
+    let mut registry = REGISTRY.write().unwrap();
+    if registry.inputs.contains_key(name) {
+        anyhow::bail!("in={name} is already registered");
+    }
+    registry.inputs.insert(name, Arc::new(provider));
+    Ok(())
+}
+
+/// Serve `out=<name>` with `provider`. Fails if the name is taken.
+pub fn register_engine(provider: impl EngineProvider + 'static) -> anyhow::Result<()> {
+    let name = provider.name();
+    if Output::BUILT_IN.contains(&name) {
+        anyhow::bail!("out={name} is built in, pick another name");
+    }
+    let mut registry = REGISTRY.write().unwrap();
+    if registry.engines.contains_key(name) {
+        anyhow::bail!("out={name} is already registered");
+    }
+    registry.engines.insert(name, Arc::new(provider));
+    Ok(())
+}
+
+/// Registers `in=watch` and the inputs of the features this binary was built with
+pub fn register_features() -> anyhow::Result<()> {
+    register_input(crate::input::watch::WatchInput)?;
+    #[cfg(feature = "kafka")]
+    register_input(crate::input::kafka::KafkaInput)?;
+    #[cfg(feature = "redis")]
+    register_input(crate::input::redis::RedisInput)?;
+    Ok(())
+}
+
+pub(crate) fn input(name: &str) -> Option<Arc<dyn InputProvider>> {
+    REGISTRY.read().unwrap().inputs.get(name).cloned()
+}
+
+pub(crate) fn engine(name: &str) -> Option<Arc<dyn EngineProvider>> {
+    REGISTRY.read().unwrap().engines.get(name).cloned()
+}
+
+/// Names of the registered engines, for `--help`
+pub(crate) fn engine_names() -> Vec<&'static str> {
+    REGISTRY.read().unwrap().engines.keys().copied().collect()
+}
+
+/// `<name>` and `<arg>` of `<name>:<arg>`, the arg empty without a `:`
+pub(crate) fn split(s: &str) -> (&str, &str) {
+    s.split_once(':').unwrap_or((s, ""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestEngine;
+
+    #[async_trait]
+    impl EngineProvider for TestEngine {
+        fn name(&self) -> &'static str {
+            "test-engine"
+        }
+
+        fn parse(&self, arg: &str) -> anyhow::Result<()> {
+            match arg {
+                "bad" => anyhow::bail!("bad arg"),
+                _ => Ok(()),
+            }
+        }
+
+        async fn make_engine(
+            &self,
+            _arg: &str,
+            model: LocalModel,
+            _flags: &Flags,
+            _cancel_token: CancellationToken,
+        ) -> anyhow::Result<EngineConfig> {
+            Ok(EngineConfig::StaticFull {
+                engine: dynamo_llm::engines::make_engine_full(),
+                model: Box::new(model),
+            })
+        }
+    }
+
+    struct Vllm;
+
+    #[async_trait]
+    impl EngineProvider for Vllm {
+        fn name(&self) -> &'static str {
+            "vllm"
+        }
+
+        async fn make_engine(
+            &self,
+            _arg: &str,
+            _model: LocalModel,
+            _flags: &Flags,
+            _cancel_token: CancellationToken,
+        ) -> anyhow::Result<EngineConfig> {
+            unreachable!()
+        }
+    }
+
+    #[test]
+    fn test_register_engine() {
+        assert!(Output::try_from("test-engine").is_err());
+        register_engine(TestEngine).unwrap();
+        assert!(register_engine(TestEngine).is_err());
+        assert!(register_engine(Vllm).is_err());
+
+        let out = Output::try_from("test-engine:/tmp/engine.toml").unwrap();
+        assert_eq!(out.to_string(), "test-engine:/tmp/engine.toml");
+        assert!(matches!(out, Output::Plugin { ref name, .. } if name == "test-engine"));
+        assert_eq!(
+            Output::try_from("test-engine").unwrap().to_string(),
+            "test-engine"
+        );
+        assert!(Output::try_from("test-engine:bad").is_err());
+        assert!(Output::available_engines().contains(&"test-engine".to_string()));
+    }
+}
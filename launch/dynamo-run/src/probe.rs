@@ -0,0 +1,359 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `dynamo-run probe [--json]`
+//!
+//! What this machine offers dynamo-run: the GPUs and their memory, the NVIDIA driver and CUDA
+//! versions, the engines this binary was built with, whether etcd and NATS answer, and the
+//! RDMA devices the block-transfer layer can use through NIXL's UCX backend. For bug reports
+//! and for checking a node before scheduling workers on it.
+
+use std::path::Path;
+use std::time::Duration;
+
+use dynamo_runtime::transports::{etcd, nats};
+use dynamo_runtime::Runtime;
+use serde::Serialize;
+
+use crate::Output;
+
+/// How long to wait for etcd and NATS
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where the NVIDIA kernel module says which version it is, if it is loaded
+pub(crate) const NVIDIA_DRIVER: &str = "/proc/driver/nvidia/version";
+
+/// One directory per RDMA device, InfiniBand or RoCE
+const RDMA_DEVICES: &str = "/sys/class/infiniband";
+
+#[derive(clap::Parser, Debug)]
+#[command(name = "dynamo-run probe")]
+struct ProbeArgs {
+    /// Print the report as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct Report {
+    gpus: Vec<Gpu>,
+    driver_version: Option<String>,
+    cuda_version: Option<String>,
+    engines: Vec<String>,
+    etcd: Connection,
+    nats: Connection,
+    rdma: Vec<RdmaPort>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct Gpu {
+    index: u32,
+    name: String,
+    memory_mib: u64,
+}
+
+#[derive(Serialize, Debug, Default)]
+struct Connection {
+    url: String,
+    reachable: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct RdmaPort {
+    device: String,
+    port: u32,
+    /// `ACTIVE` when the link is up
+    state: String,
+    /// `InfiniBand`, or `Ethernet` for RoCE
+    link_layer: String,
+}
+
+/// Entry point for `dynamo-run probe ...`. `args` starts at `probe`.
+pub async fn run(runtime: Runtime, args: &[String]) -> anyhow::Result<()> {
+    let args = <ProbeArgs as clap::Parser>::parse_from(args);
+
+    let (gpus, cuda_version) = nvidia_smi().await;
+    let report = Report {
+        gpus,
+        driver_version: driver_version(),
+        cuda_version,
+        engines: Output::available_engines(),
+        etcd: probe_etcd(runtime).await,
+        nats: probe_nats().await,
+        rdma: rdma_ports(Path::new(RDMA_DEVICES)),
+    };
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{}", report.text());
+    }
+    Ok(())
+}
+
+impl Report {
+    fn text(&self) -> String {
+        let mut out = String::new();
+        if self.gpus.is_empty() {
+            out.push_str("GPUs: none found\n");
+        } else {
+            out.push_str("GPUs:\n");
+            for gpu in &self.gpus {
+                out.push_str(&format!(
+                    "  {}: {}, {} MiB\n",
+                    gpu.index, gpu.name, gpu.memory_mib
+                ));
+            }
+        }
+        let unknown = || "unknown".to_string();
+        out.push_str(&format!(
+            "NVIDIA driver: {}, CUDA: {}\n",
+            self.driver_version.clone().unwrap_or_else(unknown),
+            self.cuda_version.clone().unwrap_or_else(unknown)
+        ));
+        out.push_str(&format!("Engines: {}\n", self.engines.join(", ")));
+        for (name, connection) in [("etcd", &self.etcd), ("NATS", &self.nats)] {
+            let status = match (&connection.error, &connection.version) {
+                (Some(err), _) => format!("unreachable, {err}"),
+                (None, Some(version)) => format!("reachable, version {version}"),
+                (None, None) => "reachable".to_string(),
+            };
+            out.push_str(&format!("{name}: {} {status}\n", connection.url));
+        }
+        if self.rdma.is_empty() {
+            out.push_str("RDMA: none found\n");
+        } else {
+            out.push_str("RDMA:\n");
+            for port in &self.rdma {
+                out.push_str(&format!(
+                    "  {} port {}: {}, {}\n",
+                    port.device, port.port, port.state, port.link_layer
+                ));
+            }
+        }
+        out
+    }
+}
+
+/// The GPUs and the CUDA version the driver supports, from `nvidia-smi`
+async fn nvidia_smi() -> (Vec<Gpu>, Option<String>) {
+    let gpus = match tokio::process::Command::new("nvidia-smi")
+        .args([
+            "--query-gpu=index,name,memory.total",
+            "--format=csv,noheader,nounits",
+        ])
+        .output()
+        .await
+    {
+        Ok(output) if output.status.success() => {
+            parse_gpus(&String::from_utf8_lossy(&output.stdout))
+        }
+        Ok(_) | Err(_) => return (vec![], None),
+    };
+    // Only the banner has it
+    let cuda_version = match tokio::process::Command::new("nvidia-smi").output().await {
+        Ok(output) if output.status.success() => {
+            parse_cuda_version(&String::from_utf8_lossy(&output.stdout))
+        }
+        Ok(_) | Err(_) => None,
+    };
+    (gpus, cuda_version)
+}
+
+/// `index, name, memory` lines
+fn parse_gpus(csv: &str) -> Vec<Gpu> {
+    csv.lines()
+        .filter_map(|line| {
+            let mut fields = line.split(',').map(str::trim);
+            let index = fields.next()?.parse().ok()?;
+            let name = fields.next()?.to_string();
+            let memory_mib = fields.next()?.parse().ok()?;
+            Some(Gpu {
+                index,
+                name,
+                memory_mib,
+            })
+        })
+        .collect()
+}
+
+/// `CUDA Version: 12.4` in the `nvidia-smi` banner
+fn parse_cuda_version(banner: &str) -> Option<String> {
+    let (_, rest) = banner.split_once("CUDA Version:")?;
+    rest.split_whitespace()
+        .next()
+        .map(|version| version.trim_end_matches('|').to_string())
+}
+
+/// The version of the loaded NVIDIA kernel module
+pub(crate) fn driver_version() -> Option<String> {
+    let text = std::fs::read_to_string(NVIDIA_DRIVER).ok()?;
+    parse_driver_version(&text)
+}
+
+/// `NVRM version: NVIDIA UNIX x86_64 Kernel Module  550.54.15  Tue Mar  5 22:23:56 UTC 2024`
+fn parse_driver_version(text: &str) -> Option<String> {
+    text.lines()
+        .next()?
+        .split_whitespace()
+        .find(|word| word.contains('.') && word.chars().all(|c| c.is_ascii_digit() || c == '.'))
+        .map(str::to_string)
+}
+
+async fn probe_etcd(runtime: Runtime) -> Connection {
+    let options = etcd::ClientOptions {
+        // Only looking, nothing to register
+        attach_lease: false,
+        ..Default::default()
+    };
+    let mut connection = Connection {
+        url: options.etcd_url.join(","),
+        ..Default::default()
+    };
+    let status = async {
+        let client = etcd::Client::new(options, runtime).await?;
+        // Connecting is lazy, a request is what reaches the server
+        let status = client.etcd_client().maintenance_client().status().await?;
+        anyhow::Ok(status.version().to_string())
+    };
+    match tokio::time::timeout(CONNECT_TIMEOUT, status).await {
+        Ok(Ok(version)) => {
+            connection.reachable = true;
+            connection.version = Some(version);
+        }
+        Ok(Err(err)) => connection.error = Some(format!("{err:#}")),
+        Err(_) => {
+            connection.error = Some(format!("no answer after {}s", CONNECT_TIMEOUT.as_secs()))
+        }
+    }
+    connection
+}
+
+async fn probe_nats() -> Connection {
+    let options = nats::ClientOptions::default();
+    let mut connection = Connection {
+        url: options.server().to_string(),
+        ..Default::default()
+    };
+    match tokio::time::timeout(CONNECT_TIMEOUT, options.connect()).await {
+        Ok(Ok(client)) => {
+            connection.reachable = true;
+            connection.version = Some(client.client().server_info().version);
+        }
+        Ok(Err(err)) => connection.error = Some(format!("{err:#}")),
+        Err(_) => {
+            connection.error = Some(format!("no answer after {}s", CONNECT_TIMEOUT.as_secs()))
+        }
+    }
+    connection
+}
+
+/// The ports of every RDMA device under `root`, `/sys/class/infiniband` outside of tests
+fn rdma_ports(root: &Path) -> Vec<RdmaPort> {
+    let Ok(devices) = std::fs::read_dir(root) else {
+        return vec![];
+    };
+    let mut found = vec![];
+    for device in devices.flatten() {
+        let Ok(ports) = std::fs::read_dir(device.path().join("ports")) else {
+            continue;
+        };
+        for port in ports.flatten() {
+            let Some(number) = port.file_name().to_str().and_then(|n| n.parse().ok()) else {
+                continue;
+            };
+            let read = |file: &str| {
+                std::fs::read_to_string(port.path().join(file))
+                    .map(|s| s.trim().to_string())
+                    .unwrap_or_default()
+            };
+            // `4: ACTIVE`
+            let state = read("state");
+            let state = state
+                .split_once(": ")
+                .map_or(state.as_str(), |(_, name)| name)
+                .to_string();
+            found.push(RdmaPort {
+                device: device.file_name().to_string_lossy().into_owned(),
+                port: number,
+                state,
+                link_layer: read("link_layer"),
+            });
+        }
+    }
+    found.sort_by(|a, b| (&a.device, a.port).cmp(&(&b.device, b.port)));
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse_gpus("0, NVIDIA H100 80GB HBM3, 81559\n1, NVIDIA H100 80GB HBM3, 81559\n"),
+            vec![
+                Gpu {
+                    index: 0,
+                    name: "NVIDIA H100 80GB HBM3".to_string(),
+                    memory_mib: 81559
+                },
+                Gpu {
+                    index: 1,
+                    name: "NVIDIA H100 80GB HBM3".to_string(),
+                    memory_mib: 81559
+                },
+            ]
+        );
+        assert_eq!(
+            parse_cuda_version(
+                "| NVIDIA-SMI 550.54.15   Driver Version: 550.54.15   CUDA Version: 12.4     |"
+            ),
+            Some("12.4".to_string())
+        );
+        assert_eq!(
+            parse_driver_version(
+                "NVRM version: NVIDIA UNIX x86_64 Kernel Module  550.54.15  Tue Mar  5 22:23:56 \
+                 UTC 2024\nGCC version:  gcc version 12.3.0"
+            ),
+            Some("550.54.15".to_string())
+        );
+    }
+
+    #[test]
+    fn test_rdma_ports() {
+        let root = tempfile::tempdir().unwrap();
+        let port = root.path().join("mlx5_0/ports/1");
+        std::fs::create_dir_all(&port).unwrap();
+        std::fs::write(port.join("state"), "4: ACTIVE\n").unwrap();
+        std::fs::write(port.join("link_layer"), "InfiniBand\n").unwrap();
+
+        let ports = rdma_ports(root.path());
+        assert_eq!(ports.len(), 1);
+        assert_eq!(ports[0].device, "mlx5_0");
+        assert_eq!(ports[0].port, 1);
+        assert_eq!(ports[0].state, "ACTIVE");
+        assert_eq!(ports[0].link_layer, "InfiniBand");
+
+        assert!(rdma_ports(&root.path().join("missing")).is_empty());
+    }
+}
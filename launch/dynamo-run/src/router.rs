@@ -0,0 +1,99 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `dynamo-run router [dyn://<namespace.component.endpoint>] [flags]`
+//!
+//! A gateway: the HTTP frontend, routing and metrics of `in=http out=dyn://...`, and nothing
+//! else. It never loads a model or starts an engine, the workers it discovers do the work. Flags
+//! that only mean something to a local engine are refused instead of ignored, so a gateway
+//! manifest can't quietly carry a worker's settings.
+
+use crate::lint;
+
+/// The endpoint workers serve on when the command line doesn't name one
+pub const DEFAULT_ENDPOINT: &str = "dyn://dynamo.backend.generate";
+
+/// The arguments `router ...` stands for, `in=http out=dyn://...` and the flags. `args` starts
+/// at `router`.
+pub fn expand(args: &[String]) -> anyhow::Result<Vec<String>> {
+    let mut rest = args.iter().skip(1).peekable();
+    let endpoint = match rest.peek() {
+        Some(arg) if arg.starts_with("dyn://") => rest.next().unwrap().clone(),
+        _ => DEFAULT_ENDPOINT.to_string(),
+    };
+    let mut expanded = vec!["in=http".to_string(), format!("out={endpoint}")];
+    for arg in rest {
+        if arg.starts_with("in=") || arg.starts_with("out=") {
+            anyhow::bail!("dynamo-run router is always in=http out=dyn://, remove '{arg}'");
+        }
+        expanded.push(arg.clone());
+    }
+    Ok(expanded)
+}
+
+/// Refuse to start with flags a router can't use. Other warnings are only logged. `args` are
+/// those [`expand`] returned.
+pub fn check(args: &[String]) -> anyhow::Result<()> {
+    let (errors, warnings) = lint::check(args);
+    let mut refused = errors;
+    for warning in warnings {
+        // Engine and model settings belong on the workers
+        if warning.contains("ignored with out=dyn://") {
+            refused.push(warning);
+        } else {
+            tracing::warn!("{warning}");
+        }
+    }
+    if !refused.is_empty() {
+        anyhow::bail!("dynamo-run router: {}", refused.join("; "));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_expand() {
+        assert_eq!(
+            expand(&args(&["router", "--http-port", "8000"])).unwrap(),
+            args(&[
+                "in=http",
+                "out=dyn://dynamo.backend.generate",
+                "--http-port",
+                "8000"
+            ])
+        );
+        assert_eq!(
+            expand(&args(&["router", "dyn://a.b.c", "--router-mode", "kv"])).unwrap(),
+            args(&["in=http", "out=dyn://a.b.c", "--router-mode", "kv"])
+        );
+        assert!(expand(&args(&["router", "out=vllm"])).is_err());
+
+        assert!(
+            check(&expand(&args(&["router", "--router-mode", "power-of-two"])).unwrap()).is_ok()
+        );
+        assert!(check(&expand(&args(&["router", "--max-batch-size", "8"])).unwrap()).is_err());
+        assert!(check(&expand(&args(&["router", "--router-mode", "kv"])).unwrap()).is_ok());
+        assert!(
+            check(&expand(&args(&["router", "--model-path", "Qwen/Qwen2.5-3B"])).unwrap()).is_err()
+        );
+    }
+}
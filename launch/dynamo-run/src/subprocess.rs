@@ -11,6 +11,7 @@ use anyhow::Context;
 use regex::Regex;
 use tokio::io::AsyncBufReadExt;
 
+use dynamo_llm::engines::load::{Device, LoadOptions};
 use dynamo_llm::engines::MultiNodeConfig;
 use dynamo_llm::LocalModel;
 
@@ -20,13 +21,21 @@ pub mod vllm;
 /// Internal endpoint to connect the subprocess over etcd/nats
 pub const ENDPOINT: &str = "dyn://dynamo.internal.worker";
 
+/// A draft model for the engine to decode speculatively with
+#[derive(Debug, Clone)]
+pub struct DraftConfig {
+    /// Path or Hugging Face repo, the engine loads it
+    pub model: String,
+    pub num_speculative_tokens: u32,
+}
+
 pub async fn start(
     // The Python code to run
     py_script: &'static str,
     // Model info
     local_model: &LocalModel,
-    // How many GPUs to use
-    tensor_parallel_size: u32,
+    // Quantization, dtype, how many GPUs to use and how much of them
+    load_options: &LoadOptions,
     // sglang which GPU to start from, on a multi-GPU system
     // vllm uses CUDA_VISIBLE_DEVICES
     base_gpu_id: Option<u32>,
@@ -34,6 +43,16 @@ pub async fn start(
     multi_node_config: Option<MultiNodeConfig>,
     // Path to a JSON file containing extra arguments to the backend engine
     extra_engine_args: Option<&Path>,
+    // Speculative decoding
+    draft: Option<DraftConfig>,
+    // vllm only, LoRA adapters a batch can use at once. LoRA is off if None.
+    max_loras: Option<u32>,
+    // Most tokens a step prefills, with chunked prefill. The engine's default if None.
+    max_prefill_chunk: Option<u32>,
+    // Jinja template the registered model card renders prompts with
+    chat_template: Option<&Path>,
+    // Prompt lengths to generate for before registering, see `crate::warmup`
+    warmup: &[u32],
 ) -> anyhow::Result<(tempfile::TempPath, tokio::process::Child)> {
     let mut tmp = tempfile::NamedTempFile::new()?;
     // Writes on Linux don't block
@@ -49,8 +68,25 @@ pub async fn start(
         "--model-name".to_string(),
         local_model.display_name().to_string(),
         "--tensor-parallel-size".to_string(),
-        tensor_parallel_size.to_string(),
+        load_options.tensor_parallel_size.to_string(),
     ];
+    if let Some(quantization) = load_options.quantization {
+        args.push("--quantization".to_string());
+        args.push(quantization.to_string());
+    }
+    if let Some(dtype) = load_options.dtype {
+        args.push("--dtype".to_string());
+        args.push(dtype.torch_name().to_string());
+    }
+    if let Some(fraction) = load_options.gpu_memory_fraction {
+        args.push("--gpu-memory-fraction".to_string());
+        args.push(fraction.to_string());
+    }
+    // The GPU is the default, its index is the base GPU id
+    if load_options.device == Some(Device::Cpu) {
+        args.push("--device".to_string());
+        args.push("cpu".to_string());
+    }
     // sglang only
     if let Some(base_gpu_id) = base_gpu_id {
         args.push("--base-gpu-id".to_string());
@@ -69,6 +105,34 @@ pub async fn start(
         args.push("--extra-engine-args".to_string());
         args.push(extra_engine_args.to_string_lossy().to_string());
     }
+    if let Some(draft) = draft {
+        args.push("--draft-model".to_string());
+        args.push(draft.model);
+        args.push("--num-speculative-tokens".to_string());
+        args.push(draft.num_speculative_tokens.to_string());
+    }
+    if let Some(max_loras) = max_loras {
+        args.push("--max-loras".to_string());
+        args.push(max_loras.to_string());
+    }
+    if let Some(max_prefill_chunk) = max_prefill_chunk {
+        args.push("--max-prefill-chunk".to_string());
+        args.push(max_prefill_chunk.to_string());
+    }
+    if let Some(chat_template) = chat_template {
+        args.push("--chat-template".to_string());
+        args.push(chat_template.to_string_lossy().to_string());
+    }
+    if !warmup.is_empty() {
+        args.push("--warmup".to_string());
+        args.push(
+            warmup
+                .iter()
+                .map(u32::to_string)
+                .collect::<Vec<_>>()
+                .join(","),
+        );
+    }
     let mut cmd = tokio::process::Command::new("python3");
     cmd.kill_on_drop(false)
         .args(args)
@@ -17,8 +17,10 @@
 
 import argparse
 import asyncio
+import json
 import logging
 import sys
+import time
 from typing import Optional
 
 import sglang
@@ -30,6 +32,9 @@ from dynamo.runtime import DistributedRuntime, dynamo_worker
 
 DEFAULT_ENDPOINT = "dyn://dynamo.backend.generate"
 DEFAULT_MODEL = "Qwen/Qwen2.5-0.5B-Instruct"
+# Any token of the vocabulary, warmup only needs the prompt lengths
+WARMUP_TOKEN_ID = 100
+WARMUP_MAX_TOKENS = 16
 
 logging.basicConfig(level=logging.DEBUG)
 
@@ -42,12 +47,21 @@ class Config:
     endpoint: str
     model_path: str
     model_name: Optional[str]
+    chat_template: Optional[str]
     base_gpu_id: int
     tensor_parallel_size: int
+    quantization: Optional[str]
+    dtype: str
+    gpu_memory_fraction: float
+    device: Optional[str]
     nnodes: int
     node_rank: int
     dist_init_addr: str
     extra_engine_args: str
+    draft_model: Optional[str]
+    num_speculative_tokens: int
+    max_prefill_chunk: int
+    warmup: list[int]
 
 
 class RequestHandler:
@@ -55,10 +69,18 @@ class RequestHandler:
     Request handler for the generate endpoint
     """
 
-    def __init__(self, engine):
+    def __init__(self, engine, num_speculative_tokens):
         self.engine_client = engine
+        # 0 without a draft model
+        self.num_speculative_tokens = num_speculative_tokens
 
     async def generate(self, request):
+        if request.get("images"):
+            raise ValueError("The sglang engine does not support image input")
+        if request.get("lora"):
+            raise ValueError("LoRA adapters are not supported by the sglang engine")
+        if request["sampling_options"].get("logit_bias"):
+            raise ValueError("logit_bias is not supported by the sglang engine")
         sampling_params = {}
         if request["sampling_options"]["temperature"] is not None:
             sampling_params["temperature"] = request["sampling_options"]["temperature"]
@@ -66,22 +88,62 @@ class RequestHandler:
             # sglang defaults this to 128
             "max_new_tokens": request["stop_conditions"]["max_tokens"],
         }
+        guided_decoding = request["sampling_options"].get("guided_decoding")
+        if guided_decoding and guided_decoding.get("json") is not None:
+            sampling_params["json_schema"] = json.dumps(guided_decoding["json"])
         num_output_tokens_so_far = 0
+        verify_steps_so_far = 0
         gen = await self.engine_client.async_generate(
             input_ids=request["token_ids"], sampling_params=sampling_params, stream=True
         )
-        async for res in gen:
-            # res is a dict
-
-            finish_reason = res["meta_info"]["finish_reason"]
-            if finish_reason:
-                # Don't forward the stop token
-                out = {"token_ids": [], "finish_reason": finish_reason["type"]}
-            else:
-                next_total_toks = len(res["output_ids"])
-                out = {"token_ids": res["output_ids"][num_output_tokens_so_far:]}
-            yield out
-            num_output_tokens_so_far = next_total_toks
+        try:
+            async for res in gen:
+                # res is a dict
+
+                finish_reason = res["meta_info"]["finish_reason"]
+                if finish_reason:
+                    # Don't forward the stop token
+                    out = {"token_ids": [], "finish_reason": finish_reason["type"]}
+                else:
+                    next_total_toks = len(res["output_ids"])
+                    out = {"token_ids": res["output_ids"][num_output_tokens_so_far:]}
+                    # Each verify step keeps one token of the target model's own,
+                    # plus the draft tokens it accepted.
+                    verify_steps = res["meta_info"].get("spec_verify_ct", 0)
+                    steps = verify_steps - verify_steps_so_far
+                    if self.num_speculative_tokens and steps > 0:
+                        new_toks = next_total_toks - num_output_tokens_so_far
+                        out["spec_decode"] = {
+                            "draft_tokens": steps * self.num_speculative_tokens,
+                            "accepted_tokens": max(new_toks - steps, 0),
+                        }
+                    verify_steps_so_far = verify_steps
+                yield out
+                num_output_tokens_so_far = next_total_toks
+        finally:
+            # When the client cancelled, closing sglang's generator aborts the request
+            await gen.aclose()
+
+
+async def warmup(engine_client, lengths):
+    """Generate a few tokens for a prompt of each length, before registering"""
+    for length in lengths:
+        sampling_params = {"max_new_tokens": WARMUP_MAX_TOKENS, "ignore_eos": True}
+        start = time.monotonic()
+        try:
+            gen = await engine_client.async_generate(
+                input_ids=[WARMUP_TOKEN_ID] * length,
+                sampling_params=sampling_params,
+                stream=True,
+            )
+            async for _ in gen:
+                pass
+        except Exception as e:
+            logging.warning(f"Warmup with a {length} token prompt failed: {e}")
+            continue
+        logging.info(
+            f"Warmed up with a {length} token prompt in {time.monotonic() - start:.2f}s"
+        )
 
 
 @dynamo_worker(static=False)
@@ -98,7 +160,12 @@ async def init(runtime: DistributedRuntime, config: Config):
 
     endpoint = component.endpoint(config.endpoint)
     await register_llm(
-        ModelType.Backend, endpoint, config.model_path, config.model_name
+        ModelType.Backend,
+        endpoint,
+        config.model_path,
+        config.model_name,
+        engine="sglang",
+        chat_template=config.chat_template,
     )
 
     arg_map = {
@@ -106,7 +173,14 @@ async def init(runtime: DistributedRuntime, config: Config):
         "skip_tokenizer_init": True,
         "tp_size": config.tensor_parallel_size,
         "base_gpu_id": config.base_gpu_id,
+        "dtype": config.dtype,
     }
+    if config.quantization:
+        arg_map["quantization"] = config.quantization
+    if config.gpu_memory_fraction > 0:
+        arg_map["mem_fraction_static"] = config.gpu_memory_fraction
+    if config.device:
+        arg_map["device"] = config.device
     if config.dist_init_addr != "":
         arg_map["trust_remote_code"] = True
         arg_map["nnodes"] = config.nnodes
@@ -114,6 +188,17 @@ async def init(runtime: DistributedRuntime, config: Config):
         # In practice this is always 0 because Dynamo only manages the leader
         arg_map["node_rank"] = config.node_rank
 
+    if config.draft_model:
+        # A chain of draft tokens, one per step
+        arg_map["speculative_algorithm"] = "EAGLE"
+        arg_map["speculative_draft_model_path"] = config.draft_model
+        arg_map["speculative_num_steps"] = config.num_speculative_tokens
+        arg_map["speculative_eagle_topk"] = 1
+        arg_map["speculative_num_draft_tokens"] = config.num_speculative_tokens + 1
+
+    if config.max_prefill_chunk > 0:
+        arg_map["chunked_prefill_size"] = config.max_prefill_chunk
+
     if config.extra_engine_args != "":
         json_map = {}
         # extra_engine_args is a filename
@@ -131,10 +216,14 @@ async def init(runtime: DistributedRuntime, config: Config):
 
     engine_args = ServerArgs(**arg_map)
     engine_client = sglang.Engine(server_args=engine_args)
+    await warmup(engine_client, config.warmup)
 
     # the server will gracefully shutdown (i.e., keep opened TCP streams finishes)
     # after the lease is revoked
-    await endpoint.serve_endpoint(RequestHandler(engine_client).generate)
+    num_speculative_tokens = config.num_speculative_tokens if config.draft_model else 0
+    await endpoint.serve_endpoint(
+        RequestHandler(engine_client, num_speculative_tokens).generate
+    )
 
 
 def cmd_line_args():
@@ -159,6 +248,12 @@ def cmd_line_args():
         default="",
         help="Name to serve the model under. Defaults to deriving it from model path.",
     )
+    parser.add_argument(
+        "--chat-template",
+        type=str,
+        default="",
+        help="Path to a Jinja chat template to use instead of the model's own.",
+    )
     parser.add_argument(
         "--base-gpu-id",
         type=int,
@@ -168,6 +263,27 @@ def cmd_line_args():
     parser.add_argument(
         "--tensor-parallel-size", type=int, default=1, help="Number of GPUs to use."
     )
+    parser.add_argument(
+        "--quantization",
+        type=str,
+        default="",
+        help="Quantize the weights as they load: fp8.",
+    )
+    parser.add_argument(
+        "--dtype",
+        type=str,
+        default="auto",
+        help="Torch dtype of the weights that aren't quantized, e.g. bfloat16.",
+    )
+    parser.add_argument(
+        "--gpu-memory-fraction",
+        type=float,
+        default=0.0,
+        help="Share of each GPU's memory for the weights and KV cache. 0 keeps SGLang's default.",
+    )
+    parser.add_argument(
+        "--device", type=str, default="", help="cpu, or the GPU if unset."
+    )
     parser.add_argument(
         "--nnodes", type=int, default=1, help="The number of machines SGLang will use"
     )
@@ -189,6 +305,30 @@ def cmd_line_args():
         default="",
         help="Path to a JSON file containing additional keyword arguments to pass to the SGLang Engine.",
     )
+    parser.add_argument(
+        "--draft-model",
+        type=str,
+        default="",
+        help="Path or HuggingFace identifier of an EAGLE draft model for speculative decoding.",
+    )
+    parser.add_argument(
+        "--num-speculative-tokens",
+        type=int,
+        default=5,
+        help="Tokens the draft model proposes per step.",
+    )
+    parser.add_argument(
+        "--max-prefill-chunk",
+        type=int,
+        default=0,
+        help="Most tokens per step with chunked prefill. 0 keeps SGLang's default.",
+    )
+    parser.add_argument(
+        "--warmup",
+        type=str,
+        default="",
+        help="Comma separated prompt lengths, in tokens, to generate for before registering.",
+    )
     args = parser.parse_args()
 
     config = Config()
@@ -198,6 +338,7 @@ def cmd_line_args():
     else:
         # This becomes an `Option` on the Rust side
         config.model_name = None
+    config.chat_template = args.chat_template or None
 
     endpoint_str = args.endpoint.replace("dyn://", "", 1)
     endpoint_parts = endpoint_str.split(".")
@@ -214,10 +355,18 @@ def cmd_line_args():
     config.endpoint = parsed_endpoint_name
     config.base_gpu_id = args.base_gpu_id
     config.tensor_parallel_size = args.tensor_parallel_size
+    config.quantization = args.quantization or None
+    config.dtype = args.dtype
+    config.gpu_memory_fraction = args.gpu_memory_fraction
+    config.device = args.device or None
     config.nnodes = args.nnodes
     config.node_rank = args.node_rank
     config.dist_init_addr = args.dist_init_addr
     config.extra_engine_args = args.extra_engine_args
+    config.draft_model = args.draft_model or None
+    config.num_speculative_tokens = args.num_speculative_tokens
+    config.max_prefill_chunk = args.max_prefill_chunk
+    config.warmup = [int(n) for n in args.warmup.split(",") if n]
 
     return config
 
@@ -16,25 +16,34 @@
 
 import argparse
 import asyncio
+import base64
+import io
 import logging
 import os
 import sys
+import time
 import uuid
 from typing import Optional
 
 import uvloop
+from PIL import Image
 from vllm import SamplingParams
 from vllm.engine.arg_utils import AsyncEngineArgs
 from vllm.entrypoints.openai.api_server import (
     build_async_engine_client_from_engine_args,
 )
 from vllm.inputs import TokensPrompt
+from vllm.lora.request import LoRARequest
+from vllm.sampling_params import GuidedDecodingParams
 
 from dynamo.llm import ModelType, register_llm
 from dynamo.runtime import DistributedRuntime, dynamo_worker
 
 DEFAULT_ENDPOINT = "dyn://dynamo.backend.generate"
 DEFAULT_MODEL = "Qwen/Qwen2.5-0.5B-Instruct"
+# Any token of the vocabulary, warmup only needs the prompt lengths
+WARMUP_TOKEN_ID = 100
+WARMUP_MAX_TOKENS = 16
 
 logging.basicConfig(level=logging.DEBUG)
 
@@ -47,8 +56,41 @@ class Config:
     endpoint: str
     model_path: str
     model_name: Optional[str]
+    chat_template: Optional[str]
     tensor_parallel_size: int
+    quantization: Optional[str]
+    dtype: str
+    gpu_memory_fraction: float
+    device: Optional[str]
     extra_engine_args: str
+    draft_model: Optional[str]
+    num_speculative_tokens: int
+    max_loras: int
+    max_prefill_chunk: int
+    warmup: list[int]
+
+
+def position_logprobs(token_ids, logprobs, top):
+    """
+    The log probabilities of new tokens from vllm's list of dict token id -> Logprob, which
+    has the sampled token plus the `top` most likely ones.
+    """
+    log_probs = []
+    top_log_probs = []
+    for token_id, candidates in zip(token_ids, logprobs):
+        log_probs.append(candidates[token_id].logprob)
+        ranked = sorted(candidates.items(), key=lambda c: c[1].rank)[:top]
+        top_log_probs.append(
+            [
+                {
+                    "token_id": candidate_id,
+                    "token": candidate.decoded_token,
+                    "logprob": candidate.logprob,
+                }
+                for candidate_id, candidate in ranked
+            ]
+        )
+    return {"log_probs": log_probs, "top_log_probs": top_log_probs}
 
 
 class RequestHandler:
@@ -56,51 +98,129 @@ class RequestHandler:
     Request handler for the generate endpoint
     """
 
-    def __init__(self, engine, default_sampling_params):
+    def __init__(self, engine, default_sampling_params, num_speculative_tokens):
         self.engine_client = engine
         self.default_sampling_params = default_sampling_params
+        # 0 without a draft model
+        self.num_speculative_tokens = num_speculative_tokens
+        # vllm tells adapters apart by a number, give each name one
+        self.lora_ids = {}
+
+    def lora_request(self, lora):
+        if not lora:
+            return None
+        lora_id = self.lora_ids.setdefault(lora["name"], len(self.lora_ids) + 1)
+        return LoRARequest(lora["name"], lora_id, lora["path"])
 
     async def generate(self, request):
         # logging.debug(f"Received request: {request}")
         request_id = str(uuid.uuid4().hex)
 
         prompt = TokensPrompt(prompt_token_ids=request["token_ids"])
+        if request.get("images"):
+            # The chat template already put the image placeholders in the token ids
+            prompt["multi_modal_data"] = {
+                "image": [
+                    Image.open(io.BytesIO(base64.b64decode(image["data"])))
+                    for image in request["images"]
+                ]
+            }
 
         sampling_params = SamplingParams(**self.default_sampling_params)
         for key, value in request["sampling_options"].items():
-            if not value:
+            # 0 is a valid seed or temperature
+            if value is None or value == {}:
                 continue
-            if hasattr(sampling_params, key):
+            if key == "guided_decoding":
+                sampling_params.guided_decoding = GuidedDecodingParams(**value)
+            elif key == "logit_bias":
+                # JSON object keys are strings
+                sampling_params.logit_bias = {int(k): v for k, v in value.items()}
+            elif hasattr(sampling_params, key):
                 setattr(sampling_params, key, value)
 
         max_tokens = request["stop_conditions"]["max_tokens"]
         if max_tokens:
             sampling_params.max_tokens = max_tokens
+        top_logprobs = request.get("output_options", {}).get("logprobs")
+        if top_logprobs is not None:
+            sampling_params.logprobs = top_logprobs
 
         num_output_tokens_so_far = 0
-        gen = self.engine_client.generate(prompt, sampling_params, request_id)
-        async for res in gen:
-            # res is vllm's RequestOutput
-
-            # This is the expected way for a request to end.
-            # The new token ID will be eos, don't forward it.
-            if res.finished:
-                yield {"finish_reason": "stop", "token_ids": []}
-                break
-
-            if not res.outputs:
-                yield {"finish_reason": "error", "token_ids": []}
-                break
-
-            output = res.outputs[0]
-            next_total_toks = len(output.token_ids)
-            out = {"token_ids": output.token_ids[num_output_tokens_so_far:]}
-            if output.finish_reason:
-                out["finish_reason"] = output.finish_reason
-            if output.stop_reason:
-                out["stop_reason"] = output.stop_reason
-            yield out
-            num_output_tokens_so_far = next_total_toks
+        finished = False
+        gen = self.engine_client.generate(
+            prompt,
+            sampling_params,
+            request_id,
+            lora_request=self.lora_request(request.get("lora")),
+        )
+        try:
+            async for res in gen:
+                # res is vllm's RequestOutput
+
+                # This is the expected way for a request to end.
+                # The new token ID will be eos, don't forward it.
+                if res.finished:
+                    finished = True
+                    yield {"finish_reason": "stop", "token_ids": []}
+                    break
+
+                if not res.outputs:
+                    finished = True
+                    yield {"finish_reason": "error", "token_ids": []}
+                    break
+
+                output = res.outputs[0]
+                next_total_toks = len(output.token_ids)
+                out = {"token_ids": output.token_ids[num_output_tokens_so_far:]}
+                if top_logprobs is not None and output.logprobs:
+                    out.update(
+                        position_logprobs(
+                            output.token_ids[num_output_tokens_so_far:],
+                            output.logprobs[num_output_tokens_so_far:],
+                            top_logprobs,
+                        )
+                    )
+                # After prefill each step makes one token of the target model's own,
+                # plus the draft tokens it accepted.
+                if self.num_speculative_tokens and num_output_tokens_so_far > 0:
+                    new_toks = next_total_toks - num_output_tokens_so_far
+                    if new_toks > 0:
+                        out["spec_decode"] = {
+                            "draft_tokens": self.num_speculative_tokens,
+                            "accepted_tokens": min(
+                                new_toks - 1, self.num_speculative_tokens
+                            ),
+                        }
+                if output.finish_reason:
+                    out["finish_reason"] = output.finish_reason
+                if output.stop_reason:
+                    out["stop_reason"] = output.stop_reason
+                yield out
+                num_output_tokens_so_far = next_total_toks
+        finally:
+            # We get here early when the client cancelled. Free the GPU for other requests.
+            if not finished:
+                await self.engine_client.abort(request_id)
+
+
+async def warmup(engine_client, lengths):
+    """Generate a few tokens for a prompt of each length, before registering"""
+    for length in lengths:
+        prompt = TokensPrompt(prompt_token_ids=[WARMUP_TOKEN_ID] * length)
+        sampling_params = SamplingParams(max_tokens=WARMUP_MAX_TOKENS, ignore_eos=True)
+        start = time.monotonic()
+        try:
+            async for _ in engine_client.generate(
+                prompt, sampling_params, str(uuid.uuid4())
+            ):
+                pass
+        except Exception as e:
+            logging.warning(f"Warmup with a {length} token prompt failed: {e}")
+            continue
+        logging.info(
+            f"Warmed up with a {length} token prompt in {time.monotonic() - start:.2f}s"
+        )
 
 
 @dynamo_worker(static=False)
@@ -117,18 +237,46 @@ async def init(runtime: DistributedRuntime, config: Config):
 
     endpoint = component.endpoint(config.endpoint)
     await register_llm(
-        ModelType.Backend, endpoint, config.model_path, config.model_name
+        ModelType.Backend,
+        endpoint,
+        config.model_path,
+        config.model_name,
+        engine="vllm",
+        chat_template=config.chat_template,
     )
 
     arg_map = {
         "model": config.model_path,
         "task": "generate",
         "tensor_parallel_size": config.tensor_parallel_size,
+        "dtype": config.dtype,
         "skip_tokenizer_init": True,
         "disable_log_requests": True,
         # KV routing relies on logging KV metrics
         "disable_log_stats": False,
     }
+    if config.quantization == "int4":
+        # Quantized to 4 bits as the weights load
+        arg_map["quantization"] = "bitsandbytes"
+        arg_map["load_format"] = "bitsandbytes"
+    elif config.quantization:
+        arg_map["quantization"] = config.quantization
+    if config.gpu_memory_fraction > 0:
+        arg_map["gpu_memory_utilization"] = config.gpu_memory_fraction
+    if config.device:
+        arg_map["device"] = config.device
+    if config.max_loras > 0:
+        arg_map["enable_lora"] = True
+        arg_map["max_loras"] = config.max_loras
+    if config.max_prefill_chunk > 0:
+        # The step's token budget, decodes first and then prompt chunks
+        arg_map["enable_chunked_prefill"] = True
+        arg_map["max_num_batched_tokens"] = config.max_prefill_chunk
+    if config.draft_model:
+        arg_map["speculative_config"] = {
+            "model": config.draft_model,
+            "num_speculative_tokens": config.num_speculative_tokens,
+        }
     if config.extra_engine_args != "":
         json_map = {}
         # extra_engine_args is a filename
@@ -150,11 +298,15 @@ async def init(runtime: DistributedRuntime, config: Config):
 
     engine_context = build_async_engine_client_from_engine_args(engine_args)
     engine_client = await engine_context.__aenter__()
+    await warmup(engine_client, config.warmup)
 
     # the server will gracefully shutdown (i.e., keep opened TCP streams finishes)
     # after the lease is revoked
+    num_speculative_tokens = config.num_speculative_tokens if config.draft_model else 0
     await endpoint.serve_endpoint(
-        RequestHandler(engine_client, default_sampling_params).generate
+        RequestHandler(
+            engine_client, default_sampling_params, num_speculative_tokens
+        ).generate
     )
 
 
@@ -180,15 +332,72 @@ def cmd_line_args():
         default="",
         help="Name to serve the model under. Defaults to deriving it from model path.",
     )
+    parser.add_argument(
+        "--chat-template",
+        type=str,
+        default="",
+        help="Path to a Jinja chat template to use instead of the model's own.",
+    )
     parser.add_argument(
         "--tensor-parallel-size", type=int, default=1, help="Number of GPUs to use."
     )
+    parser.add_argument(
+        "--quantization",
+        type=str,
+        default="",
+        help="Quantize the weights as they load: fp8 or int4.",
+    )
+    parser.add_argument(
+        "--dtype",
+        type=str,
+        default="auto",
+        help="Torch dtype of the weights that aren't quantized, e.g. bfloat16.",
+    )
+    parser.add_argument(
+        "--gpu-memory-fraction",
+        type=float,
+        default=0.0,
+        help="Share of each GPU's memory for the weights and KV cache. 0 keeps vLLM's default.",
+    )
+    parser.add_argument(
+        "--device", type=str, default="", help="cpu, or the GPU if unset."
+    )
     parser.add_argument(
         "--extra-engine-args",
         type=str,
         default="",
         help="Path to a JSON file containing additional keyword arguments to pass to the vLLM AsyncLLMEngine.",
     )
+    parser.add_argument(
+        "--draft-model",
+        type=str,
+        default="",
+        help="Path or HuggingFace identifier of a draft model for speculative decoding.",
+    )
+    parser.add_argument(
+        "--num-speculative-tokens",
+        type=int,
+        default=5,
+        help="Tokens the draft model proposes per step.",
+    )
+    parser.add_argument(
+        "--max-loras",
+        type=int,
+        default=0,
+        help="LoRA adapters a batch can use at once. 0 disables LoRA.",
+    )
+    parser.add_argument(
+        "--max-prefill-chunk",
+        type=int,
+        default=0,
+        help="Most tokens per step with chunked prefill. 0 keeps vLLM's default.",
+    )
+    parser.add_argument(
+        "--warmup",
+        type=str,
+        default="",
+        help="Comma separated prompt lengths, in tokens, to generate for before registering.",
+    )
     args = parser.parse_args()
 
     config = Config()
@@ -198,6 +407,7 @@ def cmd_line_args():
     else:
         # This becomes an `Option` on the Rust side
         config.model_name = None
+    config.chat_template = args.chat_template or None
 
     endpoint_str = args.endpoint.replace("dyn://", "", 1)
     endpoint_parts = endpoint_str.split(".")
@@ -213,7 +423,16 @@ def cmd_line_args():
     config.component = parsed_component_name
     config.endpoint = parsed_endpoint_name
     config.tensor_parallel_size = args.tensor_parallel_size
+    config.quantization = args.quantization or None
+    config.dtype = args.dtype
+    config.gpu_memory_fraction = args.gpu_memory_fraction
+    config.device = args.device or None
     config.extra_engine_args = args.extra_engine_args
+    config.draft_model = args.draft_model or None
+    config.num_speculative_tokens = args.num_speculative_tokens
+    config.max_loras = args.max_loras
+    config.max_prefill_chunk = args.max_prefill_chunk
+    config.warmup = [int(n) for n in args.warmup.split(",") if n]
 
     return config
 
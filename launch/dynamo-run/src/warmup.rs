@@ -0,0 +1,152 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A few generations before taking traffic
+//!
+//! The first requests to an engine are slow: kernels compile, caches and memory pools fill
+//! up. With `--warmup <lengths>` the engine generates a few tokens for a prompt of each
+//! length, in tokens, once it is loaded and before the HTTP service serves it or the worker
+//! registers its endpoint. vllm and sglang warm up in their sub-process instead, before they
+//! register, and capture their CUDA graphs while loading.
+//!
+//! The warmup also measures how fast the engine generates for one request. Workers advertise
+//! it so that routers send more requests to the faster workers of a pool.
+
+use std::time::Instant;
+
+use dynamo_llm::local_model::LocalModel;
+use dynamo_llm::protocols::common::preprocessor::PreprocessedRequest;
+use dynamo_llm::protocols::common::StopConditions;
+use dynamo_llm::types::openai::chat_completions::NvCreateChatCompletionRequest;
+use dynamo_runtime::pipeline::Context;
+use dynamo_runtime::protocols::annotated::Annotated;
+use futures::{Stream, StreamExt};
+
+use crate::EngineConfig;
+
+/// Tokens to generate for each warmup prompt
+const WARMUP_MAX_TOKENS: u32 = 16;
+
+/// What the warmup prompts are made of
+const WARMUP_TEXT: &str = "The quick brown fox jumps over the lazy dog. ";
+
+/// Generate for a prompt of each of `lengths`. A failure is logged, the engine still serves.
+/// Returns the output tokens per second of the last prompt, the earlier ones include warming
+/// up. None if it failed or there were no lengths.
+pub async fn run(engine_config: &EngineConfig, lengths: &[u32]) -> Option<f32> {
+    let mut tokens_per_sec = None;
+    for &length in lengths {
+        let start = Instant::now();
+        let result = match engine_config {
+            EngineConfig::StaticFull { engine, .. } => full(engine.as_ref(), length).await,
+            EngineConfig::StaticCore { engine, model } => core(engine, model, length).await,
+            // The worker warms up before it registers
+            EngineConfig::Dynamic(_) => return None,
+            // The pool warms up each model as it loads it
+            EngineConfig::Pool(_) => return None,
+            // Each engine is warmed up as it starts
+            EngineConfig::Multi(_) => return None,
+        };
+        tokens_per_sec = match result {
+            Ok(speed) => {
+                tracing::info!(
+                    length,
+                    elapsed_ms = start.elapsed().as_millis(),
+                    tokens_per_sec = speed,
+                    "Warmed up"
+                );
+                speed
+            }
+            Err(err) => {
+                tracing::warn!(length, %err, "Warmup failed");
+                None
+            }
+        };
+    }
+    tokens_per_sec
+}
+
+/// The engine tokenizes, so the prompt is about `length` tokens
+async fn full(
+    engine: &dyn dynamo_llm::engines::StreamingEngine,
+    length: u32,
+) -> anyhow::Result<Option<f32>> {
+    // Each word and its space are about a token
+    let words: Vec<&str> = WARMUP_TEXT.split_whitespace().collect();
+    let prompt = words
+        .iter()
+        .cycle()
+        .take(length as usize)
+        .copied()
+        .collect::<Vec<_>>()
+        .join(" ");
+    let inner = async_openai::types::CreateChatCompletionRequestArgs::default()
+        .model("warmup")
+        .messages(vec![
+            async_openai::types::ChatCompletionRequestMessage::User(
+                async_openai::types::ChatCompletionRequestUserMessage {
+                    content: async_openai::types::ChatCompletionRequestUserMessageContent::Text(
+                        prompt,
+                    ),
+                    name: None,
+                },
+            ),
+        ])
+        .stream(true)
+        .max_completion_tokens(WARMUP_MAX_TOKENS)
+        .build()?;
+    let request = NvCreateChatCompletionRequest { inner, nvext: None };
+    drain(engine.handle_chat(Context::new(request)).await?).await
+}
+
+async fn core(
+    engine: &dynamo_llm::backend::ExecutionContext,
+    model: &LocalModel,
+    length: u32,
+) -> anyhow::Result<Option<f32>> {
+    let Some(tokenizer) = &model.card().tokenizer else {
+        anyhow::bail!("The model has no tokenizer");
+    };
+    let text = tokenizer.load()?.encode(WARMUP_TEXT)?.token_ids;
+    let token_ids = text.into_iter().cycle().take(length as usize).collect();
+    let request = PreprocessedRequest::builder()
+        .token_ids(token_ids)
+        .stop_conditions(StopConditions {
+            max_tokens: Some(WARMUP_MAX_TOKENS),
+            ignore_eos: Some(true),
+            ..Default::default()
+        })
+        .sampling_options(Default::default())
+        .build()?;
+    drain(engine.generate(Context::new(request)).await?).await
+}
+
+/// Read the whole response, failing on an error. Returns the responses per second after the
+/// first, each is about a token. None if there was only one.
+async fn drain<R>(
+    mut stream: impl Stream<Item = Annotated<R>> + Unpin,
+) -> anyhow::Result<Option<f32>> {
+    let mut first = None;
+    let mut count = 0;
+    while let Some(response) = stream.next().await {
+        response.ok().map_err(anyhow::Error::msg)?;
+        first.get_or_insert_with(Instant::now);
+        count += 1;
+    }
+    let elapsed = first.map(|first| first.elapsed().as_secs_f32());
+    Ok(elapsed
+        .filter(|secs| count > 1 && *secs > 0.0)
+        .map(|secs| (count - 1) as f32 / secs))
+}
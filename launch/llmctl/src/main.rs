@@ -91,6 +91,18 @@ define_type_subcommands!(
         ["completions", "completion-model"],
         "Add a completion model"
     ),
+    (
+        Embedding,
+        "embedding",
+        ["embeddings", "embedding-model"],
+        "Add an embedding model"
+    ),
+    (
+        Transcription,
+        "transcription",
+        ["transcriptions", "transcription-model"],
+        "Add a speech-to-text model"
+    ),
     // Add new model types here:
 );
 
@@ -259,6 +271,7 @@ async fn add_model(
         name: model_name.to_string(),
         endpoint,
         model_type,
+        vocab_checksum: None,
     };
 
     // add model to etcd
@@ -362,7 +375,12 @@ async fn list_models(
     let mut models = Vec::new();
     let model_types = match model_type {
         Some(mt) => vec![mt],
-        None => vec![ModelType::Chat, ModelType::Completion],
+        None => vec![
+            ModelType::Chat,
+            ModelType::Completion,
+            ModelType::Embedding,
+            ModelType::Transcription,
+        ],
     };
 
     // TODO: Do we need the model_type in etcd key?
@@ -101,27 +101,41 @@ fn log_message(level: &str, message: &str, module: &str, file: &str, line: u32)
 }
 
 #[pyfunction]
-#[pyo3(signature = (model_type, endpoint, model_path, model_name=None))]
+#[pyo3(signature = (model_type, endpoint, model_path, model_name=None, engine=None, chat_template=None))]
 fn register_llm<'p>(
     py: Python<'p>,
     model_type: ModelType,
     endpoint: Endpoint,
     model_path: &str,
     model_name: Option<&str>,
+    engine: Option<&str>,
+    chat_template: Option<&str>,
 ) -> PyResult<Bound<'p, PyAny>> {
     let model_type_obj = match model_type {
         ModelType::Chat => llm_rs::model_type::ModelType::Chat,
         ModelType::Completion => llm_rs::model_type::ModelType::Completion,
         ModelType::Backend => llm_rs::model_type::ModelType::Backend,
+        ModelType::Embedding => llm_rs::model_type::ModelType::Embedding,
+        ModelType::Transcription => llm_rs::model_type::ModelType::Transcription,
     };
 
     let inner_path = model_path.to_string();
     let model_name = model_name.map(|n| n.to_string());
+    let engine = engine.map(|e| e.to_string());
+    let chat_template = chat_template.map(std::path::PathBuf::from);
     pyo3_async_runtimes::tokio::future_into_py(py, async move {
         // Download from HF, load the ModelDeploymentCard
-        let mut local_model = llm_rs::LocalModel::prepare(&inner_path, None, model_name)
+        let mut local_model = llm_rs::LocalModel::prepare(&inner_path, None, model_name, None)
             .await
             .map_err(to_pyerr)?;
+        if let Some(engine) = engine {
+            local_model.set_engine(&engine);
+        }
+        if let Some(chat_template) = chat_template {
+            local_model
+                .set_chat_template(&chat_template)
+                .map_err(to_pyerr)?;
+        }
 
         // Advertise ourself on etcd so ingress can find us
         local_model
@@ -192,6 +206,8 @@ enum ModelType {
     Chat = 1,
     Completion = 2,
     Backend = 3,
+    Embedding = 4,
+    Transcription = 5,
 }
 
 #[pymethods]
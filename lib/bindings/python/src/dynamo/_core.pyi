@@ -591,11 +591,11 @@ class HttpAsyncEngine:
     ...
 
 class ModelType:
-    """What type of request this model needs: Chat, Component or Backend (pre-processed)"""
+    """What type of request this model needs: Chat, Component, Backend (pre-processed), Embedding or Transcription"""
     ...
 
-async def register_llm(model_type: ModelType, endpoint: Endpoint, model_path: str, model_name: Optional[str]) -> None:
-    """Attach the model at path to the given endpoint, and advertise it as model_type"""
+async def register_llm(model_type: ModelType, endpoint: Endpoint, model_path: str, model_name: Optional[str], engine: Optional[str] = None, chat_template: Optional[str] = None) -> None:
+    """Attach the model at path to the given endpoint, and advertise it as model_type. `engine` (e.g. "vllm") selects which sampling options the frontend passes on. `chat_template` is the path of a Jinja template to use instead of the model's own."""
     ...
 
 class NatsQueue:
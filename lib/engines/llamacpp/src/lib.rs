@@ -13,11 +13,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{
-    num::NonZeroU32,
-    path::Path,
-    sync::{Arc, Mutex, OnceLock},
-};
+use std::{collections::VecDeque, num::NonZeroU32, path::Path, sync::Arc};
 
 use async_stream::stream;
 use dynamo_runtime::engine::{AsyncEngine, AsyncEngineContextProvider, ResponseStream};
@@ -26,30 +22,49 @@ use dynamo_runtime::pipeline::{async_trait, Error, ManyOut, SingleIn};
 use dynamo_runtime::protocols::annotated::Annotated;
 use dynamo_runtime::{CancellationToken, ErrorContext, Result};
 use llama_cpp_2::{
-    context::{params::LlamaContextParams, LlamaContext},
+    context::{
+        params::{LlamaContextParams, RopeScalingType},
+        LlamaContext,
+    },
     llama_backend::LlamaBackend,
     llama_batch::LlamaBatch,
-    model::{params::LlamaModelParams, LlamaModel},
+    model::{
+        params::{LlamaModelParams, LlamaSplitMode},
+        LlamaModel,
+    },
     sampling::LlamaSampler,
-    token::LlamaToken,
+    token::{logit_bias::LlamaLogitBias, LlamaToken},
 };
 
 use dynamo_llm::backend::ExecutionContext;
-use dynamo_llm::protocols::common::llm_backend::{BackendInput, LLMEngineOutput};
+use dynamo_llm::engines::load::Device;
+use dynamo_llm::gguf::{GgufSettings, RopeScaling};
+use dynamo_llm::gpu_telemetry::report_kv_cache_usage;
+use dynamo_llm::grammar::json_schema_to_gbnf;
+use dynamo_llm::lora::LoraError;
+use dynamo_llm::preprocessor::media::MediaError;
+use dynamo_llm::protocols::common::llm_backend::{BackendInput, LLMEngineOutput, TokenLogProb};
 use dynamo_llm::protocols::common::preprocessor::PreprocessedRequest;
+use dynamo_llm::protocols::error::RequestError;
+use dynamo_llm::protocols::openai::nvext::Priority;
+
+mod plan;
+use plan::{Admission, Candidate, Occupant, Planner};
 
 /// If user does not provide a max_tokens limit prompt+output to this many
 const DEFAULT_MAX_TOKENS: u32 = 8192;
 
-// I'm not entirely sure what this is. The model context size surely comes from the GGUF??
-const CONTEXT_SIZE: u32 = 8192;
+/// Most tokens per sequence unless `--context-length` asks for more. Models are often trained
+/// on far longer contexts than that, and the KV cache is sized for `max_batch_size` sequences.
+pub const DEFAULT_CONTEXT_LENGTH: u32 = 8192;
+
+/// How many requests decode together by default. New requests join the running batch at the
+/// next decode step, up to this many.
+pub const DEFAULT_MAX_BATCH_SIZE: u32 = 3;
 
 static LLAMA_BACKEND: tokio::sync::OnceCell<LlamaBackend> = tokio::sync::OnceCell::const_new();
 pub(crate) static LLAMA_MODEL: tokio::sync::OnceCell<LlamaModel> =
     tokio::sync::OnceCell::const_new();
-const NUM_CONTEXTS: usize = 3;
-static LLAMA_CONTEXTS: [OnceLock<Mutex<ContextWrapper>>; NUM_CONTEXTS] =
-    [OnceLock::new(), OnceLock::new(), OnceLock::new()];
 
 // Newtype to simplify LlamaContext lifetime
 #[derive(Debug)]
@@ -57,17 +72,73 @@ struct ContextWrapper(LlamaContext<'static>);
 unsafe impl Send for ContextWrapper {} // LlamaContext has a NonNull which is !Send
 unsafe impl Sync for ContextWrapper {} // LlamaContext has a NonNull which is !Sync
 
+/// How to set up the llama context
+#[derive(Debug, Clone, Copy)]
+pub struct EngineOptions {
+    /// Most requests to decode together
+    pub max_batch_size: u32,
+
+    /// Tokens per sequence, prompt and completion, see [`context_length`]
+    pub context_length: u32,
+
+    /// Replaces the RoPE scaling in the GGUF
+    pub rope_scaling: Option<RopeScaling>,
+
+    /// Replaces the RoPE base frequency in the GGUF
+    pub rope_freq_base: Option<f32>,
+
+    /// Most prompt tokens a decode step takes. Longer prompts are prefilled over several
+    /// steps, so that they don't hold up the sequences decoding. None prefills each prompt in
+    /// one step.
+    pub max_prefill_chunk: Option<u32>,
+
+    /// While sequences decode, a step takes at most this many prompt tokens for each of them.
+    /// Lower keeps the inter-token latency of running requests steadier, higher starts new
+    /// ones sooner.
+    pub prefill_decode_ratio: Option<f32>,
+
+    /// When every sequence slot is taken, a waiting request of a higher priority preempts the
+    /// running one of the lowest priority, which resumes once a slot frees up
+    pub preemption: bool,
+
+    /// `cpu` keeps every layer off the GPU, `cuda:<index>` puts all of them on that GPU.
+    /// llama.cpp's default, layers split over the GPUs it sees, if None.
+    pub device: Option<Device>,
+
+    /// More than 1 splits the layers over the GPUs llama.cpp sees, whatever the device
+    pub tensor_parallel_size: u32,
+}
+
+/// Tokens per sequence: `requested`, or else the context length in the GGUF metadata up to
+/// [`DEFAULT_CONTEXT_LENGTH`]. The pre-processor must be told the same, so it truncates
+/// prompts to what fits.
+pub fn context_length(model_path: &Path, requested: Option<u32>) -> u32 {
+    if let Some(requested) = requested {
+        return requested;
+    }
+    match GgufSettings::from_file(model_path) {
+        Ok(GgufSettings {
+            context_length: Some(trained),
+            ..
+        }) => trained.min(DEFAULT_CONTEXT_LENGTH),
+        _ => DEFAULT_CONTEXT_LENGTH,
+    }
+}
+
 pub async fn make_engine(
     cancel_token: CancellationToken,
     model_path: &Path,
+    options: EngineOptions,
 ) -> pipeline_error::Result<ExecutionContext> {
-    let engine = LlamacppEngine::new(cancel_token, model_path).await?;
+    let engine = LlamacppEngine::new(cancel_token, model_path, options).await?;
     let engine: ExecutionContext = Arc::new(engine);
     Ok(engine)
 }
 
 struct WorkRequest {
     request: PreprocessedRequest,
+    /// GBNF grammar constraining the output, from `response_format`
+    grammar: Option<String>,
     response_channel: tokio::sync::mpsc::Sender<Annotated<LLMEngineOutput>>,
 }
 
@@ -80,29 +151,48 @@ impl LlamacppEngine {
     async fn new(
         cancel_token: CancellationToken,
         model_path: &Path,
+        options: EngineOptions,
     ) -> pipeline_error::Result<Self> {
+        let max_batch_size = options.max_batch_size.max(1);
+        let seq_context = options.context_length.max(1);
+        log_settings(model_path, &options);
         let backend = LlamaBackend::init()?;
-        let model = load_model(&backend, model_path)?;
+        let model = load_model(&backend, model_path, &options)?;
         LLAMA_MODEL.set(model)?;
 
-        let (ctx_set, ctx_get) = tokio::sync::mpsc::channel(NUM_CONTEXTS);
-        // Safety: NonZeroU32::new only errors if we give it a zero
-        let context_size = NonZeroU32::new(CONTEXT_SIZE).unwrap();
-        let llama_ctx_params = LlamaContextParams::default().with_n_ctx(Some(context_size));
-        for (i, ctx_holder) in LLAMA_CONTEXTS.iter().enumerate().take(NUM_CONTEXTS) {
-            let llama_ctx = LLAMA_MODEL
-                .get()
-                .unwrap() // Safety: We put it in a few lines up
-                .new_context(&backend, llama_ctx_params.clone())
-                .with_context(|| "unable to create the llama_context")?;
-            let _ = ctx_holder.set(Mutex::new(ContextWrapper(llama_ctx)));
-            let _ = ctx_set.send(i).await;
-        }
+        let context_size = seq_context
+            .checked_mul(max_batch_size)
+            .and_then(NonZeroU32::new)
+            .with_context(|| "context length times max batch size does not fit in a u32")?;
+        let llama_ctx_params = rope_params(
+            LlamaContextParams::default()
+                .with_n_ctx(Some(context_size))
+                .with_n_batch(seq_context)
+                .with_n_seq_max(max_batch_size),
+            &options,
+        );
+        let llama_ctx = LLAMA_MODEL
+            .get()
+            .unwrap() // Safety: We put it in a few lines up
+            .new_context(&backend, llama_ctx_params)
+            .with_context(|| "unable to create the llama_context")?;
         LLAMA_BACKEND.set(backend)?;
 
-        let (req_tx, req_rx) = tokio::sync::mpsc::channel(2);
+        let (req_tx, req_rx) = tokio::sync::mpsc::channel(max_batch_size as usize);
         let ct = cancel_token.clone();
-        tokio::task::spawn(worker(ct, req_rx, ctx_get, ctx_set));
+        let handle = tokio::runtime::Handle::current();
+        let planner = Planner::new(&options, seq_context as usize);
+        tokio::task::spawn_blocking(move || {
+            let mut scheduler = Scheduler::new(
+                ct,
+                req_rx,
+                ContextWrapper(llama_ctx),
+                max_batch_size as usize,
+                seq_context as usize,
+                planner,
+            );
+            scheduler.run(handle);
+        });
 
         Ok(LlamacppEngine {
             cancel_token,
@@ -111,14 +201,70 @@ impl LlamacppEngine {
     }
 }
 
-fn load_model(backend: &LlamaBackend, model_path: &Path) -> Result<LlamaModel> {
-    let model_params = {
-        if cfg!(any(feature = "cuda", feature = "vulkan")) {
-            LlamaModelParams::default().with_n_gpu_layers(1000)
-        } else {
-            LlamaModelParams::default()
+/// Only the overrides, llama.cpp reads the rest of the RoPE settings from the GGUF itself
+fn rope_params(params: LlamaContextParams, options: &EngineOptions) -> LlamaContextParams {
+    let params = match options.rope_freq_base {
+        Some(freq_base) => params.with_rope_freq_base(freq_base),
+        None => params,
+    };
+    match options.rope_scaling {
+        Some(RopeScaling::None) => params.with_rope_scaling_type(RopeScalingType::None),
+        Some(RopeScaling::Linear(factor)) => params
+            .with_rope_scaling_type(RopeScalingType::Linear)
+            .with_rope_freq_scale(1.0 / factor),
+        Some(RopeScaling::Yarn(factor)) => params
+            .with_rope_scaling_type(RopeScalingType::Yarn)
+            .with_rope_freq_scale(1.0 / factor),
+        None => params,
+    }
+}
+
+/// What the GGUF says and what we run with instead
+fn log_settings(model_path: &Path, options: &EngineOptions) {
+    let settings = match GgufSettings::from_file(model_path) {
+        Ok(settings) => settings,
+        Err(err) => {
+            tracing::warn!("Could not read the GGUF metadata: {err:#}");
+            return;
         }
     };
+    tracing::info!(
+        context_length = options.context_length,
+        rope_scaling = options.rope_scaling.map(|r| r.to_string()),
+        rope_freq_base = options.rope_freq_base,
+        "GGUF: {settings}"
+    );
+    let Some(trained) = settings.context_length else {
+        return;
+    };
+    let factor = options.rope_scaling.map_or(1.0, |r| r.factor());
+    if options.context_length as f32 > trained as f32 * factor {
+        tracing::warn!(
+            "Context length {} is longer than the {trained} tokens the model was trained on. \
+             Quality will likely drop, --rope-scaling may help.",
+            options.context_length
+        );
+    }
+}
+
+fn load_model(
+    backend: &LlamaBackend,
+    model_path: &Path,
+    options: &EngineOptions,
+) -> Result<LlamaModel> {
+    let mut model_params = LlamaModelParams::default();
+    if options.device == Some(Device::Cpu) {
+        model_params = model_params.with_n_gpu_layers(0);
+    } else if cfg!(any(feature = "cuda", feature = "vulkan")) {
+        model_params = model_params.with_n_gpu_layers(1000);
+        if options.tensor_parallel_size > 1 {
+            model_params = model_params.with_split_mode(LlamaSplitMode::Layer);
+        } else if let Some(Device::Cuda(index)) = options.device {
+            model_params = model_params
+                .with_split_mode(LlamaSplitMode::None)
+                .with_main_gpu(index as i32);
+        }
+    }
     LlamaModel::load_from_file(backend, model_path, &model_params)
         .with_context(|| "unable to load model")
 }
@@ -135,15 +281,47 @@ impl AsyncEngine<SingleIn<BackendInput>, ManyOut<Annotated<LLMEngineOutput>>, Er
         let ctx = context.context();
         let request_id = ctx.id().to_string();
 
+        if !request.images.is_empty() {
+            return Err(MediaError::image_not_supported("served by the llamacpp engine").into());
+        }
+        if request.lora.is_some() {
+            return Err(LoraError::NotSupported("by the llamacpp engine".to_string()).into());
+        }
+
+        let grammar = match request
+            .sampling_options
+            .guided_decoding
+            .as_ref()
+            .and_then(|guided| guided.json.as_ref())
+        {
+            Some(schema) => Some(json_schema_to_gbnf(schema).map_err(|err| {
+                RequestError::invalid_request(format!(
+                    "Unsupported response_format JSON schema: {err:#}"
+                ))
+            })?),
+            None => None,
+        };
+        if let Some(logit_bias) = &request.sampling_options.logit_bias {
+            let n_vocab = LLAMA_MODEL.get().unwrap().n_vocab();
+            if let Some(token_id) = logit_bias.keys().find(|id| **id as i64 >= n_vocab as i64) {
+                return Err(RequestError::invalid_request(format!(
+                    "logit_bias token {token_id} is not in the vocabulary"
+                ))
+                .into());
+            }
+        }
+
         let (tx, mut rx) = tokio::sync::mpsc::channel(128);
         let work_request = WorkRequest {
             request,
+            grammar,
             response_channel: tx,
         };
 
         self.req_tx.send(work_request).await?;
 
         let cancel_token = self.cancel_token.clone();
+        let request_ctx = ctx.clone();
         let output = stream! {
             loop {
                 tokio::select! {
@@ -151,6 +329,12 @@ impl AsyncEngine<SingleIn<BackendInput>, ManyOut<Annotated<LLMEngineOutput>>, Er
                         tracing::trace!(request_id, "LlamacppEngine.generate stopped by cancel token");
                         break;
                     }
+                    // Closing the channel has the scheduler drop the sequence
+                    _ = request_ctx.stopped() => {
+                        tracing::trace!(request_id, "LlamacppEngine.generate request stopped");
+                        yield Annotated::from_data(LLMEngineOutput::cancelled());
+                        break;
+                    }
                     from_llamacpp = rx.recv() => {
                         match from_llamacpp {
                             Some(out) => {
@@ -169,152 +353,548 @@ impl AsyncEngine<SingleIn<BackendInput>, ManyOut<Annotated<LLMEngineOutput>>, Er
     }
 }
 
-// Run this in a thread
-async fn worker(
+/// One request in the running batch
+struct Sequence {
+    seq_id: i32,
+    work_request: WorkRequest,
+    sampler: LlamaSampler,
+    /// Position of the next token in this sequence
+    n_cur: i32,
+    /// Prompt tokens not prefilled yet, with chunked prefill
+    pending_prompt: VecDeque<LlamaToken>,
+    /// Where this sequence's logits are in the current batch, None if it has none this step
+    /// because it is still prefilling
+    logits_idx: Option<i32>,
+    /// The token we sampled last step, fed back in on the next
+    last_token: LlamaToken,
+    used_output_tokens: u32,
+    max_output_tokens: u32,
+    priority: Priority,
+    /// The tokens in the KV cache, prefilled again to resume after a preemption
+    fed: Vec<LlamaToken>,
+}
+
+impl Sequence {
+    fn send(&self, out: LLMEngineOutput) -> bool {
+        self.work_request
+            .response_channel
+            .blocking_send(Annotated::from_data(out))
+            .is_ok()
+    }
+}
+
+/// Add the next `n` tokens of the sequence's prompt to the batch. llama_decode only needs
+/// logits for the last token of the prompt, the one the first output token is sampled from.
+fn feed_prompt(batch: &mut LlamaBatch, seq: &mut Sequence, n: usize) -> Result<()> {
+    for _ in 0..n {
+        let Some(token) = seq.pending_prompt.pop_front() else {
+            break;
+        };
+        let is_last = seq.pending_prompt.is_empty();
+        if is_last {
+            seq.logits_idx = Some(batch.n_tokens());
+        }
+        batch
+            .add(token, seq.n_cur, &[seq.seq_id], is_last)
+            .with_context(|| format!("Failed adding token pos {} to batch", seq.n_cur))?;
+        seq.n_cur += 1;
+        seq.fed.push(token);
+    }
+    Ok(())
+}
+
+/// The log probability of `token` and the `top` most likely tokens, from the raw logits of
+/// its position. The text of the tokens is filled in by the backend.
+fn token_logprobs(logits: &[f32], token: LlamaToken, top: usize) -> (f64, Vec<TokenLogProb>) {
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max) as f64;
+    let log_sum = max
+        + logits
+            .iter()
+            .map(|logit| (*logit as f64 - max).exp())
+            .sum::<f64>()
+            .ln();
+    let logprob = |id: usize| TokenLogProb {
+        token_id: id as u32,
+        token: None,
+        logprob: logits[id] as f64 - log_sum,
+    };
+
+    let mut ids: Vec<usize> = (0..logits.len()).collect();
+    let by_logit = |a: &usize, b: &usize| logits[*b].total_cmp(&logits[*a]);
+    if top > 0 && top < ids.len() {
+        ids.select_nth_unstable_by(top - 1, by_logit);
+    }
+    ids.truncate(top);
+    ids.sort_by(by_logit);
+    (
+        logprob(token.0 as usize).logprob,
+        ids.into_iter().map(logprob).collect(),
+    )
+}
+
+/// Continuous batching over a single llama.cpp context. The [`Planner`] decides who gets a
+/// slot and how many prompt tokens, this runs it.
+///
+/// Every decode step runs one token for each running sequence. New requests are backfilled
+/// into the same step (their whole prompt) as long as there is a free sequence slot and room
+/// in the batch, so a short request doesn't wait for long ones to finish.
+///
+/// With prefill chunking a step only takes so many prompt tokens. A longer prompt is fed a
+/// chunk per step, and samples its first token in the step with its last chunk, while the
+/// other sequences keep decoding.
+///
+/// With preemption, a request that finds every slot taken by requests of a lower priority
+/// takes the slot of the lowest, latest started, of them. The KV cache of one sequence can't be
+/// copied out of the llama context, so the preempted sequence keeps its tokens in host memory
+/// and prefills them again when it resumes. It resumes before new requests of its priority.
+struct Scheduler {
     cancel_token: CancellationToken,
-    mut req_rx: tokio::sync::mpsc::Receiver<WorkRequest>,
-    mut ctx_get: tokio::sync::mpsc::Receiver<usize>,
-    ctx_set: tokio::sync::mpsc::Sender<usize>,
-) {
-    loop {
-        let maybe_work_request = tokio::select! {
-            _ = cancel_token.cancelled() => {
-                break;
+    req_rx: tokio::sync::mpsc::Receiver<WorkRequest>,
+    llama_context: ContextWrapper,
+    batch: LlamaBatch,
+    running: Vec<Sequence>,
+    free_seq_ids: Vec<i32>,
+    /// A request that didn't fit in the last batch. It goes first next step.
+    waiting: Option<WorkRequest>,
+    /// Tokens the llama context has room for, shared by all the sequences
+    context_size: usize,
+    planner: Planner,
+    /// Sequences out of the KV cache until a slot frees up, in the order they were preempted
+    preempted: Vec<Sequence>,
+}
+
+impl Scheduler {
+    fn new(
+        cancel_token: CancellationToken,
+        req_rx: tokio::sync::mpsc::Receiver<WorkRequest>,
+        llama_context: ContextWrapper,
+        max_batch_size: usize,
+        seq_context: usize,
+        planner: Planner,
+    ) -> Self {
+        Scheduler {
+            cancel_token,
+            req_rx,
+            llama_context,
+            batch: LlamaBatch::new(seq_context, max_batch_size as i32),
+            running: Vec::with_capacity(max_batch_size),
+            free_seq_ids: (0..max_batch_size as i32).rev().collect(),
+            waiting: None,
+            context_size: seq_context * max_batch_size,
+            planner,
+            preempted: Vec::new(),
+        }
+    }
+
+    // Runs on a blocking thread. `handle` lets us wait for work without spinning.
+    fn run(&mut self, handle: tokio::runtime::Handle) {
+        while !self.cancel_token.is_cancelled() {
+            self.batch.clear();
+            self.retire_cancelled();
+            if self.planner.preemption() {
+                self.preempt();
             }
-            maybe_work_request = req_rx.recv() => {
-                maybe_work_request
+            let decoding = match self.add_running() {
+                Ok(decoding) => decoding,
+                Err(err) => {
+                    self.fail_all(&format!("{err:#}"));
+                    continue;
+                }
+            };
+            let mut budget = self.prefill_budget(decoding);
+            if let Err(err) = self.continue_prefill(&mut budget) {
+                self.fail_all(&format!("{err:#}"));
+                continue;
             }
-        };
-        let Some(work_request) = maybe_work_request else {
-            tracing::error!("llamacpp work request sender channel closed. Worker exit");
-            break;
-        };
-        // will block if there are already NUM_CONTEXTS requests in flight
-        let Some(ctx_pos) = ctx_get.recv().await else {
-            unreachable!("We don't close ctx_set");
-        };
-        let ct = cancel_token.clone();
-        let inner_ctx_set = ctx_set.clone();
 
-        tokio::task::spawn_blocking(move || {
-            let mut ctx = LLAMA_CONTEXTS[ctx_pos].get().unwrap().lock().unwrap();
-            if let Err(err) = run_request(ct, work_request, &mut ctx) {
-                tracing::error!("run_request error: {err:#}");
+            // Nothing running, block until a request arrives
+            if self.running.is_empty() && self.waiting.is_none() && self.preempted.is_empty() {
+                let ct = self.cancel_token.clone();
+                let maybe_work_request = handle.block_on(async {
+                    tokio::select! {
+                        _ = ct.cancelled() => None,
+                        maybe_work_request = self.req_rx.recv() => maybe_work_request,
+                    }
+                });
+                match maybe_work_request {
+                    Some(work_request) => self.waiting = Some(work_request),
+                    None => {
+                        if !self.cancel_token.is_cancelled() {
+                            tracing::error!(
+                                "llamacpp work request sender channel closed. Worker exit"
+                            );
+                        }
+                        break;
+                    }
+                }
+            }
+            self.backfill(budget);
+
+            if self.batch.n_tokens() == 0 {
+                continue;
+            }
+
+            // "decode" means "run forward pass"
+            if let Err(err) = self.llama_context.0.decode(&mut self.batch) {
+                self.fail_all(&format!("llama_decode failed: {err}"));
+                continue;
             }
-            let _ = inner_ctx_set.blocking_send(ctx_pos);
+            self.sample();
+            let used: usize = self.running.iter().map(|seq| seq.n_cur as usize).sum();
+            report_kv_cache_usage(used, self.context_size);
+        }
+
+        for seq in self.running.drain(..).chain(self.preempted.drain(..)) {
+            let _ = seq.send(LLMEngineOutput::stop());
+        }
+    }
+
+    /// Drop the sequences whose client went away, before we spend another step on them
+    fn retire_cancelled(&mut self) {
+        let llama_context = &mut self.llama_context;
+        let free_seq_ids = &mut self.free_seq_ids;
+        self.running.retain(|seq| {
+            if !seq.work_request.response_channel.is_closed() {
+                return true;
+            }
+            tracing::debug!(seq.seq_id, "Request cancelled");
+            let _ = llama_context
+                .0
+                .clear_kv_cache_seq(Some(seq.seq_id as u32), None, None);
+            free_seq_ids.push(seq.seq_id);
+            false
         });
+        self.preempted
+            .retain(|seq| !seq.work_request.response_channel.is_closed());
+        if self
+            .waiting
+            .as_ref()
+            .is_some_and(|work_request| work_request.response_channel.is_closed())
+        {
+            self.waiting = None;
+        }
     }
-}
 
-fn run_request(
-    cancel_token: CancellationToken,
-    work_request: WorkRequest,
-    llama_context: &mut ContextWrapper,
-) -> Result<()> {
-    let tokens_list: Vec<LlamaToken> = work_request
-        .request
-        .token_ids
-        .into_iter()
-        .map(|u| LlamaToken::new(u as i32))
-        .collect();
-
-    let limit = DEFAULT_MAX_TOKENS; // - prompt_tokens;
-    let max_output_tokens = std::cmp::min(
-        work_request
-            .request
-            .stop_conditions
-            .max_tokens
-            .unwrap_or(limit),
-        limit,
-    );
+    /// Add the next token of every decoding sequence to the batch. Returns how many there are.
+    fn add_running(&mut self) -> Result<usize> {
+        let mut decoding = 0;
+        for seq in self.running.iter_mut() {
+            if !seq.pending_prompt.is_empty() {
+                seq.logits_idx = None;
+                continue;
+            }
+            seq.logits_idx = Some(self.batch.n_tokens());
+            self.batch
+                .add(seq.last_token, seq.n_cur, &[seq.seq_id], true)
+                .with_context(|| format!("Failed adding token pos {} to batch", seq.n_cur))?;
+            seq.n_cur += 1;
+            seq.fed.push(seq.last_token);
+            decoding += 1;
+        }
+        Ok(decoding)
+    }
 
-    // we use this object to submit token data for decoding
-    let mut batch = LlamaBatch::new(std::cmp::max(512, max_output_tokens as usize), 1);
-    let last_index: i32 = (tokens_list.len() - 1) as i32;
-    for (i, token) in (0_i32..).zip(tokens_list.into_iter()) {
-        // llama_decode will output logits only for the last token of the prompt
-        let is_last = i == last_index;
-        batch
-            .add(token, i, &[0], is_last)
-            .with_context(|| format!("Failed adding token pos {i} to batch"))?;
+    /// Prompt tokens this step can take
+    fn prefill_budget(&self, decoding: usize) -> usize {
+        self.planner
+            .prefill_budget(self.batch.n_tokens() as usize, decoding)
     }
 
-    // "decode" means "run forward pass"
-    llama_context
-        .0
-        .decode(&mut batch)
-        .with_context(|| "llama_decode failed on first pass")?;
-
-    let mut sampler = LlamaSampler::greedy();
-    let mut n_cur = batch.n_tokens() as u32;
-
-    let mut used_output_tokens = 0;
-    while !cancel_token.is_cancelled() {
-        // sample the next token
-        let n_tokens = batch.n_tokens();
-        let token = sampler.sample(&llama_context.0, n_tokens - 1);
-        sampler.accept(token);
-
-        // is it an end of stream?
-        // This is probably safe for concurrent access
-        if LLAMA_MODEL.get().unwrap().is_eog_token(token) {
-            work_request
-                .response_channel
-                .blocking_send(Annotated::from_data(LLMEngineOutput::stop()))
-                .with_context(|| "Failed sending stop to response_channel")?;
-            break;
+    /// Feed the sequences part way through their prompt its next chunk, oldest first
+    fn continue_prefill(&mut self, budget: &mut usize) -> Result<()> {
+        for seq in self.running.iter_mut() {
+            if *budget == 0 {
+                break;
+            }
+            if seq.pending_prompt.is_empty() {
+                continue;
+            }
+            let n = seq.pending_prompt.len().min(*budget);
+            feed_prompt(&mut self.batch, seq, n)?;
+            *budget -= n;
         }
+        Ok(())
+    }
 
-        let engine_out = LLMEngineOutput {
-            // todo - propagate mdcsum
-            token_ids: vec![token.0 as u32],
-            tokens: None,
-            text: None,
-            //text: if output.text.is_empty() { None } else { Some(output.text) },
-            cum_log_probs: None, // TODO output.cumulative_logprob.map(|v| v as f64),
-            log_probs: None,     // TODO  output.logprobs
-            finish_reason: None,
+    /// Take the slot of a running sequence of a lower priority than the waiting request, when
+    /// there is no free one
+    fn preempt(&mut self) {
+        if !self.free_seq_ids.is_empty() {
+            return;
+        }
+        if self.waiting.is_none() {
+            self.waiting = self.req_rx.try_recv().ok();
+        }
+        let Some(waiting) = &self.waiting else {
+            return;
+        };
+        let running: Vec<Occupant> = self
+            .running
+            .iter()
+            .map(|seq| Occupant {
+                priority: seq.priority,
+                fed: seq.fed.len(),
+            })
+            .collect();
+        let Some(idx) = self.planner.victim(waiting.request.priority, &running) else {
+            return;
         };
-        work_request
-            .response_channel
-            .blocking_send(Annotated::from_data(engine_out))
-            .with_context(|| "Failed forwarding engine output to response_channel")?;
 
-        batch.clear();
-        if let Err(err) = batch.add(token, n_cur as i32, &[0], true) {
-            let err_msg = format!(
-                "batch add error, probably insufficient space in buffer, aborting request. {err}."
-            );
-            tracing::error!(err_msg);
-            let _ = work_request
-                .response_channel
-                .blocking_send(Annotated::from_data(LLMEngineOutput::error(err_msg)));
-            break;
+        let mut seq = self.running.remove(idx);
+        let _ = self
+            .llama_context
+            .0
+            .clear_kv_cache_seq(Some(seq.seq_id as u32), None, None);
+        self.free_seq_ids.push(seq.seq_id);
+        // A decoding sequence has sampled a token it didn't feed yet. Prefilled after the
+        // others, its logits give the next token.
+        if seq.pending_prompt.is_empty() {
+            seq.fed.push(seq.last_token);
         }
-        n_cur += 1;
+        let mut replay: VecDeque<LlamaToken> = std::mem::take(&mut seq.fed).into();
+        replay.append(&mut seq.pending_prompt);
+        seq.pending_prompt = replay;
+        seq.n_cur = 0;
+        seq.logits_idx = None;
+        tracing::debug!(
+            seq.seq_id,
+            priority = %seq.priority,
+            tokens = seq.pending_prompt.len(),
+            "Preempted a request for one of a higher priority"
+        );
+        self.preempted.push(seq);
+    }
 
-        used_output_tokens += 1;
-        if used_output_tokens > max_output_tokens {
-            let _ = work_request
-                .response_channel
-                .blocking_send(Annotated::from_data(LLMEngineOutput::length()));
-            break;
+    /// Put preempted sequence `idx` back in a free slot, prefilling `chunk` of its tokens again
+    fn resume(&mut self, idx: usize, chunk: usize) {
+        let mut seq = self.preempted.remove(idx);
+        // Safety: backfill only resumes with a free slot
+        seq.seq_id = self.free_seq_ids.pop().unwrap();
+        if let Err(err) = feed_prompt(&mut self.batch, &mut seq, chunk) {
+            tracing::error!(seq.seq_id, "Failed resuming a preempted request: {err:#}");
+            let _ = seq.send(LLMEngineOutput::failed(RequestError::engine_failure(
+                format!("{err:#}"),
+            )));
+            let _ = self
+                .llama_context
+                .0
+                .clear_kv_cache_seq(Some(seq.seq_id as u32), None, None);
+            self.free_seq_ids.push(seq.seq_id);
+            return;
         }
+        tracing::debug!(seq.seq_id, "Resumed a preempted request");
+        self.running.push(seq);
+    }
 
-        llama_context
-            .0
-            .decode(&mut batch)
-            .with_context(|| "llama_decode failed during loop")?;
+    /// Start as many new requests as we have room for in this step, with `budget` prompt tokens
+    fn backfill(&mut self, mut budget: usize) {
+        while !self.free_seq_ids.is_empty() {
+            if self.waiting.is_none() {
+                self.waiting = self.req_rx.try_recv().ok();
+            }
+            if self
+                .waiting
+                .as_ref()
+                .is_some_and(|work_request| work_request.response_channel.is_closed())
+            {
+                // Cancelled while queued
+                self.waiting = None;
+                continue;
+            }
+            let preempted: Vec<Candidate> = self
+                .preempted
+                .iter()
+                .map(|seq| Candidate {
+                    priority: seq.priority,
+                    tokens: seq.pending_prompt.len(),
+                })
+                .collect();
+            let waiting = self.waiting.as_ref().map(|work_request| Candidate {
+                priority: work_request.request.priority,
+                tokens: work_request.request.token_ids.len(),
+            });
+
+            match self.planner.admit(budget, &preempted, waiting.as_ref()) {
+                Admission::Resume { idx, chunk } => {
+                    budget -= chunk;
+                    self.resume(idx, chunk);
+                }
+                Admission::Start { chunk } => {
+                    budget -= chunk;
+                    // Safety: admit only starts a waiting request, and we checked there is a
+                    // free slot in the while condition
+                    let work_request = self.waiting.take().unwrap();
+                    let seq_id = self.free_seq_ids.pop().unwrap();
+                    if let Err(err) = self.add_prompt(seq_id, work_request, chunk) {
+                        tracing::error!(seq_id, "Failed adding prompt to batch: {err:#}");
+                        let _ = self.llama_context.0.clear_kv_cache_seq(
+                            Some(seq_id as u32),
+                            None,
+                            None,
+                        );
+                        self.free_seq_ids.push(seq_id);
+                    }
+                }
+                Admission::Reject(err) => {
+                    if let Some(work_request) = self.waiting.take() {
+                        let _ = work_request
+                            .response_channel
+                            .blocking_send(Annotated::from_data(LLMEngineOutput::failed(err)));
+                    }
+                }
+                // No room in this step, the waiting request goes first next time
+                Admission::Defer | Admission::Idle => break,
+            }
+        }
     }
-    if cancel_token.is_cancelled() {
-        let _ = work_request
-            .response_channel
-            .blocking_send(Annotated::from_data(LLMEngineOutput::stop()));
+
+    /// Start a sequence with the first `chunk` tokens of its prompt
+    fn add_prompt(&mut self, seq_id: i32, work_request: WorkRequest, chunk: usize) -> Result<()> {
+        let model = LLAMA_MODEL.get().unwrap();
+        let mut samplers = vec![];
+        if let Some(logit_bias) = &work_request.request.sampling_options.logit_bias {
+            let biases: Vec<_> = logit_bias
+                .iter()
+                .map(|(token_id, bias)| {
+                    LlamaLogitBias::new(LlamaToken::new(*token_id as i32), *bias)
+                })
+                .collect();
+            samplers.push(LlamaSampler::logit_bias(model.n_vocab(), &biases));
+        }
+        if let Some(grammar) = work_request.grammar.as_deref() {
+            let Some(grammar) = LlamaSampler::grammar(model, grammar, "root") else {
+                let _ = work_request
+                    .response_channel
+                    .blocking_send(Annotated::from_data(LLMEngineOutput::failed(
+                        RequestError::invalid_request(
+                            "llama.cpp could not parse the response_format grammar",
+                        ),
+                    )));
+                dynamo_runtime::raise!("Invalid grammar for seq {seq_id}");
+            };
+            samplers.push(grammar);
+        }
+        samplers.push(LlamaSampler::greedy());
+        let sampler = LlamaSampler::chain_simple(samplers);
+
+        let pending_prompt: VecDeque<LlamaToken> = work_request
+            .request
+            .token_ids
+            .iter()
+            .map(|u| LlamaToken::new(*u as i32))
+            .collect();
+
+        // Whatever the request asks for, the sequence ends where its context does
+        let requested = work_request
+            .request
+            .stop_conditions
+            .max_tokens
+            .unwrap_or(DEFAULT_MAX_TOKENS)
+            .min(DEFAULT_MAX_TOKENS);
+        let max_output_tokens = self
+            .planner
+            .max_output_tokens(pending_prompt.len(), requested);
+
+        let priority = work_request.request.priority;
+        let mut seq = Sequence {
+            seq_id,
+            work_request,
+            sampler,
+            n_cur: 0,
+            pending_prompt,
+            logits_idx: None,
+            last_token: LlamaToken::new(0), // replaced when we sample
+            used_output_tokens: 0,
+            max_output_tokens,
+            priority,
+            fed: Vec::new(),
+        };
+        if let Err(err) = feed_prompt(&mut self.batch, &mut seq, chunk) {
+            let _ = seq
+                .work_request
+                .response_channel
+                .blocking_send(Annotated::from_data(LLMEngineOutput::failed(
+                    RequestError::engine_failure(format!("{err:#}")),
+                )));
+            return Err(err);
+        }
+        self.running.push(seq);
+        Ok(())
     }
 
-    // Clean context for next use
-    llama_context.0.clear_kv_cache();
-    llama_context.0.reset_timings();
+    /// Sample one token for every sequence in the batch and retire finished ones
+    fn sample(&mut self) {
+        let llama_context = &mut self.llama_context;
+        let free_seq_ids = &mut self.free_seq_ids;
+        let planner = self.planner;
+        self.running.retain_mut(|seq| {
+            // Still prefilling
+            let Some(logits_idx) = seq.logits_idx else {
+                return true;
+            };
+            let token = seq.sampler.sample(&llama_context.0, logits_idx);
+            seq.sampler.accept(token);
+
+            // is it an end of stream?
+            // This is probably safe for concurrent access
+            let is_done = if LLAMA_MODEL.get().unwrap().is_eog_token(token) {
+                let _ = seq.send(LLMEngineOutput::stop());
+                true
+            } else {
+                let logprobs = seq.work_request.request.output_options.logprobs.map(|top| {
+                    let logits = llama_context.0.get_logits_ith(logits_idx);
+                    token_logprobs(logits, token, top as usize)
+                });
+                let (log_probs, top_log_probs) = match logprobs {
+                    Some((logprob, top)) => (Some(vec![logprob]), Some(vec![top])),
+                    None => (None, None),
+                };
+                let engine_out = LLMEngineOutput {
+                    // todo - propagate mdcsum
+                    token_ids: vec![token.0 as u32],
+                    tokens: None,
+                    text: None,
+                    cum_log_probs: None, // TODO output.cumulative_logprob.map(|v| v as f64),
+                    log_probs,
+                    top_log_probs,
+                    finish_reason: None,
+                    spec_decode: None,
+                };
+                seq.used_output_tokens += 1;
+                if !seq.send(engine_out) {
+                    // Client went away
+                    true
+                } else if seq.used_output_tokens >= seq.max_output_tokens
+                    || planner.context_full(seq.n_cur as usize)
+                {
+                    let _ = seq.send(LLMEngineOutput::length());
+                    true
+                } else {
+                    seq.last_token = token;
+                    false
+                }
+            };
+
+            if is_done {
+                // Clean this sequence out of the KV cache for the next user of the slot
+                let _ = llama_context
+                    .0
+                    .clear_kv_cache_seq(Some(seq.seq_id as u32), None, None);
+                free_seq_ids.push(seq.seq_id);
+            }
+            !is_done
+        });
+    }
 
-    Ok(())
+    /// Error out everything running. Used when a decode fails, which poisons the whole batch.
+    fn fail_all(&mut self, err_msg: &str) {
+        tracing::error!(err_msg);
+        for seq in self.running.drain(..) {
+            let _ = seq.send(LLMEngineOutput::failed(RequestError::engine_failure(
+                err_msg,
+            )));
+            self.free_seq_ids.push(seq.seq_id);
+        }
+        self.llama_context.0.clear_kv_cache();
+    }
 }
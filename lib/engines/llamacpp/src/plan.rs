@@ -0,0 +1,389 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Which sequences get a slot and how many prompt tokens each step takes. No llama.cpp in
+//! here, the [`crate::Scheduler`] asks the [`Planner`] and does what it says.
+
+use dynamo_llm::protocols::error::{ErrorKind, RequestError};
+use dynamo_llm::protocols::openai::nvext::Priority;
+
+use crate::EngineOptions;
+
+/// How prompts are split over decode steps
+#[derive(Debug, Clone, Copy)]
+struct PrefillChunking {
+    max_chunk: usize,
+    decode_ratio: Option<f32>,
+}
+
+impl PrefillChunking {
+    fn new(options: &EngineOptions, seq_context: usize) -> Option<Self> {
+        if options.max_prefill_chunk.is_none() && options.prefill_decode_ratio.is_none() {
+            return None;
+        }
+        Some(PrefillChunking {
+            max_chunk: options
+                .max_prefill_chunk
+                .map_or(seq_context, |chunk| chunk.max(1) as usize),
+            decode_ratio: options.prefill_decode_ratio,
+        })
+    }
+
+    /// Prompt tokens a step can take alongside `decoding` decoding sequences
+    fn budget(&self, decoding: usize) -> usize {
+        match self.decode_ratio {
+            Some(ratio) if decoding > 0 => {
+                let share = ((ratio * decoding as f32).ceil() as usize).max(1);
+                share.min(self.max_chunk)
+            }
+            _ => self.max_chunk,
+        }
+    }
+}
+
+/// Where `priority` comes in [`Priority::ALL`], 0 is the highest
+fn rank(priority: Priority) -> usize {
+    Priority::ALL.iter().position(|p| *p == priority).unwrap()
+}
+
+/// A request waiting for a slot, new or preempted
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Candidate {
+    pub priority: Priority,
+    /// Tokens to prefill before it samples. For a preempted sequence, those it had in the KV
+    /// cache and what was left of its prompt.
+    pub tokens: usize,
+}
+
+/// A sequence holding a slot
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Occupant {
+    pub priority: Priority,
+    /// Tokens in its KV cache
+    pub fed: usize,
+}
+
+/// What to do with the next free slot
+#[derive(Debug)]
+pub(crate) enum Admission {
+    /// Put preempted sequence `idx` back, prefilling `chunk` of its tokens this step
+    Resume { idx: usize, chunk: usize },
+    /// Start the waiting request with the first `chunk` tokens of its prompt
+    Start { chunk: usize },
+    /// The waiting request can never run, fail it and ask again
+    Reject(RequestError),
+    /// Not enough room in this step. The waiting request stays first in line.
+    Defer,
+    /// Nobody is waiting
+    Idle,
+}
+
+/// The scheduling decisions, see [`crate::Scheduler`] for how they play out
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Planner {
+    /// Tokens each sequence has room for, also the most a batch holds
+    seq_context: usize,
+    /// None to prefill whole prompts
+    chunking: Option<PrefillChunking>,
+    preemption: bool,
+}
+
+impl Planner {
+    pub fn new(options: &EngineOptions, seq_context: usize) -> Self {
+        Planner {
+            seq_context,
+            chunking: PrefillChunking::new(options, seq_context),
+            preemption: options.preemption,
+        }
+    }
+
+    pub fn preemption(&self) -> bool {
+        self.preemption
+    }
+
+    /// Prompt tokens a step can take once `batch_tokens` are in the batch, `decoding` of them
+    /// for decoding sequences: the room left in the batch, within the chunking
+    pub fn prefill_budget(&self, batch_tokens: usize, decoding: usize) -> usize {
+        let room = self.seq_context.saturating_sub(batch_tokens);
+        match &self.chunking {
+            Some(chunking) => chunking.budget(decoding).min(room),
+            None => room,
+        }
+    }
+
+    /// Who gets the next free slot, with `budget` prompt tokens left in this step. A preempted
+    /// sequence goes before a new request of the same or a lower priority, the highest
+    /// priority and earliest preempted first.
+    pub fn admit(
+        &self,
+        budget: usize,
+        preempted: &[Candidate],
+        waiting: Option<&Candidate>,
+    ) -> Admission {
+        // min_by_key keeps the first of equals
+        let resume = preempted
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, seq)| rank(seq.priority))
+            .filter(|(_, seq)| waiting.is_none_or(|w| rank(seq.priority) <= rank(w.priority)));
+        if let Some((idx, seq)) = resume {
+            return match self.start(seq.tokens, budget) {
+                Some(chunk) => Admission::Resume { idx, chunk },
+                None => Admission::Defer,
+            };
+        }
+
+        let Some(waiting) = waiting else {
+            return Admission::Idle;
+        };
+        if waiting.tokens == 0 || waiting.tokens >= self.seq_context {
+            return Admission::Reject(RequestError::new(
+                ErrorKind::ContextLengthExceeded,
+                format!(
+                    "Prompt must be between 1 and {} tokens, got {}",
+                    self.seq_context, waiting.tokens
+                ),
+            ));
+        }
+        match self.start(waiting.tokens, budget) {
+            Some(chunk) => Admission::Start { chunk },
+            None => Admission::Defer,
+        }
+    }
+
+    /// The most tokens a sequence with a prompt of `prompt_tokens` may generate when the
+    /// request asks for up to `requested`: no more than its context has room for
+    pub fn max_output_tokens(&self, prompt_tokens: usize, requested: u32) -> u32 {
+        let room = self.seq_context.saturating_sub(prompt_tokens);
+        requested.min(u32::try_from(room).unwrap_or(u32::MAX))
+    }
+
+    /// Whether a sequence whose next token goes at position `n_cur` has filled its context.
+    /// It has to finish, that token would not fit.
+    pub fn context_full(&self, n_cur: usize) -> bool {
+        n_cur >= self.seq_context
+    }
+
+    /// Tokens to prefill now to start a sequence of `tokens`, None if `budget` is too small.
+    /// Chunked, a prompt starts with whatever room there is, else it needs all of it.
+    fn start(&self, tokens: usize, budget: usize) -> Option<usize> {
+        let needed = match self.chunking {
+            Some(_) => 1,
+            None => tokens,
+        };
+        (needed <= budget).then(|| budget.min(tokens))
+    }
+
+    /// The occupant to preempt for a request of `waiting` priority when every slot is taken:
+    /// of the lowest priority below it, the latest started. Sequences about to run out of
+    /// context are left alone, they could not be prefilled again with a token to sample.
+    pub fn victim(&self, waiting: Priority, running: &[Occupant]) -> Option<usize> {
+        if !self.preemption {
+            return None;
+        }
+        let waiting_rank = rank(waiting);
+        // max_by_key keeps the last of equals, the latest started
+        running
+            .iter()
+            .enumerate()
+            .filter(|(_, seq)| rank(seq.priority) > waiting_rank && seq.fed + 1 < self.seq_context)
+            .max_by_key(|(_, seq)| rank(seq.priority))
+            .map(|(idx, _)| idx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> EngineOptions {
+        EngineOptions {
+            max_batch_size: 2,
+            context_length: 100,
+            rope_scaling: None,
+            rope_freq_base: None,
+            max_prefill_chunk: None,
+            prefill_decode_ratio: None,
+            preemption: false,
+            device: None,
+            tensor_parallel_size: 1,
+        }
+    }
+
+    fn candidate(priority: Priority, tokens: usize) -> Candidate {
+        Candidate { priority, tokens }
+    }
+
+    fn occupant(priority: Priority, fed: usize) -> Occupant {
+        Occupant { priority, fed }
+    }
+
+    #[test]
+    fn test_admission() {
+        let planner = Planner::new(&options(), 100);
+        let waiting = |tokens| Some(candidate(Priority::Normal, tokens));
+
+        assert!(matches!(planner.admit(100, &[], None), Admission::Idle));
+        assert!(matches!(
+            planner.admit(100, &[], waiting(10).as_ref()),
+            Admission::Start { chunk: 10 }
+        ));
+        // Empty or too long prompts never fit
+        for tokens in [0, 100, 150] {
+            let Admission::Reject(err) = planner.admit(100, &[], waiting(tokens).as_ref()) else {
+                panic!("prompt of {tokens} tokens admitted");
+            };
+            assert_eq!(err.kind, ErrorKind::ContextLengthExceeded);
+        }
+    }
+
+    #[test]
+    fn test_context_limit() {
+        let planner = Planner::new(&options(), 100);
+        // The output is cut to the room the prompt leaves
+        assert_eq!(planner.max_output_tokens(10, 8192), 90);
+        assert_eq!(planner.max_output_tokens(10, 50), 50);
+        assert_eq!(planner.max_output_tokens(99, 8192), 1);
+
+        assert!(!planner.context_full(99));
+        assert!(planner.context_full(100));
+    }
+
+    #[test]
+    fn test_backfill() {
+        // Whole prompts: one joins the running batch only if all of it fits in the step
+        let planner = Planner::new(&options(), 100);
+        let waiting = Some(candidate(Priority::Normal, 30));
+        assert!(matches!(
+            planner.admit(30, &[], waiting.as_ref()),
+            Admission::Start { chunk: 30 }
+        ));
+        assert!(matches!(
+            planner.admit(29, &[], waiting.as_ref()),
+            Admission::Defer
+        ));
+
+        // Two decoding sequences leave 98 tokens of the batch to new prompts
+        assert_eq!(planner.prefill_budget(2, 2), 98);
+        assert_eq!(planner.prefill_budget(100, 2), 0);
+        assert!(matches!(
+            planner.admit(0, &[], waiting.as_ref()),
+            Admission::Defer
+        ));
+    }
+
+    #[test]
+    fn test_chunking() {
+        let chunked = EngineOptions {
+            max_prefill_chunk: Some(16),
+            ..options()
+        };
+        let planner = Planner::new(&chunked, 100);
+        assert_eq!(planner.prefill_budget(0, 0), 16);
+        // Less room left in the batch than a chunk
+        assert_eq!(planner.prefill_budget(90, 3), 10);
+
+        // A long prompt starts with whatever room there is
+        let waiting = Some(candidate(Priority::Normal, 60));
+        assert!(matches!(
+            planner.admit(16, &[], waiting.as_ref()),
+            Admission::Start { chunk: 16 }
+        ));
+        assert!(matches!(
+            planner.admit(1, &[], waiting.as_ref()),
+            Admission::Start { chunk: 1 }
+        ));
+        assert!(matches!(
+            planner.admit(0, &[], waiting.as_ref()),
+            Admission::Defer
+        ));
+
+        // The decode ratio shares the step with the decoding sequences
+        let ratio = EngineOptions {
+            max_prefill_chunk: Some(16),
+            prefill_decode_ratio: Some(1.5),
+            ..options()
+        };
+        let planner = Planner::new(&ratio, 100);
+        assert_eq!(planner.prefill_budget(0, 0), 16);
+        assert_eq!(planner.prefill_budget(3, 3), 5);
+        assert_eq!(planner.prefill_budget(20, 20), 16);
+
+        let ratio_only = EngineOptions {
+            prefill_decode_ratio: Some(0.1),
+            ..options()
+        };
+        let planner = Planner::new(&ratio_only, 100);
+        assert_eq!(planner.prefill_budget(0, 0), 100);
+        assert_eq!(planner.prefill_budget(1, 1), 1);
+    }
+
+    #[test]
+    fn test_preemption_order() {
+        let planner = Planner::new(&options(), 100);
+        let running = [occupant(Priority::Low, 10), occupant(Priority::Normal, 10)];
+        // Off unless asked for
+        assert_eq!(planner.victim(Priority::High, &running), None);
+
+        let planner = Planner::new(
+            &EngineOptions {
+                preemption: true,
+                ..options()
+            },
+            100,
+        );
+        // The lowest priority goes first, and of equals the latest started
+        assert_eq!(planner.victim(Priority::High, &running), Some(0));
+        let running = [
+            occupant(Priority::Low, 10),
+            occupant(Priority::Normal, 10),
+            occupant(Priority::Low, 10),
+        ];
+        assert_eq!(planner.victim(Priority::High, &running), Some(2));
+        // Only for a higher priority
+        assert_eq!(planner.victim(Priority::Low, &running), None);
+        assert_eq!(planner.victim(Priority::Normal, &running), Some(2));
+        // Not a sequence with no room to prefill again and sample
+        let running = [occupant(Priority::Low, 99), occupant(Priority::Normal, 10)];
+        assert_eq!(planner.victim(Priority::High, &running), Some(1));
+
+        // Preempted sequences resume highest priority first, then earliest preempted, and
+        // before new requests of their priority but not of a higher one
+        let preempted = [
+            candidate(Priority::Low, 20),
+            candidate(Priority::Normal, 20),
+            candidate(Priority::Normal, 20),
+        ];
+        assert!(matches!(
+            planner.admit(100, &preempted, None),
+            Admission::Resume { idx: 1, chunk: 20 }
+        ));
+        let normal = candidate(Priority::Normal, 5);
+        assert!(matches!(
+            planner.admit(100, &preempted, Some(&normal)),
+            Admission::Resume { idx: 1, .. }
+        ));
+        let high = candidate(Priority::High, 5);
+        assert!(matches!(
+            planner.admit(100, &preempted, Some(&high)),
+            Admission::Start { chunk: 5 }
+        ));
+        // Without chunking all its tokens are prefilled again in one step
+        assert!(matches!(
+            planner.admit(19, &preempted, Some(&normal)),
+            Admission::Defer
+        ));
+    }
+}
@@ -13,10 +13,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::HashMap;
 use std::{num::NonZero, sync::Arc};
 
-use async_openai::types::FinishReason;
+use async_openai::types::{
+    ChatCompletionRequestUserMessageContent, ChatCompletionRequestUserMessageContentPart,
+    FinishReason, ResponseFormat,
+};
 use async_stream::stream;
 use async_trait::async_trait;
 use either::Either;
@@ -35,12 +37,20 @@ use dynamo_runtime::pipeline::error as pipeline_error;
 use dynamo_runtime::pipeline::{Error, ManyOut, SingleIn};
 use dynamo_runtime::protocols::annotated::Annotated;
 
+use dynamo_llm::protocols::common::{
+    sampling::{SamplingValidator, BANNED_WORDS_ENV},
+    SamplingOptionsProvider,
+};
 use dynamo_llm::protocols::openai::{
     chat_completions::{NvCreateChatCompletionRequest, NvCreateChatCompletionStreamResponse},
     completions::{prompt_to_string, CompletionRequest, CompletionResponse},
+    nvext::{NvExt, NvResponseExt},
 };
 
+use dynamo_llm::engines::load::{self, LoadOptions, Quantization};
 use dynamo_llm::engines::{EngineDispatcher, StreamingEngine};
+use dynamo_llm::lora::LoraError;
+use dynamo_llm::preprocessor::media::{self, MediaError};
 use dynamo_llm::LocalModel;
 
 /// How many requests mistral will run at once in the paged attention scheduler.
@@ -56,12 +66,26 @@ const EXP_ENABLE_PAGED_ATTENTION: bool = false;
 /// Initial message we send to mistral.rs to warm it up. We may not need this.
 const WARMUP_MESSAGE: &str = "This is a test message. Respond only with 'OK'.";
 
-pub async fn make_engine(model: &LocalModel) -> pipeline_error::Result<Arc<dyn StreamingEngine>> {
-    let engine = MistralRsEngine::new(model).await?;
+pub async fn make_engine(
+    model: &LocalModel,
+    load_options: &LoadOptions,
+) -> pipeline_error::Result<Arc<dyn StreamingEngine>> {
+    let engine = MistralRsEngine::new(model, load_options).await?;
     let engine: Arc<dyn StreamingEngine> = Arc::new(EngineDispatcher::new(engine));
     Ok(engine)
 }
 
+/// mistral.rs merges LoRA adapters into the model when it loads it, it cannot pick one per
+/// request
+fn reject_lora(nvext: Option<&NvExt>) -> Result<(), LoraError> {
+    if nvext.is_some_and(|ext| ext.lora.is_some()) {
+        return Err(LoraError::NotSupported(
+            "by the mistralrs engine".to_string(),
+        ));
+    }
+    Ok(())
+}
+
 /// Gets the best device, cpu, cuda if compiled with CUDA
 fn best_device() -> pipeline_error::Result<Device> {
     #[cfg(not(feature = "metal"))]
@@ -74,13 +98,53 @@ fn best_device() -> pipeline_error::Result<Device> {
     }
 }
 
+/// The `--device`, or the best one
+fn device(requested: Option<load::Device>) -> pipeline_error::Result<Device> {
+    Ok(match requested {
+        None => best_device()?,
+        Some(load::Device::Cpu) => Device::Cpu,
+        Some(load::Device::Cuda(index)) => Device::new_cuda(index as usize)?,
+        Some(load::Device::Metal) => Device::new_metal(0)?,
+    })
+}
+
+fn model_dtype(dtype: Option<load::DType>) -> ModelDType {
+    match dtype {
+        None | Some(load::DType::Auto) => ModelDType::Auto,
+        Some(load::DType::Bf16) => ModelDType::BF16,
+        Some(load::DType::F16) => ModelDType::F16,
+        Some(load::DType::F32) => ModelDType::F32,
+    }
+}
+
+/// The in-situ quantization mistral.rs applies as the weights load
+fn isq_type(quantization: Quantization) -> IsqType {
+    match quantization {
+        Quantization::Fp8 => IsqType::F8E4M3,
+        Quantization::Int8 => IsqType::Q8_0,
+        Quantization::Int4 => IsqType::Q4K,
+    }
+}
+
 struct MistralRsEngine {
     mistralrs: Arc<MistralRs>,
+    display_name: String,
+    /// Vision models take `image_url` content parts
+    is_vision: bool,
+    sampling: SamplingValidator,
 }
 
 impl MistralRsEngine {
-    async fn new(model: &LocalModel) -> pipeline_error::Result<Self> {
+    async fn new(model: &LocalModel, load_options: &LoadOptions) -> pipeline_error::Result<Self> {
+        if let Some(flag) =
+            load_options.first_set(&["--tensor-parallel-size", "--gpu-memory-fraction"])
+        {
+            pipeline_error::bail!("{flag} is not supported by mistralrs");
+        }
         let model_path = model.path();
+        if model_path.is_file() && load_options.quantization.is_some() {
+            pipeline_error::bail!("GGUF files are already quantized, leave out --quantization");
+        }
         // Name some None's for clarity
         let chat_template = None;
         let tokenizer_json = None;
@@ -169,14 +233,14 @@ impl MistralRsEngine {
         let pipeline = loader.load_model_from_hf(
             None,
             TokenSource::None, // The model was already downloaded
-            &ModelDType::Auto,
-            &best_device()?,
+            &model_dtype(load_options.dtype),
+            &device(load_options.device)?,
             false,
             DeviceMapSetting::Auto(device_map_params),
-            if is_llama4(display_name) {
-                Some(IsqType::Q4K)
-            } else {
-                None
+            match load_options.quantization {
+                Some(quantization) => Some(isq_type(quantization)),
+                None if is_llama4(display_name) => Some(IsqType::Q4K),
+                None => None,
             },
             paged_attention_config,
         )?;
@@ -208,8 +272,19 @@ impl MistralRsEngine {
             search_embedding_model,
         )
         .with_prefix_cache_n(16);
+        let mut sampling = SamplingValidator::from_env(Some("mistralrs"))?;
+        // There is no pre-processor in front of mistralrs to ban them
+        if std::env::var_os(BANNED_WORDS_ENV).is_some() {
+            let Some(tokenizer) = &model.card().tokenizer else {
+                pipeline_error::bail!("{BANNED_WORDS_ENV} needs the model's tokenizer");
+            };
+            sampling = sampling.with_banned_words_from_env(tokenizer.load()?.as_ref())?;
+        }
         let engine = MistralRsEngine {
             mistralrs: builder.build(),
+            display_name: display_name.to_string(),
+            is_vision: is_vision_model(display_name),
+            sampling,
         };
 
         // skip the id used for dummy run https://github.com/EricLBuehler/mistral.rs/issues/1218
@@ -278,20 +353,66 @@ impl
         let (request, context) = request.transfer(());
         let ctx = context.context();
         let (tx, mut rx) = channel(10_000);
+        if request
+            .nvext
+            .as_ref()
+            .is_some_and(|ext| ext.suffix.is_some())
+        {
+            anyhow::bail!("mistralrs does not support fill-in-the-middle, suffix must not be set");
+        }
+        reject_lora(request.nvext.as_ref())?;
+
+        let mut sampling = request.extract_sampling_options()?;
+        let mut nvext = NvResponseExt::from_warnings(self.sampling.validate(&mut sampling)?);
 
         let mut messages = vec![];
+        let mut images = vec![];
         for m in request.inner.messages {
             let async_openai::types::ChatCompletionRequestMessage::User(inner_m) = m else {
                 continue;
             };
-            let async_openai::types::ChatCompletionRequestUserMessageContent::Text(content) =
-                inner_m.content
-            else {
-                anyhow::bail!("Only Text type chat completion supported");
+            let content = match inner_m.content {
+                ChatCompletionRequestUserMessageContent::Text(content) => Either::Left(content),
+                ChatCompletionRequestUserMessageContent::Array(parts) => {
+                    // mistral.rs vision models take the images separately, and a
+                    // `{"type": "image"}` part where each goes in the prompt.
+                    let mut content_parts = vec![];
+                    for part in parts {
+                        match part {
+                            ChatCompletionRequestUserMessageContentPart::Text(text) => {
+                                content_parts.push(IndexMap::from([
+                                    ("type".to_string(), serde_json::json!("text")),
+                                    ("text".to_string(), serde_json::json!(text.text)),
+                                ]));
+                            }
+                            ChatCompletionRequestUserMessageContentPart::ImageUrl(image) => {
+                                if !self.is_vision {
+                                    return Err(MediaError::image_not_supported(
+                                        &self.display_name,
+                                    )
+                                    .into());
+                                }
+                                images.push(load_image(&image.image_url.url).await?);
+                                content_parts.push(IndexMap::from([(
+                                    "type".to_string(),
+                                    serde_json::json!("image"),
+                                )]));
+                            }
+                            ChatCompletionRequestUserMessageContentPart::InputAudio(_) => {
+                                return Err(MediaError::ModalityNotSupported {
+                                    model: self.display_name.clone(),
+                                    modality: "audio".to_string(),
+                                }
+                                .into());
+                            }
+                        }
+                    }
+                    Either::Right(content_parts)
+                }
             };
             let r = IndexMap::from([
                 ("role".to_string(), Either::Left("user".to_string())),
-                ("content".to_string(), Either::Left(content)),
+                ("content".to_string(), content),
             ]);
             messages.push(r);
         }
@@ -303,49 +424,57 @@ impl
         // allow deprecated because max_tokens
         #[allow(deprecated)]
         let sampling_params = SamplingParams {
-            temperature: request
-                .inner
-                .temperature
-                .map(|t| t as f64)
-                .or(det.temperature),
-            top_p: request.inner.top_p.map(|t| t as f64).or(det.top_p),
+            temperature: sampling.temperature.map(|t| t as f64).or(det.temperature),
+            top_p: sampling.top_p.map(|t| t as f64).or(det.top_p),
             top_n_logprobs: request
                 .inner
                 .top_logprobs
                 .map(|t| t as usize)
                 .unwrap_or(det.top_n_logprobs),
-            frequency_penalty: request.inner.frequency_penalty.or(det.frequency_penalty),
-            presence_penalty: request.inner.presence_penalty.or(det.presence_penalty),
-            stop_toks: request.inner.stop.map(to_stop_tokens).or(det.stop_toks),
+            frequency_penalty: sampling.frequency_penalty.or(det.frequency_penalty),
+            presence_penalty: sampling.presence_penalty.or(det.presence_penalty),
+            stop_toks: stop_token_ids(request.nvext.as_ref())
+                .or(request.inner.stop.map(to_stop_tokens))
+                .or(det.stop_toks),
             max_len: request
                 .inner
                 .max_completion_tokens
                 .or(request.inner.max_tokens)
                 .map(|m| m as usize)
                 .or(det.max_len),
-            logits_bias: request
-                .inner
-                .logit_bias
-                .map(to_logit_bias)
-                .or(det.logits_bias),
+            logits_bias: sampling.logit_bias.clone().or(det.logits_bias),
             // These are not in async-openai yet
             top_k: det.top_k,
             min_p: det.min_p,
             n_choices: 1,
             dry_params: det.dry_params,
         };
+        let constraint = request
+            .inner
+            .response_format
+            .map(to_constraint)
+            .unwrap_or(Constraint::None);
         let request_id = self.mistralrs.next_request_id();
-        let mistralrs_request = Request::Normal(NormalRequest {
-            id: request_id,
-            messages: RequestMessage::Chat {
+        let messages = if images.is_empty() {
+            RequestMessage::Chat {
                 messages,
                 enable_thinking: None,
-            },
+            }
+        } else {
+            RequestMessage::VisionChat {
+                images,
+                messages,
+                enable_thinking: None,
+            }
+        };
+        let mistralrs_request = Request::Normal(NormalRequest {
+            id: request_id,
+            messages,
             sampling_params,
             response: tx,
             return_logprobs: request.inner.logprobs.unwrap_or_default(),
             is_streaming: true,
-            constraint: Constraint::None,
+            constraint,
             suffix: None,
             tools: None,
             tool_choice: None,
@@ -356,8 +485,20 @@ impl
 
         self.mistralrs.get_sender()?.send(mistralrs_request).await?;
 
+        let request_ctx = ctx.clone();
         let output = stream! {
-            while let Some(response) = rx.recv().await {
+            loop {
+                // mistral.rs cancels the request when we drop the receiver
+                let response = tokio::select! {
+                    _ = request_ctx.stopped() => {
+                        tracing::debug!(request_id, "Request stopped");
+                        break;
+                    }
+                    response = rx.recv() => match response {
+                        Some(response) => response,
+                        None => break,
+                    },
+                };
                 let response = match response.as_result() {
                     Ok(r) => r,
                     Err(err) => {
@@ -409,7 +550,7 @@ impl
                             system_fingerprint: Some(c.system_fingerprint),
                             service_tier: None,
                         };
-                        let delta = NvCreateChatCompletionStreamResponse{inner};
+                        let delta = NvCreateChatCompletionStreamResponse{inner, nvext: nvext.take()};
                         let ann = Annotated{
                             id: None,
                             data: Some(delta),
@@ -439,27 +580,31 @@ fn to_stop_tokens(t: async_openai::types::Stop) -> StopTokens {
     }
 }
 
-/// openai logit bias (strings/json) to mistralrs (u32/f32)
-/// I think the input looks like this: {"3721": -100, "17765": 100}
-fn to_logit_bias(lb: HashMap<String, serde_json::Value>) -> HashMap<u32, f32> {
-    let mut out = HashMap::new();
-    for (key, value) in &lb {
-        let token_id: u32 = match key.parse() {
-            Ok(t) => t,
-            Err(err) => {
-                tracing::warn!(
-                    "Unexpected logit_bias map. Key '{key}' is not an int: {lb:?}. {err}."
-                );
-                return HashMap::new();
-            }
-        };
-        let Some(bias) = value.as_f64() else {
-            tracing::warn!("Unexpected logit_bias map. Value '{value}' is not a float: {lb:?}");
-            return HashMap::new();
-        };
-        out.insert(token_id, bias as f32);
+/// mistralrs takes either stop strings or stop token ids. The ids win, because the stop
+/// strings are also applied to the output text by `StreamingEngineAdapter`.
+fn stop_token_ids(nvext: Option<&NvExt>) -> Option<StopTokens> {
+    let ids = nvext?.stop_token_ids.clone()?;
+    Some(StopTokens::Ids(ids))
+}
+
+/// Fetch and decode the image of an `image_url` content part
+async fn load_image(url: &str) -> anyhow::Result<image::DynamicImage> {
+    let image = media::load_image(url).await?;
+    image::load_from_memory(&image.bytes()?)
+        .map_err(|err| MediaError::InvalidImage(err.to_string()).into())
+}
+
+/// openai response_format to a mistralrs guided decoding constraint
+fn to_constraint(rf: ResponseFormat) -> Constraint {
+    match rf {
+        ResponseFormat::Text => Constraint::None,
+        ResponseFormat::JsonObject => Constraint::JsonSchema(serde_json::json!({"type": "object"})),
+        ResponseFormat::JsonSchema { json_schema } => Constraint::JsonSchema(
+            json_schema
+                .schema
+                .unwrap_or_else(|| serde_json::json!({"type": "object"})),
+        ),
     }
-    out
 }
 
 #[async_trait]
@@ -474,6 +619,13 @@ impl AsyncEngine<SingleIn<CompletionRequest>, ManyOut<Annotated<CompletionRespon
         let ctx = context.context();
         let (tx, mut rx) = channel(10_000);
         let response_generator = request.response_generator();
+        if request.inner.suffix.is_some() {
+            anyhow::bail!("mistralrs does not support fill-in-the-middle, suffix must not be set");
+        }
+        reject_lora(request.nvext.as_ref())?;
+
+        let mut sampling = request.extract_sampling_options()?;
+        let mut nvext = NvResponseExt::from_warnings(self.sampling.validate(&mut sampling)?);
 
         let messages = RequestMessage::Completion {
             text: prompt_to_string(&request.inner.prompt),
@@ -484,24 +636,17 @@ impl AsyncEngine<SingleIn<CompletionRequest>, ManyOut<Annotated<CompletionRespon
         // allow deprecated because max_tokens
         #[allow(deprecated)]
         let sampling_params = SamplingParams {
-            temperature: request
-                .inner
-                .temperature
-                .map(|t| t as f64)
-                .or(det.temperature),
-            top_p: request.inner.top_p.map(|t| t as f64).or(det.top_p),
+            temperature: sampling.temperature.map(|t| t as f64).or(det.temperature),
+            top_p: sampling.top_p.map(|t| t as f64).or(det.top_p),
             top_n_logprobs: request
                 .inner
                 .logprobs
                 .map(|t| t as usize)
                 .unwrap_or(det.top_n_logprobs),
-            frequency_penalty: request.inner.frequency_penalty.or(det.frequency_penalty),
-            presence_penalty: request.inner.presence_penalty.or(det.presence_penalty),
-            stop_toks: request
-                .inner
-                .stop
-                .clone()
-                .map(to_stop_tokens)
+            frequency_penalty: sampling.frequency_penalty.or(det.frequency_penalty),
+            presence_penalty: sampling.presence_penalty.or(det.presence_penalty),
+            stop_toks: stop_token_ids(request.nvext.as_ref())
+                .or(request.inner.stop.clone().map(to_stop_tokens))
                 .or(det.stop_toks),
             max_len: request
                 .inner
@@ -509,12 +654,7 @@ impl AsyncEngine<SingleIn<CompletionRequest>, ManyOut<Annotated<CompletionRespon
                 .or(request.inner.max_tokens)
                 .map(|m| m as usize)
                 .or(det.max_len),
-            logits_bias: request
-                .inner
-                .logit_bias
-                .clone()
-                .map(to_logit_bias)
-                .or(det.logits_bias),
+            logits_bias: sampling.logit_bias.clone().or(det.logits_bias),
             // These are not in async-openai yet
             top_k: det.top_k,
             min_p: det.min_p,
@@ -541,8 +681,20 @@ impl AsyncEngine<SingleIn<CompletionRequest>, ManyOut<Annotated<CompletionRespon
 
         self.mistralrs.get_sender()?.send(mistralrs_request).await?;
 
+        let request_ctx = ctx.clone();
         let output = stream! {
-            while let Some(response) = rx.recv().await {
+            loop {
+                // mistral.rs cancels the request when we drop the receiver
+                let response = tokio::select! {
+                    _ = request_ctx.stopped() => {
+                        tracing::debug!(request_id, "Request stopped");
+                        break;
+                    }
+                    response = rx.recv() => match response {
+                        Some(response) => response,
+                        None => break,
+                    },
+                };
                 let response = match response.as_result() {
                     Ok(r) => r,
                     Err(err) => {
@@ -568,7 +720,8 @@ impl AsyncEngine<SingleIn<CompletionRequest>, ManyOut<Annotated<CompletionRespon
                             None => None,
                         };
                         #[allow(deprecated)]
-                        let inner = response_generator.create_choice(0, Some(from_assistant), None);
+                        let mut inner = response_generator.create_choice(0, Some(from_assistant), None);
+                        inner.nvext = nvext.take();
                         let ann = Annotated{
                             id: None,
                             data: Some(inner),
@@ -36,14 +36,17 @@ use tokio::sync::mpsc;
 use tokio::sync::oneshot::Sender;
 use tokio_stream::{wrappers::ReceiverStream, StreamExt};
 
+use dynamo_llm::backend::ExecutionContext;
 use dynamo_llm::engines::{EngineDispatcher, StreamingEngine};
 
 /// Python snippet to import a file as a module
 const PY_IMPORT: &CStr = cr#"
+import asyncio
 import runpy
 import sys
 import os
 import functools
+import inspect
 import types
 
 module_dir = os.path.dirname(file_path)
@@ -53,15 +56,30 @@ if module_dir not in sys.path:
 sys.argv = sys_argv
 module_dict = runpy.run_path(file_path, run_name='__main__')
 
+_DONE = object()
+
+async def _in_thread(generate_func, request):
+    # Each item of a sync generator is made in a worker thread, so the event loop keeps
+    # serving the other requests meanwhile
+    gen = generate_func(request)
+    while (response := await asyncio.to_thread(next, gen, _DONE)) is not _DONE:
+        yield response
+
 # Create a module class with the generate function
 class Module:
     def __init__(self, module_dict):
         self.__dict__.update(module_dict)
-        self._generate_func = module_dict['generate']
-
-    async def generate(self, request):
-        async for response in self._generate_func(request):
-            yield response
+        generate_func = module_dict['generate']
+        if inspect.isasyncgenfunction(generate_func):
+            self.generate = generate_func
+        elif inspect.isgeneratorfunction(generate_func):
+            self.generate = functools.partial(_in_thread, generate_func)
+        else:
+            raise TypeError("generate must be an async generator or a generator function")
+        initialize = module_dict.get('initialize')
+        if initialize is not None and not inspect.iscoroutinefunction(initialize):
+            raise TypeError("initialize must be an async function")
+        self.initialize = initialize
 
 # Create module instance and store it in globals
 module = Module(module_dict)
@@ -74,6 +92,29 @@ pub async fn make_string_engine(
     py_file: &Path,
     py_args: Vec<String>,
 ) -> pipeline_error::Result<Arc<dyn StreamingEngine>> {
+    prepare_python();
+    let engine = new_engine(cancel_token, py_file, py_args).await?;
+    let engine: Arc<dyn StreamingEngine> = Arc::new(EngineDispatcher::new(engine));
+    Ok(engine)
+}
+
+/// An engine that takes pre-processed requests and returns tokens, feeding them to a python
+/// written engine. Its `generate` gets a `PreprocessedRequest` as a dict, with the prompt's
+/// `token_ids` after the chat template, and yields `LLMEngineOutput` dicts: the new
+/// `token_ids` and a `finish_reason` on the last one. Dynamo detokenizes and applies the
+/// stop conditions.
+pub async fn make_token_engine(
+    cancel_token: CancellationToken,
+    py_file: &Path,
+    py_args: Vec<String>,
+) -> pipeline_error::Result<ExecutionContext> {
+    prepare_python();
+    let engine = new_engine(cancel_token, py_file, py_args).await?;
+    let engine: ExecutionContext = Arc::new(engine);
+    Ok(engine)
+}
+
+fn prepare_python() {
     pyo3::prepare_freethreaded_python();
     if let Ok(venv) = env::var("VIRTUAL_ENV") {
         Python::with_gil(|py| {
@@ -82,10 +123,6 @@ pub async fn make_string_engine(
             }
         });
     }
-
-    let engine = new_engine(cancel_token, py_file, py_args).await?;
-    let engine: Arc<dyn StreamingEngine> = Arc::new(EngineDispatcher::new(engine));
-    Ok(engine)
 }
 
 #[derive(Clone)]
@@ -111,6 +148,25 @@ async fn new_engine(
             .getattr(py, "generate")
             .with_context(|| "generate")
     })?;
+
+    // Asyncio engines make their clients in `initialize`, on the event loop that runs
+    // `generate`, rather than when the file is run
+    let initialize = Python::with_gil(|py| -> anyhow::Result<_> {
+        let initialize = user_module.getattr(py, "initialize")?;
+        if initialize.is_none(py) {
+            return Ok(None);
+        }
+        let coroutine = initialize.call0(py)?;
+        let locals = TaskLocals::new(event_loop.bind(py).clone());
+        let future =
+            pyo3_async_runtimes::into_future_with_locals(&locals, coroutine.into_bound(py))?;
+        Ok(Some(future))
+    })?;
+    if let Some(initialize) = initialize {
+        initialize
+            .await
+            .with_context(|| format!("{} initialize", py_file.display()))?;
+    }
     Ok(PythonServerStreamingEngine::new(
         cancel_token,
         Arc::new(generator),
@@ -239,7 +295,20 @@ where
             let mut stream = stream;
             let mut count = 0;
 
-            while let Some(item) = stream.next().await {
+            loop {
+                // Dropping the stream closes the python async generator, which lets the
+                // engine under it abort the request
+                let item = tokio::select! {
+                    biased;
+                    _ = ctx.stopped() => {
+                        tracing::debug!(request_id, "request stopped, closing python async generator");
+                        break;
+                    }
+                    item = stream.next() => match item {
+                        Some(item) => item,
+                        None => break,
+                    },
+                };
                 count += 1;
                 tracing::trace!(
                     request_id,
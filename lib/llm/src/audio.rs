@@ -0,0 +1,274 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Audio decoding for speech-to-text
+//!
+//! Uploaded audio is decoded here, in the HTTP frontend, to the mono 16 kHz PCM that
+//! whisper-style models expect. Workers never see the container format.
+//!
+//! Only WAV (RIFF) is supported: 8, 16, 24 or 32 bit integer PCM and 32 bit float.
+//! Compressed formats (mp3, ogg, flac, ...) are rejected, clients should convert them first.
+
+/// Sample rate every decoded clip is resampled to
+pub const TARGET_SAMPLE_RATE: u32 = 16_000;
+
+/// Largest upload we accept
+pub const MAX_AUDIO_BYTES: usize = 25 * 1024 * 1024;
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+#[derive(Debug, thiserror::Error)]
+pub enum AudioError {
+    #[error("Audio file is larger than the {MAX_AUDIO_BYTES} bytes limit")]
+    TooLarge,
+
+    #[error("Unsupported audio format: {0}. Only WAV is supported.")]
+    UnsupportedFormat(String),
+
+    #[error("Invalid WAV file: {0}")]
+    InvalidWav(String),
+}
+
+/// Decoded audio, mono
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pcm {
+    pub sample_rate: u32,
+    pub samples: Vec<f32>,
+}
+
+impl Pcm {
+    /// Length of the clip in seconds
+    pub fn duration(&self) -> f32 {
+        self.samples.len() as f32 / self.sample_rate as f32
+    }
+}
+
+struct WavFormat {
+    format: u16,
+    channels: u16,
+    sample_rate: u32,
+    bits_per_sample: u16,
+}
+
+/// Decode an uploaded audio file to mono PCM at [`TARGET_SAMPLE_RATE`]
+pub fn decode(bytes: &[u8]) -> Result<Pcm, AudioError> {
+    if bytes.len() > MAX_AUDIO_BYTES {
+        return Err(AudioError::TooLarge);
+    }
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(AudioError::UnsupportedFormat(
+            sniff_format(bytes).to_string(),
+        ));
+    }
+    let pcm = decode_wav(&bytes[12..])?;
+    Ok(resample(pcm, TARGET_SAMPLE_RATE))
+}
+
+fn decode_wav(mut chunks: &[u8]) -> Result<Pcm, AudioError> {
+    let mut format: Option<WavFormat> = None;
+    while chunks.len() >= 8 {
+        let id = &chunks[0..4];
+        let size = u32::from_le_bytes(chunks[4..8].try_into().unwrap()) as usize;
+        let body = &chunks[8..];
+        // Streaming encoders sometimes write a bogus size for the last chunk
+        let body = &body[..size.min(body.len())];
+
+        match id {
+            b"fmt " => format = Some(parse_format(body)?),
+            b"data" => {
+                let Some(format) = format else {
+                    return Err(AudioError::InvalidWav(
+                        "data chunk before fmt chunk".to_string(),
+                    ));
+                };
+                return Ok(Pcm {
+                    sample_rate: format.sample_rate,
+                    samples: decode_samples(&format, body)?,
+                });
+            }
+            _ => {}
+        }
+
+        // Chunks are padded to an even size
+        let next = 8 + size + (size & 1);
+        if next > chunks.len() {
+            break;
+        }
+        chunks = &chunks[next..];
+    }
+    Err(AudioError::InvalidWav("no data chunk".to_string()))
+}
+
+fn parse_format(body: &[u8]) -> Result<WavFormat, AudioError> {
+    if body.len() < 16 {
+        return Err(AudioError::InvalidWav("fmt chunk too short".to_string()));
+    }
+    let u16_at = |i: usize| u16::from_le_bytes([body[i], body[i + 1]]);
+    let mut format = u16_at(0);
+    if format == WAVE_FORMAT_EXTENSIBLE && body.len() >= 26 {
+        // The first two bytes of the sub-format GUID are the actual format
+        format = u16_at(24);
+    }
+    let format = WavFormat {
+        format,
+        channels: u16_at(2),
+        sample_rate: u32::from_le_bytes(body[4..8].try_into().unwrap()),
+        bits_per_sample: u16_at(14),
+    };
+    if format.channels == 0 || format.sample_rate == 0 {
+        return Err(AudioError::InvalidWav(
+            "zero channels or sample rate".to_string(),
+        ));
+    }
+    match (format.format, format.bits_per_sample) {
+        (WAVE_FORMAT_PCM, 8 | 16 | 24 | 32) | (WAVE_FORMAT_IEEE_FLOAT, 32) => Ok(format),
+        (f, bits) => Err(AudioError::UnsupportedFormat(format!(
+            "WAV encoding {f} with {bits} bits per sample"
+        ))),
+    }
+}
+
+/// Decode interleaved samples to f32 in [-1, 1] and mix the channels down to mono
+fn decode_samples(format: &WavFormat, data: &[u8]) -> Result<Vec<f32>, AudioError> {
+    let width = format.bits_per_sample as usize / 8;
+    let channels = format.channels as usize;
+    let frame = width * channels;
+
+    let sample = |b: &[u8]| -> f32 {
+        match (format.format, width) {
+            (WAVE_FORMAT_IEEE_FLOAT, _) => f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            (_, 1) => (b[0] as f32 - 128.0) / 128.0,
+            (_, 2) => i16::from_le_bytes([b[0], b[1]]) as f32 / 32_768.0,
+            (_, 3) => (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f32 / 8_388_608.0,
+            _ => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2_147_483_648.0,
+        }
+    };
+
+    let samples = data
+        .chunks_exact(frame)
+        .map(|frame| {
+            let sum: f32 = frame.chunks_exact(width).map(sample).sum();
+            sum / channels as f32
+        })
+        .collect::<Vec<f32>>();
+    if samples.is_empty() {
+        return Err(AudioError::InvalidWav("no samples".to_string()));
+    }
+    Ok(samples)
+}
+
+/// Linear interpolation. Good enough for speech, whisper's own front end low-passes anyway.
+fn resample(pcm: Pcm, sample_rate: u32) -> Pcm {
+    if pcm.sample_rate == sample_rate {
+        return pcm;
+    }
+    let ratio = pcm.sample_rate as f64 / sample_rate as f64;
+    let len = (pcm.samples.len() as f64 / ratio).round() as usize;
+    let last = pcm.samples.len() - 1;
+    let samples = (0..len)
+        .map(|i| {
+            let pos = i as f64 * ratio;
+            let idx = (pos as usize).min(last);
+            let frac = (pos - idx as f64) as f32;
+            let a = pcm.samples[idx];
+            let b = pcm.samples[(idx + 1).min(last)];
+            a + (b - a) * frac
+        })
+        .collect();
+    Pcm {
+        sample_rate,
+        samples,
+    }
+}
+
+/// Best guess at what was uploaded, for the error message
+fn sniff_format(bytes: &[u8]) -> &'static str {
+    match bytes {
+        [b'I', b'D', b'3', ..] | [0xFF, 0xFB | 0xF3 | 0xF2, ..] => "mp3",
+        [b'O', b'g', b'g', b'S', ..] => "ogg",
+        [b'f', b'L', b'a', b'C', ..] => "flac",
+        [0x1A, 0x45, 0xDF, 0xA3, ..] => "webm",
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] => "mp4",
+        _ => "unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wav(format: u16, channels: u16, sample_rate: u32, bits: u16, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(b"RIFF");
+        out.extend_from_slice(&(36 + data.len() as u32).to_le_bytes());
+        out.extend_from_slice(b"WAVE");
+        out.extend_from_slice(b"fmt ");
+        out.extend_from_slice(&16u32.to_le_bytes());
+        out.extend_from_slice(&format.to_le_bytes());
+        out.extend_from_slice(&channels.to_le_bytes());
+        out.extend_from_slice(&sample_rate.to_le_bytes());
+        let block_align = channels * bits / 8;
+        out.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+        out.extend_from_slice(&block_align.to_le_bytes());
+        out.extend_from_slice(&bits.to_le_bytes());
+        out.extend_from_slice(b"data");
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out.extend_from_slice(data);
+        out
+    }
+
+    #[test]
+    fn test_decode_pcm16_stereo() {
+        // Two stereo frames, the channels are averaged
+        let data: Vec<u8> = [16_384i16, -16_384, 32_767, 32_767]
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect();
+        let pcm = decode(&wav(WAVE_FORMAT_PCM, 2, 16_000, 16, &data)).unwrap();
+        assert_eq!(pcm.sample_rate, 16_000);
+        assert_eq!(pcm.samples.len(), 2);
+        assert_eq!(pcm.samples[0], 0.0);
+        assert!((pcm.samples[1] - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_decode_float_resample() {
+        let data: Vec<u8> = (0..48_000)
+            .flat_map(|i| ((i % 2) as f32).to_le_bytes())
+            .collect();
+        let pcm = decode(&wav(WAVE_FORMAT_IEEE_FLOAT, 1, 48_000, 32, &data)).unwrap();
+        assert_eq!(pcm.sample_rate, TARGET_SAMPLE_RATE);
+        assert_eq!(pcm.samples.len(), 16_000);
+        assert!((pcm.duration() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_reject() {
+        assert!(matches!(
+            decode(b"ID3\x04\x00\x00\x00\x00\x00\x00\x00\x00"),
+            Err(AudioError::UnsupportedFormat(f)) if f == "mp3"
+        ));
+        assert!(matches!(
+            decode(&wav(WAVE_FORMAT_PCM, 1, 16_000, 16, &[])),
+            Err(AudioError::InvalidWav(_))
+        ));
+        assert!(matches!(
+            decode(&wav(2, 1, 16_000, 4, &[0; 8])),
+            Err(AudioError::UnsupportedFormat(_))
+        ));
+    }
+}
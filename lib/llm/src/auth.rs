@@ -0,0 +1,236 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! API key authentication
+//!
+//! [`ApiKeys`] checks the bearer token of a request, whatever the transport. The HTTP service
+//! applies it in a middleware layer, other inputs call [`ApiKeys::authenticate`] with the
+//! token they receive.
+//!
+//! Keys are loaded from a JSON file:
+//! ```json
+//! [
+//!   {"key": "sk-team-a-...", "name": "team-a", "models": ["Llama-3.2-3B-Instruct"]},
+//!   {"key": "sk-team-b-...", "name": "team-b", "namespace": "team-b"},
+//!   {"key": "sk-admin-...", "name": "admin"}
+//! ]
+//! ```
+//! A key without `models` may use every model. A key with a `namespace` is further limited to
+//! the models of that namespace, see [`crate::tenancy`]. Keys can also be given in the
+//! [`API_KEYS_ENV`] environment variable, comma separated, and may then use every model.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Context;
+use serde::Deserialize;
+
+/// Comma separated API keys
+pub const API_KEYS_ENV: &str = "DYN_API_KEYS";
+
+/// Path of an API keys JSON file
+pub const API_KEYS_FILE_ENV: &str = "DYN_API_KEYS_FILE";
+
+/// Who is calling, and what they may use
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ApiKey {
+    pub name: String,
+
+    /// Models this key may use, all of them if None
+    #[serde(default)]
+    pub models: Option<Vec<String>>,
+
+    /// The tenant this key belongs to. Its requests only see the models of this namespace and
+    /// count against its quota.
+    #[serde(default)]
+    pub namespace: Option<String>,
+}
+
+impl ApiKey {
+    pub fn allows_model(&self, model: &str) -> bool {
+        match &self.models {
+            Some(models) => models.iter().any(|m| m == model),
+            None => true,
+        }
+    }
+
+    /// Can this key use every model?
+    pub fn is_unrestricted(&self) -> bool {
+        self.models.is_none() && self.namespace.is_none()
+    }
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum AuthError {
+    #[error("You didn't provide an API key. Pass it in the Authorization header: 'Authorization: Bearer YOUR_KEY'.")]
+    MissingKey,
+
+    #[error("Incorrect API key provided.")]
+    InvalidKey,
+
+    #[error("API key '{key_name}' does not have access to model '{model}'.")]
+    ModelNotAllowed { key_name: String, model: String },
+
+    #[error("API key '{key_name}' is restricted to some models and cannot use {what}.")]
+    Restricted { key_name: String, what: String },
+}
+
+impl AuthError {
+    /// Authentication failed, the HTTP service answers 401. Otherwise the caller is known but
+    /// not allowed, a 403.
+    pub fn is_unauthenticated(&self) -> bool {
+        matches!(self, AuthError::MissingKey | AuthError::InvalidKey)
+    }
+}
+
+#[derive(Deserialize)]
+struct KeyEntry {
+    key: String,
+    #[serde(flatten)]
+    api_key: ApiKey,
+}
+
+/// The accepted API keys
+#[derive(Debug, Default)]
+pub struct ApiKeys {
+    /// By hash of the key, so that looking one up doesn't compare secrets byte by byte
+    keys: HashMap<[u8; 32], Arc<ApiKey>>,
+}
+
+impl ApiKeys {
+    /// Load the keys from a JSON file, see the module docs for the format
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path).with_context(|| path.display().to_string())?;
+        let entries: Vec<KeyEntry> =
+            serde_json::from_str(&contents).with_context(|| path.display().to_string())?;
+        let mut keys = ApiKeys::default();
+        for entry in entries {
+            keys.insert(&entry.key, entry.api_key)?;
+        }
+        Ok(keys)
+    }
+
+    /// Load the keys from the [`API_KEYS_FILE_ENV`] file and the [`API_KEYS_ENV`] list.
+    /// None if neither is set, meaning authentication is off.
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let mut keys = match std::env::var(API_KEYS_FILE_ENV) {
+            Ok(path) if !path.is_empty() => ApiKeys::from_file(Path::new(&path))?,
+            _ => ApiKeys::default(),
+        };
+        if let Ok(list) = std::env::var(API_KEYS_ENV) {
+            let list = list.split(',').map(str::trim).filter(|k| !k.is_empty());
+            for (i, key) in list.enumerate() {
+                let api_key = ApiKey {
+                    name: format!("{API_KEYS_ENV}[{i}]"),
+                    models: None,
+                    namespace: None,
+                };
+                keys.insert(key, api_key)?;
+            }
+        }
+        Ok((!keys.is_empty()).then_some(keys))
+    }
+
+    pub fn insert(&mut self, key: &str, api_key: ApiKey) -> anyhow::Result<()> {
+        if key.is_empty() {
+            anyhow::bail!("API key '{}' is empty", api_key.name);
+        }
+        if self.keys.insert(hash(key), Arc::new(api_key)).is_some() {
+            anyhow::bail!("Duplicate API key");
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// The namespaces keys belong to, sorted
+    pub fn namespaces(&self) -> Vec<String> {
+        let mut namespaces: Vec<_> = self
+            .keys
+            .values()
+            .filter_map(|api_key| api_key.namespace.clone())
+            .collect();
+        namespaces.sort();
+        namespaces.dedup();
+        namespaces
+    }
+
+    /// Check the value of an `Authorization: Bearer <key>` header
+    pub fn authenticate(&self, authorization: Option<&str>) -> Result<Arc<ApiKey>, AuthError> {
+        let token = authorization
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim)
+            .filter(|token| !token.is_empty())
+            .ok_or(AuthError::MissingKey)?;
+        self.keys
+            .get(&hash(token))
+            .cloned()
+            .ok_or(AuthError::InvalidKey)
+    }
+}
+
+/// Check that the caller may use `model`. `api_key` is None when authentication is off.
+pub fn check_model(api_key: Option<&ApiKey>, model: &str) -> Result<(), AuthError> {
+    match api_key {
+        Some(api_key) if !api_key.allows_model(model) => Err(AuthError::ModelNotAllowed {
+            key_name: api_key.name.clone(),
+            model: model.to_string(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+fn hash(key: &str) -> [u8; 32] {
+    *blake3::hash(key.as_bytes()).as_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_authenticate() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(
+            file.path(),
+            r#"[{"key": "sk-a", "name": "a", "models": ["llama"]}, {"key": "sk-b", "name": "b"}]"#,
+        )
+        .unwrap();
+        let keys = ApiKeys::from_file(file.path()).unwrap();
+
+        assert_eq!(keys.authenticate(None), Err(AuthError::MissingKey));
+        assert_eq!(keys.authenticate(Some("sk-a")), Err(AuthError::MissingKey));
+        assert_eq!(
+            keys.authenticate(Some("Bearer sk-c")),
+            Err(AuthError::InvalidKey)
+        );
+
+        let a = keys.authenticate(Some("Bearer sk-a")).unwrap();
+        assert_eq!(a.name, "a");
+        assert!(check_model(Some(&a), "llama").is_ok());
+        assert!(matches!(
+            check_model(Some(&a), "qwen"),
+            Err(AuthError::ModelNotAllowed { .. })
+        ));
+
+        let b = keys.authenticate(Some("Bearer sk-b")).unwrap();
+        assert!(b.is_unrestricted());
+        assert!(check_model(Some(&b), "qwen").is_ok());
+        assert!(check_model(None, "qwen").is_ok());
+    }
+}
@@ -29,22 +29,28 @@
 
 use std::{collections::HashSet, sync::Arc};
 
-use anyhow::{Error, Result};
+use anyhow::Result;
 use futures::stream::{self, StreamExt};
 use tracing as log;
 
+use crate::engines::replay::TraceRecorder;
 use crate::model_card::model::{ModelDeploymentCard, TokenizerKind};
 use dynamo_runtime::{
     pipeline::{
-        async_trait, AsyncEngineContextProvider, ManyOut, Operator, ResponseStream,
-        ServerStreamingEngine, SingleIn,
+        async_trait,
+        timings::{StageTimings, STAGE_TIMINGS_CONTEXT_KEY},
+        AsyncEngineContextProvider, ManyOut, Operator, ResponseStream, ServerStreamingEngine,
+        SingleIn,
     },
     protocols::annotated::Annotated,
 };
 
 use crate::protocols::{
     common::{
-        llm_backend::{BackendInput, BackendOutput, FinishReason, LLMEngineOutput},
+        llm_backend::{
+            BackendInput, BackendOutput, FinishReason, LLMEngineOutput, PositionLogProbs,
+            TokenLogProb,
+        },
         StopConditions,
     },
     TokenIdType,
@@ -52,6 +58,12 @@ use crate::protocols::{
 use crate::tokenizers::{DecodeStream, HuggingFaceTokenizer, Tokenizer};
 use tokenizers::Tokenizer as HfTokenizer;
 
+pub mod stop;
+use stop::StopSequences;
+
+/// Stage timing turning the generated tokens back into text, see [`StageTimings`]
+pub const DETOKENIZE_STAGE: &str = "detokenize";
+
 /// Represents the output stream from the execution engine
 pub type ExecutionOutputStream = Annotated<LLMEngineOutput>;
 
@@ -63,6 +75,8 @@ pub type ExecutionContext = ServerStreamingEngine<BackendInput, ExecutionOutputS
 pub struct Backend {
     pub tokenizer: Option<Tokenizer>, // Handles token encoding/decoding
     validate_engine_decode: bool,     // Enable validation of engine decoding
+    /// Records the engine's answers for replay, see [`crate::engines::replay::RECORD_ENV`]
+    recorder: Option<Arc<TraceRecorder>>,
 }
 
 /// Internal state for managing token decoding and stream processing
@@ -71,6 +85,9 @@ struct DecoderUnfoldState {
     stream: ManyOut<ExecutionOutputStream>,
     decoder: Decoder,
     validate_engine_decode: bool,
+    timings: Option<Arc<StageTimings>>,
+    /// A stop condition ended the output, whatever the engine still sends is dropped
+    stopped: bool,
 }
 
 impl Backend {
@@ -81,23 +98,22 @@ impl Backend {
         Ok(Arc::new(Self {
             tokenizer: Some(tokenizer),
             validate_engine_decode: false,
+            recorder: None,
         }))
     }
 
     pub async fn from_mdc(mdc: ModelDeploymentCard) -> Result<Arc<Self>> {
-        let tokenizer = match &mdc.tokenizer {
-            Some(TokenizerKind::HfTokenizerJson(file)) => {
-                HfTokenizer::from_file(file).map_err(Error::msg)?
-            }
-            Some(TokenizerKind::GGUF(t)) => *t.clone(),
-            None => {
-                return Ok(Arc::new(Self {
-                    tokenizer: None,
-                    validate_engine_decode: false,
-                }));
-            }
-        };
-        Self::from_tokenizer(tokenizer).await
+        let tokenizer = mdc
+            .tokenizer
+            .as_ref()
+            .map(TokenizerKind::load)
+            .transpose()?
+            .map(Tokenizer::from);
+        Ok(Arc::new(Self {
+            tokenizer,
+            validate_engine_decode: false,
+            recorder: TraceRecorder::from_env()?,
+        }))
     }
 
     fn decoder(
@@ -114,6 +130,8 @@ impl Backend {
             stream,
             decoder,
             validate_engine_decode: self.validate_engine_decode,
+            timings: None,
+            stopped: false,
         })
     }
 }
@@ -133,12 +151,18 @@ impl
         next: ServerStreamingEngine<BackendInput, Annotated<LLMEngineOutput>>,
     ) -> Result<ManyOut<Annotated<BackendOutput>>> {
         let stop_conditions = request.stop_conditions.clone();
+        let prompt = self.recorder.is_some().then(|| request.token_ids.clone());
+        let timings = request.get::<StageTimings>(STAGE_TIMINGS_CONTEXT_KEY).ok();
         let next_stream = next.generate(request).await?;
 
         let context = next_stream.context();
-        let state = self.decoder(next_stream, stop_conditions)?;
+        let mut state = self.decoder(next_stream, stop_conditions)?;
+        state.timings = timings;
 
         let processed_stream = stream::unfold(state, |mut state| async move {
+            if state.stopped {
+                return None;
+            }
             match state.stream.next().await {
                 Some(output) => {
                     // move to state.process_output
@@ -158,7 +182,12 @@ impl
 
                     let data = output.data.as_ref().unwrap();
 
+                    let span = state
+                        .timings
+                        .as_ref()
+                        .map(|timings| timings.span(DETOKENIZE_STAGE));
                     let result = state.decoder.process_token_ids(&data.token_ids).unwrap();
+                    drop(span);
 
                     // todo - propagate finish reason details - possibly an annotation
                     let finish_reason = match &result.stop_trigger {
@@ -176,10 +205,30 @@ impl
                             "upstream did not provide a finish reason; issuing a stop_generation request to free resources",
                         );
                         state.stream.context().stop_generating();
+                        state.stopped = true;
                     }
 
-                    let text = result.text;
+                    let mut text = result.text;
                     let tokens = result.tokens;
+                    // The tokens up to the stop, without a hidden stop token
+                    let kept = tokens.len()
+                        - matches!(
+                            result.stop_trigger,
+                            Some(StopTrigger::HiddenStopTokenDetected(_))
+                        ) as usize;
+
+                    // The last output, return the partial characters held back so far
+                    let hide_text = result
+                        .stop_trigger
+                        .as_ref()
+                        .is_some_and(StopTrigger::should_hide_text);
+                    if (data.finish_reason.is_some() || finish_reason.is_some()) && !hide_text {
+                        match state.decoder.flush() {
+                            Ok(Some(rest)) => text.get_or_insert_with(String::new).push_str(&rest),
+                            Ok(None) => {}
+                            Err(err) => tracing::warn!(%err, "Failed decoding the last tokens"),
+                        }
+                    }
 
                     if state.validate_engine_decode {
                         if data.finish_reason != finish_reason {
@@ -202,6 +251,12 @@ impl
                     data.finish_reason = finish_reason;
                     data.text = text;
                     data.tokens = Some(tokens);
+                    if let Some(log_probs) = data.log_probs.as_mut() {
+                        log_probs.truncate(kept);
+                    }
+                    if let Some(top_log_probs) = data.top_log_probs.as_mut() {
+                        top_log_probs.truncate(kept);
+                    }
 
                     output.data = Some(data);
 
@@ -212,17 +267,36 @@ impl
             }
         });
 
+        let processed_stream = match (&self.recorder, prompt) {
+            (Some(recorder), Some(prompt)) => {
+                recorder.record(&prompt, processed_stream).left_stream()
+            }
+            _ => processed_stream.right_stream(),
+        };
+
         // convert stream of processed Annotated<LLMEngineOutput> to Annotated<BackendOutput>
         //let mdcsum = self.mdcsum.clone();
+        let tokenizer = self.tokenizer.clone();
         let stream = processed_stream.map(move |output| {
             output.map_data(|data| {
+                let logprobs = match (&tokenizer, &data.log_probs) {
+                    (Some(tokenizer), Some(log_probs)) => Some(position_logprobs(
+                        tokenizer,
+                        &data.token_ids,
+                        log_probs,
+                        data.top_log_probs,
+                    )),
+                    _ => None,
+                };
                 Ok(BackendOutput {
                     token_ids: data.token_ids,
                     tokens: data.tokens.unwrap_or_default(),
                     text: data.text,
                     cum_log_probs: data.cum_log_probs,
                     log_probs: data.log_probs,
+                    logprobs,
                     finish_reason: data.finish_reason,
+                    spec_decode: data.spec_decode,
                     //mdcsum: mdcsum.clone(),
                 })
             })
@@ -251,20 +325,12 @@ pub struct Decoder {
     hidden_stop_ids: HashSet<TokenIdType>,
 
     // text sequences that if found in the response will trigger a stop condition after the
-    // minimum number of tokens have been generated
-    hidden_stop_sequences: Vec<String>,
+    // minimum number of tokens have been generated. Holds back text that could be the start
+    // of one.
+    stops: StopSequences,
 
     // number of generated tokens
     generated_tokens: u32,
-
-    // content jailed by partial hidden stop matches
-    jail: String,
-
-    // maximum number of bytes for the largest stop sequence
-    jail_max_bytes: usize,
-
-    // the number of bytes currently jailed
-    jailed_bytes: usize,
     // mdcsum
     //mdcsum: String,
 }
@@ -329,30 +395,16 @@ impl Decoder {
             .copied()
             .collect();
 
-        let hidden_stop_sequences: Vec<String> = stop_condition
-            .stop
-            .unwrap_or_default()
-            .iter()
-            .map(|x| x.to_string())
-            .collect();
-
-        let jail_max_bytes = hidden_stop_sequences
-            .iter()
-            .map(|x| x.len())
-            .max()
-            .unwrap_or(0);
+        let stops = StopSequences::new(stop_condition.stop.unwrap_or_default());
 
         Self {
             decode_stream,
             hidden_stop_ids,
-            hidden_stop_sequences,
+            stops,
             //visible_stop_ids: HashSet::new(),
             //visible_stop_sequences: Vec::new(),
             min_tokens: stop_condition.min_tokens.unwrap_or(0),
             generated_tokens: 0,
-            jail: String::new(),
-            jail_max_bytes,
-            jailed_bytes: 0,
         }
     }
 
@@ -371,62 +423,30 @@ impl Decoder {
 
         // stop conditions to not apply until the minimum number of tokens have been generated
         if self.generated_tokens < self.min_tokens {
+            let token = token.map(|token| self.stops.flush() + &token);
             return Ok(StepResult::ok(token));
         }
 
-        // check for hidden stop tokens - eos takes precedence
+        // check for hidden stop tokens - eos takes precedence. The token's text is hidden, not
+        // the text held back for the stop sequences.
         if self.hidden_stop_ids.contains(&token_id) {
             return Ok(StepResult::with_stop_trigger(
-                token,
+                non_empty(self.stops.flush()),
                 StopTrigger::HiddenStopTokenDetected(token_id),
             ));
         }
 
-        // check stop sequences - the jail will always hold at least the largest stop sequence
-        // if jail_max_bytes is 0, then there are no stop sequences
-        if self.jail_max_bytes > 0 {
-            if let Some(token) = &token {
-                let pre_append = self.jail.len();
-                log::debug!("pre_append: {}", pre_append);
-                log::debug!("jail: {}", self.jail);
-                self.jail.push_str(token);
-                log::debug!("post_append: {}", self.jail.len());
-                log::debug!("jail: {}", self.jail);
-
-                for seq in &self.hidden_stop_sequences {
-                    log::debug!("stop seq: {}", seq);
-                    if let Some(offset) =
-                        galil_seiferas::gs_find(self.jail.as_bytes(), seq.as_bytes())
-                    {
-                        log::debug!("offset: {}", offset);
-                        // return only new bytes after pre_append .. offset+seq.len()
-                        // example: seq = "ox", token = "boxes", return "b"
-                        // note: this changes when we start jailing tokens for partial matches
-                        // on the suffix of the jail with prefixes of the stop sequences
-                        //
-                        // we might have returned a partial match, if so, then offset < pre_append
-                        // in that case, we return the empty string
-                        let partial_token = if offset >= pre_append {
-                            self.jail[pre_append..offset].to_string()
-                        } else {
-                            "".to_string()
-                        };
-                        return Ok(StepResult::with_stop_trigger(
-                            Some(partial_token),
-                            StopTrigger::HiddenStopSequenceDetected(seq.to_string()),
-                        ));
-                    }
-                }
-
-                if self.jail.len() > self.jail_max_bytes {
-                    // truncate the jail
-                    let drain_len = self.jail.len() - self.jail_max_bytes;
-                    self.jail.drain(0..drain_len);
-                }
-            }
+        // check stop sequences, returning only the text that can't be the start of one
+        let Some(token) = token else {
+            return Ok(StepResult::ok(None));
+        };
+        match self.stops.push(&token) {
+            Ok(text) => Ok(StepResult::ok(non_empty(text))),
+            Err(found) => Ok(StepResult::with_stop_trigger(
+                non_empty(found.text),
+                StopTrigger::HiddenStopSequenceDetected(found.stop),
+            )),
         }
-
-        Ok(StepResult::ok(token))
     }
 
     pub fn process_token_ids(&mut self, token_ids: &[TokenIdType]) -> Result<SeqResult> {
@@ -439,15 +459,9 @@ impl Decoder {
                 stop_trigger,
             } = self.step(*token_id)?;
 
-            let hide_text = stop_trigger
-                .as_ref()
-                .map(|x| x.should_hide_text())
-                .unwrap_or(false);
-
-            if !hide_text {
-                if let Some(token) = &token {
-                    text.get_or_insert_with(String::new).push_str(token);
-                }
+            // the stop token or sequence is already left out
+            if let Some(token) = &token {
+                text.get_or_insert_with(String::new).push_str(token);
             }
             tokens.push(token);
 
@@ -467,6 +481,18 @@ impl Decoder {
         })
     }
 
+    /// The text held back, by the [`DecodeStream`] waiting for the rest of a character and
+    /// for a stop sequence that wasn't generated. Call once the stream is over, unless it
+    /// ended on a stop token or sequence.
+    pub fn flush(&mut self) -> Result<Option<String>> {
+        let rest = self.decode_stream.flush()?.unwrap_or_default();
+        let text = match self.stops.push(&rest) {
+            Ok(text) => text + &self.stops.flush(),
+            Err(found) => found.text,
+        };
+        Ok(non_empty(text))
+    }
+
     fn return_token(&self, token: Option<String>) -> StepResult {
         StepResult {
             token,
@@ -484,13 +510,45 @@ impl Decoder {
             stop_trigger: Some(stop_trigger),
         }
     }
+}
 
-    fn jailed_string(&self) -> Option<String> {
-        if self.jailed_bytes > 0 {
-            // get the last jailed_bytes from the jail
-            Some(self.jail[self.jail.len() - self.jailed_bytes..].to_string())
-        } else {
-            None
-        }
+/// The log probability of each generated token and of the alternatives at its position, with
+/// the text of each token. `log_probs` can be shorter than `token_ids`, when the tokens after
+/// it were a stop.
+fn position_logprobs(
+    tokenizer: &Tokenizer,
+    token_ids: &[TokenIdType],
+    log_probs: &[f64],
+    top_log_probs: Option<Vec<Vec<TokenLogProb>>>,
+) -> Vec<PositionLogProbs> {
+    let decode = |token_id| tokenizer.decode(&[token_id], false).ok();
+    let mut top_log_probs = top_log_probs.unwrap_or_default().into_iter();
+    token_ids
+        .iter()
+        .zip(log_probs)
+        .map(|(token_id, logprob)| {
+            let mut top = top_log_probs.next().unwrap_or_default();
+            for alternative in &mut top {
+                if alternative.token.is_none() {
+                    alternative.token = decode(alternative.token_id);
+                }
+            }
+            PositionLogProbs {
+                token: TokenLogProb {
+                    token_id: *token_id,
+                    token: decode(*token_id),
+                    logprob: *logprob,
+                },
+                top,
+            }
+        })
+        .collect()
+}
+
+fn non_empty(text: String) -> Option<String> {
+    if text.is_empty() {
+        None
+    } else {
+        Some(text)
     }
 }
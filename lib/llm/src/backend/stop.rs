@@ -0,0 +1,159 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Stop sequences
+//!
+//! The text generated for a request stops at the first of its `stop` strings, which is not
+//! returned. Text is streamed as it is generated, so the end of it that could be the start of
+//! a stop string is held back until the next text shows whether it is. Once returned, text
+//! is never taken back.
+//!
+//! The [`Backend`](super::Backend) applies this to engines returning token ids, and
+//! [`StreamingEngineAdapter`](crate::engines::StreamingEngineAdapter) to engines returning
+//! text, so `stop` behaves the same whichever engine serves the model.
+
+/// The stop strings of a request and the text held back for them
+#[derive(Debug, Clone, Default)]
+pub struct StopSequences {
+    stops: Vec<String>,
+
+    /// Generated text not yet returned, a prefix of one of the stop strings
+    held: String,
+}
+
+/// What [`StopSequences::push`] found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StopMatch {
+    /// The text before the stop string, not returned yet
+    pub text: String,
+
+    /// The stop string generated
+    pub stop: String,
+}
+
+impl StopSequences {
+    pub fn new(stops: impl IntoIterator<Item = String>) -> Self {
+        StopSequences {
+            stops: stops.into_iter().filter(|stop| !stop.is_empty()).collect(),
+            held: String::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stops.is_empty()
+    }
+
+    /// Add generated text. Returns the text that can be returned, or the match if a stop
+    /// string is complete, after which the rest of the generation is to be discarded.
+    pub fn push(&mut self, text: &str) -> Result<String, StopMatch> {
+        if self.stops.is_empty() {
+            return Ok(text.to_string());
+        }
+        self.held.push_str(text);
+
+        // The earliest match, the longest of those at the same place
+        let found = self
+            .stops
+            .iter()
+            .filter_map(|stop| Some((self.held.find(stop.as_str())?, stop)))
+            .min_by_key(|(offset, stop)| (*offset, std::cmp::Reverse(stop.len())));
+        if let Some((offset, stop)) = found {
+            let stop = stop.clone();
+            let mut text = std::mem::take(&mut self.held);
+            text.truncate(offset);
+            return Err(StopMatch { text, stop });
+        }
+
+        let keep = self.partial_match_len();
+        let ready = self.held.len() - keep;
+        let rest = self.held.split_off(ready);
+        Ok(std::mem::replace(&mut self.held, rest))
+    }
+
+    /// The text held back, when the generation ended without finishing a stop string
+    pub fn flush(&mut self) -> String {
+        std::mem::take(&mut self.held)
+    }
+
+    /// Length of the longest end of the held text that is the start of a stop string
+    fn partial_match_len(&self) -> usize {
+        let mut longest = 0;
+        for stop in &self.stops {
+            let max = stop.len().saturating_sub(1).min(self.held.len());
+            for len in (longest + 1..=max).rev() {
+                let start = self.held.len() - len;
+                if self.held.is_char_boundary(start) && stop.starts_with(&self.held[start..]) {
+                    longest = len;
+                    break;
+                }
+            }
+        }
+        longest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stops(strings: &[&str]) -> StopSequences {
+        StopSequences::new(strings.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn test_stop_sequences() {
+        let mut stop = stops(&["</answer>", "\n\n"]);
+        assert_eq!(stop.push("The answer"), Ok("The answer".to_string()));
+        // Could be the start of either stop string
+        assert_eq!(stop.push(" is 42\n"), Ok(" is 42".to_string()));
+        assert_eq!(stop.push("<"), Ok("\n".to_string()));
+        assert_eq!(stop.push("/ans"), Ok("".to_string()));
+        assert_eq!(stop.push("wer"), Ok("".to_string()));
+        // Not a stop string after all
+        assert_eq!(stop.push("!"), Ok("</answer!".to_string()));
+        assert_eq!(
+            stop.push(". </answ"),
+            Ok(". ".to_string()),
+            "the stop string could still follow"
+        );
+        assert_eq!(
+            stop.push("er> and more"),
+            Err(StopMatch {
+                text: "".to_string(),
+                stop: "</answer>".to_string()
+            })
+        );
+
+        // A stop string within one push, text on both sides of it
+        let mut stop = stops(&["STOP"]);
+        assert_eq!(
+            stop.push("abcSTOPdef"),
+            Err(StopMatch {
+                text: "abc".to_string(),
+                stop: "STOP".to_string()
+            })
+        );
+
+        // The generation ends on a partial match
+        let mut stop = stops(&["éé"]);
+        assert_eq!(stop.push("café"), Ok("caf".to_string()));
+        assert_eq!(stop.flush(), "é");
+
+        // Nothing to do without stop strings
+        let mut stop = stops(&[""]);
+        assert!(stop.is_empty());
+        assert_eq!(stop.push("\n\n"), Ok("\n\n".to_string()));
+    }
+}
@@ -27,8 +27,10 @@ use crate::block_manager::storage::{
 };
 
 use cudarc::driver::CudaStream;
+use prometheus::{IntCounterVec, Opts};
 
 use std::ops::Range;
+use std::sync::LazyLock;
 
 pub use crate::block_manager::storage::{CudaAccessible, Local, Remote};
 pub use async_trait::async_trait;
@@ -88,6 +90,29 @@ pub enum TransferStrategy {
     Invalid,
 }
 
+/// Bytes written from one block to another, by [`TransferStrategy`]
+static TRANSFER_BYTES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    dynamo_runtime::metrics::register(
+        IntCounterVec::new(
+            Opts::new(
+                "dynamo_kvbm_transfer_bytes_total",
+                "Bytes of KV cache blocks transferred",
+            ),
+            &["strategy"],
+        )
+        .unwrap(),
+    )
+});
+
+/// Size of a block's data in bytes, summed over its layers
+fn block_bytes<B: ReadableBlock>(block: &B) -> usize {
+    let data = block.block_data(private::PrivateToken);
+    (0..data.num_layers())
+        .filter_map(|layer_idx| data.layer_view(layer_idx).ok())
+        .map(|view| view.size())
+        .sum()
+}
+
 /// Trait for determining the transfer strategy for writing from a local
 /// source to a target destination which could be local or remote
 pub trait WriteToStrategy<Target> {
@@ -138,7 +163,13 @@ where
 {
     fn write_to(&self, dst: &mut WB, notify: Option<String>) -> Result<(), TransferError> {
         let ctx = self.transfer_context();
-        match Self::write_to_strategy() {
+        let strategy = Self::write_to_strategy();
+        dynamo_runtime::fault::inject_blocking(
+            dynamo_runtime::fault::FaultPoint::BlockTransfer,
+            &format!("{strategy:?}"),
+        )
+        .map_err(|err| TransferError::ExecutionError(err.to_string()))?;
+        let result = match strategy {
             TransferStrategy::Memcpy => memcpy::copy_block(self, dst),
             TransferStrategy::CudaAsyncH2D
             | TransferStrategy::CudaAsyncD2H
@@ -150,8 +181,14 @@ where
                 "Unsupported copy strategy: {:?}",
                 RB::write_to_strategy()
             ))),
-        }
+        };
         // dispatch_copy_to(self, dst, self.transfer_context())
+        if result.is_ok() {
+            TRANSFER_BYTES
+                .with_label_values(&[format!("{strategy:?}").as_str()])
+                .inc_by(block_bytes(self) as u64);
+        }
+        result
     }
 }
 
@@ -54,9 +54,17 @@
 //!     can eventually be returned to the [`InactiveBlockPool`] when its final strong reference (the `Arc`
 //!     within `ImmutableBlock`) is dropped.
 //! 6.  Dropped [`MutableBlock`]s are automatically returned to the [`InactiveBlockPool`].
+//!
+//! ## Pinned Sessions:
+//!
+//! A session can pin its blocks with [`BlockPool::pin_session`]. Inactive pinned blocks are
+//! only reused once no unpinned block is available. Pins are charged against a per tenant
+//! [`PinBudgets`] and released with [`BlockPool::unpin_session`]. [`BlockPool::pin_metrics`]
+//! reports pinned vs evictable capacity.
 
 mod active;
 mod inactive;
+mod pinned;
 mod priority_key;
 mod state;
 
@@ -64,8 +72,11 @@ use active::ActiveBlockPool;
 use derive_builder::Builder;
 use derive_getters::Dissolve;
 use inactive::InactiveBlockPool;
+use pinned::PinRegistry;
 use priority_key::PriorityKey;
 
+pub use pinned::{PinBudgets, PinMetrics, SessionPin, TenantPinUsage};
+
 pub use super::block::{ImmutableBlock, MutableBlock};
 
 use super::block::{
@@ -77,7 +88,7 @@ use super::storage::Storage;
 use crate::tokens::{SequenceHash, TokenBlock};
 
 use std::{
-    collections::{BTreeSet, HashMap, VecDeque},
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
     sync::{Arc, Weak},
 };
 use tokio_util::sync::CancellationToken;
@@ -101,6 +112,18 @@ pub enum BlockPoolError {
     #[error("Progress engine shutdown")]
     ProgressEngineShutdown,
 
+    #[error(
+        "Pin budget exceeded for tenant {tenant}, requested: {requested}, available: {available}"
+    )]
+    PinBudgetExceeded {
+        tenant: String,
+        requested: usize,
+        available: usize,
+    },
+
+    #[error("Invalid pin: {0}")]
+    InvalidPin(String),
+
     #[error(transparent)]
     BlockError(#[from] BlockError),
 }
@@ -116,15 +139,18 @@ pub struct BlockPoolArgs<S: Storage, M: BlockMetadata> {
 
     #[builder(default)]
     blocks: Vec<Block<S, M>>,
+
+    #[builder(default)]
+    pin_budgets: PinBudgets,
 }
 
 impl<S: Storage, M: BlockMetadata> BlockPoolArgsBuilder<S, M> {
     pub fn build(self) -> anyhow::Result<BlockPool<S, M>> {
         let args = self.build_internal()?;
-        let (event_manager, cancel_token, blocks) = args.dissolve();
+        let (event_manager, cancel_token, blocks, pin_budgets) = args.dissolve();
 
         tracing::info!("building block pool");
-        let pool = BlockPool::new(event_manager, cancel_token, blocks);
+        let pool = BlockPool::new(event_manager, cancel_token, blocks, pin_budgets);
 
         Ok(pool)
     }
@@ -178,6 +204,10 @@ enum PriorityRequest<S: Storage, M: BlockMetadata> {
 
 enum ControlRequest<S: Storage, M: BlockMetadata> {
     AddBlocks(Unary<Vec<Block<S, M>>, ()>),
+    PinSession(Unary<SessionPin, Result<(), BlockPoolError>>),
+    UnpinSession(Unary<String, ()>),
+    SetPinBudget(Unary<(String, Option<usize>), ()>),
+    PinMetrics(Unary<(), PinMetrics>),
 }
 
 impl<S: Storage, M: BlockMetadata> BlockPool<S, M> {
@@ -200,9 +230,10 @@ impl<S: Storage, M: BlockMetadata> BlockPool<S, M> {
         event_manager: Arc<dyn EventManager>,
         cancel_token: CancellationToken,
         blocks: Vec<Block<S, M>>,
+        pin_budgets: PinBudgets,
     ) -> Self {
         let (pool, progress_engine) =
-            Self::with_progress_engine(event_manager, cancel_token, blocks);
+            Self::with_progress_engine(event_manager, cancel_token, blocks, pin_budgets);
 
         // pool.runtime.handle().spawn(async move {
         //     let mut progress_engine = progress_engine;
@@ -239,12 +270,19 @@ impl<S: Storage, M: BlockMetadata> BlockPool<S, M> {
         event_manager: Arc<dyn EventManager>,
         cancel_token: CancellationToken,
         blocks: Vec<Block<S, M>>,
+        pin_budgets: PinBudgets,
     ) -> (Self, ProgressEngine<S, M>) {
         let (priority_tx, priority_rx) = tokio::sync::mpsc::unbounded_channel();
         let (ctrl_tx, ctrl_rx) = tokio::sync::mpsc::unbounded_channel();
 
-        let progress_engine =
-            ProgressEngine::<S, M>::new(event_manager, priority_rx, ctrl_rx, cancel_token, blocks);
+        let progress_engine = ProgressEngine::<S, M>::new(
+            event_manager,
+            priority_rx,
+            ctrl_rx,
+            cancel_token,
+            blocks,
+            pin_budgets,
+        );
 
         (
             Self {
@@ -436,11 +474,96 @@ impl<S: Storage, M: BlockMetadata> BlockPool<S, M> {
         // Await a response
         Ok(resp_rx)
     }
+
+    /// Pins the blocks of a session so they are evicted last.
+    ///
+    /// The blocks are charged to the tenant of the [`SessionPin`]. Pinning more blocks than the
+    /// tenant's budget allows fails with [`BlockPoolError::PinBudgetExceeded`] and pins nothing.
+    /// Blocks can be pinned before they are registered; the pin applies once they are.
+    pub async fn pin_session(&self, pin: SessionPin) -> Result<(), BlockPoolError> {
+        self._pin_session(pin)?
+            .await
+            .map_err(|_| BlockPoolError::ProgressEngineShutdown)?
+    }
+
+    /// Blocking version of [`BlockPool::pin_session`].
+    pub fn pin_session_blocking(&self, pin: SessionPin) -> Result<(), BlockPoolError> {
+        self._pin_session(pin)?
+            .recv()
+            .map_err(|_| BlockPoolError::ProgressEngineShutdown)?
+    }
+
+    fn _pin_session(&self, pin: SessionPin) -> UnaryResponse<Result<(), BlockPoolError>> {
+        let (req, resp_rx) = Unary::<_, Result<(), BlockPoolError>>::make_request(pin);
+
+        self.ctrl_tx
+            .send(ControlRequest::PinSession(req))
+            .map_err(|_| BlockPoolError::ProgressEngineShutdown)?;
+
+        Ok(resp_rx)
+    }
+
+    /// Releases all pins held by a session. Unknown sessions are ignored.
+    pub async fn unpin_session(&self, session_id: &str) -> Result<(), BlockPoolError> {
+        self._unpin_session(session_id)?
+            .await
+            .map_err(|_| BlockPoolError::ProgressEngineShutdown)
+    }
+
+    /// Blocking version of [`BlockPool::unpin_session`].
+    pub fn unpin_session_blocking(&self, session_id: &str) -> Result<(), BlockPoolError> {
+        self._unpin_session(session_id)?
+            .recv()
+            .map_err(|_| BlockPoolError::ProgressEngineShutdown)
+    }
+
+    fn _unpin_session(&self, session_id: &str) -> UnaryResponse<()> {
+        let (req, resp_rx) = Unary::<_, ()>::make_request(session_id.to_string());
+
+        self.ctrl_tx
+            .send(ControlRequest::UnpinSession(req))
+            .map_err(|_| BlockPoolError::ProgressEngineShutdown)?;
+
+        Ok(resp_rx)
+    }
+
+    /// Sets the number of blocks `tenant` may pin, `None` for unlimited.
+    ///
+    /// Lowering a budget does not release existing pins, it only rejects new ones.
+    pub async fn set_pin_budget(
+        &self,
+        tenant: &str,
+        budget: Option<usize>,
+    ) -> Result<(), BlockPoolError> {
+        let (req, resp_rx) = Unary::<_, ()>::make_request((tenant.to_string(), budget));
+
+        self.ctrl_tx
+            .send(ControlRequest::SetPinBudget(req))
+            .map_err(|_| BlockPoolError::ProgressEngineShutdown)?;
+
+        resp_rx
+            .await
+            .map_err(|_| BlockPoolError::ProgressEngineShutdown)
+    }
+
+    /// Returns a snapshot of pinned vs evictable capacity.
+    pub async fn pin_metrics(&self) -> Result<PinMetrics, BlockPoolError> {
+        let (req, resp_rx) = Unary::<_, PinMetrics>::make_request(());
+
+        self.ctrl_tx
+            .send(ControlRequest::PinMetrics(req))
+            .map_err(|_| BlockPoolError::ProgressEngineShutdown)?;
+
+        resp_rx
+            .await
+            .map_err(|_| BlockPoolError::ProgressEngineShutdown)
+    }
 }
 
 struct State<S: Storage, M: BlockMetadata> {
     active: ActiveBlockPool<S, M>,
     inactive: InactiveBlockPool<S, M>,
+    pins: PinRegistry,
     registry: BlockRegistry,
     return_tx: tokio::sync::mpsc::UnboundedSender<Block<S, M>>,
     event_manager: Arc<dyn EventManager>,
@@ -468,9 +591,9 @@ mod tests {
             self,
         ) -> anyhow::Result<(BlockPool<S, M>, ProgressEngine<S, M>)> {
             let args = self.build_internal()?;
-            let (event_manager, cancel_token, blocks) = args.dissolve();
+            let (event_manager, cancel_token, blocks, pin_budgets) = args.dissolve();
             let (pool, progress_engine) =
-                BlockPool::with_progress_engine(event_manager, cancel_token, blocks);
+                BlockPool::with_progress_engine(event_manager, cancel_token, blocks, pin_budgets);
 
             Ok((pool, progress_engine))
         }
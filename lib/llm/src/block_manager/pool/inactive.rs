@@ -26,6 +26,12 @@ pub struct InactiveBlockPool<S: Storage, M: BlockMetadata> {
     // Ordered by timestamp (oldest first)
     priority_set: BTreeSet<PriorityKey<M>>,
 
+    // Blocks of pinned sessions; only evicted once the priority set is empty
+    pinned_set: BTreeSet<PriorityKey<M>>,
+
+    // Sequence hashes currently pinned by at least one session
+    pinned: HashSet<SequenceHash>,
+
     // Fully Uninitialized
     uninitialized_set: VecDeque<Block<S, M>>,
 
@@ -46,6 +52,8 @@ impl<S: Storage, M: BlockMetadata> InactiveBlockPool<S, M> {
         Self {
             lookup_map: HashMap::new(),
             priority_set: BTreeSet::new(),
+            pinned_set: BTreeSet::new(),
+            pinned: HashSet::new(),
             uninitialized_set: VecDeque::new(),
             return_tick: 0,
             total_blocks: 0,
@@ -73,6 +81,45 @@ impl<S: Storage, M: BlockMetadata> InactiveBlockPool<S, M> {
         self.uninitialized_set.len() as u64 + self.lookup_map.len() as u64
     }
 
+    /// Returns the number of available blocks that are protected by a session pin.
+    pub fn pinned_blocks(&self) -> u64 {
+        self.pinned_set.len() as u64
+    }
+
+    /// Returns the number of available blocks that are not pinned and are reused first.
+    pub fn evictable_blocks(&self) -> u64 {
+        self.uninitialized_set.len() as u64 + self.priority_set.len() as u64
+    }
+
+    /// Marks the block with `sequence_hash` as pinned, now or whenever it is returned to the pool.
+    ///
+    /// Pinned blocks are only acquired by [`InactiveBlockPool::acquire_free_block`] once no
+    /// unpinned block is available.
+    pub fn pin(&mut self, sequence_hash: SequenceHash) {
+        if !self.pinned.insert(sequence_hash) {
+            return;
+        }
+        if let Some(block) = self.lookup_map.get(&sequence_hash) {
+            let priority_key = PriorityKey::new(block.metadata().clone(), sequence_hash);
+            if self.priority_set.remove(&priority_key) {
+                self.pinned_set.insert(priority_key);
+            }
+        }
+    }
+
+    /// Reverts [`InactiveBlockPool::pin`], the block is evicted in normal priority order again.
+    pub fn unpin(&mut self, sequence_hash: SequenceHash) {
+        if !self.pinned.remove(&sequence_hash) {
+            return;
+        }
+        if let Some(block) = self.lookup_map.get(&sequence_hash) {
+            let priority_key = PriorityKey::new(block.metadata().clone(), sequence_hash);
+            if self.pinned_set.remove(&priority_key) {
+                self.priority_set.insert(priority_key);
+            }
+        }
+    }
+
     /// Inserts a block into the pool using its sequence hash for potential reuse.
     ///
     /// If an entry with the same priority key already exists in the [`priority_set`],
@@ -89,7 +136,7 @@ impl<S: Storage, M: BlockMetadata> InactiveBlockPool<S, M> {
     #[instrument(level = "trace", skip(self, block), fields(sequence_hash = ?sequence_hash))]
     fn insert_with_sequence_hash(&mut self, block: Block<S, M>, sequence_hash: SequenceHash) {
         let priority_key = PriorityKey::new(block.metadata().clone(), sequence_hash);
-        if self.priority_set.contains(&priority_key) {
+        if self.priority_set.contains(&priority_key) || self.pinned_set.contains(&priority_key) {
             tracing::trace!("multiple entries with the same priority key, resetting block and inserting into uninitialized set");
             let mut block = block;
             block.reset();
@@ -98,7 +145,11 @@ impl<S: Storage, M: BlockMetadata> InactiveBlockPool<S, M> {
             self.lookup_map.entry(sequence_hash)
         {
             tracing::trace!("inserting block to map and priority set");
-            self.priority_set.insert(priority_key);
+            if self.pinned.contains(&sequence_hash) {
+                self.pinned_set.insert(priority_key);
+            } else {
+                self.priority_set.insert(priority_key);
+            }
             e.insert(block);
         } else {
             tracing::trace!("multiple entries in lookup map with the same sequence hash, inserting into uninitialized set");
@@ -240,7 +291,9 @@ impl<S: Storage, M: BlockMetadata> InactiveBlockPool<S, M> {
             Some(block) => {
                 // Remove from priority set
                 let priority_key = PriorityKey::new(block.metadata().clone(), sequence_hash);
-                self.priority_set.remove(&priority_key);
+                if !self.priority_set.remove(&priority_key) {
+                    self.pinned_set.remove(&priority_key);
+                }
                 Some(block)
             }
             None => None,
@@ -343,7 +396,8 @@ impl<S: Storage, M: BlockMetadata> InactiveBlockPool<S, M> {
     /// Acquires a single free block from the pool.
     ///
     /// Prioritizes blocks from the [`uninitialized_set`] first, then takes the
-    /// lowest priority block from the [`priority_set`] (and [`lookup_map`]).
+    /// lowest priority block from the [`priority_set`] (and [`lookup_map`]), and only
+    /// then the lowest priority pinned block.
     /// If a block is taken from the priority set, it is reset.
     ///
     /// # Returns
@@ -367,8 +421,13 @@ impl<S: Storage, M: BlockMetadata> InactiveBlockPool<S, M> {
         }
 
         // if we have blocks in the priority set, pop the first (it's sorted by priority)
+        // pinned blocks are only evicted once there is nothing else left
         // a fatal error will occur if the block is not found in the lookup map
-        if let Some(key) = self.priority_set.pop_first() {
+        if let Some(key) = self
+            .priority_set
+            .pop_first()
+            .or_else(|| self.pinned_set.pop_first())
+        {
             tracing::trace!("Acquired priority/registered block map; resetting block");
             match self.lookup_map.remove(&key.sequence_hash()) {
                 Some(mut block) => {
@@ -736,6 +795,42 @@ pub(crate) mod tests {
         }
     }
 
+    #[test]
+    fn test_pinned_blocks_evicted_last() {
+        let mut pool = InactiveBlockPool::new();
+
+        let blocks = create_blocks(create_token_sequence(&[1, 2, 3, 4, 5, 6]), 2);
+        let hashes: Vec<_> = blocks.iter().map(|b| b.sequence_hash().unwrap()).collect();
+
+        // Pin the first block before it is in the pool, the second one after
+        pool.pin(hashes[0]);
+        pool.add_blocks_with_state(blocks);
+        pool.pin(hashes[1]);
+
+        assert_eq!(pool.available_blocks(), 3);
+        assert_eq!(pool.pinned_blocks(), 2);
+        assert_eq!(pool.evictable_blocks(), 1);
+
+        // The only unpinned block is evicted first
+        let block = pool.acquire_free_block().unwrap();
+        assert!(block.state().is_reset());
+        assert_eq!(pool.match_sequence_hashes(vec![hashes[2]]).len(), 0);
+        assert_eq!(pool.pinned_blocks(), 2);
+
+        // Unpinned blocks are evictable again, pinned ones still match
+        pool.unpin(hashes[0]);
+        assert_eq!(pool.pinned_blocks(), 1);
+        assert_eq!(pool.evictable_blocks(), 1);
+
+        let matched = pool.match_sequence_hashes(vec![hashes[1]]);
+        assert_eq!(matched.len(), 1);
+        assert_eq!(pool.pinned_blocks(), 0);
+
+        // Returned pinned blocks go back to the pinned set
+        pool.return_blocks(matched);
+        assert_eq!(pool.pinned_blocks(), 1);
+    }
+
     #[test]
     fn test_basic_sequence_matching() {
         let mut pool = InactiveBlockPool::new();
@@ -0,0 +1,277 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Session pinning
+//!
+//! A session (for example a long running agent) can pin the blocks holding its KV cache.
+//! Pinned blocks that become inactive are only evicted once no unpinned block is left to reuse.
+//! Each tenant has a budget of blocks it may pin, so one tenant cannot make the whole pool
+//! sticky.
+
+use super::*;
+
+use std::collections::hash_map::Entry;
+
+/// Request to pin the blocks of a session
+#[derive(Debug, Clone)]
+pub struct SessionPin {
+    /// Unique id of the session. Pinning an already pinned session adds to its blocks.
+    pub session_id: String,
+
+    /// Tenant the pinned blocks are charged to
+    pub tenant: String,
+
+    /// Sequence hashes of the blocks to pin
+    pub sequence_hashes: Vec<SequenceHash>,
+}
+
+/// Maximum number of blocks each tenant may pin
+#[derive(Debug, Clone, Default)]
+pub struct PinBudgets {
+    /// Budget of tenants without an entry in `tenants`. `None` means unlimited.
+    pub default: Option<usize>,
+
+    /// Per tenant budget overrides. A `None` value means unlimited.
+    pub tenants: HashMap<String, Option<usize>>,
+}
+
+impl PinBudgets {
+    pub fn budget(&self, tenant: &str) -> Option<usize> {
+        match self.tenants.get(tenant) {
+            Some(budget) => *budget,
+            None => self.default,
+        }
+    }
+}
+
+/// Pinned blocks charged to a single tenant
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TenantPinUsage {
+    pub pinned_blocks: usize,
+    pub budget: Option<usize>,
+    pub sessions: usize,
+}
+
+/// Snapshot of pinned vs evictable capacity in a [`BlockPool`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PinMetrics {
+    /// Total blocks managed by the pool
+    pub total_blocks: u64,
+
+    /// Distinct blocks pinned by at least one session, active or inactive
+    pub pinned_blocks: u64,
+
+    /// Inactive blocks protected by a pin, reused only when nothing else is available
+    pub pinned_inactive_blocks: u64,
+
+    /// Inactive blocks that can be reused right away
+    pub evictable_blocks: u64,
+
+    /// Usage per tenant
+    pub tenants: HashMap<String, TenantPinUsage>,
+}
+
+struct PinnedSession {
+    tenant: String,
+    sequence_hashes: Vec<SequenceHash>,
+}
+
+/// Book keeping of pinned sessions and tenant budgets
+#[derive(Default)]
+pub(crate) struct PinRegistry {
+    budgets: PinBudgets,
+    sessions: HashMap<String, PinnedSession>,
+
+    // number of sessions pinning each sequence hash
+    refcounts: HashMap<SequenceHash, usize>,
+
+    // blocks charged to each tenant
+    usage: HashMap<String, usize>,
+}
+
+impl PinRegistry {
+    pub(crate) fn new(budgets: PinBudgets) -> Self {
+        Self {
+            budgets,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn set_budget(&mut self, tenant: String, budget: Option<usize>) {
+        self.budgets.tenants.insert(tenant, budget);
+    }
+
+    /// Pin the blocks of a session, charging them to the tenant.
+    ///
+    /// Returns the sequence hashes that were not pinned by any session before.
+    pub(crate) fn pin(&mut self, pin: SessionPin) -> Result<Vec<SequenceHash>, BlockPoolError> {
+        let SessionPin {
+            session_id,
+            tenant,
+            sequence_hashes,
+        } = pin;
+
+        let existing = match self.sessions.get(&session_id) {
+            Some(session) if session.tenant != tenant => {
+                return Err(BlockPoolError::InvalidPin(format!(
+                    "session {session_id} is pinned by tenant {}",
+                    session.tenant
+                )));
+            }
+            Some(session) => session.sequence_hashes.as_slice(),
+            None => &[],
+        };
+
+        let mut added: Vec<SequenceHash> = Vec::new();
+        for hash in sequence_hashes {
+            if !existing.contains(&hash) && !added.contains(&hash) {
+                added.push(hash);
+            }
+        }
+
+        let used = self.usage.get(&tenant).copied().unwrap_or(0);
+        if let Some(budget) = self.budgets.budget(&tenant) {
+            if used + added.len() > budget {
+                return Err(BlockPoolError::PinBudgetExceeded {
+                    tenant,
+                    requested: added.len(),
+                    available: budget.saturating_sub(used),
+                });
+            }
+        }
+
+        let mut newly_pinned = Vec::new();
+        for hash in &added {
+            let count = self.refcounts.entry(*hash).or_insert(0);
+            if *count == 0 {
+                newly_pinned.push(*hash);
+            }
+            *count += 1;
+        }
+
+        *self.usage.entry(tenant.clone()).or_insert(0) += added.len();
+        self.sessions
+            .entry(session_id)
+            .or_insert_with(|| PinnedSession {
+                tenant,
+                sequence_hashes: Vec::new(),
+            })
+            .sequence_hashes
+            .extend(added);
+
+        Ok(newly_pinned)
+    }
+
+    /// Release all pins held by a session.
+    ///
+    /// Returns the sequence hashes no longer pinned by any session.
+    pub(crate) fn unpin(&mut self, session_id: &str) -> Vec<SequenceHash> {
+        let Some(session) = self.sessions.remove(session_id) else {
+            return Vec::new();
+        };
+
+        if let Entry::Occupied(mut e) = self.usage.entry(session.tenant) {
+            *e.get_mut() -= session.sequence_hashes.len();
+            if *e.get() == 0 {
+                e.remove();
+            }
+        }
+
+        let mut released = Vec::new();
+        for hash in session.sequence_hashes {
+            if let Entry::Occupied(mut e) = self.refcounts.entry(hash) {
+                *e.get_mut() -= 1;
+                if *e.get() == 0 {
+                    e.remove();
+                    released.push(hash);
+                }
+            }
+        }
+        released
+    }
+
+    pub(crate) fn pinned_blocks(&self) -> usize {
+        self.refcounts.len()
+    }
+
+    pub(crate) fn tenant_usage(&self) -> HashMap<String, TenantPinUsage> {
+        let mut tenants: HashMap<String, TenantPinUsage> = HashMap::new();
+        for session in self.sessions.values() {
+            let usage = tenants.entry(session.tenant.clone()).or_default();
+            usage.pinned_blocks += session.sequence_hashes.len();
+            usage.sessions += 1;
+        }
+        for (tenant, usage) in tenants.iter_mut() {
+            usage.budget = self.budgets.budget(tenant);
+        }
+        tenants
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pin(session_id: &str, tenant: &str, hashes: &[u64]) -> SessionPin {
+        SessionPin {
+            session_id: session_id.to_string(),
+            tenant: tenant.to_string(),
+            sequence_hashes: hashes.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_pin_budgets() {
+        let budgets = PinBudgets {
+            default: Some(3),
+            tenants: HashMap::from([("premium".to_string(), None)]),
+        };
+        let mut registry = PinRegistry::new(budgets);
+
+        assert_eq!(registry.pin(pin("a", "free", &[1, 2])).unwrap(), vec![1, 2]);
+
+        // Re-pinning known blocks is free, new ones are charged
+        assert_eq!(registry.pin(pin("a", "free", &[1, 2, 3])).unwrap(), vec![3]);
+        assert!(matches!(
+            registry.pin(pin("b", "free", &[4])),
+            Err(BlockPoolError::PinBudgetExceeded { available: 0, .. })
+        ));
+
+        // A session belongs to a single tenant
+        assert!(registry.pin(pin("a", "premium", &[9])).is_err());
+
+        // Shared blocks are only reported the first time they are pinned
+        assert_eq!(
+            registry.pin(pin("c", "premium", &[3, 4, 5, 6])).unwrap(),
+            vec![4, 5, 6]
+        );
+        assert_eq!(registry.pinned_blocks(), 6);
+
+        let usage = registry.tenant_usage();
+        assert_eq!(usage["free"].pinned_blocks, 3);
+        assert_eq!(usage["free"].budget, Some(3));
+        assert_eq!(usage["premium"].pinned_blocks, 4);
+        assert_eq!(usage["premium"].budget, None);
+
+        // Block 3 is still pinned by session c
+        let mut released = registry.unpin("a");
+        released.sort();
+        assert_eq!(released, vec![1, 2]);
+        assert!(registry.unpin("a").is_empty());
+        assert!(!registry.tenant_usage().contains_key("free"));
+
+        assert_eq!(registry.pin(pin("b", "free", &[4])).unwrap(), vec![]);
+    }
+}
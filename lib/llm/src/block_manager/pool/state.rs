@@ -24,10 +24,12 @@ impl<S: Storage, M: BlockMetadata> State<S, M> {
     fn new(
         event_manager: Arc<dyn EventManager>,
         return_tx: tokio::sync::mpsc::UnboundedSender<Block<S, M>>,
+        pin_budgets: PinBudgets,
     ) -> Self {
         Self {
             active: ActiveBlockPool::new(),
             inactive: InactiveBlockPool::new(),
+            pins: PinRegistry::new(pin_budgets),
             registry: BlockRegistry::new(event_manager.clone()),
             return_tx,
             event_manager,
@@ -73,6 +75,56 @@ impl<S: Storage, M: BlockMetadata> State<S, M> {
                     tracing::error!("failed to send response to add blocks");
                 }
             }
+            ControlRequest::PinSession(req) => {
+                let (pin, resp_tx) = req.dissolve();
+                let result = self.pin_session(pin);
+                if resp_tx.send(result).is_err() {
+                    tracing::error!("failed to send response to pin session");
+                }
+            }
+            ControlRequest::UnpinSession(req) => {
+                let (session_id, resp_tx) = req.dissolve();
+                self.unpin_session(&session_id);
+                if resp_tx.send(()).is_err() {
+                    tracing::error!("failed to send response to unpin session");
+                }
+            }
+            ControlRequest::SetPinBudget(req) => {
+                let ((tenant, budget), resp_tx) = req.dissolve();
+                self.pins.set_budget(tenant, budget);
+                if resp_tx.send(()).is_err() {
+                    tracing::error!("failed to send response to set pin budget");
+                }
+            }
+            ControlRequest::PinMetrics(req) => {
+                let ((), resp_tx) = req.dissolve();
+                if resp_tx.send(self.pin_metrics()).is_err() {
+                    tracing::error!("failed to send response to pin metrics");
+                }
+            }
+        }
+    }
+
+    pub fn pin_session(&mut self, pin: SessionPin) -> Result<(), BlockPoolError> {
+        for sequence_hash in self.pins.pin(pin)? {
+            self.inactive.pin(sequence_hash);
+        }
+        Ok(())
+    }
+
+    pub fn unpin_session(&mut self, session_id: &str) {
+        for sequence_hash in self.pins.unpin(session_id) {
+            self.inactive.unpin(sequence_hash);
+        }
+    }
+
+    pub fn pin_metrics(&self) -> PinMetrics {
+        PinMetrics {
+            total_blocks: self.inactive.total_blocks(),
+            pinned_blocks: self.pins.pinned_blocks() as u64,
+            pinned_inactive_blocks: self.inactive.pinned_blocks(),
+            evictable_blocks: self.inactive.evictable_blocks(),
+            tenants: self.pins.tenant_usage(),
         }
     }
 
@@ -246,9 +298,10 @@ impl<S: Storage, M: BlockMetadata> ProgressEngine<S, M> {
         ctrl_rx: tokio::sync::mpsc::UnboundedReceiver<ControlRequest<S, M>>,
         cancel_token: CancellationToken,
         blocks: Vec<Block<S, M>>,
+        pin_budgets: PinBudgets,
     ) -> Self {
         let (return_tx, return_rx) = tokio::sync::mpsc::unbounded_channel();
-        let mut state = State::<S, M>::new(event_manager, return_tx);
+        let mut state = State::<S, M>::new(event_manager, return_tx, pin_budgets);
 
         tracing::debug!(count = blocks.len(), "adding blocks to inactive pool");
         state.inactive.add_blocks(blocks);
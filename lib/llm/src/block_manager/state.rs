@@ -18,6 +18,7 @@ use super::*;
 use super::{block::Block, config::NixlOptions};
 
 use cudarc::driver::CudaStream;
+use dynamo_runtime::transports::tls::TransportTls;
 use std::sync::Arc;
 
 pub struct TransferContext {
@@ -184,6 +185,7 @@ impl<Metadata: BlockMetadata> KvBlockManagerState<Metadata> {
 
     /// Exports the local blockset configuration as a serialized object.
     pub fn export_local_blockset(&self) -> Result<SerializedNixlBlockSet> {
+        check_remote_transfer_allowed()?;
         SerializedNixlBlockSet::try_from(&self.local_block_set)
             .context("Failed to serialize local blockset")
     }
@@ -205,6 +207,7 @@ impl<Metadata: BlockMetadata> KvBlockManagerState<Metadata> {
         &self,
         serialized_blockset: SerializedNixlBlockSet,
     ) -> Result<()> {
+        check_remote_transfer_allowed()?;
         let remote = NixlBlockSet::try_from(serialized_blockset)
             .context("Failed to deserialize remote blockset")?;
 
@@ -352,6 +355,20 @@ impl<Metadata: BlockMetadata> std::fmt::Debug for KvBlockManagerState<Metadata>
     }
 }
 
+/// NIXL moves blocks between workers in the clear, over whichever transport UCX picks. With
+/// transport TLS configured, traffic between nodes must be encrypted, so blocks are neither
+/// offered to nor taken from other workers. Transfers within the worker are not affected.
+fn check_remote_transfer_allowed() -> Result<()> {
+    if TransportTls::global()?.is_some() {
+        anyhow::bail!(
+            "KV blocks are not transferred between workers with transport TLS \
+             ({}): NIXL does not encrypt them",
+            dynamo_runtime::transports::tls::CERT_ENV
+        );
+    }
+    Ok(())
+}
+
 fn create_layout<S: Storage + NixlRegisterableStorage>(
     mut builder: LayoutConfigBuilder,
     config: KvManagerLayoutConfig<S>,
@@ -13,6 +13,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::sync::Arc;
 use std::sync::LazyLock;
@@ -20,18 +21,32 @@ use std::time::Duration;
 
 use async_stream::stream;
 use async_trait::async_trait;
+use futures::StreamExt;
 
 use dynamo_runtime::engine::{AsyncEngine, AsyncEngineContextProvider, ResponseStream};
 use dynamo_runtime::pipeline::{Error, ManyOut, SingleIn};
 use dynamo_runtime::protocols::annotated::Annotated;
 
+use crate::backend::stop::StopSequences;
 use crate::backend::ExecutionContext;
+use crate::engines::fan_out::FanOutRequest;
+use crate::preprocessor::media::MediaError;
+use crate::preprocessor::prompt::OAIChatLikeRequest;
 use crate::preprocessor::BackendInput;
 use crate::protocols::common::llm_backend::LLMEngineOutput;
+use crate::protocols::common::StopConditionsProvider;
+use crate::protocols::error::RequestError;
 use crate::protocols::openai::{
     chat_completions::{NvCreateChatCompletionRequest, NvCreateChatCompletionStreamResponse},
     completions::{prompt_to_string, CompletionRequest, CompletionResponse},
+    embeddings::{NvCreateEmbeddingRequest, NvCreateEmbeddingResponse},
 };
+use crate::types::openai::embeddings::OpenAIEmbeddingsStreamingEngine;
+
+pub mod fan_out;
+pub mod load;
+pub mod mock;
+pub mod replay;
 
 //
 // The engines are each in their own crate under `lib/engines`
@@ -93,6 +108,14 @@ impl AsyncEngine<SingleIn<BackendInput>, ManyOut<Annotated<LLMEngineOutput>>, Er
     ) -> Result<ManyOut<Annotated<LLMEngineOutput>>, Error> {
         let (request, context) = incoming_request.into_parts();
         let ctx = context.context();
+        reject_unsupported(
+            "response_format JSON schema",
+            request.sampling_options.guided_decoding.is_some(),
+        )?;
+        reject_unsupported("logit_bias", request.sampling_options.logit_bias.is_some())?;
+        if !request.images.is_empty() {
+            return Err(MediaError::image_not_supported("served by the echo_core engine").into());
+        }
 
         let output = stream! {
             for tok in request.token_ids {
@@ -112,11 +135,24 @@ fn delta_core(tok: u32) -> Annotated<LLMEngineOutput> {
         text: None,
         cum_log_probs: None,
         log_probs: None,
+        top_log_probs: None,
         finish_reason: None,
+        spec_decode: None,
     };
     Annotated::from_data(delta)
 }
 
+/// The echo engines cannot constrain their output to a JSON schema, or bias it
+fn reject_unsupported(option: &str, requested: bool) -> Result<(), Error> {
+    if requested {
+        return Err(RequestError::invalid_request(format!(
+            "The echo engine does not support {option}"
+        ))
+        .into());
+    }
+    Ok(())
+}
+
 /// Engine that accepts un-preprocessed requests and echos the prompt back as the response
 /// Useful for testing ingress such as service-http.
 struct EchoEngineFull {}
@@ -125,11 +161,21 @@ struct EchoEngineFull {}
 //or OpenAIChatCompletions engine
 pub struct EngineDispatcher<E> {
     inner: E,
+    embeddings: Option<OpenAIEmbeddingsStreamingEngine>,
 }
 
 impl<E> EngineDispatcher<E> {
     pub fn new(inner: E) -> Self {
-        EngineDispatcher { inner }
+        EngineDispatcher {
+            inner,
+            embeddings: None,
+        }
+    }
+
+    /// Also serve embeddings of the model with this engine
+    pub fn with_embeddings(mut self, engine: OpenAIEmbeddingsStreamingEngine) -> Self {
+        self.embeddings = Some(engine);
+        self
     }
 }
 
@@ -145,10 +191,44 @@ pub trait StreamingEngine: Send + Sync {
         &self,
         req: SingleIn<NvCreateChatCompletionRequest>,
     ) -> Result<ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>, Error>;
+
+    /// True if the engine samples the `n` choices of a request itself. Otherwise
+    /// [`StreamingEngineAdapter`] sends it one request per choice.
+    fn supports_n(&self) -> bool {
+        false
+    }
+
+    /// The engine computing embeddings for the model, if it does.
+    fn embeddings(&self) -> Option<OpenAIEmbeddingsStreamingEngine> {
+        None
+    }
 }
 
 pub fn make_engine_full() -> Arc<dyn StreamingEngine> {
-    Arc::new(EngineDispatcher::new(EchoEngineFull {}))
+    Arc::new(EngineDispatcher::new(EchoEngineFull {}).with_embeddings(make_engine_embeddings()))
+}
+
+/// Length of the echo engine's embeddings when the request doesn't ask for `dimensions`
+const ECHO_EMBEDDING_DIMENSIONS: usize = 16;
+
+/// Engine that embeds each input as the normalized histogram of its bytes. The same input
+/// always gets the same embedding, and similar inputs similar ones. Useful for testing the
+/// `/v1/embeddings` route and its callers.
+pub fn make_engine_embeddings() -> OpenAIEmbeddingsStreamingEngine {
+    Arc::new(EchoEngineFull {})
+}
+
+/// The echo engine's embedding of `bytes`, see [`make_engine_embeddings`]
+fn echo_embedding(bytes: impl Iterator<Item = u32>, dimensions: usize) -> Vec<f32> {
+    let mut embedding = vec![0f32; dimensions];
+    for b in bytes {
+        embedding[b as usize % dimensions] += 1.0;
+    }
+    let norm = embedding.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        embedding.iter_mut().for_each(|x| *x /= norm);
+    }
+    embedding
 }
 
 #[async_trait]
@@ -164,6 +244,17 @@ impl
         incoming_request: SingleIn<NvCreateChatCompletionRequest>,
     ) -> Result<ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>, Error> {
         let (request, context) = incoming_request.transfer(());
+        reject_unsupported(
+            "response_format JSON schema",
+            !matches!(
+                request.inner.response_format,
+                None | Some(async_openai::types::ResponseFormat::Text)
+            ),
+        )?;
+        reject_unsupported("logit_bias", request.inner.logit_bias.is_some())?;
+        if !request.image_urls().is_empty() {
+            return Err(MediaError::image_not_supported("served by the echo_full engine").into());
+        }
         let deltas = request.response_generator();
         let ctx = context.context();
         let req = request.inner.messages.into_iter().next_back().unwrap();
@@ -188,6 +279,7 @@ impl
                 let inner = deltas.create_choice(0, Some(c.to_string()), None, None);
                 let response = NvCreateChatCompletionStreamResponse {
                     inner,
+                    nvext: None,
                 };
                 yield Annotated{ id: Some(id.to_string()), data: Some(response), event: None, comment: None };
                 id += 1;
@@ -196,6 +288,7 @@ impl
             let inner = deltas.create_choice(0, None, Some(async_openai::types::FinishReason::Stop), None);
             let response = NvCreateChatCompletionStreamResponse {
                 inner,
+                nvext: None,
             };
             yield Annotated { id: Some(id.to_string()), data: Some(response), event: None, comment: None };
         };
@@ -213,6 +306,7 @@ impl AsyncEngine<SingleIn<CompletionRequest>, ManyOut<Annotated<CompletionRespon
         incoming_request: SingleIn<CompletionRequest>,
     ) -> Result<ManyOut<Annotated<CompletionResponse>>, Error> {
         let (request, context) = incoming_request.transfer(());
+        reject_unsupported("logit_bias", request.inner.logit_bias.is_some())?;
         let deltas = request.response_generator();
         let ctx = context.context();
         let chars_string = prompt_to_string(&request.inner.prompt);
@@ -233,6 +327,65 @@ impl AsyncEngine<SingleIn<CompletionRequest>, ManyOut<Annotated<CompletionRespon
     }
 }
 
+#[async_trait]
+impl
+    AsyncEngine<
+        SingleIn<NvCreateEmbeddingRequest>,
+        ManyOut<Annotated<NvCreateEmbeddingResponse>>,
+        Error,
+    > for EchoEngineFull
+{
+    async fn generate(
+        &self,
+        incoming_request: SingleIn<NvCreateEmbeddingRequest>,
+    ) -> Result<ManyOut<Annotated<NvCreateEmbeddingResponse>>, Error> {
+        use async_openai::types::{EmbeddingInput, EncodingFormat};
+
+        let (request, context) = incoming_request.transfer(());
+        reject_unsupported(
+            "base64 embeddings",
+            matches!(request.inner.encoding_format, Some(EncodingFormat::Base64)),
+        )?;
+        let dimensions = match request.inner.dimensions {
+            Some(0) => {
+                return Err(RequestError::invalid_request("dimensions must be at least 1").into())
+            }
+            Some(n) => n as usize,
+            None => ECHO_EMBEDDING_DIMENSIONS,
+        };
+        // Like the other echo engines, a byte of text counts as a token
+        let inputs: Vec<Vec<u32>> = match request.inner.input {
+            EmbeddingInput::String(s) => vec![s.bytes().map(u32::from).collect()],
+            EmbeddingInput::StringArray(v) => v
+                .iter()
+                .map(|s| s.bytes().map(u32::from).collect())
+                .collect(),
+            EmbeddingInput::IntegerArray(tokens) => vec![tokens],
+            EmbeddingInput::ArrayOfIntegerArray(v) => v,
+        };
+        let tokens: usize = inputs.iter().map(Vec::len).sum();
+        let data: Vec<_> = inputs
+            .into_iter()
+            .enumerate()
+            .map(|(index, input)| {
+                serde_json::json!({
+                    "index": index,
+                    "object": "embedding",
+                    "embedding": echo_embedding(input.into_iter(), dimensions),
+                })
+            })
+            .collect();
+        let response: NvCreateEmbeddingResponse = serde_json::from_value(serde_json::json!({
+            "object": "list",
+            "model": request.inner.model,
+            "data": data,
+            "usage": {"prompt_tokens": tokens, "total_tokens": tokens},
+        }))?;
+        let output = futures::stream::iter(vec![Annotated::from_data(response)]);
+        Ok(ResponseStream::new(Box::pin(output), context.context()))
+    }
+}
+
 #[async_trait]
 impl<E> StreamingEngine for EngineDispatcher<E>
 where
@@ -257,8 +410,16 @@ where
     ) -> Result<ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>, Error> {
         self.inner.generate(req).await
     }
+
+    fn embeddings(&self) -> Option<OpenAIEmbeddingsStreamingEngine> {
+        self.embeddings.clone()
+    }
 }
 
+/// A [`StreamingEngine`] as an [`AsyncEngine`] of each request type. The text of each choice
+/// ends at the first of the request's `stop` strings, whether or not the engine applies them.
+/// Requests for several choices are [fanned out](fan_out) unless the engine
+/// [supports `n`](StreamingEngine::supports_n).
 pub struct StreamingEngineAdapter(Arc<dyn StreamingEngine>);
 
 impl StreamingEngineAdapter {
@@ -275,7 +436,13 @@ impl AsyncEngine<SingleIn<CompletionRequest>, ManyOut<Annotated<CompletionRespon
         &self,
         req: SingleIn<CompletionRequest>,
     ) -> Result<ManyOut<Annotated<CompletionResponse>>, Error> {
-        self.0.handle_completion(req).await
+        if !self.0.supports_n() && (req.choices() > 1 || req.best_of() > 1) {
+            return fan_out::generate(self, req).await;
+        }
+        let stops = stop_strings(&*req)?;
+        let n = req.inner.n.unwrap_or(1).into();
+        let stream = self.0.handle_completion(req).await?;
+        Ok(apply_stops(stream, stops, n))
     }
 }
 
@@ -291,6 +458,149 @@ impl
         &self,
         req: SingleIn<NvCreateChatCompletionRequest>,
     ) -> Result<ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>, Error> {
-        self.0.handle_chat(req).await
+        if !self.0.supports_n() && (req.choices() > 1 || req.best_of() > 1) {
+            return fan_out::generate(self, req).await;
+        }
+        let stops = stop_strings(&*req)?;
+        let n = req.inner.n.unwrap_or(1).into();
+        let stream = self.0.handle_chat(req).await?;
+        Ok(apply_stops(stream, stops, n))
+    }
+}
+
+/// The `stop` strings of a request, none with `ignore_eos` like the pre-processor
+fn stop_strings(req: &impl StopConditionsProvider) -> Result<Vec<String>, Error> {
+    let mut stop_conditions = req.extract_stop_conditions()?;
+    stop_conditions.apply_ignore_eos();
+    Ok(stop_conditions.stop.unwrap_or_default())
+}
+
+/// End the text of each of the `n` choices of a response stream at its first stop string
+fn apply_stops<R>(
+    stream: ManyOut<Annotated<R>>,
+    stops: Vec<String>,
+    n: usize,
+) -> ManyOut<Annotated<R>>
+where
+    R: StopChoices + Send + Sync + 'static,
+{
+    if stops.is_empty() {
+        return stream;
+    }
+    let ctx = stream.context();
+    let mut choices = ChoiceStops::new(stops);
+    let engine_ctx = ctx.clone();
+    let output = stream! {
+        let mut stream = stream;
+        while let Some(mut item) = stream.next().await {
+            if let Some(data) = item.data.as_mut() {
+                if !data.apply_stops(&mut choices) {
+                    continue;
+                }
+            }
+            yield item;
+            if choices.stopped.len() >= n {
+                engine_ctx.stop_generating();
+                break;
+            }
+        }
+    };
+    ResponseStream::new(Box::pin(output), ctx)
+}
+
+/// The stop sequences of each choice of a request
+struct ChoiceStops {
+    stops: Vec<String>,
+    choices: HashMap<u64, StopSequences>,
+    stopped: HashSet<u64>,
+}
+
+enum StopFiltered {
+    Text(String),
+    /// The text before a stop string, which ends the choice
+    Stopped(String),
+    /// The choice already ended at a stop string, the engine has not caught up
+    Ended,
+}
+
+impl ChoiceStops {
+    fn new(stops: Vec<String>) -> Self {
+        ChoiceStops {
+            stops,
+            choices: HashMap::new(),
+            stopped: HashSet::new(),
+        }
+    }
+
+    /// `finished` when the engine ended the choice, which returns the text held back
+    fn filter(&mut self, index: u64, text: &str, finished: bool) -> StopFiltered {
+        if self.stopped.contains(&index) {
+            return StopFiltered::Ended;
+        }
+        let stops = self
+            .choices
+            .entry(index)
+            .or_insert_with(|| StopSequences::new(self.stops.iter().cloned()));
+        match stops.push(text) {
+            Ok(mut text) => {
+                if finished {
+                    text.push_str(&stops.flush());
+                }
+                StopFiltered::Text(text)
+            }
+            Err(found) => {
+                self.stopped.insert(index);
+                StopFiltered::Stopped(found.text)
+            }
+        }
+    }
+}
+
+/// Responses whose choices [`ChoiceStops`] can cut
+trait StopChoices {
+    /// Filter the text of each choice. False if no choice is left to return.
+    fn apply_stops(&mut self, choices: &mut ChoiceStops) -> bool;
+}
+
+impl StopChoices for NvCreateChatCompletionStreamResponse {
+    fn apply_stops(&mut self, stops: &mut ChoiceStops) -> bool {
+        if self.inner.choices.is_empty() {
+            return true;
+        }
+        self.inner.choices.retain_mut(|choice| {
+            let text = choice.delta.content.take().unwrap_or_default();
+            let content =
+                match stops.filter(choice.index.into(), &text, choice.finish_reason.is_some()) {
+                    StopFiltered::Text(text) => text,
+                    StopFiltered::Stopped(text) => {
+                        choice.finish_reason = Some(async_openai::types::FinishReason::Stop);
+                        text
+                    }
+                    StopFiltered::Ended => return false,
+                };
+            choice.delta.content = (!content.is_empty()).then_some(content);
+            true
+        });
+        !self.inner.choices.is_empty()
+    }
+}
+
+impl StopChoices for CompletionResponse {
+    fn apply_stops(&mut self, stops: &mut ChoiceStops) -> bool {
+        if self.choices.is_empty() {
+            return true;
+        }
+        self.choices.retain_mut(|choice| {
+            match stops.filter(choice.index, &choice.text, choice.finish_reason.is_some()) {
+                StopFiltered::Text(text) => choice.text = text,
+                StopFiltered::Stopped(text) => {
+                    choice.text = text;
+                    choice.finish_reason = Some("stop".to_string());
+                }
+                StopFiltered::Ended => return false,
+            }
+            true
+        });
+        !self.choices.is_empty()
     }
 }
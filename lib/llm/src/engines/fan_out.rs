@@ -0,0 +1,660 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Sampling `n` choices across the workers.
+//!
+//! The engines behind a pre-processed model return one choice per request. [`FanOut`] sends a
+//! request for `n > 1` choices as `n` requests for one, which the router is free to spread
+//! over the workers, and merges their streams into a single response: the choices of the
+//! `i`th request get index `i`, every chunk carries the `id` of the first one, and the usage
+//! counts the prompt once and the completion tokens of all choices. The chunks without choices
+//! that only carry the usage, which engines send last with `stream_options.include_usage`, are
+//! held back and sent as one once every choice is done. With a `seed`, the `i`th
+//! request samples with `seed + i` so the choices differ but the response is reproducible.
+//! The default seed of [`DEFAULT_SEED_ENV`](crate::protocols::common::sampling::DEFAULT_SEED_ENV)
+//! is used the same way.
+//!
+//! A completions request with `best_of` greater than `n` samples `best_of` choices and
+//! returns the `n` whose tokens have the highest mean logprob, in that order. Those choices
+//! can only be ranked once they are all done, so they are streamed at the end.
+//!
+//! A choice that fails does not fail the others. Its error is sent as a [`CHOICE_ERROR_EVENT`]
+//! event, and the response ends with an error only if every choice failed.
+
+use std::pin::Pin;
+use std::sync::Arc;
+
+use async_stream::stream;
+use async_trait::async_trait;
+use futures::future::join_all;
+use futures::stream::{select_all, BoxStream};
+use futures::{Stream, StreamExt};
+use serde::Serialize;
+
+use dynamo_runtime::engine::{AsyncEngine, AsyncEngineContextProvider, ResponseStream};
+use dynamo_runtime::pipeline::{Error, ManyOut, SingleIn};
+use dynamo_runtime::protocols::annotated::Annotated;
+
+use crate::protocols::common::sampling::default_seed_from_env;
+use crate::protocols::error::RequestError;
+use crate::protocols::openai::{
+    chat_completions::{NvCreateChatCompletionRequest, NvCreateChatCompletionStreamResponse},
+    completions::{CompletionRequest, CompletionResponse},
+};
+
+/// Event of a choice that failed while the others went on. The comment is a [`ChoiceError`].
+pub const CHOICE_ERROR_EVENT: &str = "choice_error";
+
+/// The comment of a [`CHOICE_ERROR_EVENT`]
+#[derive(Serialize, Debug, Clone)]
+pub struct ChoiceError {
+    pub index: u32,
+    pub error: String,
+}
+
+/// Requests that can ask for several choices
+pub trait FanOutRequest: Clone {
+    /// How many choices the request asks for
+    fn choices(&self) -> u8;
+
+    /// How many choices to sample, to return the `choices` most likely of them
+    fn best_of(&self) -> u8 {
+        self.choices()
+    }
+
+    /// Ask for the logprobs of the sampled tokens, to rank the choices. True if the request
+    /// did not already, so they are not returned.
+    fn add_logprobs(&mut self) -> bool {
+        false
+    }
+
+    /// The request for the choice at `index` alone
+    fn branch(&self, index: u8) -> Self;
+}
+
+/// Streamed responses [`FanOut`] can merge
+pub trait FanOutResponse {
+    fn id(&self) -> &str;
+
+    fn set_id(&mut self, id: String);
+
+    /// Number all the choices of this chunk `index`
+    fn set_choice_index(&mut self, index: u32);
+
+    /// Completion tokens of the usage so far, if the chunk carries it
+    fn completion_tokens(&self) -> Option<u32>;
+
+    /// Replace the completion tokens of the usage, and its total with them
+    fn set_completion_tokens(&mut self, completion_tokens: u32);
+
+    /// Whether the chunk has no choices and only carries the usage
+    fn is_usage_only(&self) -> bool;
+
+    /// Logprobs of the tokens of this chunk
+    fn token_logprobs(&self) -> Vec<f32> {
+        Vec::new()
+    }
+
+    fn clear_logprobs(&mut self) {}
+}
+
+/// Sends a request for `n` choices to `engine` as `n` requests for one, and merges the
+/// responses. Requests for a single choice go through untouched.
+pub struct FanOut<Req, Resp> {
+    engine: Arc<dyn AsyncEngine<SingleIn<Req>, ManyOut<Annotated<Resp>>, Error>>,
+}
+
+impl<Req, Resp> FanOut<Req, Resp> {
+    pub fn new(
+        engine: Arc<dyn AsyncEngine<SingleIn<Req>, ManyOut<Annotated<Resp>>, Error>>,
+    ) -> Self {
+        FanOut { engine }
+    }
+}
+
+#[async_trait]
+impl<Req, Resp> AsyncEngine<SingleIn<Req>, ManyOut<Annotated<Resp>>, Error> for FanOut<Req, Resp>
+where
+    Req: FanOutRequest + Send + Sync + 'static,
+    Resp: FanOutResponse + Send + Sync + 'static,
+{
+    async fn generate(&self, request: SingleIn<Req>) -> Result<ManyOut<Annotated<Resp>>, Error> {
+        generate(self.engine.as_ref(), request).await
+    }
+}
+
+type Branches<Resp> = Vec<BoxStream<'static, (usize, Annotated<Resp>)>>;
+
+type Merged<Resp> = Pin<Box<dyn Stream<Item = Annotated<Resp>> + Send>>;
+
+/// Send `request` to `engine` once per choice it samples, see the [module docs](self).
+/// `engine` sees requests for one choice only, so it can be the caller.
+pub async fn generate<E, Req, Resp>(
+    engine: &E,
+    request: SingleIn<Req>,
+) -> Result<ManyOut<Annotated<Resp>>, Error>
+where
+    E: AsyncEngine<SingleIn<Req>, ManyOut<Annotated<Resp>>, Error> + ?Sized,
+    Req: FanOutRequest + Send + Sync + 'static,
+    Resp: FanOutResponse + Send + Sync + 'static,
+{
+    let n = request.choices();
+    let best_of = request.best_of();
+    if best_of < n {
+        return Err(RequestError::invalid_request(format!(
+            "best_of ({best_of}) must be at least n ({n})"
+        ))
+        .into());
+    }
+    if best_of <= 1 {
+        return engine.generate(request).await;
+    }
+    let ranked = best_of > n;
+    let mut base = (*request).clone();
+    let strip_logprobs = ranked && base.add_logprobs();
+
+    // The branches share the request's controller, stopping the response stops them all
+    let branches = (0..best_of).map(|index| engine.generate(request.fork(base.branch(index))));
+
+    let mut context = None;
+    let mut streams = Vec::new();
+    let mut failed = Vec::new();
+    for (index, result) in join_all(branches).await.into_iter().enumerate() {
+        match result {
+            Ok(stream) => {
+                context.get_or_insert_with(|| stream.context());
+                streams.push(stream.map(move |item| (index, item)).boxed());
+            }
+            Err(err) => failed.push(ChoiceError {
+                index: index as u32,
+                error: format!("{err:#}"),
+            }),
+        }
+    }
+    let Some(context) = context else {
+        let error = failed.swap_remove(0).error;
+        anyhow::bail!(error);
+    };
+    let output = if ranked {
+        best_choices(streams, failed, n.into(), best_of.into(), strip_logprobs)
+    } else {
+        merge_choices(streams, failed, n.into())
+    };
+    Ok(ResponseStream::new(output, context))
+}
+
+/// Stream the chunks of every choice as they come
+fn merge_choices<Resp>(streams: Branches<Resp>, failed: Vec<ChoiceError>, n: usize) -> Merged<Resp>
+where
+    Resp: FanOutResponse + Send + Sync + 'static,
+{
+    Box::pin(stream! {
+        let mut merged = MergedChoices::new(n);
+        for failure in failed {
+            merged.failed[failure.index as usize] = true;
+            yield choice_error(failure);
+        }
+        let mut usage = None;
+        let mut streams = select_all(streams);
+        while let Some((index, mut item)) = streams.next().await {
+            if merged.failed[index] {
+                continue;
+            }
+            if item.is_error() {
+                merged.failed[index] = true;
+                let error = item.comment.take().unwrap_or_default().join(", ");
+                if merged.failed.iter().all(|failed| *failed) {
+                    yield Annotated::from_error(error);
+                    return;
+                }
+                yield choice_error(ChoiceError { index: index as u32, error });
+                continue;
+            }
+            if let Some(data) = item.data.as_mut() {
+                merged.apply(index, data);
+                if data.is_usage_only() {
+                    usage = item.data;
+                    continue;
+                }
+            }
+            yield item;
+        }
+        if let Some(mut data) = usage {
+            data.set_completion_tokens(merged.completion_tokens.iter().sum());
+            yield Annotated::from_data(data);
+        }
+    })
+}
+
+/// Wait for all `best_of` choices, then stream the `n` most likely
+fn best_choices<Resp>(
+    streams: Branches<Resp>,
+    failed: Vec<ChoiceError>,
+    n: usize,
+    best_of: usize,
+    strip_logprobs: bool,
+) -> Merged<Resp>
+where
+    Resp: FanOutResponse + Send + Sync + 'static,
+{
+    Box::pin(stream! {
+        let mut chunks: Vec<Vec<Annotated<Resp>>> = (0..best_of).map(|_| Vec::new()).collect();
+        let mut logprobs = vec![(0.0f64, 0usize); best_of];
+        let mut completion_tokens = vec![0u32; best_of];
+        let mut errors: Vec<Option<String>> = vec![None; best_of];
+        for failure in failed {
+            errors[failure.index as usize] = Some(failure.error);
+        }
+        let mut usage = None;
+        let mut streams = select_all(streams);
+        while let Some((index, mut item)) = streams.next().await {
+            if errors[index].is_some() {
+                continue;
+            }
+            if item.is_error() {
+                errors[index] = Some(item.comment.take().unwrap_or_default().join(", "));
+                continue;
+            }
+            if let Some(data) = item.data.as_ref() {
+                let (sum, count) = &mut logprobs[index];
+                for logprob in data.token_logprobs() {
+                    *sum += f64::from(logprob);
+                    *count += 1;
+                }
+                if let Some(tokens) = data.completion_tokens() {
+                    completion_tokens[index] = tokens;
+                }
+                if data.is_usage_only() {
+                    usage = item.data;
+                    continue;
+                }
+            }
+            chunks[index].push(item);
+        }
+
+        let mean = |index: usize| {
+            let (sum, count) = logprobs[index];
+            if count == 0 { f64::NEG_INFINITY } else { sum / count as f64 }
+        };
+        let mut ranked: Vec<usize> = (0..best_of).filter(|index| errors[*index].is_none()).collect();
+        ranked.sort_by(|a, b| mean(*b).total_cmp(&mean(*a)));
+        ranked.truncate(n);
+        let mut errors = errors.into_iter().flatten();
+        if ranked.is_empty() {
+            yield Annotated::from_error(errors.next().unwrap_or_default());
+            return;
+        }
+
+        let total = completion_tokens.iter().sum();
+        let mut id = None;
+        for (choice, branch) in ranked.iter().enumerate() {
+            for mut item in std::mem::take(&mut chunks[*branch]) {
+                if let Some(data) = item.data.as_mut() {
+                    data.set_choice_index(choice as u32);
+                    match &id {
+                        Some(id) => data.set_id(id.clone()),
+                        None => id = Some(data.id().to_string()),
+                    }
+                    data.set_completion_tokens(total);
+                    if strip_logprobs {
+                        data.clear_logprobs();
+                    }
+                }
+                yield item;
+            }
+        }
+        for index in ranked.len()..n {
+            let error = errors.next().unwrap_or_default();
+            yield choice_error(ChoiceError { index: index as u32, error });
+        }
+        if let Some(mut data) = usage {
+            if let Some(id) = id {
+                data.set_id(id);
+            }
+            data.set_completion_tokens(total);
+            yield Annotated::from_data(data);
+        }
+    })
+}
+
+fn choice_error<R>(error: ChoiceError) -> Annotated<R> {
+    let index = error.index;
+    Annotated::from_annotation(CHOICE_ERROR_EVENT, &error).unwrap_or_else(|err| {
+        tracing::warn!(%err, index, "Failed serializing a choice error");
+        Annotated::from_error(format!("choice {index} failed"))
+    })
+}
+
+/// What the chunks of the merged response have in common
+struct MergedChoices {
+    id: Option<String>,
+    completion_tokens: Vec<u32>,
+    failed: Vec<bool>,
+}
+
+impl MergedChoices {
+    fn new(n: usize) -> Self {
+        MergedChoices {
+            id: None,
+            completion_tokens: vec![0; n],
+            failed: vec![false; n],
+        }
+    }
+
+    fn apply(&mut self, index: usize, data: &mut impl FanOutResponse) {
+        data.set_choice_index(index as u32);
+        match &self.id {
+            Some(id) => data.set_id(id.clone()),
+            None => self.id = Some(data.id().to_string()),
+        }
+        if let Some(completion_tokens) = data.completion_tokens() {
+            self.completion_tokens[index] = completion_tokens;
+            data.set_completion_tokens(self.completion_tokens.iter().sum());
+        }
+    }
+}
+
+/// The seed of the choice at `index`. Without a seed in the request, the default one would
+/// sample every choice the same, so the choices get it the same way.
+fn branch_seed(seed: Option<i64>, index: u8) -> Option<i64> {
+    seed.or_else(|| default_seed_from_env().ok().flatten())
+        .map(|seed| seed.wrapping_add(index.into()))
+}
+
+impl FanOutRequest for NvCreateChatCompletionRequest {
+    fn choices(&self) -> u8 {
+        self.inner.n.unwrap_or(1)
+    }
+
+    fn branch(&self, index: u8) -> Self {
+        let mut request = self.clone();
+        request.inner.n = None;
+        request.inner.seed = branch_seed(self.inner.seed, index);
+        request
+    }
+}
+
+impl FanOutRequest for CompletionRequest {
+    fn choices(&self) -> u8 {
+        self.inner.n.unwrap_or(1)
+    }
+
+    fn best_of(&self) -> u8 {
+        self.inner.best_of.unwrap_or_else(|| self.choices())
+    }
+
+    fn add_logprobs(&mut self) -> bool {
+        let added = self.inner.logprobs.is_none();
+        self.inner.logprobs.get_or_insert(0);
+        added
+    }
+
+    fn branch(&self, index: u8) -> Self {
+        let mut request = self.clone();
+        request.inner.n = None;
+        request.inner.best_of = None;
+        request.inner.seed = branch_seed(self.inner.seed, index);
+        request
+    }
+}
+
+impl FanOutResponse for NvCreateChatCompletionStreamResponse {
+    fn id(&self) -> &str {
+        &self.inner.id
+    }
+
+    fn set_id(&mut self, id: String) {
+        self.inner.id = id;
+    }
+
+    fn set_choice_index(&mut self, index: u32) {
+        for choice in self.inner.choices.iter_mut() {
+            choice.index = index;
+        }
+    }
+
+    fn completion_tokens(&self) -> Option<u32> {
+        self.inner
+            .usage
+            .as_ref()
+            .map(|usage| usage.completion_tokens)
+    }
+
+    fn set_completion_tokens(&mut self, completion_tokens: u32) {
+        if let Some(usage) = self.inner.usage.as_mut() {
+            usage.completion_tokens = completion_tokens;
+            usage.total_tokens = usage.prompt_tokens + completion_tokens;
+        }
+    }
+
+    fn is_usage_only(&self) -> bool {
+        self.inner.choices.is_empty() && self.inner.usage.is_some()
+    }
+}
+
+impl FanOutResponse for CompletionResponse {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn set_id(&mut self, id: String) {
+        self.id = id;
+    }
+
+    fn set_choice_index(&mut self, index: u32) {
+        for choice in self.choices.iter_mut() {
+            choice.index = index.into();
+        }
+    }
+
+    fn completion_tokens(&self) -> Option<u32> {
+        self.usage
+            .as_ref()
+            .map(|usage| usage.completion_tokens.max(0) as u32)
+    }
+
+    fn set_completion_tokens(&mut self, completion_tokens: u32) {
+        if let Some(usage) = self.usage.as_mut() {
+            usage.completion_tokens = completion_tokens.min(i32::MAX as u32) as i32;
+            usage.total_tokens = usage.prompt_tokens.saturating_add(usage.completion_tokens);
+        }
+    }
+
+    fn is_usage_only(&self) -> bool {
+        self.choices.is_empty() && self.usage.is_some()
+    }
+
+    fn token_logprobs(&self) -> Vec<f32> {
+        self.choices
+            .iter()
+            .filter_map(|choice| choice.logprobs.as_ref())
+            .flat_map(|logprobs| logprobs.token_logprobs.iter().copied())
+            .collect()
+    }
+
+    fn clear_logprobs(&mut self) {
+        for choice in self.choices.iter_mut() {
+            choice.logprobs = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::openai::{
+        completions::{CompletionChoice, LogprobResult},
+        CompletionUsage,
+    };
+    use dynamo_runtime::pipeline::Context;
+
+    /// Answers with the seed as text, in two chunks, and fails the seed 8. The higher the
+    /// seed, the more likely the tokens. Ends with a chunk of the usage alone if the request
+    /// asks for it.
+    struct SeedEngine;
+
+    #[async_trait]
+    impl AsyncEngine<SingleIn<CompletionRequest>, ManyOut<Annotated<CompletionResponse>>, Error>
+        for SeedEngine
+    {
+        async fn generate(
+            &self,
+            request: SingleIn<CompletionRequest>,
+        ) -> Result<ManyOut<Annotated<CompletionResponse>>, Error> {
+            let (request, context) = request.into_parts();
+            assert_eq!(request.inner.n, None);
+            let seed = request.inner.seed.unwrap();
+            let include_usage = request
+                .inner
+                .stream_options
+                .as_ref()
+                .is_some_and(|options| options.include_usage);
+            let logprobs = request.inner.logprobs.map(|_| LogprobResult {
+                tokens: vec![seed.to_string()],
+                token_logprobs: vec![seed as f32 - 10.0],
+                top_logprobs: vec![],
+                text_offset: vec![0],
+            });
+            let chunk = move |completion_tokens| CompletionResponse {
+                id: format!("cmpl-{seed}"),
+                object: "text_completion".to_string(),
+                created: 0,
+                model: "llama".to_string(),
+                system_fingerprint: None,
+                choices: vec![CompletionChoice {
+                    text: seed.to_string(),
+                    index: 0,
+                    finish_reason: None,
+                    logprobs: logprobs.clone(),
+                }],
+                usage: Some(CompletionUsage {
+                    prompt_tokens: 3,
+                    completion_tokens,
+                    total_tokens: 3 + completion_tokens,
+                    ..Default::default()
+                }),
+                nvext: None,
+            };
+            let output = stream! {
+                yield Annotated::from_data(chunk(1));
+                if seed == 8 {
+                    yield Annotated::from_error("out of memory".to_string());
+                } else {
+                    yield Annotated::from_data(chunk(2));
+                    if include_usage {
+                        let mut usage = chunk(2);
+                        usage.choices.clear();
+                        yield Annotated::from_data(usage);
+                    }
+                }
+            };
+            Ok(ResponseStream::new(Box::pin(output), context.context()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fan_out() {
+        let engine = FanOut::new(Arc::new(SeedEngine));
+        let request: CompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "llama",
+            "prompt": "Hello",
+            "n": 3,
+            "seed": 7,
+        }))
+        .unwrap();
+        let responses: Vec<_> = engine
+            .generate(Context::new(request))
+            .await
+            .unwrap()
+            .collect()
+            .await;
+
+        let errors: Vec<_> = responses.iter().filter(|r| r.is_event()).collect();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].event.as_deref(), Some(CHOICE_ERROR_EVENT));
+        assert!(!responses.iter().any(|r| r.is_error()));
+
+        let chunks: Vec<_> = responses.iter().filter_map(|r| r.data.as_ref()).collect();
+        assert_eq!(chunks.len(), 5);
+        assert!(chunks.iter().all(|chunk| chunk.id == chunks[0].id));
+        for chunk in &chunks {
+            let choice = &chunk.choices[0];
+            assert_eq!(choice.text, (7 + choice.index).to_string());
+        }
+        // The prompt once, and the 2 + 1 + 2 tokens of the choices
+        let usage = chunks.last().unwrap().usage.as_ref().unwrap();
+        assert_eq!((usage.completion_tokens, usage.total_tokens), (5, 8));
+
+        let request: CompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "llama",
+            "prompt": "Hello",
+            "best_of": 3,
+            "seed": 7,
+        }))
+        .unwrap();
+        let responses: Vec<_> = engine
+            .generate(Context::new(request))
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        let chunks: Vec<_> = responses.iter().filter_map(|r| r.data.as_ref()).collect();
+        assert_eq!(chunks.len(), 2);
+        for chunk in &chunks {
+            let choice = &chunk.choices[0];
+            assert_eq!((choice.text.as_str(), choice.index), ("9", 0));
+            assert!(choice.logprobs.is_none());
+            assert_eq!(chunk.usage.as_ref().unwrap().completion_tokens, 5);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fan_out_usage_chunk() {
+        let engine = FanOut::new(Arc::new(SeedEngine));
+        for extra in [
+            serde_json::json!({"n": 3}),
+            serde_json::json!({"best_of": 3}),
+        ] {
+            let mut request = serde_json::json!({
+                "model": "llama",
+                "prompt": "Hello",
+                "seed": 7,
+                "stream": true,
+                "stream_options": {"include_usage": true},
+            });
+            request
+                .as_object_mut()
+                .unwrap()
+                .extend(extra.as_object().unwrap().clone());
+            let request: CompletionRequest = serde_json::from_value(request).unwrap();
+            let responses: Vec<_> = engine
+                .generate(Context::new(request))
+                .await
+                .unwrap()
+                .collect()
+                .await;
+            let chunks: Vec<_> = responses.iter().filter_map(|r| r.data.as_ref()).collect();
+
+            // One usage chunk for the whole response, last, with the tokens of every choice
+            let usage_only: Vec<_> = chunks.iter().filter(|c| c.choices.is_empty()).collect();
+            assert_eq!(usage_only.len(), 1, "{extra}");
+            let last = chunks.last().unwrap();
+            assert!(last.choices.is_empty());
+            assert_eq!(last.id, chunks[0].id);
+            let usage = last.usage.as_ref().unwrap();
+            assert_eq!((usage.completion_tokens, usage.total_tokens), (5, 8));
+        }
+    }
+}
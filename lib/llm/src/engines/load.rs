@@ -0,0 +1,218 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! How an engine loads a model, in terms every engine understands. Each engine translates
+//! these into its own options: mistral.rs ISQ types and candle devices, llama.cpp GPU layers
+//! and split mode, vllm and sglang engine arguments. An engine that can't do what is asked
+//! fails to start rather than loading the model some other way.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// Weights quantized as the model loads. Models already quantized, GGUF or AWQ checkpoints,
+/// are loaded as they are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quantization {
+    /// 8 bit floats
+    Fp8,
+    /// 8 bit integers
+    Int8,
+    /// 4 bit integers
+    Int4,
+}
+
+impl FromStr for Quantization {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fp8" => Ok(Quantization::Fp8),
+            "int8" => Ok(Quantization::Int8),
+            "int4" => Ok(Quantization::Int4),
+            _ => anyhow::bail!("Unknown quantization '{s}', expected fp8, int8 or int4"),
+        }
+    }
+}
+
+impl fmt::Display for Quantization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Quantization::Fp8 => "fp8",
+            Quantization::Int8 => "int8",
+            Quantization::Int4 => "int4",
+        };
+        write!(f, "{s}")
+    }
+}
+
+/// Type of the weights and activations that aren't quantized
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DType {
+    /// What the model's config says, or the engine's choice for the device
+    Auto,
+    Bf16,
+    F16,
+    F32,
+}
+
+impl DType {
+    /// The name torch, and so vllm and sglang, give it
+    pub fn torch_name(&self) -> &'static str {
+        match self {
+            DType::Auto => "auto",
+            DType::Bf16 => "bfloat16",
+            DType::F16 => "float16",
+            DType::F32 => "float32",
+        }
+    }
+}
+
+impl FromStr for DType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(DType::Auto),
+            "bf16" | "bfloat16" => Ok(DType::Bf16),
+            "f16" | "fp16" | "float16" => Ok(DType::F16),
+            "f32" | "fp32" | "float32" => Ok(DType::F32),
+            _ => anyhow::bail!("Unknown dtype '{s}', expected auto, bf16, f16 or f32"),
+        }
+    }
+}
+
+impl fmt::Display for DType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            DType::Auto => "auto",
+            DType::Bf16 => "bf16",
+            DType::F16 => "f16",
+            DType::F32 => "f32",
+        };
+        write!(f, "{s}")
+    }
+}
+
+/// Where the model runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Device {
+    Cpu,
+    /// The CUDA GPU of this index, the first of them with tensor parallelism
+    Cuda(u32),
+    /// Apple silicon GPU
+    Metal,
+}
+
+impl FromStr for Device {
+    type Err = anyhow::Error;
+
+    /// `cpu`, `cuda`, `cuda:<index>` or `metal`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("cuda", index)) => index
+                .parse()
+                .map(Device::Cuda)
+                .map_err(|_| anyhow::anyhow!("CUDA device '{index}' is not an index")),
+            Some(_) => anyhow::bail!("Unknown device '{s}', expected cpu, cuda:<index> or metal"),
+            None => match s {
+                "cpu" => Ok(Device::Cpu),
+                "cuda" | "gpu" => Ok(Device::Cuda(0)),
+                "metal" => Ok(Device::Metal),
+                _ => anyhow::bail!("Unknown device '{s}', expected cpu, cuda:<index> or metal"),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Device::Cpu => write!(f, "cpu"),
+            Device::Cuda(index) => write!(f, "cuda:{index}"),
+            Device::Metal => write!(f, "metal"),
+        }
+    }
+}
+
+/// Quantization, precision and placement of a model, None for the engine's default
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadOptions {
+    pub quantization: Option<Quantization>,
+    pub dtype: Option<DType>,
+    /// GPUs the model is split over
+    pub tensor_parallel_size: u32,
+    /// Share of each GPU's memory the engine may take, weights and KV cache, 0 to 1
+    pub gpu_memory_fraction: Option<f32>,
+    pub device: Option<Device>,
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        LoadOptions {
+            quantization: None,
+            dtype: None,
+            tensor_parallel_size: 1,
+            gpu_memory_fraction: None,
+            device: None,
+        }
+    }
+}
+
+impl LoadOptions {
+    /// The first of `flags` set in these options, as its command line flag, for engines to
+    /// say which ones they don't support
+    pub fn first_set(&self, flags: &[&'static str]) -> Option<&'static str> {
+        flags.iter().copied().find(|flag| match *flag {
+            "--quantization" => self.quantization.is_some(),
+            "--dtype" => self.dtype.is_some_and(|dtype| dtype != DType::Auto),
+            "--tensor-parallel-size" => self.tensor_parallel_size > 1,
+            "--gpu-memory-fraction" => self.gpu_memory_fraction.is_some(),
+            "--device" => self.device.is_some(),
+            _ => false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("int4".parse::<Quantization>().unwrap(), Quantization::Int4);
+        assert!("q4k".parse::<Quantization>().is_err());
+        assert_eq!("bfloat16".parse::<DType>().unwrap(), DType::Bf16);
+        assert_eq!("fp16".parse::<DType>().unwrap().torch_name(), "float16");
+        assert_eq!("cuda".parse::<Device>().unwrap(), Device::Cuda(0));
+        assert_eq!("cuda:3".parse::<Device>().unwrap(), Device::Cuda(3));
+        assert_eq!("cuda:3".parse::<Device>().unwrap().to_string(), "cuda:3");
+        assert!("cuda:x".parse::<Device>().is_err());
+        assert!("tpu".parse::<Device>().is_err());
+    }
+
+    #[test]
+    fn test_first_set() {
+        let options = LoadOptions {
+            dtype: Some(DType::Auto),
+            gpu_memory_fraction: Some(0.8),
+            ..Default::default()
+        };
+        assert_eq!(
+            options.first_set(&["--dtype", "--tensor-parallel-size", "--gpu-memory-fraction"]),
+            Some("--gpu-memory-fraction")
+        );
+        assert_eq!(LoadOptions::default().first_set(&["--device"]), None);
+    }
+}
@@ -0,0 +1,268 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A synthetic engine for load testing, without a model or a GPU
+//!
+//! It answers every pre-processed request with lorem ipsum, at the pace of a [`MockProfile`]:
+//! the time to the first token, the tokens per second after it, how much each delay varies
+//! and how often a request fails partway through. The tokens are real tokens of the model's
+//! tokenizer, so the pre-processor, the router, detokenizing and the HTTP service all do
+//! their usual work.
+
+use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use async_stream::stream;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use dynamo_runtime::engine::{AsyncEngine, AsyncEngineContextProvider, ResponseStream};
+use dynamo_runtime::pipeline::{Error, ManyOut, SingleIn};
+use dynamo_runtime::protocols::annotated::Annotated;
+
+use super::delta_core;
+use crate::backend::ExecutionContext;
+use crate::preprocessor::BackendInput;
+use crate::protocols::common::llm_backend::LLMEngineOutput;
+use crate::protocols::TokenIdType;
+use crate::tokenizers::traits::Tokenizer;
+
+/// What the mock engine answers with, over and over
+const LOREM_IPSUM: &str = "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do \
+    eiusmod tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam, quis \
+    nostrud exercitation ullamco laboris nisi ut aliquip ex ea commodo consequat. Duis aute \
+    irure dolor in reprehenderit in voluptate velit esse cillum dolore eu fugiat nulla \
+    pariatur. Excepteur sint occaecat cupidatat non proident, sunt in culpa qui officia \
+    deserunt mollit anim id est laborum. ";
+
+/// How fast the mock engine generates and how often it fails
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MockProfile {
+    /// Tokens per second after the first one
+    pub tokens_per_sec: f64,
+
+    /// Time to the first token, in milliseconds
+    pub ttft_ms: f64,
+
+    /// How much each delay varies, as a fraction of it: 0.2 makes it up to 20% shorter or
+    /// longer
+    pub jitter: f64,
+
+    /// Fraction of the requests that fail partway through, from 0 to 1
+    pub failure_rate: f64,
+
+    /// Tokens to generate for a request without `max_tokens`
+    pub max_tokens: u32,
+}
+
+impl Default for MockProfile {
+    fn default() -> Self {
+        MockProfile {
+            tokens_per_sec: 100.0,
+            ttft_ms: 50.0,
+            jitter: 0.0,
+            failure_rate: 0.0,
+            max_tokens: 128,
+        }
+    }
+}
+
+impl MockProfile {
+    fn validate(&self) -> Result<()> {
+        if self.tokens_per_sec.is_nan() || self.tokens_per_sec <= 0.0 {
+            anyhow::bail!("tps must be more than 0, got {}", self.tokens_per_sec);
+        }
+        if self.ttft_ms.is_nan() || self.ttft_ms < 0.0 {
+            anyhow::bail!("ttft_ms must not be negative, got {}", self.ttft_ms);
+        }
+        if !(0.0..=1.0).contains(&self.jitter) {
+            anyhow::bail!("jitter must be between 0 and 1, got {}", self.jitter);
+        }
+        if !(0.0..=1.0).contains(&self.failure_rate) {
+            anyhow::bail!(
+                "failure_rate must be between 0 and 1, got {}",
+                self.failure_rate
+            );
+        }
+        Ok(())
+    }
+
+    /// `millis` give or take the jitter
+    fn delay(&self, millis: f64) -> Duration {
+        let jitter = self.jitter * (2.0 * rand::random::<f64>() - 1.0);
+        Duration::from_secs_f64((millis * (1.0 + jitter)).max(0.0) / 1000.0)
+    }
+
+    /// Where a request fails, if it does: before one of its `n` tokens, or before it ends
+    fn failure_point(&self, n: usize) -> Option<usize> {
+        (rand::random::<f64>() < self.failure_rate)
+            .then(|| ((rand::random::<f64>() * (n + 1) as f64) as usize).min(n))
+    }
+}
+
+impl FromStr for MockProfile {
+    type Err = anyhow::Error;
+
+    /// Comma separated `key=value`, for example `tps=50,ttft_ms=300,jitter=0.2`. Keys left
+    /// out keep their default.
+    fn from_str(s: &str) -> Result<Self> {
+        let mut profile = MockProfile::default();
+        for pair in s.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let Some((key, value)) = pair.split_once('=') else {
+                anyhow::bail!("'{pair}' is not key=value");
+            };
+            let value = value.trim();
+            let number = || -> Result<f64> {
+                value
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("{key} '{value}' is not a number"))
+            };
+            match key.trim() {
+                "tps" => profile.tokens_per_sec = number()?,
+                "ttft_ms" => profile.ttft_ms = number()?,
+                "jitter" => profile.jitter = number()?,
+                "failure_rate" => profile.failure_rate = number()?,
+                "max_tokens" => {
+                    profile.max_tokens = value
+                        .parse()
+                        .map_err(|_| anyhow::anyhow!("max_tokens '{value}' is not a count"))?
+                }
+                other => anyhow::bail!(
+                    "Unknown mock profile key '{other}', expected tps, ttft_ms, jitter, \
+                     failure_rate or max_tokens"
+                ),
+            }
+        }
+        profile.validate()?;
+        Ok(profile)
+    }
+}
+
+impl Display for MockProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "tps={},ttft_ms={},jitter={},failure_rate={},max_tokens={}",
+            self.tokens_per_sec, self.ttft_ms, self.jitter, self.failure_rate, self.max_tokens
+        )
+    }
+}
+
+struct MockEngine {
+    profile: MockProfile,
+    /// Lorem ipsum in the model's tokens
+    token_ids: Arc<Vec<TokenIdType>>,
+}
+
+/// The mock engine, generating the tokens `tokenizer` has for lorem ipsum
+pub fn make_engine_mock(
+    profile: MockProfile,
+    tokenizer: &dyn Tokenizer,
+) -> Result<ExecutionContext> {
+    profile.validate()?;
+    let token_ids = tokenizer.encode(LOREM_IPSUM)?.token_ids;
+    if token_ids.is_empty() {
+        anyhow::bail!("The tokenizer has no tokens for the mock engine's text");
+    }
+    Ok(Arc::new(MockEngine {
+        profile,
+        token_ids: Arc::new(token_ids),
+    }))
+}
+
+#[async_trait]
+impl AsyncEngine<SingleIn<BackendInput>, ManyOut<Annotated<LLMEngineOutput>>, Error>
+    for MockEngine
+{
+    async fn generate(
+        &self,
+        incoming_request: SingleIn<BackendInput>,
+    ) -> Result<ManyOut<Annotated<LLMEngineOutput>>, Error> {
+        let (request, context) = incoming_request.into_parts();
+        let ctx = context.context();
+        let stop = ctx.clone();
+
+        let profile = self.profile;
+        let token_ids = self.token_ids.clone();
+        let requested = request.stop_conditions.max_tokens;
+        let n = requested.unwrap_or(profile.max_tokens) as usize;
+        let fail_at = profile.failure_point(n);
+        let token_delay_ms = 1000.0 / profile.tokens_per_sec;
+
+        let output = stream! {
+            tokio::time::sleep(profile.delay(profile.ttft_ms)).await;
+            for (i, token_id) in token_ids.iter().cycle().take(n).enumerate() {
+                if i > 0 {
+                    tokio::time::sleep(profile.delay(token_delay_ms)).await;
+                }
+                if stop.is_stopped() {
+                    yield Annotated::from_data(LLMEngineOutput::cancelled());
+                    return;
+                }
+                if fail_at == Some(i) {
+                    yield Annotated::from_data(LLMEngineOutput::error(
+                        "Mock engine failure".to_string(),
+                    ));
+                    return;
+                }
+                yield delta_core(*token_id);
+            }
+            if fail_at == Some(n) {
+                yield Annotated::from_data(LLMEngineOutput::error(
+                    "Mock engine failure".to_string(),
+                ));
+            } else if requested.is_some() {
+                yield Annotated::from_data(LLMEngineOutput::length());
+            } else {
+                yield Annotated::from_data(LLMEngineOutput::stop());
+            }
+        };
+        Ok(ResponseStream::new(Box::pin(output), ctx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile() {
+        let profile: MockProfile = "tps=50, ttft_ms=300,jitter=0.2".parse().unwrap();
+        assert_eq!(profile.tokens_per_sec, 50.0);
+        assert_eq!(profile.ttft_ms, 300.0);
+        assert_eq!(profile.max_tokens, MockProfile::default().max_tokens);
+        assert_eq!(profile.to_string().parse::<MockProfile>().unwrap(), profile);
+        assert_eq!("".parse::<MockProfile>().unwrap(), MockProfile::default());
+
+        assert!("tps=0".parse::<MockProfile>().is_err());
+        assert!("jitter=2".parse::<MockProfile>().is_err());
+        assert!("failure_rate=abc".parse::<MockProfile>().is_err());
+        assert!("speed=10".parse::<MockProfile>().is_err());
+
+        for _ in 0..100 {
+            let delay = profile.delay(100.0);
+            assert!(delay >= Duration::from_millis(80) && delay <= Duration::from_millis(120));
+        }
+        let always = MockProfile {
+            failure_rate: 1.0,
+            ..Default::default()
+        };
+        assert!(always.failure_point(10).is_some_and(|i| i <= 10));
+        assert_eq!(MockProfile::default().failure_point(10), None);
+    }
+}
@@ -0,0 +1,245 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Record an engine's responses and serve them back, for tests and demos that must not depend
+//! on a GPU or on sampling.
+//!
+//! With [`RECORD_ENV`] set, the backend of each model appends a line to a JSONL trace for every
+//! request the engine finishes: a hash of the prompt's tokens and every output of the engine,
+//! once stop conditions are applied. [`make_engine_replay`] loads such a trace and answers a
+//! request with the outputs recorded for its prompt, the last ones if it was recorded more
+//! than once. The prompt is hashed after the chat template, so replay needs the tokenizer and
+//! template the trace was recorded with.
+
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context as _, Result};
+use async_stream::stream;
+use async_trait::async_trait;
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use dynamo_runtime::engine::{AsyncEngine, AsyncEngineContextProvider, ResponseStream};
+use dynamo_runtime::pipeline::{Error, ManyOut, SingleIn};
+use dynamo_runtime::protocols::annotated::Annotated;
+
+use crate::backend::ExecutionContext;
+use crate::preprocessor::BackendInput;
+use crate::protocols::common::llm_backend::LLMEngineOutput;
+use crate::protocols::common::FinishReason;
+use crate::protocols::TokenIdType;
+
+/// A line of a trace
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TraceEntry {
+    /// [`prompt_hash`] of the request's tokens
+    pub prompt_hash: String,
+
+    /// What the engine sent back, in order
+    pub outputs: Vec<LLMEngineOutput>,
+}
+
+/// BLAKE3 of the prompt's token ids, hex
+pub fn prompt_hash(token_ids: &[TokenIdType]) -> String {
+    let mut hasher = blake3::Hasher::new();
+    for token_id in token_ids {
+        hasher.update(&token_id.to_le_bytes());
+    }
+    hasher.finalize().to_hex().to_string()
+}
+
+/// Path of the JSONL file to record the responses of the engines to
+pub const RECORD_ENV: &str = "DYN_RECORD";
+
+/// Appends what the engine answered to a trace. The backend of each model records through
+/// one, when [`RECORD_ENV`] is set.
+pub struct TraceRecorder {
+    file: Mutex<std::fs::File>,
+}
+
+impl TraceRecorder {
+    pub fn open(path: &Path) -> Result<Arc<Self>> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Trace {}", path.display()))?;
+        Ok(Arc::new(TraceRecorder {
+            file: Mutex::new(file),
+        }))
+    }
+
+    /// A recorder for the file of [`RECORD_ENV`], None if it is not set
+    pub fn from_env() -> Result<Option<Arc<Self>>> {
+        match std::env::var_os(RECORD_ENV) {
+            Some(path) if !path.is_empty() => Ok(Some(Self::open(Path::new(&path))?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Pass the outputs for the prompt `token_ids` through, and record them once the engine
+    /// finished. Requests that fail, or that the client stops, are not recorded.
+    pub fn record(
+        self: &Arc<Self>,
+        token_ids: &[TokenIdType],
+        stream: impl Stream<Item = Annotated<LLMEngineOutput>> + Send + 'static,
+    ) -> impl Stream<Item = Annotated<LLMEngineOutput>> + Send + 'static {
+        let recorder = self.clone();
+        let prompt_hash = prompt_hash(token_ids);
+        stream! {
+            let mut stream = std::pin::pin!(stream);
+            let mut outputs = Vec::new();
+            while let Some(response) = stream.next().await {
+                if let Some(data) = &response.data {
+                    outputs.push(data.clone());
+                }
+                yield response;
+            }
+            let finished = outputs.last().is_some_and(|output| {
+                matches!(
+                    output.finish_reason,
+                    Some(FinishReason::EoS | FinishReason::Stop | FinishReason::Length)
+                )
+            });
+            if finished {
+                recorder.write(&TraceEntry { prompt_hash, outputs });
+            }
+        }
+    }
+
+    fn write(&self, entry: &TraceEntry) {
+        let line = match serde_json::to_string(entry) {
+            Ok(line) => line + "\n",
+            Err(err) => {
+                tracing::warn!(%err, "Failed to serialize a trace entry");
+                return;
+            }
+        };
+        // In one write, so that the lines of several models don't mix
+        if let Err(err) = self.file.lock().unwrap().write_all(line.as_bytes()) {
+            tracing::warn!(%err, "Failed to write to the trace");
+        }
+    }
+}
+
+struct ReplayEngine {
+    /// By prompt hash
+    recorded: HashMap<String, Arc<Vec<LLMEngineOutput>>>,
+}
+
+/// An engine answering with the outputs of the trace at `path`
+pub fn make_engine_replay(path: &Path) -> Result<ExecutionContext> {
+    let file = std::fs::File::open(path).with_context(|| format!("Trace {}", path.display()))?;
+    let mut recorded = HashMap::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.with_context(|| format!("Trace {}", path.display()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: TraceEntry = serde_json::from_str(&line)
+            .with_context(|| format!("Trace {} line {}", path.display(), number + 1))?;
+        recorded.insert(entry.prompt_hash, Arc::new(entry.outputs));
+    }
+    tracing::info!(
+        "Replaying {} prompts from {}",
+        recorded.len(),
+        path.display()
+    );
+    Ok(Arc::new(ReplayEngine { recorded }))
+}
+
+#[async_trait]
+impl AsyncEngine<SingleIn<BackendInput>, ManyOut<Annotated<LLMEngineOutput>>, Error>
+    for ReplayEngine
+{
+    async fn generate(
+        &self,
+        incoming_request: SingleIn<BackendInput>,
+    ) -> Result<ManyOut<Annotated<LLMEngineOutput>>, Error> {
+        let (request, context) = incoming_request.into_parts();
+        let prompt_hash = prompt_hash(&request.token_ids);
+        let Some(outputs) = self.recorded.get(&prompt_hash).cloned() else {
+            anyhow::bail!("The trace has no response for this prompt (hash {prompt_hash})");
+        };
+        let output = stream! {
+            for output in outputs.iter() {
+                yield Annotated::from_data(output.clone());
+            }
+        };
+        Ok(ResponseStream::new(Box::pin(output), context.context()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::common::preprocessor::PreprocessedRequest;
+    use crate::protocols::common::StopConditions;
+    use dynamo_runtime::pipeline::Context;
+
+    fn request(token_ids: Vec<TokenIdType>) -> SingleIn<BackendInput> {
+        Context::new(
+            PreprocessedRequest::builder()
+                .token_ids(token_ids)
+                .stop_conditions(StopConditions::default())
+                .sampling_options(Default::default())
+                .build()
+                .unwrap(),
+        )
+    }
+
+    fn output(token_id: TokenIdType) -> LLMEngineOutput {
+        LLMEngineOutput {
+            token_ids: vec![token_id],
+            tokens: None,
+            text: Some(format!("<{token_id}>")),
+            cum_log_probs: None,
+            log_probs: None,
+            top_log_probs: None,
+            finish_reason: None,
+            spec_decode: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_record_and_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trace.jsonl");
+        let recorder = TraceRecorder::open(&path).unwrap();
+
+        let outputs = vec![output(7), output(8), LLMEngineOutput::length()];
+        let stream = futures::stream::iter(outputs.clone()).map(Annotated::from_data);
+        let passed: Vec<_> = recorder.record(&[1, 2, 3], stream).collect().await;
+        assert_eq!(passed.len(), 3);
+        // Not finished, not recorded
+        let stream = futures::stream::iter(vec![Annotated::from_data(output(9))]);
+        let _: Vec<_> = recorder.record(&[4], stream).collect().await;
+
+        let replay = make_engine_replay(&path).unwrap();
+        let replayed: Vec<_> = replay
+            .generate(request(vec![1, 2, 3]))
+            .await
+            .unwrap()
+            .filter_map(|response| async move { response.data })
+            .collect()
+            .await;
+        assert_eq!(replayed, outputs);
+        assert!(replay.generate(request(vec![4])).await.is_err());
+    }
+}
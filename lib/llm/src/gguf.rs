@@ -27,6 +27,7 @@
 
 mod content;
 mod gguf_metadata;
+mod gguf_settings;
 mod gguf_tokenizer;
 use strum::EnumString;
 
@@ -34,6 +35,7 @@ use anyhow::{Context, Result};
 pub(crate) use content::Content;
 pub(crate) use gguf_metadata::ContentConfig;
 pub use gguf_metadata::ModelConfigLike;
+pub use gguf_settings::{GgufSettings, RopeScaling};
 pub(crate) use gguf_tokenizer::convert_gguf_to_hf_tokenizer;
 
 use std::str::FromStr;
@@ -0,0 +1,225 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! How a GGUF file says its model should be run: context length, RoPE and tokenizer
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use candle_core::quantized::gguf_file::Value;
+
+/// The settings in a GGUF file's metadata that engines and the pre-processor need
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GgufSettings {
+    /// `general.architecture`, e.g. `llama`. It prefixes the model's own keys.
+    pub architecture: String,
+
+    /// `<arch>.context_length`, how many tokens the model was trained on
+    pub context_length: Option<u32>,
+
+    /// `<arch>.rope.scaling.type` and `<arch>.rope.scaling.factor`
+    pub rope_scaling: Option<RopeScaling>,
+
+    /// `<arch>.rope.freq_base`
+    pub rope_freq_base: Option<f32>,
+
+    /// `<arch>.rope.scaling.original_context_length`, the context length before scaling
+    pub rope_original_context_length: Option<u32>,
+
+    /// `tokenizer.ggml.model`, e.g. `gpt2` or `llama`
+    pub tokenizer_model: Option<String>,
+
+    /// Whether `tokenizer.chat_template` is set
+    pub has_chat_template: bool,
+}
+
+impl GgufSettings {
+    pub fn from_file(gguf_file: &Path) -> anyhow::Result<Self> {
+        let content = crate::model_card::model::load_gguf(gguf_file)?;
+        Ok(Self::from_metadata(content.get_metadata()))
+    }
+
+    pub fn from_metadata(metadata: &HashMap<String, Value>) -> Self {
+        let text = |key: &str| Some(metadata.get(key)?.to_string().ok()?.clone());
+        let number = |key: &str| {
+            let value = metadata.get(key)?.to_u64().ok()?;
+            u32::try_from(value).ok()
+        };
+        let float = |key: &str| metadata.get(key)?.to_f32().ok();
+
+        let architecture = text("general.architecture").unwrap_or_default();
+        let arch = &architecture;
+        let rope_scaling = match text(&format!("{arch}.rope.scaling.type")) {
+            Some(kind) => {
+                let factor = float(&format!("{arch}.rope.scaling.factor")).unwrap_or(1.0);
+                RopeScaling::new(&kind, factor).ok()
+            }
+            None => None,
+        };
+        GgufSettings {
+            context_length: number(&format!("{arch}.context_length")),
+            rope_scaling,
+            rope_freq_base: float(&format!("{arch}.rope.freq_base")),
+            rope_original_context_length: number(&format!(
+                "{arch}.rope.scaling.original_context_length"
+            )),
+            tokenizer_model: text("tokenizer.ggml.model"),
+            has_chat_template: metadata.contains_key("tokenizer.chat_template"),
+            architecture,
+        }
+    }
+}
+
+impl fmt::Display for GgufSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.architecture)?;
+        if let Some(context_length) = self.context_length {
+            write!(f, ", context length {context_length}")?;
+        }
+        if let Some(rope_scaling) = self.rope_scaling {
+            write!(f, ", RoPE scaling {rope_scaling}")?;
+        }
+        if let Some(freq_base) = self.rope_freq_base {
+            write!(f, ", RoPE base {freq_base}")?;
+        }
+        if let Some(tokenizer) = &self.tokenizer_model {
+            write!(f, ", {tokenizer} tokenizer")?;
+        }
+        if !self.has_chat_template {
+            write!(f, ", no chat template")?;
+        }
+        Ok(())
+    }
+}
+
+/// How RoPE stretches positions past the context the model was trained on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RopeScaling {
+    None,
+    /// Positions divided by the factor
+    Linear(f32),
+    /// YaRN, see <https://arxiv.org/abs/2309.00071>
+    Yarn(f32),
+}
+
+impl RopeScaling {
+    fn new(kind: &str, factor: f32) -> anyhow::Result<Self> {
+        if !(factor.is_finite() && factor > 0.0) {
+            anyhow::bail!("RoPE scaling factor must be positive, got {factor}");
+        }
+        match kind {
+            "none" => Ok(RopeScaling::None),
+            "linear" => Ok(RopeScaling::Linear(factor)),
+            "yarn" => Ok(RopeScaling::Yarn(factor)),
+            _ => anyhow::bail!("Unknown RoPE scaling '{kind}', expected none, linear or yarn"),
+        }
+    }
+
+    /// How much longer the context gets, 1 for no scaling
+    pub fn factor(&self) -> f32 {
+        match self {
+            RopeScaling::None => 1.0,
+            RopeScaling::Linear(factor) | RopeScaling::Yarn(factor) => *factor,
+        }
+    }
+}
+
+impl FromStr for RopeScaling {
+    type Err = anyhow::Error;
+
+    /// `none`, `linear:<factor>` or `yarn:<factor>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some((kind, factor)) => {
+                let factor = factor.parse().map_err(|_| {
+                    anyhow::anyhow!("RoPE scaling factor '{factor}' is not a number")
+                })?;
+                RopeScaling::new(kind, factor)
+            }
+            None if s == "none" => Ok(RopeScaling::None),
+            None => anyhow::bail!("RoPE scaling '{s}' needs a factor, e.g. {s}:4"),
+        }
+    }
+}
+
+impl fmt::Display for RopeScaling {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RopeScaling::None => write!(f, "none"),
+            RopeScaling::Linear(factor) => write!(f, "linear:{factor}"),
+            RopeScaling::Yarn(factor) => write!(f, "yarn:{factor}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_metadata() {
+        let metadata: HashMap<String, Value> = [
+            ("general.architecture", Value::String("llama".to_string())),
+            ("llama.context_length", Value::U32(131072)),
+            ("llama.rope.freq_base", Value::F32(500000.0)),
+            ("llama.rope.scaling.type", Value::String("yarn".to_string())),
+            ("llama.rope.scaling.factor", Value::F32(4.0)),
+            (
+                "llama.rope.scaling.original_context_length",
+                Value::U64(32768),
+            ),
+            ("tokenizer.ggml.model", Value::String("gpt2".to_string())),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect();
+
+        let settings = GgufSettings::from_metadata(&metadata);
+        assert_eq!(
+            settings,
+            GgufSettings {
+                architecture: "llama".to_string(),
+                context_length: Some(131072),
+                rope_scaling: Some(RopeScaling::Yarn(4.0)),
+                rope_freq_base: Some(500000.0),
+                rope_original_context_length: Some(32768),
+                tokenizer_model: Some("gpt2".to_string()),
+                has_chat_template: false,
+            }
+        );
+        assert_eq!(
+            settings.to_string(),
+            "llama, context length 131072, RoPE scaling yarn:4, RoPE base 500000, gpt2 tokenizer, no chat template"
+        );
+    }
+
+    #[test]
+    fn test_rope_scaling() {
+        assert_eq!("none".parse::<RopeScaling>().unwrap(), RopeScaling::None);
+        assert_eq!(
+            "linear:2".parse::<RopeScaling>().unwrap(),
+            RopeScaling::Linear(2.0)
+        );
+        assert_eq!(
+            "yarn:4.5".parse::<RopeScaling>().unwrap(),
+            RopeScaling::Yarn(4.5)
+        );
+        assert!("yarn".parse::<RopeScaling>().is_err());
+        assert!("yarn:0".parse::<RopeScaling>().is_err());
+        assert!("ntk:2".parse::<RopeScaling>().is_err());
+    }
+}
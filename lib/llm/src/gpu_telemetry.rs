@@ -0,0 +1,292 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! GPU telemetry of a worker.
+//!
+//! [`start`] samples the utilization and memory of the GPUs the worker uses through NVML, and
+//! the KV cache occupancy its engine reports with [`report_kv_cache_usage`]. Samples go to the
+//! process wide metrics and, summed up as a [`GpuLoad`], to the returned channel, which
+//! workers pass on to their endpoint so that routers see it with the registration.
+//!
+//! [`capabilities`] reads the model and memory of those GPUs once, for the worker to advertise
+//! so that routers weight it against workers with other GPUs.
+//!
+//! NVML needs the `nvml` feature, without it [`start`] and [`capabilities`] fail.
+
+// Only the NVML collector samples GPUs
+#![cfg_attr(not(feature = "nvml"), allow(dead_code))]
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use dynamo_runtime::component::{GpuLoad, WorkerCapabilities};
+use dynamo_runtime::metrics::register;
+use dynamo_runtime::CancellationToken;
+use prometheus::{Gauge, GaugeVec, IntGaugeVec, Opts};
+use tokio::sync::watch;
+
+/// Bits of the last KV cache usage the engine reported, [`NO_KV_USAGE`] until it does
+static KV_CACHE_USAGE: AtomicU32 = AtomicU32::new(NO_KV_USAGE);
+const NO_KV_USAGE: u32 = u32::MAX;
+
+static GPU_UTILIZATION: LazyLock<GaugeVec> = LazyLock::new(|| {
+    register(
+        GaugeVec::new(
+            Opts::new(
+                "dynamo_gpu_utilization_ratio",
+                "Fraction of the last sample period the GPU was busy",
+            ),
+            &["gpu"],
+        )
+        .unwrap(),
+    )
+});
+
+static GPU_MEMORY_USED: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register(
+        IntGaugeVec::new(
+            Opts::new("dynamo_gpu_memory_used_bytes", "GPU memory in use"),
+            &["gpu"],
+        )
+        .unwrap(),
+    )
+});
+
+static GPU_MEMORY_TOTAL: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register(
+        IntGaugeVec::new(
+            Opts::new("dynamo_gpu_memory_total_bytes", "GPU memory"),
+            &["gpu"],
+        )
+        .unwrap(),
+    )
+});
+
+static KV_CACHE_USAGE_GAUGE: LazyLock<Gauge> = LazyLock::new(|| {
+    register(
+        Gauge::new(
+            "dynamo_kv_cache_usage_ratio",
+            "Fraction of the engine's KV cache in use",
+        )
+        .unwrap(),
+    )
+});
+
+/// For engines: `used` of the `total` slots of the KV cache, blocks or tokens, hold a sequence
+pub fn report_kv_cache_usage(used: usize, total: usize) {
+    if total == 0 {
+        return;
+    }
+    let usage = (used as f32 / total as f32).clamp(0.0, 1.0);
+    KV_CACHE_USAGE.store(usage.to_bits(), Ordering::Relaxed);
+    KV_CACHE_USAGE_GAUGE.set(usage as f64);
+}
+
+fn kv_cache_usage() -> Option<f32> {
+    match KV_CACHE_USAGE.load(Ordering::Relaxed) {
+        NO_KV_USAGE => None,
+        bits => Some(f32::from_bits(bits)),
+    }
+}
+
+/// One GPU at one point in time
+#[derive(Debug, Clone, PartialEq)]
+struct GpuSample {
+    index: u32,
+    /// From 0 to 1
+    utilization: f32,
+    memory_used: u64,
+    memory_total: u64,
+}
+
+/// Export `samples` as metrics and sum them up for routers
+fn record(samples: &[GpuSample], kv_cache_usage: Option<f32>) -> GpuLoad {
+    for sample in samples {
+        let gpu = sample.index.to_string();
+        GPU_UTILIZATION
+            .with_label_values(&[&gpu])
+            .set(sample.utilization as f64);
+        GPU_MEMORY_USED
+            .with_label_values(&[&gpu])
+            .set(sample.memory_used as i64);
+        GPU_MEMORY_TOTAL
+            .with_label_values(&[&gpu])
+            .set(sample.memory_total as i64);
+    }
+    summarize(samples, kv_cache_usage)
+}
+
+fn summarize(samples: &[GpuSample], kv_cache_usage: Option<f32>) -> GpuLoad {
+    let utilization = if samples.is_empty() {
+        0.0
+    } else {
+        samples.iter().map(|s| s.utilization).sum::<f32>() / samples.len() as f32
+    };
+    let used: u64 = samples.iter().map(|s| s.memory_used).sum();
+    let total: u64 = samples.iter().map(|s| s.memory_total).sum();
+    let memory_used = if total == 0 {
+        0.0
+    } else {
+        (used as f64 / total as f64) as f32
+    };
+    GpuLoad {
+        utilization,
+        memory_used,
+        kv_cache_usage,
+    }
+}
+
+/// The GPUs this process may use, from `CUDA_VISIBLE_DEVICES`, all of them if it is unset.
+/// Devices given by UUID are not supported and count as all.
+fn visible_devices(count: u32) -> Vec<u32> {
+    let all = || (0..count).collect();
+    let Ok(visible) = std::env::var("CUDA_VISIBLE_DEVICES") else {
+        return all();
+    };
+    let parsed: Result<Vec<u32>, _> = visible
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::parse)
+        .collect();
+    match parsed {
+        Ok(devices) => devices.into_iter().filter(|&i| i < count).collect(),
+        Err(_) => all(),
+    }
+}
+
+/// Sample the GPUs every `interval` until `cancel_token` is cancelled. The channel has the
+/// latest [`GpuLoad`], None before the first sample.
+#[cfg(feature = "nvml")]
+pub fn start(
+    interval: Duration,
+    cancel_token: CancellationToken,
+) -> anyhow::Result<watch::Receiver<Option<GpuLoad>>> {
+    use nvml_wrapper::Nvml;
+
+    let nvml = Nvml::init().map_err(|err| anyhow::anyhow!("NVML: {err}"))?;
+    let devices = visible_devices(nvml.device_count()?);
+    if devices.is_empty() {
+        anyhow::bail!("NVML found no GPU to sample");
+    }
+    tracing::info!(?devices, "Sampling GPUs every {}s", interval.as_secs_f32());
+
+    let sample = move || -> anyhow::Result<Vec<GpuSample>> {
+        devices
+            .iter()
+            .map(|&index| {
+                let device = nvml.device_by_index(index)?;
+                let utilization = device.utilization_rates()?;
+                let memory = device.memory_info()?;
+                Ok(GpuSample {
+                    index,
+                    utilization: utilization.gpu as f32 / 100.0,
+                    memory_used: memory.used,
+                    memory_total: memory.total,
+                })
+            })
+            .collect()
+    };
+
+    let (tx, rx) = watch::channel(None);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = cancel_token.cancelled() => break,
+            }
+            match sample() {
+                Ok(samples) => {
+                    let load = record(&samples, kv_cache_usage());
+                    if tx.send(Some(load)).is_err() {
+                        break;
+                    }
+                }
+                Err(err) => tracing::warn!(%err, "Failed sampling the GPUs"),
+            }
+        }
+    });
+    Ok(rx)
+}
+
+/// The model, count and total memory of the GPUs the worker uses. The model is the first
+/// GPU's, workers are expected to have one kind.
+#[cfg(feature = "nvml")]
+pub fn capabilities() -> anyhow::Result<WorkerCapabilities> {
+    use nvml_wrapper::Nvml;
+
+    let nvml = Nvml::init().map_err(|err| anyhow::anyhow!("NVML: {err}"))?;
+    let devices = visible_devices(nvml.device_count()?);
+    let mut capabilities = WorkerCapabilities {
+        gpu_count: Some(devices.len() as u32),
+        ..Default::default()
+    };
+    for index in devices {
+        let device = nvml.device_by_index(index)?;
+        if capabilities.gpu_model.is_none() {
+            capabilities.gpu_model = Some(device.name()?);
+        }
+        *capabilities.vram_bytes.get_or_insert(0) += device.memory_info()?.total;
+    }
+    Ok(capabilities)
+}
+
+#[cfg(not(feature = "nvml"))]
+pub fn capabilities() -> anyhow::Result<WorkerCapabilities> {
+    anyhow::bail!("Detecting the GPUs needs NVML, build with the nvml feature")
+}
+
+#[cfg(not(feature = "nvml"))]
+pub fn start(
+    _interval: Duration,
+    _cancel_token: CancellationToken,
+) -> anyhow::Result<watch::Receiver<Option<GpuLoad>>> {
+    anyhow::bail!("GPU telemetry needs NVML, build with the nvml feature")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summarize() {
+        let gib = 1 << 30;
+        let samples = [
+            GpuSample {
+                index: 0,
+                utilization: 0.9,
+                memory_used: 60 * gib,
+                memory_total: 80 * gib,
+            },
+            GpuSample {
+                index: 1,
+                utilization: 0.5,
+                memory_used: 20 * gib,
+                memory_total: 80 * gib,
+            },
+        ];
+        let load = summarize(&samples, Some(0.25));
+        assert!((load.utilization - 0.7).abs() < 1e-6);
+        assert!((load.memory_used - 0.5).abs() < 1e-6);
+        assert_eq!(load.kv_cache_usage, Some(0.25));
+        assert_eq!(load.pressure(), 0.25);
+
+        let load = summarize(&[], None);
+        assert_eq!(load, GpuLoad::default());
+    }
+}
@@ -0,0 +1,414 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Grammars for guided decoding
+//!
+//! Engines that constrain sampling with a grammar (llama.cpp) need the JSON schema from a
+//! `response_format` request turned into GBNF. This covers the parts of JSON schema that
+//! structured output requests use in practice: objects with `properties` and `required`,
+//! arrays, the scalar types, `enum`, `const`, `anyOf` / `oneOf` and local `$ref`s.
+//! Anything else is an error, rather than a grammar that silently allows invalid output.
+
+use std::collections::HashMap;
+
+use anyhow::Context as _;
+use serde_json::Value;
+
+const PRIMITIVES: &str = r#"ws ::= ([ \t\n] ws)?
+string ::= "\"" char* "\""
+char ::= [^"\\\x7F\x00-\x1F] | "\\" (["\\/bfnrt] | "u" [0-9a-fA-F] [0-9a-fA-F] [0-9a-fA-F] [0-9a-fA-F])
+integer ::= "-"? ([0-9] | [1-9] [0-9]*)
+number ::= integer ("." [0-9]+)? ([eE] [-+]? [0-9]+)?
+boolean ::= "true" | "false"
+null ::= "null"
+value ::= object | array | string | number | boolean | null
+object ::= "{" ws (string ws ":" ws value ws ("," ws string ws ":" ws value ws)*)? "}"
+array ::= "[" ws (value ws ("," ws value ws)*)? "]"
+"#;
+
+/// Convert a JSON schema to a GBNF grammar whose root rule is `root`.
+pub fn json_schema_to_gbnf(schema: &Value) -> anyhow::Result<String> {
+    let mut converter = Converter {
+        root: schema,
+        rules: Vec::new(),
+        refs: HashMap::new(),
+    };
+    let body = converter.visit(schema, "root")?;
+    // Not through `add_rule`, which keeps the name for this
+    converter.rules.push(("root".to_string(), body));
+
+    let mut grammar = String::new();
+    for (name, body) in &converter.rules {
+        grammar.push_str(&format!("{name} ::= {body}\n"));
+    }
+    grammar.push_str(PRIMITIVES);
+    Ok(grammar)
+}
+
+struct Converter<'a> {
+    root: &'a Value,
+    rules: Vec<(String, String)>,
+    // rule name of each `$ref` we have started converting
+    refs: HashMap<String, String>,
+}
+
+impl<'a> Converter<'a> {
+    /// Add a rule, returning its name. The name is made unique if needed, also against `root`
+    /// and the rules of [`PRIMITIVES`].
+    fn add_rule(&mut self, name: &str, body: String) -> String {
+        let mut unique = name.to_string();
+        let mut i = 1;
+        while is_reserved(&unique) || self.rules.iter().any(|(n, _)| *n == unique) {
+            unique = format!("{name}{i}");
+            i += 1;
+        }
+        self.rules.push((unique.clone(), body));
+        unique
+    }
+
+    /// Returns the grammar expression matching `schema`, adding any rules it needs.
+    fn visit(&mut self, schema: &'a Value, name: &str) -> anyhow::Result<String> {
+        let obj = match schema {
+            Value::Bool(true) => return Ok("value".to_string()),
+            Value::Bool(false) => anyhow::bail!("Schema '{name}' matches nothing"),
+            Value::Object(obj) => obj,
+            _ => anyhow::bail!("Schema '{name}' is not an object"),
+        };
+
+        if let Some(reference) = obj.get("$ref").and_then(Value::as_str) {
+            return self.visit_ref(reference);
+        }
+        if let Some(value) = obj.get("const") {
+            return Ok(literal(&value.to_string()));
+        }
+        if let Some(values) = obj.get("enum") {
+            let values = values.as_array().context("'enum' must be an array")?;
+            let alts: Vec<_> = values.iter().map(|v| literal(&v.to_string())).collect();
+            return Ok(format!("({})", alts.join(" | ")));
+        }
+        for key in ["anyOf", "oneOf"] {
+            if let Some(schemas) = obj.get(key) {
+                let schemas = schemas
+                    .as_array()
+                    .with_context(|| format!("'{key}' must be an array"))?;
+                return self.alternatives(schemas.iter(), name);
+            }
+        }
+        if let Some(schemas) = obj.get("allOf") {
+            match schemas.as_array().map(Vec::as_slice) {
+                Some([schema]) => return self.visit(schema, name),
+                _ => anyhow::bail!("'allOf' with more than one schema is not supported"),
+            }
+        }
+        for key in ["pattern", "prefixItems", "patternProperties", "not", "if"] {
+            if obj.contains_key(key) {
+                anyhow::bail!("JSON schema keyword '{key}' is not supported");
+            }
+        }
+
+        match obj.get("type") {
+            Some(Value::String(ty)) => self.visit_type(ty, obj, name),
+            Some(Value::Array(types)) => {
+                let mut alts = Vec::with_capacity(types.len());
+                for ty in types {
+                    let ty = ty
+                        .as_str()
+                        .context("'type' must be a string or array of strings")?;
+                    alts.push(self.visit_type(ty, obj, name)?);
+                }
+                Ok(format!("({})", alts.join(" | ")))
+            }
+            Some(_) => anyhow::bail!("'type' must be a string or array of strings"),
+            None if obj.contains_key("properties") => self.visit_type("object", obj, name),
+            None if obj.contains_key("items") => self.visit_type("array", obj, name),
+            None => Ok("value".to_string()),
+        }
+    }
+
+    fn alternatives(
+        &mut self,
+        schemas: impl Iterator<Item = &'a Value>,
+        name: &str,
+    ) -> anyhow::Result<String> {
+        let mut alts = Vec::new();
+        for (i, schema) in schemas.enumerate() {
+            alts.push(self.visit(schema, &format!("{name}-{i}"))?);
+        }
+        if alts.is_empty() {
+            anyhow::bail!("Schema '{name}' has no alternatives");
+        }
+        Ok(format!("({})", alts.join(" | ")))
+    }
+
+    fn visit_ref(&mut self, reference: &str) -> anyhow::Result<String> {
+        if let Some(rule) = self.refs.get(reference) {
+            return Ok(rule.clone());
+        }
+        let Some(pointer) = reference.strip_prefix('#') else {
+            anyhow::bail!("Only local '$ref's are supported, got '{reference}'");
+        };
+        let root = self.root;
+        let target = root
+            .pointer(pointer)
+            .with_context(|| format!("'$ref' {reference} not found"))?;
+
+        // Reserve the rule name first so recursive schemas refer to it
+        let name = match pointer.rsplit('/').next() {
+            Some(last) if !last.is_empty() => rule_name(last),
+            _ => "ref".to_string(),
+        };
+        let rule = self.add_rule(&name, String::new());
+        self.refs.insert(reference.to_string(), rule.clone());
+
+        let body = self.visit(target, &rule)?;
+        if let Some(entry) = self.rules.iter_mut().find(|(n, _)| *n == rule) {
+            entry.1 = body;
+        }
+        Ok(rule)
+    }
+
+    fn visit_type(
+        &mut self,
+        ty: &str,
+        obj: &'a serde_json::Map<String, Value>,
+        name: &str,
+    ) -> anyhow::Result<String> {
+        match ty {
+            "string" | "number" | "integer" | "boolean" | "null" => Ok(ty.to_string()),
+            "array" => {
+                let item = match obj.get("items") {
+                    Some(items) => {
+                        let body = self.visit(items, &format!("{name}-item"))?;
+                        self.add_rule(&format!("{name}-item"), body)
+                    }
+                    None => "value".to_string(),
+                };
+                let min_items = obj.get("minItems").and_then(Value::as_u64).unwrap_or(0);
+                let items = format!("{item} ws (\",\" ws {item} ws)*");
+                if min_items > 0 {
+                    Ok(format!("\"[\" ws {items} \"]\""))
+                } else {
+                    Ok(format!("\"[\" ws ({items})? \"]\""))
+                }
+            }
+            "object" => self.visit_object(obj, name),
+            other => anyhow::bail!("Unknown JSON schema type '{other}'"),
+        }
+    }
+
+    fn visit_object(
+        &mut self,
+        obj: &'a serde_json::Map<String, Value>,
+        name: &str,
+    ) -> anyhow::Result<String> {
+        let properties = match obj.get("properties") {
+            Some(Value::Object(properties)) if !properties.is_empty() => properties,
+            Some(Value::Object(_)) | None => {
+                return match obj.get("additionalProperties") {
+                    Some(Value::Bool(false)) => Ok("\"{\" ws \"}\"".to_string()),
+                    Some(schema @ Value::Object(_)) => {
+                        let body = self.visit(schema, &format!("{name}-additional"))?;
+                        let value = self.add_rule(&format!("{name}-additional"), body);
+                        let kv = format!("string ws \":\" ws {value} ws");
+                        Ok(format!("\"{{\" ws ({kv} (\",\" ws {kv})*)? \"}}\""))
+                    }
+                    _ => Ok("object".to_string()),
+                };
+            }
+            Some(_) => anyhow::bail!("'properties' must be an object"),
+        };
+
+        let required: Vec<&str> = match obj.get("required") {
+            Some(Value::Array(required)) => required.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+
+        // Required properties first, then optional ones, each in declaration order
+        let mut required_kvs = Vec::new();
+        let mut optional_kvs = Vec::new();
+        for (key, schema) in properties {
+            let rule = format!("{name}-{}", rule_name(key));
+            let body = self.visit(schema, &rule)?;
+            let value = self.add_rule(&rule, body);
+            let kv = format!(
+                "{} ws \":\" ws {value} ws",
+                literal(&Value::from(key.as_str()).to_string())
+            );
+            if required.contains(&key.as_str()) {
+                required_kvs.push(kv);
+            } else {
+                optional_kvs.push(kv);
+            }
+        }
+        for key in &required {
+            if !properties.contains_key(*key) {
+                anyhow::bail!("Required property '{key}' is not in 'properties'");
+            }
+        }
+
+        let mut body = String::from("\"{\" ws ");
+        if required_kvs.is_empty() {
+            // Any subset of the optional properties, in order: the first one present has
+            // no leading comma.
+            let mut rest: Option<String> = None;
+            for (i, kv) in optional_kvs.iter().enumerate().rev() {
+                let tail: String = optional_kvs[i + 1..]
+                    .iter()
+                    .map(|kv| format!(" (\",\" ws {kv})?"))
+                    .collect();
+                let alt = format!("{kv}{tail}");
+                let rule_body = match rest {
+                    Some(rest) => format!("{alt} | {rest}"),
+                    None => alt,
+                };
+                rest = Some(self.add_rule(&format!("{name}-rest{i}"), rule_body));
+            }
+            if let Some(rest) = rest {
+                body.push_str(&format!("({rest})? "));
+            }
+        } else {
+            body.push_str(&required_kvs.join(" \",\" ws "));
+            for kv in &optional_kvs {
+                body.push_str(&format!(" (\",\" ws {kv})?"));
+            }
+            body.push(' ');
+        }
+        body.push_str("\"}\"");
+        Ok(body)
+    }
+}
+
+/// `root`, and the names of the rules of [`PRIMITIVES`]
+fn is_reserved(name: &str) -> bool {
+    name == "root"
+        || PRIMITIVES.lines().any(|line| {
+            line.split_once(" ::= ")
+                .is_some_and(|(rule, _)| rule == name)
+        })
+}
+
+/// GBNF string literal matching exactly `s`
+fn literal(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\x{:02X}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// GBNF rule names are letters, digits and dashes
+fn rule_name(s: &str) -> String {
+    s.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_object() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "name": {"type": "string"},
+                "age": {"type": "integer"},
+                "tags": {"type": "array", "items": {"enum": ["a", "b"]}}
+            },
+            "required": ["name"]
+        });
+        let grammar = json_schema_to_gbnf(&schema).unwrap();
+        assert!(grammar.contains("root-name ::= string\n"));
+        assert!(grammar.contains(r#"root-tags-item ::= ("\"a\"" | "\"b\"")"#));
+        assert!(grammar.contains(r#"root ::= "{" ws "\"name\"" ws ":" ws root-name ws (","#));
+        assert!(grammar.contains("\nobject ::= "));
+    }
+
+    #[test]
+    fn test_optional_only() {
+        let schema = json!({
+            "properties": {"a": {"type": "number"}, "b": {"type": "boolean"}}
+        });
+        let grammar = json_schema_to_gbnf(&schema).unwrap();
+        assert!(grammar.contains("root-rest1 ::= \"\\\"b\\\"\" ws \":\" ws root-b ws\n"));
+        assert!(grammar.contains(
+            "root-rest0 ::= \"\\\"a\\\"\" ws \":\" ws root-a ws (\",\" ws \"\\\"b\\\"\" ws \":\" ws root-b ws)? | root-rest1\n"
+        ));
+    }
+
+    #[test]
+    fn test_ref() {
+        let schema = json!({
+            "$defs": {"node": {"type": "object", "properties": {
+                "children": {"type": "array", "items": {"$ref": "#/$defs/node"}}
+            }}},
+            "$ref": "#/$defs/node"
+        });
+        let grammar = json_schema_to_gbnf(&schema).unwrap();
+        assert!(grammar.contains("node-children-item ::= node\n"));
+        assert!(grammar.contains("root ::= node\n"));
+    }
+
+    #[test]
+    fn test_ref_reserved_name() {
+        let schema = json!({
+            "$defs": {
+                "object": {"type": "object", "properties": {"a": {"type": "string"}}},
+                "root": {"type": "integer"},
+            },
+            "properties": {
+                "o": {"$ref": "#/$defs/object"},
+                "r": {"$ref": "#/$defs/root"},
+            },
+            "required": ["o", "r"]
+        });
+        let grammar = json_schema_to_gbnf(&schema).unwrap();
+        assert!(grammar.contains("root-o ::= object1\n"));
+        assert!(grammar.contains("object1 ::= \"{\" ws"));
+        assert!(grammar.contains("root-r ::= root1\n"));
+        assert!(grammar.contains("root1 ::= integer\n"));
+        // The primitives and the root are each defined once
+        for rule in ["object", "root", "value"] {
+            let definitions = grammar
+                .lines()
+                .filter(|line| line.starts_with(&format!("{rule} ::= ")))
+                .count();
+            assert_eq!(definitions, 1, "{rule} in {grammar}");
+        }
+    }
+
+    #[test]
+    fn test_unsupported() {
+        assert!(json_schema_to_gbnf(&json!({"type": "string", "pattern": "^a+$"})).is_err());
+        assert!(json_schema_to_gbnf(&json!({"type": "date"})).is_err());
+        assert!(json_schema_to_gbnf(&json!({"$ref": "https://example.com/schema"})).is_err());
+        assert!(json_schema_to_gbnf(&json!({
+            "properties": {"a": {}}, "required": ["b"]
+        }))
+        .is_err());
+    }
+}
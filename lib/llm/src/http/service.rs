@@ -30,12 +30,37 @@
 //!
 //! The [`service_v2::HttpService`] can be further extended to host any [`axum::Router`] using the [`service_v2::HttpServiceConfigBuilder`].
 
+mod admin;
+mod auth;
+mod batches;
+mod drain;
+mod health;
+mod lora;
 mod openai;
-
+mod tenancy;
+mod timings;
+mod trace;
+mod usage;
+
+pub mod access_log;
+pub mod admission;
+pub mod coalesce;
+pub mod conversations;
+pub mod cors;
 pub mod discovery;
 pub mod error;
+pub mod forwarded;
+pub mod idempotency;
+pub mod limits;
 pub mod metrics;
+pub mod model_admin;
+pub mod moderation;
+pub mod prefetch;
+pub mod rate_limit;
+pub mod response_cache;
 pub mod service_v2;
+pub mod shedding;
+pub mod tls;
 
 // #[cfg(feature = "py3")]
 // pub mod py3;
@@ -45,14 +70,32 @@ pub use axum;
 pub use error::ServiceHttpError;
 pub use metrics::Metrics;
 
+use admin::ModelWorkers;
+use admission::{AdmissionConfig, AdmissionQueue};
+use coalesce::StreamCoalescing;
+use limits::RequestLimits;
+use moderation::ContentFilter;
+use shedding::{LoadShedder, SloConfig};
+
+use crate::lora::{LoraAdapter, LoraAdapters, LoraEngine, LoraError};
+use crate::model_alias::ModelAliases;
+use crate::preprocessor::OpenAIPreprocessor;
 use crate::types::openai::{
     chat_completions::OpenAIChatCompletionsStreamingEngine,
-    completions::OpenAICompletionsStreamingEngine,
+    completions::OpenAICompletionsStreamingEngine, embeddings::OpenAIEmbeddingsStreamingEngine,
+    transcriptions::OpenAITranscriptionsStreamingEngine,
 };
+use dynamo_runtime::component::Client;
+use dynamo_runtime::pipeline::network::egress::sticky::StickySessions;
+use dynamo_runtime::pipeline::AsyncEngineContext;
+use dynamo_runtime::protocols::Endpoint;
 use std::{
     collections::HashMap,
-    sync::{Arc, Mutex},
-    time::Duration,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 #[derive(Clone)]
@@ -68,7 +111,24 @@ impl Default for ModelManager {
 
 impl ModelManager {
     pub fn new() -> Self {
-        let state = Arc::new(DeploymentState::new());
+        Self::with_overload_control(None, None)
+    }
+
+    /// Shed load from models missing their latency SLOs
+    pub fn with_load_shedding(slo: SloConfig) -> Self {
+        Self::with_overload_control(Some(slo), None)
+    }
+
+    /// Shed load from models missing their latency `slo`, and hold requests past the
+    /// `admission` limits of their model in a bounded queue
+    pub fn with_overload_control(
+        slo: Option<SloConfig>,
+        admission: Option<AdmissionConfig>,
+    ) -> Self {
+        let state = Arc::new(DeploymentState::new(
+            slo.map(|slo| Arc::new(LoadShedder::new(slo))),
+            admission.map(|admission| Arc::new(AdmissionQueue::new(admission))),
+        ));
         Self { state }
     }
 
@@ -77,17 +137,7 @@ impl ModelManager {
     }
 
     pub fn has_model_any(&self, model: &str) -> bool {
-        self.state
-            .chat_completion_engines
-            .lock()
-            .unwrap()
-            .contains(model)
-            || self
-                .state
-                .completion_engines
-                .lock()
-                .unwrap()
-                .contains(model)
+        self.state.has_model_any(model)
     }
 
     pub fn list_chat_completions_models(&self) -> Vec<String> {
@@ -98,6 +148,14 @@ impl ModelManager {
         self.state.completion_engines.lock().unwrap().list()
     }
 
+    pub fn list_embeddings_models(&self) -> Vec<String> {
+        self.state.embedding_engines.lock().unwrap().list()
+    }
+
+    pub fn list_transcriptions_models(&self) -> Vec<String> {
+        self.state.transcription_engines.lock().unwrap().list()
+    }
+
     pub fn add_completions_model(
         &self,
         model: &str,
@@ -116,6 +174,34 @@ impl ModelManager {
         clients.add(model, engine)
     }
 
+    pub fn add_embeddings_model(
+        &self,
+        model: &str,
+        engine: OpenAIEmbeddingsStreamingEngine,
+    ) -> Result<(), ServiceHttpError> {
+        let mut clients = self.state.embedding_engines.lock().unwrap();
+        clients.add(model, engine)
+    }
+
+    pub fn add_transcriptions_model(
+        &self,
+        model: &str,
+        engine: OpenAITranscriptionsStreamingEngine,
+    ) -> Result<(), ServiceHttpError> {
+        let mut clients = self.state.transcription_engines.lock().unwrap();
+        clients.add(model, engine)
+    }
+
+    /// Register the pre-processor of a model, used to tokenize and detokenize without generating
+    pub fn add_preprocessor(
+        &self,
+        model: &str,
+        preprocessor: Arc<OpenAIPreprocessor>,
+    ) -> Result<(), ServiceHttpError> {
+        let mut preprocessors = self.state.preprocessors.lock().unwrap();
+        preprocessors.add(model, preprocessor)
+    }
+
     pub fn remove_completions_model(&self, model: &str) -> Result<(), ServiceHttpError> {
         let mut clients = self.state.completion_engines.lock().unwrap();
         clients.remove(model)
@@ -126,6 +212,106 @@ impl ModelManager {
         clients.remove(model)
     }
 
+    pub fn remove_embeddings_model(&self, model: &str) -> Result<(), ServiceHttpError> {
+        let mut clients = self.state.embedding_engines.lock().unwrap();
+        clients.remove(model)
+    }
+
+    pub fn remove_transcriptions_model(&self, model: &str) -> Result<(), ServiceHttpError> {
+        let mut clients = self.state.transcription_engines.lock().unwrap();
+        clients.remove(model)
+    }
+
+    pub fn remove_preprocessor(&self, model: &str) -> Result<(), ServiceHttpError> {
+        let mut preprocessors = self.state.preprocessors.lock().unwrap();
+        preprocessors.remove(model)
+    }
+
+    /// Remove the model from every API, whatever types it was registered as. Returns false
+    /// if it wasn't there.
+    pub fn remove_model(&self, model: &str) -> bool {
+        self.state.remove_model(model)
+    }
+
+    /// Record the endpoint a discovered model was registered at. Callers with a namespace
+    /// only see the models of theirs, see [`crate::tenancy`], and aliases can require it.
+    pub fn set_model_endpoint(&self, model: &str, endpoint: &Endpoint) {
+        self.state
+            .model_endpoints
+            .lock()
+            .unwrap()
+            .insert(model.to_string(), endpoint.clone());
+    }
+
+    pub fn model_namespace(&self, model: &str) -> Option<String> {
+        self.state.model_namespace(model)
+    }
+
+    /// Record the workers of a discovered model and the sticky sessions of its routers, for
+    /// `/admin/workers` and `/admin/sessions`
+    pub fn set_model_workers(&self, model: &str, client: Client, sticky: Vec<Arc<StickySessions>>) {
+        self.state
+            .model_workers
+            .lock()
+            .unwrap()
+            .insert(model.to_string(), ModelWorkers { client, sticky });
+    }
+
+    /// Record the engine serving a model, such as `vllm`, see
+    /// [`crate::model_card::ModelDeploymentCard::engine`]
+    pub fn set_engine_name(&self, model: &str, engine: &str) {
+        self.state
+            .engine_names
+            .lock()
+            .unwrap()
+            .insert(model.to_string(), engine.to_string());
+    }
+
+    /// Serve requests for each alias with the models it maps to, replacing the previous
+    /// aliases. Requests already running are not affected.
+    pub fn set_model_aliases(&self, aliases: ModelAliases) {
+        *self.state.model_aliases.lock().unwrap() = aliases;
+    }
+
+    /// Send the events of streaming responses in bursts, see [`coalesce`]. None sends each
+    /// event at once, unless the request asks otherwise.
+    pub fn set_stream_coalescing(&self, coalescing: Option<StreamCoalescing>) {
+        *self.state.stream_coalescing.lock().unwrap() = coalescing;
+    }
+
+    /// Send a comment on idle streaming responses this often, so that load balancers don't
+    /// close them as idle. None sends nothing.
+    pub fn set_sse_keep_alive(&self, interval: Option<Duration>) {
+        *self.state.sse_keep_alive.lock().unwrap() = interval;
+    }
+
+    /// Cap the tokens and the time of every request, see [`limits`]. Requests already
+    /// running keep the limits they started with.
+    pub fn set_request_limits(&self, limits: RequestLimits) {
+        *self.state.request_limits.lock().unwrap() = limits;
+    }
+
+    /// Check the prompts and responses of completions and chat completions, see
+    /// [`moderation`]. None checks nothing.
+    pub fn set_content_filter(&self, filter: Option<Arc<ContentFilter>>) {
+        *self.state.content_filter.lock().unwrap() = filter;
+    }
+
+    /// Give `model` a LoRA adapter, served as `<model>:<adapter>`. The model need not be
+    /// served yet.
+    pub fn add_lora_adapter(&self, model: &str, adapter: LoraAdapter) -> Result<(), LoraError> {
+        self.state.lora_adapters.lock().unwrap().add(model, adapter)
+    }
+
+    pub fn remove_lora_adapter(&self, model: &str, name: &str) -> Option<LoraAdapter> {
+        self.state.lora_adapters.lock().unwrap().remove(model, name)
+    }
+
+    /// Every model with adapters and its adapters
+    pub fn lora_adapters(&self) -> Vec<(String, LoraAdapter)> {
+        self.state.lora_adapters.lock().unwrap().list()
+    }
+
     /// Get the Prometheus [`Metrics`] object which tracks request counts and inflight requests
     pub fn metrics(&self) -> Arc<Metrics> {
         self.state.metrics.clone()
@@ -136,6 +322,8 @@ struct ModelEngines<E> {
     /// Optional default model name
     default: Option<String>,
     engines: HashMap<String, E>,
+    /// When each model was added, seconds since epoch
+    created: HashMap<String, u64>,
 }
 
 impl<E> Default for ModelEngines<E> {
@@ -143,6 +331,7 @@ impl<E> Default for ModelEngines<E> {
         Self {
             default: None,
             engines: HashMap::new(),
+            created: HashMap::new(),
         }
     }
 }
@@ -162,17 +351,32 @@ impl<E> ModelEngines<E> {
         if self.engines.contains_key(model) {
             return Err(ServiceHttpError::ModelAlreadyExists(model.to_string()));
         }
-        self.engines.insert(model.to_string(), engine);
+        self.replace(model, engine);
         Ok(())
     }
 
+    /// Add the model, or give it a new engine if it has one. Returns the old engine.
+    fn replace(&mut self, model: &str, engine: E) -> Option<E> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.created.insert(model.to_string(), now);
+        self.engines.insert(model.to_string(), engine)
+    }
+
     fn remove(&mut self, model: &str) -> Result<(), ServiceHttpError> {
         if self.engines.remove(model).is_none() {
             return Err(ServiceHttpError::ModelNotFound(model.to_string()));
         }
+        self.created.remove(model);
         Ok(())
     }
 
+    fn created(&self, model: &str) -> Option<u64> {
+        self.created.get(model).copied()
+    }
+
     fn get(&self, model: &str) -> Option<&E> {
         self.engines.get(model)
     }
@@ -191,24 +395,253 @@ impl<E> ModelEngines<E> {
 pub struct DeploymentState {
     completion_engines: Arc<Mutex<ModelEngines<OpenAICompletionsStreamingEngine>>>,
     chat_completion_engines: Arc<Mutex<ModelEngines<OpenAIChatCompletionsStreamingEngine>>>,
+    embedding_engines: Arc<Mutex<ModelEngines<OpenAIEmbeddingsStreamingEngine>>>,
+    transcription_engines: Arc<Mutex<ModelEngines<OpenAITranscriptionsStreamingEngine>>>,
+    preprocessors: Arc<Mutex<ModelEngines<Arc<OpenAIPreprocessor>>>>,
     metrics: Arc<Metrics>,
-    sse_keep_alive: Option<Duration>,
+    /// How often idle streaming responses get a keep-alive comment
+    sse_keep_alive: Mutex<Option<Duration>>,
+    /// Default coalescing of the events of streaming responses
+    stream_coalescing: Mutex<Option<StreamCoalescing>>,
+    /// Caps on what each request can ask for
+    request_limits: Mutex<RequestLimits>,
+    /// Checks prompts and responses, see [`moderation`]
+    content_filter: Mutex<Option<Arc<ContentFilter>>>,
+    load_shedder: Option<Arc<LoadShedder>>,
+    admission: Option<Arc<AdmissionQueue>>,
+    /// Requests being generated, by the id in their `x-request-id` response header
+    running: Mutex<HashMap<String, Arc<dyn AsyncEngineContext>>>,
+    /// Endpoint each discovered model was registered at
+    model_endpoints: Mutex<HashMap<String, Endpoint>>,
+    /// Engine serving each model, for the `engine` label of the latency metrics
+    engine_names: Mutex<HashMap<String, String>>,
+    /// Workers of each discovered model
+    model_workers: Mutex<HashMap<String, ModelWorkers>>,
+    /// Other names clients may use for models
+    model_aliases: Mutex<ModelAliases>,
+    /// Picks the target of an alias with several, in turn
+    alias_turn: AtomicUsize,
+    /// Served as `<model>:<adapter>`
+    lora_adapters: Mutex<LoraAdapters>,
 }
 
 impl DeploymentState {
-    fn new() -> Self {
+    fn new(load_shedder: Option<Arc<LoadShedder>>, admission: Option<Arc<AdmissionQueue>>) -> Self {
         Self {
             completion_engines: Arc::new(Mutex::new(ModelEngines::default())),
             chat_completion_engines: Arc::new(Mutex::new(ModelEngines::default())),
+            embedding_engines: Arc::new(Mutex::new(ModelEngines::default())),
+            transcription_engines: Arc::new(Mutex::new(ModelEngines::default())),
+            preprocessors: Arc::new(Mutex::new(ModelEngines::default())),
             metrics: Arc::new(Metrics::default()),
-            sse_keep_alive: None,
+            sse_keep_alive: Mutex::new(None),
+            stream_coalescing: Mutex::new(None),
+            request_limits: Mutex::new(RequestLimits::default()),
+            content_filter: Mutex::new(None),
+            load_shedder,
+            admission,
+            running: Mutex::new(HashMap::new()),
+            model_endpoints: Mutex::new(HashMap::new()),
+            engine_names: Mutex::new(HashMap::new()),
+            model_workers: Mutex::new(HashMap::new()),
+            model_aliases: Mutex::new(ModelAliases::default()),
+            alias_turn: AtomicUsize::new(0),
+            lora_adapters: Mutex::new(LoraAdapters::default()),
         }
     }
 
+    fn has_model_any(&self, model: &str) -> bool {
+        self.chat_completion_engines.lock().unwrap().contains(model)
+            || self.completion_engines.lock().unwrap().contains(model)
+            || self.embedding_engines.lock().unwrap().contains(model)
+            || self.transcription_engines.lock().unwrap().contains(model)
+    }
+
+    /// Remove the model from every API, whatever types it was registered as. Returns false
+    /// if it wasn't there.
+    fn remove_model(&self, model: &str) -> bool {
+        // Only some of these will have it, the others error
+        let removed = [
+            self.chat_completion_engines.lock().unwrap().remove(model),
+            self.completion_engines.lock().unwrap().remove(model),
+            self.embedding_engines.lock().unwrap().remove(model),
+            self.transcription_engines.lock().unwrap().remove(model),
+        ];
+        let _ = self.preprocessors.lock().unwrap().remove(model);
+        self.model_endpoints.lock().unwrap().remove(model);
+        self.engine_names.lock().unwrap().remove(model);
+        self.model_workers.lock().unwrap().remove(model);
+        removed.iter().any(Result::is_ok)
+    }
+
+    /// Serve `model` with these engines from the next request on, replacing the ones it has.
+    /// Chat and completions switch together. Returns whether the model was served before.
+    fn swap_model(
+        &self,
+        model: &str,
+        engine_name: Option<&str>,
+        chat: OpenAIChatCompletionsStreamingEngine,
+        completions: OpenAICompletionsStreamingEngine,
+        preprocessor: Option<Arc<OpenAIPreprocessor>>,
+    ) -> bool {
+        match engine_name {
+            Some(engine_name) => {
+                let mut engine_names = self.engine_names.lock().unwrap();
+                engine_names.insert(model.to_string(), engine_name.to_string());
+            }
+            None => {
+                self.engine_names.lock().unwrap().remove(model);
+            }
+        }
+        match preprocessor {
+            Some(preprocessor) => {
+                self.preprocessors
+                    .lock()
+                    .unwrap()
+                    .replace(model, preprocessor);
+            }
+            None => {
+                let _ = self.preprocessors.lock().unwrap().remove(model);
+            }
+        }
+        let mut chat_engines = self.chat_completion_engines.lock().unwrap();
+        let mut completion_engines = self.completion_engines.lock().unwrap();
+        let old_chat = chat_engines.replace(model, chat);
+        let old_completions = completion_engines.replace(model, completions);
+        old_chat.is_some() || old_completions.is_some()
+    }
+
+    /// Every model served, whatever its API
+    fn model_names(&self) -> Vec<String> {
+        let mut names = self.chat_completion_engines.lock().unwrap().list();
+        names.extend(self.completion_engines.lock().unwrap().list());
+        names.extend(self.embedding_engines.lock().unwrap().list());
+        names.extend(self.transcription_engines.lock().unwrap().list());
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// The engine serving `model`, [`metrics::ENGINE_UNKNOWN`] if it wasn't recorded
+    fn engine_name(&self, model: &str) -> String {
+        let base = self.lora_adapter(model).map(|(base, _)| base);
+        self.engine_names
+            .lock()
+            .unwrap()
+            .get(base.as_deref().unwrap_or(model))
+            .cloned()
+            .unwrap_or_else(|| metrics::ENGINE_UNKNOWN.to_string())
+    }
+
+    /// The model and adapter of a `<model>:<adapter>` name. A model served under the whole
+    /// name is not an adapter.
+    fn lora_adapter(&self, model: &str) -> Option<(String, LoraAdapter)> {
+        if self.has_model_any(model) {
+            return None;
+        }
+        let adapters = self.lora_adapters.lock().unwrap();
+        let (base, adapter) = adapters.resolve(model)?;
+        Some((base.to_string(), adapter.clone()))
+    }
+
+    /// The model the next request for `model` goes to: itself unless it is an alias, else the
+    /// next of the alias's targets being served. An alias none of whose targets are served
+    /// stays as it is, and is then not found.
+    fn resolve_alias(&self, model: &str) -> String {
+        let mut served = self.served_targets(model).unwrap_or_default();
+        if served.is_empty() {
+            return model.to_string();
+        }
+        let turn = self.alias_turn.fetch_add(1, Ordering::Relaxed);
+        served.swap_remove(turn % served.len())
+    }
+
+    /// The models `model` may be resolved to, whether they are served or not
+    fn alias_targets(&self, model: &str) -> Vec<String> {
+        match self.model_aliases.lock().unwrap().targets(model) {
+            Some(targets) => targets.iter().map(|target| target.model.clone()).collect(),
+            None => vec![model.to_string()],
+        }
+    }
+
+    /// The targets of alias `alias` that are served now, from the right endpoint. None if it
+    /// is not an alias.
+    fn served_targets(&self, alias: &str) -> Option<Vec<String>> {
+        let aliases = self.model_aliases.lock().unwrap();
+        let targets = aliases.targets(alias)?;
+        let endpoints = self.model_endpoints.lock().unwrap();
+        let served = targets
+            .iter()
+            .filter(|target| {
+                self.has_model_any(&target.model)
+                    && target
+                        .endpoint
+                        .as_ref()
+                        .is_none_or(|endpoint| endpoints.get(&target.model) == Some(endpoint))
+            })
+            .map(|target| target.model.clone())
+            .collect();
+        Some(served)
+    }
+
+    /// Each alias with at least one target served, and those targets
+    fn served_aliases(&self) -> Vec<(String, Vec<String>)> {
+        let names: Vec<String> = self
+            .model_aliases
+            .lock()
+            .unwrap()
+            .names()
+            .map(str::to_string)
+            .collect();
+        names
+            .into_iter()
+            .filter_map(|alias| {
+                let served = self.served_targets(&alias)?;
+                (!served.is_empty()).then_some((alias, served))
+            })
+            .collect()
+    }
+
+    /// The namespace `model` was registered in, None for models that were not discovered.
+    /// Adapters are in the namespace of their model.
+    fn model_namespace(&self, model: &str) -> Option<String> {
+        let base = self.lora_adapter(model).map(|(base, _)| base);
+        self.model_endpoints
+            .lock()
+            .unwrap()
+            .get(base.as_deref().unwrap_or(model))
+            .map(|endpoint| endpoint.namespace.clone())
+    }
+
+    /// Make a request cancellable by id until the returned guard is dropped
+    fn register_running(
+        self: &Arc<Self>,
+        request_id: &str,
+        context: Arc<dyn AsyncEngineContext>,
+    ) -> RunningGuard {
+        self.running
+            .lock()
+            .unwrap()
+            .insert(request_id.to_string(), context);
+        RunningGuard {
+            state: self.clone(),
+            request_id: request_id.to_string(),
+        }
+    }
+
+    fn running_request(&self, request_id: &str) -> Option<Arc<dyn AsyncEngineContext>> {
+        self.running.lock().unwrap().get(request_id).cloned()
+    }
+
     fn get_completions_engine(
         &self,
         model: &str,
     ) -> Result<OpenAICompletionsStreamingEngine, ServiceHttpError> {
+        if let Some((base, adapter)) = self.lora_adapter(model) {
+            let engine = self.get_completions_engine(&base)?;
+            return Ok(LoraEngine::wrap(engine, adapter));
+        }
+        let model = &self.resolve_alias(model);
         self.completion_engines
             .lock()
             .unwrap()
@@ -221,6 +654,11 @@ impl DeploymentState {
         &self,
         model: &str,
     ) -> Result<OpenAIChatCompletionsStreamingEngine, ServiceHttpError> {
+        if let Some((base, adapter)) = self.lora_adapter(model) {
+            let engine = self.get_chat_completions_engine(&base)?;
+            return Ok(LoraEngine::wrap(engine, adapter));
+        }
+        let model = &self.resolve_alias(model);
         self.chat_completion_engines
             .lock()
             .unwrap()
@@ -228,13 +666,110 @@ impl DeploymentState {
             .cloned()
             .ok_or(ServiceHttpError::ModelNotFound(model.to_string()))
     }
+
+    /// The embeddings engine for this model. If the model exists but was not registered as
+    /// an embedding model this is a capability error rather than not found.
+    fn get_embeddings_engine(
+        &self,
+        model: &str,
+    ) -> Result<OpenAIEmbeddingsStreamingEngine, ServiceHttpError> {
+        let model = &self.resolve_alias(model);
+        if let Some(engine) = self.embedding_engines.lock().unwrap().get(model) {
+            return Ok(engine.clone());
+        }
+        let exists = self.chat_completion_engines.lock().unwrap().contains(model)
+            || self.completion_engines.lock().unwrap().contains(model)
+            || self.transcription_engines.lock().unwrap().contains(model);
+        if exists {
+            Err(ServiceHttpError::UnsupportedCapability {
+                model: model.to_string(),
+                capability: "embeddings".to_string(),
+            })
+        } else {
+            Err(ServiceHttpError::ModelNotFound(model.to_string()))
+        }
+    }
+
+    /// The speech-to-text engine for this model. As for embeddings, a model of another type is
+    /// a capability error.
+    fn get_transcriptions_engine(
+        &self,
+        model: &str,
+    ) -> Result<OpenAITranscriptionsStreamingEngine, ServiceHttpError> {
+        let model = &self.resolve_alias(model);
+        if let Some(engine) = self.transcription_engines.lock().unwrap().get(model) {
+            return Ok(engine.clone());
+        }
+        let exists = self.chat_completion_engines.lock().unwrap().contains(model)
+            || self.completion_engines.lock().unwrap().contains(model)
+            || self.embedding_engines.lock().unwrap().contains(model);
+        if exists {
+            Err(ServiceHttpError::UnsupportedCapability {
+                model: model.to_string(),
+                capability: "transcription".to_string(),
+            })
+        } else {
+            Err(ServiceHttpError::ModelNotFound(model.to_string()))
+        }
+    }
+
+    /// When the model was first registered, under any of its types
+    fn model_created(&self, model: &str) -> Option<u64> {
+        [
+            self.chat_completion_engines.lock().unwrap().created(model),
+            self.completion_engines.lock().unwrap().created(model),
+            self.embedding_engines.lock().unwrap().created(model),
+            self.transcription_engines.lock().unwrap().created(model),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+    }
+
+    /// The pre-processor of this model. Models whose requests are pre-processed by the worker
+    /// rather than here cannot be tokenized, that is a capability error rather than not found.
+    fn get_preprocessor(&self, model: &str) -> Result<Arc<OpenAIPreprocessor>, ServiceHttpError> {
+        // An adapter doesn't change the tokenizer
+        if let Some((base, _)) = self.lora_adapter(model) {
+            return self.get_preprocessor(&base);
+        }
+        let model = &self.resolve_alias(model);
+        if let Some(preprocessor) = self.preprocessors.lock().unwrap().get(model) {
+            return Ok(preprocessor.clone());
+        }
+        let exists = self.chat_completion_engines.lock().unwrap().contains(model)
+            || self.completion_engines.lock().unwrap().contains(model)
+            || self.embedding_engines.lock().unwrap().contains(model)
+            || self.transcription_engines.lock().unwrap().contains(model);
+        if exists {
+            Err(ServiceHttpError::UnsupportedCapability {
+                model: model.to_string(),
+                capability: "tokenization".to_string(),
+            })
+        } else {
+            Err(ServiceHttpError::ModelNotFound(model.to_string()))
+        }
+    }
+}
+
+/// A request registered with [`DeploymentState::register_running`]
+struct RunningGuard {
+    state: Arc<DeploymentState>,
+    request_id: String,
+}
+
+impl Drop for RunningGuard {
+    fn drop(&mut self) {
+        self.state.running.lock().unwrap().remove(&self.request_id);
+    }
 }
 
 /// Documentation for a route
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct RouteDoc {
     method: axum::http::Method,
     path: String,
+    multipart: bool,
 }
 
 impl std::fmt::Display for RouteDoc {
@@ -248,6 +783,25 @@ impl RouteDoc {
         RouteDoc {
             method,
             path: path.into(),
+            multipart: false,
         }
     }
+
+    /// The request body is `multipart/form-data` rather than JSON
+    pub fn multipart(mut self) -> Self {
+        self.multipart = true;
+        self
+    }
+
+    pub fn method(&self) -> &axum::http::Method {
+        &self.method
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn is_multipart(&self) -> bool {
+        self.multipart
+    }
 }
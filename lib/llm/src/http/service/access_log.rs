@@ -0,0 +1,372 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! One JSON line per request, for billing and abuse investigation.
+//!
+//! The middleware starts an entry for each request and finishes it with the response status.
+//! Handlers fill in what only they know through [`AccessRecord`]: the model, the token counts
+//! and when the first token came out. A streamed response is logged once its stream is done,
+//! so the latency covers all of it.
+//!
+//! Requests that failed are always logged, successful ones at [`AccessLogConfig::sample_rate`].
+//! Prompts are left out unless [`AccessLogConfig::prompts`] asks for them.
+
+use std::convert::Infallible;
+use std::fmt::Display;
+use std::fs::OpenOptions;
+use std::io::{LineWriter, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use axum::{
+    extract::{FromRequestParts, Request, State},
+    http::request::Parts,
+    middleware::Next,
+    response::Response,
+};
+use serde::Serialize;
+
+use super::auth::PUBLIC_PATHS;
+use super::openai::REQUEST_ID_HEADER;
+use crate::types::Annotated;
+
+#[derive(Debug, Clone)]
+pub struct AccessLogConfig {
+    /// File the lines are appended to, stdout if None
+    pub path: Option<PathBuf>,
+
+    /// Fraction of successful requests to log, from 0 to 1
+    pub sample_rate: f64,
+
+    pub prompts: PromptLogging,
+}
+
+impl Default for AccessLogConfig {
+    fn default() -> Self {
+        AccessLogConfig {
+            path: None,
+            sample_rate: 1.0,
+            prompts: PromptLogging::default(),
+        }
+    }
+}
+
+/// How much of the prompt goes in the log
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PromptLogging {
+    /// Not at all
+    #[default]
+    Omit,
+
+    /// A BLAKE3 hash of it, to find the same prompt sent again without keeping its content
+    Hash,
+
+    /// All of it
+    Full,
+}
+
+impl FromStr for PromptLogging {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "omit" => Ok(PromptLogging::Omit),
+            "hash" => Ok(PromptLogging::Hash),
+            "full" => Ok(PromptLogging::Full),
+            _ => anyhow::bail!("'{s}' is not one of omit, hash, full"),
+        }
+    }
+}
+
+impl Display for PromptLogging {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PromptLogging::Omit => write!(f, "omit"),
+            PromptLogging::Hash => write!(f, "hash"),
+            PromptLogging::Full => write!(f, "full"),
+        }
+    }
+}
+
+pub(crate) struct AccessLog {
+    out: Mutex<Box<dyn Write + Send>>,
+    sample_rate: f64,
+    prompts: PromptLogging,
+}
+
+impl AccessLog {
+    pub(crate) fn new(config: AccessLogConfig) -> anyhow::Result<Self> {
+        if !(0.0..=1.0).contains(&config.sample_rate) {
+            anyhow::bail!(
+                "Access log sample rate {} is not between 0 and 1",
+                config.sample_rate
+            );
+        }
+        let out: Box<dyn Write + Send> = match &config.path {
+            Some(path) => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|err| anyhow::anyhow!("Access log {}: {err}", path.display()))?;
+                Box::new(LineWriter::new(file))
+            }
+            None => Box::new(std::io::stdout()),
+        };
+        Ok(AccessLog {
+            out: Mutex::new(out),
+            sample_rate: config.sample_rate,
+            prompts: config.prompts,
+        })
+    }
+
+    fn write(&self, line: &AccessLine) {
+        let Ok(json) = serde_json::to_string(line) else {
+            return;
+        };
+        let mut out = self.out.lock().unwrap();
+        if let Err(err) = writeln!(out, "{json}") {
+            tracing::warn!(%err, "Failed to write access log");
+        }
+    }
+}
+
+/// A line of the log
+#[derive(Serialize, Default, Debug)]
+struct AccessLine {
+    timestamp: String,
+    request_id: Option<String>,
+    method: String,
+    path: String,
+    status: u16,
+    model: Option<String>,
+    /// Name of the API key, never the key
+    key: Option<String>,
+    input_tokens: Option<u32>,
+    output_tokens: Option<u32>,
+    ttft_ms: Option<f64>,
+    latency_ms: f64,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prompt: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prompt_hash: Option<String>,
+}
+
+/// A request being served. Written out when the middleware and the response stream are both
+/// done with it.
+struct Entry {
+    log: Arc<AccessLog>,
+    received: Instant,
+    first_token: Mutex<Option<Instant>>,
+    line: Mutex<AccessLine>,
+}
+
+impl Drop for Entry {
+    fn drop(&mut self) {
+        let line = self.line.get_mut().unwrap();
+        let failed = line.status >= 400;
+        if !failed && rand::random::<f64>() >= self.log.sample_rate {
+            return;
+        }
+        line.latency_ms = millis(self.received.elapsed());
+        line.ttft_ms = self
+            .first_token
+            .get_mut()
+            .unwrap()
+            .map(|first| millis(first.duration_since(self.received)));
+        self.log.write(line);
+    }
+}
+
+fn millis(duration: std::time::Duration) -> f64 {
+    duration.as_micros() as f64 / 1000.0
+}
+
+/// Middleware starting the access log entry of every request but scrapes and probes
+pub(crate) async fn log_request(
+    State(log): State<Arc<AccessLog>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if PUBLIC_PATHS.contains(&request.uri().path()) {
+        return next.run(request).await;
+    }
+    let entry = Arc::new(Entry {
+        log,
+        received: Instant::now(),
+        first_token: Mutex::new(None),
+        line: Mutex::new(AccessLine {
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            method: request.method().to_string(),
+            path: request.uri().path().to_string(),
+            ..Default::default()
+        }),
+    });
+    request
+        .extensions_mut()
+        .insert(AccessRecord(Some(entry.clone())));
+
+    let response = next.run(request).await;
+    let mut line = entry.line.lock().unwrap();
+    line.status = response.status().as_u16();
+    line.request_id = response
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    drop(line);
+    response
+}
+
+/// What handlers know about a request for its access log entry. Does nothing while the access
+/// log is off. Keep a clone with the response stream so the entry waits for it.
+#[derive(Clone, Default)]
+pub(crate) struct AccessRecord(Option<Arc<Entry>>);
+
+impl AccessRecord {
+    pub fn set_key(&self, name: &str) {
+        self.update(|line| line.key = Some(name.to_string()));
+    }
+
+    pub fn set_model(&self, model: &str) {
+        self.update(|line| line.model = Some(model.to_string()));
+    }
+
+    /// `prompt` is only called if the log keeps prompts
+    pub fn set_prompt(&self, prompt: impl FnOnce() -> String) {
+        let Some(entry) = &self.0 else {
+            return;
+        };
+        let mut line = entry.line.lock().unwrap();
+        match entry.log.prompts {
+            PromptLogging::Omit => {}
+            PromptLogging::Hash => {
+                line.prompt_hash = Some(blake3::hash(prompt().as_bytes()).to_hex().to_string())
+            }
+            PromptLogging::Full => line.prompt = Some(prompt()),
+        }
+    }
+
+    pub fn set_usage(&self, input_tokens: u32, output_tokens: u32) {
+        self.update(|line| {
+            line.input_tokens = Some(input_tokens);
+            line.output_tokens = Some(output_tokens);
+        });
+    }
+
+    /// Call with each response, the first with data carries the first token
+    pub fn observe<T>(&self, response: &Annotated<T>) {
+        let Some(entry) = &self.0 else {
+            return;
+        };
+        if response.data.is_some() {
+            entry
+                .first_token
+                .lock()
+                .unwrap()
+                .get_or_insert_with(Instant::now);
+        }
+    }
+
+    fn update(&self, f: impl FnOnce(&mut AccessLine)) {
+        if let Some(entry) = &self.0 {
+            f(&mut entry.line.lock().unwrap());
+        }
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for AccessRecord {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts
+            .extensions
+            .get::<AccessRecord>()
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("access.log");
+        let log = Arc::new(
+            AccessLog::new(AccessLogConfig {
+                path: Some(path.clone()),
+                sample_rate: 0.0,
+                prompts: PromptLogging::Hash,
+            })
+            .unwrap(),
+        );
+        let start = |status| {
+            let entry = Arc::new(Entry {
+                log: log.clone(),
+                received: Instant::now(),
+                first_token: Mutex::new(None),
+                line: Mutex::new(AccessLine {
+                    status,
+                    ..Default::default()
+                }),
+            });
+            AccessRecord(Some(entry))
+        };
+
+        // Not sampled
+        let record = start(200);
+        record.set_model("llama");
+        drop(record);
+
+        let record = start(500);
+        record.set_model("llama");
+        record.set_key("team-a");
+        record.set_prompt(|| "Hello".to_string());
+        record.set_usage(5, 7);
+        record.observe(&Annotated::from_data(()));
+        drop(record);
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0]["status"], 500);
+        assert_eq!(lines[0]["key"], "team-a");
+        assert_eq!(lines[0]["output_tokens"], 7);
+        assert!(lines[0]["ttft_ms"].is_number());
+        assert!(lines[0].get("prompt").is_none());
+        assert_eq!(
+            lines[0]["prompt_hash"],
+            blake3::hash(b"Hello").to_hex().to_string()
+        );
+
+        assert!(AccessLog::new(AccessLogConfig {
+            sample_rate: 2.0,
+            ..Default::default()
+        })
+        .is_err());
+        // Off, it does nothing
+        AccessRecord::default().set_model("llama");
+    }
+}
@@ -0,0 +1,366 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Bounded admission queue
+//!
+//! Each model runs at most `max_running` requests at once. The next ones wait their turn in a
+//! queue of at most `max_queued` requests. Past that they get a 429 with the queue depth in an
+//! [`QUEUE_DEPTH_HEADER`] header, rather than piling up in NATS behind busy workers. A streamed
+//! response runs until its stream ends.
+//!
+//! A free slot goes to the oldest waiting request of the highest [`Priority`], so interactive
+//! traffic overtakes batch traffic. To keep the lower priorities from starving, a request that
+//! waited [`AdmissionConfig::starvation_timeout`] goes ahead of everything that waited less.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use dynamo_runtime::metrics::register;
+use prometheus::{IntCounterVec, IntGaugeVec, Opts};
+use tokio::sync::oneshot;
+
+use crate::protocols::openai::nvext::Priority;
+
+/// Response header with the depth of the queue that turned the request away
+pub const QUEUE_DEPTH_HEADER: &str = "x-dynamo-queue-depth";
+
+/// Request header with the request's [`Priority`], unless its `nvext.priority` says otherwise
+pub const PRIORITY_HEADER: &str = "x-dynamo-priority";
+
+static QUEUE_DEPTH: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register(
+        IntGaugeVec::new(
+            Opts::new(
+                "dynamo_admission_queue_depth",
+                "Requests waiting for one of the model's running slots",
+            ),
+            &["model"],
+        )
+        .unwrap(),
+    )
+});
+
+static SHED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "dynamo_admission_shed_total",
+                "Requests rejected with a 429 because the model's queue was full",
+            ),
+            &["model"],
+        )
+        .unwrap(),
+    )
+});
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdmissionConfig {
+    /// Requests of one model running at once
+    pub max_running: u32,
+
+    /// Requests of one model waiting for a running slot
+    pub max_queued: u32,
+
+    /// A request waiting this long is admitted before those that waited less, whatever
+    /// their priority
+    pub starvation_timeout: Duration,
+}
+
+/// The model's queue is full
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueFull {
+    pub model: String,
+    pub depth: u32,
+}
+
+impl std::fmt::Display for QueueFull {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Model {} is busy with {} requests waiting, try again later",
+            self.model, self.depth
+        )
+    }
+}
+
+struct Waiter {
+    id: u64,
+    since: Instant,
+    admit: oneshot::Sender<()>,
+}
+
+#[derive(Default)]
+struct QueueState {
+    running: u32,
+    next_id: u64,
+    /// By [`Priority::ALL`] order
+    waiting: [VecDeque<Waiter>; Priority::ALL.len()],
+}
+
+impl QueueState {
+    fn depth(&self) -> u32 {
+        self.waiting.iter().map(VecDeque::len).sum::<usize>() as u32
+    }
+
+    /// Take the next request to admit: the oldest one past the starvation timeout, otherwise
+    /// the oldest of the highest priority
+    fn next(&mut self, starvation_timeout: Duration) -> Option<Waiter> {
+        let starving = self
+            .waiting
+            .iter()
+            .enumerate()
+            .filter_map(|(tier, waiters)| {
+                let oldest = waiters.front()?;
+                (oldest.since.elapsed() >= starvation_timeout).then_some((oldest.since, tier))
+            })
+            .min()
+            .map(|(_, tier)| tier);
+        let tier = starving.or_else(|| self.waiting.iter().position(|w| !w.is_empty()))?;
+        self.waiting[tier].pop_front()
+    }
+}
+
+struct ModelQueue {
+    model: String,
+    config: AdmissionConfig,
+    state: Mutex<QueueState>,
+}
+
+impl ModelQueue {
+    /// Free a running slot, and hand it to the next waiting request
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.running -= 1;
+        if let Some(waiter) = state.next(self.config.starvation_timeout) {
+            // The waiter leaves the queue under this lock, so it is still listening
+            let _ = waiter.admit.send(());
+            state.running += 1;
+        }
+        self.set_depth(&state);
+    }
+
+    fn set_depth(&self, state: &QueueState) {
+        QUEUE_DEPTH
+            .with_label_values(&[&self.model])
+            .set(state.depth() as i64);
+    }
+}
+
+pub struct AdmissionQueue {
+    config: AdmissionConfig,
+    models: Mutex<HashMap<String, Arc<ModelQueue>>>,
+}
+
+impl AdmissionQueue {
+    pub fn new(config: AdmissionConfig) -> Self {
+        AdmissionQueue {
+            config,
+            models: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Wait for a running slot of `model`, or fail straight away if its queue is full. The
+    /// request runs until the returned [`Admitted`] is dropped.
+    pub async fn admit(&self, model: &str, priority: Priority) -> Result<Admitted, QueueFull> {
+        let queue = self
+            .models
+            .lock()
+            .unwrap()
+            .entry(model.to_string())
+            .or_insert_with(|| {
+                Arc::new(ModelQueue {
+                    model: model.to_string(),
+                    config: self.config,
+                    state: Mutex::new(QueueState::default()),
+                })
+            })
+            .clone();
+
+        let tier = Priority::ALL.iter().position(|p| *p == priority).unwrap();
+        let (id, admitted) = {
+            let mut state = queue.state.lock().unwrap();
+            let depth = state.depth();
+            if depth == 0 && state.running < self.config.max_running {
+                state.running += 1;
+                drop(state);
+                return Ok(Admitted { queue });
+            }
+            if depth >= self.config.max_queued {
+                SHED.with_label_values(&[model]).inc();
+                return Err(QueueFull {
+                    model: model.to_string(),
+                    depth,
+                });
+            }
+            let id = state.next_id;
+            state.next_id += 1;
+            let (admit, admitted) = oneshot::channel();
+            state.waiting[tier].push_back(Waiter {
+                id,
+                since: Instant::now(),
+                admit,
+            });
+            queue.set_depth(&state);
+            (id, admitted)
+        };
+
+        let mut waiting = Waiting {
+            queue: &queue,
+            id,
+            tier,
+            admitted: false,
+        };
+        // Only fails if the queue is gone, which it can't be while we hold it
+        let _ = admitted.await;
+        waiting.admitted = true;
+        drop(waiting);
+        Ok(Admitted { queue })
+    }
+
+    /// Requests waiting for a slot of `model`
+    pub fn depth(&self, model: &str) -> u32 {
+        self.models
+            .lock()
+            .unwrap()
+            .get(model)
+            .map_or(0, |queue| queue.state.lock().unwrap().depth())
+    }
+}
+
+/// A request holding one of its model's running slots
+pub struct Admitted {
+    queue: Arc<ModelQueue>,
+}
+
+impl Drop for Admitted {
+    fn drop(&mut self) {
+        self.queue.release();
+    }
+}
+
+/// Leaves the queue if the client goes away while waiting
+struct Waiting<'a> {
+    queue: &'a ModelQueue,
+    id: u64,
+    tier: usize,
+    admitted: bool,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        if self.admitted {
+            return;
+        }
+        let mut state = self.queue.state.lock().unwrap();
+        let waiters = &mut state.waiting[self.tier];
+        match waiters.iter().position(|w| w.id == self.id) {
+            Some(index) => {
+                waiters.remove(index);
+                self.queue.set_depth(&state);
+            }
+            None => {
+                // Admitted just now, pass the slot on
+                drop(state);
+                self.queue.release();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(starvation_timeout: Duration) -> AdmissionConfig {
+        AdmissionConfig {
+            max_running: 1,
+            max_queued: 2,
+            starvation_timeout,
+        }
+    }
+
+    /// Queue a request of `priority` for model "a" and wait until it is in the queue
+    async fn queue(
+        admission: &Arc<AdmissionQueue>,
+        priority: Priority,
+    ) -> tokio::task::JoinHandle<Admitted> {
+        let depth = admission.depth("a");
+        let handle = tokio::spawn({
+            let admission = admission.clone();
+            async move { admission.admit("a", priority).await.unwrap() }
+        });
+        while admission.depth("a") == depth {
+            tokio::task::yield_now().await;
+        }
+        handle
+    }
+
+    #[tokio::test]
+    async fn test_admission_queue() {
+        let admission = Arc::new(AdmissionQueue::new(config(Duration::from_secs(60))));
+
+        let running = admission.admit("a", Priority::Normal).await.unwrap();
+        let low = queue(&admission, Priority::Low).await;
+        let high = queue(&admission, Priority::High).await;
+        assert_eq!(
+            admission.admit("a", Priority::High).await.err(),
+            Some(QueueFull {
+                model: "a".to_string(),
+                depth: 2
+            })
+        );
+        // Other models have their own queue
+        drop(admission.admit("b", Priority::Low).await.unwrap());
+
+        // High goes first although low waited longer
+        drop(running);
+        let running = high.await.unwrap();
+        assert!(!low.is_finished());
+        drop(running);
+        drop(low.await.unwrap());
+        assert_eq!(admission.depth("a"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_starvation() {
+        let admission = Arc::new(AdmissionQueue::new(config(Duration::from_millis(20))));
+
+        let running = admission.admit("a", Priority::Normal).await.unwrap();
+        let low = queue(&admission, Priority::Low).await;
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        let high = queue(&admission, Priority::High).await;
+
+        drop(running);
+        let running = low.await.unwrap();
+        assert!(!high.is_finished());
+        drop(running);
+        drop(high.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_waiter() {
+        let admission = Arc::new(AdmissionQueue::new(config(Duration::from_secs(60))));
+
+        let running = admission.admit("a", Priority::Normal).await.unwrap();
+        let cancelled = queue(&admission, Priority::High).await;
+        cancelled.abort();
+        let _ = cancelled.await;
+        assert_eq!(admission.depth("a"), 0);
+
+        drop(running);
+        drop(admission.admit("a", Priority::Normal).await.unwrap());
+    }
+}
@@ -0,0 +1,165 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::convert::Infallible;
+use std::sync::{Arc, RwLock};
+
+use axum::{
+    extract::{FromRequestParts, Request, State},
+    http::{header, request::Parts, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+
+use super::access_log::AccessRecord;
+use super::error::ErrorKind;
+use super::openai::ErrorResponse;
+use super::tenancy::NamespaceAccess;
+use crate::auth::{check_model, ApiKey, ApiKeys, AuthError};
+
+/// Scrapers and probes don't have a key
+pub(super) const PUBLIC_PATHS: &[&str] = &["/metrics", "/health", "/live", "/ready"];
+
+/// APIs that are not tied to one model, so only keys allowed to use every model can call them.
+/// The files and batches APIs are open to every key, each line of a batch is checked.
+const UNRESTRICTED_KEY_PATHS: &[&str] = &["/admin"];
+
+/// The API keys the service currently accepts, None when authentication is off. Replaced as a
+/// whole when the keys are reloaded.
+pub(crate) type CurrentApiKeys = RwLock<Option<Arc<ApiKeys>>>;
+
+/// Middleware rejecting requests without a valid `Authorization: Bearer <key>` header. The
+/// [`ApiKey`] is added to the request extensions, handlers check the model with [`Access`].
+pub(crate) async fn require_api_key(
+    State(current): State<Arc<CurrentApiKeys>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path();
+    let keys = current.read().unwrap().clone();
+    let Some(keys) = keys.filter(|_| !PUBLIC_PATHS.contains(&path)) else {
+        return next.run(request).await;
+    };
+
+    let authorization = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    let api_key = match keys.authenticate(authorization) {
+        Ok(api_key) => api_key,
+        Err(err) => return auth_error(err),
+    };
+
+    if !api_key.is_unrestricted() {
+        if let Some(prefix) = UNRESTRICTED_KEY_PATHS
+            .iter()
+            .find(|p| path.starts_with(**p))
+        {
+            let err = AuthError::Restricted {
+                key_name: api_key.name.clone(),
+                what: prefix.to_string(),
+            };
+            return auth_error(err);
+        }
+    }
+
+    tracing::trace!(key = api_key.name, "Authenticated request");
+    if let Some(record) = request.extensions().get::<AccessRecord>() {
+        record.set_key(&api_key.name);
+    }
+    request.extensions_mut().insert(api_key);
+    next.run(request).await
+}
+
+/// 401 with a `WWW-Authenticate` challenge if we don't know who is calling, 403 if we do but
+/// they may not do this
+pub(crate) fn auth_error(err: AuthError) -> Response {
+    let message = err.to_string();
+    if err.is_unauthenticated() {
+        (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+            ErrorResponse::json_of_kind(ErrorKind::Unauthenticated, &message),
+        )
+            .into_response()
+    } else {
+        ErrorResponse::of_kind(ErrorKind::Forbidden, &message).into_response()
+    }
+}
+
+/// What the caller may use: the [`ApiKey`] the middleware accepted, or everything when API
+/// keys are off, narrowed to the models of the key's namespace.
+#[derive(Clone)]
+pub(crate) struct Access(Option<Arc<ApiKey>>, Option<NamespaceAccess>);
+
+impl Access {
+    /// Name of the caller's API key, empty when API keys are off
+    pub fn client(&self) -> &str {
+        self.0.as_ref().map_or("", |api_key| api_key.name.as_str())
+    }
+
+    /// Namespace of the caller's API key, None without one
+    pub fn namespace(&self) -> Option<&str> {
+        self.1.as_ref().map(NamespaceAccess::name)
+    }
+
+    /// Whether the caller may use every model, which is everyone when API keys are off
+    pub fn is_unrestricted(&self) -> bool {
+        self.0
+            .as_ref()
+            .is_none_or(|api_key| api_key.is_unrestricted())
+    }
+
+    pub fn allows_model(&self, model: &str) -> bool {
+        self.check(model).is_ok()
+    }
+
+    /// Why the caller may not use `model`, None if they may
+    pub fn refusal(&self, model: &str) -> Option<String> {
+        self.check(model).err().map(|err| err.to_string())
+    }
+
+    /// 403 if the caller may not use `model`
+    pub fn check_model(&self, model: &str) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+        self.check(model)
+            .map_err(|err| ErrorResponse::of_kind(ErrorKind::Forbidden, &err.to_string()))
+    }
+
+    fn check(&self, model: &str) -> Result<(), AuthError> {
+        check_model(self.0.as_deref(), model)?;
+        match (&self.0, &self.1) {
+            // Models of other namespaces look the same as those the key is not allowed
+            (Some(api_key), Some(namespace)) if !namespace.allows_model(model) => {
+                Err(AuthError::ModelNotAllowed {
+                    key_name: api_key.name.clone(),
+                    model: model.to_string(),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Access {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Access(
+            parts.extensions.get::<Arc<ApiKey>>().cloned(),
+            parts.extensions.get::<NamespaceAccess>().cloned(),
+        ))
+    }
+}
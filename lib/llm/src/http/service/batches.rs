@@ -0,0 +1,784 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! OpenAI Batch API
+//!
+//! Clients upload a JSON Lines file with `POST /v1/files`, then start a batch over it with
+//! `POST /v1/batches`. Each line is run through the chat completions engine registered for its
+//! model, which is the same engine `dynamo-run in=batch:` drives. Results are written to an output
+//! file in the batch directory which can be fetched with `GET /v1/files/{file_id}/content`. The
+//! lines that failed go to an error file instead, the batch's `error_file_id`.
+//!
+//! Files and batches belong to the API key that created them, others get a 404. Each line is
+//! held to the models the key may use, and its tokens count against the key's rate limit and
+//! its namespace's quota, as if it came in on its own. `DELETE /v1/files/{file_id}` and
+//! `DELETE /v1/batches/{batch_id}` remove them from disk.
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::{DefaultBodyLimit, Multipart, Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+use tokio_util::sync::CancellationToken;
+
+use super::{
+    auth::Access, error::ErrorKind, metrics::Endpoint, moderation::with_content_filter,
+    openai::ErrorResponse, rate_limit::TokenMeter, DeploymentState, RouteDoc,
+};
+use crate::protocols::openai::chat_completions::{
+    NvCreateChatCompletionRequest, NvCreateChatCompletionResponse,
+};
+use dynamo_runtime::pipeline::Context;
+
+/// The only `url` we accept on a batch line, and the only `endpoint` on a batch
+const CHAT_COMPLETIONS_URL: &str = "/v1/chat/completions";
+
+/// How many lines of a batch we run against the engine at once, unless configured
+const DEFAULT_BATCH_CONCURRENCY: usize = 8;
+
+/// Largest file `POST /v1/files` takes, as OpenAI's
+const MAX_FILE_BYTES: usize = 200 * 1024 * 1024;
+
+type HttpResult<T> = Result<T, (StatusCode, Json<ErrorResponse>)>;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchStatus {
+    Validating,
+    Failed,
+    InProgress,
+    Finalizing,
+    Completed,
+    Cancelling,
+    Cancelled,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct BatchRequestCounts {
+    pub total: u64,
+    pub completed: u64,
+    pub failed: u64,
+}
+
+/// OpenAI compatible Batch object
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Batch {
+    pub id: String,
+    pub object: String, // always "batch"
+    pub endpoint: String,
+    pub input_file_id: String,
+    pub completion_window: String,
+    pub status: BatchStatus,
+    pub output_file_id: Option<String>,
+    pub error_file_id: Option<String>,
+    pub created_at: u64,
+    pub in_progress_at: Option<u64>,
+    pub completed_at: Option<u64>,
+    pub failed_at: Option<u64>,
+    pub cancelled_at: Option<u64>,
+    pub request_counts: BatchRequestCounts,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<HashMap<String, String>>,
+}
+
+/// OpenAI compatible File object
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FileObject {
+    pub id: String,
+    pub object: String, // always "file"
+    pub bytes: u64,
+    pub created_at: u64,
+    pub filename: String,
+    pub purpose: String,
+}
+
+/// Answer of `DELETE /v1/files/{file_id}` and `DELETE /v1/batches/{batch_id}`
+#[derive(Serialize, Debug)]
+struct Deleted {
+    id: String,
+    object: &'static str,
+    deleted: bool,
+}
+
+/// Who created a file or batch: the API key and its namespace, both empty when API keys are
+/// off. Only they see it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Owner {
+    key: String,
+    namespace: Option<String>,
+}
+
+impl Owner {
+    fn of(access: &Access) -> Self {
+        Owner {
+            key: access.client().to_string(),
+            namespace: access.namespace().map(str::to_string),
+        }
+    }
+}
+
+/// A batch, how to stop it and who created it
+struct BatchEntry {
+    batch: Batch,
+    cancel_token: CancellationToken,
+    owner: Owner,
+}
+
+#[derive(Deserialize, Debug)]
+struct CreateBatchRequest {
+    input_file_id: String,
+    endpoint: String,
+    #[serde(default = "default_completion_window")]
+    completion_window: String,
+    #[serde(default)]
+    metadata: Option<HashMap<String, String>>,
+}
+
+fn default_completion_window() -> String {
+    "24h".to_string()
+}
+
+/// One line of the batch input file
+#[derive(Deserialize, Debug)]
+struct BatchInputLine {
+    custom_id: String,
+    #[serde(default)]
+    method: Option<String>,
+    url: String,
+    body: serde_json::Value,
+}
+
+/// One line of the batch output file
+#[derive(Serialize, Debug)]
+struct BatchOutputLine {
+    id: String,
+    custom_id: String,
+    response: Option<BatchOutputResponse>,
+    error: Option<BatchOutputError>,
+}
+
+#[derive(Serialize, Debug)]
+struct BatchOutputResponse {
+    status_code: u16,
+    request_id: String,
+    body: serde_json::Value,
+}
+
+#[derive(Serialize, Debug)]
+struct BatchOutputError {
+    code: String,
+    message: String,
+}
+
+/// Files and batches known to this HTTP service. Files live on disk in `dir`, the metadata
+/// is in memory only.
+pub struct BatchState {
+    dir: PathBuf,
+    deployment: Arc<DeploymentState>,
+    /// Lines of a batch run against the engine at once
+    concurrency: usize,
+    files: Mutex<HashMap<String, (FileObject, Owner)>>,
+    batches: Mutex<HashMap<String, BatchEntry>>,
+}
+
+impl BatchState {
+    /// `concurrency` is how many lines of a batch run at once, 8 if None
+    pub fn new(
+        deployment: Arc<DeploymentState>,
+        dir: PathBuf,
+        concurrency: Option<usize>,
+    ) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(BatchState {
+            dir,
+            deployment,
+            concurrency: concurrency.unwrap_or(DEFAULT_BATCH_CONCURRENCY).max(1),
+            files: Mutex::new(HashMap::new()),
+            batches: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn file_path(&self, file_id: &str) -> PathBuf {
+        self.dir.join(format!("{file_id}.jsonl"))
+    }
+
+    /// Apply `f` to batch `batch_id`. False if it was deleted.
+    fn update_batch(&self, batch_id: &str, f: impl FnOnce(&mut Batch)) -> bool {
+        match self.batches.lock().unwrap().get_mut(batch_id) {
+            Some(entry) => {
+                f(&mut entry.batch);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// File `file_id` if `owner` created it, else 404 as if it didn't exist
+    fn file(&self, file_id: &str, owner: &Owner) -> HttpResult<FileObject> {
+        match self.files.lock().unwrap().get(file_id) {
+            Some((file, file_owner)) if file_owner == owner => Ok(file.clone()),
+            _ => Err(ErrorResponse::not_found(&format!(
+                "No such file: {file_id}"
+            ))),
+        }
+    }
+
+    /// Batch `batch_id` if `owner` created it, else 404 as if it didn't exist
+    fn batch(&self, batch_id: &str, owner: &Owner) -> HttpResult<Batch> {
+        match self.batches.lock().unwrap().get(batch_id) {
+            Some(entry) if entry.owner == *owner => Ok(entry.batch.clone()),
+            _ => Err(ErrorResponse::not_found(&format!(
+                "No such batch: {batch_id}"
+            ))),
+        }
+    }
+
+    fn add_file(&self, filename: String, purpose: String, bytes: u64, owner: Owner) -> FileObject {
+        let file = FileObject {
+            id: format!("file-{}", uuid::Uuid::new_v4().simple()),
+            object: "file".to_string(),
+            bytes,
+            created_at: now(),
+            filename,
+            purpose,
+        };
+        self.files
+            .lock()
+            .unwrap()
+            .insert(file.id.clone(), (file.clone(), owner));
+        file
+    }
+
+    /// Forget file `file_id` and delete it from disk
+    async fn remove_file(&self, file_id: &str) {
+        self.files.lock().unwrap().remove(file_id);
+        match tokio::fs::remove_file(self.file_path(file_id)).await {
+            Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => tracing::warn!(file_id, %err, "Failed deleting batch file"),
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// `POST /v1/files`, multipart with a `file` and a `purpose` field
+async fn upload_file(
+    State(state): State<Arc<BatchState>>,
+    access: Access,
+    mut multipart: Multipart,
+) -> HttpResult<Response> {
+    let mut purpose = "batch".to_string();
+    let mut contents = None;
+    let mut filename = "input.jsonl".to_string();
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| ErrorResponse::bad_request(&format!("Invalid multipart body: {e}")))?
+    {
+        match field.name() {
+            Some("purpose") => {
+                purpose = field
+                    .text()
+                    .await
+                    .map_err(|e| ErrorResponse::bad_request(&e.to_string()))?;
+            }
+            Some("file") => {
+                if let Some(name) = field.file_name() {
+                    filename = name.to_string();
+                }
+                contents = Some(
+                    field
+                        .bytes()
+                        .await
+                        .map_err(|e| ErrorResponse::bad_request(&e.to_string()))?,
+                );
+            }
+            _ => {}
+        }
+    }
+    let Some(contents) = contents else {
+        return Err(ErrorResponse::bad_request("Missing 'file' field"));
+    };
+
+    let file = state.add_file(filename, purpose, contents.len() as u64, Owner::of(&access));
+    tokio::fs::write(state.file_path(&file.id), &contents)
+        .await
+        .map_err(|e| ErrorResponse::internal_server_error(&format!("Saving upload: {e}")))?;
+    Ok(Json(file).into_response())
+}
+
+/// `GET /v1/files/{file_id}`
+async fn retrieve_file(
+    State(state): State<Arc<BatchState>>,
+    access: Access,
+    Path(file_id): Path<String>,
+) -> HttpResult<Response> {
+    let file = state.file(&file_id, &Owner::of(&access))?;
+    Ok(Json(file).into_response())
+}
+
+/// `DELETE /v1/files/{file_id}`
+async fn delete_file(
+    State(state): State<Arc<BatchState>>,
+    access: Access,
+    Path(file_id): Path<String>,
+) -> HttpResult<Response> {
+    state.file(&file_id, &Owner::of(&access))?;
+    state.remove_file(&file_id).await;
+    Ok(Json(Deleted {
+        id: file_id,
+        object: "file",
+        deleted: true,
+    })
+    .into_response())
+}
+
+/// `GET /v1/files/{file_id}/content`
+async fn file_content(
+    State(state): State<Arc<BatchState>>,
+    access: Access,
+    Path(file_id): Path<String>,
+) -> HttpResult<Response> {
+    state.file(&file_id, &Owner::of(&access))?;
+    let contents = tokio::fs::read(state.file_path(&file_id))
+        .await
+        .map_err(|e| ErrorResponse::internal_server_error(&format!("Reading file: {e}")))?;
+    Ok(([(header::CONTENT_TYPE, "application/jsonl")], contents).into_response())
+}
+
+/// `POST /v1/batches`
+async fn create_batch(
+    State(state): State<Arc<BatchState>>,
+    access: Access,
+    meter: TokenMeter,
+    Json(request): Json<CreateBatchRequest>,
+) -> HttpResult<Response> {
+    if request.endpoint != CHAT_COMPLETIONS_URL {
+        return Err(ErrorResponse::bad_request(&format!(
+            "Unsupported batch endpoint '{}'. Only {CHAT_COMPLETIONS_URL} is supported.",
+            request.endpoint
+        )));
+    }
+    let owner = Owner::of(&access);
+    state.file(&request.input_file_id, &owner)?;
+
+    let batch = Batch {
+        id: format!("batch_{}", uuid::Uuid::new_v4().simple()),
+        object: "batch".to_string(),
+        endpoint: request.endpoint,
+        input_file_id: request.input_file_id,
+        completion_window: request.completion_window,
+        status: BatchStatus::Validating,
+        output_file_id: None,
+        error_file_id: None,
+        created_at: now(),
+        in_progress_at: None,
+        completed_at: None,
+        failed_at: None,
+        cancelled_at: None,
+        request_counts: BatchRequestCounts::default(),
+        metadata: request.metadata,
+    };
+    let cancel_token = CancellationToken::new();
+    state.batches.lock().unwrap().insert(
+        batch.id.clone(),
+        BatchEntry {
+            batch: batch.clone(),
+            cancel_token: cancel_token.clone(),
+            owner,
+        },
+    );
+
+    let batch_id = batch.id.clone();
+    let task_state = state.clone();
+    tokio::spawn(async move {
+        let executed =
+            execute_batch(task_state.clone(), &batch_id, access, meter, cancel_token).await;
+        if let Err(err) = executed {
+            tracing::error!(batch_id, %err, "Batch failed");
+            task_state.update_batch(&batch_id, |b| {
+                b.status = BatchStatus::Failed;
+                b.failed_at = Some(now());
+            });
+        }
+    });
+
+    Ok(Json(batch).into_response())
+}
+
+/// `GET /v1/batches/{batch_id}`
+async fn retrieve_batch(
+    State(state): State<Arc<BatchState>>,
+    access: Access,
+    Path(batch_id): Path<String>,
+) -> HttpResult<Response> {
+    let batch = state.batch(&batch_id, &Owner::of(&access))?;
+    Ok(Json(batch).into_response())
+}
+
+/// `POST /v1/batches/{batch_id}/cancel`
+async fn cancel_batch(
+    State(state): State<Arc<BatchState>>,
+    access: Access,
+    Path(batch_id): Path<String>,
+) -> HttpResult<Response> {
+    let owner = Owner::of(&access);
+    let mut batches = state.batches.lock().unwrap();
+    let Some(entry) = batches
+        .get_mut(&batch_id)
+        .filter(|entry| entry.owner == owner)
+    else {
+        return Err(ErrorResponse::not_found(&format!(
+            "No such batch: {batch_id}"
+        )));
+    };
+    if matches!(
+        entry.batch.status,
+        BatchStatus::Validating | BatchStatus::InProgress
+    ) {
+        entry.batch.status = BatchStatus::Cancelling;
+        entry.cancel_token.cancel();
+    }
+    Ok(Json(entry.batch.clone()).into_response())
+}
+
+/// `DELETE /v1/batches/{batch_id}`: stop the batch if it is running, forget it and delete its
+/// output and error files. Its input file stays, it may be used again.
+async fn delete_batch(
+    State(state): State<Arc<BatchState>>,
+    access: Access,
+    Path(batch_id): Path<String>,
+) -> HttpResult<Response> {
+    state.batch(&batch_id, &Owner::of(&access))?;
+    let Some(entry) = state.batches.lock().unwrap().remove(&batch_id) else {
+        return Err(ErrorResponse::not_found(&format!(
+            "No such batch: {batch_id}"
+        )));
+    };
+    // A running batch deletes the files it wrote once it stops, see execute_batch
+    entry.cancel_token.cancel();
+    for file_id in [entry.batch.output_file_id, entry.batch.error_file_id]
+        .into_iter()
+        .flatten()
+    {
+        state.remove_file(&file_id).await;
+    }
+    Ok(Json(Deleted {
+        id: batch_id,
+        object: "batch",
+        deleted: true,
+    })
+    .into_response())
+}
+
+/// Run every line of the input file through the engine and write the output file.
+async fn execute_batch(
+    state: Arc<BatchState>,
+    batch_id: &str,
+    access: Access,
+    meter: TokenMeter,
+    cancel_token: CancellationToken,
+) -> anyhow::Result<()> {
+    let (input_file_id, owner) = match state.batches.lock().unwrap().get(batch_id) {
+        Some(entry) => (entry.batch.input_file_id.clone(), entry.owner.clone()),
+        None => anyhow::bail!("Unknown batch {batch_id}"),
+    };
+    let input_path = state.file_path(&input_file_id);
+
+    // Validate and count the lines before starting
+    let mut lines = vec![];
+    let f = tokio::fs::File::open(&input_path).await?;
+    let mut reader = tokio::io::BufReader::new(f).lines();
+    while let Some(line) = reader.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: BatchInputLine = serde_json::from_str(&line)
+            .map_err(|err| anyhow::anyhow!("Invalid batch line '{line}': {err}"))?;
+        lines.push(entry);
+    }
+    state.update_batch(batch_id, |b| {
+        b.status = BatchStatus::InProgress;
+        b.in_progress_at = Some(now());
+        b.request_counts.total = lines.len() as u64;
+    });
+
+    let output = state.add_file(
+        format!("{batch_id}_output.jsonl"),
+        "batch_output".to_string(),
+        0,
+        owner.clone(),
+    );
+    let output_path = state.file_path(&output.id);
+    let mut out = tokio::fs::File::create(&output_path).await?;
+    // Created with the first failed line
+    let mut errors: Option<(FileObject, tokio::fs::File)> = None;
+
+    let deployment = state.deployment.clone();
+    let access = &access;
+    let mut results = futures::stream::iter(lines)
+        .map(|line| {
+            let deployment = deployment.clone();
+            let meter = meter.fork();
+            async move { evaluate_line(&deployment, access, meter, line).await }
+        })
+        .buffered(state.concurrency);
+
+    let mut bytes = 0;
+    let mut error_bytes = 0;
+    loop {
+        let result = tokio::select! {
+            _ = cancel_token.cancelled() => {
+                break;
+            }
+            maybe_result = results.next() => {
+                match maybe_result {
+                    Some(result) => result,
+                    None => break,
+                }
+            }
+        };
+        let is_ok = result.error.is_none();
+        let mut s = serde_json::to_string(&result)?;
+        s.push('\n');
+        if is_ok {
+            out.write_all(s.as_bytes()).await?;
+            bytes += s.len() as u64;
+        } else {
+            let (_, error_out) = match &mut errors {
+                Some(errors) => errors,
+                None => {
+                    let file = state.add_file(
+                        format!("{batch_id}_error.jsonl"),
+                        "batch_output".to_string(),
+                        0,
+                        owner.clone(),
+                    );
+                    let error_out = tokio::fs::File::create(state.file_path(&file.id)).await?;
+                    errors.insert((file, error_out))
+                }
+            };
+            error_out.write_all(s.as_bytes()).await?;
+            error_bytes += s.len() as u64;
+        }
+        state.update_batch(batch_id, |b| {
+            if is_ok {
+                b.request_counts.completed += 1;
+            } else {
+                b.request_counts.failed += 1;
+            }
+        });
+    }
+    out.flush().await?;
+    let error_file_id = match errors {
+        Some((file, mut error_out)) => {
+            error_out.flush().await?;
+            Some(file.id)
+        }
+        None => None,
+    };
+
+    {
+        let mut files = state.files.lock().unwrap();
+        if let Some((f, _)) = files.get_mut(&output.id) {
+            f.bytes = bytes;
+        }
+        if let Some((f, _)) = error_file_id.as_ref().and_then(|id| files.get_mut(id)) {
+            f.bytes = error_bytes;
+        }
+    }
+    let cancelled = cancel_token.is_cancelled();
+    let kept = state.update_batch(batch_id, |b| {
+        b.output_file_id = Some(output.id.clone());
+        b.error_file_id = error_file_id.clone();
+        if cancelled {
+            b.status = BatchStatus::Cancelled;
+            b.cancelled_at = Some(now());
+        } else {
+            b.status = BatchStatus::Completed;
+            b.completed_at = Some(now());
+        }
+    });
+    if !kept {
+        // Deleted while it ran
+        state.remove_file(&output.id).await;
+        if let Some(file_id) = &error_file_id {
+            state.remove_file(file_id).await;
+        }
+    }
+    Ok(())
+}
+
+/// Run a single batch line through the chat completions engine for its model, if the batch's
+/// creator may use it. Its tokens are charged to `meter`.
+async fn evaluate_line(
+    deployment: &DeploymentState,
+    access: &Access,
+    mut meter: TokenMeter,
+    line: BatchInputLine,
+) -> BatchOutputLine {
+    let request_id = uuid::Uuid::new_v4().to_string();
+    let id = format!("batch_req_{}", uuid::Uuid::new_v4().simple());
+    let custom_id = line.custom_id.clone();
+    let as_error = |code: &str, message: String| BatchOutputLine {
+        id: id.clone(),
+        custom_id: custom_id.clone(),
+        response: None,
+        error: Some(BatchOutputError {
+            code: code.to_string(),
+            message,
+        }),
+    };
+
+    if line.url != CHAT_COMPLETIONS_URL || line.method.as_deref().is_some_and(|m| m != "POST") {
+        return as_error(
+            "invalid_url",
+            format!("Unsupported batch line url '{}'", line.url),
+        );
+    }
+    let mut request: NvCreateChatCompletionRequest = match serde_json::from_value(line.body) {
+        Ok(r) => r,
+        Err(err) => return as_error("invalid_request", err.to_string()),
+    };
+    if let Some(refusal) = access.refusal(&request.inner.model) {
+        return as_error(ErrorKind::Forbidden.as_str(), refusal);
+    }
+    // The engines always stream, we fold it back into a single response
+    request.inner.stream = Some(true);
+    let content_filter = deployment.content_filter.lock().unwrap().clone();
+    if let Some(filter) = &content_filter {
+        if let Err(err) = filter.check_messages(&mut request.inner.messages).await {
+            return as_error(err.kind.as_str(), err.message);
+        }
+    }
+
+    let engine = match deployment.get_chat_completions_engine(&request.inner.model) {
+        Ok(engine) => engine,
+        Err(err) => return as_error("model_not_found", err.to_string()),
+    };
+    let mut inflight =
+        deployment.create_inflight_guard(&request.inner.model, Endpoint::ChatCompletions, false);
+    let stream = match engine
+        .generate(Context::with_id(request, request_id.clone()))
+        .await
+    {
+        Ok(stream) => stream,
+        Err(err) => return as_error("engine_error", err.to_string()),
+    };
+    let ctx = stream.context();
+    let stream = match content_filter {
+        Some(filter) => with_content_filter(stream, filter, ctx).left_stream(),
+        None => stream.right_stream(),
+    };
+    let response = NvCreateChatCompletionResponse::from_annotated_stream(Box::pin(stream)).await;
+    let response = match response {
+        Ok(response) => response,
+        Err(err) => return as_error("engine_error", err),
+    };
+    if let Some(usage) = &response.inner.usage {
+        meter.observe(usage.prompt_tokens + usage.completion_tokens);
+    }
+    let body = match serde_json::to_value(&response) {
+        Ok(body) => body,
+        Err(err) => return as_error("internal_error", err.to_string()),
+    };
+    inflight.mark_ok();
+    BatchOutputLine {
+        id,
+        custom_id: line.custom_id,
+        response: Some(BatchOutputResponse {
+            status_code: StatusCode::OK.as_u16(),
+            request_id,
+            body,
+        }),
+        error: None,
+    }
+}
+
+/// Create an Axum [`Router`] for the OpenAI Files and Batches endpoints
+pub fn batches_router(state: Arc<BatchState>) -> (Vec<RouteDoc>, Router) {
+    let docs = vec![
+        RouteDoc::new(axum::http::Method::POST, "/v1/files").multipart(),
+        RouteDoc::new(axum::http::Method::GET, "/v1/files/{file_id}"),
+        RouteDoc::new(axum::http::Method::DELETE, "/v1/files/{file_id}"),
+        RouteDoc::new(axum::http::Method::GET, "/v1/files/{file_id}/content"),
+        RouteDoc::new(axum::http::Method::POST, "/v1/batches"),
+        RouteDoc::new(axum::http::Method::GET, "/v1/batches/{batch_id}"),
+        RouteDoc::new(axum::http::Method::DELETE, "/v1/batches/{batch_id}"),
+        RouteDoc::new(axum::http::Method::POST, "/v1/batches/{batch_id}/cancel"),
+    ];
+    let router = Router::new()
+        .route("/v1/files", post(upload_file))
+        .route(
+            "/v1/files/{file_id}",
+            get(retrieve_file).delete(delete_file),
+        )
+        .route("/v1/files/{file_id}/content", get(file_content))
+        .route("/v1/batches", post(create_batch))
+        .route(
+            "/v1/batches/{batch_id}",
+            get(retrieve_batch).delete(delete_batch),
+        )
+        .route("/v1/batches/{batch_id}/cancel", post(cancel_batch))
+        // Leave room for the multipart framing and the purpose field
+        .layer(DefaultBodyLimit::max(MAX_FILE_BYTES + 64 * 1024))
+        .with_state(state);
+    (docs, router)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batch_input_line() {
+        let line = r#"{"custom_id": "request-1", "method": "POST", "url": "/v1/chat/completions", "body": {"model": "llama", "messages": [{"role": "user", "content": "Hello"}]}}"#;
+        let entry: BatchInputLine = serde_json::from_str(line).unwrap();
+        assert_eq!(entry.custom_id, "request-1");
+        assert_eq!(entry.url, CHAT_COMPLETIONS_URL);
+        let request: NvCreateChatCompletionRequest = serde_json::from_value(entry.body).unwrap();
+        assert_eq!(request.inner.model, "llama");
+    }
+
+    #[test]
+    fn test_batch_status_serialization() {
+        assert_eq!(
+            serde_json::to_string(&BatchStatus::InProgress).unwrap(),
+            r#""in_progress""#
+        );
+        assert_eq!(
+            serde_json::from_str::<BatchStatus>(r#""cancelling""#).unwrap(),
+            BatchStatus::Cancelling
+        );
+    }
+}
@@ -0,0 +1,167 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Sending the events of a stream in bursts
+//!
+//! A fast model streams hundreds of chunks a second. Each is an SSE event, a write to the
+//! socket and, behind a proxy, a proxied write of its own. Coalescing holds events back for a
+//! few milliseconds and then sends all those that arrived together, in one write. Clients get
+//! the same events, a few at a time. The first event of a stream is never held back, so the
+//! time to first token doesn't change.
+//!
+//! The server's setting applies to every streaming request. A request can change it with
+//! `nvext.stream_coalesce_ms`, `0` to get each event at once, and `nvext.stream_coalesce_max`.
+
+use std::time::Duration;
+
+use async_stream::stream;
+use futures::{Stream, StreamExt};
+
+use crate::protocols::openai::nvext::NvExt;
+
+/// Most events sent together when only the interval is given
+pub const DEFAULT_MAX_EVENTS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamCoalescing {
+    /// Longest an event waits for others
+    pub interval: Duration,
+    /// Most events sent together, at least 1
+    pub max_events: usize,
+}
+
+impl StreamCoalescing {
+    pub fn new(interval: Duration) -> Self {
+        StreamCoalescing {
+            interval,
+            max_events: DEFAULT_MAX_EVENTS,
+        }
+    }
+
+    /// The coalescing of a request: the server's, changed by the request's. None to send each
+    /// event as it comes.
+    pub fn for_request(server: Option<StreamCoalescing>, nvext: Option<&NvExt>) -> Option<Self> {
+        let interval = nvext
+            .and_then(|ext| ext.stream_coalesce_ms)
+            .map(Duration::from_millis)
+            .or(server.map(|c| c.interval))?;
+        if interval.is_zero() {
+            return None;
+        }
+        let max_events = nvext
+            .and_then(|ext| ext.stream_coalesce_max)
+            .map(|max| max as usize)
+            .or(server.map(|c| c.max_events))
+            .unwrap_or(DEFAULT_MAX_EVENTS)
+            .max(1);
+        Some(StreamCoalescing {
+            interval,
+            max_events,
+        })
+    }
+}
+
+/// Hold the events of `stream` back until `interval` after the first one waiting, or until
+/// `max_events` are waiting, then hand them on one after the other without a pause, which the
+/// server writes out together.
+pub fn coalesce<T, S>(stream: S, coalescing: StreamCoalescing) -> impl Stream<Item = T> + Send
+where
+    T: Send + 'static,
+    S: Stream<Item = T> + Send + 'static,
+{
+    stream! {
+        let mut stream = Box::pin(stream);
+        let Some(first) = stream.next().await else {
+            return;
+        };
+        yield first;
+        let bursts = tokio_stream::StreamExt::chunks_timeout(
+            stream,
+            coalescing.max_events,
+            coalescing.interval,
+        );
+        let mut bursts = std::pin::pin!(bursts);
+        while let Some(burst) = bursts.next().await {
+            for event in burst {
+                yield event;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn test_for_request() {
+        let server = Some(StreamCoalescing::new(Duration::from_millis(20)));
+        assert_eq!(StreamCoalescing::for_request(server, None), server);
+        assert_eq!(StreamCoalescing::for_request(None, None), None);
+
+        let off = NvExt::builder().stream_coalesce_ms(0).build().unwrap();
+        assert_eq!(StreamCoalescing::for_request(server, Some(&off)), None);
+
+        let own = NvExt::builder()
+            .stream_coalesce_ms(5)
+            .stream_coalesce_max(4)
+            .build()
+            .unwrap();
+        assert_eq!(
+            StreamCoalescing::for_request(None, Some(&own)),
+            Some(StreamCoalescing {
+                interval: Duration::from_millis(5),
+                max_events: 4
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_coalesce() {
+        let (tx, rx) = tokio::sync::mpsc::channel(16);
+        let coalescing = StreamCoalescing {
+            interval: Duration::from_millis(200),
+            max_events: 3,
+        };
+        let mut events = Box::pin(coalesce(
+            tokio_stream::wrappers::ReceiverStream::new(rx),
+            coalescing,
+        ));
+
+        let start = Instant::now();
+        tx.send(0).await.unwrap();
+        // The first straight away
+        assert_eq!(events.next().await, Some(0));
+        assert!(start.elapsed() < coalescing.interval);
+
+        // A full burst doesn't wait for the interval either
+        for i in 1..=3 {
+            tx.send(i).await.unwrap();
+        }
+        for i in 1..=3 {
+            assert_eq!(events.next().await, Some(i));
+        }
+        assert!(start.elapsed() < coalescing.interval);
+
+        // The rest when the interval is up
+        tx.send(4).await.unwrap();
+        assert_eq!(events.next().await, Some(4));
+        assert!(start.elapsed() >= coalescing.interval);
+
+        drop(tx);
+        assert_eq!(events.next().await, None);
+    }
+}
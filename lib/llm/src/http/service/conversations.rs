@@ -0,0 +1,318 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Conversations the server keeps, for clients that send only the new messages of a chat.
+//!
+//! A chat request with `nvext.session_id` continues the conversation of that id: its earlier
+//! messages, and the replies to them, go before the messages of the request. Once the reply
+//! finishes, the request's messages and the reply are added to the conversation. A reply the
+//! client disconnects from, or that fails, is not.
+//!
+//! Conversations are scoped to the caller's API key and live for [`ConversationConfig::ttl`]
+//! after their last turn, in memory or in a [`ConversationStore`] the frontends share.
+//!
+//! The session id is also the request's routing session, so with sticky sessions every turn
+//! goes to the worker that already holds the conversation's prefix in its KV cache. Only the
+//! messages are stored here, not KV blocks.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_openai::types::{
+    ChatCompletionRequestAssistantMessage, ChatCompletionRequestAssistantMessageContent,
+    ChatCompletionRequestMessage,
+};
+use async_trait::async_trait;
+
+use crate::protocols::openai::chat_completions::{
+    NvCreateChatCompletionRequest, NvCreateChatCompletionResponse,
+    NvCreateChatCompletionStreamResponse,
+};
+use crate::types::Annotated;
+
+#[derive(Debug, Clone)]
+pub struct ConversationConfig {
+    /// Conversations without a turn for this long are dropped
+    pub ttl: Duration,
+
+    /// Most conversations to keep in memory. The one closest to expiring makes room.
+    pub max_conversations: usize,
+}
+
+impl Default for ConversationConfig {
+    fn default() -> Self {
+        ConversationConfig {
+            ttl: Duration::from_secs(3600),
+            max_conversations: 10_000,
+        }
+    }
+}
+
+/// Where conversations are kept, shared by several frontends
+#[async_trait]
+pub trait ConversationStore: Send + Sync {
+    async fn load(&self, key: &str) -> anyhow::Result<Option<Vec<ChatCompletionRequestMessage>>>;
+
+    /// Keep `messages` for `ttl`, replacing the conversation's earlier messages
+    async fn save(
+        &self,
+        key: &str,
+        messages: &[ChatCompletionRequestMessage],
+        ttl: Duration,
+    ) -> anyhow::Result<()>;
+}
+
+/// Conversations of this frontend only
+pub struct MemoryConversationStore {
+    capacity: usize,
+    entries: Mutex<HashMap<String, (Vec<ChatCompletionRequestMessage>, Instant)>>,
+}
+
+impl MemoryConversationStore {
+    pub fn new(capacity: usize) -> Self {
+        MemoryConversationStore {
+            capacity,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl ConversationStore for MemoryConversationStore {
+    async fn load(&self, key: &str) -> anyhow::Result<Option<Vec<ChatCompletionRequestMessage>>> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some((_, expires)) if *expires <= Instant::now() => {
+                entries.remove(key);
+                Ok(None)
+            }
+            Some((messages, _)) => Ok(Some(messages.clone())),
+            None => Ok(None),
+        }
+    }
+
+    async fn save(
+        &self,
+        key: &str,
+        messages: &[ChatCompletionRequestMessage],
+        ttl: Duration,
+    ) -> anyhow::Result<()> {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        if !entries.contains_key(key) && entries.len() >= self.capacity {
+            entries.retain(|_, (_, expires)| *expires > now);
+            if entries.len() >= self.capacity {
+                let soonest = entries
+                    .iter()
+                    .min_by_key(|(_, (_, expires))| *expires)
+                    .map(|(key, _)| key.clone());
+                if let Some(soonest) = soonest {
+                    entries.remove(&soonest);
+                }
+            }
+        }
+        entries.insert(key.to_string(), (messages.to_vec(), now + ttl));
+        Ok(())
+    }
+}
+
+pub struct Conversations {
+    config: ConversationConfig,
+    store: Arc<dyn ConversationStore>,
+}
+
+impl Conversations {
+    /// Keep conversations in `store`, or in memory if None
+    pub fn new(config: ConversationConfig, store: Option<Arc<dyn ConversationStore>>) -> Self {
+        let store = store
+            .unwrap_or_else(|| Arc::new(MemoryConversationStore::new(config.max_conversations)));
+        Conversations { config, store }
+    }
+
+    /// Put the earlier messages of the caller's conversation `session_id` before the messages
+    /// of `request`. The returned [`Turn`] adds the reply to the conversation.
+    pub async fn continue_conversation(
+        self: &Arc<Self>,
+        client: &str,
+        session_id: &str,
+        request: &mut NvCreateChatCompletionRequest,
+    ) -> anyhow::Result<Turn> {
+        let key = format!("{client}\n{session_id}");
+        let mut messages = self.store.load(&key).await?.unwrap_or_default();
+        messages.append(&mut request.inner.messages);
+        request.inner.messages = messages.clone();
+        Ok(Turn {
+            conversations: self.clone(),
+            key,
+            messages,
+            chunks: Vec::new(),
+            finished: false,
+        })
+    }
+}
+
+/// One request of a conversation. Watches the reply stream, and when dropped after the first
+/// choice finished, adds the request's messages and the reply to the conversation.
+pub struct Turn {
+    conversations: Arc<Conversations>,
+    key: String,
+    messages: Vec<ChatCompletionRequestMessage>,
+    chunks: Vec<Annotated<NvCreateChatCompletionStreamResponse>>,
+    finished: bool,
+}
+
+impl Turn {
+    pub fn observe(&mut self, response: &Annotated<NvCreateChatCompletionStreamResponse>) {
+        if let Some(data) = &response.data {
+            self.finished |= data
+                .inner
+                .choices
+                .iter()
+                .any(|choice| choice.index == 0 && choice.finish_reason.is_some());
+        }
+        self.chunks.push(response.clone());
+    }
+
+    async fn save(
+        conversations: Arc<Conversations>,
+        key: String,
+        mut messages: Vec<ChatCompletionRequestMessage>,
+        chunks: Vec<Annotated<NvCreateChatCompletionStreamResponse>>,
+    ) {
+        let stream = Box::pin(futures::stream::iter(chunks));
+        let reply = match NvCreateChatCompletionResponse::from_annotated_stream(stream).await {
+            Ok(reply) => reply,
+            Err(err) => {
+                tracing::debug!(%err, "Not adding a failed reply to the conversation");
+                return;
+            }
+        };
+        let Some(choice) = reply
+            .inner
+            .choices
+            .into_iter()
+            .find(|choice| choice.index == 0)
+        else {
+            return;
+        };
+        messages.push(ChatCompletionRequestMessage::Assistant(
+            ChatCompletionRequestAssistantMessage {
+                content: choice
+                    .message
+                    .content
+                    .map(ChatCompletionRequestAssistantMessageContent::Text),
+                refusal: choice.message.refusal,
+                tool_calls: choice.message.tool_calls,
+                ..Default::default()
+            },
+        ));
+        let ttl = conversations.config.ttl;
+        if let Err(err) = conversations.store.save(&key, &messages, ttl).await {
+            tracing::warn!(%err, "Failed to save a conversation");
+        }
+    }
+}
+
+impl Drop for Turn {
+    fn drop(&mut self) {
+        if !self.finished {
+            return;
+        }
+        tokio::spawn(Turn::save(
+            self.conversations.clone(),
+            std::mem::take(&mut self.key),
+            std::mem::take(&mut self.messages),
+            std::mem::take(&mut self.chunks),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(content: &str) -> NvCreateChatCompletionRequest {
+        serde_json::from_value(serde_json::json!({
+            "model": "m",
+            "messages": [{"role": "user", "content": content}],
+        }))
+        .unwrap()
+    }
+
+    fn chunk(
+        content: &str,
+        finish_reason: Option<&str>,
+    ) -> Annotated<NvCreateChatCompletionStreamResponse> {
+        Annotated::from_data(
+            serde_json::from_value(serde_json::json!({
+                "id": "1",
+                "object": "chat.completion.chunk",
+                "created": 0,
+                "model": "m",
+                "choices": [{
+                    "index": 0,
+                    "delta": {"role": "assistant", "content": content},
+                    "finish_reason": finish_reason,
+                }],
+            }))
+            .unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_conversation() {
+        let conversations = Arc::new(Conversations::new(ConversationConfig::default(), None));
+
+        let mut first = request("What is the capital of South Africa?");
+        let mut turn = conversations
+            .continue_conversation("key", "s", &mut first)
+            .await
+            .unwrap();
+        turn.observe(&chunk("Pretoria", None));
+        turn.observe(&chunk(", among others.", Some("stop")));
+        drop(turn);
+
+        // a reply the client disconnected from is not kept
+        let mut abandoned = request("And of Bolivia?");
+        let mut turn = conversations
+            .continue_conversation("key", "s", &mut abandoned)
+            .await
+            .unwrap();
+        turn.observe(&chunk("Sucre", None));
+        drop(turn);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let mut second = request("And of Bolivia?");
+        conversations
+            .continue_conversation("key", "s", &mut second)
+            .await
+            .unwrap();
+        let messages = serde_json::to_value(&second.inner.messages).unwrap();
+        assert_eq!(messages.as_array().unwrap().len(), 3);
+        assert_eq!(messages[1]["content"], "Pretoria, among others.");
+        assert_eq!(messages[2]["content"], "And of Bolivia?");
+
+        // other API keys have their own conversations
+        let mut other = request("Hello");
+        conversations
+            .continue_conversation("other", "s", &mut other)
+            .await
+            .unwrap();
+        assert_eq!(other.inner.messages.len(), 1);
+    }
+}
@@ -0,0 +1,133 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! CORS, for browser apps calling the API from pages of another origin
+//!
+//! The layer sits outside of authentication: browsers send preflight `OPTIONS` requests
+//! without the `Authorization` header, and they get their answer before it is checked.
+
+use anyhow::Result;
+use axum::http::{header, HeaderName, HeaderValue, Method};
+use dynamo_runtime::pipeline::network::egress::routing_hints::ROUTING_HINTS_HEADER;
+use tower_http::cors::{AllowHeaders, AllowOrigin, CorsLayer};
+
+use super::admission::{PRIORITY_HEADER, QUEUE_DEPTH_HEADER};
+use super::openai::REQUEST_ID_HEADER;
+
+/// How long browsers may cache the answer to a preflight request
+const MAX_AGE: std::time::Duration = std::time::Duration::from_secs(600);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CorsConfig {
+    /// Origins pages may call the API from, such as `https://chat.example.com`. `*` for any.
+    pub origins: Vec<String>,
+
+    /// Request headers pages may send. If empty, `Authorization`, `Content-Type`,
+    /// `traceparent` and the Dynamo request headers.
+    pub headers: Vec<String>,
+
+    /// Let pages send cookies and `Authorization` with their requests. Not with any origin.
+    pub credentials: bool,
+}
+
+impl CorsConfig {
+    pub fn layer(&self) -> Result<CorsLayer> {
+        if self.origins.is_empty() {
+            anyhow::bail!("CORS needs at least one allowed origin");
+        }
+        let any_origin = self.origins.iter().any(|origin| origin == "*");
+        if any_origin && self.credentials {
+            anyhow::bail!("CORS credentials cannot be allowed for any origin, list the origins");
+        }
+
+        let origin = if any_origin {
+            AllowOrigin::any()
+        } else {
+            let origins = self
+                .origins
+                .iter()
+                .map(|origin| {
+                    HeaderValue::from_str(origin.trim_end_matches('/'))
+                        .map_err(|_| anyhow::anyhow!("Invalid CORS origin '{origin}'"))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            AllowOrigin::list(origins)
+        };
+
+        let headers = if self.headers.is_empty() {
+            vec![
+                header::AUTHORIZATION,
+                header::CONTENT_TYPE,
+                HeaderName::from_static("traceparent"),
+                HeaderName::from_static(PRIORITY_HEADER),
+                HeaderName::from_static(ROUTING_HINTS_HEADER),
+            ]
+        } else {
+            self.headers
+                .iter()
+                .map(|name| {
+                    HeaderName::try_from(name.as_str())
+                        .map_err(|_| anyhow::anyhow!("Invalid CORS header '{name}'"))
+                })
+                .collect::<Result<Vec<_>>>()?
+        };
+
+        Ok(CorsLayer::new()
+            .allow_origin(origin)
+            .allow_methods([Method::GET, Method::POST, Method::DELETE])
+            .allow_headers(AllowHeaders::list(headers))
+            .allow_credentials(self.credentials)
+            .expose_headers([
+                HeaderName::from_static(REQUEST_ID_HEADER),
+                HeaderName::from_static(QUEUE_DEPTH_HEADER),
+                header::RETRY_AFTER,
+            ])
+            .max_age(MAX_AGE))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layer() {
+        let config = CorsConfig {
+            origins: vec!["https://chat.example.com/".to_string()],
+            credentials: true,
+            ..Default::default()
+        };
+        assert!(config.layer().is_ok());
+
+        let any = CorsConfig {
+            origins: vec!["*".to_string()],
+            ..Default::default()
+        };
+        assert!(any.layer().is_ok());
+        let any_with_credentials = CorsConfig {
+            credentials: true,
+            ..any
+        };
+        assert!(any_with_credentials.layer().is_err());
+
+        assert!(CorsConfig::default().layer().is_err());
+        let bad_header = CorsConfig {
+            origins: vec!["*".to_string()],
+            headers: vec!["not a header".to_string()],
+            credentials: false,
+        };
+        assert!(bad_header.layer().is_err());
+    }
+}
@@ -13,17 +13,18 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 
 use anyhow::Context as _;
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc::Receiver;
 
 use dynamo_runtime::{
-    component::{self, ComponentEndpointInfo},
+    component::{self, Client, ComponentEndpointInfo},
     pipeline::{
-        network::egress::push_router::PushRouter, ManyOut, Operator, RouterMode, SegmentSource,
-        ServiceBackend, SingleIn, Source,
+        network::egress::push_router::PushRouter, Data, ManyOut, Operator, RouterMode,
+        SegmentSource, ServiceBackend, SharedRouterMode, SingleIn, Source,
     },
     protocols::{self, annotated::Annotated},
     slug::Slug,
@@ -36,8 +37,14 @@ use crate::protocols::openai::chat_completions::{
     NvCreateChatCompletionRequest, NvCreateChatCompletionStreamResponse,
 };
 use crate::protocols::openai::completions::{CompletionRequest, CompletionResponse};
+use crate::protocols::openai::embeddings::{NvCreateEmbeddingRequest, NvCreateEmbeddingResponse};
+use crate::protocols::openai::transcriptions::{
+    NvCreateTranscriptionRequest, NvCreateTranscriptionResponse,
+};
 use crate::{
-    backend::Backend,
+    backend::{Backend, ExecutionContext},
+    engines::fan_out::FanOut,
+    kv_router::{KvPushRouter, KvRouter},
     model_type::ModelType,
     preprocessor::{BackendInput, OpenAIPreprocessor},
     protocols::common::llm_backend::LLMEngineOutput,
@@ -62,6 +69,11 @@ pub struct ModelEntry {
 
     /// Specifies whether the model is a chat or completion model.s
     pub model_type: ModelType,
+
+    /// [`ModelDeploymentCard::vocab_checksum`] of the worker, for a [`ModelType::Backend`] model.
+    /// The frontend tokenizes for those, so it only routes to workers that agree with it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vocab_checksum: Option<String>,
 }
 
 impl ModelEntry {
@@ -165,6 +177,121 @@ pub struct ModelWatchState {
     pub prefix: String,
     pub manager: ModelManager,
     pub drt: DistributedRuntime,
+    /// How the routers of the models we add choose between workers
+    router_mode: SharedRouterMode,
+    /// The KV cache block size of the workers, in tokens, when we route pre-processed requests
+    /// by the KV blocks the workers have cached
+    kv_block_size: Option<usize>,
+    /// Model name of each etcd key we added, so we know which model a delete is about, and
+    /// only remove it once the last worker serving it is gone.
+    entries: Mutex<HashMap<String, String>>,
+    /// Tokenizer of each pre-processed model we added, see [`ModelWatchState::check_vocab`]
+    vocabs: Mutex<HashMap<String, Vocab>>,
+}
+
+/// The tokenizer we pre-process a model's requests with, and the client routing them
+struct Vocab {
+    checksum: String,
+    client: Client,
+}
+
+impl ModelWatchState {
+    pub fn new(prefix: &str, manager: ModelManager, drt: DistributedRuntime) -> Self {
+        ModelWatchState {
+            prefix: prefix.to_string(),
+            manager,
+            drt,
+            router_mode: SharedRouterMode::default(),
+            kv_block_size: None,
+            entries: Mutex::new(HashMap::new()),
+            vocabs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Route the requests to each model with `router_mode`, random by default
+    pub fn with_router_mode(mut self, router_mode: RouterMode) -> Self {
+        self.router_mode = SharedRouterMode::new(router_mode);
+        self
+    }
+
+    /// Route with a mode that can be changed later, for the models added so far too
+    pub fn with_shared_router_mode(mut self, router_mode: SharedRouterMode) -> Self {
+        self.router_mode = router_mode;
+        self
+    }
+
+    /// Send the pre-processed requests of [`ModelType::Backend`] models to the worker with most
+    /// of their prompt in its KV cache, see [`KvPushRouter`]. `block_size` must be the one the
+    /// workers publish their KV events with. Models whose workers pre-process their requests
+    /// themselves are routed with our router mode.
+    pub fn with_kv_routing(mut self, block_size: usize) -> Self {
+        self.kv_block_size = Some(block_size);
+        self
+    }
+
+    /// A router to the workers of `client`, following our router mode when it changes
+    async fn router<T, U>(&self, client: Client) -> anyhow::Result<PushRouter<T, U>>
+    where
+        T: Data + Serialize,
+        U: Data + for<'de> Deserialize<'de>,
+    {
+        Ok(PushRouter::from_client(client, self.router_mode.get())
+            .await?
+            .with_shared_router_mode(self.router_mode.clone()))
+    }
+
+    /// The engine sending pre-processed requests through `router`, KV aware with `kv_router`
+    fn backend_engine(
+        router: PushRouter<BackendInput, Annotated<LLMEngineOutput>>,
+        kv_router: Option<&Arc<KvRouter>>,
+    ) -> ExecutionContext {
+        match kv_router {
+            Some(kv_router) => Arc::new(KvPushRouter::new(router, kv_router.clone())),
+            None => Arc::new(router),
+        }
+    }
+
+    fn add_entry(&self, key: &str, model_name: &str) {
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), model_name.to_string());
+    }
+
+    /// Forget `key`. Returns the model name and whether other keys still have it.
+    fn remove_entry(&self, key: &str) -> Option<(String, bool)> {
+        let mut entries = self.entries.lock().unwrap();
+        let model_name = entries.remove(key)?;
+        let still_served = entries.values().any(|name| *name == model_name);
+        if !still_served {
+            self.vocabs.lock().unwrap().remove(&model_name);
+        }
+        Some((model_name, still_served))
+    }
+
+    /// Stop routing to the worker `instance_id` if it tokenizes differently from us. It would
+    /// read our token ids as other words and answer with garbage. Workers that tokenize
+    /// themselves, and older workers that don't say, are let through.
+    fn check_vocab(&self, model_entry: &ModelEntry, instance_id: i64) -> anyhow::Result<()> {
+        let Some(worker_checksum) = &model_entry.vocab_checksum else {
+            return Ok(());
+        };
+        let vocabs = self.vocabs.lock().unwrap();
+        let Some(vocab) = vocabs.get(&model_entry.name) else {
+            return Ok(());
+        };
+        if vocab.checksum == *worker_checksum {
+            return Ok(());
+        }
+        vocab.client.exclude(instance_id);
+        anyhow::bail!(
+            "Worker {instance_id:x} of model {} has a different tokenizer or prompt template \
+             (checksum {worker_checksum}) from the one this frontend uses ({}), not routing \
+             to it",
+            model_entry.name,
+            vocab.checksum
+        );
+    }
 }
 
 pub async fn model_watcher(state: Arc<ModelWatchState>, mut events_rx: Receiver<WatchEvent>) {
@@ -180,27 +307,57 @@ pub async fn model_watcher(state: Arc<ModelWatchState>, mut events_rx: Receiver<
                         continue;
                     }
                 };
+                let key = match kv.key_str() {
+                    Ok(key) => key,
+                    Err(err) => {
+                        tracing::error!(%err, ?kv, "Invalid model entry key");
+                        continue;
+                    }
+                };
+                let namespace = &model_entry.endpoint.namespace;
                 if state.manager.has_model_any(&model_entry.name) {
+                    // Two tenants' workers must not end up behind one model
+                    if let Some(existing) = state
+                        .manager
+                        .model_namespace(&model_entry.name)
+                        .filter(|existing| existing != namespace)
+                    {
+                        tracing::error!(
+                            model_name = model_entry.name,
+                            "Model is already served from namespace {existing}, ignoring the \
+                             one in {namespace}"
+                        );
+                        continue;
+                    }
                     tracing::trace!(
                         service_name = model_entry.name,
                         "New endpoint for existing model"
                     );
-                    continue;
-                }
-
-                match handle_put(&model_entry, state.clone()).await {
-                    Ok(()) => {
-                        tracing::info!(model_name = model_entry.name, "added model");
-                    }
-                    Err(e) => {
-                        tracing::error!(%e, "error adding model {}", model_entry.name);
+                } else {
+                    match handle_put(&model_entry, state.clone()).await {
+                        Ok(()) => {
+                            state
+                                .manager
+                                .set_model_endpoint(&model_entry.name, &model_entry.endpoint);
+                            tracing::info!(model_name = model_entry.name, "added model");
+                        }
+                        Err(e) => {
+                            tracing::error!(%e, "error adding model {}", model_entry.name);
+                            continue;
+                        }
                     }
                 }
+                state.add_entry(key, &model_entry.name);
+                // The card we loaded may be from another worker, so check this one too
+                if let Err(err) = state.check_vocab(&model_entry, kv.lease()) {
+                    tracing::error!(%err, "Incompatible worker");
+                }
             }
-            WatchEvent::Delete(kv) => match handle_delete(&kv, state.clone()).await {
-                Ok(model_name) => {
+            WatchEvent::Delete(kv) => match handle_delete(&kv, &state) {
+                Ok(Some(model_name)) => {
                     tracing::info!("removed model {}", model_name);
                 }
+                Ok(None) => {}
                 Err(e) => {
                     tracing::error!("error removing model: {}", e);
                 }
@@ -209,17 +366,22 @@ pub async fn model_watcher(state: Arc<ModelWatchState>, mut events_rx: Receiver<
     }
 }
 
-async fn handle_delete(kv: &KeyValue, state: Arc<ModelWatchState>) -> anyhow::Result<&str> {
+/// A worker went away. Returns the name of the model if it was the last one serving it, in
+/// which case the model is removed.
+fn handle_delete(kv: &KeyValue, state: &ModelWatchState) -> anyhow::Result<Option<String>> {
     let key = kv.key_str()?;
-    tracing::debug!(key, "removing model");
-
-    let model_name = key.trim_start_matches(&state.prefix);
-
-    // Ignore the errors because model could be either type
-    let _ = state.manager.remove_chat_completions_model(model_name);
-    let _ = state.manager.remove_completions_model(model_name);
+    let Some((model_name, still_served)) = state.remove_entry(key) else {
+        tracing::debug!(key, "delete of unknown model entry");
+        return Ok(None);
+    };
+    if still_served {
+        tracing::debug!(key, model_name, "endpoint removed, model still served");
+        return Ok(None);
+    }
 
-    Ok(model_name)
+    tracing::debug!(key, model_name, "removing model");
+    state.manager.remove_model(&model_name);
+    Ok(Some(model_name))
 }
 
 // Handles a PUT event from etcd, this usually means adding a new model to the list of served
@@ -251,10 +413,21 @@ async fn handle_put(model_entry: &ModelEntry, state: Arc<ModelWatchState>) -> an
             None
         }
     };
-    match model_entry.model_type {
+    if let Some(engine) = card.as_ref().and_then(|card| card.engine.as_deref()) {
+        state.manager.set_engine_name(&model_entry.name, engine);
+    }
+    if state.kv_block_size.is_some() && !model_entry.requires_preprocessing() {
+        tracing::warn!(
+            model_name = model_entry.name,
+            "The workers of this model pre-process requests themselves, so there are no tokens \
+             to route them by KV cache with, using the router mode instead"
+        );
+    }
+    let sticky = match model_entry.model_type {
         ModelType::Backend => {
             // A Backend model expects pre-processed requests meaning it's up to us whether we
-            // handle Chat or Completions requests, so handle both.
+            // handle Chat or Completions requests, so handle both. The workers sample one
+            // choice per request, requests for more are fanned out to them.
 
             let Some(mut card) = card else {
                 anyhow::bail!("Missing model deployment card");
@@ -264,77 +437,129 @@ async fn handle_put(model_entry: &ModelEntry, state: Arc<ModelWatchState>) -> an
             // OpenAIPreprocessor::new loads the files, so we can delete them after this
             // function. Needs checking carefully, possibly we need to store it in state.
             let _cache_dir = Some(card.move_from_nats(state.drt.nats_client()).await?);
+            let vocab = Vocab {
+                checksum: card.vocab_checksum()?,
+                client: client.clone(),
+            };
+            let openai_preprocessor = OpenAIPreprocessor::new(card.clone()).await?;
+            // One per model, chat and completions requests share the workers' caches
+            let kv_router = match state.kv_block_size {
+                Some(block_size) => Some(
+                    KvRouter::new(client.endpoint.component().clone(), block_size, None).await?,
+                ),
+                None => None,
+            };
 
             let frontend = SegmentSource::<
                 SingleIn<NvCreateChatCompletionRequest>,
                 ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
             >::new();
-            let preprocessor = OpenAIPreprocessor::new(card.clone()).await?.into_operator();
+            let preprocessor = openai_preprocessor.into_operator();
             let backend = Backend::from_mdc(card.clone()).await?.into_operator();
-            let router = PushRouter::<BackendInput, Annotated<LLMEngineOutput>>::from_client(
-                client.clone(),
-                RouterMode::Random, // TODO how do we configure this?
-            )
-            .await?;
+            let router = state
+                .router::<BackendInput, Annotated<LLMEngineOutput>>(client.clone())
+                .await?;
+            let mut sticky: Vec<_> = router.sticky_sessions().into_iter().collect();
+            let router = ModelWatchState::backend_engine(router, kv_router.as_ref());
 
             let chat_engine = frontend
                 .link(preprocessor.forward_edge())?
                 .link(backend.forward_edge())?
-                .link(ServiceBackend::from_engine(Arc::new(router)))?
+                .link(ServiceBackend::from_engine(router))?
                 .link(backend.backward_edge())?
                 .link(preprocessor.backward_edge())?
                 .link(frontend)?;
-            state
-                .manager
-                .add_chat_completions_model(&model_entry.name, chat_engine)?;
+            state.manager.add_chat_completions_model(
+                &model_entry.name,
+                Arc::new(FanOut::new(chat_engine)),
+            )?;
 
             let frontend = SegmentSource::<
                 SingleIn<CompletionRequest>,
                 ManyOut<Annotated<CompletionResponse>>,
             >::new();
-            let preprocessor = OpenAIPreprocessor::new(card.clone()).await?.into_operator();
+            let preprocessor = openai_preprocessor.into_operator();
             let backend = Backend::from_mdc(card.clone()).await?.into_operator();
-            let router = PushRouter::<BackendInput, Annotated<LLMEngineOutput>>::from_client(
-                client,
-                RouterMode::Random, // TODO how do we configure this?
-            )
-            .await?;
+            let router = state
+                .router::<BackendInput, Annotated<LLMEngineOutput>>(client.clone())
+                .await?;
+            sticky.extend(router.sticky_sessions());
+            let router = ModelWatchState::backend_engine(router, kv_router.as_ref());
 
             let completions_engine = frontend
                 .link(preprocessor.forward_edge())?
                 .link(backend.forward_edge())?
-                .link(ServiceBackend::from_engine(Arc::new(router)))?
+                .link(ServiceBackend::from_engine(router))?
                 .link(backend.backward_edge())?
                 .link(preprocessor.backward_edge())?
                 .link(frontend)?;
+            state.manager.add_completions_model(
+                &model_entry.name,
+                Arc::new(FanOut::new(completions_engine)),
+            )?;
             state
                 .manager
-                .add_completions_model(&model_entry.name, completions_engine)?;
+                .add_preprocessor(&model_entry.name, openai_preprocessor)?;
+            state
+                .vocabs
+                .lock()
+                .unwrap()
+                .insert(model_entry.name.clone(), vocab);
+            sticky
         }
         ModelType::Chat => {
-            let push_router = PushRouter::<
+            let push_router: PushRouter<
                 NvCreateChatCompletionRequest,
                 Annotated<NvCreateChatCompletionStreamResponse>,
-            >::from_client(client, Default::default())
-            .await?;
+            > = state.router(client.clone()).await?;
+            let sticky = push_router.sticky_sessions().into_iter().collect();
             let engine = Arc::new(push_router);
             state
                 .manager
                 .add_chat_completions_model(&model_entry.name, engine)?;
+            sticky
         }
         ModelType::Completion => {
-            let push_router =
-                PushRouter::<CompletionRequest, Annotated<CompletionResponse>>::from_client(
-                    client,
-                    Default::default(),
-                )
+            let push_router = state
+                .router::<CompletionRequest, Annotated<CompletionResponse>>(client.clone())
                 .await?;
+            let sticky = push_router.sticky_sessions().into_iter().collect();
             let engine = Arc::new(push_router);
             state
                 .manager
                 .add_completions_model(&model_entry.name, engine)?;
+            sticky
         }
-    }
+        ModelType::Embedding => {
+            let push_router = state
+                .router::<NvCreateEmbeddingRequest, Annotated<NvCreateEmbeddingResponse>>(
+                    client.clone(),
+                )
+                .await?;
+            let sticky = push_router.sticky_sessions().into_iter().collect();
+            let engine = Arc::new(push_router);
+            state
+                .manager
+                .add_embeddings_model(&model_entry.name, engine)?;
+            sticky
+        }
+        ModelType::Transcription => {
+            let push_router = state
+                .router::<NvCreateTranscriptionRequest, Annotated<NvCreateTranscriptionResponse>>(
+                    client.clone(),
+                )
+                .await?;
+            let sticky = push_router.sticky_sessions().into_iter().collect();
+            let engine = Arc::new(push_router);
+            state
+                .manager
+                .add_transcriptions_model(&model_entry.name, engine)?;
+            sticky
+        }
+    };
+    state
+        .manager
+        .set_model_workers(&model_entry.name, client, sticky);
 
     Ok(())
 }
@@ -0,0 +1,66 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Stop taking requests on shutdown, see [`dynamo_runtime::drain`]
+
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use dynamo_runtime::drain::Drain;
+use futures::StreamExt;
+
+use super::auth::PUBLIC_PATHS;
+use super::error::ErrorKind;
+use super::openai::ErrorResponse;
+
+/// `/v1/requests/{request_id}/cancel`
+const CANCEL_PATH_PREFIX: &str = "/v1/requests/";
+
+/// Middleware answering 503 once the drain started, and counting the other requests as
+/// running until their response body is done streaming.
+pub(crate) async fn reject_when_draining(
+    State(drain): State<Arc<Drain>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path();
+    // Cancelling a running request helps the drain along
+    if PUBLIC_PATHS.contains(&path) || path.starts_with(CANCEL_PATH_PREFIX) {
+        return next.run(request).await;
+    }
+    if drain.is_draining() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::CONNECTION, "close")],
+            ErrorResponse::json_of_kind(ErrorKind::Unavailable, "Server is shutting down"),
+        )
+            .into_response();
+    }
+
+    let running = drain.track();
+    let response = next.run(request).await;
+    response.map(|body| {
+        Body::from_stream(body.into_data_stream().map(move |chunk| {
+            let _ = &running;
+            chunk
+        }))
+    })
+}
@@ -13,8 +13,20 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+//! Errors of the HTTP service
+//!
+//! Requests fail with a [`RequestError`], see [`crate::protocols::error`].
+//! [`RequestError::classify`] finds the kind of the other errors the request path returns, for
+//! the status and `code` of the error response.
+
 use thiserror::Error;
 
+pub use crate::protocols::error::{ErrorKind, RequestError};
+
+use crate::kv_router::scheduler::KvSchedulerError;
+use crate::lora::LoraError;
+use crate::preprocessor::media::MediaError;
+
 #[derive(Debug, Error)]
 pub enum ServiceHttpError {
     #[error("Model not found: {0}")]
@@ -22,6 +34,9 @@ pub enum ServiceHttpError {
 
     #[error("Model already exists: {0}")]
     ModelAlreadyExists(String),
+
+    #[error("Model {model} does not support {capability}")]
+    UnsupportedCapability { model: String, capability: String },
 }
 
 /// Implementation of the Completion Engines served by the HTTP service should
@@ -33,3 +48,57 @@ pub struct HttpError {
     pub code: u16,
     pub message: String,
 }
+
+impl RequestError {
+    /// What `err` says about its kind: a [`RequestError`], an [`HttpError`] by its status, a
+    /// [`LoraError`] or [`MediaError`], which are the client's fault, a [`KvSchedulerError`],
+    /// or a message starting with a kind. None for anything else.
+    pub fn classify(err: &anyhow::Error) -> Option<RequestError> {
+        if let Some(err) = err.downcast_ref::<RequestError>() {
+            return Some(err.clone());
+        }
+        if let Some(err) = err.downcast_ref::<HttpError>() {
+            return Some(RequestError::new(
+                ErrorKind::from_status(err.code),
+                err.message.clone(),
+            ));
+        }
+        if err.is::<LoraError>() || err.is::<MediaError>() {
+            return Some(RequestError::invalid_request(err.to_string()));
+        }
+        if let Some(err) = err.downcast_ref::<KvSchedulerError>() {
+            let kind = match err {
+                KvSchedulerError::AllWorkersBusy => ErrorKind::Overloaded,
+                KvSchedulerError::NoEndpoints | KvSchedulerError::SubscriberShutdown => {
+                    ErrorKind::Unavailable
+                }
+            };
+            return Some(RequestError::new(kind, err.to_string()));
+        }
+        RequestError::from_event_message(&err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        let err = anyhow::Error::from(HttpError {
+            code: 503,
+            message: "try again later".to_string(),
+        });
+        assert_eq!(
+            RequestError::classify(&err).unwrap().kind,
+            ErrorKind::Unavailable
+        );
+
+        let err = anyhow::anyhow!(RequestError::engine_failure("crashed").to_string());
+        assert_eq!(
+            RequestError::classify(&err).unwrap().kind,
+            ErrorKind::EngineFailure
+        );
+        assert!(RequestError::classify(&anyhow::anyhow!("oops")).is_none());
+    }
+}
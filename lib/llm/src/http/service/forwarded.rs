@@ -0,0 +1,154 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The client's address behind load balancers and proxies
+//!
+//! Behind a load balancer every request comes from the balancer. For a request from one of
+//! the trusted proxies the client is read from `X-Forwarded-For` instead: each proxy appends
+//! the address it got the request from, so walking it from the end, the first address that is
+//! not a trusted proxy is the client. Addresses before that one could be made up by the
+//! client. Rate limiting by client address, without API keys, then limits clients rather than
+//! the balancer.
+
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::HeaderMap,
+    middleware::Next,
+    response::Response,
+};
+use ipnet::IpNet;
+
+pub const FORWARDED_FOR_HEADER: &str = "x-forwarded-for";
+
+/// Addresses and networks of the proxies in front of us
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrustedProxies {
+    networks: Vec<IpNet>,
+}
+
+impl FromStr for TrustedProxies {
+    type Err = String;
+
+    /// Comma separated addresses and CIDR networks, e.g. `10.0.0.0/8,192.168.1.5`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let networks = s
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|s| match s.parse::<IpAddr>() {
+                Ok(ip) => Ok(IpNet::from(ip)),
+                Err(_) => s
+                    .parse::<IpNet>()
+                    .map_err(|_| format!("'{s}' is not an IP address or network")),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(TrustedProxies { networks })
+    }
+}
+
+impl TrustedProxies {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.networks.iter().any(|network| network.contains(&ip))
+    }
+
+    /// The client of a request from `peer` with these headers
+    pub fn client(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let mut client = peer;
+        if !self.contains(client) {
+            return client;
+        }
+        let forwarded: Vec<&str> = headers
+            .get_all(FORWARDED_FOR_HEADER)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect();
+        for hop in forwarded.into_iter().rev() {
+            let Ok(ip) = hop.parse::<IpAddr>() else {
+                break;
+            };
+            client = ip;
+            if !self.contains(client) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+/// Middleware replacing the peer address of requests from trusted proxies with the client's,
+/// for the layers and handlers after it
+pub(crate) async fn resolve_client(
+    State(proxies): State<Arc<TrustedProxies>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(peer)| *peer);
+    if let Some(peer) = peer {
+        let client = proxies.client(peer.ip(), request.headers());
+        if client != peer.ip() {
+            // The port was the proxy's, we don't know the client's
+            request
+                .extensions_mut()
+                .insert(ConnectInfo(SocketAddr::new(client, 0)));
+        }
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_client() {
+        let proxies: TrustedProxies = "10.0.0.0/8, 192.168.1.5".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            FORWARDED_FOR_HEADER,
+            HeaderValue::from_static("1.2.3.4, 5.6.7.8, 10.1.1.1"),
+        );
+
+        // The last hop that is not a proxy, not what the client claims before it
+        assert_eq!(proxies.client(ip("192.168.1.5"), &headers), ip("5.6.7.8"));
+        // Anyone else could have written the header
+        assert_eq!(proxies.client(ip("5.6.7.8"), &headers), ip("5.6.7.8"));
+        // No header
+        assert_eq!(
+            proxies.client(ip("10.0.0.1"), &HeaderMap::new()),
+            ip("10.0.0.1")
+        );
+
+        // Nothing but proxies, the first of them
+        headers.insert(FORWARDED_FOR_HEADER, HeaderValue::from_static("10.2.2.2"));
+        assert_eq!(proxies.client(ip("10.0.0.1"), &headers), ip("10.2.2.2"));
+
+        assert!("10.0.0.0/33".parse::<TrustedProxies>().is_err());
+        assert!("proxy".parse::<TrustedProxies>().is_err());
+    }
+}
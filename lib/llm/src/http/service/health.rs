@@ -0,0 +1,97 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Liveness and readiness probes: `/live`, `/ready` and `/health`
+//!
+//! `/live` answers as long as the service does. `/ready` answers 200 once a model is served,
+//! and 503 before that and while draining. A model is only added once its engine is built, or
+//! for a discovered model once its worker registered its endpoint, so a served model is a
+//! loaded and warm one. `/health` makes the same check and lists the models, as JSON.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::{Method, StatusCode},
+    response::IntoResponse,
+    routing::get,
+    Json, Router,
+};
+use dynamo_runtime::drain::Drain;
+use serde::Serialize;
+
+use super::{DeploymentState, RouteDoc};
+
+#[derive(Clone)]
+struct HealthState {
+    deployment: Arc<DeploymentState>,
+    drain: Option<Arc<Drain>>,
+}
+
+#[derive(Serialize)]
+struct Health {
+    /// `ready`, `starting` until a model is served, or `draining`
+    status: &'static str,
+    models: Vec<String>,
+}
+
+impl HealthState {
+    fn check(&self) -> (StatusCode, Health) {
+        let models = self.deployment.model_names();
+        let status = if self.drain.as_ref().is_some_and(|drain| drain.is_draining()) {
+            "draining"
+        } else if models.is_empty() {
+            "starting"
+        } else {
+            "ready"
+        };
+        let code = if status == "ready" {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+        (code, Health { status, models })
+    }
+}
+
+pub(crate) fn router(
+    deployment: Arc<DeploymentState>,
+    drain: Option<Arc<Drain>>,
+) -> (Vec<RouteDoc>, Router) {
+    let docs = ["/live", "/ready", "/health"]
+        .into_iter()
+        .map(|path| RouteDoc::new(Method::GET, path))
+        .collect();
+    let router = Router::new()
+        .route("/live", get(live))
+        .route("/ready", get(ready))
+        .route("/health", get(health))
+        .with_state(HealthState { deployment, drain });
+    (docs, router)
+}
+
+async fn live() -> &'static str {
+    "live\n"
+}
+
+async fn ready(State(state): State<HealthState>) -> impl IntoResponse {
+    let (code, health) = state.check();
+    (code, format!("{}\n", health.status))
+}
+
+async fn health(State(state): State<HealthState>) -> impl IntoResponse {
+    let (code, health) = state.check();
+    (code, Json(health))
+}
@@ -0,0 +1,340 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `Idempotency-Key`: a retried request gets the response of its first attempt
+//!
+//! Clients on flaky networks retry requests whose response they lost, and each retry would be
+//! generated again. A `POST` with an `Idempotency-Key` header is recorded under that key, per
+//! API key, or per client address when API keys are off, for a while. A request with the same key gets the recorded response, with an
+//! `Idempotent-Replayed: true` header, instead of a second generation. If the first is still
+//! streaming, the retry gets what was sent so far and then follows the rest as it comes.
+//!
+//! The response is generated to the end even if the client that asked for it goes away, so
+//! there is something to replay. A key used again with a different request body gets a 422.
+//! Failed responses, 5xx and 429, are not kept, so that retrying them runs them again.
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_stream::stream;
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    extract::{Request, State},
+    http::{HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures::StreamExt;
+use tokio::sync::watch;
+use xxhash_rust::xxh3::Xxh3;
+
+use super::openai::ErrorResponse;
+use super::rate_limit::client_id;
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// Response header marking a recorded response
+pub const REPLAYED_HEADER: &str = "idempotent-replayed";
+
+/// Most keys remembered when only the TTL is given
+pub const DEFAULT_MAX_KEYS: usize = 10_000;
+
+/// Longest key accepted
+const MAX_KEY_LEN: usize = 255;
+
+/// Largest request body, they are read whole to compare retries with the first attempt
+const MAX_BODY: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdempotencyConfig {
+    /// How long a key's response is kept, from the first request
+    pub ttl: Duration,
+    /// Past this many keys, requests with a new key are served without recording them
+    pub max_keys: usize,
+}
+
+impl IdempotencyConfig {
+    pub fn new(ttl: Duration) -> Self {
+        IdempotencyConfig {
+            ttl,
+            max_keys: DEFAULT_MAX_KEYS,
+        }
+    }
+}
+
+/// A response as far as it got
+#[derive(Debug, Default)]
+struct Recorded {
+    head: Option<(StatusCode, HeaderMap)>,
+    chunks: Vec<Bytes>,
+    done: bool,
+}
+
+struct Entry {
+    /// Of the request that started it
+    fingerprint: u64,
+    expires: Instant,
+    response: watch::Receiver<Recorded>,
+}
+
+enum Begin {
+    /// The first request with the key, record its response here
+    First(watch::Sender<Recorded>),
+    Replay(watch::Receiver<Recorded>),
+    /// The key was used by another request
+    Conflict,
+    /// Too many keys to record another
+    Full,
+}
+
+/// The responses of requests with an idempotency key
+pub struct IdempotencyStore {
+    config: IdempotencyConfig,
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl IdempotencyStore {
+    pub fn new(config: IdempotencyConfig) -> Self {
+        IdempotencyStore {
+            config,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn begin(&self, key: String, fingerprint: u64) -> Begin {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(entry) = entries.get(&key).filter(|entry| entry.expires > now) {
+            if entry.fingerprint != fingerprint {
+                return Begin::Conflict;
+            }
+            return Begin::Replay(entry.response.clone());
+        }
+        if entries.len() >= self.config.max_keys {
+            entries.retain(|_, entry| entry.expires > now);
+            if entries.len() >= self.config.max_keys {
+                return Begin::Full;
+            }
+        }
+        let (tx, rx) = watch::channel(Recorded::default());
+        entries.insert(
+            key,
+            Entry {
+                fingerprint,
+                expires: now + self.config.ttl,
+                response: rx,
+            },
+        );
+        Begin::First(tx)
+    }
+
+    fn forget(&self, key: &str) {
+        self.entries.lock().unwrap().remove(key);
+    }
+}
+
+/// Middleware recording and replaying the responses of requests with an idempotency key.
+/// Inside authentication, keys are per API key, else per client address.
+pub(crate) async fn idempotent(
+    State(store): State<Arc<IdempotencyStore>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if request.method() != Method::POST {
+        return next.run(request).await;
+    }
+    let Some(key) = request.headers().get(IDEMPOTENCY_KEY_HEADER) else {
+        return next.run(request).await;
+    };
+    let key = match key.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LEN => key.to_string(),
+        _ => {
+            return ErrorResponse::bad_request(&format!(
+                "Idempotency-Key must be 1 to {MAX_KEY_LEN} visible ASCII characters"
+            ))
+            .into_response()
+        }
+    };
+    // Two clients picking the same key must not get each other's responses
+    let scoped_key = format!("{}\n{key}", client_id(&request));
+
+    let (parts, body) = request.into_parts();
+    let Ok(body) = to_bytes(body, MAX_BODY).await else {
+        return (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorResponse::json("Request body too large"),
+        )
+            .into_response();
+    };
+    let fingerprint = fingerprint(&parts.method, parts.uri.path(), &body);
+    let request = Request::from_parts(parts, Body::from(body));
+
+    let tx = match store.begin(scoped_key.clone(), fingerprint) {
+        Begin::First(tx) => tx,
+        Begin::Replay(rx) => {
+            tracing::debug!(key, "Replaying the response to an idempotency key");
+            let mut response = replay(rx).await;
+            response
+                .headers_mut()
+                .insert(REPLAYED_HEADER, HeaderValue::from_static("true"));
+            return response;
+        }
+        Begin::Conflict => {
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                ErrorResponse::json(&format!(
+                    "Idempotency-Key {key} was already used with a different request"
+                )),
+            )
+                .into_response()
+        }
+        Begin::Full => {
+            tracing::warn!(key, "Too many idempotency keys, not recording this one");
+            return next.run(request).await;
+        }
+    };
+
+    let response = next.run(request).await;
+    let status = response.status();
+    if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+        // Retries run again. Requests waiting for this one see the recording stop.
+        store.forget(&scoped_key);
+        return response;
+    }
+    let (parts, body) = response.into_parts();
+    let rx = tx.subscribe();
+    tx.send_modify(|recorded| recorded.head = Some((parts.status, parts.headers)));
+    tokio::spawn(record(body, tx));
+    replay(rx).await
+}
+
+fn fingerprint(method: &Method, path: &str, body: &[u8]) -> u64 {
+    let mut hasher = Xxh3::new();
+    hasher.update(method.as_str().as_bytes());
+    hasher.update(path.as_bytes());
+    hasher.update(body);
+    hasher.digest()
+}
+
+/// Read the whole response body into the recording
+async fn record(body: Body, tx: watch::Sender<Recorded>) {
+    let mut chunks = body.into_data_stream();
+    while let Some(chunk) = chunks.next().await {
+        match chunk {
+            Ok(chunk) => tx.send_modify(|recorded| recorded.chunks.push(chunk)),
+            Err(err) => {
+                tracing::warn!(%err, "Response to an idempotency key failed");
+                break;
+            }
+        }
+    }
+    tx.send_modify(|recorded| recorded.done = true);
+}
+
+/// The recorded response, following it until it is done
+async fn replay(mut rx: watch::Receiver<Recorded>) -> Response {
+    let (status, headers) = loop {
+        if let Some(head) = rx.borrow_and_update().head.clone() {
+            break head;
+        }
+        if rx.changed().await.is_err() {
+            return (
+                StatusCode::CONFLICT,
+                ErrorResponse::json(
+                    "The request first sent with this Idempotency-Key failed, retry it",
+                ),
+            )
+                .into_response();
+        }
+    };
+
+    let body = stream! {
+        let mut sent = 0;
+        loop {
+            let (chunks, done) = {
+                let recorded = rx.borrow_and_update();
+                (recorded.chunks[sent..].to_vec(), recorded.done)
+            };
+            sent += chunks.len();
+            for chunk in chunks {
+                yield Ok::<_, Infallible>(chunk);
+            }
+            if done {
+                break;
+            }
+            if rx.changed().await.is_err() {
+                // The recording stopped early, what it got
+                let rest = rx.borrow().chunks[sent..].to_vec();
+                for chunk in rest {
+                    yield Ok(chunk);
+                }
+                break;
+            }
+        }
+    };
+    let mut response = Response::new(Body::from_stream(body));
+    *response.status_mut() = status;
+    *response.headers_mut() = headers;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_begin() {
+        let store = IdempotencyStore::new(IdempotencyConfig {
+            ttl: Duration::from_secs(60),
+            max_keys: 2,
+        });
+        assert!(matches!(store.begin("a".to_string(), 1), Begin::First(_)));
+        assert!(matches!(store.begin("a".to_string(), 1), Begin::Replay(_)));
+        assert!(matches!(store.begin("a".to_string(), 2), Begin::Conflict));
+        assert!(matches!(store.begin("b".to_string(), 1), Begin::First(_)));
+        assert!(matches!(store.begin("c".to_string(), 1), Begin::Full));
+
+        store.forget("a");
+        assert!(matches!(store.begin("a".to_string(), 2), Begin::First(_)));
+    }
+
+    #[tokio::test]
+    async fn test_replay() {
+        let (tx, rx) = watch::channel(Recorded::default());
+        let early = tokio::spawn(replay(rx.clone()));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-request-id", HeaderValue::from_static("1"));
+        tx.send_modify(|recorded| {
+            recorded.head = Some((StatusCode::OK, headers));
+            recorded.chunks.push(Bytes::from("data: 1\n\n"));
+        });
+        // Joins while it streams
+        let late = replay(rx).await;
+        tx.send_modify(|recorded| {
+            recorded.chunks.push(Bytes::from("data: 2\n\n"));
+            recorded.done = true;
+        });
+
+        for response in [early.await.unwrap(), late] {
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()["x-request-id"], "1");
+            let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            assert_eq!(body, "data: 1\n\ndata: 2\n\n");
+        }
+    }
+}
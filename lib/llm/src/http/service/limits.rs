@@ -0,0 +1,164 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Hard limits on the requests of a deployment
+//!
+//! The operator caps what one request can cost, whatever the client asks for:
+//!
+//! - `max_output_tokens`: a request asking for more completion tokens gets a 400, one that
+//!   doesn't say gets this many.
+//! - `max_prompt_tokens`: the pre-processor counts the prompt's tokens and rejects a longer
+//!   one with a 400, see
+//!   [`crate::preprocessor::MAX_PROMPT_TOKENS_CONTEXT_KEY`].
+//! - `timeout`: a request still generating this long after it arrived is stopped. A
+//!   non-streaming request gets a 504, a streaming one an error event ending its stream.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_stream::stream;
+use dynamo_runtime::pipeline::AsyncEngineContext;
+use dynamo_runtime::protocols::annotated::Annotated;
+use futures::{Stream, StreamExt};
+
+use super::error::{ErrorKind, RequestError};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RequestLimits {
+    /// Most completion tokens a request can ask for
+    pub max_output_tokens: Option<u32>,
+    /// Most tokens in a prompt, after the chat template
+    pub max_prompt_tokens: Option<u32>,
+    /// Longest a request can take, from when it arrives to its last token
+    pub timeout: Option<Duration>,
+}
+
+impl RequestLimits {
+    /// The max tokens of a request that asked for `requested`, worded like OpenAI's 400 if it
+    /// asked for too many
+    pub fn max_tokens(&self, requested: Option<u32>) -> Result<Option<u32>, String> {
+        let Some(cap) = self.max_output_tokens else {
+            return Ok(requested);
+        };
+        match requested {
+            Some(n) if n > cap => Err(format!(
+                "max_tokens is too large: {n}. This model supports at most {cap} completion \
+                 tokens, whereas you provided {n}."
+            )),
+            Some(n) => Ok(Some(n)),
+            None => Ok(Some(cap)),
+        }
+    }
+
+    /// When a request that arrived at `received` must be done by
+    pub fn deadline(&self, received: Instant) -> Option<Deadline> {
+        self.timeout.map(|timeout| Deadline {
+            at: tokio::time::Instant::from_std(received + timeout),
+            timeout,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Deadline {
+    at: tokio::time::Instant,
+    timeout: Duration,
+}
+
+impl Deadline {
+    /// What the client is told when the deadline passes
+    pub fn message(&self) -> String {
+        format!(
+            "Request timed out: it ran for longer than the {:.1}s this deployment allows",
+            self.timeout.as_secs_f64()
+        )
+    }
+
+    /// `fut`'s output, or [`Deadline::message`] if the deadline passes first
+    pub async fn run<F: Future>(&self, fut: F) -> Result<F::Output, String> {
+        tokio::time::timeout_at(self.at, fut)
+            .await
+            .map_err(|_| self.message())
+    }
+
+    /// End `stream` with an error when the deadline passes, and stop the generation
+    pub fn limit<R, S>(
+        self,
+        stream: S,
+        context: Arc<dyn AsyncEngineContext>,
+    ) -> impl Stream<Item = Annotated<R>> + Send
+    where
+        R: Send + 'static,
+        S: Stream<Item = Annotated<R>> + Send + 'static,
+    {
+        stream! {
+            let mut stream = Box::pin(stream);
+            loop {
+                match tokio::time::timeout_at(self.at, stream.next()).await {
+                    Ok(Some(response)) => yield response,
+                    Ok(None) => return,
+                    Err(_) => {
+                        tracing::warn!(
+                            request_id = context.id(),
+                            timeout = ?self.timeout,
+                            "Stopping a request that ran past its deadline"
+                        );
+                        context.stop_generating();
+                        let err = RequestError::new(ErrorKind::Timeout, self.message());
+                        yield Annotated::from_error(err.to_string());
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_max_tokens() {
+        let limits = RequestLimits {
+            max_output_tokens: Some(512),
+            ..Default::default()
+        };
+        assert_eq!(limits.max_tokens(None), Ok(Some(512)));
+        assert_eq!(limits.max_tokens(Some(100)), Ok(Some(100)));
+        assert_eq!(limits.max_tokens(Some(512)), Ok(Some(512)));
+        assert!(limits.max_tokens(Some(513)).is_err());
+
+        let unlimited = RequestLimits::default();
+        assert_eq!(unlimited.max_tokens(None), Ok(None));
+        assert_eq!(unlimited.max_tokens(Some(100_000)), Ok(Some(100_000)));
+        assert!(unlimited.deadline(Instant::now()).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_deadline() {
+        let limits = RequestLimits {
+            timeout: Some(Duration::from_millis(50)),
+            ..Default::default()
+        };
+        let deadline = limits.deadline(Instant::now()).unwrap();
+        assert_eq!(deadline.run(async { 1 }).await, Ok(1));
+        assert_eq!(
+            deadline.run(futures::future::pending::<()>()).await,
+            Err(deadline.message())
+        );
+    }
+}
@@ -0,0 +1,132 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `/admin/lora`: the LoRA adapters of the served models, see [`crate::lora`]. Only served
+//! when turned on, see [`super::service_v2::HttpServiceConfig`], as it loads adapters from
+//! paths the client gives.
+//!
+//! - `GET` lists them.
+//! - `POST {"model": ..., "name": ..., "path": ...}` adds one to a served model.
+//! - `DELETE ?model=...&name=...` removes one. Model names can have a `/`, so they are not
+//!   part of the path.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+
+use super::openai::ErrorResponse;
+use super::{DeploymentState, RouteDoc};
+use crate::lora::{LoraAdapter, LoraError};
+
+const PATH: &str = "/admin/lora";
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ModelAdapter {
+    model: String,
+    #[serde(flatten)]
+    adapter: LoraAdapter,
+}
+
+#[derive(Deserialize)]
+struct RemoveQuery {
+    model: String,
+    name: String,
+}
+
+pub(crate) fn admin_router(state: Arc<DeploymentState>) -> (Vec<RouteDoc>, Router) {
+    let docs = vec![
+        RouteDoc::new(axum::http::Method::GET, PATH),
+        RouteDoc::new(axum::http::Method::POST, PATH),
+        RouteDoc::new(axum::http::Method::DELETE, PATH),
+    ];
+    let router = Router::new()
+        .route(
+            PATH,
+            get(list_adapters).post(add_adapter).delete(remove_adapter),
+        )
+        .with_state(state);
+    (docs, router)
+}
+
+async fn list_adapters(State(state): State<Arc<DeploymentState>>) -> Json<Vec<ModelAdapter>> {
+    let adapters = state.lora_adapters.lock().unwrap().list();
+    Json(
+        adapters
+            .into_iter()
+            .map(|(model, adapter)| ModelAdapter { model, adapter })
+            .collect(),
+    )
+}
+
+async fn add_adapter(
+    State(state): State<Arc<DeploymentState>>,
+    Json(entry): Json<ModelAdapter>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    if !state.has_model_any(&entry.model) {
+        return Err(ErrorResponse::model_not_found());
+    }
+    let added = state
+        .lora_adapters
+        .lock()
+        .unwrap()
+        .add(&entry.model, entry.adapter.clone());
+    match added {
+        Ok(()) => {
+            tracing::info!(
+                model = entry.model,
+                adapter = entry.adapter.name,
+                path = entry.adapter.path,
+                "added LoRA adapter"
+            );
+            Ok((StatusCode::CREATED, Json(entry)).into_response())
+        }
+        Err(err @ LoraError::AlreadyExists { .. }) => {
+            Err((StatusCode::CONFLICT, ErrorResponse::json(&err.to_string())))
+        }
+        Err(err) => Err(ErrorResponse::bad_request(&err.to_string())),
+    }
+}
+
+async fn remove_adapter(
+    State(state): State<Arc<DeploymentState>>,
+    Query(query): Query<RemoveQuery>,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    let removed = state
+        .lora_adapters
+        .lock()
+        .unwrap()
+        .remove(&query.model, &query.name);
+    match removed {
+        Some(_) => {
+            tracing::info!(
+                model = query.model,
+                adapter = query.name,
+                "removed LoRA adapter"
+            );
+            Ok(StatusCode::NO_CONTENT)
+        }
+        None => Err(ErrorResponse::not_found(&format!(
+            "Model {} has no LoRA adapter {}",
+            query.model, query.name
+        ))),
+    }
+}
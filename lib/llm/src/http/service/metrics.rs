@@ -14,12 +14,13 @@
 // limitations under the License.
 
 use axum::{extract::State, http::StatusCode, response::IntoResponse, routing::get, Router};
-use prometheus::{Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts};
+use prometheus::{GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts};
 use std::{sync::Arc, time::Instant};
 
 pub use prometheus::Registry;
 
 use super::{DeploymentState, RouteDoc};
+use crate::types::Annotated;
 
 /// Value for the `status` label in the request counter for successful requests
 pub const REQUEST_STATUS_SUCCESS: &str = "success";
@@ -33,10 +34,14 @@ pub const REQUEST_TYPE_STREAM: &str = "stream";
 /// Partial value for the `type` label in the request counter for unary requests
 pub const REQUEST_TYPE_UNARY: &str = "unary";
 
+/// Value for the `engine` label of a model whose engine we don't know
+pub const ENGINE_UNKNOWN: &str = "unknown";
+
 pub struct Metrics {
     request_counter: IntCounterVec,
     inflight_gauge: IntGaugeVec,
     request_duration: HistogramVec,
+    output_tokens_per_second: GaugeVec,
 }
 
 /// RAII object for inflight gauge and request counters
@@ -52,6 +57,21 @@ pub struct InflightGuard {
     timer: Instant,
 }
 
+/// Times the tokens of a response as they are streamed to the client, for its decode speed,
+/// see [`DeploymentState::create_token_timer`]. Records when dropped.
+///
+/// Time to first token and inter-token latency are recorded once, by the pre-processor, as
+/// `dynamo_llm_time_to_first_token_seconds` and `dynamo_llm_inter_token_latency_seconds`.
+pub struct TokenTimer {
+    metrics: Arc<Metrics>,
+    model: String,
+    engine: String,
+    first_token: Option<Instant>,
+    last_token: Option<Instant>,
+    chunks: usize,
+    output_tokens: Option<usize>,
+}
+
 /// Requests will be logged by the type of endpoint hit
 /// This will include llamastack in the future
 pub enum Endpoint {
@@ -60,6 +80,12 @@ pub enum Endpoint {
 
     /// OAI Chat Completions
     ChatCompletions,
+
+    /// OAI Embeddings
+    Embeddings,
+
+    /// OAI Audio Transcriptions
+    Transcriptions,
 }
 
 /// Metrics for the HTTP service
@@ -89,6 +115,8 @@ impl Metrics {
     /// - `{prefix}_http_service_requests_total` - IntCounterVec for the total number of requests processed
     /// - `{prefix}_http_service_inflight_requests` - IntGaugeVec for the number of inflight requests
     /// - `{prefix}_http_service_request_duration_seconds` - HistogramVec for the duration of requests
+    /// - `{prefix}_http_service_output_tokens_per_second` - GaugeVec of the decode speed of the
+    ///   latest finished response, by model and engine
     pub fn new(prefix: &str) -> Self {
         let request_counter = IntCounterVec::new(
             Opts::new(
@@ -120,10 +148,20 @@ impl Metrics {
         )
         .unwrap();
 
+        let output_tokens_per_second = GaugeVec::new(
+            Opts::new(
+                format!("{}_http_service_output_tokens_per_second", prefix),
+                "Tokens per second generated after the first, of the latest finished response",
+            ),
+            &["model", "engine"],
+        )
+        .unwrap();
+
         Metrics {
             request_counter,
             inflight_gauge,
             request_duration,
+            output_tokens_per_second,
         }
     }
 
@@ -188,6 +226,7 @@ impl Metrics {
         registry.register(Box::new(self.request_counter.clone()))?;
         registry.register(Box::new(self.inflight_gauge.clone()))?;
         registry.register(Box::new(self.request_duration.clone()))?;
+        registry.register(Box::new(self.output_tokens_per_second.clone()))?;
         Ok(())
     }
 }
@@ -219,6 +258,56 @@ impl DeploymentState {
     }
 }
 
+impl DeploymentState {
+    /// Create a [`TokenTimer`] for a response of `model`
+    pub fn create_token_timer(&self, model: &str) -> TokenTimer {
+        TokenTimer {
+            metrics: self.metrics.clone(),
+            model: model.to_string(),
+            engine: self.engine_name(model),
+            first_token: None,
+            last_token: None,
+            chunks: 0,
+            output_tokens: None,
+        }
+    }
+}
+
+impl TokenTimer {
+    /// Call with each response, those with data carry tokens. Usually one per chunk.
+    pub fn observe<T>(&mut self, response: &Annotated<T>) {
+        if response.data.is_none() {
+            return;
+        }
+        let now = Instant::now();
+        self.first_token.get_or_insert(now);
+        self.last_token = Some(now);
+        self.chunks += 1;
+    }
+
+    /// The number of tokens generated, from the usage the engine reports. Without it each
+    /// chunk counts as a token.
+    pub fn set_output_tokens(&mut self, tokens: usize) {
+        self.output_tokens = Some(tokens);
+    }
+}
+
+impl Drop for TokenTimer {
+    fn drop(&mut self) {
+        let (Some(first), Some(last)) = (self.first_token, self.last_token) else {
+            return;
+        };
+        let decode = last.duration_since(first).as_secs_f64();
+        let tokens = self.output_tokens.unwrap_or(self.chunks);
+        if decode > 0.0 && tokens > 1 {
+            self.metrics
+                .output_tokens_per_second
+                .with_label_values(&[self.model.as_str(), self.engine.as_str()])
+                .set((tokens - 1) as f64 / decode);
+        }
+    }
+}
+
 impl InflightGuard {
     fn new(
         metrics: Arc<Metrics>,
@@ -276,6 +365,8 @@ impl std::fmt::Display for Endpoint {
         match self {
             Endpoint::Completions => write!(f, "completions"),
             Endpoint::ChatCompletions => write!(f, "chat_completions"),
+            Endpoint::Embeddings => write!(f, "embeddings"),
+            Endpoint::Transcriptions => write!(f, "transcriptions"),
         }
     }
 }
@@ -285,6 +376,8 @@ impl Endpoint {
         match self {
             Endpoint::Completions => "completions",
             Endpoint::ChatCompletions => "chat_completions",
+            Endpoint::Embeddings => "embeddings",
+            Endpoint::Transcriptions => "transcriptions",
         }
     }
 }
@@ -319,28 +412,16 @@ pub fn router(registry: Registry, path: Option<String>) -> (Vec<RouteDoc>, Route
 }
 
 /// Metrics Handler
+///
+/// Along with the HTTP service's own metrics, this reports everything in the process wide
+/// [`dynamo_runtime::metrics::registry`]: engine, router and block transfer metrics.
 async fn handler_metrics(State(registry): State<Arc<Registry>>) -> impl IntoResponse {
-    let encoder = prometheus::TextEncoder::new();
-    let metric_families = registry.gather();
-    let mut buffer = vec![];
-    if encoder.encode(&metric_families, &mut buffer).is_err() {
-        return (
+    match dynamo_runtime::metrics::encode_text(&[registry.as_ref()]) {
+        Ok(metrics) => (StatusCode::OK, metrics).into_response(),
+        Err(_) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             "Failed to encode metrics",
         )
-            .into_response();
+            .into_response(),
     }
-
-    let metrics = match String::from_utf8(buffer) {
-        Ok(metrics) => metrics,
-        Err(_) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to encode metrics",
-            )
-                .into_response()
-        }
-    };
-
-    (StatusCode::OK, metrics).into_response()
 }
@@ -0,0 +1,153 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `/admin/models`: load, swap and unload models while the service runs.
+//!
+//! - `POST /admin/models/load {"path": ..., "name": ...}` loads the model at `path` and serves
+//!   it as `name`, by default the name the model gets from its path. If a model of that name
+//!   is served already, requests go to the new one once it has loaded. Requests already
+//!   running finish on the old one, which is released after the last of them.
+//! - `POST /admin/models/unload {"name": ...}` stops serving a model. Requests already running
+//!   finish.
+//!
+//! The service doesn't load models itself, these routes are only there when the service was
+//! built with a [`ModelLoader`].
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use axum::{extract::State, http::StatusCode, routing::post, Json, Router};
+use serde::{Deserialize, Serialize};
+
+use super::openai::ErrorResponse;
+use super::{DeploymentState, RouteDoc};
+use crate::model_card::ModelDeploymentCard;
+use crate::preprocessor::OpenAIPreprocessor;
+use crate::types::openai::{
+    chat_completions::OpenAIChatCompletionsStreamingEngine,
+    completions::OpenAICompletionsStreamingEngine,
+};
+
+const LOAD_PATH: &str = "/admin/models/load";
+const UNLOAD_PATH: &str = "/admin/models/unload";
+
+/// Loads the models the admin routes ask for, usually by starting an engine in this process
+#[async_trait]
+pub trait ModelLoader: Send + Sync {
+    /// Load the model at `path`, a file, directory or Hugging Face repo, to be served as
+    /// `name`, or under the name of its card if None. Replaces the model of that name if the
+    /// loader has one.
+    async fn load(&self, path: &str, name: Option<&str>) -> anyhow::Result<LoadedModel>;
+
+    /// The service no longer serves `name`, release it once its running requests are done
+    async fn unload(&self, name: &str) -> anyhow::Result<()>;
+}
+
+/// A model a [`ModelLoader`] loaded, for the service to serve
+pub struct LoadedModel {
+    /// Names the model, and pre-processes tokenize requests if it has a tokenizer
+    pub card: ModelDeploymentCard,
+    pub chat: OpenAIChatCompletionsStreamingEngine,
+    pub completions: OpenAICompletionsStreamingEngine,
+}
+
+#[derive(Deserialize)]
+struct LoadRequest {
+    path: String,
+    name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct UnloadRequest {
+    name: String,
+}
+
+#[derive(Serialize)]
+struct LoadResponse {
+    name: String,
+    /// A model of that name was served before, it now finishes its running requests
+    replaced: bool,
+}
+
+#[derive(Clone)]
+struct AdminState {
+    deployment: Arc<DeploymentState>,
+    loader: Arc<dyn ModelLoader>,
+}
+
+pub(crate) fn admin_router(
+    deployment: Arc<DeploymentState>,
+    loader: Arc<dyn ModelLoader>,
+) -> (Vec<RouteDoc>, Router) {
+    let docs = vec![
+        RouteDoc::new(axum::http::Method::POST, LOAD_PATH),
+        RouteDoc::new(axum::http::Method::POST, UNLOAD_PATH),
+    ];
+    let router = Router::new()
+        .route(LOAD_PATH, post(load_model))
+        .route(UNLOAD_PATH, post(unload_model))
+        .with_state(AdminState { deployment, loader });
+    (docs, router)
+}
+
+async fn load_model(
+    State(state): State<AdminState>,
+    Json(request): Json<LoadRequest>,
+) -> Result<Json<LoadResponse>, (StatusCode, Json<ErrorResponse>)> {
+    if request.path.is_empty() {
+        return Err(ErrorResponse::bad_request("Missing model path"));
+    }
+    tracing::info!(path = request.path, name = request.name, "loading model");
+    let loaded = state
+        .loader
+        .load(&request.path, request.name.as_deref())
+        .await
+        .map_err(|err| ErrorResponse::from_anyhow(err, "Failed to load model"))?;
+    let preprocessor = if loaded.card.has_tokenizer() {
+        let preprocessor = OpenAIPreprocessor::new(loaded.card.clone())
+            .await
+            .map_err(|err| ErrorResponse::from_anyhow(err, "Failed to load model tokenizer"))?;
+        Some(preprocessor)
+    } else {
+        None
+    };
+    let name = loaded.card.service_name.clone();
+    let replaced = state.deployment.swap_model(
+        &name,
+        loaded.card.engine.as_deref(),
+        loaded.chat,
+        loaded.completions,
+        preprocessor,
+    );
+    tracing::info!(name, path = request.path, replaced, "serving loaded model");
+    Ok(Json(LoadResponse { name, replaced }))
+}
+
+async fn unload_model(
+    State(state): State<AdminState>,
+    Json(request): Json<UnloadRequest>,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    // Stop new requests first, the loader releases the model once the running ones are done
+    if !state.deployment.remove_model(&request.name) {
+        return Err(ErrorResponse::model_not_found());
+    }
+    state
+        .loader
+        .unload(&request.name)
+        .await
+        .map_err(|err| ErrorResponse::from_anyhow(err, "Failed to unload model"))?;
+    tracing::info!(name = request.name, "unloaded model");
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,611 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Content filtering of prompts and responses.
+//!
+//! A [`ContentFilter`] runs [`Moderator`]s over the text clients send, before anything is
+//! generated, and over the text of responses. A prompt is rejected with a 400 whose `code` is
+//! `content_filter`, or has what matched redacted. What a response generated so far is checked
+//! each time it grew by [`ContentFilter::scan_chars`], and held back until it is: a flagged
+//! response stops generating, and its choices end with the `content_filter` finish reason
+//! instead of the text that was flagged.
+//!
+//! Two moderators come with it. [`Rules`] are regexes and keywords from a JSON file:
+//!
+//! ```json
+//! [
+//!   {"name": "card-number", "pattern": "\\b(?:\\d[ -]?){13,16}\\b", "action": "redact"},
+//!   {"name": "banned", "keywords": ["foo", "bar"], "stages": ["response"]}
+//! ]
+//! ```
+//!
+//! A [`Classifier`] is an HTTP endpoint answering like OpenAI's `/v1/moderations`.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context as _;
+use async_openai::types::{
+    ChatChoiceStream, ChatCompletionRequestMessage, ChatCompletionStreamResponseDelta,
+    FinishReason, Prompt,
+};
+use async_trait::async_trait;
+use dynamo_runtime::pipeline::AsyncEngineContext;
+use futures::{Stream, StreamExt};
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::Value;
+
+use super::error::{ErrorKind, RequestError};
+use crate::protocols::openai::chat_completions::NvCreateChatCompletionStreamResponse;
+use crate::protocols::openai::completions::{CompletionChoice, CompletionResponse};
+use crate::types::Annotated;
+
+/// What the text being checked is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    /// A message or prompt of the request
+    Prompt,
+    /// What a response generated so far
+    Response,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    /// Use this text instead. Only for prompts, a response can only be blocked.
+    Redact(String),
+    /// Reject the prompt or stop the response, for this category
+    Block(String),
+}
+
+#[async_trait]
+pub trait Moderator: Send + Sync {
+    async fn check(&self, stage: Stage, text: &str) -> anyhow::Result<Verdict>;
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    #[default]
+    Block,
+    /// Replace what matched in prompts. A response was partly sent already, so there it
+    /// blocks.
+    Redact,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleSpec {
+    name: String,
+    #[serde(default)]
+    pattern: Option<String>,
+    /// Matched as whole words, ignoring case
+    #[serde(default)]
+    keywords: Vec<String>,
+    #[serde(default)]
+    action: Action,
+    #[serde(default = "both_stages")]
+    stages: Vec<Stage>,
+    #[serde(default)]
+    replacement: Option<String>,
+}
+
+fn both_stages() -> Vec<Stage> {
+    vec![Stage::Prompt, Stage::Response]
+}
+
+struct Rule {
+    name: String,
+    regex: Regex,
+    action: Action,
+    stages: Vec<Stage>,
+    replacement: String,
+}
+
+impl TryFrom<RuleSpec> for Rule {
+    type Error = anyhow::Error;
+
+    fn try_from(spec: RuleSpec) -> anyhow::Result<Self> {
+        let pattern = match (spec.pattern, spec.keywords.is_empty()) {
+            (Some(pattern), true) => pattern,
+            (None, false) => {
+                let words: Vec<_> = spec.keywords.iter().map(|k| regex::escape(k)).collect();
+                format!(r"(?i)\b(?:{})\b", words.join("|"))
+            }
+            _ => anyhow::bail!("Rule '{}' needs either a pattern or keywords", spec.name),
+        };
+        Ok(Rule {
+            regex: Regex::new(&pattern)
+                .with_context(|| format!("Invalid pattern of rule '{}'", spec.name))?,
+            name: spec.name,
+            action: spec.action,
+            stages: spec.stages,
+            replacement: spec.replacement.unwrap_or_else(|| "[REDACTED]".to_string()),
+        })
+    }
+}
+
+/// Regex and keyword rules, the first blocking rule that matches wins
+pub struct Rules(Vec<Rule>);
+
+impl Rules {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Rules::from_json(&text).with_context(|| format!("Invalid rules in {}", path.display()))
+    }
+
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        let specs: Vec<RuleSpec> = serde_json::from_str(json)?;
+        let rules = specs
+            .into_iter()
+            .map(Rule::try_from)
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Rules(rules))
+    }
+}
+
+#[async_trait]
+impl Moderator for Rules {
+    async fn check(&self, stage: Stage, text: &str) -> anyhow::Result<Verdict> {
+        let mut redacted: Option<String> = None;
+        for rule in self.0.iter().filter(|rule| rule.stages.contains(&stage)) {
+            let current = redacted.as_deref().unwrap_or(text);
+            if !rule.regex.is_match(current) {
+                continue;
+            }
+            match (rule.action, stage) {
+                (Action::Redact, Stage::Prompt) => {
+                    redacted = Some(
+                        rule.regex
+                            .replace_all(current, rule.replacement.as_str())
+                            .into_owned(),
+                    );
+                }
+                _ => return Ok(Verdict::Block(rule.name.clone())),
+            }
+        }
+        Ok(redacted.map_or(Verdict::Allow, Verdict::Redact))
+    }
+}
+
+/// An endpoint taking `{"input": "..."}` and answering like OpenAI's moderations API, with
+/// `{"results": [{"flagged": true, "categories": {"violence": true, ...}}]}`
+pub struct Classifier {
+    client: reqwest::Client,
+    url: String,
+    model: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ModerationResponse {
+    results: Vec<ModerationResult>,
+}
+
+#[derive(Deserialize)]
+struct ModerationResult {
+    flagged: bool,
+    #[serde(default)]
+    categories: HashMap<String, bool>,
+}
+
+impl Classifier {
+    /// `model` is sent along for endpoints serving several
+    pub fn new(url: &str, model: Option<String>, timeout: Duration) -> anyhow::Result<Self> {
+        Ok(Classifier {
+            client: reqwest::Client::builder().timeout(timeout).build()?,
+            url: url.to_string(),
+            model,
+        })
+    }
+}
+
+#[async_trait]
+impl Moderator for Classifier {
+    async fn check(&self, _stage: Stage, text: &str) -> anyhow::Result<Verdict> {
+        let mut body = serde_json::json!({ "input": text });
+        if let Some(model) = &self.model {
+            body["model"] = model.clone().into();
+        }
+        let response: ModerationResponse = self
+            .client
+            .post(&self.url)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let Some(result) = response.results.into_iter().find(|result| result.flagged) else {
+            return Ok(Verdict::Allow);
+        };
+        let mut categories: Vec<_> = result
+            .categories
+            .into_iter()
+            .filter_map(|(category, flagged)| flagged.then_some(category))
+            .collect();
+        categories.sort();
+        if categories.is_empty() {
+            categories.push("flagged".to_string());
+        }
+        Ok(Verdict::Block(categories.join(", ")))
+    }
+}
+
+pub struct ContentFilter {
+    moderators: Vec<Arc<dyn Moderator>>,
+    scan_chars: usize,
+}
+
+impl ContentFilter {
+    /// Each text goes through `moderators` in turn. A response is checked again once it grew
+    /// by `scan_chars` characters.
+    pub fn new(moderators: Vec<Arc<dyn Moderator>>, scan_chars: usize) -> Self {
+        ContentFilter {
+            moderators,
+            scan_chars: scan_chars.max(1),
+        }
+    }
+
+    pub fn scan_chars(&self) -> usize {
+        self.scan_chars
+    }
+
+    /// Check each message's text, redacting it in place
+    pub async fn check_messages(
+        &self,
+        messages: &mut Vec<ChatCompletionRequestMessage>,
+    ) -> Result<(), RequestError> {
+        let mut value = serde_json::to_value(&*messages).map_err(internal)?;
+        let mut redacted = false;
+        for message in value.as_array_mut().into_iter().flatten() {
+            match message.get_mut("content") {
+                Some(Value::String(text)) => redacted |= self.check_prompt(text).await?,
+                Some(Value::Array(parts)) => {
+                    for part in parts {
+                        if let Some(Value::String(text)) = part.get_mut("text") {
+                            redacted |= self.check_prompt(text).await?;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        if redacted {
+            *messages = serde_json::from_value(value).map_err(internal)?;
+        }
+        Ok(())
+    }
+
+    /// Check the text of a completions prompt, redacting it in place. Token ids aren't checked.
+    pub async fn check_completion_prompt(&self, prompt: &mut Prompt) -> Result<(), RequestError> {
+        match prompt {
+            Prompt::String(text) => {
+                self.check_prompt(text).await?;
+            }
+            Prompt::StringArray(texts) => {
+                for text in texts {
+                    self.check_prompt(text).await?;
+                }
+            }
+            Prompt::IntegerArray(_) | Prompt::ArrayOfIntegerArray(_) => {}
+        }
+        Ok(())
+    }
+
+    /// Whether `text` was redacted
+    async fn check_prompt(&self, text: &mut String) -> Result<bool, RequestError> {
+        let mut redacted = false;
+        for moderator in &self.moderators {
+            match moderator.check(Stage::Prompt, text).await.map_err(failed)? {
+                Verdict::Allow => {}
+                Verdict::Redact(new) => {
+                    *text = new;
+                    redacted = true;
+                }
+                Verdict::Block(category) => {
+                    return Err(RequestError::new(
+                        ErrorKind::ContentFilter,
+                        format!("The prompt was rejected by the content filter: {category}"),
+                    ))
+                }
+            }
+        }
+        Ok(redacted)
+    }
+
+    /// A [`ErrorKind::ContentFilter`] error if `text`, what a response generated so far, is
+    /// flagged
+    pub async fn check_response(&self, text: &str) -> Result<(), RequestError> {
+        for moderator in &self.moderators {
+            if let Verdict::Block(category) = moderator
+                .check(Stage::Response, text)
+                .await
+                .map_err(failed)?
+            {
+                return Err(RequestError::new(
+                    ErrorKind::ContentFilter,
+                    format!("The response was stopped by the content filter: {category}"),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn internal(err: serde_json::Error) -> RequestError {
+    RequestError::new(ErrorKind::Internal, format!("Content filter: {err}"))
+}
+
+/// A moderator we could not ask. We don't let text through unchecked.
+fn failed(err: anyhow::Error) -> RequestError {
+    tracing::warn!("Content filter failed: {err:#}");
+    RequestError::new(
+        ErrorKind::Unavailable,
+        format!("The content filter is unavailable: {err}"),
+    )
+}
+
+/// Streamed responses whose choices' text can be checked
+pub(crate) trait FilteredChunk: Sized {
+    /// The index, new text and whether it finished, of each choice in this chunk
+    fn choice_texts(&self) -> Vec<(u32, &str, bool)>;
+
+    /// A chunk like this one, ending the choices of `indexes` with the `content_filter`
+    /// finish reason
+    fn content_filter_chunk(&self, indexes: &[u32]) -> Self;
+}
+
+impl FilteredChunk for NvCreateChatCompletionStreamResponse {
+    fn choice_texts(&self) -> Vec<(u32, &str, bool)> {
+        self.inner
+            .choices
+            .iter()
+            .map(|choice| {
+                let text = choice.delta.content.as_deref().unwrap_or_default();
+                (choice.index, text, choice.finish_reason.is_some())
+            })
+            .collect()
+    }
+
+    fn content_filter_chunk(&self, indexes: &[u32]) -> Self {
+        let mut inner = self.inner.clone();
+        inner.usage = None;
+        inner.choices = indexes
+            .iter()
+            .map(|index| ChatChoiceStream {
+                index: *index,
+                delta: ChatCompletionStreamResponseDelta {
+                    content: None,
+                    function_call: None,
+                    tool_calls: None,
+                    role: None,
+                    refusal: None,
+                },
+                finish_reason: Some(FinishReason::ContentFilter),
+                logprobs: None,
+            })
+            .collect();
+        NvCreateChatCompletionStreamResponse { inner, nvext: None }
+    }
+}
+
+impl FilteredChunk for CompletionResponse {
+    fn choice_texts(&self) -> Vec<(u32, &str, bool)> {
+        self.choices
+            .iter()
+            .map(|choice| {
+                let index = choice.index as u32;
+                (index, choice.text.as_str(), choice.finish_reason.is_some())
+            })
+            .collect()
+    }
+
+    fn content_filter_chunk(&self, indexes: &[u32]) -> Self {
+        let choices = indexes
+            .iter()
+            .map(
+                |index| crate::protocols::openai::completions::CompletionChoice {
+                    text: String::new(),
+                    index: *index as u64,
+                    finish_reason: Some("content_filter".to_string()),
+                    logprobs: None,
+                },
+            )
+            .collect();
+        CompletionResponse {
+            choices,
+            usage: None,
+            nvext: None,
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(Default)]
+struct Scanned {
+    text: String,
+    /// Length of `text` when it was last checked
+    checked: usize,
+    finished: bool,
+    /// The chunk finishing it went out
+    finish_sent: bool,
+}
+
+/// Hold the responses of `stream` back until the text of their choices was checked. A flagged
+/// response stops generating and ends with a chunk finishing its choices with the
+/// `content_filter` finish reason.
+pub(crate) fn with_content_filter<R>(
+    stream: impl Stream<Item = Annotated<R>> + Send + 'static,
+    filter: Arc<ContentFilter>,
+    context: Arc<dyn AsyncEngineContext>,
+) -> impl Stream<Item = Annotated<R>> + Send
+where
+    R: FilteredChunk + Clone + Send + 'static,
+{
+    async_stream::stream! {
+        let mut stream = std::pin::pin!(stream);
+        let mut choices: HashMap<u32, Scanned> = HashMap::new();
+        let mut held = Vec::new();
+        let mut last = None;
+        loop {
+            let response = stream.next().await;
+            let ended = match &response {
+                Some(response) => response.is_error(),
+                None => true,
+            };
+            if let Some(data) = response.as_ref().and_then(|response| response.data.as_ref()) {
+                for (index, text, finished) in data.choice_texts() {
+                    let choice = choices.entry(index).or_default();
+                    choice.text.push_str(text);
+                    choice.finished |= finished;
+                }
+                last = Some(data.clone());
+            }
+            held.extend(response);
+
+            // releasing what is held releases the text of every choice, so all of it is checked
+            let unchecked: Vec<_> = choices
+                .values()
+                .filter(|choice| choice.text.len() > choice.checked)
+                .collect();
+            let due = ended
+                || unchecked.iter().any(|choice| {
+                    choice.finished || choice.text.len() - choice.checked >= filter.scan_chars
+                });
+            if !unchecked.is_empty() && !due {
+                continue;
+            }
+            let mut flagged = None;
+            for text in unchecked.into_iter().map(|choice| choice.text.clone()) {
+                // as a task, so that this stream stays Sync for folding it
+                let filter = filter.clone();
+                let checked = tokio::spawn(async move { filter.check_response(&text).await })
+                    .await
+                    .unwrap_or_else(|err| {
+                        Err(RequestError::new(ErrorKind::Internal, err.to_string()))
+                    });
+                if let Err(err) = checked {
+                    flagged = Some(err);
+                    break;
+                }
+            }
+            if let Some(err) = flagged {
+                context.stop_generating();
+                if err.kind != ErrorKind::ContentFilter {
+                    yield Annotated::from_error(err.to_string());
+                    return;
+                }
+                tracing::info!(request_id = context.id(), "{}", err.message);
+                let mut open: Vec<_> = choices
+                    .iter()
+                    .filter(|(_, choice)| !choice.finish_sent)
+                    .map(|(index, _)| *index)
+                    .collect();
+                open.sort();
+                if let (Some(last), false) = (&last, open.is_empty()) {
+                    yield Annotated::from_data(last.content_filter_chunk(&open));
+                }
+                return;
+            }
+            for choice in choices.values_mut() {
+                choice.checked = choice.text.len();
+                choice.finish_sent = choice.finished;
+            }
+            for response in held.drain(..) {
+                yield response;
+            }
+            if ended {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: &str = r#"[
+        {"name": "card-number", "pattern": "\\b\\d{4}-\\d{4}-\\d{4}-\\d{4}\\b", "action": "redact"},
+        {"name": "banned", "keywords": ["Foo", "bar baz"], "stages": ["response"]},
+        {"name": "secret", "keywords": ["password"]}
+    ]"#;
+
+    #[tokio::test]
+    async fn test_rules() {
+        let rules = Rules::from_json(RULES).unwrap();
+        assert_eq!(
+            rules
+                .check(Stage::Prompt, "Pay with 1234-5678-9012-3456 please")
+                .await
+                .unwrap(),
+            Verdict::Redact("Pay with [REDACTED] please".to_string())
+        );
+        assert_eq!(
+            rules.check(Stage::Prompt, "foo bar baz").await.unwrap(),
+            Verdict::Allow
+        );
+        assert_eq!(
+            rules.check(Stage::Response, "so, FOO!").await.unwrap(),
+            Verdict::Block("banned".to_string())
+        );
+        assert_eq!(
+            rules.check(Stage::Response, "food").await.unwrap(),
+            Verdict::Allow
+        );
+        // a redact rule blocks responses
+        assert_eq!(
+            rules
+                .check(Stage::Response, "1234-5678-9012-3456")
+                .await
+                .unwrap(),
+            Verdict::Block("card-number".to_string())
+        );
+        assert_eq!(
+            rules
+                .check(Stage::Prompt, "my Password is 1234-5678-9012-3456")
+                .await
+                .unwrap(),
+            Verdict::Block("secret".to_string())
+        );
+        assert!(Rules::from_json(r#"[{"name": "empty"}]"#).is_err());
+        assert!(Rules::from_json(r#"[{"name": "bad", "pattern": "("}]"#).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_check_completion_prompt() {
+        let filter = ContentFilter::new(vec![Arc::new(Rules::from_json(RULES).unwrap())], 16);
+        let mut prompt = Prompt::StringArray(vec![
+            "hello".to_string(),
+            "card 1234-5678-9012-3456".to_string(),
+        ]);
+        filter.check_completion_prompt(&mut prompt).await.unwrap();
+        assert_eq!(
+            prompt,
+            Prompt::StringArray(vec!["hello".to_string(), "card [REDACTED]".to_string()])
+        );
+
+        let mut prompt = Prompt::String("the password".to_string());
+        let err = filter
+            .check_completion_prompt(&mut prompt)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::ContentFilter);
+    }
+}
@@ -14,8 +14,8 @@
 // limitations under the License.
 
 use axum::{
-    extract::State,
-    http::StatusCode,
+    extract::{DefaultBodyLimit, Multipart, Path, State},
+    http::{HeaderMap, HeaderValue, StatusCode},
     response::{
         sse::{Event, KeepAlive, Sse},
         IntoResponse, Response,
@@ -27,55 +27,125 @@ use futures::{Stream, StreamExt};
 use serde::{Deserialize, Serialize};
 use std::{
     collections::{HashMap, HashSet},
+    future::Future,
     pin::Pin,
-    sync::Arc,
-    time::{SystemTime, UNIX_EPOCH},
+    sync::{Arc, Mutex},
+    time::{Instant, SystemTime, UNIX_EPOCH},
 };
 use tokio_stream::wrappers::ReceiverStream;
 
-use super::DeploymentState;
+use super::access_log::AccessRecord;
+use super::admission::{Admitted, PRIORITY_HEADER, QUEUE_DEPTH_HEADER};
+use super::auth::Access;
+use super::coalesce::{coalesce, StreamCoalescing};
+use super::conversations::Conversations;
+use super::limits::Deadline;
+use super::moderation::with_content_filter;
+use super::rate_limit::TokenMeter;
+use super::shedding::RequestTimer;
+use super::timings::{with_timings, ResponseTimer};
+use super::usage::with_stream_usage;
 use super::{
-    error::HttpError,
+    error::{ErrorKind, HttpError, RequestError, ServiceHttpError},
     metrics::{Endpoint, InflightGuard},
+    response_cache::{Lookup, ResponseCache},
     RouteDoc,
 };
+use super::{DeploymentState, ModelEngines};
 
+use crate::preprocessor::MAX_PROMPT_TOKENS_CONTEXT_KEY;
+use crate::presets::PresetLibrary;
 use crate::protocols::openai::{
-    chat_completions::NvCreateChatCompletionResponse, completions::CompletionResponse,
+    chat_completions::NvCreateChatCompletionResponse,
+    completions::CompletionResponse,
+    embeddings::{NvCreateEmbeddingRequest, NvCreateEmbeddingResponse},
+    nvext::{NvExt, Priority},
+    tokenize::{
+        DetokenizeRequest, DetokenizeResponse, TokenizeInput, TokenizeRequest, TokenizeResponse,
+    },
+    transcriptions::{
+        NvCreateTranscriptionRequest, NvCreateTranscriptionResponse, TranscriptionFormat,
+    },
 };
 use crate::request_template::RequestTemplate;
+use crate::tokenizers::pool::TokenizerPool;
 use crate::types::{
     openai::{chat_completions::NvCreateChatCompletionRequest, completions::CompletionRequest},
     Annotated,
 };
 
-use dynamo_runtime::pipeline::{AsyncEngineContext, Context};
+use dynamo_runtime::pipeline::{
+    network::egress::routing_hints::{
+        parse_worker_id, PINNED_WORKER_CONTEXT_KEY, PINNED_WORKER_HEADER,
+        ROUTING_HINTS_CONTEXT_KEY, ROUTING_HINTS_HEADER,
+    },
+    AsyncEngineContext, Context, RoutingHints,
+};
+
+/// Response header with the id to cancel a request with
+pub(super) const REQUEST_ID_HEADER: &str = "x-request-id";
 
 #[derive(Serialize, Deserialize)]
 pub(crate) struct ErrorResponse {
     error: String,
+
+    /// What went wrong, for clients deciding whether to retry. See [`ErrorKind`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    code: Option<ErrorKind>,
 }
 
 impl ErrorResponse {
+    /// The body of an error response
+    pub fn json(msg: &str) -> Json<ErrorResponse> {
+        Json(ErrorResponse {
+            error: msg.to_string(),
+            code: None,
+        })
+    }
+
+    /// The body of an error response of `kind`
+    pub fn json_of_kind(kind: ErrorKind, msg: &str) -> Json<ErrorResponse> {
+        Json(ErrorResponse {
+            error: msg.to_string(),
+            code: Some(kind),
+        })
+    }
+
+    /// An error response of `kind`, with its status
+    pub fn of_kind(kind: ErrorKind, msg: &str) -> (StatusCode, Json<ErrorResponse>) {
+        if kind == ErrorKind::Internal {
+            tracing::error!("Internal server error: {msg}");
+        }
+        let status = StatusCode::from_u16(kind.status()).unwrap();
+        (status, ErrorResponse::json_of_kind(kind, msg))
+    }
+
     /// Not Found Error
     pub fn model_not_found() -> (StatusCode, Json<ErrorResponse>) {
-        (
-            StatusCode::NOT_FOUND,
-            Json(ErrorResponse {
-                error: "Model not found".to_string(),
-            }),
-        )
+        ErrorResponse::of_kind(ErrorKind::NotFound, "Model not found")
+    }
+
+    /// Not Found Error with a custom message, for resources other than models
+    pub fn not_found(msg: &str) -> (StatusCode, Json<ErrorResponse>) {
+        ErrorResponse::of_kind(ErrorKind::NotFound, msg)
+    }
+
+    /// Bad Request
+    /// The request was malformed or asked for something we do not support.
+    pub fn bad_request(msg: &str) -> (StatusCode, Json<ErrorResponse>) {
+        ErrorResponse::of_kind(ErrorKind::InvalidRequest, msg)
     }
 
     /// Service Unavailable
     /// This is returned when the service is live, but not ready.
     pub fn _service_unavailable() -> (StatusCode, Json<ErrorResponse>) {
-        (
-            StatusCode::SERVICE_UNAVAILABLE,
-            Json(ErrorResponse {
-                error: "Service is not ready".to_string(),
-            }),
-        )
+        ErrorResponse::of_kind(ErrorKind::Unavailable, "Service is not ready")
+    }
+
+    /// Gateway Timeout
+    /// The request ran past the deployment's timeout and was stopped.
+    pub fn gateway_timeout(msg: &str) -> (StatusCode, Json<ErrorResponse>) {
+        ErrorResponse::of_kind(ErrorKind::Timeout, msg)
     }
 
     /// Internal Service Error
@@ -83,41 +153,63 @@ impl ErrorResponse {
     /// We should return a generic message to the client instead of the real error.
     /// Internal Services errors are the result of misconfiguration or bugs in the service.
     pub fn internal_server_error(msg: &str) -> (StatusCode, Json<ErrorResponse>) {
-        tracing::error!("Internal server error: {msg}");
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: msg.to_string(),
-            }),
-        )
+        ErrorResponse::of_kind(ErrorKind::Internal, msg)
     }
 
     /// The OAI endpoints call an [`dynamo.runtime::engine::AsyncEngine`] which are specialized to return
     /// an [`anyhow::Error`]. This method will convert the [`anyhow::Error`] into an [`HttpError`].
     /// If successful, it will return the [`HttpError`] as an [`ErrorResponse::internal_server_error`]
-    /// with the details of the error.
+    /// with the details of the error. A [`RequestError`], and the errors
+    /// [`RequestError::classify`] knows, get the status of their kind.
     pub fn from_anyhow(err: anyhow::Error, alt_msg: &str) -> (StatusCode, Json<ErrorResponse>) {
-        match err.downcast::<HttpError>() {
-            Ok(http_error) => ErrorResponse::from_http_error(http_error),
-            Err(err) => ErrorResponse::internal_server_error(&format!("{alt_msg}: {err}")),
+        let err = match err.downcast::<HttpError>() {
+            Ok(http_error) => return ErrorResponse::from_http_error(http_error),
+            Err(err) => err,
+        };
+        match RequestError::classify(&err) {
+            Some(err) => ErrorResponse::from_request_error(err),
+            None => ErrorResponse::internal_server_error(&format!("{alt_msg}: {err}")),
         }
     }
 
-    /// Implementers should only be able to throw 400-499 errors.
+    /// Implementers should only be able to throw 400-499 errors, and 503 when they can't
+    /// serve the request for now.
     pub fn from_http_error(err: HttpError) -> (StatusCode, Json<ErrorResponse>) {
-        if err.code < 400 || err.code >= 500 {
+        if !(400..500).contains(&err.code) && err.code != 503 {
             return ErrorResponse::internal_server_error(&err.message);
         }
         match StatusCode::from_u16(err.code) {
-            Ok(code) => (code, Json(ErrorResponse { error: err.message })),
+            Ok(code) => (
+                code,
+                Json(ErrorResponse {
+                    code: Some(ErrorKind::from_status(err.code)),
+                    error: err.message,
+                }),
+            ),
             Err(_) => ErrorResponse::internal_server_error(&err.message),
         }
     }
+
+    pub fn from_request_error(err: RequestError) -> (StatusCode, Json<ErrorResponse>) {
+        ErrorResponse::of_kind(err.kind, &err.message)
+    }
+
+    /// The error of a stream that failed while folding it into one response. The kind is in
+    /// the message if the engine gave one, else it's an internal error.
+    pub fn from_stream_error(err: &str, alt_msg: &str) -> (StatusCode, Json<ErrorResponse>) {
+        match RequestError::from_event_message(err) {
+            Some(err) => ErrorResponse::from_request_error(err),
+            None => ErrorResponse::internal_server_error(&format!("{alt_msg}: {err}")),
+        }
+    }
 }
 
 impl From<HttpError> for ErrorResponse {
     fn from(err: HttpError) -> Self {
-        ErrorResponse { error: err.message }
+        ErrorResponse {
+            code: Some(ErrorKind::from_status(err.code)),
+            error: err.message,
+        }
     }
 }
 
@@ -132,16 +224,41 @@ impl From<HttpError> for ErrorResponse {
 #[tracing::instrument(skip_all)]
 async fn completions(
     State(state): State<Arc<DeploymentState>>,
-    Json(request): Json<CompletionRequest>,
+    headers: HeaderMap,
+    access: Access,
+    mut meter: TokenMeter,
+    record: AccessRecord,
+    Json(mut request): Json<CompletionRequest>,
 ) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let received = Instant::now();
+
+    record.set_model(&request.inner.model);
+
     // return a 503 if the service is not ready
     check_ready(&state)?;
 
+    access.check_model(&request.inner.model)?;
+
+    // before the prompt is logged, it may have been redacted
+    let content_filter = state.content_filter.lock().unwrap().clone();
+    if let Some(filter) = &content_filter {
+        filter
+            .check_completion_prompt(&mut request.inner.prompt)
+            .await
+            .map_err(ErrorResponse::from_request_error)?;
+    }
+    record.set_prompt(|| serde_json::to_string(&request.inner.prompt).unwrap_or_default());
+
+    let routing_hints = routing_hints(&headers)?;
+    let routing_hints = with_user_session(routing_hints, request.inner.user.as_deref());
+    let pinned_worker = pinned_worker(&headers, &access)?;
+
     // todo - extract distributed tracing id and context id from headers
     let request_id = uuid::Uuid::new_v4().to_string();
 
     // todo - decide on default
     let streaming = request.inner.stream.unwrap_or(false);
+    let include_usage = include_usage(request.inner.stream_options.as_ref());
 
     // update the request to always stream
     let inner = async_openai::types::CreateCompletionRequest {
@@ -149,64 +266,145 @@ async fn completions(
         ..request.inner
     };
 
-    let request = CompletionRequest {
+    let mut request = CompletionRequest {
         inner,
         nvext: request.nvext,
     };
-
-    // todo - make the protocols be optional for model name
-    // todo - when optional, if none, apply a default
-    let model = &request.inner.model;
+    let wants_timings = wants_timings(&request.nvext);
+    let priority = priority(&headers, &mut request.nvext)?;
+    let coalescing = StreamCoalescing::for_request(
+        *state.stream_coalescing.lock().unwrap(),
+        request.nvext.as_ref(),
+    );
+    let limits = *state.request_limits.lock().unwrap();
+    request.inner.max_tokens = limits
+        .max_tokens(request.inner.max_tokens)
+        .map_err(|msg| ErrorResponse::bad_request(&msg))?;
+    let deadline = limits.deadline(received);
 
     // todo - error handling should be more robust
     let engine = state
-        .get_completions_engine(model)
+        .get_completions_engine(&request.inner.model)
         .map_err(|_| ErrorResponse::model_not_found())?;
 
+    let mut timer = admit(&state, &request.inner.model, received)?;
+    if let Some(cap) = timer.as_ref().and_then(RequestTimer::max_tokens) {
+        request.inner.max_tokens = Some(request.inner.max_tokens.map_or(cap, |n| n.min(cap)));
+    }
+    let admitted = match wait_for_slot(&state, &request.inner.model, priority).await {
+        Ok(admitted) => admitted,
+        Err(queue_full) => return Ok(queue_full),
+    };
+
+    // todo - make the protocols be optional for model name
+    // todo - when optional, if none, apply a default
+    let model = &request.inner.model;
+
     // this will increment the inflight gauge for the model
     let mut inflight = state.create_inflight_guard(model, Endpoint::Completions, streaming);
+    let mut token_timer = state.create_token_timer(model);
 
     // setup context
     // todo - inherit request_id from distributed trace details
-    let request = Context::with_id(request, request_id.clone());
+    let mut request = Context::with_id(request, request_id.clone());
+    if let Some(hints) = routing_hints {
+        request.insert(ROUTING_HINTS_CONTEXT_KEY, hints);
+    }
+    if let Some(worker) = pinned_worker {
+        request.insert(PINNED_WORKER_CONTEXT_KEY, worker);
+    }
+    if let Some(max_prompt_tokens) = limits.max_prompt_tokens {
+        request.insert(MAX_PROMPT_TOKENS_CONTEXT_KEY, max_prompt_tokens as usize);
+    }
+    let mut timings = wants_timings.then(|| ResponseTimer::start(&mut request, received));
 
     // issue the generate call on the engine
-    let stream = engine
-        .generate(request)
-        .await
+    let stream = before_deadline(deadline, engine.generate(request))
+        .await?
         .map_err(|e| ErrorResponse::from_anyhow(e, "Failed to generate completions"))?;
+    if let Some(timer) = &timer {
+        timer.accepted();
+    }
+    if let Some(timings) = &mut timings {
+        timings.accepted();
+    }
 
     // capture the context to cancel the stream if the client disconnects
     let ctx = stream.context();
+    // or asks to cancel it, until the stream is done
+    let running = state.register_running(&request_id, ctx.clone());
 
     // todo - tap the stream and propagate request level metrics
     // note - we might do this as part of the post processing set to make it more generic
+    let stream = stream.inspect(move |response| {
+        let _running = &running;
+        let _admitted = &admitted;
+        if let Some(timer) = &mut timer {
+            timer.observe(response);
+        }
+        token_timer.observe(response);
+        record.observe(response);
+        if let Some(usage) = response.data.as_ref().and_then(|r| r.usage.as_ref()) {
+            token_timer.set_output_tokens(usage.completion_tokens as usize);
+            record.set_usage(usage.prompt_tokens as u32, usage.completion_tokens as u32);
+        }
+    });
+    let stream = match content_filter {
+        Some(filter) => with_content_filter(stream, filter, ctx.clone()).left_stream(),
+        None => stream.right_stream(),
+    };
+    let stream = match timings {
+        Some(timings) => with_timings(stream, timings).left_stream(),
+        None => stream.right_stream(),
+    };
 
     if streaming {
+        let stream = match deadline {
+            Some(deadline) => deadline.limit(stream, ctx.clone()).left_stream(),
+            None => stream.right_stream(),
+        };
+        let stream = stream.inspect(move |response| {
+            if let Some(usage) = response.data.as_ref().and_then(|r| r.usage.as_ref()) {
+                meter.observe((usage.prompt_tokens + usage.completion_tokens) as u32);
+            }
+        });
+        let stream = with_stream_usage(stream, include_usage);
         let stream = stream.map(|response| Event::try_from(EventConverter::from(response)));
         let stream = monitor_for_disconnects(stream.boxed(), ctx, inflight).await;
+        let stream = match coalescing {
+            Some(coalescing) => coalesce(stream, coalescing).left_stream(),
+            None => stream.right_stream(),
+        };
 
         let mut sse_stream = Sse::new(stream);
 
-        if let Some(keep_alive) = state.sse_keep_alive {
+        if let Some(keep_alive) = *state.sse_keep_alive.lock().unwrap() {
             sse_stream = sse_stream.keep_alive(KeepAlive::default().interval(keep_alive));
         }
 
-        Ok(sse_stream.into_response())
+        Ok(with_request_id(sse_stream.into_response(), &request_id))
     } else {
-        let response = CompletionResponse::from_annotated_stream(stream.into())
+        let fold = CompletionResponse::from_annotated_stream(Box::pin(stream));
+        let response = before_deadline(deadline, fold)
             .await
+            .inspect_err(|_| ctx.stop_generating())?
             .map_err(|e| {
                 tracing::error!(
                     "Failed to fold completions stream for {}: {:?}",
                     request_id,
                     e
                 );
-                ErrorResponse::internal_server_error("Failed to fold completions stream")
+                ErrorResponse::from_stream_error(
+                    &e.to_string(),
+                    "Failed to fold completions stream",
+                )
             })?;
 
+        if let Some(usage) = &response.usage {
+            meter.observe((usage.prompt_tokens + usage.completion_tokens) as u32);
+        }
         inflight.mark_ok();
-        Ok(Json(response).into_response())
+        Ok(with_request_id(Json(response).into_response(), &request_id))
     }
 }
 
@@ -220,12 +418,31 @@ async fn completions(
 /// non-streaming requests, we will fold the stream into a single response as part of this handler.
 #[tracing::instrument(skip_all)]
 async fn chat_completions(
-    State((state, template)): State<(Arc<DeploymentState>, Option<RequestTemplate>)>,
+    State((state, template, cache, presets, conversations)): State<ChatCompletionsState>,
+    headers: HeaderMap,
+    access: Access,
+    mut meter: TokenMeter,
+    record: AccessRecord,
     Json(mut request): Json<NvCreateChatCompletionRequest>,
 ) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let received = Instant::now();
+
     // return a 503 if the service is not ready
     check_ready(&state)?;
 
+    let routing_hints = routing_hints(&headers)?;
+    let session_id = request
+        .nvext
+        .as_ref()
+        .and_then(|nvext| nvext.session_id.clone())
+        .filter(|session_id| !session_id.is_empty());
+    // a conversation's turns share a session, and so the worker caching its prefix
+    let routing_hints = with_user_session(
+        routing_hints,
+        session_id.as_deref().or(request.inner.user.as_deref()),
+    );
+    let pinned_worker = pinned_worker(&headers, &access)?;
+
     // Apply template values if present
     if let Some(template) = template {
         if request.inner.model.is_empty() {
@@ -238,13 +455,53 @@ async fn chat_completions(
             request.inner.max_completion_tokens = Some(template.max_completion_tokens);
         }
     }
+    // only what the client sent, the conversation's earlier turns and the preset were checked
+    // or are ours
+    let content_filter = state.content_filter.lock().unwrap().clone();
+    if let Some(filter) = &content_filter {
+        filter
+            .check_messages(&mut request.inner.messages)
+            .await
+            .map_err(ErrorResponse::from_request_error)?;
+    }
+    // the preset's messages go before the whole conversation, and are not part of it
+    let mut turn = match (&session_id, &conversations) {
+        (None, _) => None,
+        (Some(_), None) => {
+            return Err(ErrorResponse::bad_request(
+                "nvext.session_id is not supported: conversations are not kept by this server",
+            ))
+        }
+        (Some(session_id), Some(conversations)) => Some(
+            conversations
+                .continue_conversation(access.client(), session_id, &mut request)
+                .await
+                .map_err(|e| {
+                    ErrorResponse::internal_server_error(&format!(
+                        "Failed to load conversation: {e}"
+                    ))
+                })?,
+        ),
+    };
+    // before the cache key, the preset's messages are part of the request
+    if let Some(presets) = presets {
+        presets
+            .apply(&mut request)
+            .map_err(|msg| ErrorResponse::bad_request(&msg))?;
+    }
     tracing::trace!("Received chat completions request: {:?}", request.inner);
+    record.set_model(&request.inner.model);
+    record.set_prompt(|| serde_json::to_string(&request.inner.messages).unwrap_or_default());
+
+    // before the cache, which would otherwise answer for any model
+    access.check_model(&request.inner.model)?;
 
     // todo - extract distributed tracing id and context id from headers
     let request_id = uuid::Uuid::new_v4().to_string();
 
     // todo - decide on default
     let streaming = request.inner.stream.unwrap_or(false);
+    let include_usage = include_usage(request.inner.stream_options.as_ref());
 
     // update the request to always stream
     let inner_request = async_openai::types::CreateChatCompletionRequest {
@@ -252,10 +509,63 @@ async fn chat_completions(
         ..request.inner
     };
 
-    let request = NvCreateChatCompletionRequest {
+    let mut request = NvCreateChatCompletionRequest {
         inner: inner_request,
         nvext: request.nvext,
     };
+    let wants_timings = wants_timings(&request.nvext);
+    let priority = priority(&headers, &mut request.nvext)?;
+    let coalescing = StreamCoalescing::for_request(
+        *state.stream_coalescing.lock().unwrap(),
+        request.nvext.as_ref(),
+    );
+    let limits = *state.request_limits.lock().unwrap();
+    if limits.max_output_tokens.is_some() {
+        // ALLOW: max_tokens is deprecated in favor of max_completion_tokens
+        #[allow(deprecated)]
+        let max_tokens = request
+            .inner
+            .max_completion_tokens
+            .or(request.inner.max_tokens);
+        request.inner.max_completion_tokens = limits
+            .max_tokens(max_tokens)
+            .map_err(|msg| ErrorResponse::bad_request(&msg))?;
+    }
+    let deadline = limits.deadline(received);
+
+    // serve deterministic non-streaming requests from the cache if we can, unless the client
+    // wants to know how long generating took, or is in a conversation the cached response would
+    // not be added to
+    let cache_key = match &cache {
+        Some(cache)
+            if !streaming
+                && !wants_timings
+                && turn.is_none()
+                && cache.caches(&request.inner.model) =>
+        {
+            ResponseCache::key(&request).map(|key| (cache.clone(), key))
+        }
+        _ => None,
+    };
+    if let Some((cache, key)) = &cache_key {
+        match cache.lookup(key) {
+            Lookup::Fresh(response) => return Ok(Json(response).into_response()),
+            Lookup::Stale(response) => {
+                tokio::spawn(refresh_cached_response(
+                    state.clone(),
+                    cache.clone(),
+                    key.clone(),
+                    request,
+                ));
+                return Ok(Json(response).into_response());
+            }
+            Lookup::Miss => {
+                if let Some(response) = cache.lookup_shared(key).await {
+                    return Ok(Json(response).into_response());
+                }
+            }
+        }
+    }
 
     // todo - make the protocols be optional for model name
     // todo - when optional, if none, apply a default
@@ -268,60 +578,552 @@ async fn chat_completions(
         .get_chat_completions_engine(model)
         .map_err(|_| ErrorResponse::model_not_found())?;
 
+    let mut timer = admit(&state, model, received)?;
+    if let Some(cap) = timer.as_ref().and_then(RequestTimer::max_tokens) {
+        // ALLOW: max_tokens is deprecated in favor of max_completion_tokens
+        #[allow(deprecated)]
+        let max_tokens = request
+            .inner
+            .max_completion_tokens
+            .or(request.inner.max_tokens);
+        request.inner.max_completion_tokens = Some(max_tokens.map_or(cap, |n| n.min(cap)));
+    }
+    let admitted = match wait_for_slot(&state, &request.inner.model, priority).await {
+        Ok(admitted) => admitted,
+        Err(queue_full) => return Ok(queue_full),
+    };
+    let model = &request.inner.model;
+
     // this will increment the inflight gauge for the model
     let mut inflight = state.create_inflight_guard(model, Endpoint::ChatCompletions, streaming);
+    let mut token_timer = state.create_token_timer(model);
 
     // setup context
     // todo - inherit request_id from distributed trace details
-    let request = Context::with_id(request, request_id.clone());
+    let mut request = Context::with_id(request, request_id.clone());
+    if let Some(hints) = routing_hints {
+        request.insert(ROUTING_HINTS_CONTEXT_KEY, hints);
+    }
+    if let Some(worker) = pinned_worker {
+        request.insert(PINNED_WORKER_CONTEXT_KEY, worker);
+    }
+    if let Some(max_prompt_tokens) = limits.max_prompt_tokens {
+        request.insert(MAX_PROMPT_TOKENS_CONTEXT_KEY, max_prompt_tokens as usize);
+    }
+    let mut timings = wants_timings.then(|| ResponseTimer::start(&mut request, received));
 
     tracing::trace!("Issuing generate call for chat completions");
 
     // issue the generate call on the engine
-    let stream = engine
-        .generate(request)
-        .await
+    let stream = before_deadline(deadline, engine.generate(request))
+        .await?
         .map_err(|e| ErrorResponse::from_anyhow(e, "Failed to generate completions"))?;
+    if let Some(timer) = &timer {
+        timer.accepted();
+    }
+    if let Some(timings) = &mut timings {
+        timings.accepted();
+    }
 
     // capture the context to cancel the stream if the client disconnects
     let ctx = stream.context();
+    // or asks to cancel it, until the stream is done
+    let running = state.register_running(&request_id, ctx.clone());
 
     // todo - tap the stream and propagate request level metrics
     // note - we might do this as part of the post processing set to make it more generic
+    let stream = stream.inspect(move |response| {
+        let _running = &running;
+        let _admitted = &admitted;
+        if let Some(timer) = &mut timer {
+            timer.observe(response);
+        }
+        token_timer.observe(response);
+        record.observe(response);
+        if let Some(turn) = &mut turn {
+            turn.observe(response);
+        }
+        if let Some(usage) = response.data.as_ref().and_then(|r| r.inner.usage.as_ref()) {
+            token_timer.set_output_tokens(usage.completion_tokens as usize);
+            record.set_usage(usage.prompt_tokens, usage.completion_tokens);
+        }
+    });
+    let stream = match content_filter {
+        Some(filter) => with_content_filter(stream, filter, ctx.clone()).left_stream(),
+        None => stream.right_stream(),
+    };
+    let stream = match timings {
+        Some(timings) => with_timings(stream, timings).left_stream(),
+        None => stream.right_stream(),
+    };
 
     if streaming {
+        let stream = match deadline {
+            Some(deadline) => deadline.limit(stream, ctx.clone()).left_stream(),
+            None => stream.right_stream(),
+        };
+        let stream = stream.inspect(move |response| {
+            if let Some(usage) = response.data.as_ref().and_then(|r| r.inner.usage.as_ref()) {
+                meter.observe(usage.prompt_tokens + usage.completion_tokens);
+            }
+        });
+        let stream = with_stream_usage(stream, include_usage);
         let stream = stream.map(|response| Event::try_from(EventConverter::from(response)));
         let stream = monitor_for_disconnects(stream.boxed(), ctx, inflight).await;
+        let stream = match coalescing {
+            Some(coalescing) => coalesce(stream, coalescing).left_stream(),
+            None => stream.right_stream(),
+        };
 
         let mut sse_stream = Sse::new(stream);
 
-        if let Some(keep_alive) = state.sse_keep_alive {
+        if let Some(keep_alive) = *state.sse_keep_alive.lock().unwrap() {
             sse_stream = sse_stream.keep_alive(KeepAlive::default().interval(keep_alive));
         }
 
-        Ok(sse_stream.into_response())
+        Ok(with_request_id(sse_stream.into_response(), &request_id))
     } else {
-        let response = NvCreateChatCompletionResponse::from_annotated_stream(stream.into())
+        let fold = NvCreateChatCompletionResponse::from_annotated_stream(Box::pin(stream));
+        let response = before_deadline(deadline, fold)
             .await
+            .inspect_err(|_| ctx.stop_generating())?
             .map_err(|e| {
                 tracing::error!(
                     request_id,
                     "Failed to fold chat completions stream for: {:?}",
                     e
                 );
-                ErrorResponse::internal_server_error(&format!(
-                    "Failed to fold chat completions stream: {}",
-                    e
-                ))
+                ErrorResponse::from_stream_error(
+                    &e.to_string(),
+                    "Failed to fold chat completions stream",
+                )
             })?;
 
+        if let Some(usage) = &response.inner.usage {
+            meter.observe(usage.prompt_tokens + usage.completion_tokens);
+        }
+        inflight.mark_ok();
+        if let Some((cache, key)) = cache_key {
+            cache.insert(key, response.clone());
+        }
+        Ok(with_request_id(Json(response).into_response(), &request_id))
+    }
+}
+
+/// Re-generate a stale cached chat completion in the background
+async fn refresh_cached_response(
+    state: Arc<DeploymentState>,
+    cache: Arc<ResponseCache>,
+    key: String,
+    request: NvCreateChatCompletionRequest,
+) {
+    let model = request.inner.model.clone();
+    let result = async {
+        let engine = state.get_chat_completions_engine(&model)?;
+        let mut inflight = state.create_inflight_guard(&model, Endpoint::ChatCompletions, false);
+        let stream = engine.generate(Context::new(request)).await?;
+        let ctx = stream.context();
+        let content_filter = state.content_filter.lock().unwrap().clone();
+        let stream = match content_filter {
+            Some(filter) => with_content_filter(stream, filter, ctx).left_stream(),
+            None => stream.right_stream(),
+        };
+        let response = NvCreateChatCompletionResponse::from_annotated_stream(Box::pin(stream))
+            .await
+            .map_err(|e| anyhow::anyhow!(e))?;
         inflight.mark_ok();
-        Ok(Json(response).into_response())
+        anyhow::Ok(response)
+    }
+    .await;
+
+    match result {
+        Ok(response) => cache.insert(key, response),
+        Err(err) => {
+            tracing::warn!(model, %err, "Failed to refresh cached chat completion");
+            cache.refresh_failed(&key);
+        }
+    }
+}
+
+/// OpenAI Embeddings Request Handler
+///
+/// Embedding engines return a single response on their stream, there is no streaming option
+/// for the client.
+#[tracing::instrument(skip_all)]
+async fn embeddings(
+    State(state): State<Arc<DeploymentState>>,
+    headers: HeaderMap,
+    access: Access,
+    mut meter: TokenMeter,
+    record: AccessRecord,
+    Json(request): Json<NvCreateEmbeddingRequest>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    record.set_model(&request.inner.model);
+
+    // return a 503 if the service is not ready
+    check_ready(&state)?;
+
+    access.check_model(&request.inner.model)?;
+
+    let routing_hints = routing_hints(&headers)?;
+    let pinned_worker = pinned_worker(&headers, &access)?;
+
+    let request_id = uuid::Uuid::new_v4().to_string();
+    let model = &request.inner.model;
+
+    let engine = state
+        .get_embeddings_engine(model)
+        .map_err(|err| match err {
+            ServiceHttpError::UnsupportedCapability { .. } => {
+                ErrorResponse::bad_request(&err.to_string())
+            }
+            _ => ErrorResponse::model_not_found(),
+        })?;
+
+    // this will increment the inflight gauge for the model
+    let mut inflight = state.create_inflight_guard(model, Endpoint::Embeddings, false);
+
+    let mut request = Context::with_id(request, request_id.clone());
+    if let Some(hints) = routing_hints {
+        request.insert(ROUTING_HINTS_CONTEXT_KEY, hints);
+    }
+    if let Some(worker) = pinned_worker {
+        request.insert(PINNED_WORKER_CONTEXT_KEY, worker);
+    }
+
+    let stream = engine
+        .generate(request)
+        .await
+        .map_err(|e| ErrorResponse::from_anyhow(e, "Failed to generate embeddings"))?;
+
+    let response = NvCreateEmbeddingResponse::from_annotated_stream(stream.into())
+        .await
+        .map_err(|e| {
+            tracing::error!(request_id, "Failed to fold embeddings stream: {:?}", e);
+            ErrorResponse::from_stream_error(&e.to_string(), "Failed to fold embeddings stream")
+        })?;
+
+    meter.observe(response.inner.usage.total_tokens);
+    record.set_usage(response.inner.usage.prompt_tokens, 0);
+    inflight.mark_ok();
+    Ok(Json(response).into_response())
+}
+
+/// OpenAI Audio Transcriptions Request Handler
+///
+/// A multipart upload with a `file` and a `model` field, and optionally `language`, `prompt`,
+/// `temperature` and `response_format`. The audio is decoded to PCM here, see
+/// [`crate::audio`], so the engine receives samples rather than a file.
+async fn transcriptions(
+    State(state): State<Arc<DeploymentState>>,
+    headers: HeaderMap,
+    access: Access,
+    mut multipart: Multipart,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    // return a 503 if the service is not ready
+    check_ready(&state)?;
+
+    let routing_hints = routing_hints(&headers)?;
+    let pinned_worker = pinned_worker(&headers, &access)?;
+
+    let mut model = None;
+    let mut file = None;
+    let mut language = None;
+    let mut prompt = None;
+    let mut temperature = None;
+    let mut response_format = TranscriptionFormat::default();
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| ErrorResponse::bad_request(&format!("Invalid multipart body: {e}")))?
+    {
+        let name = field.name().unwrap_or_default().to_string();
+        if name == "file" {
+            file = Some(
+                field
+                    .bytes()
+                    .await
+                    .map_err(|e| ErrorResponse::bad_request(&e.to_string()))?,
+            );
+            continue;
+        }
+        let value = field
+            .text()
+            .await
+            .map_err(|e| ErrorResponse::bad_request(&e.to_string()))?;
+        match name.as_str() {
+            "model" => model = Some(value),
+            "language" => language = Some(value),
+            "prompt" => prompt = Some(value),
+            "temperature" => {
+                temperature = Some(value.parse::<f32>().map_err(|_| {
+                    ErrorResponse::bad_request(&format!("Invalid temperature '{value}'"))
+                })?)
+            }
+            "response_format" => {
+                response_format = value
+                    .parse()
+                    .map_err(|e: String| ErrorResponse::bad_request(&e))?
+            }
+            _ => {}
+        }
+    }
+    let Some(model) = model else {
+        return Err(ErrorResponse::bad_request("Missing 'model' field"));
+    };
+    let Some(file) = file else {
+        return Err(ErrorResponse::bad_request("Missing 'file' field"));
+    };
+    access.check_model(&model)?;
+
+    let engine = state
+        .get_transcriptions_engine(&model)
+        .map_err(|err| match err {
+            ServiceHttpError::UnsupportedCapability { .. } => {
+                ErrorResponse::bad_request(&err.to_string())
+            }
+            _ => ErrorResponse::model_not_found(),
+        })?;
+
+    let pcm = tokio::task::block_in_place(|| crate::audio::decode(&file))
+        .map_err(|err| ErrorResponse::bad_request(&err.to_string()))?;
+    let duration = pcm.duration();
+
+    let request_id = uuid::Uuid::new_v4().to_string();
+    let mut request = NvCreateTranscriptionRequest::new(model.clone(), &pcm);
+    request.language = language.clone();
+    request.prompt = prompt;
+    request.temperature = temperature;
+    request.response_format = response_format;
+
+    // this will increment the inflight gauge for the model
+    let mut inflight = state.create_inflight_guard(&model, Endpoint::Transcriptions, false);
+
+    let mut request = Context::with_id(request, request_id.clone());
+    if let Some(hints) = routing_hints {
+        request.insert(ROUTING_HINTS_CONTEXT_KEY, hints);
+    }
+    if let Some(worker) = pinned_worker {
+        request.insert(PINNED_WORKER_CONTEXT_KEY, worker);
+    }
+
+    let stream = engine
+        .generate(request)
+        .await
+        .map_err(|e| ErrorResponse::from_anyhow(e, "Failed to transcribe audio"))?;
+
+    let mut response = NvCreateTranscriptionResponse::from_annotated_stream(stream.into())
+        .await
+        .map_err(|e| {
+            tracing::error!(request_id, "Failed to fold transcription stream: {:?}", e);
+            ErrorResponse::from_stream_error(&e.to_string(), "Failed to fold transcription stream")
+        })?;
+    response.duration.get_or_insert(duration);
+    if response.language.is_none() {
+        response.language = language;
     }
+
+    inflight.mark_ok();
+    Ok(match response_format {
+        TranscriptionFormat::Json => Json(response.into_json()).into_response(),
+        TranscriptionFormat::Text => response.text.into_response(),
+        TranscriptionFormat::VerboseJson => Json(response).into_response(),
+    })
+}
+
+/// Tokenize a prompt without generating, token ids included by default
+async fn tokenize(
+    State(state): State<Arc<DeploymentState>>,
+    access: Access,
+    Json(request): Json<TokenizeRequest>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    access.check_model(&request.model)?;
+    let response = tokenize_inner(&state, request, true).await?;
+    Ok(Json(response).into_response())
+}
+
+/// Count the tokens of a prompt without generating, token ids excluded by default
+async fn count_tokens(
+    State(state): State<Arc<DeploymentState>>,
+    access: Access,
+    Json(request): Json<TokenizeRequest>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    access.check_model(&request.model)?;
+    let response = tokenize_inner(&state, request, false).await?;
+    Ok(Json(response).into_response())
+}
+
+/// Turn token ids back into text
+async fn detokenize(
+    State(state): State<Arc<DeploymentState>>,
+    access: Access,
+    Json(request): Json<DetokenizeRequest>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    check_ready(&state)?;
+    access.check_model(&request.model)?;
+
+    let preprocessor = state
+        .get_preprocessor(&request.model)
+        .map_err(|err| match err {
+            ServiceHttpError::UnsupportedCapability { .. } => {
+                ErrorResponse::bad_request(&err.to_string())
+            }
+            _ => ErrorResponse::model_not_found(),
+        })?;
+
+    let skip_special_tokens = request.skip_special_tokens.unwrap_or(false);
+    let decode = |token_ids: &[u32]| {
+        preprocessor
+            .detokenize(token_ids, skip_special_tokens)
+            .map_err(|err| ErrorResponse::bad_request(&format!("{err:#}")))
+    };
+    let text = decode(&request.token_ids)?;
+    let tokens = if request.return_tokens.unwrap_or(false) {
+        let tokens = request
+            .token_ids
+            .iter()
+            .map(|token_id| decode(std::slice::from_ref(token_id)))
+            .collect::<Result<Vec<_>, _>>()?;
+        Some(tokens)
+    } else {
+        None
+    };
+
+    Ok(Json(DetokenizeResponse {
+        model: request.model,
+        text,
+        tokens,
+    })
+    .into_response())
+}
+
+async fn tokenize_inner(
+    state: &Arc<DeploymentState>,
+    request: TokenizeRequest,
+    return_token_ids_default: bool,
+) -> Result<TokenizeResponse, (StatusCode, Json<ErrorResponse>)> {
+    check_ready(state)?;
+
+    let model = request.model.clone();
+    let return_token_ids = request.return_token_ids.unwrap_or(return_token_ids_default);
+
+    let preprocessor = state.get_preprocessor(&model).map_err(|err| match err {
+        ServiceHttpError::UnsupportedCapability { .. } => {
+            ErrorResponse::bad_request(&err.to_string())
+        }
+        _ => ErrorResponse::model_not_found(),
+    })?;
+
+    let input = request
+        .input()
+        .map_err(|err| ErrorResponse::bad_request(&err))?;
+    let (formatted_prompt, token_ids) = match input {
+        TokenizeInput::Chat(chat) => preprocessor
+            .tokenize_on_pool(*chat)
+            .await
+            .map_err(|err| ErrorResponse::bad_request(&format!("{err:#}")))?,
+        TokenizeInput::Prompt(prompt) => {
+            let tokenizer = preprocessor.clone();
+            let encoding = TokenizerPool::global()
+                .run(move || tokenizer.tokenize(&prompt))
+                .await
+                .and_then(|encoding| encoding)
+                .map_err(|err| ErrorResponse::bad_request(&format!("{err:#}")))?;
+            (None, encoding.token_ids)
+        }
+    };
+
+    Ok(TokenizeResponse {
+        model,
+        count: token_ids.len(),
+        max_model_len: preprocessor.context_length(),
+        token_ids: return_token_ids.then_some(token_ids),
+        formatted_prompt,
+    })
 }
 
 // todo - abstract this to the top level lib.rs to be reused
 // todo - move the service_observer to its own state/arc
+/// Tell the client the id to cancel the request with
+fn with_request_id(mut response: Response, request_id: &str) -> Response {
+    if let Ok(value) = HeaderValue::from_str(request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+/// Stop a running completion or chat completion, by the id in its `x-request-id` response
+/// header. The engine stops generating, and the client gets what was generated so far.
+async fn cancel_request(
+    State(state): State<Arc<DeploymentState>>,
+    Path(request_id): Path<String>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let Some(context) = state.running_request(&request_id) else {
+        return Err(ErrorResponse::not_found(&format!(
+            "No running request with id {request_id}"
+        )));
+    };
+    tracing::debug!(request_id, "Cancelling request");
+    context.stop_generating();
+    Ok(Json(CancelResponse {
+        id: request_id,
+        cancelled: true,
+    })
+    .into_response())
+}
+
+#[derive(Serialize)]
+struct CancelResponse {
+    id: String,
+    cancelled: bool,
+}
+
+/// Apply the load shedding policy, if there is one: a 503 if the model is over its latency
+/// SLOs and we reject, otherwise a timer to report the request's latencies to.
+fn admit(
+    state: &DeploymentState,
+    model: &str,
+    received: Instant,
+) -> Result<Option<RequestTimer>, (StatusCode, Json<ErrorResponse>)> {
+    let Some(shedder) = &state.load_shedder else {
+        return Ok(None);
+    };
+    match shedder.admit(model, received) {
+        Ok(timer) => Ok(Some(timer)),
+        Err(overloaded) => {
+            tracing::debug!(%overloaded, "Shedding load");
+            Err((
+                StatusCode::SERVICE_UNAVAILABLE,
+                ErrorResponse::json_of_kind(ErrorKind::Overloaded, &overloaded.to_string()),
+            ))
+        }
+    }
+}
+
+/// Wait for one of the model's running slots if there is an admission queue. A 429 with the
+/// queue depth if it is full.
+async fn wait_for_slot(
+    state: &DeploymentState,
+    model: &str,
+    priority: Priority,
+) -> Result<Option<Admitted>, Response> {
+    let Some(admission) = &state.admission else {
+        return Ok(None);
+    };
+    match admission.admit(model, priority).await {
+        Ok(admitted) => Ok(Some(admitted)),
+        Err(queue_full) => {
+            tracing::debug!(%queue_full, "Queue full");
+            Err((
+                StatusCode::TOO_MANY_REQUESTS,
+                [(QUEUE_DEPTH_HEADER, queue_full.depth.to_string())],
+                ErrorResponse::json_of_kind(ErrorKind::Overloaded, &queue_full.to_string()),
+            )
+                .into_response())
+        }
+    }
+}
+
 fn check_ready(_state: &Arc<DeploymentState>) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
     // if state.service_observer.stage() != ServiceStage::Ready {
     //     return Err(ErrorResponse::service_unavailable());
@@ -332,34 +1134,45 @@ fn check_ready(_state: &Arc<DeploymentState>) -> Result<(), (StatusCode, Json<Er
 /// list models handler, non-standard format
 async fn list_models_custom(
     State(state): State<Arc<DeploymentState>>,
+    access: Access,
 ) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
     check_ready(&state)?;
     let mut models = HashMap::new();
+    let aliases = state.served_aliases();
 
-    let chat_models = state
-        .chat_completion_engines
-        .lock()
-        .unwrap()
-        .engines
-        .keys()
-        .cloned()
-        .collect::<Vec<String>>();
-
-    let completion_models = state
-        .completion_engines
-        .lock()
-        .unwrap()
-        .engines
-        .keys()
-        .cloned()
-        .collect::<Vec<String>>();
+    let chat_models = listed_models(&state.chat_completion_engines, &aliases, &access);
+    let completion_models = listed_models(&state.completion_engines, &aliases, &access);
+    let embedding_models = listed_models(&state.embedding_engines, &aliases, &access);
+    let transcription_models = listed_models(&state.transcription_engines, &aliases, &access);
 
     models.insert("chat_completion_models", chat_models);
     models.insert("completion_models", completion_models);
+    models.insert("embedding_models", embedding_models);
+    models.insert("transcription_models", transcription_models);
 
     Ok(Json(models).into_response())
 }
 
+/// The models of `engines` the caller may use, and the aliases that can go to one of them
+fn listed_models<E>(
+    engines: &Mutex<ModelEngines<E>>,
+    aliases: &[(String, Vec<String>)],
+    access: &Access,
+) -> Vec<String> {
+    let engines = engines.lock().unwrap();
+    let aliased = aliases
+        .iter()
+        .filter(|(_, targets)| targets.iter().any(|target| engines.contains(target)))
+        .map(|(alias, _)| alias);
+    engines
+        .engines
+        .keys()
+        .chain(aliased)
+        .filter(|model| access.allows_model(model))
+        .cloned()
+        .collect()
+}
+
 /// openai compatible format
 /// Example:
 /// {
@@ -375,10 +1188,11 @@ async fn list_models_custom(
 /// }
 async fn list_models_openai(
     State(state): State<Arc<DeploymentState>>,
+    access: Access,
 ) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
     check_ready(&state)?;
 
-    let created = SystemTime::now()
+    let now = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
         .as_secs();
@@ -391,17 +1205,60 @@ async fn list_models_openai(
         .engines
         .keys()
         .chain(state.completion_engines.lock().unwrap().engines.keys())
+        .chain(state.embedding_engines.lock().unwrap().engines.keys())
+        .chain(state.transcription_engines.lock().unwrap().engines.keys())
+        .filter(|model| access.allows_model(model))
         .cloned()
         .collect();
 
-    for model_id in models {
+    // An alias is listed as the first of its targets being served
+    let aliases: Vec<(String, String)> = state
+        .served_aliases()
+        .into_iter()
+        .filter(|(alias, _)| !models.contains(alias) && access.allows_model(alias))
+        .map(|(alias, mut targets)| (alias, targets.swap_remove(0)))
+        .collect();
+    // An adapter is listed as its model
+    let adapters: Vec<(String, String)> = state
+        .lora_adapters
+        .lock()
+        .unwrap()
+        .list()
+        .into_iter()
+        .filter(|(model, _)| models.contains(model))
+        .map(|(model, adapter)| (format!("{model}:{}", adapter.name), model))
+        .filter(|(model_id, _)| access.allows_model(model_id))
+        .collect();
+    let listed = models
+        .into_iter()
+        .map(|model| (model.clone(), model))
+        .chain(aliases)
+        .chain(adapters);
+
+    for (model_id, model) in listed {
+        // Only models pre-processed here know their config
+        let preprocessor = state.preprocessors.lock().unwrap().get(&model).cloned();
+        let is_transcription = state.transcription_engines.lock().unwrap().contains(&model);
+        let input = match &preprocessor {
+            Some(preprocessor) if preprocessor.is_multimodal() => "text+image",
+            _ if is_transcription => "audio",
+            _ => "text",
+        };
+        let output = if state.embedding_engines.lock().unwrap().contains(&model) {
+            "embedding"
+        } else {
+            "text"
+        };
         data.push(ModelListing {
-            id: model_id.clone(),
+            created: state.model_created(&model).unwrap_or(now),
+            id: model_id,
             object: "object",
-            created,                        // Where would this come from? The GGUF?
             owned_by: "nvidia".to_string(), // Get organization from GGUF
+            context_length: preprocessor.map(|p| p.context_length()),
+            modality: format!("{input}->{output}"),
         });
     }
+    data.sort_by(|a, b| a.id.cmp(&b.id));
 
     let out = ListModelOpenAI {
         object: "list",
@@ -420,8 +1277,103 @@ struct ListModelOpenAI {
 struct ModelListing {
     id: String,
     object: &'static str, // always "object"
-    created: u64,         //  Seconds since epoch
+    created: u64,         // Seconds since epoch the model was registered
     owned_by: String,
+    /// Max tokens of prompt and completion together, when known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    context_length: Option<usize>,
+    /// Input and output kinds, e.g. "text+image->text"
+    modality: String,
+}
+
+/// Whether the client asked for the per-stage timings with `nvext.timings`
+fn wants_timings(nvext: &Option<NvExt>) -> bool {
+    nvext.as_ref().and_then(|ext| ext.timings).unwrap_or(false)
+}
+
+/// Whether a streaming client asked for a last chunk with the usage
+fn include_usage(options: Option<&async_openai::types::ChatCompletionStreamOptions>) -> bool {
+    options.is_some_and(|options| options.include_usage)
+}
+
+/// Parse the optional `x-dynamo-routing` header. The router decides what to do with the hints.
+pub(super) fn routing_hints(
+    headers: &HeaderMap,
+) -> Result<Option<RoutingHints>, (StatusCode, Json<ErrorResponse>)> {
+    let Some(value) = headers.get(ROUTING_HINTS_HEADER) else {
+        return Ok(None);
+    };
+    let value = value.to_str().map_err(|_| {
+        ErrorResponse::bad_request(&format!("Invalid {ROUTING_HINTS_HEADER} header"))
+    })?;
+    value
+        .parse::<RoutingHints>()
+        .map(Some)
+        .map_err(|err| ErrorResponse::bad_request(&format!("{ROUTING_HINTS_HEADER}: {err}")))
+}
+
+/// Parse the optional `x-dynamo-worker` header, which sends the request to that worker
+/// whatever the router would do. Only callers allowed every model may pin requests.
+fn pinned_worker(
+    headers: &HeaderMap,
+    access: &Access,
+) -> Result<Option<i64>, (StatusCode, Json<ErrorResponse>)> {
+    let Some(value) = headers.get(PINNED_WORKER_HEADER) else {
+        return Ok(None);
+    };
+    if !access.is_unrestricted() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            ErrorResponse::json_of_kind(
+                ErrorKind::Forbidden,
+                &format!("{PINNED_WORKER_HEADER} needs an API key allowed to use every model"),
+            ),
+        ));
+    }
+    let value = value.to_str().map_err(|_| {
+        ErrorResponse::bad_request(&format!("Invalid {PINNED_WORKER_HEADER} header"))
+    })?;
+    parse_worker_id(value)
+        .map(Some)
+        .map_err(|err| ErrorResponse::bad_request(&format!("{PINNED_WORKER_HEADER}: {err}")))
+}
+
+/// Use the OpenAI `user` of a request as its session when the routing hints don't name one,
+/// so a client's chat turns can stay on the worker that has their prefix cached.
+pub(super) fn with_user_session(
+    hints: Option<RoutingHints>,
+    user: Option<&str>,
+) -> Option<RoutingHints> {
+    let Some(user) = user.filter(|user| !user.is_empty()) else {
+        return hints;
+    };
+    let mut hints = hints.unwrap_or_default();
+    if hints.session.is_none() {
+        hints.session = Some(user.to_string());
+    }
+    Some(hints)
+}
+
+/// The request's `nvext.priority`, or else the one in its [`PRIORITY_HEADER`] header, which
+/// becomes its `nvext.priority` so that engines preempting by priority see it too
+fn priority(
+    headers: &HeaderMap,
+    nvext: &mut Option<NvExt>,
+) -> Result<Priority, (StatusCode, Json<ErrorResponse>)> {
+    if let Some(priority) = nvext.as_ref().and_then(|ext| ext.priority) {
+        return Ok(priority);
+    }
+    let Some(value) = headers.get(PRIORITY_HEADER) else {
+        return Ok(Priority::default());
+    };
+    let value = value
+        .to_str()
+        .map_err(|_| ErrorResponse::bad_request(&format!("Invalid {PRIORITY_HEADER} header")))?;
+    let priority = value
+        .parse()
+        .map_err(|err| ErrorResponse::bad_request(&format!("{PRIORITY_HEADER}: {err}")))?;
+    nvext.get_or_insert_with(NvExt::default).priority = Some(priority);
+    Ok(priority)
 }
 
 /// This method will consume a stream of SSE events and forward them to a new stream defined by a tokio channel.
@@ -430,6 +1382,20 @@ struct ModelListing {
 ///
 /// If a disconnect is detected, then the context will issue a `stop_generating` call to the context which will
 /// propagate the cancellation signal to the backend.
+/// `fut`'s output, or a 504 if the request's deadline passes first
+async fn before_deadline<F: Future>(
+    deadline: Option<Deadline>,
+    fut: F,
+) -> Result<F::Output, (StatusCode, Json<ErrorResponse>)> {
+    match deadline {
+        Some(deadline) => deadline
+            .run(fut)
+            .await
+            .map_err(|msg| ErrorResponse::gateway_timeout(&msg)),
+        None => Ok(fut.await),
+    }
+}
+
 async fn monitor_for_disconnects(
     stream: Pin<
         Box<dyn Stream<Item = Result<axum::response::sse::Event, axum::Error>> + std::marker::Send>,
@@ -523,21 +1489,93 @@ pub fn completions_router(
     (vec![doc], router)
 }
 
+type ChatCompletionsState = (
+    Arc<DeploymentState>,
+    Option<RequestTemplate>,
+    Option<Arc<ResponseCache>>,
+    Option<Arc<PresetLibrary>>,
+    Option<Arc<Conversations>>,
+);
+
 /// Create an Axum [`Router`] for the OpenAI API Chat Completions endpoint
 /// If not path is provided, the default path is `/v1/chat/completions`
 pub fn chat_completions_router(
     state: Arc<DeploymentState>,
     template: Option<RequestTemplate>,
+    cache: Option<Arc<ResponseCache>>,
+    presets: Option<Arc<PresetLibrary>>,
+    conversations: Option<Arc<Conversations>>,
     path: Option<String>,
 ) -> (Vec<RouteDoc>, Router) {
     let path = path.unwrap_or("/v1/chat/completions".to_string());
     let doc = RouteDoc::new(axum::http::Method::POST, &path);
     let router = Router::new()
         .route(&path, post(chat_completions))
-        .with_state((state, template));
+        .with_state((state, template, cache, presets, conversations));
     (vec![doc], router)
 }
 
+/// Create an Axum [`Router`] for the OpenAI API Embeddings endpoint
+/// If not path is provided, the default path is `/v1/embeddings`
+pub fn embeddings_router(
+    state: Arc<DeploymentState>,
+    path: Option<String>,
+) -> (Vec<RouteDoc>, Router) {
+    let path = path.unwrap_or("/v1/embeddings".to_string());
+    let doc = RouteDoc::new(axum::http::Method::POST, &path);
+    let router = Router::new()
+        .route(&path, post(embeddings))
+        .with_state(state);
+    (vec![doc], router)
+}
+
+/// Create an Axum [`Router`] for the OpenAI Audio Transcriptions endpoint
+/// If not path is provided, the default path is `/v1/audio/transcriptions`
+pub fn transcriptions_router(
+    state: Arc<DeploymentState>,
+    path: Option<String>,
+) -> (Vec<RouteDoc>, Router) {
+    let path = path.unwrap_or("/v1/audio/transcriptions".to_string());
+    let doc = RouteDoc::new(axum::http::Method::POST, &path).multipart();
+    let router = Router::new()
+        .route(&path, post(transcriptions))
+        // Leave room for the multipart framing and the other fields
+        .layer(DefaultBodyLimit::max(
+            crate::audio::MAX_AUDIO_BYTES + 64 * 1024,
+        ))
+        .with_state(state);
+    (vec![doc], router)
+}
+
+/// Create an Axum [`Router`] for the tokenizer endpoints: `/v1/tokenize`, `/v1/count_tokens`
+/// and `/v1/detokenize`
+pub fn tokenize_router(state: Arc<DeploymentState>) -> (Vec<RouteDoc>, Router) {
+    let tokenize_path = "/v1/tokenize".to_string();
+    let count_tokens_path = "/v1/count_tokens".to_string();
+    let detokenize_path = "/v1/detokenize".to_string();
+    let docs = vec![
+        RouteDoc::new(axum::http::Method::POST, &tokenize_path),
+        RouteDoc::new(axum::http::Method::POST, &count_tokens_path),
+        RouteDoc::new(axum::http::Method::POST, &detokenize_path),
+    ];
+    let router = Router::new()
+        .route(&tokenize_path, post(tokenize))
+        .route(&count_tokens_path, post(count_tokens))
+        .route(&detokenize_path, post(detokenize))
+        .with_state(state);
+    (docs, router)
+}
+
+/// Cancel a running request
+pub fn cancel_router(state: Arc<DeploymentState>) -> (Vec<RouteDoc>, Router) {
+    let path = "/v1/requests/{request_id}/cancel";
+    let docs = vec![RouteDoc::new(axum::http::Method::POST, path)];
+    let router = Router::new()
+        .route(path, post(cancel_request))
+        .with_state(state);
+    (docs, router)
+}
+
 /// List Models
 pub fn list_models_router(
     state: Arc<DeploymentState>,
@@ -618,4 +1656,30 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn test_with_user_session() {
+        let hints = with_user_session(None, Some("alice")).unwrap();
+        assert_eq!(hints.session.as_deref(), Some("alice"));
+        assert_eq!(with_user_session(None, Some("")), None);
+
+        // A session in the header wins
+        let header: RoutingHints = "session=chat-42, zone=us-east".parse().unwrap();
+        let hints = with_user_session(Some(header.clone()), Some("alice"));
+        assert_eq!(hints, Some(header));
+    }
+
+    #[test]
+    fn test_routing_hints_header() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(routing_hints(&headers).unwrap(), None);
+
+        headers.insert(ROUTING_HINTS_HEADER, "prefer=cache".parse().unwrap());
+        assert!(routing_hints(&headers).unwrap().unwrap().prefer.is_some());
+
+        // A preference no router understands is the client's mistake
+        headers.insert(ROUTING_HINTS_HEADER, "prefer=fastest".parse().unwrap());
+        let (status, _) = routing_hints(&headers).unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
 }
@@ -0,0 +1,253 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Prefill of prompts ahead of the requests that will use them
+//!
+//! `POST /v1/prefetch` takes a chat completion request, typically a long system prompt or a
+//! document the next requests will start with, and has the engine generate one token for it in
+//! the background. The worker keeps the prompt's KV blocks in its prefix cache, so the real
+//! request skips most of its prefill. The KV router sends that request to the worker holding the
+//! blocks. With sticky sessions, give the prefetch the `user` or `x-dynamo-routing` session the
+//! real requests will have.
+//!
+//! A prompt prefetched less than [`PrefetchConfig::ttl`] ago is still warm and is not prefetched
+//! again. At most [`PrefetchConfig::capacity`] prompts are warm at once, more prefetches are
+//! refused until some expire, so that prefetches don't evict each other's blocks. Workers evict
+//! blocks as they need memory, a warm prompt is likely cached, not certainly.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::post,
+    Json, Router,
+};
+use serde::Serialize;
+
+use super::{
+    auth::Access,
+    error::ErrorKind,
+    metrics::Endpoint,
+    openai::{routing_hints, with_user_session, ErrorResponse},
+    DeploymentState, RouteDoc,
+};
+use crate::protocols::openai::chat_completions::{
+    NvCreateChatCompletionRequest, NvCreateChatCompletionResponse,
+};
+use dynamo_runtime::pipeline::{
+    network::egress::routing_hints::ROUTING_HINTS_CONTEXT_KEY, Context,
+};
+
+const PREFETCH_PATH: &str = "/v1/prefetch";
+
+#[derive(Debug, Clone)]
+pub struct PrefetchConfig {
+    /// How long a prefetched prompt counts as warm
+    pub ttl: Duration,
+
+    /// Most prompts warm at once
+    pub capacity: usize,
+}
+
+impl Default for PrefetchConfig {
+    fn default() -> Self {
+        PrefetchConfig {
+            ttl: Duration::from_secs(300),
+            capacity: 64,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PrefetchStatus {
+    /// Prefilling in the background
+    Started,
+
+    /// Prefetched recently, nothing to do
+    Warm,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Prefetched {
+    pub status: PrefetchStatus,
+
+    /// Seconds until the prompt is no longer counted as warm
+    pub expires_in: u64,
+}
+
+/// Prompts prefetched within the TTL, by key
+struct WarmPrompts {
+    config: PrefetchConfig,
+    prompts: Mutex<HashMap<String, Instant>>,
+}
+
+impl WarmPrompts {
+    fn new(config: PrefetchConfig) -> Self {
+        WarmPrompts {
+            config,
+            prompts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Count `key` as warm from now. The status to reply with, None when full.
+    fn reserve(&self, key: &str) -> Option<Prefetched> {
+        let now = Instant::now();
+        let mut prompts = self.prompts.lock().unwrap();
+        prompts.retain(|_, expires| *expires > now);
+        if let Some(expires) = prompts.get(key) {
+            return Some(Prefetched {
+                status: PrefetchStatus::Warm,
+                expires_in: expires.duration_since(now).as_secs(),
+            });
+        }
+        if prompts.len() >= self.config.capacity {
+            return None;
+        }
+        prompts.insert(key.to_string(), now + self.config.ttl);
+        Some(Prefetched {
+            status: PrefetchStatus::Started,
+            expires_in: self.config.ttl.as_secs(),
+        })
+    }
+
+    /// The prefetch of `key` failed, it is not warm
+    fn release(&self, key: &str) {
+        self.prompts.lock().unwrap().remove(key);
+    }
+}
+
+pub struct PrefetchState {
+    deployment: Arc<DeploymentState>,
+    warm: WarmPrompts,
+}
+
+impl PrefetchState {
+    pub fn new(deployment: Arc<DeploymentState>, config: PrefetchConfig) -> Self {
+        PrefetchState {
+            deployment,
+            warm: WarmPrompts::new(config),
+        }
+    }
+}
+
+type HttpResult<T> = Result<T, (StatusCode, Json<ErrorResponse>)>;
+
+async fn prefetch(
+    State(state): State<Arc<PrefetchState>>,
+    headers: HeaderMap,
+    access: Access,
+    Json(mut request): Json<NvCreateChatCompletionRequest>,
+) -> HttpResult<Response> {
+    access.check_model(&request.inner.model)?;
+    let routing_hints = with_user_session(routing_hints(&headers)?, request.inner.user.as_deref());
+    let engine = state
+        .deployment
+        .get_chat_completions_engine(&request.inner.model)
+        .map_err(|_| ErrorResponse::model_not_found())?;
+
+    // the blocks are only shared with requests rendering the same prompt on the same worker
+    let session = routing_hints
+        .as_ref()
+        .and_then(|hints| hints.session.as_ref());
+    let body = serde_json::to_vec(&(&request.inner.model, &request.inner.messages, session))
+        .map_err(|e| ErrorResponse::bad_request(&e.to_string()))?;
+    let key = blake3::hash(&body).to_hex().to_string();
+    let Some(prefetched) = state.warm.reserve(&key) else {
+        return Err(ErrorResponse::of_kind(
+            ErrorKind::Overloaded,
+            "Too many prompts prefetched, try again once some expire",
+        ));
+    };
+    if prefetched.status == PrefetchStatus::Warm {
+        return Ok(Json(prefetched).into_response());
+    }
+
+    // one token is enough to prefill the prompt
+    request.inner.stream = Some(true);
+    request.inner.n = None;
+    request.inner.max_completion_tokens = Some(1);
+    // ALLOW: max_tokens is deprecated in favor of max_completion_tokens
+    #[allow(deprecated)]
+    {
+        request.inner.max_tokens = None;
+    }
+    let model = request.inner.model.clone();
+    let request_id = uuid::Uuid::new_v4().to_string();
+    let mut request = Context::with_id(request, request_id.clone());
+    if let Some(hints) = routing_hints {
+        request.insert(ROUTING_HINTS_CONTEXT_KEY, hints);
+    }
+
+    tokio::spawn(async move {
+        let mut inflight =
+            state
+                .deployment
+                .create_inflight_guard(&model, Endpoint::ChatCompletions, false);
+        let result = match engine.generate(request).await {
+            Ok(stream) => NvCreateChatCompletionResponse::from_annotated_stream(stream.into())
+                .await
+                .map(|_| ()),
+            Err(err) => Err(err.to_string()),
+        };
+        match result {
+            Ok(()) => {
+                inflight.mark_ok();
+                tracing::debug!(request_id, model, "Prefetched prompt");
+            }
+            Err(err) => {
+                tracing::warn!(request_id, model, %err, "Prefetch failed");
+                state.warm.release(&key);
+            }
+        }
+    });
+    Ok((StatusCode::ACCEPTED, Json(prefetched)).into_response())
+}
+
+/// Create an Axum [`Router`] for `/v1/prefetch`
+pub fn prefetch_router(state: Arc<PrefetchState>) -> (Vec<RouteDoc>, Router) {
+    let docs = vec![RouteDoc::new(axum::http::Method::POST, PREFETCH_PATH)];
+    let router = Router::new()
+        .route(PREFETCH_PATH, post(prefetch))
+        .with_state(state);
+    (docs, router)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_warm_prompts() {
+        let warm = WarmPrompts::new(PrefetchConfig {
+            ttl: Duration::from_secs(60),
+            capacity: 2,
+        });
+        assert_eq!(warm.reserve("a").unwrap().status, PrefetchStatus::Started);
+        assert_eq!(warm.reserve("a").unwrap().status, PrefetchStatus::Warm);
+        assert_eq!(warm.reserve("b").unwrap().status, PrefetchStatus::Started);
+        assert!(warm.reserve("c").is_none());
+
+        // a failed prefetch makes room
+        warm.release("b");
+        assert_eq!(warm.reserve("c").unwrap().status, PrefetchStatus::Started);
+    }
+}
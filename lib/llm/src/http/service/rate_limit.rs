@@ -0,0 +1,444 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Per-client rate limits
+//!
+//! A client is the API key of the request when authentication is on, its IP address
+//! otherwise. Each client has:
+//! - A requests per minute token bucket.
+//! - A tokens per minute bucket, charged with the usage of each response once it is complete.
+//!   Requests are admitted while the bucket isn't empty, so one large response can take it
+//!   below zero and hold the next requests back until it refilled.
+//! - A cap on concurrent requests. A streamed response counts until the stream ends.
+//!
+//! Requests over a limit get a 429 with a `Retry-After` header.
+//!
+//! The limits can be changed while the service runs with [`RateLimiter::set_config`].
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, FromRequestParts, Request, State},
+    http::{header, request::Parts, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures::StreamExt;
+
+use super::error::ErrorKind;
+use super::openai::ErrorResponse;
+use super::tenancy::NamespaceAccess;
+use crate::auth::ApiKey;
+
+/// Past this many clients, forget those that are back to a clean slate
+const PRUNE_THRESHOLD: usize = 4096;
+
+#[derive(Debug, Clone, Default)]
+pub struct RateLimitConfig {
+    /// Requests a client may send per minute, in bursts of up to as many
+    pub requests_per_minute: Option<u32>,
+
+    /// Prompt and generated tokens a client may use per minute
+    pub tokens_per_minute: Option<u32>,
+
+    /// Requests a client may have in flight at once
+    pub max_concurrent_requests: Option<u32>,
+}
+
+impl RateLimitConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.requests_per_minute.is_some()
+            || self.tokens_per_minute.is_some()
+            || self.max_concurrent_requests.is_some()
+    }
+}
+
+/// Refills continuously at `per_minute / 60` per second, up to `per_minute`
+struct Bucket {
+    capacity: f64,
+    level: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(per_minute: u32, now: Instant) -> Self {
+        Bucket {
+            capacity: per_minute as f64,
+            level: per_minute as f64,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.level = (self.level + elapsed * self.capacity / 60.0).min(self.capacity);
+        self.updated = now;
+    }
+
+    /// Seconds until the level reaches `amount`
+    fn wait_for(&self, amount: f64) -> f64 {
+        if self.level >= amount {
+            return 0.0;
+        }
+        (amount - self.level) * 60.0 / self.capacity
+    }
+
+    fn is_full(&self) -> bool {
+        self.level >= self.capacity
+    }
+}
+
+struct ClientState {
+    requests: Option<Bucket>,
+    tokens: Option<Bucket>,
+    in_flight: u32,
+}
+
+impl ClientState {
+    fn is_idle(&self) -> bool {
+        self.in_flight == 0
+            && self.requests.as_ref().is_none_or(Bucket::is_full)
+            && self.tokens.as_ref().is_none_or(Bucket::is_full)
+    }
+}
+
+/// Why a request was turned away
+#[derive(Debug, Clone, PartialEq)]
+pub enum Limited {
+    Requests { retry_after: f64 },
+    Tokens { retry_after: f64 },
+    Concurrency { max: u32 },
+}
+
+impl Limited {
+    fn retry_after(&self) -> u64 {
+        match self {
+            Limited::Requests { retry_after } | Limited::Tokens { retry_after } => {
+                retry_after.ceil().max(1.0) as u64
+            }
+            Limited::Concurrency { .. } => 1,
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            Limited::Requests { .. } => "Rate limit reached for requests per minute".to_string(),
+            Limited::Tokens { .. } => "Rate limit reached for tokens per minute".to_string(),
+            Limited::Concurrency { max } => {
+                format!("Too many concurrent requests, at most {max} are allowed")
+            }
+        }
+    }
+}
+
+pub struct RateLimiter {
+    config: RwLock<RateLimitConfig>,
+    clients: Mutex<HashMap<String, ClientState>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        RateLimiter {
+            config: RwLock::new(config),
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.read().unwrap().is_enabled()
+    }
+
+    /// Apply new limits. Each client keeps its requests in flight, its buckets are resized
+    /// keeping how much of them was used.
+    pub fn set_config(&self, config: RateLimitConfig) {
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap();
+        for state in clients.values_mut() {
+            state.requests = resize(state.requests.take(), config.requests_per_minute, now);
+            state.tokens = resize(state.tokens.take(), config.tokens_per_minute, now);
+        }
+        *self.config.write().unwrap() = config;
+    }
+
+    /// Admit a request from `client`. The request counts as in flight until the [`Permit`]
+    /// is dropped.
+    pub fn acquire(self: &Arc<Self>, client: &str) -> Result<Permit, Limited> {
+        let now = Instant::now();
+        let config = self.config.read().unwrap().clone();
+        let mut clients = self.clients.lock().unwrap();
+        if clients.len() > PRUNE_THRESHOLD {
+            clients.retain(|_, state| {
+                if let Some(bucket) = state.requests.as_mut() {
+                    bucket.refill(now);
+                }
+                if let Some(bucket) = state.tokens.as_mut() {
+                    bucket.refill(now);
+                }
+                !state.is_idle()
+            });
+        }
+        let state = clients
+            .entry(client.to_string())
+            .or_insert_with(|| ClientState {
+                requests: config.requests_per_minute.map(|n| Bucket::new(n, now)),
+                tokens: config.tokens_per_minute.map(|n| Bucket::new(n, now)),
+                in_flight: 0,
+            });
+
+        if let Some(max) = config.max_concurrent_requests {
+            if state.in_flight >= max {
+                return Err(Limited::Concurrency { max });
+            }
+        }
+        if let Some(bucket) = state.tokens.as_mut() {
+            bucket.refill(now);
+            // Any tokens left will do, we don't know yet how many the request needs
+            let retry_after = bucket.wait_for(f64::MIN_POSITIVE);
+            if retry_after > 0.0 {
+                return Err(Limited::Tokens { retry_after });
+            }
+        }
+        if let Some(bucket) = state.requests.as_mut() {
+            bucket.refill(now);
+            let retry_after = bucket.wait_for(1.0);
+            if retry_after > 0.0 {
+                return Err(Limited::Requests { retry_after });
+            }
+            bucket.level -= 1.0;
+        }
+
+        state.in_flight += 1;
+        Ok(Permit {
+            limiter: self.clone(),
+            client: client.to_string(),
+        })
+    }
+
+    /// Take `tokens` out of the client's tokens per minute bucket
+    pub fn charge_tokens(&self, client: &str, tokens: u32) {
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(bucket) = clients
+            .get_mut(client)
+            .and_then(|state| state.tokens.as_mut())
+        {
+            bucket.refill(Instant::now());
+            bucket.level -= tokens as f64;
+        }
+    }
+
+    fn release(&self, client: &str) {
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(state) = clients.get_mut(client) {
+            state.in_flight = state.in_flight.saturating_sub(1);
+        }
+    }
+}
+
+/// `bucket` with a capacity of `per_minute`, as full in proportion as it was
+fn resize(bucket: Option<Bucket>, per_minute: Option<u32>, now: Instant) -> Option<Bucket> {
+    let mut resized = Bucket::new(per_minute?, now);
+    if let Some(mut bucket) = bucket {
+        bucket.refill(now);
+        resized.level = bucket.level / bucket.capacity * resized.capacity;
+    }
+    Some(resized)
+}
+
+/// An admitted request, in flight until dropped
+pub struct Permit {
+    limiter: Arc<RateLimiter>,
+    client: String,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.limiter.release(&self.client);
+    }
+}
+
+/// Who to charge the tokens of a request to, added to the request extensions
+#[derive(Clone)]
+struct TokenAccount {
+    limiter: Arc<RateLimiter>,
+    client: String,
+}
+
+/// Middleware applying the [`RateLimiter`]. Must run after authentication, which tells us
+/// the API key.
+pub(crate) async fn rate_limit(
+    State(limiter): State<Arc<RateLimiter>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if !limiter.is_enabled() || super::auth::PUBLIC_PATHS.contains(&request.uri().path()) {
+        return next.run(request).await;
+    }
+
+    let client = client_id(&request);
+    let permit = match limiter.acquire(&client) {
+        Ok(permit) => permit,
+        Err(limited) => {
+            tracing::debug!(client, ?limited, "Rate limited");
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, limited.retry_after().to_string())],
+                ErrorResponse::json_of_kind(ErrorKind::Overloaded, &limited.message()),
+            )
+                .into_response();
+        }
+    };
+    request
+        .extensions_mut()
+        .insert(TokenAccount { limiter, client });
+
+    // Streamed responses are still in flight after the handler returns
+    let (parts, body) = next.run(request).await.into_parts();
+    let body = body.into_data_stream().map(move |chunk| {
+        let _in_flight = &permit;
+        chunk
+    });
+    Response::from_parts(parts, Body::from_stream(body))
+}
+
+/// Who sent `request`: its API key, or its address when API keys are off
+pub(crate) fn client_id(request: &Request) -> String {
+    if let Some(api_key) = request.extensions().get::<Arc<ApiKey>>() {
+        return format!("key:{}", api_key.name);
+    }
+    match request.extensions().get::<ConnectInfo<SocketAddr>>() {
+        Some(ConnectInfo(addr)) => format!("ip:{}", addr.ip()),
+        None => "unknown".to_string(),
+    }
+}
+
+/// Counts the tokens of a response, and charges them to the client's tokens per minute
+/// limit and its namespace's quota when dropped. Does nothing without either.
+pub(crate) struct TokenMeter {
+    account: Option<TokenAccount>,
+    namespace: Option<NamespaceAccess>,
+    tokens: u32,
+}
+
+impl TokenMeter {
+    /// Record the usage so far, prompt and generated tokens together
+    pub fn observe(&mut self, total_tokens: u32) {
+        self.tokens = total_tokens;
+    }
+
+    /// A meter charging the same client and namespace, for another request made on behalf of
+    /// this one, such as a line of a batch
+    pub fn fork(&self) -> TokenMeter {
+        TokenMeter {
+            account: self.account.clone(),
+            namespace: self.namespace.clone(),
+            tokens: 0,
+        }
+    }
+}
+
+impl Drop for TokenMeter {
+    fn drop(&mut self) {
+        if let Some(account) = &self.account {
+            account.limiter.charge_tokens(&account.client, self.tokens);
+        }
+        if let Some(namespace) = &self.namespace {
+            namespace.charge(self.tokens as u64);
+        }
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for TokenMeter {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(TokenMeter {
+            account: parts.extensions.get::<TokenAccount>().cloned(),
+            namespace: parts.extensions.get::<NamespaceAccess>().cloned(),
+            tokens: 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = Arc::new(RateLimiter::new(RateLimitConfig {
+            requests_per_minute: Some(2),
+            tokens_per_minute: Some(100),
+            max_concurrent_requests: Some(1),
+        }));
+
+        let permit = limiter.acquire("a").unwrap();
+        assert_eq!(
+            limiter.acquire("a").err(),
+            Some(Limited::Concurrency { max: 1 })
+        );
+        // Other clients have their own limits
+        drop(limiter.acquire("b").unwrap());
+        drop(permit);
+
+        limiter.charge_tokens("a", 150);
+        assert!(matches!(
+            limiter.acquire("a"),
+            Err(Limited::Tokens { retry_after }) if retry_after > 29.0 && retry_after <= 30.0
+        ));
+
+        drop(limiter.acquire("b").unwrap());
+        assert!(matches!(
+            limiter.acquire("b"),
+            Err(Limited::Requests { retry_after }) if retry_after > 29.0 && retry_after <= 30.0
+        ));
+    }
+
+    #[test]
+    fn test_set_config() {
+        let limiter = Arc::new(RateLimiter::new(RateLimitConfig {
+            requests_per_minute: Some(2),
+            max_concurrent_requests: Some(1),
+            ..Default::default()
+        }));
+        let permit = limiter.acquire("a").unwrap();
+
+        limiter.set_config(RateLimitConfig {
+            requests_per_minute: Some(4),
+            max_concurrent_requests: Some(2),
+            ..Default::default()
+        });
+        // Half the requests were used, and the one in flight still counts
+        let second = limiter.acquire("a").unwrap();
+        assert_eq!(
+            limiter.acquire("a").err(),
+            Some(Limited::Concurrency { max: 2 })
+        );
+        drop((permit, second));
+        drop(limiter.acquire("a").unwrap());
+        assert!(matches!(
+            limiter.acquire("a"),
+            Err(Limited::Requests { .. })
+        ));
+
+        limiter.set_config(RateLimitConfig::default());
+        assert!(!limiter.is_enabled());
+        drop(limiter.acquire("a").unwrap());
+    }
+}
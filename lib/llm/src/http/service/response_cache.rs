@@ -0,0 +1,325 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Cache of non-streaming chat completion responses for deterministic requests.
+//!
+//! Only requests that always produce the same output are cached: greedy sampling
+//! (`temperature: 0` or `nvext.greed_sampling`) and a single choice.
+//!
+//! Entries live for [`ResponseCacheConfig::ttl`]. With [`ResponseCacheConfig::stale_after`] set,
+//! an entry older than that is still served, but the first request to see it stale triggers a
+//! re-generation in the background (stale-while-revalidate). Hot entries stay fresh without a
+//! client ever waiting on the model.
+//!
+//! When full, the least recently used entry makes room. [`ResponseCacheConfig::models`] limits
+//! caching to some models. Frontends can share a [`SharedResponseCache`], such as Redis, behind
+//! their own: a miss in memory looks there, and new responses are written there too.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+
+use crate::protocols::openai::chat_completions::{
+    NvCreateChatCompletionRequest, NvCreateChatCompletionResponse,
+};
+
+#[derive(Debug, Clone)]
+pub struct ResponseCacheConfig {
+    /// Entries older than this are dropped and re-generated in the foreground
+    pub ttl: Duration,
+
+    /// Entries older than this, but younger than `ttl`, are served and refreshed in the
+    /// background. `None` disables stale-while-revalidate.
+    pub stale_after: Option<Duration>,
+
+    /// Most entries to keep. The least recently used is dropped to make room.
+    pub capacity: usize,
+
+    /// Only cache the responses of these models, of all models if None
+    pub models: Option<HashSet<String>>,
+}
+
+impl Default for ResponseCacheConfig {
+    fn default() -> Self {
+        ResponseCacheConfig {
+            ttl: Duration::from_secs(300),
+            stale_after: None,
+            capacity: 1024,
+            models: None,
+        }
+    }
+}
+
+/// Result of a cache lookup
+#[derive(Debug)]
+pub enum Lookup {
+    /// Serve this response
+    Fresh(NvCreateChatCompletionResponse),
+
+    /// Serve this response, and the caller must refresh the entry with
+    /// [`ResponseCache::insert`] or [`ResponseCache::refresh_failed`]. Only one caller at a
+    /// time is told to refresh an entry.
+    Stale(NvCreateChatCompletionResponse),
+
+    /// Not cached, or expired
+    Miss,
+}
+
+/// A cache several frontends share, behind the in-memory cache of each
+#[async_trait]
+pub trait SharedResponseCache: Send + Sync {
+    async fn get(&self, key: &str) -> anyhow::Result<Option<NvCreateChatCompletionResponse>>;
+
+    /// Keep `response` for `ttl`
+    async fn put(
+        &self,
+        key: &str,
+        response: &NvCreateChatCompletionResponse,
+        ttl: Duration,
+    ) -> anyhow::Result<()>;
+}
+
+struct Entry {
+    response: NvCreateChatCompletionResponse,
+    created: Instant,
+    last_used: Instant,
+    refreshing: bool,
+}
+
+pub struct ResponseCache {
+    config: ResponseCacheConfig,
+    entries: Mutex<HashMap<String, Entry>>,
+    shared: Option<Arc<dyn SharedResponseCache>>,
+}
+
+impl ResponseCache {
+    pub fn new(config: ResponseCacheConfig) -> Self {
+        ResponseCache {
+            config,
+            entries: Mutex::new(HashMap::new()),
+            shared: None,
+        }
+    }
+
+    pub fn with_shared(mut self, shared: Option<Arc<dyn SharedResponseCache>>) -> Self {
+        self.shared = shared;
+        self
+    }
+
+    /// Whether responses of `model` are cached
+    pub fn caches(&self, model: &str) -> bool {
+        self.config
+            .models
+            .as_ref()
+            .is_none_or(|models| models.contains(model))
+    }
+
+    /// The cache key for this request, or None if its response must not be cached.
+    pub fn key(request: &NvCreateChatCompletionRequest) -> Option<String> {
+        let greedy = request.inner.temperature == Some(0.0)
+            || request
+                .nvext
+                .as_ref()
+                .and_then(|ext| ext.greed_sampling)
+                .unwrap_or(false);
+        if !greedy || request.inner.n.unwrap_or(1) != 1 {
+            return None;
+        }
+
+        // Streaming or not, the response is the same
+        let mut inner = request.inner.clone();
+        inner.stream = None;
+        inner.stream_options = None;
+        let body = serde_json::to_vec(&(inner, &request.nvext)).ok()?;
+        Some(blake3::hash(&body).to_hex().to_string())
+    }
+
+    pub fn lookup(&self, key: &str) -> Lookup {
+        let mut entries = self.entries.lock().unwrap();
+        let Some(entry) = entries.get_mut(key) else {
+            return Lookup::Miss;
+        };
+
+        let age = entry.created.elapsed();
+        if age >= self.config.ttl {
+            entries.remove(key);
+            return Lookup::Miss;
+        }
+
+        entry.last_used = Instant::now();
+        match self.config.stale_after {
+            Some(stale_after) if age >= stale_after && !entry.refreshing => {
+                entry.refreshing = true;
+                Lookup::Stale(entry.response.clone())
+            }
+            _ => Lookup::Fresh(entry.response.clone()),
+        }
+    }
+
+    /// After a [`Lookup::Miss`], the response in the shared cache if there is one. It is kept
+    /// in memory from then on. A shared cache that fails is logged and counts as a miss.
+    pub async fn lookup_shared(&self, key: &str) -> Option<NvCreateChatCompletionResponse> {
+        let shared = self.shared.as_ref()?;
+        match shared.get(key).await {
+            Ok(Some(response)) => {
+                self.insert_local(key.to_string(), response.clone());
+                Some(response)
+            }
+            Ok(None) => None,
+            Err(err) => {
+                tracing::warn!(%err, "Shared response cache lookup failed");
+                None
+            }
+        }
+    }
+
+    /// Cache `response`, and write it to the shared cache in the background
+    pub fn insert(&self, key: String, response: NvCreateChatCompletionResponse) {
+        if let Some(shared) = &self.shared {
+            let shared = shared.clone();
+            let (key, response, ttl) = (key.clone(), response.clone(), self.config.ttl);
+            tokio::spawn(async move {
+                if let Err(err) = shared.put(&key, &response, ttl).await {
+                    tracing::warn!(%err, "Shared response cache write failed");
+                }
+            });
+        }
+        self.insert_local(key, response);
+    }
+
+    fn insert_local(&self, key: String, response: NvCreateChatCompletionResponse) {
+        let mut entries = self.entries.lock().unwrap();
+        if !entries.contains_key(&key) && entries.len() >= self.config.capacity {
+            let least_used = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(least_used) = least_used {
+                entries.remove(&least_used);
+            }
+        }
+        let now = Instant::now();
+        entries.insert(
+            key,
+            Entry {
+                response,
+                created: now,
+                last_used: now,
+                refreshing: false,
+            },
+        );
+    }
+
+    /// A background refresh did not complete. Keep serving the stale entry and let the next
+    /// request try again.
+    pub fn refresh_failed(&self, key: &str) {
+        if let Some(entry) = self.entries.lock().unwrap().get_mut(key) {
+            entry.refreshing = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(temperature: f32) -> NvCreateChatCompletionRequest {
+        serde_json::from_value(serde_json::json!({
+            "model": "m",
+            "temperature": temperature,
+            "messages": [{"role": "user", "content": "What is the capital of South Africa?"}],
+        }))
+        .unwrap()
+    }
+
+    fn response(id: &str) -> NvCreateChatCompletionResponse {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "object": "chat.completion",
+            "created": 0,
+            "model": "m",
+            "choices": [],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_key() {
+        assert!(ResponseCache::key(&request(0.7)).is_none());
+
+        let key = ResponseCache::key(&request(0.0)).unwrap();
+        let mut streaming = request(0.0);
+        streaming.inner.stream = Some(true);
+        assert_eq!(ResponseCache::key(&streaming).unwrap(), key);
+    }
+
+    #[test]
+    fn test_stale_while_revalidate() {
+        let cache = ResponseCache::new(ResponseCacheConfig {
+            ttl: Duration::from_secs(60),
+            stale_after: Some(Duration::ZERO),
+            capacity: 1,
+        });
+        assert!(matches!(cache.lookup("a"), Lookup::Miss));
+
+        cache.insert("a".to_string(), response("1"));
+
+        // Only the first caller refreshes, the others are served the same entry
+        assert!(matches!(cache.lookup("a"), Lookup::Stale(r) if r.inner.id == "1"));
+        assert!(matches!(cache.lookup("a"), Lookup::Fresh(r) if r.inner.id == "1"));
+
+        cache.refresh_failed("a");
+        assert!(matches!(cache.lookup("a"), Lookup::Stale(_)));
+        cache.insert("a".to_string(), response("2"));
+        assert!(matches!(cache.lookup("a"), Lookup::Stale(r) if r.inner.id == "2"));
+
+        // At capacity the oldest entry goes
+        cache.insert("b".to_string(), response("3"));
+        assert!(matches!(cache.lookup("a"), Lookup::Miss));
+    }
+
+    #[test]
+    fn test_least_recently_used() {
+        let cache = ResponseCache::new(ResponseCacheConfig {
+            capacity: 2,
+            models: Some(HashSet::from(["m".to_string()])),
+            ..Default::default()
+        });
+        assert!(cache.caches("m"));
+        assert!(!cache.caches("other"));
+
+        cache.insert("a".to_string(), response("1"));
+        cache.insert("b".to_string(), response("2"));
+        // Using the older entry keeps it, the other one makes room
+        assert!(matches!(cache.lookup("a"), Lookup::Fresh(_)));
+        cache.insert("c".to_string(), response("3"));
+        assert!(matches!(cache.lookup("a"), Lookup::Fresh(_)));
+        assert!(matches!(cache.lookup("b"), Lookup::Miss));
+    }
+
+    #[test]
+    fn test_ttl() {
+        let cache = ResponseCache::new(ResponseCacheConfig {
+            ttl: Duration::ZERO,
+            ..Default::default()
+        });
+        cache.insert("a".to_string(), response("1"));
+        assert!(matches!(cache.lookup("a"), Lookup::Miss));
+    }
+}
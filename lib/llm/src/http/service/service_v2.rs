@@ -13,20 +13,63 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use super::access_log::{AccessLog, AccessLogConfig};
+use super::admission::AdmissionConfig;
+use super::auth::CurrentApiKeys;
+use super::coalesce::StreamCoalescing;
+use super::conversations::{ConversationConfig, ConversationStore, Conversations};
+use super::cors::CorsConfig;
+use super::forwarded::TrustedProxies;
+use super::idempotency::{IdempotencyConfig, IdempotencyStore};
+use super::limits::RequestLimits;
 use super::metrics;
-use super::ModelManager;
+use super::model_admin::ModelLoader;
+use super::moderation::ContentFilter;
+use super::prefetch::{PrefetchConfig, PrefetchState};
+use super::rate_limit::{RateLimitConfig, RateLimiter};
+use super::response_cache::{ResponseCache, ResponseCacheConfig, SharedResponseCache};
+use super::shedding::SloConfig;
+use super::tenancy::TenancyState;
+use super::tls::TlsConfig;
+use super::{ModelManager, RouteDoc};
+use crate::auth::ApiKeys;
+use crate::model_alias::ModelAliases;
+use crate::presets::PresetLibrary;
 use crate::request_template::RequestTemplate;
+use crate::tenancy::Tenants;
 use anyhow::Result;
 use derive_builder::Builder;
+use dynamo_runtime::drain::Drain;
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 use tokio::task::JoinHandle;
+use tokio_rustls::TlsAcceptor;
 use tokio_util::sync::CancellationToken;
 
 #[derive(Clone)]
 pub struct HttpService {
     models: ModelManager,
     router: axum::Router,
+    route_docs: Vec<RouteDoc>,
     port: u16,
     host: String,
+    tls: Option<TlsAcceptor>,
+    api_keys: Arc<CurrentApiKeys>,
+    rate_limiter: Arc<RateLimiter>,
+    tenants: Option<Arc<Tenants>>,
+}
+
+/// The settings [`HttpService::reload`] changes while the service runs. Each is the setting
+/// of the same name of [`HttpServiceConfig`].
+#[derive(Clone, Default)]
+pub struct ReloadConfig {
+    pub api_keys: Option<Arc<ApiKeys>>,
+    pub rate_limit: Option<RateLimitConfig>,
+    pub model_aliases: ModelAliases,
 }
 
 #[derive(Clone, Builder)]
@@ -46,8 +89,144 @@ pub struct HttpServiceConfig {
     #[builder(default = "true")]
     enable_cmpl_endpoints: bool,
 
+    #[builder(default = "true")]
+    enable_embeddings_endpoints: bool,
+
+    /// Speech-to-text: `/v1/audio/transcriptions`
+    #[builder(default = "true")]
+    enable_transcriptions_endpoints: bool,
+
+    /// Tokenizer endpoints: `/v1/tokenize`, `/v1/count_tokens` and `/v1/detokenize`
+    #[builder(default = "true")]
+    enable_tokenize_endpoints: bool,
+
+    /// OpenAI Batch API: `/v1/files` and `/v1/batches`
+    #[builder(default = "false")]
+    enable_batches_endpoints: bool,
+
+    /// The operator API under `/admin`, see [`super::admin`]. Without API keys anyone who
+    /// reaches the port can use it.
+    #[builder(default = "false")]
+    enable_admin_endpoints: bool,
+
+    /// `/admin/lora`, to add and remove LoRA adapters while serving, see [`super::lora`].
+    /// Without API keys anyone who reaches the port can load adapters from the paths they give.
+    #[builder(default = "false")]
+    enable_lora_admin_endpoints: bool,
+
+    /// Where uploaded batch input files and batch results are kept.
+    /// Defaults to a `dynamo-batches` directory under the system temp dir.
+    #[builder(default = "None")]
+    batch_dir: Option<PathBuf>,
+
+    /// How many lines of a batch run against the engine at once. Defaults to 8.
+    #[builder(default = "None")]
+    batch_concurrency: Option<usize>,
+
     #[builder(default = "None")]
     request_template: Option<RequestTemplate>,
+
+    /// Cache non-streaming responses to deterministic chat completion requests
+    #[builder(default = "None")]
+    response_cache: Option<ResponseCacheConfig>,
+
+    /// Behind the response cache, shared with other frontends
+    #[builder(default = "None")]
+    response_cache_shared: Option<Arc<dyn SharedResponseCache>>,
+
+    /// Send the events of streaming responses in bursts. Each event at once if None, unless
+    /// the request asks otherwise.
+    #[builder(default = "None")]
+    stream_coalescing: Option<StreamCoalescing>,
+
+    /// Send a keep-alive comment on streaming responses idle this long. None sends nothing.
+    #[builder(default = "None")]
+    sse_keep_alive: Option<Duration>,
+
+    /// Answer CORS preflights and add CORS headers, for browser apps. No CORS if None.
+    #[builder(default = "None")]
+    cors: Option<CorsConfig>,
+
+    /// Take the client's address from `X-Forwarded-For` for requests from these proxies, see
+    /// [`super::forwarded`]. The header is ignored if None.
+    #[builder(default = "None")]
+    trusted_proxies: Option<TrustedProxies>,
+
+    /// Replay the response of the first request with an `Idempotency-Key` to the requests
+    /// repeating it, see [`super::idempotency`]. The header is ignored if None.
+    #[builder(default = "None")]
+    idempotency: Option<IdempotencyConfig>,
+
+    /// Caps on the tokens and time of each request. No limits by default.
+    #[builder(default)]
+    request_limits: RequestLimits,
+
+    /// Check the prompts and responses of completions and chat completions, see
+    /// [`super::moderation`]. Nothing is checked if None.
+    #[builder(default = "None")]
+    content_filter: Option<Arc<ContentFilter>>,
+
+    /// Prompt presets chat requests can pick with `nvext.preset`
+    #[builder(default = "None")]
+    presets: Option<Arc<PresetLibrary>>,
+
+    /// Keep the conversations of chat requests with `nvext.session_id`, see
+    /// [`super::conversations`]. Such requests are refused if None.
+    #[builder(default = "None")]
+    conversations: Option<ConversationConfig>,
+
+    /// Where conversations are kept, shared with other frontends. In memory if None.
+    #[builder(default = "None")]
+    conversation_store: Option<Arc<dyn ConversationStore>>,
+
+    /// `/v1/prefetch`, which prefills prompts ahead of the requests using them, see
+    /// [`super::prefetch`]. Not served if None.
+    #[builder(default = "None")]
+    prefetch: Option<PrefetchConfig>,
+
+    /// Require one of these keys in an `Authorization: Bearer` header. No authentication if None.
+    #[builder(default = "None")]
+    api_keys: Option<Arc<ApiKeys>>,
+
+    /// Limits per API key, or per client IP without API keys. No limits if None.
+    #[builder(default = "None")]
+    rate_limit: Option<RateLimitConfig>,
+
+    /// Other names clients may use for models, see [`crate::model_alias`]
+    #[builder(default)]
+    model_aliases: ModelAliases,
+
+    /// Namespaces API keys can belong to, with their token quotas. Required if a key has a
+    /// namespace.
+    #[builder(default = "None")]
+    tenants: Option<Arc<Tenants>>,
+
+    /// Serve HTTPS with this certificate. Plain HTTP if None.
+    #[builder(default = "None")]
+    tls: Option<TlsConfig>,
+
+    /// Reject or shorten new requests to models over these latency objectives
+    #[builder(default = "None")]
+    slo: Option<SloConfig>,
+
+    /// Run at most this many requests of each model at once, queue a bounded number more and
+    /// answer 429 past that. No limit if None.
+    #[builder(default = "None")]
+    admission: Option<AdmissionConfig>,
+
+    /// Answer 503 once this drain starts, and count requests as running until their response
+    /// is done. Usually the runtime's, so shutdown waits for them.
+    #[builder(default = "None")]
+    drain: Option<Arc<Drain>>,
+
+    /// Write a JSON line per request, see [`super::access_log`]. No access log if None.
+    #[builder(default = "None")]
+    access_log: Option<AccessLogConfig>,
+
+    /// Serve `/admin/models/load` and `/admin/models/unload` with this loader, see
+    /// [`super::model_admin`]. Not served if None.
+    #[builder(default = "None")]
+    model_loader: Option<Arc<dyn ModelLoader>>,
 }
 
 impl HttpService {
@@ -59,6 +238,22 @@ impl HttpService {
         &self.models
     }
 
+    /// Every route this service serves, given the endpoints that were enabled
+    pub fn route_docs(&self) -> &[RouteDoc] {
+        &self.route_docs
+    }
+
+    /// Apply new API keys, rate limits and model aliases. Requests already admitted finish
+    /// with the settings they started with. On error nothing changes.
+    pub fn reload(&self, config: ReloadConfig) -> Result<()> {
+        check_namespaces(config.api_keys.as_deref(), self.tenants.as_deref())?;
+        *self.api_keys.write().unwrap() = config.api_keys;
+        self.rate_limiter
+            .set_config(config.rate_limit.unwrap_or_default());
+        self.models.set_model_aliases(config.model_aliases);
+        Ok(())
+    }
+
     pub async fn spawn(&self, cancel_token: CancellationToken) -> JoinHandle<Result<()>> {
         let this = self.clone();
         tokio::spawn(async move { this.run(cancel_token).await })
@@ -66,7 +261,8 @@ impl HttpService {
 
     pub async fn run(&self, cancel_token: CancellationToken) -> Result<()> {
         let address = format!("{}:{}", self.host, self.port);
-        tracing::info!(address, "Starting HTTP service on: {address}");
+        let scheme = if self.tls.is_some() { "HTTPS" } else { "HTTP" };
+        tracing::info!(address, "Starting {scheme} service on: {address}");
 
         let listener = tokio::net::TcpListener::bind(address.as_str())
             .await
@@ -75,6 +271,14 @@ impl HttpService {
         let router = self.router.clone();
         let observer = cancel_token.child_token();
 
+        if let Some(acceptor) = self.tls.clone() {
+            return super::tls::serve(listener, router, acceptor, observer)
+                .await
+                .inspect_err(|_| cancel_token.cancel());
+        }
+
+        // The client address is the rate limiting key when there are no API keys
+        let router = router.into_make_service_with_connect_info::<SocketAddr>();
         axum::serve(listener, router)
             .with_graceful_shutdown(observer.cancelled_owned())
             .await
@@ -87,8 +291,21 @@ impl HttpService {
 impl HttpServiceConfigBuilder {
     pub fn build(self) -> Result<HttpService, anyhow::Error> {
         let config = self.build_internal()?;
+        // Fail now on bad certificates rather than when the service starts
+        let tls = config.tls.as_ref().map(TlsConfig::acceptor).transpose()?;
+        let access_log = config.access_log.map(AccessLog::new).transpose()?;
+        check_namespaces(config.api_keys.as_deref(), config.tenants.as_deref())?;
 
-        let model_manager = ModelManager::new();
+        let model_manager = ModelManager::with_overload_control(
+            config.slo.filter(SloConfig::is_enabled),
+            config.admission,
+        );
+        model_manager.set_model_aliases(config.model_aliases);
+        model_manager.set_stream_coalescing(config.stream_coalescing);
+        model_manager.set_request_limits(config.request_limits);
+        model_manager.set_content_filter(config.content_filter);
+        model_manager.set_sse_keep_alive(config.sse_keep_alive);
+        let cors = config.cors.as_ref().map(CorsConfig::layer).transpose()?;
 
         // enable prometheus metrics
         let registry = metrics::Registry::new();
@@ -100,13 +317,25 @@ impl HttpServiceConfigBuilder {
 
         let mut routes = vec![
             metrics::router(registry, None),
+            super::health::router(model_manager.state(), config.drain.clone()),
             super::openai::list_models_router(model_manager.state(), None),
         ];
 
         if config.enable_chat_endpoints {
+            let shared = config.response_cache_shared;
+            let cache = config
+                .response_cache
+                .map(|cache_config| Arc::new(ResponseCache::new(cache_config).with_shared(shared)));
+            let store = config.conversation_store;
+            let conversations = config.conversations.map(|conversation_config| {
+                Arc::new(Conversations::new(conversation_config, store))
+            });
             routes.push(super::openai::chat_completions_router(
                 model_manager.state(),
                 config.request_template,
+                cache,
+                config.presets,
+                conversations,
                 None,
             ));
         }
@@ -118,6 +347,63 @@ impl HttpServiceConfigBuilder {
             ));
         }
 
+        if config.enable_chat_endpoints || config.enable_cmpl_endpoints {
+            routes.push(super::openai::cancel_router(model_manager.state()));
+        }
+
+        if config.enable_embeddings_endpoints {
+            routes.push(super::openai::embeddings_router(
+                model_manager.state(),
+                None,
+            ));
+        }
+
+        if config.enable_transcriptions_endpoints {
+            routes.push(super::openai::transcriptions_router(
+                model_manager.state(),
+                None,
+            ));
+        }
+
+        if let Some(prefetch) = config.prefetch {
+            let prefetch_state = PrefetchState::new(model_manager.state(), prefetch);
+            routes.push(super::prefetch::prefetch_router(Arc::new(prefetch_state)));
+        }
+
+        if config.enable_tokenize_endpoints {
+            routes.push(super::openai::tokenize_router(model_manager.state()));
+        }
+
+        if let Some(tenants) = &config.tenants {
+            routes.push(super::tenancy::admin_router(tenants.clone()));
+        }
+
+        if config.enable_lora_admin_endpoints {
+            routes.push(super::lora::admin_router(model_manager.state()));
+        }
+        if config.enable_admin_endpoints {
+            routes.push(super::admin::admin_router(model_manager.state()));
+        }
+
+        if let Some(loader) = &config.model_loader {
+            routes.push(super::model_admin::admin_router(
+                model_manager.state(),
+                loader.clone(),
+            ));
+        }
+
+        if config.enable_batches_endpoints {
+            let batch_dir = config
+                .batch_dir
+                .unwrap_or_else(|| std::env::temp_dir().join("dynamo-batches"));
+            let batch_state = super::batches::BatchState::new(
+                model_manager.state(),
+                batch_dir,
+                config.batch_concurrency,
+            )?;
+            routes.push(super::batches::batches_router(Arc::new(batch_state)));
+        }
+
         // for (route_docs, route) in routes.into_iter().chain(self.routes.into_iter()) {
         //     router = router.merge(route);
         //     all_docs.extend(route_docs);
@@ -128,11 +414,71 @@ impl HttpServiceConfigBuilder {
             all_docs.extend(route_docs);
         }
 
+        // Applied after authentication, layers run from the last added
+        if let Some(idempotency) = config.idempotency {
+            router = router.layer(axum::middleware::from_fn_with_state(
+                Arc::new(IdempotencyStore::new(idempotency)),
+                super::idempotency::idempotent,
+            ));
+        }
+        if let Some(tenants) = config.tenants.clone() {
+            let state = TenancyState {
+                tenants,
+                deployment: model_manager.state(),
+            };
+            router = router.layer(axum::middleware::from_fn_with_state(
+                Arc::new(state),
+                super::tenancy::enforce_quota,
+            ));
+        }
+        // Always installed so that a reload can turn them on, they let everything through
+        // while off
+        let rate_limiter = Arc::new(RateLimiter::new(config.rate_limit.unwrap_or_default()));
+        router = router.layer(axum::middleware::from_fn_with_state(
+            rate_limiter.clone(),
+            super::rate_limit::rate_limit,
+        ));
+        let api_keys = Arc::new(RwLock::new(config.api_keys));
+        router = router.layer(axum::middleware::from_fn_with_state(
+            api_keys.clone(),
+            super::auth::require_api_key,
+        ));
+        if let Some(drain) = config.drain {
+            router = router.layer(axum::middleware::from_fn_with_state(
+                drain,
+                super::drain::reject_when_draining,
+            ));
+        }
+        // Outside of the others, to log the requests they reject
+        if let Some(access_log) = access_log {
+            router = router.layer(axum::middleware::from_fn_with_state(
+                Arc::new(access_log),
+                super::access_log::log_request,
+            ));
+        }
+        // Before rate limiting and the access log, which see the client it finds
+        if let Some(proxies) = config.trusted_proxies {
+            router = router.layer(axum::middleware::from_fn_with_state(
+                Arc::new(proxies),
+                super::forwarded::resolve_client,
+            ));
+        }
+        router = router.layer(axum::middleware::from_fn(super::trace::trace_request));
+        // Outermost, preflights have no API key
+        if let Some(cors) = cors {
+            router = router.layer(cors);
+        }
+
         Ok(HttpService {
             models: model_manager,
             router,
+            route_docs: all_docs,
             port: config.port,
             host: config.host,
+            tls,
+            api_keys,
+            rate_limiter,
+            tenants: config.tenants,
         })
     }
 
@@ -141,3 +487,13 @@ impl HttpServiceConfigBuilder {
         self
     }
 }
+
+/// Keys with a namespace need it to be one of the tenants
+fn check_namespaces(api_keys: Option<&ApiKeys>, tenants: Option<&Tenants>) -> Result<()> {
+    for namespace in api_keys.iter().flat_map(|api_keys| api_keys.namespaces()) {
+        if !tenants.is_some_and(|tenants| tenants.contains(&namespace)) {
+            anyhow::bail!("API key namespace '{namespace}' is not one of the tenants");
+        }
+    }
+    Ok(())
+}
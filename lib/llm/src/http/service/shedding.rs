@@ -0,0 +1,302 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Load shedding on latency SLOs
+//!
+//! For each model we keep the latencies of the requests of the last [`WINDOW`]:
+//! - Queue delay, from the request arriving until an engine accepted it.
+//! - Time to first token.
+//!
+//! While the p99 of either is over its SLO the model is overloaded, and new requests to it are
+//! rejected with a 503, or have their max tokens lowered so they free the engine sooner.
+//! Requests already running are left alone. Once the slow requests are out of the window the
+//! model is admitting everything again.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::types::Annotated;
+
+/// Latencies older than this don't count
+const WINDOW: Duration = Duration::from_secs(30);
+
+/// Below this many latencies in the window a p99 means nothing, we admit everything
+const MIN_SAMPLES: usize = 20;
+
+/// Re-compute the percentiles at most this often
+const RECHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Default)]
+pub struct SloConfig {
+    /// p99 time to first token
+    pub ttft_p99: Option<Duration>,
+
+    /// p99 time from a request arriving until an engine accepted it
+    pub queue_delay_p99: Option<Duration>,
+
+    /// What to do with new requests while a model is over its SLOs
+    pub action: ShedAction,
+}
+
+impl SloConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.ttft_p99.is_some() || self.queue_delay_p99.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ShedAction {
+    /// Fail with a 503
+    #[default]
+    Reject,
+
+    /// Admit, but generate at most `max_tokens`
+    Degrade { max_tokens: u32 },
+}
+
+/// Latencies in the window, oldest first
+#[derive(Default)]
+struct Samples(VecDeque<(Instant, Duration)>);
+
+impl Samples {
+    fn push(&mut self, now: Instant, latency: Duration) {
+        self.0.push_back((now, latency));
+    }
+
+    fn p99(&mut self, now: Instant) -> Option<Duration> {
+        while let Some((at, _)) = self.0.front() {
+            if now.duration_since(*at) <= WINDOW {
+                break;
+            }
+            self.0.pop_front();
+        }
+        if self.0.len() < MIN_SAMPLES {
+            return None;
+        }
+        let mut latencies: Vec<Duration> = self.0.iter().map(|(_, latency)| *latency).collect();
+        latencies.sort_unstable();
+        let rank = (latencies.len() as f64 * 0.99).ceil() as usize;
+        Some(latencies[rank.saturating_sub(1)])
+    }
+}
+
+#[derive(Default)]
+struct ModelLatency {
+    queue_delay: Samples,
+    ttft: Samples,
+    /// Why the model is overloaded, None if it isn't
+    overloaded: Option<String>,
+    checked: Option<Instant>,
+}
+
+/// A model is over its SLOs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Overloaded {
+    pub model: String,
+    pub reason: String,
+}
+
+impl std::fmt::Display for Overloaded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Model {} is overloaded: {}", self.model, self.reason)
+    }
+}
+
+pub struct LoadShedder {
+    config: SloConfig,
+    models: Mutex<HashMap<String, ModelLatency>>,
+}
+
+impl LoadShedder {
+    pub fn new(config: SloConfig) -> Self {
+        LoadShedder {
+            config,
+            models: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Decide on a request to `model` that arrived at `received`. The returned timer reports
+    /// its latencies, and says whether to lower its max tokens.
+    pub fn admit(
+        self: &Arc<Self>,
+        model: &str,
+        received: Instant,
+    ) -> Result<RequestTimer, Overloaded> {
+        let now = Instant::now();
+        let mut models = self.models.lock().unwrap();
+        let latency = models.entry(model.to_string()).or_default();
+        if latency
+            .checked
+            .is_none_or(|checked| now.duration_since(checked) >= RECHECK_INTERVAL)
+        {
+            latency.overloaded = self.check(latency, now);
+            latency.checked = Some(now);
+        }
+
+        let mut max_tokens = None;
+        if let Some(reason) = &latency.overloaded {
+            match self.config.action {
+                ShedAction::Reject => {
+                    return Err(Overloaded {
+                        model: model.to_string(),
+                        reason: reason.clone(),
+                    });
+                }
+                ShedAction::Degrade { max_tokens: cap } => max_tokens = Some(cap),
+            }
+        }
+        Ok(RequestTimer {
+            shedder: self.clone(),
+            model: model.to_string(),
+            received,
+            max_tokens,
+            first_token: false,
+        })
+    }
+
+    /// Which SLO the model is missing, if any
+    fn check(&self, latency: &mut ModelLatency, now: Instant) -> Option<String> {
+        let slos = [
+            (
+                "queue delay",
+                self.config.queue_delay_p99,
+                &mut latency.queue_delay,
+            ),
+            (
+                "time to first token",
+                self.config.ttft_p99,
+                &mut latency.ttft,
+            ),
+        ];
+        for (what, slo, samples) in slos {
+            let Some(slo) = slo else {
+                continue;
+            };
+            match samples.p99(now) {
+                Some(p99) if p99 > slo => {
+                    return Some(format!(
+                        "p99 {what} is {}ms, over the {}ms objective",
+                        p99.as_millis(),
+                        slo.as_millis()
+                    ));
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    fn observe(&self, model: &str, queue_delay: Option<Duration>, ttft: Option<Duration>) {
+        let now = Instant::now();
+        let mut models = self.models.lock().unwrap();
+        let latency = models.entry(model.to_string()).or_default();
+        if let Some(queue_delay) = queue_delay {
+            latency.queue_delay.push(now, queue_delay);
+        }
+        if let Some(ttft) = ttft {
+            latency.ttft.push(now, ttft);
+        }
+    }
+}
+
+/// Times one admitted request for the [`LoadShedder`]
+pub struct RequestTimer {
+    shedder: Arc<LoadShedder>,
+    model: String,
+    received: Instant,
+    max_tokens: Option<u32>,
+    first_token: bool,
+}
+
+impl RequestTimer {
+    /// Lower the request's max tokens to this, the model is overloaded
+    pub fn max_tokens(&self) -> Option<u32> {
+        self.max_tokens
+    }
+
+    /// The engine accepted the request
+    pub fn accepted(&self) {
+        self.shedder
+            .observe(&self.model, Some(self.received.elapsed()), None);
+    }
+
+    /// Call with each response, the first with data is the first token
+    pub fn observe<T>(&mut self, response: &Annotated<T>) {
+        if self.first_token || response.data.is_none() {
+            return;
+        }
+        self.first_token = true;
+        self.shedder
+            .observe(&self.model, None, Some(self.received.elapsed()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_shedder() {
+        let shedder = Arc::new(LoadShedder::new(SloConfig {
+            ttft_p99: Some(Duration::from_millis(500)),
+            queue_delay_p99: None,
+            action: ShedAction::Reject,
+        }));
+        let now = Instant::now();
+        {
+            let mut models = shedder.models.lock().unwrap();
+            let latency = models.entry("slow".to_string()).or_default();
+            for _ in 0..MIN_SAMPLES {
+                latency.ttft.push(now, Duration::from_secs(1));
+            }
+            // Not considered, the queue delay has no SLO
+            let latency = models.entry("fast".to_string()).or_default();
+            for _ in 0..MIN_SAMPLES {
+                latency.ttft.push(now, Duration::from_millis(100));
+                latency.queue_delay.push(now, Duration::from_secs(1));
+            }
+        }
+
+        let err = shedder.admit("slow", now).err().unwrap();
+        assert_eq!(
+            err.reason,
+            "p99 time to first token is 1000ms, over the 500ms objective"
+        );
+        let timer = shedder.admit("fast", now).unwrap();
+        assert_eq!(timer.max_tokens(), None);
+        // Never seen
+        assert!(shedder.admit("other", now).is_ok());
+
+        let degrading = Arc::new(LoadShedder::new(SloConfig {
+            action: ShedAction::Degrade { max_tokens: 64 },
+            ..shedder.config.clone()
+        }));
+        *degrading.models.lock().unwrap() = std::mem::take(&mut *shedder.models.lock().unwrap());
+        assert_eq!(degrading.admit("slow", now).unwrap().max_tokens(), Some(64));
+    }
+
+    #[test]
+    fn test_p99() {
+        let now = Instant::now();
+        let mut samples = Samples::default();
+        for ms in 1..=100 {
+            samples.push(now, Duration::from_millis(ms));
+        }
+        assert_eq!(samples.p99(now), Some(Duration::from_millis(99)));
+        // All out of the window
+        assert_eq!(samples.p99(now + WINDOW * 2), None);
+    }
+}
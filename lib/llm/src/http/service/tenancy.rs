@@ -0,0 +1,121 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+
+use super::error::ErrorKind;
+use super::openai::ErrorResponse;
+use super::{DeploymentState, RouteDoc};
+use crate::auth::ApiKey;
+use crate::tenancy::{NamespaceUsage, TenancyError, Tenants};
+
+pub(crate) struct TenancyState {
+    pub tenants: Arc<Tenants>,
+    pub deployment: Arc<DeploymentState>,
+}
+
+/// The namespace of the caller's API key, added to the request extensions by
+/// [`enforce_quota`]
+#[derive(Clone)]
+pub(crate) struct NamespaceAccess {
+    namespace: String,
+    state: Arc<TenancyState>,
+}
+
+impl NamespaceAccess {
+    pub fn name(&self) -> &str {
+        &self.namespace
+    }
+
+    /// Only the models registered in our namespace, and aliases all of whose targets are
+    pub fn allows_model(&self, model: &str) -> bool {
+        let deployment = &self.state.deployment;
+        deployment.alias_targets(model).iter().all(|target| {
+            deployment.model_namespace(target).as_deref() == Some(self.namespace.as_str())
+        })
+    }
+
+    /// Count the tokens of a response against our quota
+    pub fn charge(&self, tokens: u64) {
+        self.state.tenants.charge(&self.namespace, tokens);
+    }
+}
+
+/// Middleware turning away requests of namespaces over their quota. Must run after
+/// authentication, the API key says which namespace the request belongs to.
+pub(crate) async fn enforce_quota(
+    State(state): State<Arc<TenancyState>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let namespace = request
+        .extensions()
+        .get::<Arc<ApiKey>>()
+        .and_then(|api_key| api_key.namespace.clone());
+    let Some(namespace) = namespace else {
+        return next.run(request).await;
+    };
+    if let Err(err) = state.tenants.check(&namespace) {
+        tracing::debug!(namespace, %err, "Request refused");
+        return tenancy_error(err);
+    }
+    request
+        .extensions_mut()
+        .insert(NamespaceAccess { namespace, state });
+    next.run(request).await
+}
+
+fn tenancy_error(err: TenancyError) -> Response {
+    let message = err.to_string();
+    match err {
+        TenancyError::UnknownNamespace(_) => {
+            ErrorResponse::of_kind(ErrorKind::Forbidden, &message).into_response()
+        }
+        TenancyError::QuotaExceeded {
+            resets_in: Some(resets_in),
+            ..
+        } => (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, resets_in.as_secs().max(1).to_string())],
+            ErrorResponse::json_of_kind(ErrorKind::Overloaded, &message),
+        )
+            .into_response(),
+        TenancyError::QuotaExceeded { .. } => {
+            ErrorResponse::of_kind(ErrorKind::Overloaded, &message).into_response()
+        }
+    }
+}
+
+/// `GET /admin/namespaces`: the tokens each namespace used against its quota
+pub(crate) fn admin_router(tenants: Arc<Tenants>) -> (Vec<RouteDoc>, Router) {
+    let path = "/admin/namespaces";
+    let router = Router::new()
+        .route(path, get(namespace_usage))
+        .with_state(tenants);
+    (vec![RouteDoc::new(axum::http::Method::GET, path)], router)
+}
+
+async fn namespace_usage(State(tenants): State<Arc<Tenants>>) -> Json<Vec<NamespaceUsage>> {
+    Json(tenants.usage())
+}
@@ -0,0 +1,196 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Per-stage timings for clients that ask for them with `nvext.timings`.
+//!
+//! The pipeline stages time themselves into the [`StageTimings`] of the request context:
+//! tokenizing in the preprocessor, detokenizing in the backend, and the transfer to the worker
+//! in the router. The rest we see from here, when the engine accepted the request and when
+//! tokens came out of it.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use dynamo_runtime::pipeline::{
+    timings::{StageTimings, STAGE_TIMINGS_CONTEXT_KEY, TRANSFER_STAGE},
+    Context,
+};
+use futures::{Stream, StreamExt};
+
+use crate::backend::DETOKENIZE_STAGE;
+use crate::preprocessor::TOKENIZE_STAGE;
+use crate::protocols::openai::{
+    chat_completions::NvCreateChatCompletionStreamResponse,
+    completions::CompletionResponse,
+    nvext::{NvResponseExt, Timings},
+};
+use crate::types::Annotated;
+
+pub(crate) struct ResponseTimer {
+    stages: Arc<StageTimings>,
+    received: Instant,
+    accepted: Option<Instant>,
+    first_token: Option<Instant>,
+    last_token: Option<Instant>,
+}
+
+impl ResponseTimer {
+    /// Have the pipeline time `request`, which arrived at `received`
+    pub(crate) fn start<T: Send + Sync + 'static>(
+        request: &mut Context<T>,
+        received: Instant,
+    ) -> Self {
+        request.insert(STAGE_TIMINGS_CONTEXT_KEY, StageTimings::default());
+        ResponseTimer {
+            stages: request
+                .get::<StageTimings>(STAGE_TIMINGS_CONTEXT_KEY)
+                .expect("just inserted"),
+            received,
+            accepted: None,
+            first_token: None,
+            last_token: None,
+        }
+    }
+
+    /// The engine accepted the request
+    pub(crate) fn accepted(&mut self) {
+        self.accepted = Some(Instant::now());
+    }
+
+    /// Call with each response, those with data carry tokens
+    pub(crate) fn observe<T>(&mut self, response: &Annotated<T>) {
+        if response.data.is_none() {
+            return;
+        }
+        let now = Instant::now();
+        self.first_token.get_or_insert(now);
+        self.last_token = Some(now);
+    }
+
+    pub(crate) fn timings(&self) -> Timings {
+        let accepted = self.accepted.unwrap_or_else(Instant::now);
+        let first_token = self.first_token.unwrap_or(accepted);
+        let last_token = self.last_token.unwrap_or(first_token);
+
+        let tokenize = self.stages.get(TOKENIZE_STAGE);
+        let transfer = self.stages.get(TRANSFER_STAGE);
+        let queue = accepted
+            .saturating_duration_since(self.received)
+            .saturating_sub(tokenize + transfer);
+        Timings {
+            queue_ms: millis(queue),
+            tokenize_ms: millis(tokenize),
+            transfer_ms: millis(transfer),
+            prefill_ms: millis(first_token.saturating_duration_since(accepted)),
+            decode_ms: millis(last_token.saturating_duration_since(first_token)),
+            detokenize_ms: millis(self.stages.get(DETOKENIZE_STAGE)),
+        }
+    }
+}
+
+/// Milliseconds, to the microsecond
+fn millis(duration: Duration) -> f64 {
+    duration.as_micros() as f64 / 1000.0
+}
+
+/// Streamed responses that can end with a chunk carrying only the timings
+pub(crate) trait TimingsChunk: Sized {
+    /// A chunk like this one, without choices or usage, carrying `timings`
+    fn timings_chunk(&self, timings: Timings) -> Self;
+}
+
+impl TimingsChunk for NvCreateChatCompletionStreamResponse {
+    fn timings_chunk(&self, timings: Timings) -> Self {
+        let mut inner = self.inner.clone();
+        inner.choices.clear();
+        inner.usage = None;
+        NvCreateChatCompletionStreamResponse {
+            inner,
+            nvext: Some(NvResponseExt::from_timings(timings)),
+        }
+    }
+}
+
+impl TimingsChunk for CompletionResponse {
+    fn timings_chunk(&self, timings: Timings) -> Self {
+        CompletionResponse {
+            choices: vec![],
+            usage: None,
+            nvext: Some(NvResponseExt::from_timings(timings)),
+            ..self.clone()
+        }
+    }
+}
+
+/// Time the responses of `stream`, and end it with a chunk carrying the timings. Folding
+/// the stream into one response keeps them. A stream without any data, such as one that
+/// failed, gets no timings.
+pub(crate) fn with_timings<R>(
+    stream: impl Stream<Item = Annotated<R>> + Send + 'static,
+    mut timer: ResponseTimer,
+) -> impl Stream<Item = Annotated<R>> + Send
+where
+    R: TimingsChunk + Clone + Send + 'static,
+{
+    async_stream::stream! {
+        let mut stream = std::pin::pin!(stream);
+        let mut last = None;
+        while let Some(response) = stream.next().await {
+            timer.observe(&response);
+            if let Some(data) = &response.data {
+                last = Some(data.clone());
+            }
+            yield response;
+        }
+        if let Some(last) = last {
+            yield Annotated::from_data(last.timings_chunk(timer.timings()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_timings() {
+        let mut request = Context::new(());
+        let received = Instant::now();
+        let mut timer = ResponseTimer::start(&mut request, received);
+        let stages = request
+            .get::<StageTimings>(STAGE_TIMINGS_CONTEXT_KEY)
+            .unwrap();
+        stages.record(TOKENIZE_STAGE, Duration::from_millis(2));
+        stages.record(TRANSFER_STAGE, Duration::from_millis(3));
+        stages.record(DETOKENIZE_STAGE, Duration::from_micros(1500));
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        timer.accepted();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        timer.observe(&Annotated::from_data(()));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        // Events don't count
+        timer.observe(&Annotated::<()>::from_annotation("event", &"").unwrap());
+        timer.observe(&Annotated::from_data(()));
+
+        let timings = timer.timings();
+        assert_eq!(timings.tokenize_ms, 2.0);
+        assert_eq!(timings.transfer_ms, 3.0);
+        assert_eq!(timings.detokenize_ms, 1.5);
+        assert!(timings.queue_ms >= 15.0, "{timings:?}");
+        assert!(timings.prefill_ms >= 20.0, "{timings:?}");
+        assert!(timings.decode_ms >= 20.0, "{timings:?}");
+    }
+}
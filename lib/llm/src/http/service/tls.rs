@@ -0,0 +1,169 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! HTTPS, and optionally mutual TLS, for the HTTP service
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{extract::ConnectInfo, Extension, Router};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::{conn::auto, graceful::GracefulShutdown},
+    service::TowerToHyperService,
+};
+use tokio::net::TcpListener;
+use tokio_rustls::{
+    rustls::{
+        crypto::ring,
+        pki_types::{CertificateDer, PrivateKeyDer},
+        server::WebPkiClientVerifier,
+        RootCertStore, ServerConfig,
+    },
+    TlsAcceptor,
+};
+use tokio_util::sync::CancellationToken;
+
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    /// PEM certificate chain, the server's certificate first
+    pub cert: PathBuf,
+
+    /// PEM private key of the certificate
+    pub key: PathBuf,
+
+    /// PEM certificates of the CAs client certificates must be signed by. When set, clients
+    /// without such a certificate cannot connect.
+    pub client_ca: Option<PathBuf>,
+}
+
+impl TlsConfig {
+    pub(crate) fn acceptor(&self) -> anyhow::Result<TlsAcceptor> {
+        let certs = load_certs(&self.cert)?;
+        let key = load_key(&self.key)?;
+
+        let provider = Arc::new(ring::default_provider());
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?;
+        let builder = match &self.client_ca {
+            Some(client_ca) => {
+                let mut roots = RootCertStore::empty();
+                for cert in load_certs(client_ca)? {
+                    roots
+                        .add(cert)
+                        .with_context(|| client_ca.display().to_string())?;
+                }
+                let verifier =
+                    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                        .build()?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let mut config = builder
+            .with_single_cert(certs, key)
+            .with_context(|| self.cert.display().to_string())?;
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+fn load_certs(path: &Path) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    let mut reader = BufReader::new(File::open(path).with_context(|| path.display().to_string())?);
+    let certs = rustls_pemfile::certs(&mut reader)
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| path.display().to_string())?;
+    if certs.is_empty() {
+        anyhow::bail!("No certificate found in {}", path.display());
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &Path) -> anyhow::Result<PrivateKeyDer<'static>> {
+    let mut reader = BufReader::new(File::open(path).with_context(|| path.display().to_string())?);
+    rustls_pemfile::private_key(&mut reader)
+        .with_context(|| path.display().to_string())?
+        .ok_or_else(|| anyhow::anyhow!("No private key found in {}", path.display()))
+}
+
+/// Serve `router` over TLS until `cancel_token` is cancelled, then wait for the open
+/// connections to finish.
+pub(crate) async fn serve(
+    listener: TcpListener,
+    router: Router,
+    acceptor: TlsAcceptor,
+    cancel_token: CancellationToken,
+) -> anyhow::Result<()> {
+    let graceful = GracefulShutdown::new();
+    let builder = auto::Builder::new(TokioExecutor::new());
+    loop {
+        let (stream, addr) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    tracing::warn!(%err, "Failed accepting connection");
+                    continue;
+                }
+            },
+            _ = cancel_token.cancelled() => break,
+        };
+
+        // What `into_make_service_with_connect_info` does for plain HTTP
+        let service = TowerToHyperService::new(router.clone().layer(Extension(ConnectInfo(addr))));
+        let acceptor = acceptor.clone();
+        let builder = builder.clone();
+        let watcher = graceful.watcher();
+        // Handshake off the accept loop, a slow client must not hold the others back
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(err) => {
+                    tracing::debug!(%err, %addr, "TLS handshake failed");
+                    return;
+                }
+            };
+            let connection = builder.serve_connection_with_upgrades(TokioIo::new(stream), service);
+            if let Err(err) = watcher.watch(connection.into_owned()).await {
+                tracing::debug!(%err, %addr, "Connection error");
+            }
+        });
+    }
+
+    graceful.shutdown().await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_files() {
+        let config = TlsConfig {
+            cert: PathBuf::from("/nonexistent/cert.pem"),
+            key: PathBuf::from("/nonexistent/key.pem"),
+            client_ca: None,
+        };
+        let err = config.acceptor().unwrap_err();
+        assert_eq!(err.to_string(), "/nonexistent/cert.pem");
+
+        let empty = tempfile::NamedTempFile::new().unwrap();
+        let err = load_certs(empty.path()).unwrap_err();
+        assert!(err.to_string().starts_with("No certificate found"));
+    }
+}
@@ -0,0 +1,140 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Token usage of streamed responses.
+//!
+//! The delta generators put the usage so far in every chunk. The service reads it from there
+//! for the metrics, the access log and the token quotas, then takes it out of the chunks sent
+//! to the client. As with OpenAI, a client that sets `stream_options: {"include_usage": true}`
+//! gets one more chunk at the end, without choices, carrying the usage of the whole response.
+
+use futures::{Stream, StreamExt};
+
+use crate::protocols::openai::{
+    chat_completions::NvCreateChatCompletionStreamResponse, completions::CompletionResponse,
+};
+use crate::types::Annotated;
+
+/// Streamed responses that carry the usage so far
+pub(crate) trait UsageChunk: Sized {
+    type Usage;
+
+    /// Take the usage out of this chunk
+    fn take_usage(&mut self) -> Option<Self::Usage>;
+
+    /// A chunk like this one, without choices, carrying `usage`
+    fn usage_chunk(&self, usage: Self::Usage) -> Self;
+}
+
+impl UsageChunk for NvCreateChatCompletionStreamResponse {
+    type Usage = async_openai::types::CompletionUsage;
+
+    fn take_usage(&mut self) -> Option<Self::Usage> {
+        self.inner.usage.take()
+    }
+
+    fn usage_chunk(&self, usage: Self::Usage) -> Self {
+        let mut inner = self.inner.clone();
+        inner.choices.clear();
+        inner.usage = Some(usage);
+        NvCreateChatCompletionStreamResponse { inner, nvext: None }
+    }
+}
+
+impl UsageChunk for CompletionResponse {
+    type Usage = crate::protocols::openai::CompletionUsage;
+
+    fn take_usage(&mut self) -> Option<Self::Usage> {
+        self.usage.take()
+    }
+
+    fn usage_chunk(&self, usage: Self::Usage) -> Self {
+        CompletionResponse {
+            choices: vec![],
+            usage: Some(usage),
+            nvext: None,
+            ..self.clone()
+        }
+    }
+}
+
+/// Take the usage out of the chunks of `stream`, and if `include_usage`, end it with a chunk
+/// carrying the last of it. A stream that never had usage gets no such chunk.
+pub(crate) fn with_stream_usage<R>(
+    stream: impl Stream<Item = Annotated<R>> + Send + 'static,
+    include_usage: bool,
+) -> impl Stream<Item = Annotated<R>> + Send
+where
+    R: UsageChunk + Clone + Send + 'static,
+    R::Usage: Send,
+{
+    async_stream::stream! {
+        let mut stream = std::pin::pin!(stream);
+        let mut last = None;
+        while let Some(mut response) = stream.next().await {
+            if let Some(data) = &mut response.data {
+                if let Some(usage) = data.take_usage() {
+                    last = Some((data.clone(), usage));
+                }
+            }
+            yield response;
+        }
+        if let Some((chunk, usage)) = last.filter(|_| include_usage) {
+            yield Annotated::from_data(chunk.usage_chunk(usage));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::openai::{completions::CompletionChoice, CompletionUsage};
+
+    fn chunk(text: &str, completion_tokens: i32) -> Annotated<CompletionResponse> {
+        Annotated::from_data(CompletionResponse {
+            id: "cmpl-1".to_string(),
+            object: "text_completion".to_string(),
+            created: 0,
+            model: "llama".to_string(),
+            system_fingerprint: None,
+            choices: vec![CompletionChoice::builder().text(text).build().unwrap()],
+            usage: Some(CompletionUsage {
+                prompt_tokens: 3,
+                completion_tokens,
+                total_tokens: 3 + completion_tokens,
+                ..Default::default()
+            }),
+            nvext: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_stream_usage() {
+        let chunks = || futures::stream::iter(vec![chunk("Hello", 1), chunk(" world", 2)]);
+
+        let responses: Vec<_> = with_stream_usage(chunks(), false).collect().await;
+        assert_eq!(responses.len(), 2);
+        assert!(responses
+            .iter()
+            .all(|r| r.data.as_ref().unwrap().usage.is_none()));
+
+        let responses: Vec<_> = with_stream_usage(chunks(), true).collect().await;
+        assert_eq!(responses.len(), 3);
+        let last = responses[2].data.as_ref().unwrap();
+        assert!(last.choices.is_empty());
+        let usage = last.usage.as_ref().unwrap();
+        assert_eq!((usage.completion_tokens, usage.total_tokens), (2, 5));
+    }
+}
@@ -13,28 +13,162 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use hf_hub::api::tokio::ApiBuilder;
+//! Models from Hugging Face
+//!
+//! Files go to the Hugging Face cache, `$HF_HOME/hub` or `~/.cache/huggingface/hub`, in the
+//! layout the Hugging Face tools use, so a model any of them downloaded is not downloaded
+//! again. A file downloads to `blobs/<id>.incomplete` first: when a download dies, the next
+//! one continues from there instead of from zero. Several files download at once, weights are
+//! checked against the SHA-256 the hub lists for them, and progress is logged as it goes.
+//!
+//! [`REVISION_ENV`] picks the branch, tag or commit, `main` by default. `HF_ENDPOINT` and
+//! `HF_TOKEN` work as they do with the Hugging Face tools. If the hub can't be reached, a
+//! snapshot of the revision downloaded before is used.
+
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::Context as _;
+use futures::{StreamExt, TryStreamExt};
+use hf_hub::Cache;
+use reqwest::header::{AUTHORIZATION, RANGE};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
+
+/// The branch, tag or commit of the models to download
+pub const REVISION_ENV: &str = "DYN_HF_REVISION";
+
+const ENDPOINT_ENV: &str = "HF_ENDPOINT";
+const TOKEN_ENV: &str = "HF_TOKEN";
+const DEFAULT_ENDPOINT: &str = "https://huggingface.co";
+const DEFAULT_REVISION: &str = "main";
+
+/// Files downloading at once
+pub(crate) const PARALLEL_FILES: usize = 4;
+
+/// Tries per file, each one continuing where the one before stopped
+pub(crate) const MAX_ATTEMPTS: u32 = 5;
+
+/// How often a download logs its progress
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+pub(crate) const BYTES_PER_GB: f64 = 1_000_000_000.0;
 
 const IGNORED: [&str; 3] = [".gitattributes", "LICENSE", "README.md"];
 
+/// Extensions of the files holding a model's weights
+const WEIGHTS: [&str; 7] = [
+    ".safetensors",
+    ".bin",
+    ".gguf",
+    ".pt",
+    ".pth",
+    ".onnx",
+    ".msgpack",
+];
+
 /// Attempt to download a model from Hugging Face
 /// Returns the directory it is in
 pub async fn from_hf(name: impl AsRef<Path>) -> anyhow::Result<PathBuf> {
-    let name = name.as_ref();
-    let api = ApiBuilder::new().with_progress(true).build()?;
-    let model_name = name.display().to_string();
+    download(name.as_ref(), true).await
+}
 
-    let repo = api.model(model_name.clone());
+/// Download a model's config, tokenizer and other small files from Hugging Face, skipping its
+/// weights. Returns the directory they are in.
+pub async fn metadata_from_hf(name: impl AsRef<Path>) -> anyhow::Result<PathBuf> {
+    download(name.as_ref(), false).await
+}
+
+/// `/api/models/<model>/revision/<revision>?blobs=true`, what we use of it
+#[derive(Deserialize, Debug)]
+struct RepoInfo {
+    /// The commit the revision is at
+    sha: String,
+    siblings: Vec<Sibling>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Sibling {
+    rfilename: String,
+    size: Option<u64>,
+    /// Git's id of the file. Large files are in LFS, and their blob is named after `lfs.sha256`.
+    #[serde(rename = "blobId")]
+    blob_id: Option<String>,
+    lfs: Option<Lfs>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Lfs {
+    sha256: String,
+    size: u64,
+}
 
-    let info = match repo.info().await {
+impl Sibling {
+    /// The name of the file in the cache's `blobs`
+    fn blob_name(&self) -> Option<&str> {
+        match &self.lfs {
+            Some(lfs) => Some(&lfs.sha256),
+            None => self.blob_id.as_deref(),
+        }
+    }
+
+    fn expected_size(&self) -> Option<u64> {
+        self.lfs.as_ref().map(|lfs| lfs.size).or(self.size)
+    }
+}
+
+/// The commit that [`REVISION_ENV`] of the model `name` is at, from the hub, or from the cache
+/// if the hub can't be reached
+pub async fn resolve_revision(name: &str) -> anyhow::Result<String> {
+    let revision = revision_from_env();
+    let hub = Hub::new(name)?;
+    match hub.info(&revision).await {
+        Ok(info) => Ok(info.sha),
+        Err(err) => hub.cached_commit(&revision).ok_or(err),
+    }
+}
+
+/// The commit of a snapshot [`from_hf`] returned
+pub fn snapshot_commit(snapshot: &Path) -> Option<String> {
+    let name = snapshot.file_name()?.to_str()?;
+    let in_snapshots = snapshot
+        .parent()
+        .and_then(Path::file_name)
+        .is_some_and(|parent| parent == "snapshots");
+    in_snapshots.then(|| name.to_string())
+}
+
+fn revision_from_env() -> String {
+    std::env::var(REVISION_ENV)
+        .ok()
+        .filter(|revision| !revision.is_empty())
+        .unwrap_or_else(|| DEFAULT_REVISION.to_string())
+}
+
+async fn download(name: &Path, with_weights: bool) -> anyhow::Result<PathBuf> {
+    let model_name = name.display().to_string();
+    let revision = revision_from_env();
+    let hub = Hub::new(&model_name)?;
+
+    let info = match hub.info(&revision).await {
         Ok(info) => info,
-        Err(e) => {
-            return Err(anyhow::anyhow!(
-                "Failed to fetch model '{}' from HuggingFace: {}. Is this a valid HuggingFace ID?",
-                model_name,
-                e
-            ));
+        Err(err) => {
+            if let Some(snapshot) = hub.cached_snapshot(&revision) {
+                tracing::warn!(
+                    model = model_name,
+                    revision,
+                    "Using the model downloaded before, Hugging Face could not be reached: {err:#}"
+                );
+                return Ok(snapshot);
+            }
+            return Err(err.context(format!(
+                "Failed to fetch model '{model_name}' from HuggingFace. Is this a valid HuggingFace ID?"
+            )));
         }
     };
 
@@ -44,42 +178,488 @@ pub async fn from_hf(name: impl AsRef<Path>) -> anyhow::Result<PathBuf> {
             model_name
         ));
     }
+    let files: Vec<&Sibling> = info
+        .siblings
+        .iter()
+        .filter(|sib| !IGNORED.contains(&sib.rfilename.as_str()) && !is_image(&sib.rfilename))
+        .filter(|sib| with_weights || !is_weights(&sib.rfilename))
+        .collect();
+    if files.is_empty() {
+        return Err(anyhow::anyhow!(
+            "No valid files found for model '{}'.",
+            model_name
+        ));
+    }
 
-    let mut p = PathBuf::new();
-    let mut files_downloaded = false;
+    let snapshot = hub.repo_dir.join("snapshots").join(&info.sha);
+    let total = files.iter().filter_map(|file| file.expected_size()).sum();
+    let progress = Progress::new(&model_name, total);
+    futures::stream::iter(files)
+        .map(|file| hub.fetch(&info.sha, file, &snapshot, &progress))
+        .buffer_unordered(PARALLEL_FILES)
+        .try_collect::<Vec<()>>()
+        .await?;
+    progress.finish();
+    hub.set_ref(&revision, &info.sha)?;
+    Ok(snapshot)
+}
+
+/// One model on the hub, and where it goes in the cache
+struct Hub {
+    client: reqwest::Client,
+    endpoint: String,
+    token: Option<String>,
+    model: String,
+    /// `models--<org>--<name>` in the cache
+    repo_dir: PathBuf,
+}
+
+impl Hub {
+    fn new(model: &str) -> anyhow::Result<Self> {
+        let cache = Cache::from_env();
+        let token = std::env::var(TOKEN_ENV)
+            .ok()
+            .filter(|token| !token.is_empty())
+            .or_else(|| cache.token());
+        let endpoint = std::env::var(ENDPOINT_ENV)
+            .unwrap_or_else(|_| DEFAULT_ENDPOINT.to_string())
+            .trim_end_matches('/')
+            .to_string();
+        let client = reqwest::Client::builder()
+            .user_agent(concat!("dynamo/", env!("CARGO_PKG_VERSION")))
+            .build()?;
+        Ok(Hub {
+            client,
+            endpoint,
+            token,
+            model: model.to_string(),
+            repo_dir: cache
+                .path()
+                .join(format!("models--{}", model.replace('/', "--"))),
+        })
+    }
 
-    for sib in info.siblings {
-        if IGNORED.contains(&sib.rfilename.as_str()) || is_image(&sib.rfilename) {
-            continue;
+    fn get(&self, url: &str) -> reqwest::RequestBuilder {
+        let request = self.client.get(url);
+        match &self.token {
+            // reqwest drops it when a download redirects to another host
+            Some(token) => request.header(AUTHORIZATION, format!("Bearer {token}")),
+            None => request,
         }
+    }
 
-        match repo.get(&sib.rfilename).await {
-            Ok(path) => {
-                p = path;
-                files_downloaded = true;
+    async fn info(&self, revision: &str) -> anyhow::Result<RepoInfo> {
+        let url = format!(
+            "{}/api/models/{}/revision/{}?blobs=true",
+            self.endpoint,
+            self.model,
+            revision.replace('/', "%2F")
+        );
+        let response = self.get(&url).send().await?;
+        match response.status() {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => anyhow::bail!(
+                "Model '{}' is private or gated, set {TOKEN_ENV} to a token with access to it",
+                self.model
+            ),
+            StatusCode::NOT_FOUND => {
+                anyhow::bail!("No model '{}' at revision '{revision}'", self.model)
             }
-            Err(e) => {
-                return Err(anyhow::anyhow!(
-                    "Failed to download file '{}' from model '{}': {}",
-                    sib.rfilename,
-                    model_name,
-                    e
-                ));
+            _ => Ok(response.error_for_status()?.json().await?),
+        }
+    }
+
+    /// The snapshot of `revision` if it was downloaded before
+    fn cached_snapshot(&self, revision: &str) -> Option<PathBuf> {
+        let snapshot = self
+            .repo_dir
+            .join("snapshots")
+            .join(self.cached_commit(revision)?);
+        snapshot.is_dir().then_some(snapshot)
+    }
+
+    /// The commit `revision` was at when it was downloaded
+    fn cached_commit(&self, revision: &str) -> Option<String> {
+        match std::fs::read_to_string(self.repo_dir.join("refs").join(revision)) {
+            Ok(commit) => Some(commit.trim().to_string()),
+            // A commit has no ref, its snapshot is named after it
+            Err(_) => self
+                .repo_dir
+                .join("snapshots")
+                .join(revision)
+                .is_dir()
+                .then(|| revision.to_string()),
+        }
+    }
+
+    /// Record that `revision` is at `commit`, as the Hugging Face tools do
+    fn set_ref(&self, revision: &str, commit: &str) -> anyhow::Result<()> {
+        if revision == commit {
+            return Ok(());
+        }
+        let path = self.repo_dir.join("refs").join(revision);
+        std::fs::create_dir_all(path.parent().unwrap_or(&self.repo_dir))?;
+        std::fs::write(&path, commit).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Download `file` of `commit` unless the cache has it, and link it from `snapshot`
+    async fn fetch(
+        &self,
+        commit: &str,
+        file: &Sibling,
+        snapshot: &Path,
+        progress: &Progress,
+    ) -> anyhow::Result<()> {
+        let Some(blob_name) = file.blob_name() else {
+            anyhow::bail!("The hub lists no blob for '{}'", file.rfilename);
+        };
+        let blob = self.repo_dir.join("blobs").join(blob_name);
+        if blob.exists() {
+            progress.skip(file.expected_size().unwrap_or_default());
+        } else {
+            self.download_blob(commit, file, &blob, progress).await?;
+        }
+        link(
+            &snapshot.join(&file.rfilename),
+            &blob_link_target(&file.rfilename, blob_name),
+        )
+    }
+
+    async fn download_blob(
+        &self,
+        commit: &str,
+        file: &Sibling,
+        blob: &Path,
+        progress: &Progress,
+    ) -> anyhow::Result<()> {
+        let _lock = BlobLock::acquire(blob).await?;
+        if blob.exists() {
+            // Another process downloaded it while we waited
+            progress.skip(file.expected_size().unwrap_or_default());
+            return Ok(());
+        }
+        let partial = with_suffix(blob, ".incomplete");
+        let resumed = std::fs::metadata(&partial).map(|m| m.len()).unwrap_or(0);
+        if resumed > 0 {
+            tracing::info!(
+                file = file.rfilename,
+                resumed_gb = resumed as f64 / BYTES_PER_GB,
+                "Resuming download"
+            );
+            progress.skip(resumed);
+        }
+        let url = format!(
+            "{}/{}/resolve/{commit}/{}",
+            self.endpoint, self.model, file.rfilename
+        );
+        let mut attempt = 1;
+        loop {
+            match self.download_rest(&url, file, &partial, progress).await {
+                Ok(()) => break,
+                Err(err) if attempt < MAX_ATTEMPTS => {
+                    tracing::warn!(
+                        file = file.rfilename,
+                        attempt,
+                        "Download interrupted, resuming: {err:#}"
+                    );
+                    tokio::time::sleep(Duration::from_secs(1 << attempt)).await;
+                    attempt += 1;
+                }
+                Err(err) => {
+                    return Err(err.context(format!(
+                        "Failed to download file '{}' from model '{}'",
+                        file.rfilename, self.model
+                    )));
+                }
             }
         }
+        verify(&partial, file).await?;
+        tokio::fs::rename(&partial, blob).await?;
+        Ok(())
     }
 
-    if !files_downloaded {
-        return Err(anyhow::anyhow!(
-            "No valid files found for model '{}'.",
-            model_name
-        ));
+    /// Append to `partial` what it doesn't have yet of the file at `url`
+    async fn download_rest(
+        &self,
+        url: &str,
+        file: &Sibling,
+        partial: &Path,
+        progress: &Progress,
+    ) -> anyhow::Result<()> {
+        let mut start = tokio::fs::metadata(partial)
+            .await
+            .map(|m| m.len())
+            .unwrap_or(0);
+        if let Some(size) = file.expected_size() {
+            if start == size {
+                return Ok(());
+            }
+            if start > size {
+                progress.rewind(start);
+                start = 0;
+            }
+        }
+        let mut request = self.get(url);
+        if start > 0 {
+            request = request.header(RANGE, format!("bytes={start}-"));
+        }
+        let response = request.send().await?.error_for_status()?;
+        let mut out = if start > 0 && response.status() == StatusCode::PARTIAL_CONTENT {
+            tokio::fs::OpenOptions::new()
+                .append(true)
+                .open(partial)
+                .await?
+        } else {
+            // From the start, the server sends the whole file
+            progress.rewind(start);
+            tokio::fs::File::create(partial).await?
+        };
+        let mut body = response.bytes_stream();
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk?;
+            out.write_all(&chunk).await?;
+            progress.add(chunk.len() as u64);
+        }
+        out.flush().await?;
+        Ok(())
+    }
+}
+
+/// Check a download against the size the hub lists, and the SHA-256 of an LFS file. A
+/// download that doesn't match is deleted, the next one starts over.
+async fn verify(path: &Path, file: &Sibling) -> anyhow::Result<()> {
+    let len = tokio::fs::metadata(path).await?.len();
+    if let Some(size) = file.expected_size() {
+        if len != size {
+            let _ = tokio::fs::remove_file(path).await;
+            anyhow::bail!("'{}' is {len} bytes, expected {size}", file.rfilename);
+        }
+    }
+    let Some(lfs) = &file.lfs else {
+        return Ok(());
+    };
+    let owned = path.to_path_buf();
+    let sha256 = tokio::task::spawn_blocking(move || sha256_file(&owned)).await??;
+    if sha256 != lfs.sha256 {
+        let _ = tokio::fs::remove_file(path).await;
+        anyhow::bail!(
+            "'{}' is corrupt, its SHA-256 is {sha256}, expected {}",
+            file.rfilename,
+            lfs.sha256
+        );
+    }
+    Ok(())
+}
+
+pub(crate) fn sha256_file(path: &Path) -> anyhow::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 1 << 20];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Where the link to a blob in a snapshot points, relative to it as the Hugging Face tools
+/// make them: `snapshots/<commit>/<file>` to `blobs/<blob>`
+fn blob_link_target(file: &str, blob_name: &str) -> PathBuf {
+    let depth = Path::new(file).components().count();
+    let mut target: PathBuf = std::iter::repeat_n("..", depth + 1).collect();
+    target.push("blobs");
+    target.push(blob_name);
+    target
+}
+
+fn link(link: &Path, target: &Path) -> anyhow::Result<()> {
+    if link.exists() {
+        return Ok(());
+    }
+    if let Some(parent) = link.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    // A link to a blob that was deleted
+    let _ = std::fs::remove_file(link);
+    symlink(target, link).with_context(|| format!("Failed to link {}", link.display()))
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+/// Creating symlinks on Windows needs Developer Mode or admin rights. Without them the blob
+/// is copied, as the Hugging Face tools do.
+#[cfg(windows)]
+fn symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    if std::os::windows::fs::symlink_file(target, link).is_ok() {
+        return Ok(());
+    }
+    let blob = link.parent().unwrap_or(Path::new(".")).join(target);
+    std::fs::copy(blob, link).map(|_| ())
+}
+
+/// Keeps other processes sharing the cache from downloading the same file. `<file>.lock`
+/// holds the process id, a lock of a process that is gone is taken over.
+pub(crate) struct BlobLock(PathBuf);
+
+impl BlobLock {
+    pub(crate) async fn acquire(blob: &Path) -> anyhow::Result<Self> {
+        let path = with_suffix(blob, ".lock");
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        loop {
+            match std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(mut file) => {
+                    write!(file, "{}", std::process::id())?;
+                    return Ok(BlobLock(path));
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                    if !lock_holder_alive(&path) {
+                        let _ = std::fs::remove_file(&path);
+                        continue;
+                    }
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+impl Drop for BlobLock {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Whether the process named in a lock file runs. A lock still being written counts.
+fn lock_holder_alive(lock: &Path) -> bool {
+    let pid = std::fs::read_to_string(lock)
+        .ok()
+        .and_then(|pid| pid.trim().parse::<u32>().ok());
+    match pid {
+        Some(pid) => process_alive(pid),
+        None => true,
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn process_alive(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+/// macOS and the BSDs have no `/proc`
+#[cfg(all(unix, not(target_os = "linux")))]
+fn process_alive(pid: u32) -> bool {
+    std::process::Command::new("ps")
+        .args(["-p", &pid.to_string()])
+        .stdout(std::process::Stdio::null())
+        .status()
+        .map_or(true, |status| status.success())
+}
+
+#[cfg(windows)]
+fn process_alive(pid: u32) -> bool {
+    std::process::Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {pid}"), "/NH"])
+        .output()
+        .map_or(true, |output| {
+            String::from_utf8_lossy(&output.stdout).contains(&pid.to_string())
+        })
+}
+
+/// `path` with `suffix` after its name, its extension included
+pub(crate) fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Logs how far the download of a model is, every [`PROGRESS_INTERVAL`]
+pub(crate) struct Progress {
+    model: String,
+    total: u64,
+    /// In the cache, downloaded before or now
+    done: AtomicU64,
+    /// Downloaded now, for the speed
+    fetched: AtomicU64,
+    start: Instant,
+    last_log: Mutex<Instant>,
+}
+
+impl Progress {
+    pub(crate) fn new(model: &str, total: u64) -> Self {
+        Progress {
+            model: model.to_string(),
+            total,
+            done: AtomicU64::new(0),
+            fetched: AtomicU64::new(0),
+            start: Instant::now(),
+            last_log: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Bytes there already
+    pub(crate) fn skip(&self, bytes: u64) {
+        self.done.fetch_add(bytes, Ordering::Relaxed);
     }
 
-    match p.parent() {
-        Some(p) => Ok(p.to_path_buf()),
-        None => Err(anyhow::anyhow!("Invalid HF cache path: {}", p.display())),
+    /// Bytes that will be downloaded again
+    pub(crate) fn rewind(&self, bytes: u64) {
+        self.done.fetch_sub(bytes, Ordering::Relaxed);
     }
+
+    /// Bytes downloaded now
+    pub(crate) fn add(&self, bytes: u64) {
+        self.done.fetch_add(bytes, Ordering::Relaxed);
+        self.fetched.fetch_add(bytes, Ordering::Relaxed);
+        let mut last_log = self.last_log.lock().unwrap();
+        if last_log.elapsed() < PROGRESS_INTERVAL {
+            return;
+        }
+        *last_log = Instant::now();
+        drop(last_log);
+
+        let done = self.done.load(Ordering::Relaxed);
+        let rate = self.fetched.load(Ordering::Relaxed) as f64 / self.start.elapsed().as_secs_f64();
+        let eta_secs = if rate > 0.0 {
+            (self.total.saturating_sub(done) as f64 / rate) as u64
+        } else {
+            0
+        };
+        tracing::info!(
+            model = self.model,
+            "Downloading: {:.2} of {:.2} GB, {:.0} MB/s, {eta_secs}s left",
+            done as f64 / BYTES_PER_GB,
+            self.total as f64 / BYTES_PER_GB,
+            rate / 1_000_000.0,
+        );
+    }
+
+    pub(crate) fn finish(&self) {
+        let fetched = self.fetched.load(Ordering::Relaxed);
+        if fetched > 0 {
+            tracing::info!(
+                model = self.model,
+                elapsed_s = self.start.elapsed().as_secs(),
+                "Downloaded {:.2} GB",
+                fetched as f64 / BYTES_PER_GB
+            );
+        }
+    }
+}
+
+pub(crate) fn is_weights(s: &str) -> bool {
+    WEIGHTS.iter().any(|ext| s.ends_with(ext))
 }
 
 fn is_image(s: &str) -> bool {
@@ -90,3 +670,44 @@ fn is_image(s: &str) -> bool {
         || s.ends_with(".jpeg")
         || s.ends_with("JPEG")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blob_link_target() {
+        assert_eq!(
+            blob_link_target("config.json", "abc"),
+            PathBuf::from("../../blobs/abc")
+        );
+        assert_eq!(
+            blob_link_target("onnx/model.onnx", "def"),
+            PathBuf::from("../../../blobs/def")
+        );
+    }
+
+    #[test]
+    fn test_snapshot_commit() {
+        let snapshot = Path::new("/hub/models--org--name/snapshots/c0ffee");
+        assert_eq!(snapshot_commit(snapshot), Some("c0ffee".to_string()));
+        assert_eq!(snapshot_commit(Path::new("/models/qwen3")), None);
+    }
+
+    #[test]
+    fn test_sibling() {
+        let info: RepoInfo = serde_json::from_str(
+            r#"{"sha": "c0ffee", "siblings": [
+                {"rfilename": "config.json", "size": 727, "blobId": "e1b2"},
+                {"rfilename": "model.safetensors", "size": 1024, "blobId": "a9f3",
+                 "lfs": {"sha256": "5d41", "size": 1024, "pointerSize": 134}}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(info.siblings[0].blob_name(), Some("e1b2"));
+        assert_eq!(info.siblings[0].expected_size(), Some(727));
+        // LFS blobs are named after their SHA-256
+        assert_eq!(info.siblings[1].blob_name(), Some("5d41"));
+        assert_eq!(info.siblings[1].expected_size(), Some(1024));
+    }
+}
@@ -17,8 +17,10 @@ use anyhow::Result;
 use dynamo_runtime::{
     component::Component,
     pipeline::{
-        async_trait, AsyncEngine, AsyncEngineContextProvider, Error, ManyOut, ResponseStream,
-        SingleIn,
+        async_trait,
+        network::egress::routing_hints::{PINNED_WORKER_CONTEXT_KEY, ROUTING_HINTS_CONTEXT_KEY},
+        AsyncEngine, AsyncEngineContextProvider, Error, ManyOut, PushRouter, ResponseStream,
+        RoutingHintPolicy, RoutingHints, SingleIn,
     },
     prelude::*,
     protocols::annotated::Annotated,
@@ -42,6 +44,7 @@ use crate::{
         scheduler::{KvScheduler, KvSchedulerError, SchedulingRequest},
         scoring::ProcessedEndpoints,
     },
+    protocols::common::llm_backend::{BackendInput, LLMEngineOutput},
     tokens::TokenBlockSequence,
 };
 
@@ -166,3 +169,76 @@ impl AsyncEngine<SingleIn<RouterRequest>, ManyOut<Annotated<RouterResponse>>, Er
         Ok(ResponseStream::new(Box::pin(stream), ctx.context()))
     }
 }
+
+/// Sends pre-processed requests to the worker [`KvRouter`] scores best for their prompt, the one
+/// with most of its KV blocks cached and room to run it. Requests pinned to a worker, and those
+/// the KV router can't place yet, for example before the workers published their metrics, go
+/// through `inner` as usual.
+///
+/// Routing hints that name a `worker` or `zone` take precedence, those requests go through
+/// `inner` too. With `prefer=cache` as well, the KV router's pick is kept if it matches them.
+/// A hard `prefer=cache!` under [`RoutingHintPolicy::Enforce`] fails the request when the KV
+/// router can't honor it, instead of falling back.
+pub struct KvPushRouter {
+    inner: PushRouter<BackendInput, Annotated<LLMEngineOutput>>,
+    chooser: Arc<KvRouter>,
+}
+
+impl KvPushRouter {
+    pub fn new(
+        inner: PushRouter<BackendInput, Annotated<LLMEngineOutput>>,
+        chooser: Arc<KvRouter>,
+    ) -> Self {
+        KvPushRouter { inner, chooser }
+    }
+}
+
+#[async_trait]
+impl AsyncEngine<SingleIn<BackendInput>, ManyOut<Annotated<LLMEngineOutput>>, Error>
+    for KvPushRouter
+{
+    async fn generate(
+        &self,
+        request: SingleIn<BackendInput>,
+    ) -> Result<ManyOut<Annotated<LLMEngineOutput>>> {
+        if request.get::<i64>(PINNED_WORKER_CONTEXT_KEY).is_ok() {
+            return self.inner.generate(request).await;
+        }
+        let policy = self.inner.hint_policy();
+        let hints = match policy {
+            RoutingHintPolicy::Ignore => None,
+            _ => request.get::<RoutingHints>(ROUTING_HINTS_CONTEXT_KEY).ok(),
+        };
+        let prefer = hints.as_ref().and_then(|hints| hints.prefer.clone());
+        let placed = hints.as_ref().is_some_and(|hints| hints.has_placement());
+        if placed && prefer.is_none() {
+            return self.inner.generate(request).await;
+        }
+        let required = prefer.is_some_and(|p| p.hard) && policy == RoutingHintPolicy::Enforce;
+
+        let worker_id = match self.chooser.schedule(&request.token_ids, 0).await {
+            Ok(worker_id) => worker_id,
+            Err(err) if required => {
+                anyhow::bail!("KV router could not place a request with prefer=cache!: {err}");
+            }
+            Err(err) => {
+                tracing::debug!(%err, "KV router could not place the request, routing it as usual");
+                return self.inner.generate(request).await;
+            }
+        };
+        if let Some(hints) = hints.as_ref().filter(|_| placed) {
+            let allowed = hints.filter_placement(self.inner.client.endpoints(), policy)?;
+            if !allowed.iter().any(|ep| ep.id() == worker_id) {
+                if required {
+                    anyhow::bail!(
+                        "Worker {worker_id:x} with the prompt cached does not match the routing hints"
+                    );
+                }
+                tracing::trace!("KV router selected {worker_id:x}, not allowed by routing hints");
+                return self.inner.generate(request).await;
+            }
+        }
+        tracing::trace!("KV router selected {worker_id:x}");
+        self.inner.direct(request, worker_id).await
+    }
+}
@@ -18,21 +18,30 @@
 //! The `dynamo.llm` crate is a Rust library that provides a set of traits and types for building
 //! distributed LLM inference solutions.
 
+pub mod audio;
+pub mod auth;
 pub mod backend;
 pub mod common;
 pub mod disagg_router;
 pub mod engines;
 pub mod gguf;
+pub mod gpu_telemetry;
+pub mod grammar;
 pub mod http;
 pub mod hub;
 pub mod key_value_store;
 pub mod kv_router;
+pub mod lora;
+pub mod model_alias;
 pub mod model_card;
+pub mod model_source;
 pub mod model_type;
 pub mod preprocessor;
+pub mod presets;
 pub mod protocols;
 pub mod recorder;
 pub mod request_template;
+pub mod tenancy;
 pub mod tokenizers;
 pub mod tokens;
 pub mod types;
@@ -24,6 +24,9 @@ const DEFAULT_NAME: &str = "dynamo";
 pub struct LocalModel {
     full_path: PathBuf,
     card: ModelDeploymentCard,
+    /// The commit or digest the model is at, when it is pinned to one. Only such models are
+    /// copied from or offered to other workers.
+    revision: Option<String>,
 }
 
 impl Default for LocalModel {
@@ -31,6 +34,7 @@ impl Default for LocalModel {
         LocalModel {
             full_path: PathBuf::new(),
             card: ModelDeploymentCard::with_name_only(DEFAULT_NAME),
+            revision: None,
         }
     }
 }
@@ -40,6 +44,21 @@ impl LocalModel {
         &self.card
     }
 
+    /// Record which engine serves this model, see [`ModelDeploymentCard::engine`]
+    pub fn set_engine(&mut self, engine: &str) {
+        self.card.engine = Some(engine.to_string());
+    }
+
+    /// Render prompts with this Jinja template, see [`ModelDeploymentCard::set_chat_template`]
+    pub fn set_chat_template(&mut self, path: &Path) -> anyhow::Result<()> {
+        self.card.set_chat_template(path)
+    }
+
+    /// Limit requests to this many tokens, see [`ModelDeploymentCard::context_length`]
+    pub fn set_context_length(&mut self, context_length: u32) {
+        self.card.context_length = Some(context_length);
+    }
+
     pub fn path(&self) -> &Path {
         &self.full_path
     }
@@ -53,7 +72,8 @@ impl LocalModel {
     }
 
     /// Make an LLM ready for use:
-    /// - Download it from Hugging Face (and NGC in future) if necessary
+    /// - Download it from Hugging Face (and NGC in future) if necessary, or copy it from
+    ///   another worker, see [`crate::model_source::peer`]
     /// - Resolve the path
     /// - Load it's ModelDeploymentCard card
     /// - Name it correctly
@@ -62,50 +82,148 @@ impl LocalModel {
     /// - A folder: The last part of the folder name: "/data/llms/Qwen2.5-3B-Instruct" -> "Qwen2.5-3B-Instruct"
     /// - A file: The GGUF filename: "/data/llms/Qwen2.5-3B-Instruct-Q6_K.gguf" -> "Qwen2.5-3B-Instruct-Q6_K.gguf"
     /// - An HF repo: The HF repo name: "Qwen/Qwen2.5-3B-Instruct" stays the same
+    ///
+    /// `override_tokenizer` replaces the model's own tokenizer, for models that ship a broken
+    /// one or none.
     pub async fn prepare(
         model_path: &str,
         override_config: Option<&Path>,
         override_name: Option<String>,
+        override_tokenizer: Option<&Path>,
+    ) -> anyhow::Result<LocalModel> {
+        Self::load(
+            model_path,
+            override_config,
+            override_name,
+            override_tokenizer,
+            true,
+        )
+        .await
+    }
+
+    /// Like [`LocalModel::prepare`], but only download the config and tokenizer of a Hugging
+    /// Face model, not its weights. For checking a model without loading it.
+    pub async fn inspect(
+        model_path: &str,
+        override_config: Option<&Path>,
+        override_name: Option<String>,
+        override_tokenizer: Option<&Path>,
+    ) -> anyhow::Result<LocalModel> {
+        Self::load(
+            model_path,
+            override_config,
+            override_name,
+            override_tokenizer,
+            false,
+        )
+        .await
+    }
+
+    async fn load(
+        model_path: &str,
+        override_config: Option<&Path>,
+        override_name: Option<String>,
+        override_tokenizer: Option<&Path>,
+        with_weights: bool,
     ) -> anyhow::Result<LocalModel> {
         // Name it
 
         // Check for hf:// prefix first, in case we really want an HF repo but it conflicts
         // with a relative path.
-        let is_hf_repo =
-            model_path.starts_with(HF_SCHEME) || !fs::exists(model_path).unwrap_or(false);
+        let is_remote = super::model_source::is_remote(model_path);
+        let is_hf_repo = !is_remote
+            && (model_path.starts_with(HF_SCHEME) || !fs::exists(model_path).unwrap_or(false));
         let relative_path = model_path.trim_start_matches(HF_SCHEME);
 
-        let full_path = if is_hf_repo {
-            // HF download if necessary
-            super::hub::from_hf(relative_path).await?
+        // What a downloaded model is called unless we name it
+        let remote_name = if is_remote {
+            Some(super::model_source::model_name(model_path))
+        } else if is_hf_repo {
+            // HF repos use their full name ("org/name") not the folder name
+            Some(relative_path.to_string())
         } else {
-            fs::canonicalize(relative_path)?
+            None
         };
 
-        let model_name = override_name.unwrap_or_else(|| {
-            if is_hf_repo {
-                // HF repos use their full name ("org/name") not the folder name
-                relative_path.to_string()
+        // Copied from another worker if one has the same revision, instead of downloading it
+        let peer_revision =
+            if remote_name.is_some() && with_weights && super::model_source::peer::enabled() {
+                if is_hf_repo {
+                    super::hub::resolve_revision(relative_path)
+                        .await
+                        .inspect_err(|err| {
+                            tracing::warn!(
+                                model_path,
+                                "Not copying the model from another worker: {err:#}"
+                            )
+                        })
+                        .ok()
+                } else {
+                    super::model_source::pinned_revision(model_path)
+                }
+            } else {
+                None
+            };
+        let from_peer = match (
+            override_name.as_ref().or(remote_name.as_ref()),
+            &peer_revision,
+        ) {
+            (Some(name), Some(revision)) => super::model_source::peer::fetch(name, revision).await,
+            _ => None,
+        };
+        let copied = from_peer.is_some();
+
+        let full_path = if let Some(path) = from_peer {
+            path
+        } else if is_remote {
+            // S3, GCS or OCI, downloaded to the local cache if necessary
+            super::model_source::fetch(model_path, with_weights).await?
+        } else if is_hf_repo {
+            // HF download if necessary
+            if with_weights {
+                super::hub::from_hf(relative_path).await?
             } else {
-                full_path
-                    .iter()
-                    .next_back()
-                    .map(|n| n.to_string_lossy().into_owned())
-                    .unwrap_or_else(|| {
-                        // Panic because we can't do anything without a model
-                        panic!("Invalid model path, too short: '{}'", full_path.display())
-                    })
+                super::hub::metadata_from_hf(relative_path).await?
             }
+        } else {
+            fs::canonicalize(relative_path)?
+        };
+
+        let model_name = override_name.or(remote_name).unwrap_or_else(|| {
+            full_path
+                .iter()
+                .next_back()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_else(|| {
+                    // Panic because we can't do anything without a model
+                    panic!("Invalid model path, too short: '{}'", full_path.display())
+                })
         });
 
         // Load the ModelDeploymentCard
 
         // --model-config takes precedence over --model-path
         let model_config_path = override_config.unwrap_or(&full_path);
-        let mut card = ModelDeploymentCard::load(&model_config_path).await?;
+        let mut card =
+            ModelDeploymentCard::load_with_tokenizer(&model_config_path, override_tokenizer)
+                .await?;
         card.set_name(&model_name);
+        if override_config.is_some() && full_path.is_file() {
+            card.fallback_to_gguf_chat_template(&full_path);
+        }
 
-        Ok(LocalModel { full_path, card })
+        let revision = match peer_revision {
+            // The revision may have moved on since we resolved it, the snapshot says which
+            // commit was downloaded
+            Some(_) if is_hf_repo && !copied => super::hub::snapshot_commit(&full_path),
+            revision => revision,
+        };
+
+        Ok(LocalModel {
+            full_path,
+            card,
+            revision,
+        })
     }
 
     /// Attach this model the endpoint. This registers it on the network
@@ -119,6 +237,14 @@ impl LocalModel {
         let Some(etcd_client) = endpoint.drt().etcd_client() else {
             anyhow::bail!("Cannot attach to static endpoint");
         };
+        // Checksum our tokenizer while its files are still local, for the frontend to check
+        // that it tokenizes the same way. Only pre-processed requests need that.
+        let vocab_checksum = if matches!(model_type, ModelType::Backend) {
+            Some(self.card.vocab_checksum()?)
+        } else {
+            None
+        };
+
         // Store model config files in NATS object store
         let nats_client = endpoint.drt().nats_client();
         self.card.move_to_nats(nats_client.clone()).await?;
@@ -141,6 +267,7 @@ impl LocalModel {
             name: self.service_name().to_string(),
             endpoint: endpoint_id.clone(),
             model_type,
+            vocab_checksum,
         };
         etcd_client
             .kv_create(
@@ -148,6 +275,21 @@ impl LocalModel {
                 serde_json::to_vec_pretty(&model_registration)?,
                 None, // use primary lease
             )
-            .await
+            .await?;
+
+        // Let workers starting later copy the model from this one
+        match &self.revision {
+            Some(revision) if super::model_source::peer::enabled() && self.full_path.exists() => {
+                super::model_source::peer::serve(
+                    endpoint.drt(),
+                    &self.card,
+                    &self.full_path,
+                    revision,
+                )
+                .await?;
+            }
+            _ => {}
+        }
+        Ok(())
     }
 }
@@ -0,0 +1,249 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! LoRA adapters
+//!
+//! A served model can have LoRA adapters, each a name and the path or Hugging Face repo of
+//! its weights. Clients pick one with the model name `<model>:<adapter>`:
+//!
+//! ```text
+//! {"model": "llama-3.1-8b-instruct:sql", "messages": [...]}
+//! ```
+//!
+//! The HTTP service sends such a request to the engine of `<model>` wrapped in an
+//! [`LoraEngine`], which hands the adapter to the pre-processor in [`NvExt::lora`]. From there
+//! it goes to the engine in [`PreprocessedRequest::lora`]. Engines load an adapter the first
+//! time a request uses it, so adapters can be added while the model is served.
+//!
+//! [`PreprocessedRequest::lora`]: crate::protocols::common::preprocessor::PreprocessedRequest::lora
+
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use dynamo_runtime::engine::AsyncEngine;
+use dynamo_runtime::pipeline::{Error, ManyOut, ServerStreamingEngine, SingleIn};
+use serde::{Deserialize, Serialize};
+
+use crate::protocols::openai::chat_completions::NvCreateChatCompletionRequest;
+use crate::protocols::openai::completions::CompletionRequest;
+use crate::protocols::openai::nvext::NvExt;
+
+/// An adapter of a model
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LoraAdapter {
+    /// What clients put after the `:` of the model name
+    pub name: String,
+
+    /// Where the engine loads the weights from, a directory or a Hugging Face repo
+    pub path: String,
+}
+
+impl FromStr for LoraAdapter {
+    type Err = String;
+
+    /// `name=path`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((name, path)) = s.split_once('=') else {
+            return Err(format!("'{s}' is not name=path"));
+        };
+        let adapter = LoraAdapter {
+            name: name.trim().to_string(),
+            path: path.trim().to_string(),
+        };
+        adapter.validate().map_err(|err| err.to_string())?;
+        Ok(adapter)
+    }
+}
+
+impl LoraAdapter {
+    fn validate(&self) -> Result<(), LoraError> {
+        if self.name.is_empty() || self.name.contains(':') {
+            return Err(LoraError::InvalidName(self.name.clone()));
+        }
+        if self.path.is_empty() {
+            return Err(LoraError::NoPath(self.name.clone()));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum LoraError {
+    #[error("LoRA adapter name '{0}' must not be empty or contain ':'")]
+    InvalidName(String),
+
+    #[error("LoRA adapter {0} has no path")]
+    NoPath(String),
+
+    #[error("Model {model} already has a LoRA adapter named {name}")]
+    AlreadyExists { model: String, name: String },
+
+    /// A request picked an adapter of a model whose engine cannot apply one
+    #[error("LoRA adapters are not supported {0}")]
+    NotSupported(String),
+}
+
+/// The adapters of each model
+#[derive(Debug, Clone, Default)]
+pub struct LoraAdapters {
+    models: HashMap<String, BTreeMap<String, LoraAdapter>>,
+}
+
+impl LoraAdapters {
+    /// Give `model` an adapter. The model need not be served yet.
+    pub fn add(&mut self, model: &str, adapter: LoraAdapter) -> Result<(), LoraError> {
+        adapter.validate()?;
+        let adapters = self.models.entry(model.to_string()).or_default();
+        if adapters.contains_key(&adapter.name) {
+            return Err(LoraError::AlreadyExists {
+                model: model.to_string(),
+                name: adapter.name,
+            });
+        }
+        adapters.insert(adapter.name.clone(), adapter);
+        Ok(())
+    }
+
+    /// Requests already running with the adapter finish with it
+    pub fn remove(&mut self, model: &str, name: &str) -> Option<LoraAdapter> {
+        let adapters = self.models.get_mut(model)?;
+        let removed = adapters.remove(name);
+        if adapters.is_empty() {
+            self.models.remove(model);
+        }
+        removed
+    }
+
+    pub fn get(&self, model: &str, name: &str) -> Option<&LoraAdapter> {
+        self.models.get(model)?.get(name)
+    }
+
+    /// The adapters of `model`, by name
+    pub fn adapters(&self, model: &str) -> impl Iterator<Item = &LoraAdapter> {
+        self.models.get(model).into_iter().flat_map(|a| a.values())
+    }
+
+    /// Every model and adapter, sorted
+    pub fn list(&self) -> Vec<(String, LoraAdapter)> {
+        let mut all: Vec<_> = self
+            .models
+            .iter()
+            .flat_map(|(model, adapters)| {
+                adapters
+                    .values()
+                    .map(move |adapter| (model.clone(), adapter.clone()))
+            })
+            .collect();
+        all.sort_by(|a, b| (&a.0, &a.1.name).cmp(&(&b.0, &b.1.name)));
+        all
+    }
+
+    /// Split `<model>:<adapter>` into the model and its adapter, None unless the model has an
+    /// adapter of that name. Model names can have a `:` of their own, the adapter is after the
+    /// last one.
+    pub fn resolve<'a>(&self, requested: &'a str) -> Option<(&'a str, &LoraAdapter)> {
+        let (model, name) = requested.rsplit_once(':')?;
+        Some((model, self.get(model, name)?))
+    }
+}
+
+/// Requests that can carry an adapter to the pre-processor
+pub trait WithLora {
+    fn set_lora(&mut self, adapter: LoraAdapter);
+}
+
+impl WithLora for NvCreateChatCompletionRequest {
+    fn set_lora(&mut self, adapter: LoraAdapter) {
+        self.nvext.get_or_insert_with(NvExt::default).lora = Some(adapter);
+    }
+}
+
+impl WithLora for CompletionRequest {
+    fn set_lora(&mut self, adapter: LoraAdapter) {
+        self.nvext.get_or_insert_with(NvExt::default).lora = Some(adapter);
+    }
+}
+
+/// The engine of a model, for the requests to one of its adapters
+pub struct LoraEngine<Req, Resp> {
+    engine: ServerStreamingEngine<Req, Resp>,
+    adapter: LoraAdapter,
+}
+
+impl<Req, Resp> LoraEngine<Req, Resp>
+where
+    Req: WithLora + Send + Sync + 'static,
+    Resp: Send + Sync + 'static,
+{
+    pub fn wrap(
+        engine: ServerStreamingEngine<Req, Resp>,
+        adapter: LoraAdapter,
+    ) -> ServerStreamingEngine<Req, Resp> {
+        std::sync::Arc::new(LoraEngine { engine, adapter })
+    }
+}
+
+#[async_trait]
+impl<Req, Resp> AsyncEngine<SingleIn<Req>, ManyOut<Resp>, Error> for LoraEngine<Req, Resp>
+where
+    Req: WithLora + Send + Sync + 'static,
+    Resp: Send + Sync + 'static,
+{
+    async fn generate(&self, request: SingleIn<Req>) -> Result<ManyOut<Resp>, Error> {
+        let adapter = self.adapter.clone();
+        let request = request.map(|mut request| {
+            request.set_lora(adapter);
+            request
+        });
+        self.engine.generate(request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let adapter: LoraAdapter = "sql=/adapters/sql".parse().unwrap();
+        let mut adapters = LoraAdapters::default();
+        adapters.add("llama3:8b", adapter.clone()).unwrap();
+        assert_eq!(
+            adapters.add("llama3:8b", adapter.clone()),
+            Err(LoraError::AlreadyExists {
+                model: "llama3:8b".to_string(),
+                name: "sql".to_string()
+            })
+        );
+
+        assert_eq!(
+            adapters.resolve("llama3:8b:sql"),
+            Some(("llama3:8b", &adapter))
+        );
+        // The model itself, or an adapter it doesn't have
+        assert_eq!(adapters.resolve("llama3:8b"), None);
+        assert_eq!(adapters.resolve("llama3:8b:chat"), None);
+        assert_eq!(adapters.list(), vec![("llama3:8b".to_string(), adapter)]);
+
+        assert!(adapters.remove("llama3:8b", "sql").is_some());
+        assert_eq!(adapters.resolve("llama3:8b:sql"), None);
+        assert!(adapters.list().is_empty());
+
+        assert!("a:b=/path".parse::<LoraAdapter>().is_err());
+        assert!("sql=".parse::<LoraAdapter>().is_err());
+        assert!("sql".parse::<LoraAdapter>().is_err());
+    }
+}
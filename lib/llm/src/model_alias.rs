@@ -0,0 +1,158 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Model aliases
+//!
+//! An alias is a model name clients can keep using while the models behind it change. It maps
+//! to one or more targets, each a model and optionally the endpoint it must be served from:
+//!
+//! ```text
+//! gpt-4o-mini=qwen2.5-7b-instruct@team-a.backend.generate,llama-3.1-8b-instruct
+//! ```
+//!
+//! A request for the alias goes to one of the targets currently served, in turn, so an alias
+//! with several targets spreads its traffic over them and keeps working while one is gone.
+//! A target with an endpoint only counts while its model was discovered at that endpoint.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use dynamo_runtime::protocols::Endpoint;
+
+/// A model an alias stands for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AliasTarget {
+    pub model: String,
+
+    /// Only while the model is served from this endpoint, from any if None
+    pub endpoint: Option<Endpoint>,
+}
+
+impl FromStr for AliasTarget {
+    type Err = String;
+
+    /// `model` or `model@namespace.component.endpoint`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (model, endpoint) = match s.rsplit_once('@') {
+            Some((model, endpoint)) => {
+                if endpoint.split(['.', '/']).filter(|p| !p.is_empty()).count() != 3 {
+                    return Err(format!(
+                        "'{endpoint}' in '{s}' is not namespace.component.endpoint"
+                    ));
+                }
+                (model, Some(Endpoint::from(endpoint)))
+            }
+            None => (s, None),
+        };
+        let model = model.trim();
+        if model.is_empty() {
+            return Err(format!("'{s}' has no model name"));
+        }
+        Ok(AliasTarget {
+            model: model.to_string(),
+            endpoint,
+        })
+    }
+}
+
+impl fmt::Display for AliasTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.model)?;
+        if let Some(endpoint) = &self.endpoint {
+            write!(
+                f,
+                "@{}.{}.{}",
+                endpoint.namespace, endpoint.component, endpoint.name
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Aliases and their targets, in the order they were given
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModelAliases {
+    aliases: HashMap<String, Vec<AliasTarget>>,
+}
+
+impl ModelAliases {
+    /// Parse `alias=target,target...`, see the module docs for the targets
+    pub fn parse_entry(entry: &str) -> Result<(String, Vec<AliasTarget>), String> {
+        let Some((alias, targets)) = entry.split_once('=') else {
+            return Err(format!("'{entry}' is not alias=model"));
+        };
+        let alias = alias.trim();
+        if alias.is_empty() {
+            return Err(format!("'{entry}' has no alias"));
+        }
+        let targets = targets
+            .split(',')
+            .map(|target| target.parse())
+            .collect::<Result<Vec<AliasTarget>, _>>()?;
+        Ok((alias.to_string(), targets))
+    }
+
+    /// Add an alias, replacing any of the same name
+    pub fn insert(&mut self, alias: &str, targets: Vec<AliasTarget>) {
+        self.aliases.insert(alias.to_string(), targets);
+    }
+
+    pub fn targets(&self, alias: &str) -> Option<&[AliasTarget]> {
+        self.aliases.get(alias).map(Vec::as_slice)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.aliases.keys().map(String::as_str)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.aliases.is_empty()
+    }
+}
+
+impl FromIterator<(String, Vec<AliasTarget>)> for ModelAliases {
+    fn from_iter<I: IntoIterator<Item = (String, Vec<AliasTarget>)>>(iter: I) -> Self {
+        ModelAliases {
+            aliases: iter.into_iter().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_entry() {
+        let (alias, targets) =
+            ModelAliases::parse_entry("gpt-4o-mini=qwen2.5-7b@team-a.backend.generate,llama")
+                .unwrap();
+        assert_eq!(alias, "gpt-4o-mini");
+        assert_eq!(targets.len(), 2);
+        assert_eq!(targets[0].model, "qwen2.5-7b");
+        assert_eq!(
+            targets[0].endpoint,
+            Some(Endpoint::from("team-a.backend.generate"))
+        );
+        assert_eq!(targets[0].to_string(), "qwen2.5-7b@team-a.backend.generate");
+        assert_eq!(targets[1].endpoint, None);
+
+        assert!(ModelAliases::parse_entry("gpt-4o-mini").is_err());
+        assert!(ModelAliases::parse_entry("=llama").is_err());
+        assert!(ModelAliases::parse_entry("a=llama@backend").is_err());
+        assert!(ModelAliases::parse_entry("a=llama,").is_err());
+    }
+}
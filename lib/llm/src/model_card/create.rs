@@ -20,7 +20,18 @@ use anyhow::{Context, Result};
 use std::fs;
 use std::path::Path;
 
-use crate::model_card::model::{ModelInfoType, PromptFormatterArtifact, TokenizerKind};
+use crate::model_card::model::{load_gguf, ModelInfoType, PromptFormatterArtifact, TokenizerKind};
+use crate::protocols::TokenIdType;
+
+/// Tokens that end an assistant turn in well known chat templates
+const END_OF_TURN_TOKENS: &[&str] = &[
+    "<|eot_id|>",      // Llama 3
+    "<|eom_id|>",      // Llama 3.1 tool call
+    "<|im_end|>",      // ChatML: Qwen, Yi, ...
+    "<|end|>",         // Phi 3
+    "<end_of_turn>",   // Gemma
+    "<|end_of_turn|>", // OpenChat
+];
 
 impl ModelDeploymentCard {
     /// Allow user to override the name we register this model under.
@@ -30,15 +41,67 @@ impl ModelDeploymentCard {
         self.service_name = name.to_string();
     }
 
+    /// Render prompts with the Jinja template in `path` instead of the model's own. For models
+    /// whose tokenizer_config.json has no chat template, or a broken one.
+    pub fn set_chat_template(&mut self, path: &Path) -> anyhow::Result<()> {
+        let template = fs::read_to_string(path)
+            .with_context(|| format!("chat template {}", path.display()))?;
+        if template.trim().is_empty() {
+            anyhow::bail!("chat template {} is empty", path.display());
+        }
+        self.chat_template = Some(template);
+        Ok(())
+    }
+
+    /// Whether prompts can be rendered: the card has a template of its own, or the prompt
+    /// formatter's file has one
+    pub fn has_chat_template(&self) -> bool {
+        if self.chat_template.is_some() {
+            return true;
+        }
+        match &self.prompt_formatter {
+            Some(PromptFormatterArtifact::HfTokenizerConfigJson(path)) => {
+                read_json(path).is_ok_and(|config| !config["chat_template"].is_null())
+            }
+            Some(PromptFormatterArtifact::GGUF(gguf_file)) => {
+                gguf_chat_template(gguf_file).is_some()
+            }
+            None => false,
+        }
+    }
+
+    /// Use the template embedded in a GGUF file if the card doesn't have one. For a GGUF
+    /// whose config and tokenizer come from elsewhere, see `--model-config`.
+    pub fn fallback_to_gguf_chat_template(&mut self, gguf_file: &Path) {
+        if self.has_chat_template() {
+            return;
+        }
+        if let Some(template) = gguf_chat_template(gguf_file) {
+            tracing::debug!(gguf = %gguf_file.display(), "Using the chat template in the GGUF");
+            self.chat_template = Some(template);
+        }
+    }
+
     /// Build an in-memory ModelDeploymentCard from either:
     /// - a folder containing config.json, tokenizer.json and token_config.json
     /// - a GGUF file
     pub async fn load(config_path: impl AsRef<Path>) -> anyhow::Result<ModelDeploymentCard> {
+        Self::load_with_tokenizer(config_path, None).await
+    }
+
+    /// Like [`ModelDeploymentCard::load`], but with the tokenizer in `tokenizer_path` instead
+    /// of the model's own if set, see [`TokenizerKind::from_file`]. The model then does not
+    /// need a tokenizer of its own.
+    pub async fn load_with_tokenizer(
+        config_path: impl AsRef<Path>,
+        tokenizer_path: Option<&Path>,
+    ) -> anyhow::Result<ModelDeploymentCard> {
         let config_path = config_path.as_ref();
+        let tokenizer = tokenizer_path.map(TokenizerKind::from_file).transpose()?;
         if config_path.is_dir() {
-            Self::from_local_path(config_path).await
+            Self::from_local_path(config_path, tokenizer).await
         } else {
-            Self::from_gguf(config_path).await
+            Self::from_gguf(config_path, tokenizer).await
         }
     }
 
@@ -57,7 +120,10 @@ impl ModelDeploymentCard {
     /// - The path doesn't exist or isn't a directory
     /// - The path contains invalid Unicode characters
     /// - Required model files are missing or invalid
-    async fn from_local_path(local_root_dir: impl AsRef<Path>) -> anyhow::Result<Self> {
+    async fn from_local_path(
+        local_root_dir: impl AsRef<Path>,
+        tokenizer: Option<TokenizerKind>,
+    ) -> anyhow::Result<Self> {
         let local_root_dir = local_root_dir.as_ref();
         check_valid_local_repo_path(local_root_dir)?;
         let repo_id = local_root_dir
@@ -69,10 +135,10 @@ impl ModelDeploymentCard {
             .file_name()
             .and_then(|n| n.to_str())
             .ok_or_else(|| anyhow::anyhow!("Invalid model directory name"))?;
-        Self::from_repo(&repo_id, model_name).await
+        Self::from_repo(&repo_id, model_name, tokenizer).await
     }
 
-    async fn from_gguf(gguf_file: &Path) -> anyhow::Result<Self> {
+    async fn from_gguf(gguf_file: &Path, tokenizer: Option<TokenizerKind>) -> anyhow::Result<Self> {
         let model_name = gguf_file
             .iter()
             .next_back()
@@ -84,16 +150,38 @@ impl ModelDeploymentCard {
                 gguf_file.display()
             );
         };
-        Ok(Self {
+        let mut card = Self {
             display_name: model_name.to_string(),
             service_name: model_name.to_string(),
             model_info: Some(ModelInfoType::GGUF(gguf_file.to_path_buf())),
-            tokenizer: Some(TokenizerKind::from_gguf(gguf_file)?),
+            tokenizer: Some(match tokenizer {
+                Some(tokenizer) => tokenizer,
+                None => TokenizerKind::from_gguf(gguf_file)?,
+            }),
             prompt_formatter: Some(PromptFormatterArtifact::GGUF(gguf_file.to_path_buf())),
             prompt_context: None, // TODO - auto-detect prompt context
+            chat_template: None,
+            stop_token_ids: vec![],
+            context_length: None,
+            engine: None,
             revision: 0,
             last_published: None,
-        })
+        };
+
+        // GGUF files usually only have the end of text token as eos
+        let content = load_gguf(gguf_file)?;
+        let metadata = content.get_metadata();
+        let mut stop_token_ids: Vec<TokenIdType> = [
+            "tokenizer.ggml.eos_token_id",
+            "tokenizer.ggml.eot_token_id",
+            "tokenizer.ggml.eom_token_id",
+        ]
+        .iter()
+        .filter_map(|key| metadata.get(*key)?.to_u32().ok())
+        .collect();
+        card.add_end_of_turn_tokens(&mut stop_token_ids);
+        card.stop_token_ids = stop_token_ids;
+        Ok(card)
     }
 
     /// TODO: This will be implemented after nova-hub is integrated with the model-card
@@ -105,20 +193,85 @@ impl ModelDeploymentCard {
         ))
     }
 
-    async fn from_repo(repo_id: &str, model_name: &str) -> anyhow::Result<Self> {
-        Ok(Self {
+    async fn from_repo(
+        repo_id: &str,
+        model_name: &str,
+        tokenizer: Option<TokenizerKind>,
+    ) -> anyhow::Result<Self> {
+        let mut card = Self {
             display_name: model_name.to_string(),
             service_name: model_name.to_string(),
             model_info: Some(ModelInfoType::from_repo(repo_id).await?),
-            tokenizer: Some(TokenizerKind::from_repo(repo_id).await?),
+            tokenizer: Some(match tokenizer {
+                Some(tokenizer) => tokenizer,
+                None => TokenizerKind::from_repo(repo_id).await?,
+            }),
             prompt_formatter: PromptFormatterArtifact::from_repo(repo_id).await?,
             prompt_context: None, // TODO - auto-detect prompt context
+            chat_template: None,
+            stop_token_ids: vec![],
+            context_length: None,
+            engine: None,
             revision: 0,
             last_published: None,
-        })
+        };
+
+        let mut stop_token_ids = vec![];
+        // generation_config.json is where multi-eos models such as Llama 3 list them all
+        if let Ok(path) = check_for_file(repo_id, "generation_config.json").await {
+            let config = read_json(&path)?;
+            match &config["eos_token_id"] {
+                serde_json::Value::Number(n) => stop_token_ids.extend(n.as_u64()),
+                serde_json::Value::Array(ids) => {
+                    stop_token_ids.extend(ids.iter().filter_map(|id| id.as_u64()))
+                }
+                _ => {}
+            }
+        }
+        let mut stop_token_ids: Vec<TokenIdType> = stop_token_ids
+            .into_iter()
+            .map(|id| id as TokenIdType)
+            .collect();
+        // tokenizer_config.json's eos_token is a string or an AddedToken
+        if let Some(PromptFormatterArtifact::HfTokenizerConfigJson(path)) = &card.prompt_formatter {
+            let config = read_json(path)?;
+            let eos_token = &config["eos_token"];
+            let eos_token = eos_token.as_str().or(eos_token["content"].as_str());
+            if let (Some(eos_token), Ok(tokenizer)) = (eos_token, card.tokenizer()) {
+                stop_token_ids.extend(tokenizer.token_to_id(eos_token));
+            }
+        }
+        card.add_end_of_turn_tokens(&mut stop_token_ids);
+        card.stop_token_ids = stop_token_ids;
+        Ok(card)
+    }
+
+    /// Add the [`END_OF_TURN_TOKENS`] the tokenizer knows, and remove duplicates
+    fn add_end_of_turn_tokens(&self, stop_token_ids: &mut Vec<TokenIdType>) {
+        if let Ok(tokenizer) = self.tokenizer() {
+            stop_token_ids.extend(
+                END_OF_TURN_TOKENS
+                    .iter()
+                    .filter_map(|token| tokenizer.token_to_id(token)),
+            );
+        }
+        let mut seen = std::collections::HashSet::new();
+        stop_token_ids.retain(|id| seen.insert(*id));
     }
 }
 
+/// The `tokenizer.chat_template` of a GGUF file
+fn gguf_chat_template(gguf_file: &Path) -> Option<String> {
+    let content = load_gguf(gguf_file).ok()?;
+    let template = content.get_metadata().get("tokenizer.chat_template")?;
+    template.to_string().ok().cloned()
+}
+
+fn read_json(path: &str) -> anyhow::Result<serde_json::Value> {
+    let contents = fs::read_to_string(path).with_context(|| path.to_string())?;
+    serde_json::from_str(&contents).with_context(|| path.to_string())
+}
+
 impl ModelInfoType {
     pub async fn from_repo(repo_id: &str) -> Result<Self> {
         Self::try_is_hf_repo(repo_id)
@@ -151,10 +304,37 @@ impl PromptFormatterArtifact {
 }
 
 impl TokenizerKind {
+    /// The repo's `tokenizer.json`, or if it has none a SentencePiece `tokenizer.model` or a
+    /// tiktoken vocabulary
     pub async fn from_repo(repo_id: &str) -> Result<Self> {
-        Self::try_is_hf_repo(repo_id)
-            .await
-            .with_context(|| format!("unable to extract tokenizer kind from repo {}", repo_id))
+        let kind = match Self::try_is_hf_repo(repo_id).await {
+            Ok(kind) => Ok(kind),
+            Err(_) => Self::try_is_sentencepiece_or_tiktoken(repo_id).await,
+        };
+        kind.with_context(|| format!("unable to extract tokenizer kind from repo {}", repo_id))
+    }
+
+    /// Some models ship only the tokenizer they were trained with. `tokenizer.model` is
+    /// usually SentencePiece but can be tiktoken, [`TokenizerKind::classify`] tells them apart.
+    async fn try_is_sentencepiece_or_tiktoken(repo: &str) -> anyhow::Result<Self> {
+        // Not canonicalized: in the Hugging Face cache that is a blob, and the tiktoken
+        // tokenizer looks for the tokenizer_config.json next to its file.
+        if let Ok(file) = check_for_file(repo, "tokenizer.model").await {
+            return Self::classify(Path::new(&file), file.clone());
+        }
+        let mut vocabs: Vec<_> = fs::read_dir(repo)?
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "tiktoken"))
+            .collect();
+        vocabs.sort();
+        let Some(vocab) = vocabs.first() else {
+            anyhow::bail!("no tokenizer.json, tokenizer.model or .tiktoken file");
+        };
+        let file = vocab
+            .to_str()
+            .ok_or_else(|| anyhow::anyhow!("Path contains invalid Unicode"))?
+            .to_string();
+        Self::classify(vocab, file)
     }
 
     async fn try_is_hf_repo(repo: &str) -> anyhow::Result<Self> {
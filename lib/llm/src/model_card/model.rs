@@ -27,11 +27,13 @@
 
 use std::fmt;
 use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::{Context, Result};
+use base64::Engine as _;
 use derive_builder::Builder;
 use dynamo_runtime::slug::Slug;
 use dynamo_runtime::transports::nats;
@@ -43,6 +45,7 @@ use url::Url;
 use crate::gguf::{Content, ContentConfig, ModelConfigLike};
 use crate::key_value_store::Versioned;
 use crate::protocols::TokenIdType;
+use crate::tokenizers::{traits, HuggingFaceTokenizer};
 
 pub const BUCKET_NAME: &str = "mdc";
 
@@ -65,6 +68,10 @@ pub enum ModelInfoType {
 pub enum TokenizerKind {
     HfTokenizerJson(String),
     GGUF(Box<HfTokenizer>),
+    /// A SentencePiece model, needs the `sentencepiece` feature
+    SentencePiece(String),
+    /// A tiktoken BPE file, needs the `tiktoken` feature
+    Tiktoken(String),
 }
 
 /// Supported types of prompt formatters.
@@ -119,6 +126,29 @@ pub struct ModelDeploymentCard {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub prompt_context: Option<Vec<PromptContextMixin>>,
 
+    /// Jinja chat template to render prompts with instead of the one `prompt_formatter`
+    /// points at, see [`ModelDeploymentCard::set_chat_template`]. The template itself, not a
+    /// path, so it travels with the card.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chat_template: Option<String>,
+
+    /// Tokens that end generation, found in generation_config.json, the tokenizer or GGUF
+    /// metadata. Models such as Llama 3 end a turn with a token that config.json's
+    /// `eos_token_id` does not list.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stop_token_ids: Vec<TokenIdType>,
+
+    /// Most tokens a request can have, prompt and completion, when that is not the model's
+    /// own context length from config.json or the GGUF. Set by `--context-length`, or by an
+    /// engine that runs with a smaller context.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_length: Option<u32>,
+
+    /// Name of the engine serving the model, e.g. "vllm". Selects which sampling options the
+    /// pre-processor passes on, see [`crate::protocols::common::sampling::SamplingCaps`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub engine: Option<String>,
+
     /// When this card was last advertised by a worker. None if not yet published.
     pub last_published: Option<chrono::DateTime<chrono::Utc>>,
 
@@ -201,6 +231,40 @@ impl ModelDeploymentCard {
         format!("{}", blake3::hash(json.as_bytes()))
     }
 
+    /// Checksum of what turns text into tokens and back: the tokenizer and the prompt
+    /// template. Unlike [`Self::mdcsum`] it doesn't depend on where the files are, so a frontend
+    /// and a worker can compare theirs. Needs the files on local disk.
+    pub fn vocab_checksum(&self) -> anyhow::Result<String> {
+        let mut hasher = blake3::Hasher::new();
+        match &self.tokenizer {
+            Some(
+                TokenizerKind::HfTokenizerJson(file)
+                | TokenizerKind::SentencePiece(file)
+                | TokenizerKind::Tiktoken(file),
+            ) => {
+                hasher.update(&std::fs::read(file)?);
+            }
+            Some(kind @ TokenizerKind::GGUF(_)) => {
+                hasher.update(&serde_json::to_vec(kind)?);
+            }
+            None => {
+                anyhow::bail!("Blank ModelDeploymentCard does not have a tokenizer");
+            }
+        }
+        match &self.prompt_formatter {
+            Some(PromptFormatterArtifact::HfTokenizerConfigJson(file)) => {
+                hasher.update(&std::fs::read(file)?);
+            }
+            // The template is inside the GGUF with the weights, too big to hash. The
+            // tokenizer already tells GGUF models apart.
+            Some(PromptFormatterArtifact::GGUF(_)) | None => {}
+        }
+        if let Some(chat_template) = &self.chat_template {
+            hasher.update(chat_template.as_bytes());
+        }
+        Ok(hasher.finalize().to_string())
+    }
+
     /// Was this card last published a long time ago, suggesting the worker is gone?
     pub fn is_expired(&self) -> bool {
         if let Some(last_published) = self.last_published.as_ref() {
@@ -216,12 +280,10 @@ impl ModelDeploymentCard {
         self.tokenizer.is_some()
     }
 
-    pub fn tokenizer_hf(&self) -> anyhow::Result<HfTokenizer> {
+    /// Load the tokenizer, see [`TokenizerKind::load`]
+    pub fn tokenizer(&self) -> anyhow::Result<Arc<dyn traits::Tokenizer>> {
         match &self.tokenizer {
-            Some(TokenizerKind::HfTokenizerJson(file)) => {
-                HfTokenizer::from_file(file).map_err(anyhow::Error::msg)
-            }
-            Some(TokenizerKind::GGUF(t)) => Ok(*t.clone()),
+            Some(kind) => kind.load(),
             None => {
                 anyhow::bail!("Blank ModelDeploymentCard does not have a tokenizer");
             }
@@ -262,13 +324,13 @@ impl ModelDeploymentCard {
             }
         }
 
-        if let Some(TokenizerKind::HfTokenizerJson(ref src_file)) = self.tokenizer {
+        if let Some((src_file, name)) = self.tokenizer.as_mut().and_then(TokenizerKind::file_mut) {
             if !nats::is_nats_url(src_file) {
-                let target = format!("nats://{nats_addr}/{bucket_name}/tokenizer.json");
+                let target = format!("nats://{nats_addr}/{bucket_name}/{name}");
                 nats_client
-                    .object_store_upload(&PathBuf::from(src_file), Url::parse(&target)?)
+                    .object_store_upload(&PathBuf::from(src_file.as_str()), Url::parse(&target)?)
                     .await?;
-                self.tokenizer = Some(TokenizerKind::HfTokenizerJson(target));
+                *src_file = target;
             }
         }
 
@@ -314,13 +376,13 @@ impl ModelDeploymentCard {
             }
         }
 
-        if let Some(TokenizerKind::HfTokenizerJson(ref src_url)) = self.tokenizer {
+        if let Some((src_url, name)) = self.tokenizer.as_mut().and_then(TokenizerKind::file_mut) {
             if nats::is_nats_url(src_url) {
-                let target = target_dir.path().join("tokenizer.json");
+                let target = target_dir.path().join(name);
                 nats_client
                     .object_store_download(Url::parse(src_url)?, &target)
                     .await?;
-                self.tokenizer = Some(TokenizerKind::HfTokenizerJson(target.display().to_string()));
+                *src_url = target.display().to_string();
             }
         }
 
@@ -373,6 +435,9 @@ pub trait ModelInfo: Send + Sync {
 
     /// Vocabulary size
     fn vocab_size(&self) -> usize;
+
+    /// Whether the model accepts images as well as text
+    fn is_multimodal(&self) -> bool;
 }
 
 impl ModelInfoType {
@@ -394,6 +459,10 @@ struct HFConfig {
     model_type: String,
 
     text_config: Option<HFTextConfig>,
+
+    /// Present in vision-language models
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    vision_config: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -457,6 +526,7 @@ impl HFConfig {
                 // "tokenizer.ggml.tokens".len()
                 vocab_size,
             }),
+            vision_config: None,
         }))
     }
 }
@@ -484,6 +554,10 @@ impl ModelInfo for HFConfig {
     fn vocab_size(&self) -> usize {
         self.text_config.as_ref().unwrap().vocab_size
     }
+
+    fn is_multimodal(&self) -> bool {
+        self.vision_config.is_some()
+    }
 }
 
 impl TokenizerKind {
@@ -493,9 +567,100 @@ impl TokenizerKind {
             .with_context(|| gguf_file.display().to_string())?;
         Ok(TokenizerKind::GGUF(Box::new(out.tokenizer)))
     }
+
+    /// The tokenizer in `path`, by its extension: `.json` is a Hugging Face tokenizer,
+    /// `.tiktoken` a tiktoken BPE file and `.model` a SentencePiece model. Some models ship
+    /// their tiktoken file as `tokenizer.model`, so those are told apart by their contents.
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        if !path.is_file() {
+            anyhow::bail!("Tokenizer {} is not a file", path.display());
+        }
+        let file = path
+            .canonicalize()?
+            .to_str()
+            .ok_or_else(|| anyhow::anyhow!("Path contains invalid Unicode"))?
+            .to_string();
+        Self::classify(path, file)
+    }
+
+    /// The kind of tokenizer in `path`, see [`TokenizerKind::from_file`], loaded from `file`
+    pub(super) fn classify(path: &Path, file: String) -> anyhow::Result<Self> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => Ok(TokenizerKind::HfTokenizerJson(file)),
+            Some("tiktoken") => Ok(TokenizerKind::Tiktoken(file)),
+            Some("model") if is_tiktoken_file(path)? => Ok(TokenizerKind::Tiktoken(file)),
+            Some("model") => Ok(TokenizerKind::SentencePiece(file)),
+            _ => anyhow::bail!(
+                "Unknown tokenizer {}, expected a .json, .model or .tiktoken file",
+                path.display()
+            ),
+        }
+    }
+
+    /// Load the tokenizer. SentencePiece and tiktoken need their crate feature.
+    pub fn load(&self) -> anyhow::Result<Arc<dyn traits::Tokenizer>> {
+        match self {
+            TokenizerKind::HfTokenizerJson(file) => {
+                Ok(Arc::new(HuggingFaceTokenizer::from_file(file)?))
+            }
+            TokenizerKind::GGUF(tokenizer) => Ok(Arc::new(HuggingFaceTokenizer::from_tokenizer(
+                *tokenizer.clone(),
+            ))),
+            #[cfg(feature = "sentencepiece")]
+            TokenizerKind::SentencePiece(file) => Ok(Arc::new(
+                crate::tokenizers::SentencePieceTokenizer::from_file(file)?,
+            )),
+            #[cfg(not(feature = "sentencepiece"))]
+            TokenizerKind::SentencePiece(file) => {
+                anyhow::bail!(
+                    "{file} is a SentencePiece model, build with the sentencepiece feature"
+                )
+            }
+            #[cfg(feature = "tiktoken")]
+            TokenizerKind::Tiktoken(file) => Ok(Arc::new(
+                crate::tokenizers::TiktokenTokenizer::from_file(file)?,
+            )),
+            #[cfg(not(feature = "tiktoken"))]
+            TokenizerKind::Tiktoken(file) => {
+                anyhow::bail!("{file} is a tiktoken file, build with the tiktoken feature")
+            }
+        }
+    }
+
+    /// The tokenizer's file and what we call it in the NATS object store. None for GGUF,
+    /// whose tokenizer travels inside the card.
+    fn file_mut(&mut self) -> Option<(&mut String, &'static str)> {
+        match self {
+            TokenizerKind::HfTokenizerJson(file) => Some((file, "tokenizer.json")),
+            TokenizerKind::SentencePiece(file) => Some((file, "tokenizer.model")),
+            TokenizerKind::Tiktoken(file) => Some((file, "tokenizer.tiktoken")),
+            TokenizerKind::GGUF(_) => None,
+        }
+    }
+}
+
+/// Whether the first line of `path` is a tiktoken rank: a base64 token, a space and a number.
+/// A SentencePiece model is a protobuf.
+fn is_tiktoken_file(path: &Path) -> anyhow::Result<bool> {
+    let mut first_line = Vec::new();
+    BufReader::new(File::open(path)?)
+        .take(1024)
+        .read_until(b'\n', &mut first_line)?;
+    let Ok(line) = std::str::from_utf8(&first_line) else {
+        return Ok(false);
+    };
+    Ok(line
+        .trim_end()
+        .split_once(' ')
+        .is_some_and(|(token, rank)| {
+            rank.parse::<TokenIdType>().is_ok()
+                && base64::engine::general_purpose::STANDARD
+                    .decode(token)
+                    .is_ok()
+        }))
 }
 
-fn load_gguf(gguf_file: &Path) -> anyhow::Result<Content> {
+pub(crate) fn load_gguf(gguf_file: &Path) -> anyhow::Result<Content> {
     let filename = gguf_file.display().to_string();
     let mut f = File::open(gguf_file).with_context(|| filename.clone())?;
     // vec because GGUF can be split into multiple files (shards)
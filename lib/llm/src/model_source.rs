@@ -0,0 +1,421 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Models in object storage and OCI registries
+//!
+//! Besides a local path and a Hugging Face repo, a model can be at
+//!
+//! - `s3://<bucket>/<key>`: an object, or every object under a prefix, in Amazon S3 or a store
+//!   compatible with it at `AWS_ENDPOINT`. Credentials come from the `AWS_*` environment
+//!   variables, a web identity token as EKS sets up, or the instance metadata.
+//! - `gs://<bucket>/<key>` or `gcs://...`: the same in Google Cloud Storage, with
+//!   `GOOGLE_APPLICATION_CREDENTIALS`, the application default credentials of `gcloud` or
+//!   the metadata server as on GKE.
+//! - `oci://<registry>/<repository>[:<tag>|@<digest>]`: an artifact in an OCI registry, see
+//!   [`oci`].
+//!
+//! The files download to [`CACHE_ENV`], `~/.cache/dynamo/models` by default, before the
+//! engine loads them. A file already there is not downloaded again unless it changed. As with
+//! Hugging Face, see [`crate::hub`], several files download at once and a download that stops
+//! continues where it got to.
+//!
+//! A worker can also copy a model from another worker that has it, see [`peer`].
+
+use std::future::Future;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context as _;
+use bytes::Bytes;
+use futures::stream::BoxStream;
+use futures::{StreamExt, TryStreamExt};
+use object_store::aws::AmazonS3Builder;
+use object_store::gcp::GoogleCloudStorageBuilder;
+use object_store::path::Path as ObjectPath;
+use object_store::{GetOptions, GetRange, ObjectMeta, ObjectStore};
+use tokio::io::AsyncWriteExt;
+
+use crate::hub::{self, BlobLock, Progress, MAX_ATTEMPTS, PARALLEL_FILES};
+
+pub mod oci;
+pub mod peer;
+
+/// Where models from object storage and registries are kept
+pub const CACHE_ENV: &str = "DYN_MODEL_CACHE";
+
+const S3_SCHEME: &str = "s3://";
+const GCS_SCHEMES: [&str; 2] = ["gs://", "gcs://"];
+const OCI_SCHEME: &str = "oci://";
+
+/// The body of a file, from where a download asked for
+type Body = BoxStream<'static, anyhow::Result<Bytes>>;
+
+/// Whether the model is in object storage or a registry
+pub fn is_remote(model_path: &str) -> bool {
+    model_path.starts_with(S3_SCHEME)
+        || model_path.starts_with(OCI_SCHEME)
+        || GCS_SCHEMES
+            .iter()
+            .any(|scheme| model_path.starts_with(scheme))
+}
+
+/// What a model at `url` is called unless it is given a name: the last part of its path,
+/// without the tag or digest of an artifact
+pub fn model_name(url: &str) -> String {
+    let path = url.split_once("://").map_or(url, |(_, path)| path);
+    let path = path.trim_end_matches('/');
+    let last = path.rsplit('/').next().unwrap_or(path);
+    if url.starts_with(OCI_SCHEME) {
+        let last = last.split('@').next().unwrap_or(last);
+        return last.split(':').next().unwrap_or(last).to_string();
+    }
+    last.to_string()
+}
+
+/// The version the model at `url` is pinned to, the digest of an OCI artifact referenced by
+/// digest. Tags and objects in S3 or GCS can change under the same name, so they have none.
+pub(crate) fn pinned_revision(url: &str) -> Option<String> {
+    oci::pinned_digest(url.strip_prefix(OCI_SCHEME)?)
+}
+
+/// Download the model at `url` unless the cache has it. Returns its directory, or its file if
+/// it is one file.
+pub async fn fetch(url: &str, with_weights: bool) -> anyhow::Result<PathBuf> {
+    if let Some(reference) = url.strip_prefix(OCI_SCHEME) {
+        return oci::fetch(reference, with_weights).await;
+    }
+    let (kind, location) = match url.strip_prefix(S3_SCHEME) {
+        Some(location) => ("s3", location),
+        None => {
+            let location = GCS_SCHEMES
+                .iter()
+                .find_map(|scheme| url.strip_prefix(scheme))
+                .with_context(|| format!("{url} is not in S3, GCS or an OCI registry"))?;
+            ("gcs", location)
+        }
+    };
+    let (bucket, key) = location.split_once('/').unwrap_or((location, ""));
+    let key = key.trim_end_matches('/');
+    let store: Arc<dyn ObjectStore> = if kind == "s3" {
+        Arc::new(
+            AmazonS3Builder::from_env()
+                .with_bucket_name(bucket)
+                .build()?,
+        )
+    } else {
+        Arc::new(
+            GoogleCloudStorageBuilder::from_env()
+                .with_bucket_name(bucket)
+                .build()?,
+        )
+    };
+    let local = cache_dir()?.join(kind).join(bucket).join(key);
+    fetch_objects(store.as_ref(), url, key, &local, with_weights).await
+}
+
+/// `DYN_MODEL_CACHE`, else `dynamo/models` in the user's cache directory
+pub(crate) fn cache_dir() -> anyhow::Result<PathBuf> {
+    if let Some(dir) = std::env::var_os(CACHE_ENV).filter(|dir| !dir.is_empty()) {
+        return Ok(PathBuf::from(dir));
+    }
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .with_context(|| format!("Set {CACHE_ENV} to where downloaded models go"))?;
+    Ok(base.join("dynamo").join("models"))
+}
+
+/// A path from a remote listing, if it stays inside the directory it goes in
+pub(crate) fn safe_relative(path: &str) -> Option<PathBuf> {
+    let path = Path::new(path);
+    let normal = path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+    (normal && path.components().next().is_some()).then(|| path.to_path_buf())
+}
+
+/// The object at `key`, or the objects under it
+async fn fetch_objects(
+    store: &dyn ObjectStore,
+    url: &str,
+    key: &str,
+    local: &Path,
+    with_weights: bool,
+) -> anyhow::Result<PathBuf> {
+    let prefix = ObjectPath::from(key);
+    let listed = match store.head(&prefix).await {
+        Ok(meta) => Ok((vec![meta], true)),
+        Err(_) => store
+            .list((!key.is_empty()).then_some(&prefix))
+            .try_collect::<Vec<_>>()
+            .await
+            .map(|objects| (objects, false)),
+    };
+    let (objects, is_file) = match listed {
+        Ok(listed) => listed,
+        Err(err) if local.exists() => {
+            tracing::warn!(
+                url,
+                "Using the model downloaded before, could not list it: {err}"
+            );
+            return Ok(local.to_path_buf());
+        }
+        Err(err) => return Err(anyhow::Error::new(err).context(format!("Failed to list {url}"))),
+    };
+
+    let mut files = vec![];
+    for meta in objects {
+        let dest = if is_file {
+            local.to_path_buf()
+        } else {
+            let relative: Vec<String> = meta
+                .location
+                .prefix_match(&prefix)
+                .into_iter()
+                .flatten()
+                .map(|part| part.as_ref().to_string())
+                .collect();
+            match safe_relative(&relative.join("/")) {
+                Some(relative) => local.join(relative),
+                None => continue,
+            }
+        };
+        let name = dest.to_string_lossy();
+        if !with_weights && hub::is_weights(&name) {
+            continue;
+        }
+        files.push((meta, dest));
+    }
+    if files.is_empty() {
+        anyhow::bail!("No files at {url}");
+    }
+
+    let total = files.iter().map(|(meta, _)| meta.size).sum();
+    let progress = Progress::new(url, total);
+    futures::stream::iter(&files)
+        .map(|(meta, dest)| fetch_object(store, meta, dest, &progress))
+        .buffer_unordered(PARALLEL_FILES)
+        .try_collect::<Vec<()>>()
+        .await?;
+    progress.finish();
+    Ok(local.to_path_buf())
+}
+
+async fn fetch_object(
+    store: &dyn ObjectStore,
+    meta: &ObjectMeta,
+    dest: &Path,
+    progress: &Progress,
+) -> anyhow::Result<()> {
+    // As long as the object, and written after it last changed
+    let up_to_date = |path: &Path| {
+        std::fs::metadata(path)
+            .and_then(|local| {
+                Ok(local.len() == meta.size && local.modified()? >= meta.last_modified.into())
+            })
+            .unwrap_or(false)
+    };
+    if up_to_date(dest) {
+        progress.skip(meta.size);
+        return Ok(());
+    }
+    let version = meta
+        .e_tag
+        .clone()
+        .unwrap_or_else(|| meta.last_modified.to_rfc3339());
+    let file = RemoteFile {
+        dest,
+        size: meta.size,
+        version: Some(&version),
+        sha256: None,
+    };
+    let open = |start| async move {
+        let options = GetOptions {
+            range: (start > 0).then_some(GetRange::Offset(start)),
+            ..Default::default()
+        };
+        let body = store
+            .get_opts(&meta.location, options)
+            .await?
+            .into_stream()
+            .map_err(anyhow::Error::new)
+            .boxed();
+        Ok::<_, anyhow::Error>((start, body))
+    };
+    download_file(file, progress, open, &up_to_date)
+        .await
+        .with_context(|| format!("Failed to download {}", meta.location))
+}
+
+/// A file to download
+pub(crate) struct RemoteFile<'a> {
+    pub dest: &'a Path,
+    pub size: u64,
+    /// Changes when the file does. A partial download of another version is not continued.
+    pub version: Option<&'a str>,
+    /// Checked once it is downloaded
+    pub sha256: Option<&'a str>,
+}
+
+/// Download `file` through `<dest>.incomplete`. `open(start)` returns the file from where the
+/// body it returns starts, `start`, or 0 if the source only sends whole files. A download that
+/// stops is tried again from where it got to, and the next run continues it too.
+/// `up_to_date` says whether another process downloaded it while this one waited for it.
+pub(crate) async fn download_file<F, Fut>(
+    file: RemoteFile<'_>,
+    progress: &Progress,
+    open: F,
+    up_to_date: &dyn Fn(&Path) -> bool,
+) -> anyhow::Result<()>
+where
+    F: Fn(u64) -> Fut,
+    Fut: Future<Output = anyhow::Result<(u64, Body)>>,
+{
+    let RemoteFile {
+        dest,
+        size,
+        version,
+        sha256,
+    } = file;
+    if let Some(parent) = dest.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let _lock = BlobLock::acquire(dest).await?;
+    if up_to_date(dest) {
+        progress.skip(size);
+        return Ok(());
+    }
+    let suffix = match version {
+        Some(version) => format!(
+            ".{}.incomplete",
+            &blake3::hash(version.as_bytes()).to_hex()[..16]
+        ),
+        None => ".incomplete".to_string(),
+    };
+    let partial = hub::with_suffix(dest, &suffix);
+    if file_len(&partial).await > size {
+        let _ = tokio::fs::remove_file(&partial).await;
+    }
+    progress.skip(file_len(&partial).await);
+
+    let mut attempt = 1;
+    loop {
+        match download_rest(&partial, size, progress, &open).await {
+            Ok(()) => break,
+            Err(err) if attempt < MAX_ATTEMPTS => {
+                tracing::warn!(
+                    file = %dest.display(),
+                    attempt,
+                    "Download interrupted, resuming: {err:#}"
+                );
+                tokio::time::sleep(Duration::from_secs(1 << attempt)).await;
+                attempt += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    let len = file_len(&partial).await;
+    if len != size {
+        let _ = tokio::fs::remove_file(&partial).await;
+        anyhow::bail!("{} is {len} bytes, expected {size}", dest.display());
+    }
+    if let Some(expected) = sha256 {
+        let owned = partial.clone();
+        let actual = tokio::task::spawn_blocking(move || hub::sha256_file(&owned)).await??;
+        if actual != expected {
+            let _ = tokio::fs::remove_file(&partial).await;
+            anyhow::bail!(
+                "{} is corrupt, its SHA-256 is {actual}, expected {expected}",
+                dest.display()
+            );
+        }
+    }
+    tokio::fs::rename(&partial, dest).await?;
+    Ok(())
+}
+
+async fn download_rest<F, Fut>(
+    partial: &Path,
+    size: u64,
+    progress: &Progress,
+    open: &F,
+) -> anyhow::Result<()>
+where
+    F: Fn(u64) -> Fut,
+    Fut: Future<Output = anyhow::Result<(u64, Body)>>,
+{
+    let start = file_len(partial).await;
+    if start == size {
+        return Ok(());
+    }
+    let (from, mut body) = open(start).await?;
+    let mut out = if from > 0 {
+        tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(partial)
+            .await?
+    } else {
+        progress.rewind(start);
+        tokio::fs::File::create(partial).await?
+    };
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk?;
+        out.write_all(&chunk).await?;
+        progress.add(chunk.len() as u64);
+    }
+    out.flush().await?;
+    Ok(())
+}
+
+async fn file_len(path: &Path) -> u64 {
+    tokio::fs::metadata(path)
+        .await
+        .map(|m| m.len())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_model_name() {
+        assert_eq!(model_name("s3://models/llama-3.1-8b/"), "llama-3.1-8b");
+        assert_eq!(model_name("gs://models/qwen3.gguf"), "qwen3.gguf");
+        assert_eq!(
+            model_name("oci://registry.example.com:5000/models/qwen3:v1"),
+            "qwen3"
+        );
+        assert_eq!(
+            model_name("oci://registry.example.com/models/qwen3@sha256:abcd"),
+            "qwen3"
+        );
+        assert!(is_remote("gcs://models/qwen3"));
+        assert!(!is_remote("Qwen/Qwen3-0.6B"));
+    }
+
+    #[test]
+    fn test_safe_relative() {
+        assert_eq!(
+            safe_relative("onnx/model.onnx"),
+            Some(PathBuf::from("onnx/model.onnx"))
+        );
+        assert_eq!(safe_relative("../etc/passwd"), None);
+        assert_eq!(safe_relative("/etc/passwd"), None);
+        assert_eq!(safe_relative(""), None);
+    }
+}
@@ -0,0 +1,456 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Models packaged as OCI artifacts
+//!
+//! `oci://<registry>/<repository>[:<tag>|@<digest>]`, tag `latest` if neither, is an artifact
+//! whose layers are the files of the model, each named by its `org.opencontainers.image.title`
+//! annotation. `oras push` makes those:
+//!
+//! ```text
+//! oras push registry.example.com/models/qwen3-0.6b:v1 config.json tokenizer.json model.safetensors
+//! ```
+//!
+//! Credentials come from the Docker config, `$DOCKER_CONFIG/config.json` or
+//! `~/.docker/config.json`, as `docker login` writes it. Credential helpers are not supported.
+//! Each layer is checked against its digest. A registry on `localhost` is reached over plain
+//! HTTP, others over HTTPS.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+
+use anyhow::Context as _;
+use base64::Engine as _;
+use futures::{StreamExt, TryStreamExt};
+use regex::Regex;
+use reqwest::header::{ACCEPT, AUTHORIZATION, RANGE, WWW_AUTHENTICATE};
+use reqwest::{Response, StatusCode};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use super::{download_file, safe_relative, RemoteFile};
+use crate::hub::{self, Progress, PARALLEL_FILES};
+
+const DEFAULT_TAG: &str = "latest";
+
+const TITLE_ANNOTATION: &str = "org.opencontainers.image.title";
+
+const MANIFEST_TYPES: &str =
+    "application/vnd.oci.image.manifest.v1+json, application/vnd.docker.distribution.manifest.v2+json";
+
+/// Docker Hub's name, its API and the key of its credentials in the Docker config
+const DOCKER_HUB: (&str, &str, &str) = (
+    "docker.io",
+    "registry-1.docker.io",
+    "https://index.docker.io/v1/",
+);
+
+/// `key="value"` in a `WWW-Authenticate` header
+static CHALLENGE_PARAM: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(\w+)="([^"]*)""#).unwrap());
+
+#[derive(Debug, PartialEq, Eq)]
+struct Reference {
+    registry: String,
+    repository: String,
+    /// A tag or a digest
+    reference: String,
+}
+
+impl Reference {
+    fn parse(s: &str) -> anyhow::Result<Self> {
+        let Some((registry, rest)) = s.split_once('/') else {
+            anyhow::bail!("oci://{s} is not oci://<registry>/<repository>[:<tag>]");
+        };
+        let (repository, reference) = match rest.split_once('@') {
+            Some((repository, digest)) => (repository, digest),
+            None => match rest.rsplit_once(':') {
+                Some((repository, tag)) if !tag.contains('/') => (repository, tag),
+                _ => (rest, DEFAULT_TAG),
+            },
+        };
+        if repository.is_empty() || reference.is_empty() {
+            anyhow::bail!("oci://{s} is not oci://<registry>/<repository>[:<tag>]");
+        }
+        Ok(Reference {
+            registry: registry.to_string(),
+            repository: repository.to_string(),
+            reference: reference.to_string(),
+        })
+    }
+
+    fn is_digest(&self) -> bool {
+        self.reference.contains(':')
+    }
+}
+
+#[derive(Deserialize)]
+struct Manifest {
+    layers: Vec<Layer>,
+}
+
+#[derive(Deserialize)]
+struct Layer {
+    digest: String,
+    size: u64,
+    #[serde(default)]
+    annotations: HashMap<String, String>,
+}
+
+/// The digest `reference` pins the artifact to, None if it is a tag
+pub(crate) fn pinned_digest(reference: &str) -> Option<String> {
+    Reference::parse(reference)
+        .ok()
+        .filter(Reference::is_digest)
+        .map(|reference| reference.reference)
+}
+
+/// Download the artifact `reference` unless the cache has it. Returns its directory, or its
+/// file if it has one.
+pub async fn fetch(reference: &str, with_weights: bool) -> anyhow::Result<PathBuf> {
+    let reference = Reference::parse(reference)?;
+    let repo_dir = super::cache_dir()?
+        .join("oci")
+        .join(&reference.registry)
+        .join(&reference.repository);
+    let tag_file = repo_dir.join("tags").join(&reference.reference);
+    let registry = Registry::new(&reference)?;
+
+    let (digest, manifest) = match registry.manifest().await {
+        Ok(found) => found,
+        Err(err) => {
+            // The registry can't be reached, use what was downloaded before
+            let digest = if reference.is_digest() {
+                Some(reference.reference.clone())
+            } else {
+                std::fs::read_to_string(&tag_file).ok()
+            };
+            let cached = digest.and_then(|digest| {
+                let dir = repo_dir.join(digest.trim().replace(':', "-"));
+                dir.is_dir().then_some(dir)
+            });
+            let Some(dir) = cached else {
+                return Err(err.context(format!(
+                    "Failed to fetch oci://{}/{}:{}",
+                    reference.registry, reference.repository, reference.reference
+                )));
+            };
+            tracing::warn!(
+                reference = reference.reference,
+                "Using the artifact downloaded before, the registry could not be reached: {err:#}"
+            );
+            return Ok(single_file(&dir).unwrap_or(dir));
+        }
+    };
+
+    let dir = repo_dir.join(digest.replace(':', "-"));
+    let mut files = vec![];
+    for layer in &manifest.layers {
+        let Some(title) = layer.annotations.get(TITLE_ANNOTATION) else {
+            continue;
+        };
+        let Some(relative) = safe_relative(title) else {
+            anyhow::bail!(
+                "Layer {} is named '{title}', outside the model",
+                layer.digest
+            );
+        };
+        if !with_weights && hub::is_weights(title) {
+            continue;
+        }
+        files.push((layer, dir.join(relative)));
+    }
+    if files.is_empty() {
+        anyhow::bail!(
+            "No layer of {}/{} has a file name, push models with `oras push`",
+            reference.registry,
+            reference.repository
+        );
+    }
+
+    let total = files.iter().map(|(layer, _)| layer.size).sum();
+    let progress = Progress::new(&reference.repository, total);
+    futures::stream::iter(&files)
+        .map(|(layer, dest)| registry.fetch_layer(layer, dest, &progress))
+        .buffer_unordered(PARALLEL_FILES)
+        .try_collect::<Vec<()>>()
+        .await?;
+    progress.finish();
+
+    if !reference.is_digest() {
+        std::fs::create_dir_all(tag_file.parent().unwrap_or(&repo_dir))?;
+        std::fs::write(&tag_file, &digest)?;
+    }
+    Ok(if files.len() == 1 {
+        files.swap_remove(0).1
+    } else {
+        dir
+    })
+}
+
+/// The file of an artifact directory with one
+fn single_file(dir: &Path) -> Option<PathBuf> {
+    let mut entries = std::fs::read_dir(dir).ok()?.flatten();
+    let first = entries.next()?.path();
+    (entries.next().is_none() && first.is_file()).then_some(first)
+}
+
+/// A repository in a registry, `/v2/<repository>`
+struct Registry {
+    client: reqwest::Client,
+    base: String,
+    reference: String,
+    /// From the Docker config, base64 of `user:password`
+    credentials: Option<String>,
+    /// The `Authorization` the registry asked for, once it did
+    authorization: Mutex<Option<String>>,
+}
+
+impl Registry {
+    fn new(reference: &Reference) -> anyhow::Result<Self> {
+        let (host, config_key) = if reference.registry == DOCKER_HUB.0 {
+            (DOCKER_HUB.1, DOCKER_HUB.2)
+        } else {
+            (reference.registry.as_str(), reference.registry.as_str())
+        };
+        let scheme = if host.starts_with("localhost") || host.starts_with("127.0.0.1") {
+            "http"
+        } else {
+            "https"
+        };
+        Ok(Registry {
+            client: reqwest::Client::builder()
+                .user_agent(concat!("dynamo/", env!("CARGO_PKG_VERSION")))
+                .build()?,
+            base: format!("{scheme}://{host}/v2/{}", reference.repository),
+            reference: reference.reference.clone(),
+            credentials: docker_credentials(config_key),
+            authorization: Mutex::new(None),
+        })
+    }
+
+    /// The digest of the manifest and the manifest
+    async fn manifest(&self) -> anyhow::Result<(String, Manifest)> {
+        let url = format!("{}/manifests/{}", self.base, self.reference);
+        let body = self
+            .get(&url, Some(MANIFEST_TYPES), 0)
+            .await?
+            .bytes()
+            .await?;
+        let digest = format!("sha256:{:x}", Sha256::digest(&body));
+        if self.reference.contains(':') && self.reference != digest {
+            anyhow::bail!("Manifest has digest {digest}, expected {}", self.reference);
+        }
+        let manifest = serde_json::from_slice(&body).context("Not an OCI image manifest")?;
+        Ok((digest, manifest))
+    }
+
+    async fn fetch_layer(
+        &self,
+        layer: &Layer,
+        dest: &Path,
+        progress: &Progress,
+    ) -> anyhow::Result<()> {
+        // The directory is the manifest's digest, so a file there of the right size is this one
+        let up_to_date =
+            |path: &Path| std::fs::metadata(path).is_ok_and(|local| local.len() == layer.size);
+        if up_to_date(dest) {
+            progress.skip(layer.size);
+            return Ok(());
+        }
+        let Some(sha256) = layer.digest.strip_prefix("sha256:") else {
+            anyhow::bail!("Layer digest {} is not SHA-256", layer.digest);
+        };
+        let url = format!("{}/blobs/{}", self.base, layer.digest);
+        let file = RemoteFile {
+            dest,
+            size: layer.size,
+            version: None,
+            sha256: Some(sha256),
+        };
+        let open = |start| {
+            let url = url.clone();
+            async move {
+                let response = self.get(&url, None, start).await?;
+                let from = if response.status() == StatusCode::PARTIAL_CONTENT {
+                    start
+                } else {
+                    0
+                };
+                let body = response.bytes_stream().map_err(anyhow::Error::new).boxed();
+                Ok::<_, anyhow::Error>((from, body))
+            }
+        };
+        download_file(file, progress, open, &up_to_date)
+            .await
+            .with_context(|| format!("Failed to download layer {}", layer.digest))
+    }
+
+    /// GET `url`, from byte `start`, authenticating if the registry asks
+    async fn get(&self, url: &str, accept: Option<&str>, start: u64) -> anyhow::Result<Response> {
+        let request = || {
+            let mut request = self.client.get(url);
+            if let Some(accept) = accept {
+                request = request.header(ACCEPT, accept);
+            }
+            if start > 0 {
+                request = request.header(RANGE, format!("bytes={start}-"));
+            }
+            if let Some(authorization) = self.authorization.lock().unwrap().as_ref() {
+                request = request.header(AUTHORIZATION, authorization);
+            }
+            request
+        };
+        let mut response = request().send().await?;
+        if response.status() == StatusCode::UNAUTHORIZED {
+            let challenge = response
+                .headers()
+                .get(WWW_AUTHENTICATE)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default()
+                .to_string();
+            let authorization = self.authenticate(&challenge).await?;
+            *self.authorization.lock().unwrap() = Some(authorization);
+            response = request().send().await?;
+        }
+        match response.status() {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => anyhow::bail!(
+                "The registry refused access to {url}, `docker login` to it with a user who has access"
+            ),
+            _ => Ok(response.error_for_status()?),
+        }
+    }
+
+    /// The `Authorization` header a `WWW-Authenticate` challenge asks for
+    async fn authenticate(&self, challenge: &str) -> anyhow::Result<String> {
+        if challenge.starts_with("Basic") {
+            let Some(credentials) = &self.credentials else {
+                anyhow::bail!("The registry needs credentials, `docker login` to it");
+            };
+            return Ok(format!("Basic {credentials}"));
+        }
+        let params: HashMap<&str, &str> = CHALLENGE_PARAM
+            .captures_iter(challenge)
+            .filter_map(|c| Some((c.get(1)?.as_str(), c.get(2)?.as_str())))
+            .collect();
+        let Some(realm) = params.get("realm") else {
+            anyhow::bail!(
+                "The registry asked for authentication it doesn't describe: '{challenge}'"
+            );
+        };
+        let query: Vec<(&str, &str)> = ["service", "scope"]
+            .into_iter()
+            .filter_map(|key| Some((key, *params.get(key)?)))
+            .collect();
+        let mut request = self.client.get(*realm).query(&query);
+        if let Some(credentials) = &self.credentials {
+            request = request.header(AUTHORIZATION, format!("Basic {credentials}"));
+        }
+        #[derive(Deserialize)]
+        struct Token {
+            token: Option<String>,
+            access_token: Option<String>,
+        }
+        let token: Token = request.send().await?.error_for_status()?.json().await?;
+        let Some(token) = token.token.or(token.access_token) else {
+            anyhow::bail!("The registry's token service sent no token");
+        };
+        Ok(format!("Bearer {token}"))
+    }
+}
+
+/// The credentials `docker login` saved for `registry`
+fn docker_credentials(registry: &str) -> Option<String> {
+    let config_dir = std::env::var_os("DOCKER_CONFIG")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".docker")))?;
+    let config = std::fs::read_to_string(config_dir.join("config.json")).ok()?;
+    parse_docker_config(&config, registry)
+}
+
+fn parse_docker_config(config: &str, registry: &str) -> Option<String> {
+    #[derive(Deserialize)]
+    struct DockerConfig {
+        #[serde(default)]
+        auths: HashMap<String, DockerAuth>,
+    }
+    #[derive(Deserialize)]
+    struct DockerAuth {
+        auth: Option<String>,
+        username: Option<String>,
+        password: Option<String>,
+    }
+    let config: DockerConfig = serde_json::from_str(config).ok()?;
+    let auth = config.auths.into_iter().find_map(|(key, auth)| {
+        let host = key
+            .trim_start_matches("https://")
+            .trim_start_matches("http://");
+        (key == registry || host.trim_end_matches('/') == registry).then_some(auth)
+    })?;
+    match (auth.auth, auth.username, auth.password) {
+        (Some(auth), _, _) if !auth.is_empty() => Some(auth),
+        (_, Some(username), Some(password)) => {
+            Some(base64::engine::general_purpose::STANDARD.encode(format!("{username}:{password}")))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_reference() {
+        let parsed = Reference::parse("registry.example.com:5000/models/qwen3:v1").unwrap();
+        assert_eq!(
+            parsed,
+            Reference {
+                registry: "registry.example.com:5000".to_string(),
+                repository: "models/qwen3".to_string(),
+                reference: "v1".to_string(),
+            }
+        );
+        let parsed = Reference::parse("ghcr.io/org/qwen3").unwrap();
+        assert_eq!(parsed.reference, DEFAULT_TAG);
+        let parsed = Reference::parse("ghcr.io/org/qwen3@sha256:abcd").unwrap();
+        assert_eq!(parsed.repository, "org/qwen3");
+        assert!(parsed.is_digest());
+        assert!(Reference::parse("qwen3").is_err());
+        assert_eq!(
+            pinned_digest("ghcr.io/org/qwen3@sha256:abcd").as_deref(),
+            Some("sha256:abcd")
+        );
+        assert_eq!(pinned_digest("ghcr.io/org/qwen3:v1"), None);
+    }
+
+    #[test]
+    fn test_docker_config() {
+        let config = r#"{"auths": {
+            "https://index.docker.io/v1/": {"auth": "dXNlcjpodWI="},
+            "ghcr.io": {"username": "user", "password": "secret"}
+        }}"#;
+        assert_eq!(
+            parse_docker_config(config, DOCKER_HUB.2),
+            Some("dXNlcjpodWI=".to_string())
+        );
+        assert_eq!(
+            parse_docker_config(config, "ghcr.io"),
+            Some("dXNlcjpzZWNyZXQ=".to_string())
+        );
+        assert_eq!(parse_docker_config(config, "quay.io"), None);
+    }
+}
@@ -0,0 +1,542 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Models copied from another worker
+//!
+//! With [`PEERS_ENV`] set, a worker that has loaded a model offers its files to the others:
+//! it lists them in etcd under [`ROOT_PATH`], with the address of a TCP server that sends them.
+//! A worker starting with the same model copies the files from one of them before trying
+//! Hugging Face or object storage. Across a fast network this is much quicker than a
+//! download, and it doesn't read the cache from a shared disk, so scaling up a large model
+//! is limited by the network between the workers.
+//!
+//! Only a model pinned to a revision is offered and copied: the commit of a Hugging Face repo,
+//! or the digest of an OCI artifact referenced by digest. Offers are listed under it, and a
+//! worker only copies from workers that have the revision it resolved.
+//!
+//! The files are sent over TLS if it is set up for the connections between nodes, see
+//! [`TransportTls`]. They go to `peers/<model>/<revision>` in the cache, see
+//! [`super::cache_dir`]. A copy that stops
+//! continues from where it got to, from that worker or another. If no worker has the model,
+//! or they all fail, the model is downloaded as usual.
+//!
+//! The offer lists the SHA-256 of every file, and each copy is checked against it. A checked
+//! file gets a `<file>.sha256` next to it, so that the next start doesn't copy it again.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Context as _;
+use bytes::BytesMut;
+use futures::{StreamExt, TryStreamExt};
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use tokio::io::{
+    AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::codec::{BytesCodec, FramedRead};
+
+use dynamo_runtime::transports::etcd;
+use dynamo_runtime::transports::tls::TransportTls;
+use dynamo_runtime::{DistributedRuntime, Runtime};
+
+use super::{download_file, Body, RemoteFile};
+use crate::hub::{self, Progress, PARALLEL_FILES};
+use crate::model_card::ModelDeploymentCard;
+
+/// Set to copy models from the workers that have them, and offer them to the others
+pub const PEERS_ENV: &str = "DYN_WEIGHTS_FROM_PEERS";
+
+/// Where in etcd workers list the models they offer: `weights/<model>/<revision>/<lease id>`
+pub const ROOT_PATH: &str = "weights/";
+
+/// A request is one line, much shorter than this
+const MAX_REQUEST_LEN: u64 = 4096;
+
+/// Next to a copied file, the SHA-256 it was checked against
+const CHECKED_SUFFIX: &str = ".sha256";
+
+/// What a worker offers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Offer {
+    /// Of its file server
+    address: String,
+    /// The commit or digest of the model
+    revision: String,
+    /// The model is this one file, not a directory
+    is_file: bool,
+    files: Vec<OfferedFile>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct OfferedFile {
+    /// Relative to the model's directory, or its name if the model is a file
+    path: String,
+    size: u64,
+    /// Seconds since the epoch, so that a partial copy of an older file is not continued
+    modified: u64,
+    /// Hex, the copy must match it
+    sha256: String,
+}
+
+/// Send `path` from byte `start` on
+#[derive(Debug, Serialize, Deserialize)]
+struct FileRequest {
+    path: String,
+    start: u64,
+}
+
+/// Whether [`PEERS_ENV`] is set
+pub fn enabled() -> bool {
+    std::env::var_os(PEERS_ENV).is_some_and(|val| !val.is_empty() && val != "0")
+}
+
+fn model_prefix(model_name: &str, revision: &str) -> String {
+    format!(
+        "{ROOT_PATH}{}/{}/",
+        ModelDeploymentCard::service_name_slug(model_name),
+        revision_slug(revision)
+    )
+}
+
+/// `revision` fit for a key or a directory name, digests are `sha256:<hex>`
+fn revision_slug(revision: &str) -> String {
+    revision.replace(|c: char| !c.is_ascii_alphanumeric(), "-")
+}
+
+/// Offer the model at `path`, at `revision`, to the other workers, until the runtime stops.
+/// The offer is held by the runtime's lease, so it goes away with this worker.
+pub async fn serve(
+    drt: &DistributedRuntime,
+    card: &ModelDeploymentCard,
+    path: &Path,
+    revision: &str,
+) -> anyhow::Result<()> {
+    let Some(etcd_client) = drt.etcd_client() else {
+        anyhow::bail!("Offering the model to other workers needs etcd");
+    };
+    // Hashing a large model takes a while
+    let owned = path.to_path_buf();
+    let (is_file, files, paths) = tokio::task::spawn_blocking(move || list_files(&owned)).await??;
+    let host = drt.tcp_server().await?.local_ip().to_string();
+    let listener = TcpListener::bind((host.as_str(), 0)).await?;
+    let address = listener.local_addr()?.to_string();
+
+    let offer = Offer {
+        address: address.clone(),
+        revision: revision.to_string(),
+        is_file,
+        files,
+    };
+    let key = format!(
+        "{}{:x}",
+        model_prefix(&card.service_name, revision),
+        etcd_client.lease_id()
+    );
+    etcd_client
+        .kv_create(key, serde_json::to_vec(&offer)?, None)
+        .await?;
+    tracing::info!(
+        model = card.display_name,
+        revision,
+        address,
+        "Offering the model to other workers"
+    );
+
+    let paths = Arc::new(paths);
+    let cancel_token = drt.primary_token();
+    tokio::spawn(async move {
+        loop {
+            let (stream, peer) = tokio::select! {
+                _ = cancel_token.cancelled() => break,
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(err) => {
+                        tracing::warn!("Model file server failed to accept: {err}");
+                        continue;
+                    }
+                },
+            };
+            let paths = paths.clone();
+            tokio::spawn(async move {
+                let sent = match TransportTls::global() {
+                    Ok(Some(tls)) => match tls.accept(stream).await {
+                        Ok(stream) => send_file(stream, &paths).await,
+                        Err(err) => Err(err.into()),
+                    },
+                    Ok(None) => send_file(stream, &paths).await,
+                    Err(err) => Err(err),
+                };
+                if let Err(err) = sent {
+                    tracing::debug!(%peer, "Sending a model file failed: {err:#}");
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+/// The files of the model at `path`, and where each is. Links are followed, as in the Hugging
+/// Face cache. Hashes every file that isn't named after its SHA-256.
+type Listing = (bool, Vec<OfferedFile>, HashMap<String, PathBuf>);
+
+fn list_files(path: &Path) -> anyhow::Result<Listing> {
+    let mut files = vec![];
+    let mut paths = HashMap::new();
+    let mut add = |relative: String, full: PathBuf| -> anyhow::Result<()> {
+        let meta = std::fs::metadata(&full)?;
+        let modified = meta
+            .modified()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        files.push(OfferedFile {
+            path: relative.clone(),
+            size: meta.len(),
+            modified,
+            sha256: file_sha256(&full)?,
+        });
+        paths.insert(relative, full);
+        Ok(())
+    };
+    if path.is_file() {
+        let name = path
+            .file_name()
+            .context("Model file has no name")?
+            .to_string_lossy()
+            .into_owned();
+        add(name, path.to_path_buf())?;
+        return Ok((true, files, paths));
+    }
+
+    let mut dirs = vec![path.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let full = entry?.path();
+            if full.is_dir() {
+                dirs.push(full);
+                continue;
+            }
+            let name = full.to_string_lossy();
+            if name.ends_with(".incomplete")
+                || name.ends_with(".lock")
+                || name.ends_with(CHECKED_SUFFIX)
+            {
+                continue;
+            }
+            let Some(relative) = full
+                .strip_prefix(path)
+                .ok()
+                .and_then(|relative| relative.to_str())
+                .map(str::to_string)
+            else {
+                continue;
+            };
+            add(relative, full)?;
+        }
+    }
+    Ok((false, files, paths))
+}
+
+/// The SHA-256 of the file at `path`. Large files in the Hugging Face cache are links to a
+/// blob named after it, which the hub checked when it downloaded it.
+fn file_sha256(path: &Path) -> anyhow::Result<String> {
+    let target = std::fs::canonicalize(path)?;
+    let name = target.file_name().and_then(|name| name.to_str());
+    if let Some(name) = name.filter(|name| is_sha256(name)) {
+        return Ok(name.to_string());
+    }
+    hub::sha256_file(path)
+}
+
+fn is_sha256(s: &str) -> bool {
+    s.len() == 64 && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+async fn send_file<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    paths: &HashMap<String, PathBuf>,
+) -> anyhow::Result<()> {
+    let mut stream = BufReader::new(stream);
+    let mut line = String::new();
+    (&mut stream)
+        .take(MAX_REQUEST_LEN)
+        .read_line(&mut line)
+        .await?;
+    let request: FileRequest = serde_json::from_str(&line)?;
+    // Only what was offered
+    let path = paths
+        .get(&request.path)
+        .with_context(|| format!("{} is not a file of the model", request.path))?;
+    let mut file = tokio::fs::File::open(path).await?;
+    file.seek(std::io::SeekFrom::Start(request.start)).await?;
+    let mut stream = stream.into_inner();
+    tokio::io::copy(&mut file, &mut stream).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Copy the model called `model_name` at `revision` from a worker that offers it. None if none
+/// does, or none of them could send it.
+pub async fn fetch(model_name: &str, revision: &str) -> Option<PathBuf> {
+    let offers = match offers(model_name, revision).await {
+        Ok(offers) => offers,
+        Err(err) => {
+            tracing::warn!(
+                model_name,
+                "Could not look for workers with the model: {err:#}"
+            );
+            return None;
+        }
+    };
+    if offers.is_empty() {
+        tracing::debug!(model_name, revision, "No other worker has the model");
+        return None;
+    }
+    let local = match super::cache_dir() {
+        Ok(dir) => dir
+            .join("peers")
+            .join(ModelDeploymentCard::service_name_slug(model_name).to_string())
+            .join(revision_slug(revision)),
+        Err(err) => {
+            tracing::warn!("{err:#}");
+            return None;
+        }
+    };
+    for offer in offers {
+        match fetch_offer(model_name, &offer, &local).await {
+            Ok(path) => return Some(path),
+            Err(err) => {
+                tracing::warn!(
+                    model_name,
+                    peer = offer.address,
+                    "Copying the model from another worker failed: {err:#}"
+                );
+            }
+        }
+    }
+    None
+}
+
+/// The offers of `model_name` at `revision`, in random order so that new workers spread over
+/// them
+async fn offers(model_name: &str, revision: &str) -> anyhow::Result<Vec<Offer>> {
+    let options = etcd::ClientOptions {
+        attach_lease: false,
+        ..Default::default()
+    };
+    let etcd_client = etcd::Client::new(options, Runtime::from_current()?).await?;
+    let mut offers = etcd_client
+        .kv_get_prefix(model_prefix(model_name, revision))
+        .await?
+        .into_iter()
+        .filter_map(|kv| serde_json::from_slice::<Offer>(kv.value()).ok())
+        .filter(|offer| offer.revision == revision)
+        .collect::<Vec<_>>();
+    offers.shuffle(&mut rand::rng());
+    Ok(offers)
+}
+
+async fn fetch_offer(model_name: &str, offer: &Offer, local: &Path) -> anyhow::Result<PathBuf> {
+    let mut files = vec![];
+    for file in &offer.files {
+        let relative = super::safe_relative(&file.path)
+            .with_context(|| format!("Invalid file name {}", file.path))?;
+        files.push((file, local.join(relative)));
+    }
+    if files.is_empty() {
+        anyhow::bail!("No files");
+    }
+
+    let total = files.iter().map(|(file, _)| file.size).sum();
+    let progress = Progress::new(model_name, total);
+    futures::stream::iter(&files)
+        .map(|(file, dest)| fetch_file(&offer.address, file, dest, &progress))
+        .buffer_unordered(PARALLEL_FILES)
+        .try_collect::<Vec<()>>()
+        .await?;
+    progress.finish();
+
+    if offer.is_file {
+        Ok(files.swap_remove(0).1)
+    } else {
+        Ok(local.to_path_buf())
+    }
+}
+
+async fn fetch_file(
+    address: &str,
+    file: &OfferedFile,
+    dest: &Path,
+    progress: &Progress,
+) -> anyhow::Result<()> {
+    // Copied and checked against this hash before
+    let checked = hub::with_suffix(dest, CHECKED_SUFFIX);
+    let up_to_date = |path: &Path| {
+        std::fs::metadata(path).is_ok_and(|local| local.len() == file.size)
+            && std::fs::read_to_string(&checked).is_ok_and(|sha256| sha256.trim() == file.sha256)
+    };
+    if up_to_date(dest) {
+        progress.skip(file.size);
+        return Ok(());
+    }
+    let version = format!("{}-{}", file.size, file.modified);
+    let remote = RemoteFile {
+        dest,
+        size: file.size,
+        version: Some(&version),
+        sha256: Some(&file.sha256),
+    };
+    let open = |start| async move {
+        let request = FileRequest {
+            path: file.path.clone(),
+            start,
+        };
+        let stream = TcpStream::connect(address).await?;
+        let body = match TransportTls::global()? {
+            Some(tls) => request_file(tls.connect(stream).await?, &request).await?,
+            None => request_file(stream, &request).await?,
+        };
+        Ok::<_, anyhow::Error>((start, body))
+    };
+    download_file(remote, progress, open, &up_to_date)
+        .await
+        .with_context(|| format!("Failed to copy {}", file.path))?;
+    tokio::fs::write(&checked, &file.sha256).await?;
+    Ok(())
+}
+
+async fn request_file<S>(mut stream: S, request: &FileRequest) -> anyhow::Result<Body>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let mut line = serde_json::to_vec(request)?;
+    line.push(b'\n');
+    stream.write_all(&line).await?;
+    Ok(FramedRead::new(stream, BytesCodec::new())
+        .map_ok(BytesMut::freeze)
+        .map_err(anyhow::Error::new)
+        .boxed())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_list_files() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("onnx")).unwrap();
+        std::fs::write(dir.path().join("config.json"), "{}").unwrap();
+        std::fs::write(dir.path().join("onnx/model.onnx"), "1234").unwrap();
+        std::fs::write(dir.path().join("model.safetensors.incomplete"), "12").unwrap();
+
+        let (is_file, files, paths) = list_files(dir.path()).unwrap();
+        assert!(!is_file);
+        let mut sizes: Vec<_> = files.iter().map(|f| (f.path.as_str(), f.size)).collect();
+        sizes.sort();
+        assert_eq!(sizes, [("config.json", 2), ("onnx/model.onnx", 4)]);
+        assert_eq!(paths["onnx/model.onnx"], dir.path().join("onnx/model.onnx"));
+
+        let (is_file, files, _) = list_files(&dir.path().join("config.json")).unwrap();
+        assert!(is_file);
+        assert_eq!(files[0].path, "config.json");
+        assert_eq!(
+            files[0].sha256,
+            "44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a"
+        );
+    }
+
+    #[test]
+    fn test_model_prefix() {
+        let prefix = model_prefix("Qwen/Qwen3-0.6B", "sha256:abcd");
+        assert!(prefix.starts_with(ROOT_PATH));
+        assert!(prefix.ends_with("/sha256-abcd/"));
+        // Another revision's offers are not under it
+        assert!(!model_prefix("Qwen/Qwen3-0.6B", "c0ffee").starts_with(&prefix));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_blob_sha256() {
+        // Like the Hugging Face cache, the weights link to a blob named after their hash
+        let dir = tempfile::tempdir().unwrap();
+        let blob = "a".repeat(64);
+        std::fs::write(dir.path().join(&blob), "not hashed").unwrap();
+        std::os::unix::fs::symlink(dir.path().join(&blob), dir.path().join("model.gguf")).unwrap();
+        assert_eq!(file_sha256(&dir.path().join("model.gguf")).unwrap(), blob);
+    }
+
+    /// Serve `contents` as the file `model.bin` of a model in `dir`
+    async fn file_server(dir: &Path, contents: &str) -> String {
+        std::fs::write(dir.join("model.bin"), contents).unwrap();
+        let paths = HashMap::from([("model.bin".to_string(), dir.join("model.bin"))]);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let _ = send_file(stream, &paths).await;
+            }
+        });
+        address
+    }
+
+    #[tokio::test]
+    async fn test_fetch_file_checks_sha256() {
+        let served = tempfile::tempdir().unwrap();
+        let address = file_server(served.path(), "weights").await;
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("model.bin");
+        let progress = Progress::new("test", 7);
+        let offered = |sha256: &str| OfferedFile {
+            path: "model.bin".to_string(),
+            size: 7,
+            modified: 1,
+            sha256: sha256.to_string(),
+        };
+
+        // A copy that doesn't match the offer is thrown away
+        let wrong = offered(&"0".repeat(64));
+        assert!(fetch_file(&address, &wrong, &dest, &progress)
+            .await
+            .is_err());
+        assert!(!dest.exists());
+
+        let right = offered(
+            &hub::sha256_file(&{
+                let path = dir.path().join("expected");
+                std::fs::write(&path, "weights").unwrap();
+                path
+            })
+            .unwrap(),
+        );
+        fetch_file(&address, &right, &dest, &progress)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&dest).unwrap(), "weights");
+        let checked = hub::with_suffix(&dest, CHECKED_SUFFIX);
+        assert_eq!(std::fs::read_to_string(&checked).unwrap(), right.sha256);
+
+        // The right size isn't enough to skip the copy, it must have been checked against the
+        // same hash
+        std::fs::write(&dest, "corrupt").unwrap();
+        std::fs::remove_file(&checked).unwrap();
+        fetch_file(&address, &right, &dest, &progress)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&dest).unwrap(), "weights");
+    }
+}
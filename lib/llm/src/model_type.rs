@@ -24,6 +24,10 @@ pub enum ModelType {
     Completion,
     // Pre-processed requests
     Backend,
+    /// Embeddings API. Only engines that can run an embedding model register this.
+    Embedding,
+    /// Audio Transcriptions API, speech-to-text
+    Transcription,
 }
 
 impl ModelType {
@@ -32,10 +36,18 @@ impl ModelType {
             Self::Chat => "chat",
             Self::Completion => "completion",
             Self::Backend => "backend",
+            Self::Embedding => "embedding",
+            Self::Transcription => "transcription",
         }
     }
 
     pub fn all() -> Vec<Self> {
-        vec![Self::Chat, Self::Completion, Self::Backend]
+        vec![
+            Self::Chat,
+            Self::Completion,
+            Self::Backend,
+            Self::Embedding,
+            Self::Transcription,
+        ]
     }
 }
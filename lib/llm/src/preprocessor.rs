@@ -23,27 +23,49 @@
 //!
 //! The Preprocessor will accept any IngressRequest and transform it to a BackendRequest.
 
+pub mod fim;
+pub mod media;
+mod metrics;
+pub mod prefix_cache;
 pub mod prompt;
 pub mod tools;
+pub mod truncation;
 
 use anyhow::Result;
 use futures::stream::{self, StreamExt};
 use prompt::OAIPromptFormatter;
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Weak},
+};
 use tracing;
 
-use crate::model_card::model::{ModelDeploymentCard, ModelInfo, TokenizerKind};
+use crate::http::service::metrics::ENGINE_UNKNOWN;
+use crate::model_card::model::{ModelDeploymentCard, ModelInfo};
+use crate::preprocessor::fim::FimTokens;
+use crate::preprocessor::media::MediaError;
+use crate::preprocessor::prefix_cache::PrefixCache;
 use crate::preprocessor::prompt::OAIChatLikeRequest;
+use crate::preprocessor::truncation::{
+    context_overflow_error, drop_oldest_message, is_context_overflow, prompt_limit_error,
+    TrimmedChat, Truncation,
+};
+use crate::protocols::TokenIdType;
+use crate::tokenizers::pool::TokenizerPool;
 use crate::tokenizers::Encoding;
 
 use dynamo_runtime::engine::{AsyncEngine, AsyncEngineContextProvider, ResponseStream};
+use dynamo_runtime::pipeline::timings::{StageSpan, StageTimings, STAGE_TIMINGS_CONTEXT_KEY};
 use dynamo_runtime::pipeline::{
-    async_trait, AsyncEngineContext, Error, ManyOut, Operator, SingleIn,
+    async_trait, AsyncEngineContext, Context, Error, ManyOut, Operator, SingleIn,
 };
 use dynamo_runtime::protocols::annotated::{Annotated, AnnotationsProvider};
 
 use crate::protocols::{
-    common::{SamplingOptionsProvider, StopConditionsProvider},
+    common::{
+        sampling::SamplingValidator, OutputOptionsProvider, SamplingOptionsProvider,
+        StopConditionsProvider,
+    },
     openai::{
         chat_completions::{NvCreateChatCompletionRequest, NvCreateChatCompletionStreamResponse},
         completions::{CompletionRequest, CompletionResponse},
@@ -51,7 +73,7 @@ use crate::protocols::{
         DeltaGeneratorExt,
     },
 };
-use crate::tokenizers::{traits::Tokenizer, HuggingFaceTokenizer};
+use crate::tokenizers::traits::Tokenizer;
 
 use crate::preprocessor::prompt::PromptFormatter;
 
@@ -60,31 +82,68 @@ pub use crate::protocols::common::llm_backend::{BackendInput, BackendOutput};
 pub const ANNOTATION_FORMATTED_PROMPT: &str = "formatted_prompt";
 pub const ANNOTATION_TOKEN_IDS: &str = "token_ids";
 
+/// Set to `1` or `true` to log every prompt after the chat template is applied, for debugging
+/// templates. Logs what users send, so not for production.
+pub const PRINT_PROMPT_ENV: &str = "DYN_PRINT_PROMPT";
+
+/// Request context key of the most tokens the prompt can have, a `usize` the HTTP service
+/// sets from its request limits. Longer prompts are rejected with a 400.
+pub const MAX_PROMPT_TOKENS_CONTEXT_KEY: &str = "max_prompt_tokens";
+
+/// Stage timing applying the prompt template and tokenizing, see [`StageTimings`]
+pub const TOKENIZE_STAGE: &str = "tokenize";
+
 pub struct OpenAIPreprocessor {
     mdcsum: String,
+    model: String,
+    /// The card's engine, for the `engine` label of the latency metrics
+    engine: String,
     formatter: Arc<dyn OAIPromptFormatter>,
     tokenizer: Arc<dyn Tokenizer>,
     model_info: Arc<dyn ModelInfo>,
+    /// config.json's eos tokens and the card's other stop tokens
+    eos_token_ids: Vec<TokenIdType>,
+    /// The card's context length if it has one, otherwise the model's
+    context_length: usize,
+    /// Checks the sampling options against what the engine supports
+    sampling: SamplingValidator,
+    /// None if the model can't fill in the middle
+    fim: Option<FimTokens>,
+    /// What to do with prompts that don't fit in the context
+    truncation: Truncation,
+    /// Whether to truncate harder and retry when the engine says the prompt is too long
+    retry_overflow: bool,
+    /// Log each rendered prompt, see [`PRINT_PROMPT_ENV`]
+    print_prompt: bool,
+    /// Tokens of chat prefixes seen before, None if turned off
+    prefix_cache: Option<PrefixCache>,
+    /// Ourselves, to hand to the tokenizer threads
+    this: Weak<Self>,
 }
 
 impl OpenAIPreprocessor {
     pub async fn new(mdc: ModelDeploymentCard) -> Result<Arc<Self>> {
         let mdcsum = mdc.mdcsum();
+        let model = mdc.display_name.clone();
+        let engine = mdc
+            .engine
+            .clone()
+            .unwrap_or_else(|| ENGINE_UNKNOWN.to_string());
+        let truncation = Truncation::from_env()?;
         let formatter = PromptFormatter::from_mdc(mdc.clone()).await?;
         let PromptFormatter::OAI(formatter) = formatter;
 
         let tokenizer = match &mdc.tokenizer {
-            Some(TokenizerKind::HfTokenizerJson(file)) => HuggingFaceTokenizer::from_file(file)?,
-            Some(TokenizerKind::GGUF(tokenizer)) => {
-                HuggingFaceTokenizer::from_tokenizer(*tokenizer.clone())
-            }
+            Some(kind) => kind.load()?,
             None => {
                 anyhow::bail!(
                     "Blank ModelDeploymentCard cannot be used for pre-processing, no tokenizer"
                 );
             }
         };
-        let tokenizer = Arc::new(tokenizer);
+        let fim = FimTokens::detect(|token| tokenizer.token_to_id(token));
+        let sampling = SamplingValidator::from_env(mdc.engine.as_deref())?
+            .with_banned_words_from_env(tokenizer.as_ref())?;
 
         let Some(model_info) = mdc.model_info else {
             anyhow::bail!(
@@ -92,22 +151,211 @@ impl OpenAIPreprocessor {
             );
         };
         let model_info = model_info.get_model_info().await?;
+        let context_length = match mdc.context_length {
+            Some(context_length) => context_length as usize,
+            None => model_info.max_position_embeddings(),
+        };
+
+        let mut eos_token_ids = model_info.eos_token_ids();
+        for token_id in mdc.stop_token_ids {
+            if !eos_token_ids.contains(&token_id) {
+                eos_token_ids.push(token_id);
+            }
+        }
 
-        Ok(Arc::new(Self {
+        Ok(Arc::new_cyclic(|this| Self {
             formatter,
             tokenizer,
             model_info,
+            eos_token_ids,
+            context_length,
+            sampling,
+            fim,
+            truncation,
+            retry_overflow: Truncation::retry_from_env(),
+            print_prompt: std::env::var(PRINT_PROMPT_ENV)
+                .map(|val| matches!(val.trim().to_lowercase().as_str(), "1" | "true"))
+                .unwrap_or(false),
+            prefix_cache: PrefixCache::from_env()?,
+            this: this.clone(),
             mdcsum,
+            model,
+            engine,
         }))
     }
 
+    /// Empty the cache of chat prefix tokens, returns how many prefixes it held. 0 when the
+    /// cache is off.
+    pub fn flush_prefix_cache(&self) -> usize {
+        self.prefix_cache.as_ref().map_or(0, PrefixCache::clear)
+    }
+
     /// Encode a string to it's tokens
     pub fn tokenize(&self, s: &str) -> anyhow::Result<Encoding> {
         self.tokenizer.encode(s)
     }
 
+    /// Decode token ids back to text
+    pub fn detokenize(
+        &self,
+        token_ids: &[TokenIdType],
+        skip_special_tokens: bool,
+    ) -> anyhow::Result<String> {
+        self.tokenizer.decode(token_ids, skip_special_tokens)
+    }
+
+    /// Maximum number of tokens the model accepts, prompt and completion together
+    pub fn context_length(&self) -> usize {
+        self.context_length
+    }
+
+    /// Whether the model accepts images as well as text
+    pub fn is_multimodal(&self) -> bool {
+        self.model_info.is_multimodal()
+    }
+
+    /// Apply the prompt template to a request and tokenize the result, exactly as
+    /// [`OpenAIPreprocessor::preprocess_request`] would, but without building a backend request.
+    ///
+    /// Returns the formatted prompt, or None if the request was already tokenized, and the token ids.
+    pub fn tokenize_request<R: OAIChatLikeRequest + NvExtProvider>(
+        &self,
+        request: &R,
+    ) -> Result<(Option<String>, Vec<TokenIdType>)> {
+        if !request.image_urls().is_empty() && !self.model_info.is_multimodal() {
+            return Err(MediaError::image_not_supported(&self.model_info.model_type()).into());
+        }
+        if let Some((prefix, suffix)) = request.fill_in_the_middle()? {
+            let Some(fim) = &self.fim else {
+                anyhow::bail!(
+                    "Model '{}' does not support fill-in-the-middle, suffix must not be set",
+                    self.model
+                );
+            };
+            let token_ids = tokio::task::block_in_place(|| -> Result<_> {
+                let prefix = self.tokenizer.encode(&prefix)?.token_ids;
+                let suffix = self.tokenizer.encode(&suffix)?.token_ids;
+                Ok(fim.prompt(&prefix, &suffix))
+            })?;
+            let formatted_prompt = self.tokenizer.decode(&token_ids, false)?;
+            return Ok((Some(formatted_prompt), token_ids));
+        }
+        if let Some(token_ids) = request.prompt_token_ids()? {
+            return Ok((None, token_ids));
+        }
+        let raw_prompt = if request.use_raw_prompt() {
+            let prompt = request.raw_prompt();
+            if prompt.is_none() {
+                tracing::warn!("Raw prompt requested but not available");
+            }
+            prompt
+        } else {
+            None
+        };
+        let rendered = raw_prompt.is_none();
+        let formatted_prompt = match raw_prompt {
+            Some(prompt) => prompt,
+            None => self.formatter.render(request)?,
+        };
+        let token_ids = tokio::task::block_in_place(|| match &self.prefix_cache {
+            Some(cache) if rendered => cache.encode(
+                request,
+                &formatted_prompt,
+                self.formatter.as_ref(),
+                self.tokenizer.as_ref(),
+            ),
+            _ => Ok(self.tokenizer.encode(&formatted_prompt)?.token_ids),
+        })?;
+        Ok((Some(formatted_prompt), token_ids))
+    }
+
+    /// Render the chat without its oldest messages, one more each time, until it fits in
+    /// `budget` tokens or only the system prompt and the last message are left. Returns the
+    /// prompt, its tokens and how many messages were dropped, None if the prompt isn't
+    /// rendered from the messages or none could be dropped.
+    fn drop_messages<R: OAIChatLikeRequest + NvExtProvider>(
+        &self,
+        request: &R,
+        budget: usize,
+    ) -> Result<Option<(String, Vec<TokenIdType>, usize)>> {
+        let from_template = request.fill_in_the_middle()?.is_none()
+            && request.prompt_token_ids()?.is_none()
+            && !(request.use_raw_prompt() && request.raw_prompt().is_some());
+        // Each image has a placeholder in its message, they have to stay in step
+        if !from_template || !request.image_urls().is_empty() {
+            return Ok(None);
+        }
+        let Ok(mut messages) = serde_json::from_value::<Vec<serde_json::Value>>(
+            serde_json::to_value(request.messages())?,
+        ) else {
+            return Ok(None);
+        };
+
+        let mut dropped = 0;
+        let mut trimmed = None;
+        while drop_oldest_message(&mut messages) {
+            dropped += 1;
+            let chat = TrimmedChat::new(request, &messages);
+            let prompt = self.formatter.render(&chat)?;
+            let token_ids =
+                tokio::task::block_in_place(|| self.tokenizer.encode(&prompt))?.token_ids;
+            let fits = token_ids.len() <= budget;
+            trimmed = Some((prompt, token_ids, dropped));
+            if fits {
+                break;
+            }
+        }
+        Ok(trimmed)
+    }
+
+    /// [`OpenAIPreprocessor::preprocess_request`] on the [`TokenizerPool`], so that a long
+    /// prompt doesn't hold up the tokio worker. Hands the request back with the result.
+    pub async fn preprocess_on_pool<R>(
+        &self,
+        request: R,
+    ) -> Result<(R, BackendInput, HashMap<String, String>, Vec<String>)>
+    where
+        R: OAIChatLikeRequest
+            + AnnotationsProvider
+            + SamplingOptionsProvider
+            + StopConditionsProvider
+            + OutputOptionsProvider
+            + NvExtProvider
+            + Send
+            + 'static,
+    {
+        let this = self
+            .this
+            .upgrade()
+            .ok_or_else(|| anyhow::anyhow!("Pre-processor of {} was dropped", self.model))?;
+        TokenizerPool::global()
+            .run(move || {
+                let (common_request, annotations, warnings) = this.preprocess_request(&request)?;
+                Ok((request, common_request, annotations, warnings))
+            })
+            .await?
+    }
+
+    /// [`OpenAIPreprocessor::tokenize_request`] on the [`TokenizerPool`]
+    pub async fn tokenize_on_pool<R>(
+        &self,
+        request: R,
+    ) -> Result<(Option<String>, Vec<TokenIdType>)>
+    where
+        R: OAIChatLikeRequest + NvExtProvider + Send + 'static,
+    {
+        let this = self
+            .this
+            .upgrade()
+            .ok_or_else(|| anyhow::anyhow!("Pre-processor of {} was dropped", self.model))?;
+        TokenizerPool::global()
+            .run(move || this.tokenize_request(&request))
+            .await?
+    }
+
     /// Translate a [`NvCreateChatCompletionRequest`] request to a common completion request.
-    /// Returns both the common completion request and a hashmap of annotations.
+    /// Returns the common completion request, a hashmap of annotations, and warnings for the
+    /// client about changes made to the sampling options.
     ///
     /// Annotations evaluated by this method include:
     /// - `formatted_prompt`
@@ -117,68 +365,159 @@ impl OpenAIPreprocessor {
             + AnnotationsProvider
             + SamplingOptionsProvider
             + StopConditionsProvider
+            + OutputOptionsProvider
             + NvExtProvider,
     >(
         &self,
         request: &R,
-    ) -> Result<(BackendInput, HashMap<String, String>)> {
+    ) -> Result<(BackendInput, HashMap<String, String>, Vec<String>)> {
+        let _span = tracing::info_span!("preprocess", model = %self.model).entered();
         let mut annotations = HashMap::new();
         let mut builder = BackendInput::builder();
 
-        let use_raw_prompt = request
-            .nvext()
-            .is_some_and(|ext| ext.use_raw_prompt.unwrap_or(false));
+        let (mut formatted_prompt, mut token_ids) = self.tokenize_request(request)?;
 
-        let formatted_prompt = if use_raw_prompt {
-            match request.raw_prompt() {
-                Some(prompt) => prompt,
-                None => {
-                    tracing::warn!("Raw prompt requested but not available");
-                    self.formatter.render(request)?
+        // Leave room for the completion. An unknown context length is not a limit.
+        let mut stop_conditions = request.extract_stop_conditions()?;
+        let context_length = self.context_length();
+        let max_tokens = stop_conditions.max_tokens.unwrap_or(0) as usize;
+        let budget = context_length.saturating_sub(max_tokens).max(1);
+        let mut truncated = Vec::new();
+        if context_length > 0 && token_ids.len() > budget {
+            match self.truncation {
+                Truncation::Reject => {
+                    return Err(context_overflow_error(
+                        token_ids.len(),
+                        max_tokens,
+                        context_length,
+                    ));
+                }
+                Truncation::Messages => {
+                    if let Some((prompt, ids, dropped)) = self.drop_messages(request, budget)? {
+                        truncated.push(format!(
+                            "dropped the {dropped} oldest messages, from {} to {} tokens, to \
+                             fit the context",
+                            token_ids.len(),
+                            ids.len()
+                        ));
+                        formatted_prompt = Some(prompt);
+                        token_ids = ids;
+                    }
                 }
+                _ => {}
             }
-        } else {
-            self.formatter.render(request)?
-        };
+            truncated.extend(self.truncation.apply(&mut token_ids, budget));
+        }
 
-        let encoding = tokio::task::block_in_place(|| self.tokenizer.encode(&formatted_prompt))?;
+        if self.print_prompt {
+            if let Some(prompt) = &formatted_prompt {
+                tracing::info!(tokens = token_ids.len(), "Rendered prompt:\n{prompt}");
+            }
+        }
 
         if request.has_annotation(ANNOTATION_FORMATTED_PROMPT) {
-            annotations.insert(ANNOTATION_FORMATTED_PROMPT.to_string(), formatted_prompt);
+            if let Some(formatted_prompt) = formatted_prompt {
+                annotations.insert(ANNOTATION_FORMATTED_PROMPT.to_string(), formatted_prompt);
+            }
         }
 
         if request.has_annotation(ANNOTATION_TOKEN_IDS) {
             annotations.insert(
                 ANNOTATION_TOKEN_IDS.to_string(),
-                serde_json::to_string(&encoding.token_ids)?,
+                serde_json::to_string(&token_ids)?,
             );
         }
 
-        let mut stop_conditions = request.extract_stop_conditions()?;
         if let Some(stop_tokens) = &mut stop_conditions.stop_token_ids_hidden {
-            for eos_token in self.model_info.eos_token_ids() {
-                if !stop_tokens.contains(&eos_token) {
-                    stop_tokens.push(eos_token);
+            for eos_token in &self.eos_token_ids {
+                if !stop_tokens.contains(eos_token) {
+                    stop_tokens.push(*eos_token);
                 }
             }
         } else {
-            stop_conditions.stop_token_ids_hidden = Some(self.model_info.eos_token_ids());
+            stop_conditions.stop_token_ids_hidden = Some(self.eos_token_ids.clone());
+        }
+        // Some FIM models end the middle with a token of their own
+        if let Some(end) = self.fim.as_ref().and_then(FimTokens::end_token_id) {
+            if request.fill_in_the_middle()?.is_some() {
+                let stop_tokens = stop_conditions
+                    .stop_token_ids_hidden
+                    .get_or_insert_default();
+                if !stop_tokens.contains(&end) {
+                    stop_tokens.push(end);
+                }
+            }
         }
 
         // apply ignore eos if not already set
         stop_conditions.apply_ignore_eos();
 
         if !stop_conditions.ignore_eos.unwrap_or(false) {
-            builder.eos_token_ids(self.model_info.eos_token_ids());
+            builder.eos_token_ids(self.eos_token_ids.clone());
         }
 
-        builder.token_ids(encoding.token_ids);
-        builder.sampling_options(request.extract_sampling_options()?);
+        builder.token_ids(token_ids);
+        let mut sampling_options = request.extract_sampling_options()?;
+        let mut warnings = self.sampling.validate(&mut sampling_options)?;
+        warnings.extend(truncated);
+        builder.sampling_options(sampling_options);
         builder.stop_conditions(stop_conditions);
+        builder.output_options(request.extract_output_options()?);
         builder.annotations(request.annotations().unwrap_or_default());
         builder.mdc_sum(Some(self.mdcsum.clone()));
+        builder.lora(request.nvext().and_then(|ext| ext.lora.clone()));
+        builder.priority(
+            request
+                .nvext()
+                .and_then(|ext| ext.priority)
+                .unwrap_or_default(),
+        );
+
+        Ok((builder.build()?, annotations, warnings))
+    }
 
-        Ok((builder.build()?, annotations))
+    /// Send `request` to the engine. If it rejects the prompt as too long and retries are on,
+    /// send it once more with the prompt cut by a quarter. Returns the response stream, and if
+    /// we retried, a warning for the client and the new prompt length.
+    async fn generate_backend(
+        &self,
+        request: SingleIn<BackendInput>,
+        next: &Arc<
+            dyn AsyncEngine<SingleIn<BackendInput>, ManyOut<Annotated<BackendOutput>>, Error>,
+        >,
+    ) -> Result<(ManyOut<Annotated<BackendOutput>>, Option<(String, usize)>), Error> {
+        if !self.retry_overflow || !self.truncation.truncates() {
+            return Ok((next.generate(request).await?, None));
+        }
+        let mut retry = request.fork((*request).clone());
+
+        // The rejection comes back as an error, or as the first response
+        let error = match next.generate(request).await {
+            Err(err) if is_context_overflow(&format!("{err:#}")) => format!("{err:#}"),
+            Err(err) => return Err(err),
+            Ok(mut stream) => {
+                let first = stream.next().await;
+                let error = first
+                    .as_ref()
+                    .filter(|response| response.is_error())
+                    .map(|response| response.comment.clone().unwrap_or_default().join(", "));
+                match error {
+                    Some(error) if is_context_overflow(&error) => error,
+                    _ => {
+                        let context = stream.context();
+                        let stream = stream::iter(first).chain(stream);
+                        return Ok((ResponseStream::new(Box::pin(stream), context), None));
+                    }
+                }
+            }
+        };
+
+        let budget = retry.token_ids.len() * 3 / 4;
+        let Some(warning) = self.truncation.apply(&mut retry.token_ids, budget) else {
+            anyhow::bail!(error);
+        };
+        tracing::debug!(%error, "Engine rejected the prompt, retrying with {budget} tokens");
+        Ok((next.generate(retry).await?, Some((warning, budget))))
     }
 
     pub fn transform_postprocessor_stream<Resp: Send + Sync + 'static + std::fmt::Debug>(
@@ -260,6 +599,25 @@ impl OpenAIPreprocessor {
 // any prompt template that does not support this should return an error
 // oob - we should update any prompt template that does not support this to support it
 
+/// Times preprocessing the request, if the client asked for timings
+fn tokenize_span<T: Send + Sync + 'static>(context: &Context<T>) -> Option<StageSpan> {
+    context
+        .get::<StageTimings>(STAGE_TIMINGS_CONTEXT_KEY)
+        .ok()
+        .map(|timings| timings.span(TOKENIZE_STAGE))
+}
+
+/// Reject a prompt over the limit of [`MAX_PROMPT_TOKENS_CONTEXT_KEY`], if the request has one
+fn check_prompt_limit<T: Send + Sync + 'static>(
+    context: &Context<T>,
+    prompt_tokens: usize,
+) -> Result<()> {
+    match context.get::<usize>(MAX_PROMPT_TOKENS_CONTEXT_KEY) {
+        Ok(max) if prompt_tokens > *max => Err(prompt_limit_error(prompt_tokens, *max)),
+        _ => Ok(()),
+    }
+}
+
 #[async_trait]
 impl
     Operator<
@@ -284,10 +642,16 @@ impl
         let mut response_generator = Box::new(response_generator);
 
         // convert the chat completion request to a common completion request
-        let (common_request, annotations) = self.preprocess_request(&request)?;
+        let span = tokenize_span(&context);
+        let (request, mut common_request, annotations, mut warnings) =
+            self.preprocess_on_pool(request).await?;
+        drop(span);
+        check_prompt_limit(&context, common_request.token_ids.len())?;
 
-        // update isl
-        response_generator.update_isl(common_request.token_ids.len() as u32);
+        // fetch the images the prompt refers to
+        common_request.images = media::load_images(&request.image_urls()).await?;
+
+        response_generator.set_seed(common_request.sampling_options.seed);
 
         // repack the common completion request
         let common_request = context.map(|_| common_request);
@@ -300,7 +664,18 @@ impl
         let annotations_stream = stream::iter(annotations);
 
         // forward the common completion request to the next operator
-        let response_stream = next.generate(common_request).await?;
+        let mut isl = common_request.token_ids.len();
+        let observe = metrics::start_request(&self.model, &self.engine, isl);
+        let (response_stream, truncated) = self.generate_backend(common_request, &next).await?;
+        let response_stream = observe(response_stream);
+        if let Some((warning, len)) = truncated {
+            warnings.push(warning);
+            isl = len;
+        }
+
+        // update isl
+        response_generator.update_isl(isl as u32);
+        response_generator.set_warnings(warnings);
 
         // transform the postprocessor stream
         let stream = Self::transform_postprocessor_stream(response_stream, response_generator);
@@ -337,10 +712,13 @@ impl
         let response_generator = request.response_generator();
         let mut response_generator = Box::new(response_generator);
         // convert the chat completion request to a common completion request
-        let (common_request, annotations) = self.preprocess_request(&request)?;
+        let span = tokenize_span(&context);
+        let (_, common_request, annotations, mut warnings) =
+            self.preprocess_on_pool(request).await?;
+        drop(span);
+        check_prompt_limit(&context, common_request.token_ids.len())?;
 
-        // update isl
-        response_generator.update_isl(common_request.token_ids.len() as i32);
+        response_generator.set_seed(common_request.sampling_options.seed);
 
         // repack the common completion request
         let common_request = context.map(|_| common_request);
@@ -353,7 +731,18 @@ impl
         let annotations_stream = stream::iter(annotations);
 
         // forward the common completion request to the next operator
-        let response_stream = next.generate(common_request).await?;
+        let mut isl = common_request.token_ids.len();
+        let observe = metrics::start_request(&self.model, &self.engine, isl);
+        let (response_stream, truncated) = self.generate_backend(common_request, &next).await?;
+        let response_stream = observe(response_stream);
+        if let Some((warning, len)) = truncated {
+            warnings.push(warning);
+            isl = len;
+        }
+
+        // update isl
+        response_generator.update_isl(isl as i32);
+        response_generator.set_warnings(warnings);
 
         // transform the postprocessor stream
         let stream = Self::transform_postprocessor_stream(response_stream, response_generator);
@@ -0,0 +1,181 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Fill-in-the-middle prompts
+//!
+//! Code models trained for infilling take the code before and after the cursor separated by
+//! special tokens, and generate what goes in between. Which tokens, and in which order, depends
+//! on the model family. We recognize the family from the tokenizer's vocabulary.
+
+use crate::protocols::TokenIdType;
+
+/// Order of the parts in the prompt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Order {
+    /// `<prefix> prefix <suffix> suffix <middle>`
+    PrefixSuffixMiddle,
+    /// `<suffix> suffix <prefix> prefix`
+    SuffixPrefix,
+}
+
+struct Family {
+    name: &'static str,
+    prefix: &'static str,
+    suffix: &'static str,
+    middle: Option<&'static str>,
+    /// Ends the middle, when the model doesn't use its eos token for that
+    end: Option<&'static str>,
+    order: Order,
+}
+
+const FAMILIES: &[Family] = &[
+    Family {
+        name: "starcoder",
+        prefix: "<fim_prefix>",
+        suffix: "<fim_suffix>",
+        middle: Some("<fim_middle>"),
+        end: None,
+        order: Order::PrefixSuffixMiddle,
+    },
+    // Qwen2.5-Coder and CodeGemma
+    Family {
+        name: "qwen",
+        prefix: "<|fim_prefix|>",
+        suffix: "<|fim_suffix|>",
+        middle: Some("<|fim_middle|>"),
+        end: None,
+        order: Order::PrefixSuffixMiddle,
+    },
+    Family {
+        name: "deepseek-coder",
+        prefix: "<｜fim▁begin｜>",
+        suffix: "<｜fim▁hole｜>",
+        middle: Some("<｜fim▁end｜>"),
+        end: None,
+        order: Order::PrefixSuffixMiddle,
+    },
+    Family {
+        name: "codellama",
+        prefix: "▁<PRE>",
+        suffix: "▁<SUF>",
+        middle: Some("▁<MID>"),
+        end: Some("▁<EOT>"),
+        order: Order::PrefixSuffixMiddle,
+    },
+    // Codestral
+    Family {
+        name: "mistral",
+        prefix: "[PREFIX]",
+        suffix: "[SUFFIX]",
+        middle: None,
+        end: None,
+        order: Order::SuffixPrefix,
+    },
+];
+
+/// The fill-in-the-middle tokens of a model
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FimTokens {
+    pub name: &'static str,
+    prefix: TokenIdType,
+    suffix: TokenIdType,
+    middle: Option<TokenIdType>,
+    end: Option<TokenIdType>,
+    order: Order,
+}
+
+impl FimTokens {
+    /// The tokens of the first known model family whose tokens are all in the vocabulary, or
+    /// None if the model doesn't do fill-in-the-middle.
+    pub fn detect(token_to_id: impl Fn(&str) -> Option<TokenIdType>) -> Option<Self> {
+        FAMILIES.iter().find_map(|family| {
+            let middle = match family.middle {
+                Some(token) => Some(token_to_id(token)?),
+                None => None,
+            };
+            Some(FimTokens {
+                name: family.name,
+                prefix: token_to_id(family.prefix)?,
+                suffix: token_to_id(family.suffix)?,
+                middle,
+                end: family.end.and_then(&token_to_id),
+                order: family.order,
+            })
+        })
+    }
+
+    /// The prompt asking the model for what goes between `prefix` and `suffix`, both
+    /// already tokenized
+    pub fn prompt(&self, prefix: &[TokenIdType], suffix: &[TokenIdType]) -> Vec<TokenIdType> {
+        let mut token_ids = Vec::with_capacity(prefix.len() + suffix.len() + 3);
+        match self.order {
+            Order::PrefixSuffixMiddle => {
+                token_ids.push(self.prefix);
+                token_ids.extend_from_slice(prefix);
+                token_ids.push(self.suffix);
+                token_ids.extend_from_slice(suffix);
+            }
+            Order::SuffixPrefix => {
+                token_ids.push(self.suffix);
+                token_ids.extend_from_slice(suffix);
+                token_ids.push(self.prefix);
+                token_ids.extend_from_slice(prefix);
+            }
+        }
+        token_ids.extend(self.middle);
+        token_ids
+    }
+
+    /// Token ending the middle, to stop on in addition to the eos tokens
+    pub fn end_token_id(&self) -> Option<TokenIdType> {
+        self.end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vocab(tokens: &[(&str, TokenIdType)]) -> impl Fn(&str) -> Option<TokenIdType> + '_ {
+        move |token: &str| tokens.iter().find(|(t, _)| *t == token).map(|(_, id)| *id)
+    }
+
+    #[test]
+    fn test_fim_tokens() {
+        assert_eq!(FimTokens::detect(vocab(&[("<fim_prefix>", 1)])), None);
+
+        let qwen = FimTokens::detect(vocab(&[
+            ("<|fim_prefix|>", 1),
+            ("<|fim_suffix|>", 2),
+            ("<|fim_middle|>", 3),
+        ]))
+        .unwrap();
+        assert_eq!(qwen.name, "qwen");
+        assert_eq!(qwen.prompt(&[10, 11], &[20]), vec![1, 10, 11, 2, 20, 3]);
+        assert_eq!(qwen.end_token_id(), None);
+
+        let codestral = FimTokens::detect(vocab(&[("[PREFIX]", 1), ("[SUFFIX]", 2)])).unwrap();
+        assert_eq!(codestral.prompt(&[10, 11], &[20]), vec![2, 20, 1, 10, 11]);
+
+        let codellama = FimTokens::detect(vocab(&[
+            ("▁<PRE>", 1),
+            ("▁<SUF>", 2),
+            ("▁<MID>", 3),
+            ("▁<EOT>", 4),
+        ]))
+        .unwrap();
+        assert_eq!(codellama.end_token_id(), Some(4));
+    }
+}
@@ -0,0 +1,413 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Images in chat requests
+//!
+//! Chat messages can carry `image_url` content parts, either a `data:` URL with the image in
+//! base64 or an `http(s)` URL. The pre-processor loads them here and attaches them to the
+//! [`BackendInput`](super::BackendInput), so that engines never have to reach the network.
+//! The images are not decoded to pixels, only checked to be a known image format. Each engine
+//! decodes them in the way its vision model needs.
+//!
+//! Fetching `http(s)` URLs makes the server connect wherever a client asks, so it is off
+//! unless [`IMAGE_FETCH_ENV`] turns it on, see [`ImageFetch`].
+
+use std::fmt::{self, Display};
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+
+use base64::Engine as _;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+
+use crate::protocols::common::preprocessor::ImageData;
+
+/// Largest image we accept, encoded
+pub const MAX_IMAGE_BYTES: usize = 20 * 1024 * 1024;
+
+/// Which `http(s)` image URLs are fetched, see [`ImageFetch`]
+pub const IMAGE_FETCH_ENV: &str = "DYN_IMAGE_FETCH";
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+const MAX_REDIRECTS: usize = 5;
+
+static IMAGE_FETCH: LazyLock<ImageFetch> = LazyLock::new(|| {
+    ImageFetch::from_env().unwrap_or_else(|err| {
+        tracing::error!("{err:#}, not fetching image URLs");
+        ImageFetch::Off
+    })
+});
+
+static HTTP_CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| http_client(false));
+
+static PUBLIC_HTTP_CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| http_client(true));
+
+/// Which `http(s)` image URLs the pre-processor fetches. `data:` URLs are always accepted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ImageFetch {
+    /// None, requests with such a URL fail
+    #[default]
+    Off,
+
+    /// Those of hosts on public addresses. Loopback, private, link-local (where cloud metadata
+    /// services live) and other special addresses are refused, after DNS resolution and on
+    /// every redirect.
+    Public,
+
+    /// Any, for a server only trusted clients reach
+    Any,
+}
+
+impl ImageFetch {
+    /// Read from [`IMAGE_FETCH_ENV`], [`ImageFetch::Off`] if it is not set
+    pub fn from_env() -> anyhow::Result<Self> {
+        match std::env::var(IMAGE_FETCH_ENV) {
+            Ok(s) if !s.is_empty() => s
+                .parse()
+                .map_err(|err| anyhow::anyhow!("Invalid {IMAGE_FETCH_ENV}: {err}")),
+            _ => Ok(ImageFetch::default()),
+        }
+    }
+}
+
+impl FromStr for ImageFetch {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "off" => Ok(ImageFetch::Off),
+            "public" => Ok(ImageFetch::Public),
+            "any" => Ok(ImageFetch::Any),
+            _ => anyhow::bail!("'{s}' is not one of off, public, any"),
+        }
+    }
+}
+
+impl Display for ImageFetch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ImageFetch::Off => "off",
+            ImageFetch::Public => "public",
+            ImageFetch::Any => "any",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A client for fetching images. A `public_only` one never connects to an address that is not
+/// [`is_public`].
+fn http_client(public_only: bool) -> reqwest::Client {
+    let mut builder = reqwest::Client::builder().timeout(FETCH_TIMEOUT);
+    if public_only {
+        // Host names are checked as they are resolved, addresses in URLs here. A proxy would
+        // connect for us, wherever it is asked to.
+        builder = builder
+            .no_proxy()
+            .dns_resolver(Arc::new(PublicResolver))
+            .redirect(reqwest::redirect::Policy::custom(|attempt| {
+                if attempt.previous().len() >= MAX_REDIRECTS {
+                    attempt.error("too many redirects")
+                } else if attempt.url().host_str().is_some_and(is_private_host) {
+                    attempt.error("redirected to a non-public address")
+                } else {
+                    attempt.follow()
+                }
+            }));
+    }
+    builder
+        .build()
+        .expect("Failed to build HTTP client for image fetching")
+}
+
+/// Resolves host names to their public addresses only, failing if they have none
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let host = name.as_str().to_string();
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{host} has no public address").into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Whether `host`, from a URL, is an address that is not [`is_public`]. Names are checked by
+/// [`PublicResolver`].
+fn is_private_host(host: &str) -> bool {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    host.parse::<IpAddr>().is_ok_and(|ip| !is_public(ip))
+}
+
+/// Whether `ip` is a unicast address on the internet, as opposed to this host, a private or
+/// link-local network, or a reserved range
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                // Carrier-grade NAT, 100.64.0.0/10
+                || (a == 100 && (64..128).contains(&b))
+                // Reserved and benchmarking, 240.0.0.0/4 and 198.18.0.0/15
+                || a >= 240
+                || (a == 198 && (b == 18 || b == 19))
+                || a == 0)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                !(ip.is_unspecified()
+                    || ip.is_loopback()
+                    || ip.is_multicast()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local())
+            }
+        },
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum MediaError {
+    #[error("Model {model} does not support {modality} input")]
+    ModalityNotSupported { model: String, modality: String },
+
+    #[error("Invalid image: {0}")]
+    InvalidImage(String),
+
+    #[error("Failed to fetch image from {url}: {reason}")]
+    Fetch { url: String, reason: String },
+}
+
+impl MediaError {
+    pub fn image_not_supported(model: &str) -> Self {
+        MediaError::ModalityNotSupported {
+            model: model.to_string(),
+            modality: "image".to_string(),
+        }
+    }
+}
+
+impl ImageData {
+    /// Wrap an encoded image, checking that it is in a format we recognise
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MediaError> {
+        if bytes.len() > MAX_IMAGE_BYTES {
+            return Err(MediaError::InvalidImage(format!(
+                "{} bytes is larger than the {MAX_IMAGE_BYTES} bytes limit",
+                bytes.len()
+            )));
+        }
+        let Some(mime_type) = sniff_mime_type(bytes) else {
+            return Err(MediaError::InvalidImage(
+                "not a PNG, JPEG, GIF, WebP or BMP image".to_string(),
+            ));
+        };
+        Ok(ImageData {
+            mime_type: mime_type.to_string(),
+            data: base64::engine::general_purpose::STANDARD.encode(bytes),
+        })
+    }
+
+    /// The encoded image
+    pub fn bytes(&self) -> Result<Vec<u8>, MediaError> {
+        base64::engine::general_purpose::STANDARD
+            .decode(&self.data)
+            .map_err(|err| MediaError::InvalidImage(err.to_string()))
+    }
+}
+
+/// Load the image an `image_url` content part points at, fetching it as [`IMAGE_FETCH_ENV`]
+/// allows
+pub async fn load_image(url: &str) -> Result<ImageData, MediaError> {
+    load_image_with(url, *IMAGE_FETCH).await
+}
+
+async fn load_image_with(url: &str, fetch: ImageFetch) -> Result<ImageData, MediaError> {
+    if let Some(data_url) = url.strip_prefix("data:") {
+        let Some((header, data)) = data_url.split_once(',') else {
+            return Err(MediaError::InvalidImage("malformed data URL".to_string()));
+        };
+        if !header.ends_with(";base64") {
+            return Err(MediaError::InvalidImage(
+                "data URL must be base64 encoded".to_string(),
+            ));
+        }
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(data.trim())
+            .map_err(|err| MediaError::InvalidImage(err.to_string()))?;
+        return ImageData::from_bytes(&bytes);
+    }
+
+    if !(url.starts_with("http://") || url.starts_with("https://")) {
+        return Err(MediaError::InvalidImage(
+            "image_url must be a data: or http(s) URL".to_string(),
+        ));
+    }
+    let fetch_err = |reason: String| MediaError::Fetch {
+        url: url.to_string(),
+        reason,
+    };
+    let client = match fetch {
+        ImageFetch::Off => {
+            return Err(MediaError::InvalidImage(
+                "image URLs are not fetched by this server, send the image as a data: URL"
+                    .to_string(),
+            ))
+        }
+        ImageFetch::Public => {
+            let parsed = reqwest::Url::parse(url).map_err(|err| fetch_err(err.to_string()))?;
+            if parsed.host_str().is_some_and(is_private_host) {
+                return Err(fetch_err("not a public address".to_string()));
+            }
+            &*PUBLIC_HTTP_CLIENT
+        }
+        ImageFetch::Any => &*HTTP_CLIENT,
+    };
+    let mut response = client
+        .get(url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|err| fetch_err(format!("{:#}", anyhow::Error::from(err))))?;
+    let too_large = || fetch_err(format!("larger than the {MAX_IMAGE_BYTES} bytes limit"));
+    if response
+        .content_length()
+        .is_some_and(|len| len as usize > MAX_IMAGE_BYTES)
+    {
+        return Err(too_large());
+    }
+    // Content-Length can be missing or wrong, so count as the body comes in
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|err| fetch_err(err.to_string()))?
+    {
+        if bytes.len() + chunk.len() > MAX_IMAGE_BYTES {
+            return Err(too_large());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    ImageData::from_bytes(&bytes)
+}
+
+/// Load all the images of a request, concurrently, keeping their order
+pub async fn load_images(urls: &[String]) -> Result<Vec<ImageData>, MediaError> {
+    futures::future::try_join_all(urls.iter().map(|url| load_image(url))).await
+}
+
+fn sniff_mime_type(bytes: &[u8]) -> Option<&'static str> {
+    match bytes {
+        [0x89, b'P', b'N', b'G', ..] => Some("image/png"),
+        [0xFF, 0xD8, 0xFF, ..] => Some("image/jpeg"),
+        [b'G', b'I', b'F', b'8', ..] => Some("image/gif"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("image/webp"),
+        [b'B', b'M', ..] => Some("image/bmp"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 1x1 transparent PNG
+    const PNG: &str = "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAQAAAC1HAwCAAAAC0lEQVR42mNkYAAAAAYAAjCB0C8AAAAASUVORK5CYII=";
+
+    #[tokio::test]
+    async fn test_data_url() {
+        let image = load_image(&format!("data:image/png;base64,{PNG}"))
+            .await
+            .unwrap();
+        assert_eq!(image.mime_type, "image/png");
+        assert_eq!(image.data, PNG);
+
+        let err = load_image("data:text/plain;base64,aGVsbG8=")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, MediaError::InvalidImage(_)));
+
+        let err = load_image("file:///etc/passwd").await.unwrap_err();
+        assert!(matches!(err, MediaError::InvalidImage(_)));
+    }
+
+    #[tokio::test]
+    async fn test_image_fetch() {
+        // Off unless turned on, data: URLs still work
+        let err = load_image_with("http://example.com/cat.png", ImageFetch::Off)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, MediaError::InvalidImage(_)));
+        let data_url = format!("data:image/png;base64,{PNG}");
+        assert!(load_image_with(&data_url, ImageFetch::Off).await.is_ok());
+
+        for url in [
+            "http://127.0.0.1/cat.png",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://10.1.2.3:8080/cat.png",
+            "http://[::1]/cat.png",
+            "http://[::ffff:192.168.0.1]/cat.png",
+        ] {
+            let err = load_image_with(url, ImageFetch::Public).await.unwrap_err();
+            assert!(matches!(err, MediaError::Fetch { .. }), "{url}");
+        }
+        // Resolved to loopback, refused before connecting
+        let err = load_image_with("http://localhost/cat.png", ImageFetch::Public)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, MediaError::Fetch { .. }));
+
+        assert_eq!("public".parse::<ImageFetch>().unwrap(), ImageFetch::Public);
+        assert!("yes".parse::<ImageFetch>().is_err());
+    }
+
+    #[test]
+    fn test_is_public() {
+        for ip in ["8.8.8.8", "1.1.1.1", "2606:4700::1111"] {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in [
+            "0.0.0.0",
+            "127.0.0.1",
+            "10.0.0.1",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "224.0.0.1",
+            "255.255.255.255",
+            "::",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+}
@@ -0,0 +1,232 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Token counts and latencies of the requests going through the pre-processor.
+//!
+//! The pre-processor is the one stage that sees both the prompt tokens and every response
+//! the engine sends back, whichever engine and whichever input (HTTP, text, batch) is in use.
+
+use std::sync::LazyLock;
+use std::time::Instant;
+
+use dynamo_runtime::engine::{AsyncEngineContextProvider, ResponseStream};
+use dynamo_runtime::metrics::register;
+use dynamo_runtime::pipeline::ManyOut;
+use dynamo_runtime::protocols::annotated::Annotated;
+use futures::StreamExt;
+use prometheus::{HistogramOpts, HistogramVec, IntCounterVec, Opts};
+
+use super::BackendOutput;
+
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+static INPUT_TOKENS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new("dynamo_llm_input_tokens_total", "Prompt tokens processed"),
+            &["model"],
+        )
+        .unwrap(),
+    )
+});
+
+static OUTPUT_TOKENS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new("dynamo_llm_output_tokens_total", "Tokens generated"),
+            &["model"],
+        )
+        .unwrap(),
+    )
+});
+
+static TIME_TO_FIRST_TOKEN: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register(
+        HistogramVec::new(
+            HistogramOpts::new(
+                "dynamo_llm_time_to_first_token_seconds",
+                "Time from sending a request to the engine until its first token",
+            )
+            .buckets(LATENCY_BUCKETS.to_vec()),
+            &["model", "engine"],
+        )
+        .unwrap(),
+    )
+});
+
+static INTER_TOKEN_LATENCY: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register(
+        HistogramVec::new(
+            HistogramOpts::new(
+                "dynamo_llm_inter_token_latency_seconds",
+                "Time between two generated tokens of a request",
+            )
+            .buckets(LATENCY_BUCKETS.to_vec()),
+            &["model", "engine"],
+        )
+        .unwrap(),
+    )
+});
+
+static SPEC_DRAFT_TOKENS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "dynamo_llm_spec_decode_draft_tokens_total",
+                "Tokens the draft model proposed",
+            ),
+            &["model"],
+        )
+        .unwrap(),
+    )
+});
+
+static SPEC_ACCEPTED_TOKENS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "dynamo_llm_spec_decode_accepted_tokens_total",
+                "Draft tokens the target model accepted",
+            ),
+            &["model"],
+        )
+        .unwrap(),
+    )
+});
+
+/// Timing of a single request
+struct RequestTimer {
+    model: String,
+    /// The card's engine, such as `vllm`
+    engine: String,
+    start: Instant,
+    last_token: Option<Instant>,
+    output_tokens: usize,
+    /// Lasts from sending the request to the engine until the response stream is dropped
+    span: tracing::Span,
+}
+
+impl RequestTimer {
+    fn observe(&mut self, output: &BackendOutput) {
+        if let Some(spec) = &output.spec_decode {
+            SPEC_DRAFT_TOKENS
+                .with_label_values(&[&self.model])
+                .inc_by(spec.draft_tokens as u64);
+            SPEC_ACCEPTED_TOKENS
+                .with_label_values(&[&self.model])
+                .inc_by(spec.accepted_tokens as u64);
+        }
+        let tokens = output.token_ids.len();
+        if tokens == 0 {
+            return;
+        }
+        let now = Instant::now();
+        self.output_tokens += tokens;
+        self.span.record("output_tokens", self.output_tokens);
+        OUTPUT_TOKENS
+            .with_label_values(&[&self.model])
+            .inc_by(tokens as u64);
+        match self.last_token {
+            None => {
+                let ttft = now - self.start;
+                self.span.record("ttft_ms", ttft.as_millis() as u64);
+                TIME_TO_FIRST_TOKEN
+                    .with_label_values(&[&self.model, &self.engine])
+                    .observe(ttft.as_secs_f64());
+            }
+            Some(last) => {
+                // Engines may send several tokens at once, spread the wait over them
+                let itl = (now - last).as_secs_f64() / tokens as f64;
+                let histogram = INTER_TOKEN_LATENCY.with_label_values(&[&self.model, &self.engine]);
+                for _ in 0..tokens {
+                    histogram.observe(itl);
+                }
+            }
+        }
+        self.last_token = Some(now);
+    }
+}
+
+/// Call right before sending a request with `isl` prompt tokens to `engine`, the name of the
+/// engine serving `model`. Pass the engine's response stream to the returned closure to have
+/// it measured, and traced in an `engine` span.
+pub(crate) fn start_request(
+    model: &str,
+    engine: &str,
+    isl: usize,
+) -> impl FnOnce(ManyOut<Annotated<BackendOutput>>) -> ManyOut<Annotated<BackendOutput>> {
+    INPUT_TOKENS.with_label_values(&[model]).inc_by(isl as u64);
+    let mut timer = RequestTimer {
+        model: model.to_string(),
+        engine: engine.to_string(),
+        start: Instant::now(),
+        last_token: None,
+        output_tokens: 0,
+        span: tracing::info_span!(
+            "engine",
+            model,
+            input_tokens = isl,
+            output_tokens = 0,
+            ttft_ms = tracing::field::Empty
+        ),
+    };
+    move |stream| {
+        let context = stream.context();
+        let stream = stream.inspect(move |response| {
+            if let Some(output) = &response.data {
+                timer.observe(output);
+            }
+        });
+        ResponseStream::new(Box::pin(stream), context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dynamo_runtime::pipeline::Context;
+
+    fn output(tokens: usize) -> Annotated<BackendOutput> {
+        Annotated::from_data(BackendOutput {
+            token_ids: vec![1; tokens],
+            tokens: vec![None; tokens],
+            text: None,
+            cum_log_probs: None,
+            log_probs: None,
+            logprobs: None,
+            finish_reason: None,
+            spec_decode: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_latency_by_engine() {
+        let ctx = Context::new(()).context();
+        let responses = vec![output(1), output(0), output(2)];
+        let stream = ResponseStream::new(Box::pin(futures::stream::iter(responses)), ctx);
+        let observe = start_request("test-latency-model", "test-engine", 4);
+        let responses: Vec<_> = observe(stream).collect().await;
+        assert_eq!(responses.len(), 3);
+
+        let labels = ["test-latency-model", "test-engine"];
+        let ttft = TIME_TO_FIRST_TOKEN.with_label_values(&labels);
+        assert_eq!(ttft.get_sample_count(), 1);
+        // The empty response has no tokens, the last one two
+        let itl = INTER_TOKEN_LATENCY.with_label_values(&labels);
+        assert_eq!(itl.get_sample_count(), 2);
+    }
+}
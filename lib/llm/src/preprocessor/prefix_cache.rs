@@ -0,0 +1,213 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tokens of long chat prefixes
+//!
+//! Chats often start the same way: a system prompt of thousands of tokens, or the few-shot
+//! examples of an eval. The template still renders the whole chat, but only what follows a
+//! prefix seen before is tokenized.
+//!
+//! The prefix of a chat is every message but the last. The first time it is seen it is rendered
+//! on its own and tokenized. A later chat with the same prefix whose prompt starts with that
+//! rendering takes its tokens and tokenizes the rest. That is only the tokenization of the
+//! whole prompt if the tokenizer cuts it where the prefix ends, so a prefix is only kept if its
+//! last token is a special token, such as the end of a message, and tokenizing the rest of the
+//! first prompt on its own gave the same tokens as tokenizing all of it.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use anyhow::{Context as _, Result};
+
+use crate::preprocessor::prompt::{OAIChatLikeRequest, OAIPromptFormatter};
+use crate::protocols::TokenIdType;
+use crate::tokenizers::traits::Tokenizer;
+
+/// How many prefixes to keep the tokens of, default 64. `0` turns the cache off.
+pub const PREFIX_CACHE_ENV: &str = "DYN_PROMPT_PREFIX_CACHE";
+
+const DEFAULT_CAPACITY: usize = 64;
+
+/// Shorter prefixes tokenize quickly enough
+const MIN_PREFIX_BYTES: usize = 2048;
+
+pub struct PrefixCache {
+    capacity: usize,
+    entries: Mutex<HashMap<blake3::Hash, Entry>>,
+}
+
+struct Entry {
+    /// None if the prefix is too short or the prompt can't be cut after it
+    prefix: Option<Prefix>,
+    last_used: Instant,
+}
+
+#[derive(Clone)]
+struct Prefix {
+    /// The prefix rendered on its own
+    text: String,
+    token_ids: Vec<TokenIdType>,
+}
+
+impl PrefixCache {
+    pub fn new(capacity: usize) -> Self {
+        PrefixCache {
+            capacity,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// None if [`PREFIX_CACHE_ENV`] turns it off
+    pub fn from_env() -> Result<Option<Self>> {
+        let capacity = match std::env::var(PREFIX_CACHE_ENV) {
+            Ok(s) if !s.trim().is_empty() => s
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid {PREFIX_CACHE_ENV} '{s}'"))?,
+            _ => DEFAULT_CAPACITY,
+        };
+        Ok((capacity > 0).then(|| PrefixCache::new(capacity)))
+    }
+
+    /// The tokens of `prompt`, the rendering of `request` by `formatter`
+    pub fn encode(
+        &self,
+        request: &dyn OAIChatLikeRequest,
+        prompt: &str,
+        formatter: &dyn OAIPromptFormatter,
+        tokenizer: &dyn Tokenizer,
+    ) -> Result<Vec<TokenIdType>> {
+        let Some((key, prefix_messages)) = prefix_key(request) else {
+            return Ok(tokenizer.encode(prompt)?.token_ids);
+        };
+        let cached = self.get(&key);
+        match cached {
+            Some(Some(prefix)) if prompt.starts_with(&prefix.text) => {
+                let mut token_ids = prefix.token_ids;
+                let rest = tokenizer.encode(&prompt[prefix.text.len()..])?.token_ids;
+                token_ids.extend(rest);
+                return Ok(token_ids);
+            }
+            // Known, but of no use for this prompt
+            Some(_) => return Ok(tokenizer.encode(prompt)?.token_ids),
+            None => {}
+        }
+
+        let token_ids = tokenizer.encode(prompt)?.token_ids;
+        let chat = PrefixChat {
+            request,
+            messages: minijinja::value::Value::from_serialize(&prefix_messages),
+        };
+        let text = formatter.render(&chat)?;
+        let prefix = if text.len() >= MIN_PREFIX_BYTES && prompt.starts_with(&text) {
+            let prefix_ids = tokenizer.encode(&text)?.token_ids;
+            let splits = match prefix_ids.last() {
+                Some(&last) => {
+                    tokenizer.decode(&[last], true)?.is_empty()
+                        && token_ids.starts_with(&prefix_ids)
+                        && tokenizer.encode(&prompt[text.len()..])?.token_ids
+                            == token_ids[prefix_ids.len()..]
+                }
+                None => false,
+            };
+            splits.then_some(Prefix {
+                text,
+                token_ids: prefix_ids,
+            })
+        } else {
+            None
+        };
+        if prefix.is_none() {
+            tracing::trace!("Prompt prefix not cacheable");
+        }
+        self.insert(key, prefix);
+        Ok(token_ids)
+    }
+
+    /// Forget every prefix, returns how many there were
+    pub fn clear(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let count = entries.len();
+        entries.clear();
+        count
+    }
+
+    /// None if the prefix was never seen, Some(None) if it can't be used
+    fn get(&self, key: &blake3::Hash) -> Option<Option<Prefix>> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get_mut(key)?;
+        entry.last_used = Instant::now();
+        Some(entry.prefix.clone())
+    }
+
+    fn insert(&self, key: blake3::Hash, prefix: Option<Prefix>) {
+        let mut entries = self.entries.lock().unwrap();
+        if !entries.contains_key(&key) && entries.len() >= self.capacity {
+            let least_used = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| *key);
+            if let Some(least_used) = least_used {
+                entries.remove(&least_used);
+            }
+        }
+        entries.insert(
+            key,
+            Entry {
+                prefix,
+                last_used: Instant::now(),
+            },
+        );
+    }
+}
+
+/// The hash of the messages but the last, and the tools, which the template may render with
+/// them. Also returns those messages. None for a single message.
+fn prefix_key(request: &dyn OAIChatLikeRequest) -> Option<(blake3::Hash, Vec<serde_json::Value>)> {
+    let mut messages: Vec<serde_json::Value> =
+        serde_json::from_value(serde_json::to_value(request.messages()).ok()?).ok()?;
+    if messages.len() < 2 {
+        return None;
+    }
+    messages.pop();
+    let body = serde_json::to_vec(&(&messages, request.tools())).ok()?;
+    Some((blake3::hash(&body), messages))
+}
+
+/// The prefix of a chat, for rendering on its own
+struct PrefixChat<'a> {
+    request: &'a dyn OAIChatLikeRequest,
+    messages: minijinja::value::Value,
+}
+
+impl OAIChatLikeRequest for PrefixChat<'_> {
+    fn messages(&self) -> minijinja::value::Value {
+        self.messages.clone()
+    }
+
+    fn tools(&self) -> Option<minijinja::value::Value> {
+        self.request.tools()
+    }
+
+    fn tool_choice(&self) -> Option<minijinja::value::Value> {
+        self.request.tool_choice()
+    }
+
+    /// The chat goes on after the prefix
+    fn should_add_generation_prompt(&self) -> bool {
+        false
+    }
+}
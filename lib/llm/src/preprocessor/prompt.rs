@@ -34,6 +34,8 @@ use anyhow::Result;
 use minijinja::value::Value;
 use std::sync::Arc;
 
+use crate::protocols::TokenIdType;
+
 mod template;
 
 pub use template::ContextMixins;
@@ -49,6 +51,24 @@ pub trait OAIChatLikeRequest {
     }
 
     fn should_add_generation_prompt(&self) -> bool;
+
+    /// Prompt supplied as token ids. When set, the preprocessor uses these as-is and does not
+    /// render a template or tokenize.
+    fn prompt_token_ids(&self) -> Result<Option<Vec<TokenIdType>>> {
+        Ok(None)
+    }
+
+    /// URLs of the images in the messages, in order
+    fn image_urls(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// The code before and after the cursor of a fill-in-the-middle request. When set, the
+    /// preprocessor builds the prompt from them with the model's FIM tokens instead of
+    /// rendering a template.
+    fn fill_in_the_middle(&self) -> Result<Option<(String, String)>> {
+        Ok(None)
+    }
 }
 
 pub trait OAIPromptFormatter: Send + Sync + 'static {
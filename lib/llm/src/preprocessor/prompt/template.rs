@@ -16,6 +16,7 @@
 use std::{collections::HashSet, sync::Arc};
 
 use anyhow::{Context, Ok, Result};
+use either::Either;
 use minijinja::Environment;
 
 use crate::model_card::model::{ModelDeploymentCard, PromptContextMixin, PromptFormatterArtifact};
@@ -26,29 +27,34 @@ mod oai;
 mod tokcfg;
 
 use super::{OAIChatLikeRequest, OAIPromptFormatter, PromptFormatter};
-use tokcfg::ChatTemplate;
+use tokcfg::{ChatTemplate, ChatTemplateValue};
 
 impl PromptFormatter {
     pub async fn from_mdc(mdc: ModelDeploymentCard) -> Result<PromptFormatter> {
-        match mdc
-            .prompt_formatter
-            .ok_or(anyhow::anyhow!("MDC does not contain a prompt formatter"))?
-        {
-            PromptFormatterArtifact::HfTokenizerConfigJson(file) => {
+        let (mut config, context) = match mdc.prompt_formatter {
+            Some(PromptFormatterArtifact::HfTokenizerConfigJson(file)) => {
                 let content = std::fs::read_to_string(&file)
                     .with_context(|| format!("fs:read_to_string '{file}'"))?;
                 let config: ChatTemplate = serde_json::from_str(&content)?;
-                Self::from_parts(
-                    config,
-                    mdc.prompt_context
-                        .map_or(ContextMixins::default(), |x| ContextMixins::new(&x)),
-                )
+                let context = mdc
+                    .prompt_context
+                    .map_or(ContextMixins::default(), |x| ContextMixins::new(&x));
+                (config, context)
             }
-            PromptFormatterArtifact::GGUF(gguf_path) => {
-                let config = ChatTemplate::from_gguf(&gguf_path)?;
-                Self::from_parts(config, ContextMixins::default())
+            Some(PromptFormatterArtifact::GGUF(gguf_path)) => (
+                ChatTemplate::from_gguf(&gguf_path)?,
+                ContextMixins::default(),
+            ),
+            // A template of its own is enough, the model has no tokenizer_config.json
+            None if mdc.chat_template.is_some() => {
+                (ChatTemplate::default(), ContextMixins::default())
             }
+            None => anyhow::bail!("MDC does not contain a prompt formatter"),
+        };
+        if let Some(template) = mdc.chat_template {
+            config.chat_template = Some(ChatTemplateValue(Either::Left(template)));
         }
+        Self::from_parts(config, context)
     }
 
     pub fn from_parts(config: ChatTemplate, context: ContextMixins) -> Result<PromptFormatter> {
@@ -43,7 +43,8 @@ impl HfTokenizerConfigJsonFormatter {
         let mut env = JinjaEnvironment::default().env();
 
         let chat_template = config.chat_template.as_ref().ok_or(anyhow::anyhow!(
-            "chat_template field is required in the tokenizer_config.json file"
+            "The model has no chat template in its tokenizer_config.json or GGUF. Give it one, \
+             for example with dynamo-run's --chat-template <file.jinja>."
         ))?;
 
         // add pycompat
@@ -17,14 +17,78 @@ use super::*;
 
 use minijinja::{context, value::Value};
 
-use crate::protocols::openai::{
-    chat_completions::NvCreateChatCompletionRequest, completions::CompletionRequest,
+use crate::protocols::{
+    openai::{chat_completions::NvCreateChatCompletionRequest, completions::CompletionRequest},
+    TokenIdType,
 };
 use tracing;
 
 impl OAIChatLikeRequest for NvCreateChatCompletionRequest {
     fn messages(&self) -> Value {
-        Value::from_serialize(&self.inner.messages)
+        if self.image_urls().is_empty() {
+            return Value::from_serialize(&self.inner.messages);
+        }
+
+        // Vision chat templates put the model's image placeholder where they find a
+        // `{"type": "image"}` part. The image itself is passed to the engine separately.
+        let mut messages = serde_json::to_value(&self.inner.messages).unwrap_or_default();
+        let parts = messages
+            .as_array_mut()
+            .into_iter()
+            .flatten()
+            .filter_map(|message| message.get_mut("content"))
+            .filter_map(|content| content.as_array_mut())
+            .flatten();
+        for part in parts {
+            if part.get("type").and_then(|t| t.as_str()) == Some("image_url") {
+                *part = serde_json::json!({"type": "image"});
+            }
+        }
+        Value::from_serialize(&messages)
+    }
+
+    fn image_urls(&self) -> Vec<String> {
+        use async_openai::types::{
+            ChatCompletionRequestMessage, ChatCompletionRequestUserMessageContent,
+            ChatCompletionRequestUserMessageContentPart,
+        };
+        let mut urls = Vec::new();
+        for message in &self.inner.messages {
+            let ChatCompletionRequestMessage::User(user) = message else {
+                continue;
+            };
+            let ChatCompletionRequestUserMessageContent::Array(parts) = &user.content else {
+                continue;
+            };
+            for part in parts {
+                if let ChatCompletionRequestUserMessageContentPart::ImageUrl(image) = part {
+                    urls.push(image.image_url.url.clone());
+                }
+            }
+        }
+        urls
+    }
+
+    /// `nvext.suffix` makes the last message, which must be a text user message, the code
+    /// before the cursor. The chat template is not applied.
+    fn fill_in_the_middle(&self) -> Result<Option<(String, String)>> {
+        use async_openai::types::{
+            ChatCompletionRequestMessage, ChatCompletionRequestUserMessageContent,
+        };
+        let Some(suffix) = self.nvext.as_ref().and_then(|ext| ext.suffix.clone()) else {
+            return Ok(None);
+        };
+        match self.inner.messages.last() {
+            Some(ChatCompletionRequestMessage::User(user)) => match &user.content {
+                ChatCompletionRequestUserMessageContent::Text(prefix) => {
+                    Ok(Some((prefix.clone(), suffix)))
+                }
+                ChatCompletionRequestUserMessageContent::Array(_) => {
+                    anyhow::bail!("With nvext.suffix the last message must be text")
+                }
+            },
+            _ => anyhow::bail!("With nvext.suffix the last message must be a user message"),
+        }
     }
 
     fn tools(&self) -> Option<Value> {
@@ -72,6 +136,34 @@ impl OAIChatLikeRequest for CompletionRequest {
     fn should_add_generation_prompt(&self) -> bool {
         true
     }
+
+    fn prompt_token_ids(&self) -> Result<Option<Vec<TokenIdType>>> {
+        use async_openai::types::Prompt;
+        match &self.inner.prompt {
+            Prompt::String(_) | Prompt::StringArray(_) => Ok(None),
+            Prompt::IntegerArray(ids) => {
+                Ok(Some(ids.iter().map(|&id| TokenIdType::from(id)).collect()))
+            }
+            Prompt::ArrayOfIntegerArray(prompts) => match prompts.as_slice() {
+                [ids] => Ok(Some(ids.iter().map(|&id| TokenIdType::from(id)).collect())),
+                _ => anyhow::bail!(
+                    "Batched prompts are not supported, got {} token arrays",
+                    prompts.len()
+                ),
+            },
+        }
+    }
+
+    fn fill_in_the_middle(&self) -> Result<Option<(String, String)>> {
+        use async_openai::types::Prompt;
+        let Some(suffix) = &self.inner.suffix else {
+            return Ok(None);
+        };
+        match &self.inner.prompt {
+            Prompt::String(prefix) => Ok(Some((prefix.clone(), suffix.clone()))),
+            _ => anyhow::bail!("suffix requires the prompt to be a single string"),
+        }
+    }
 }
 
 impl OAIPromptFormatter for HfTokenizerConfigJsonFormatter {
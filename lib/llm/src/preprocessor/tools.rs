@@ -13,9 +13,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod parser;
 mod request;
 mod response;
 
+pub use parser::*;
 pub use request::*;
 pub use response::*;
 use serde_json::Value;
@@ -113,3 +115,36 @@ impl ToolCallingMatcher {
         }
     }
 }
+
+/// Parse a tool call JSON object, or an array of them. A call has a `name`, and its arguments
+/// as an object in `arguments` or `parameters`. Returns None if this is not a tool call.
+pub fn parse_calls(message: &str) -> Option<Vec<ToolCallResponse>> {
+    let values = match serde_json::from_str::<Value>(message).ok()? {
+        Value::Array(values) if !values.is_empty() => values,
+        Value::Array(_) => return None,
+        value => vec![value],
+    };
+    values
+        .into_iter()
+        .map(|value| {
+            let Value::Object(mut call) = value else {
+                return None;
+            };
+            let name = call.remove("name")?.as_str()?.to_string();
+            let arguments = match call
+                .remove("arguments")
+                .or_else(|| call.remove("parameters"))?
+            {
+                arguments @ Value::Object(_) => arguments.to_string(),
+                // Already serialized by the model
+                Value::String(arguments) => arguments,
+                _ => return None,
+            };
+            Some(ToolCallResponse {
+                id: format!("call-{}", Uuid::new_v4()),
+                tp: ToolCallType::Function,
+                function: CalledFunction { name, arguments },
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,297 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Incremental extraction of tool calls from a model's streamed text output.
+//!
+//! Models announce tool calls in one of a few styles:
+//! - Hermes (Qwen, Hermes): `<tool_call>{"name": .., "arguments": {..}}</tool_call>`, possibly
+//!   several, possibly after some text.
+//! - Mistral: `[TOOL_CALLS][{"name": .., "arguments": {..}}, ..]`
+//! - Llama 3: `<|python_tag|>{"name": .., "parameters": {..}}`, several separated by `;`
+//! - Bare JSON: the whole output is a call object, or an array of them. Llama 3 and Mistral
+//!   look like this when their marker is a special token that the detokenizer skips.
+//!
+//! Text is passed through as content as soon as it cannot be the start of a tool call.
+
+use super::{parse_calls, ToolCallResponse};
+
+const HERMES_START: &str = "<tool_call>";
+const HERMES_END: &str = "</tool_call>";
+const MISTRAL_START: &str = "[TOOL_CALLS]";
+const LLAMA3_START: &str = "<|python_tag|>";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ParserState {
+    /// Start of the output, waiting for enough text to tell content from a tool call
+    Detecting,
+    /// Plain text, which may still be followed by a Hermes tool call
+    Content,
+    /// Inside Hermes `<tool_call>` blocks
+    Hermes,
+    /// A Mistral, Llama 3 or bare JSON tool call, parsed once the output is complete
+    Buffering,
+}
+
+/// Output of the parser for one chunk of text
+#[derive(Debug, Default)]
+pub struct ParsedDelta {
+    pub content: Option<String>,
+    pub tool_calls: Vec<ToolCallResponse>,
+}
+
+/// Splits streamed model output into content and tool calls. Feed it with
+/// [`ToolCallParser::push`] and call [`ToolCallParser::finish`] at the end of the stream.
+#[derive(Debug, Clone)]
+pub struct ToolCallParser {
+    state: ParserState,
+    buffer: String,
+    tool_call_count: usize,
+}
+
+impl Default for ToolCallParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ToolCallParser {
+    pub fn new() -> Self {
+        ToolCallParser {
+            state: ParserState::Detecting,
+            buffer: String::new(),
+            tool_call_count: 0,
+        }
+    }
+
+    /// Number of tool calls returned so far
+    pub fn tool_call_count(&self) -> usize {
+        self.tool_call_count
+    }
+
+    pub fn push(&mut self, text: &str) -> ParsedDelta {
+        self.buffer.push_str(text);
+        let mut out = ParsedDelta::default();
+        self.process(&mut out);
+        out
+    }
+
+    /// The stream ended. Parse whatever is buffered, returning it as content if it is not a
+    /// complete tool call.
+    pub fn finish(&mut self) -> ParsedDelta {
+        let mut out = ParsedDelta::default();
+        self.process(&mut out);
+
+        let rest = std::mem::take(&mut self.buffer);
+        match self.state {
+            ParserState::Buffering => {
+                let payload = rest
+                    .trim_start()
+                    .trim_start_matches(MISTRAL_START)
+                    .trim_start_matches(LLAMA3_START)
+                    .trim();
+                match parse_payload(payload) {
+                    Some(calls) => self.add_calls(&mut out, calls),
+                    None => push_content(&mut out, &rest),
+                }
+            }
+            ParserState::Hermes if rest.trim().is_empty() => {}
+            // An unfinished Hermes call, or a partial marker
+            _ => push_content(&mut out, &rest),
+        }
+        out
+    }
+
+    fn process(&mut self, out: &mut ParsedDelta) {
+        loop {
+            match self.state {
+                ParserState::Detecting => {
+                    let start = self.buffer.trim_start();
+                    if start.is_empty() {
+                        return;
+                    }
+                    if start.starts_with(HERMES_START) {
+                        self.state = ParserState::Hermes;
+                    } else if start.starts_with(MISTRAL_START)
+                        || start.starts_with(LLAMA3_START)
+                        || start.starts_with('{')
+                        || start.starts_with('[')
+                    {
+                        self.state = ParserState::Buffering;
+                        return;
+                    } else if [HERMES_START, MISTRAL_START, LLAMA3_START]
+                        .iter()
+                        .any(|marker| marker.starts_with(start))
+                    {
+                        // Could still become a marker
+                        return;
+                    } else {
+                        self.state = ParserState::Content;
+                    }
+                }
+                ParserState::Content => {
+                    if let Some(pos) = self.buffer.find(HERMES_START) {
+                        let content: String = self.buffer.drain(..pos).collect();
+                        push_content(out, &content);
+                        self.state = ParserState::Hermes;
+                        continue;
+                    }
+                    // Hold back the end of the text if it could be the start of the marker
+                    let keep = partial_marker_len(&self.buffer, HERMES_START);
+                    let content: String = self.buffer.drain(..self.buffer.len() - keep).collect();
+                    push_content(out, &content);
+                    return;
+                }
+                ParserState::Hermes => {
+                    let start = self.buffer.trim_start();
+                    if start.is_empty() || HERMES_START.starts_with(start) {
+                        return;
+                    }
+                    if !start.starts_with(HERMES_START) {
+                        // Text after the tool calls
+                        self.state = ParserState::Content;
+                        continue;
+                    }
+                    let Some(end) = start.find(HERMES_END) else {
+                        return;
+                    };
+                    let payload = start[HERMES_START.len()..end].trim().to_string();
+                    let consumed = self.buffer.len() - start.len() + end + HERMES_END.len();
+                    match parse_calls(&payload) {
+                        Some(calls) => {
+                            self.buffer.drain(..consumed);
+                            self.add_calls(out, calls);
+                        }
+                        None => {
+                            tracing::warn!(payload, "Invalid tool call");
+                            let content: String = self.buffer.drain(..consumed).collect();
+                            push_content(out, &content);
+                        }
+                    }
+                }
+                ParserState::Buffering => return,
+            }
+        }
+    }
+
+    fn add_calls(&mut self, out: &mut ParsedDelta, calls: Vec<ToolCallResponse>) {
+        self.tool_call_count += calls.len();
+        out.tool_calls.extend(calls);
+    }
+}
+
+/// A Mistral or bare JSON payload, or Llama 3 calls separated by `;`
+fn parse_payload(payload: &str) -> Option<Vec<ToolCallResponse>> {
+    if let Some(calls) = parse_calls(payload) {
+        return Some(calls);
+    }
+    let mut calls = Vec::new();
+    for part in payload.split(';').map(str::trim).filter(|p| !p.is_empty()) {
+        calls.extend(parse_calls(part)?);
+    }
+    (!calls.is_empty()).then_some(calls)
+}
+
+fn push_content(out: &mut ParsedDelta, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+    out.content.get_or_insert_with(String::new).push_str(text);
+}
+
+/// Length of the longest suffix of `text` that is a proper prefix of `marker`
+fn partial_marker_len(text: &str, marker: &str) -> usize {
+    (1..marker.len())
+        .rev()
+        .find(|&n| text.ends_with(&marker[..n]))
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(chunks: &[&str]) -> (String, Vec<ToolCallResponse>) {
+        let mut parser = ToolCallParser::new();
+        let mut content = String::new();
+        let mut calls = Vec::new();
+        let mut deltas: Vec<ParsedDelta> = chunks.iter().map(|chunk| parser.push(chunk)).collect();
+        deltas.push(parser.finish());
+        for delta in deltas {
+            content.push_str(&delta.content.unwrap_or_default());
+            calls.extend(delta.tool_calls);
+        }
+        (content, calls)
+    }
+
+    #[test]
+    fn test_content_passes_through() {
+        let mut parser = ToolCallParser::new();
+        assert_eq!(parser.push("Hello").content.as_deref(), Some("Hello"));
+        // Could be the start of a tool call
+        assert_eq!(
+            parser.push(" there <tool").content.as_deref(),
+            Some(" there ")
+        );
+        assert_eq!(parser.push("box>").content.as_deref(), Some("<toolbox>"));
+        assert!(parser.finish().content.is_none());
+    }
+
+    #[test]
+    fn test_hermes() {
+        let (content, calls) = run(&[
+            "Let me check.\n<tool",
+            "_call>\n{\"name\": \"get_weather\", \"arguments\": {\"city\": \"Paris\"}}\n</tool_call>",
+            "\n<tool_call>{\"name\": \"get_time\", \"arguments\": {}}</tool_call>",
+        ]);
+        assert_eq!(content, "Let me check.\n");
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].function.name, "get_weather");
+        assert_eq!(calls[0].function.arguments, r#"{"city":"Paris"}"#);
+        assert_eq!(calls[1].function.name, "get_time");
+    }
+
+    #[test]
+    fn test_mistral() {
+        let (content, calls) = run(&[
+            "[TOOL_CALLS][{\"name\": \"get_weather\", ",
+            "\"arguments\": {\"city\": \"Paris\"}}]",
+        ]);
+        assert!(content.is_empty());
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].function.name, "get_weather");
+    }
+
+    #[test]
+    fn test_llama3() {
+        let (_, calls) = run(&[
+            "<|python_tag|>{\"name\": \"a\", \"parameters\": {\"x\": 1}}; ",
+            "{\"name\": \"b\", \"parameters\": {}}",
+        ]);
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].function.arguments, r#"{"x":1}"#);
+        assert_eq!(calls[1].function.name, "b");
+
+        // Marker skipped by the detokenizer
+        let (_, calls) = run(&["{\"name\": \"a\", \"parameters\": {}}"]);
+        assert_eq!(calls.len(), 1);
+    }
+
+    #[test]
+    fn test_json_content() {
+        let (content, calls) = run(&["{\"answer\": ", "42}"]);
+        assert_eq!(content, "{\"answer\": 42}");
+        assert!(calls.is_empty());
+    }
+}
@@ -0,0 +1,326 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Prompts longer than the context
+//!
+//! A prompt that doesn't leave room for `max_tokens` in the model's context is rejected with
+//! a 400, sent as it is, or cut down to fit, depending on the [`Truncation`] policy. Engines
+//! count differently than we do (their own chat template, special tokens), so a prompt we
+//! think fits can still be rejected. With retries on, such a request is sent once more with
+//! the prompt cut by a quarter. Either way the response carries a warning saying how much was
+//! cut.
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::preprocessor::prompt::OAIChatLikeRequest;
+use crate::protocols::error::{ErrorKind, RequestError};
+use crate::protocols::TokenIdType;
+
+/// What to do with prompts longer than the context: `reject` (the default), `off`,
+/// `messages`, `left` or `middle`
+pub const CONTEXT_OVERFLOW_POLICY_ENV: &str = "DYN_CONTEXT_OVERFLOW_POLICY";
+
+/// Older name of [`CONTEXT_OVERFLOW_POLICY_ENV`], read if that is not set
+pub const TRUNCATION_ENV: &str = "DYN_TRUNCATION";
+
+/// Set to `1` or `true` to retry requests the engine rejects for exceeding the context
+pub const TRUNCATION_RETRY_ENV: &str = "DYN_TRUNCATION_RETRY";
+
+/// What to do with a prompt that is too long, and where to cut it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Truncation {
+    /// Fail the request with a 400 saying how many tokens it has and how many fit
+    #[default]
+    Reject,
+
+    /// Leave it to the engine to reject it
+    Off,
+
+    /// Drop the oldest messages of a chat, keeping the system prompt and the last message.
+    /// If that is not enough, or the prompt is not made of messages, drop the oldest tokens.
+    Messages,
+
+    /// Drop the oldest tokens
+    Left,
+
+    /// Drop tokens from the middle, keeping the start (system prompt) and the end (latest
+    /// turn and generation prompt)
+    Middle,
+}
+
+impl Truncation {
+    /// Read from [`CONTEXT_OVERFLOW_POLICY_ENV`] or [`TRUNCATION_ENV`], [`Truncation::Reject`]
+    /// if neither is set
+    pub fn from_env() -> Result<Self> {
+        for var in [CONTEXT_OVERFLOW_POLICY_ENV, TRUNCATION_ENV] {
+            match std::env::var(var) {
+                Ok(s) if !s.is_empty() => {
+                    return s
+                        .parse()
+                        .map_err(|err| anyhow::anyhow!("Invalid {var}: {err}"))
+                }
+                _ => {}
+            }
+        }
+        Ok(Truncation::default())
+    }
+
+    /// Whether prompts that are too long get cut down
+    pub fn truncates(&self) -> bool {
+        matches!(
+            self,
+            Truncation::Messages | Truncation::Left | Truncation::Middle
+        )
+    }
+
+    /// Whether [`TRUNCATION_RETRY_ENV`] asks for retries
+    pub fn retry_from_env() -> bool {
+        std::env::var(TRUNCATION_RETRY_ENV)
+            .map(|val| matches!(val.trim().to_lowercase().as_str(), "1" | "true"))
+            .unwrap_or(false)
+    }
+
+    /// Cut `token_ids` down to `budget` tokens. Returns a warning for the client if it did.
+    /// Messages are dropped before the prompt is tokenized, see [`drop_oldest_message`], here
+    /// [`Truncation::Messages`] drops the oldest tokens.
+    pub fn apply(&self, token_ids: &mut Vec<TokenIdType>, budget: usize) -> Option<String> {
+        let len = token_ids.len();
+        if len <= budget {
+            return None;
+        }
+        match self {
+            Truncation::Reject | Truncation::Off => return None,
+            Truncation::Messages | Truncation::Left => {
+                token_ids.drain(..len - budget);
+            }
+            Truncation::Middle => {
+                let head = budget / 2;
+                token_ids.drain(head..len - (budget - head));
+            }
+        }
+        Some(format!(
+            "prompt truncated ({self}) from {len} to {budget} tokens to fit the context"
+        ))
+    }
+}
+
+impl FromStr for Truncation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "reject" => Ok(Truncation::Reject),
+            "off" => Ok(Truncation::Off),
+            "messages" => Ok(Truncation::Messages),
+            "left" => Ok(Truncation::Left),
+            "middle" => Ok(Truncation::Middle),
+            _ => anyhow::bail!("'{s}' is not one of reject, off, messages, left, middle"),
+        }
+    }
+}
+
+impl Display for Truncation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Truncation::Reject => write!(f, "reject"),
+            Truncation::Off => write!(f, "off"),
+            Truncation::Messages => write!(f, "messages"),
+            Truncation::Left => write!(f, "left"),
+            Truncation::Middle => write!(f, "middle"),
+        }
+    }
+}
+
+/// The 400 for a prompt that doesn't leave room for `max_tokens`, worded like OpenAI's
+pub fn context_overflow_error(
+    prompt_tokens: usize,
+    max_tokens: usize,
+    context_length: usize,
+) -> anyhow::Error {
+    RequestError::new(
+        ErrorKind::ContextLengthExceeded,
+        format!(
+            "This model's maximum context length is {context_length} tokens. However, you \
+             requested {} tokens ({prompt_tokens} in the prompt, {max_tokens} for the \
+             completion). Please reduce the length of the prompt or of max_tokens.",
+            prompt_tokens + max_tokens
+        ),
+    )
+    .into()
+}
+
+/// The 400 for a prompt over the deployment's `max_prompt_tokens`
+pub fn prompt_limit_error(prompt_tokens: usize, max_prompt_tokens: usize) -> anyhow::Error {
+    RequestError::new(
+        ErrorKind::ContextLengthExceeded,
+        format!(
+            "This deployment accepts prompts of at most {max_prompt_tokens} tokens. However, \
+             your prompt has {prompt_tokens} tokens. Please reduce the length of the prompt."
+        ),
+    )
+    .into()
+}
+
+/// Remove the oldest message of a chat that is neither a system message nor the last
+/// message. Returns false if there is none.
+pub fn drop_oldest_message(messages: &mut Vec<serde_json::Value>) -> bool {
+    let last = messages.len().saturating_sub(1);
+    let oldest = messages[..last]
+        .iter()
+        .position(|message| message.get("role").and_then(|role| role.as_str()) != Some("system"));
+    match oldest {
+        Some(index) => {
+            messages.remove(index);
+            true
+        }
+        None => false,
+    }
+}
+
+/// A chat request with the messages left after [`drop_oldest_message`], for rendering
+pub struct TrimmedChat<'a> {
+    request: &'a dyn OAIChatLikeRequest,
+    messages: minijinja::value::Value,
+}
+
+impl<'a> TrimmedChat<'a> {
+    pub fn new(request: &'a dyn OAIChatLikeRequest, messages: &[serde_json::Value]) -> Self {
+        TrimmedChat {
+            request,
+            messages: minijinja::value::Value::from_serialize(messages),
+        }
+    }
+}
+
+impl OAIChatLikeRequest for TrimmedChat<'_> {
+    fn messages(&self) -> minijinja::value::Value {
+        self.messages.clone()
+    }
+
+    fn tools(&self) -> Option<minijinja::value::Value> {
+        self.request.tools()
+    }
+
+    fn tool_choice(&self) -> Option<minijinja::value::Value> {
+        self.request.tool_choice()
+    }
+
+    fn should_add_generation_prompt(&self) -> bool {
+        self.request.should_add_generation_prompt()
+    }
+}
+
+/// Whether an engine error says the prompt didn't fit in the context. Each engine words it
+/// its own way.
+pub fn is_context_overflow(error: &str) -> bool {
+    const PATTERNS: &[&str] = &[
+        // vllm, sglang
+        "maximum context length",
+        "max_model_len",
+        "longer than the model's context",
+        // llama.cpp
+        "exceeds the available context",
+        "context size",
+        // mistral.rs, TensorRT-LLM
+        "max sequence length",
+        "exceeds max_input_len",
+        "prompt is too long",
+        // engines reporting a RequestError
+        "[context_length_exceeded]",
+    ];
+    let error = error.to_lowercase();
+    PATTERNS.iter().any(|pattern| error.contains(pattern))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncation() {
+        let prompt: Vec<TokenIdType> = (0..10).collect();
+
+        let mut token_ids = prompt.clone();
+        assert!(Truncation::Left.apply(&mut token_ids, 4).is_some());
+        assert_eq!(token_ids, vec![6, 7, 8, 9]);
+
+        let mut token_ids = prompt.clone();
+        let warning = Truncation::Middle.apply(&mut token_ids, 5).unwrap();
+        assert_eq!(token_ids, vec![0, 1, 7, 8, 9]);
+        assert!(warning.contains("from 10 to 5"), "{warning}");
+
+        let mut token_ids = prompt.clone();
+        assert_eq!(Truncation::Off.apply(&mut token_ids, 4), None);
+        assert_eq!(Truncation::Left.apply(&mut token_ids, 10), None);
+        assert_eq!(token_ids, prompt);
+
+        assert_eq!(Truncation::Reject.apply(&mut token_ids, 4), None);
+        assert_eq!(token_ids, prompt);
+        assert!(Truncation::Messages.apply(&mut token_ids, 4).is_some());
+        assert_eq!(token_ids, vec![6, 7, 8, 9]);
+
+        assert_eq!("middle".parse::<Truncation>().unwrap(), Truncation::Middle);
+        assert!("right".parse::<Truncation>().is_err());
+    }
+
+    #[test]
+    fn test_drop_oldest_message() {
+        let mut messages = vec![
+            serde_json::json!({"role": "system", "content": "Be brief"}),
+            serde_json::json!({"role": "user", "content": "Hi"}),
+            serde_json::json!({"role": "assistant", "content": "Hello"}),
+            serde_json::json!({"role": "user", "content": "Bye"}),
+        ];
+        assert!(drop_oldest_message(&mut messages));
+        assert!(drop_oldest_message(&mut messages));
+        assert!(!drop_oldest_message(&mut messages));
+        let roles: Vec<_> = messages
+            .iter()
+            .map(|m| m["role"].as_str().unwrap())
+            .collect();
+        assert_eq!(roles, vec!["system", "user"]);
+        assert_eq!(messages[1]["content"], "Bye");
+    }
+
+    #[test]
+    fn test_context_overflow_error() {
+        let err = context_overflow_error(4000, 200, 4096);
+        let err = err.downcast::<RequestError>().unwrap();
+        assert_eq!(err.kind, ErrorKind::ContextLengthExceeded);
+        assert_eq!(err.kind.status(), 400);
+        assert!(err.message.contains("4096 tokens"), "{}", err.message);
+        assert!(
+            err.message.contains("requested 4200 tokens"),
+            "{}",
+            err.message
+        );
+    }
+
+    #[test]
+    fn test_is_context_overflow() {
+        assert!(is_context_overflow(
+            "This model's maximum context length is 4096 tokens. However, you requested 5000 tokens"
+        ));
+        assert!(is_context_overflow(
+            "the request exceeds the available context size, try increasing it"
+        ));
+        assert!(!is_context_overflow("CUDA out of memory"));
+    }
+}
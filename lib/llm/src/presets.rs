@@ -0,0 +1,247 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Named prompt presets
+//!
+//! A JSON file maps names to a system prompt and few-shot examples:
+//!
+//! ```json
+//! {
+//!   "summarize-v2": {
+//!     "system": "Summarize the text in one sentence.",
+//!     "examples": [{"user": "The cat sat on the mat all day.", "assistant": "A cat idled."}]
+//!   }
+//! }
+//! ```
+//!
+//! A chat request picks one with `nvext.preset`. Its system prompt and examples go before the
+//! request's own messages. The file is read again when it changes, so prompts can be tuned
+//! without restarting or touching the clients.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use anyhow::Context as _;
+use async_openai::types::{
+    ChatCompletionRequestAssistantMessage, ChatCompletionRequestAssistantMessageContent,
+    ChatCompletionRequestMessage, ChatCompletionRequestSystemMessage,
+    ChatCompletionRequestSystemMessageContent, ChatCompletionRequestUserMessage,
+    ChatCompletionRequestUserMessageContent,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::protocols::openai::chat_completions::NvCreateChatCompletionRequest;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Preset {
+    /// Becomes the first message of the conversation
+    #[serde(default)]
+    pub system: Option<String>,
+
+    /// Exchanges showing the model what is expected, after the system prompt
+    #[serde(default)]
+    pub examples: Vec<Example>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Example {
+    pub user: String,
+    pub assistant: String,
+}
+
+impl Preset {
+    /// The messages this preset puts before a conversation
+    pub fn messages(&self) -> Vec<ChatCompletionRequestMessage> {
+        let system = self.system.iter().map(|system| {
+            ChatCompletionRequestMessage::System(ChatCompletionRequestSystemMessage {
+                content: ChatCompletionRequestSystemMessageContent::Text(system.clone()),
+                name: None,
+            })
+        });
+        let examples = self.examples.iter().flat_map(|example| {
+            [
+                ChatCompletionRequestMessage::User(ChatCompletionRequestUserMessage {
+                    content: ChatCompletionRequestUserMessageContent::Text(example.user.clone()),
+                    name: None,
+                }),
+                ChatCompletionRequestMessage::Assistant(ChatCompletionRequestAssistantMessage {
+                    content: Some(ChatCompletionRequestAssistantMessageContent::Text(
+                        example.assistant.clone(),
+                    )),
+                    ..Default::default()
+                }),
+            ]
+        });
+        system.chain(examples).collect()
+    }
+}
+
+/// The presets of a file, reloaded when the file changes
+pub struct PresetLibrary {
+    path: PathBuf,
+    loaded: Mutex<Loaded>,
+}
+
+struct Loaded {
+    modified: Option<SystemTime>,
+    presets: Arc<HashMap<String, Preset>>,
+}
+
+impl PresetLibrary {
+    /// Load the presets in `path`, failing if it isn't a valid presets file
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let presets = read(path)?;
+        Ok(PresetLibrary {
+            path: path.to_path_buf(),
+            loaded: Mutex::new(Loaded {
+                modified: modified(path),
+                presets: Arc::new(presets),
+            }),
+        })
+    }
+
+    /// The current presets. If the file changed since we last read it we read it again, and
+    /// keep the previous presets if the new file is broken.
+    pub fn presets(&self) -> Arc<HashMap<String, Preset>> {
+        let mut loaded = self.loaded.lock().unwrap();
+        let modified = modified(&self.path);
+        if modified != loaded.modified {
+            loaded.modified = modified;
+            match read(&self.path) {
+                Ok(presets) => {
+                    tracing::info!(path = %self.path.display(), "Reloaded {} presets", presets.len());
+                    loaded.presets = Arc::new(presets);
+                }
+                Err(err) => {
+                    tracing::warn!(%err, "Keeping the previous presets");
+                }
+            }
+        }
+        loaded.presets.clone()
+    }
+
+    /// Names of the current presets, sorted
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<_> = self.presets().keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Put the messages of the preset the request asks for in `nvext.preset` before its own.
+    /// The preset is then cleared from the request, so it is only applied once. Fails if
+    /// there is no such preset.
+    pub fn apply(&self, request: &mut NvCreateChatCompletionRequest) -> Result<(), String> {
+        let Some(name) = request.nvext.as_mut().and_then(|ext| ext.preset.take()) else {
+            return Ok(());
+        };
+        let presets = self.presets();
+        let Some(preset) = presets.get(&name) else {
+            return Err(format!(
+                "Unknown preset '{name}', expected one of: {}",
+                self.names().join(", ")
+            ));
+        };
+        let mut messages = preset.messages();
+        messages.append(&mut request.inner.messages);
+        request.inner.messages = messages;
+        Ok(())
+    }
+}
+
+fn read(path: &Path) -> anyhow::Result<HashMap<String, Preset>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed reading presets {}", path.display()))?;
+    serde_json::from_str(&contents)
+        .with_context(|| format!("Invalid presets file {}", path.display()))
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::openai::nvext::NvExt;
+
+    fn request(preset: Option<&str>) -> NvCreateChatCompletionRequest {
+        let inner = async_openai::types::CreateChatCompletionRequestArgs::default()
+            .model("m")
+            .messages(vec![ChatCompletionRequestMessage::User(
+                ChatCompletionRequestUserMessage {
+                    content: ChatCompletionRequestUserMessageContent::Text("Hi".to_string()),
+                    name: None,
+                },
+            )])
+            .build()
+            .unwrap();
+        NvCreateChatCompletionRequest {
+            inner,
+            nvext: Some(NvExt {
+                preset: preset.map(str::to_string),
+                ..Default::default()
+            }),
+        }
+    }
+
+    #[test]
+    fn test_presets() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("presets.json");
+        std::fs::write(
+            &path,
+            r#"{"short": {"system": "Be brief.", "examples": [{"user": "Q", "assistant": "A"}]}}"#,
+        )
+        .unwrap();
+        let library = PresetLibrary::load(&path).unwrap();
+
+        let mut req = request(Some("short"));
+        library.apply(&mut req).unwrap();
+        assert_eq!(req.inner.messages.len(), 4);
+        assert!(matches!(
+            req.inner.messages[0],
+            ChatCompletionRequestMessage::System(_)
+        ));
+        assert_eq!(req.nvext.unwrap().preset, None);
+
+        let mut req = request(None);
+        library.apply(&mut req).unwrap();
+        assert_eq!(req.inner.messages.len(), 1);
+
+        let err = library.apply(&mut request(Some("long"))).unwrap_err();
+        assert!(err.contains("short"), "{err}");
+
+        // A broken edit keeps the presets we have, a good one replaces them
+        std::fs::write(&path, "{").unwrap();
+        bump_modified(&path, 1);
+        assert_eq!(library.names(), vec!["short"]);
+        std::fs::write(&path, r#"{"long": {"system": "Take your time."}}"#).unwrap();
+        bump_modified(&path, 2);
+        assert_eq!(library.names(), vec!["long"]);
+
+        assert!(PresetLibrary::load(&dir.path().join("missing.json")).is_err());
+    }
+
+    /// Writes in quick succession can have the same modification time
+    fn bump_modified(path: &Path, secs: u64) {
+        let file = std::fs::File::options().write(true).open(path).unwrap();
+        let later = SystemTime::now() + std::time::Duration::from_secs(secs);
+        file.set_modified(later).unwrap();
+    }
+}
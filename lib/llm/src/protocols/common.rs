@@ -37,6 +37,7 @@ use super::TokenIdType;
 pub mod llm_backend;
 pub mod postprocessor;
 pub mod preprocessor;
+pub mod sampling;
 
 /// SamplingOptionsProvider is a trait that allows the caller to extract the sampling options from
 /// the object that implements it. This will mutate the object.
@@ -48,6 +49,10 @@ pub trait StopConditionsProvider {
     fn extract_stop_conditions(&self) -> Result<StopConditions>;
 }
 
+pub trait OutputOptionsProvider {
+    fn extract_output_options(&self) -> Result<OutputOptions>;
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub enum FinishReason {
     #[serde(rename = "eos")]
@@ -64,6 +69,10 @@ pub enum FinishReason {
 
     #[serde(rename = "cancelled")]
     Cancelled,
+
+    /// Stopped by the content filter
+    #[serde(rename = "content_filter")]
+    ContentFilter,
 }
 
 impl std::fmt::Display for FinishReason {
@@ -74,6 +83,7 @@ impl std::fmt::Display for FinishReason {
             FinishReason::Stop => write!(f, "stop"),
             FinishReason::Error(msg) => write!(f, "error: {}", msg),
             FinishReason::Cancelled => write!(f, "cancelled"),
+            FinishReason::ContentFilter => write!(f, "content_filter"),
         }
     }
 }
@@ -87,6 +97,7 @@ impl std::str::FromStr for FinishReason {
             "length" => Ok(FinishReason::Length),
             "stop" => Ok(FinishReason::Stop),
             "cancelled" => Ok(FinishReason::Cancelled),
+            "content_filter" => Ok(FinishReason::ContentFilter),
             s if s.starts_with("error: ") => Ok(FinishReason::Error(s[7..].to_string())),
             _ => Err(anyhow::anyhow!("Invalid FinishReason variant: '{}'", s)),
         }
@@ -301,6 +312,25 @@ pub struct SamplingOptions {
 
     /// The seed to use when sampling
     pub seed: Option<i64>,
+
+    /// Constrain the output, for engines that support guided decoding.
+    /// Engines that cannot honor it must fail the request rather than ignore it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub guided_decoding: Option<GuidedDecodingOptions>,
+
+    /// Added to the logit of each of these tokens before sampling. -100 bans a token, 100
+    /// makes it the only choice. Like `guided_decoding`, engines that cannot apply it must
+    /// fail the request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logit_bias: Option<HashMap<TokenIdType, f32>>,
+}
+
+/// Constraints for guided decoding
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct GuidedDecodingOptions {
+    /// The output must be JSON matching this JSON schema
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub json: Option<serde_json::Value>,
 }
 
 impl SamplingOptions {
@@ -15,6 +15,7 @@
 
 use serde::{Deserialize, Serialize};
 
+use crate::protocols::error::RequestError;
 use crate::protocols::TokenIdType;
 
 pub type TokenType = Option<String>;
@@ -41,9 +42,18 @@ pub struct BackendOutput {
     /// Optional log probabilities
     pub log_probs: Option<LogProbs>,
 
+    /// The log probability of each of `token_ids` and the most likely tokens at its position,
+    /// with their text, when the request asked for `logprobs`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<Vec<PositionLogProbs>>,
+
     // TODO: Enrich this with more information as can apply our first-level postprocessing
     // logic and return more detailed information
     pub finish_reason: Option<FinishReason>,
+
+    /// Speculative decoding behind these tokens, if the engine drafts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spec_decode: Option<SpecDecodeStats>,
     // Model Deployment Card checksum
     //pub mdcsum: String,
 }
@@ -74,9 +84,46 @@ pub struct LLMEngineOutput {
     /// Optional log probabilities
     pub log_probs: Option<LogProbs>,
 
+    /// For each of `token_ids`, the most likely tokens at its position, most likely first.
+    /// Engines return them when the request's `output_options.logprobs` is set, along with
+    /// `log_probs`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_log_probs: Option<Vec<Vec<TokenLogProb>>>,
+
     // TODO: Enrich this with more information as can apply our first-level postprocessing
     // logic and return more detailed information
     pub finish_reason: Option<FinishReason>,
+
+    /// Set by engines running a draft model, for the acceptance rate metrics
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spec_decode: Option<SpecDecodeStats>,
+}
+
+/// The log probability of a token
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TokenLogProb {
+    pub token_id: TokenIdType,
+
+    /// The token's text. Engines can leave it out, the backend decodes it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+
+    pub logprob: f64,
+}
+
+/// A generated token's log probability and the alternatives the model considered
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PositionLogProbs {
+    pub token: TokenLogProb,
+    pub top: Vec<TokenLogProb>,
+}
+
+/// How many tokens the draft model proposed and how many of those the target model kept, over
+/// the decode steps that made one output. The acceptance rate is `accepted / draft`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SpecDecodeStats {
+    pub draft_tokens: u32,
+    pub accepted_tokens: u32,
 }
 
 impl LLMEngineOutput {
@@ -87,6 +134,8 @@ impl LLMEngineOutput {
             text: None,
             cum_log_probs: None,
             log_probs: None,
+            top_log_probs: None,
+            spec_decode: None,
             finish_reason: Some(FinishReason::Cancelled),
         }
     }
@@ -98,6 +147,8 @@ impl LLMEngineOutput {
             text: None,
             cum_log_probs: None,
             log_probs: None,
+            top_log_probs: None,
+            spec_decode: None,
             finish_reason: Some(FinishReason::Stop),
         }
     }
@@ -109,6 +160,8 @@ impl LLMEngineOutput {
             text: None,
             cum_log_probs: None,
             log_probs: None,
+            top_log_probs: None,
+            spec_decode: None,
             finish_reason: Some(FinishReason::Length),
         }
     }
@@ -120,7 +173,14 @@ impl LLMEngineOutput {
             text: None,
             cum_log_probs: None,
             log_probs: None,
+            top_log_probs: None,
+            spec_decode: None,
             finish_reason: Some(FinishReason::Error(err_msg)),
         }
     }
+
+    /// An error that keeps its kind on the way to the client
+    pub fn failed(err: RequestError) -> Self {
+        Self::error(err.to_string())
+    }
 }
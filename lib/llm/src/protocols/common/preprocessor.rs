@@ -16,7 +16,9 @@
 use derive_builder::Builder;
 use serde::{Deserialize, Serialize};
 
-use super::{SamplingOptions, StopConditions};
+use super::{OutputOptions, SamplingOptions, StopConditions};
+use crate::lora::LoraAdapter;
+use crate::protocols::openai::nvext::Priority;
 use crate::protocols::TokenIdType;
 
 /// [`PreprocessedRequest`] is the internal representation of an LLM request. The [`dynamo.llm-preprocessor`]
@@ -34,6 +36,11 @@ pub struct PreprocessedRequest {
     /// are needed.
     pub sampling_options: SamplingOptions,
 
+    /// What the engine returns besides the tokens, such as their log probabilities
+    #[builder(default)]
+    #[serde(default)]
+    pub output_options: OutputOptions,
+
     /// The EOS token ID(s) for the Model
     /// Not every backend needs this, but those that do can find it here.
     /// TODO - refactor this to a better location
@@ -47,6 +54,33 @@ pub struct PreprocessedRequest {
     /// User requested annotations for the request
     #[builder(default)]
     pub annotations: Vec<String>,
+
+    /// Images in the prompt, in order. The chat template put the model's image placeholder in
+    /// `token_ids` for each of them.
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<ImageData>,
+
+    /// LoRA adapter to generate with, the base model's weights alone if None
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lora: Option<LoraAdapter>,
+
+    /// Scheduling class, from `nvext.priority`. Engines that preempt running requests preempt
+    /// the lower ones first.
+    #[builder(default)]
+    #[serde(default)]
+    pub priority: Priority,
+}
+
+/// An encoded image (PNG, JPEG, ...) fetched by the pre-processor
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ImageData {
+    /// Format of the image, e.g. `image/png`
+    pub mime_type: String,
+
+    /// The image file contents, base64 encoded
+    pub data: String,
 }
 
 impl PreprocessedRequest {
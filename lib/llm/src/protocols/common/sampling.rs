@@ -0,0 +1,543 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Validation of sampling options
+//!
+//! Whatever the input, requests reach the engine through the pre-processor (or, for full
+//! engines, the engine itself), which checks their [`SamplingOptions`] with a
+//! [`SamplingValidator`]. The allowed values are those of the OpenAI API, narrowed by what the
+//! engine serving the model supports ([`SamplingCaps::for_engine`]):
+//! - An option the engine doesn't support is dropped.
+//! - An out of range value fails the request, or is clamped to the nearest allowed value,
+//!   depending on [`OutOfRange`].
+//!
+//! Every change is reported as a warning, which the response carries in its `nvext`.
+//!
+//! `logit_bias` is never dropped, because it is used to keep words out of the output: an engine
+//! that can't apply it fails the request with a 400. The operator's banned words
+//! ([`BANNED_WORDS_ENV`]) are added to the `logit_bias` of every request.
+//!
+//! A request without a `seed` is sampled with the operator's default ([`DEFAULT_SEED_ENV`]),
+//! if the engine takes a seed. The response says which seed it was sampled with.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use anyhow::{Context as _, Result};
+use serde::{Deserialize, Serialize};
+
+use super::SamplingOptions;
+use crate::protocols::error::RequestError;
+use crate::protocols::openai::{
+    FREQUENCY_PENALTY_RANGE, LOGIT_BIAS_RANGE, PRESENCE_PENALTY_RANGE, TEMPERATURE_RANGE,
+    TOP_P_RANGE,
+};
+use crate::protocols::TokenIdType;
+use crate::tokenizers::traits::Tokenizer;
+
+/// What to do with out of range sampling options: `reject` (the default) or `clamp`
+pub const OUT_OF_RANGE_ENV: &str = "DYN_SAMPLING_OUT_OF_RANGE";
+
+/// Path of a file of words the model must never generate, one per line
+pub const BANNED_WORDS_ENV: &str = "DYN_BANNED_WORDS";
+
+/// Seed of the requests that don't set one, so that their output is reproducible
+pub const DEFAULT_SEED_ENV: &str = "DYN_SEED";
+
+/// The seed of [`DEFAULT_SEED_ENV`], if set
+pub fn default_seed_from_env() -> Result<Option<i64>> {
+    match std::env::var(DEFAULT_SEED_ENV) {
+        Ok(seed) if !seed.is_empty() => seed
+            .trim()
+            .parse()
+            .map(Some)
+            .with_context(|| format!("Invalid {DEFAULT_SEED_ENV} '{seed}'")),
+        _ => Ok(None),
+    }
+}
+
+/// The logit bias of a banned token
+const BANNED: f32 = LOGIT_BIAS_RANGE.0;
+
+/// What to do with a sampling option outside of the allowed range
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutOfRange {
+    /// Fail the request
+    #[default]
+    Reject,
+
+    /// Use the nearest allowed value, and warn
+    Clamp,
+}
+
+impl OutOfRange {
+    /// Read from [`OUT_OF_RANGE_ENV`], [`OutOfRange::Reject`] if not set
+    pub fn from_env() -> Result<Self> {
+        match std::env::var(OUT_OF_RANGE_ENV) {
+            Ok(s) if !s.is_empty() => s
+                .parse()
+                .map_err(|err| anyhow::anyhow!("Invalid {OUT_OF_RANGE_ENV}: {err}")),
+            _ => Ok(OutOfRange::default()),
+        }
+    }
+}
+
+impl FromStr for OutOfRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "reject" => Ok(OutOfRange::Reject),
+            "clamp" => Ok(OutOfRange::Clamp),
+            _ => anyhow::bail!("'{s}' is not one of reject, clamp"),
+        }
+    }
+}
+
+impl Display for OutOfRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutOfRange::Reject => write!(f, "reject"),
+            OutOfRange::Clamp => write!(f, "clamp"),
+        }
+    }
+}
+
+/// The sampling options an engine honors, and their allowed ranges. None if the engine
+/// ignores the option.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SamplingCaps {
+    pub temperature: Option<(f32, f32)>,
+    pub top_p: Option<(f32, f32)>,
+    /// -1, meaning all tokens, is always allowed
+    pub top_k: Option<(i32, i32)>,
+    pub min_p: Option<(f32, f32)>,
+    pub frequency_penalty: Option<(f32, f32)>,
+    pub presence_penalty: Option<(f32, f32)>,
+    pub repetition_penalty: Option<(f32, f32)>,
+    pub seed: Option<(i64, i64)>,
+    /// Whether the engine can add a bias to the logit of given tokens
+    pub logit_bias: bool,
+}
+
+impl SamplingCaps {
+    /// Everything the API accepts
+    pub const ALL: SamplingCaps = SamplingCaps {
+        temperature: Some(TEMPERATURE_RANGE),
+        top_p: Some(TOP_P_RANGE),
+        top_k: Some((1, i32::MAX)),
+        min_p: Some((0.0, 1.0)),
+        frequency_penalty: Some(FREQUENCY_PENALTY_RANGE),
+        presence_penalty: Some(PRESENCE_PENALTY_RANGE),
+        // Zero is not allowed
+        repetition_penalty: Some((f32::MIN_POSITIVE, 2.0)),
+        seed: Some((i64::MIN, i64::MAX)),
+        logit_bias: true,
+    };
+
+    /// Nothing, the engine samples in its own way
+    pub const NONE: SamplingCaps = SamplingCaps {
+        temperature: None,
+        top_p: None,
+        top_k: None,
+        min_p: None,
+        frequency_penalty: None,
+        presence_penalty: None,
+        repetition_penalty: None,
+        seed: None,
+        logit_bias: false,
+    };
+
+    /// The capabilities of the engine of this name, as set in
+    /// [`crate::model_card::model::ModelDeploymentCard::engine`]. Unknown engines are assumed
+    /// to support everything.
+    pub fn for_engine(engine: Option<&str>) -> SamplingCaps {
+        match engine {
+            // vllm's SamplingParams has them all
+            Some("vllm") => SamplingCaps::ALL,
+            // dynamo-run's sglang worker only passes the temperature on
+            Some("sglang") => SamplingCaps {
+                temperature: Some(TEMPERATURE_RANGE),
+                ..SamplingCaps::NONE
+            },
+            // Greedy, or a grammar then greedy, after the logit bias
+            Some("llamacpp") => SamplingCaps {
+                logit_bias: true,
+                ..SamplingCaps::NONE
+            },
+            Some("mistralrs") => SamplingCaps {
+                temperature: Some(TEMPERATURE_RANGE),
+                top_p: Some(TOP_P_RANGE),
+                frequency_penalty: Some(FREQUENCY_PENALTY_RANGE),
+                presence_penalty: Some(PRESENCE_PENALTY_RANGE),
+                logit_bias: true,
+                ..SamplingCaps::NONE
+            },
+            _ => SamplingCaps::ALL,
+        }
+    }
+}
+
+impl Default for SamplingCaps {
+    fn default() -> Self {
+        SamplingCaps::ALL
+    }
+}
+
+/// Checks sampling options against an engine's [`SamplingCaps`]. The default validator
+/// rejects values the API does not allow.
+#[derive(Debug, Clone, Default)]
+pub struct SamplingValidator {
+    engine: Option<String>,
+    caps: SamplingCaps,
+    out_of_range: OutOfRange,
+    /// The logit bias of the banned words' tokens
+    banned: HashMap<TokenIdType, f32>,
+    /// Seed of the requests without one
+    default_seed: Option<i64>,
+}
+
+impl SamplingValidator {
+    pub fn new(engine: Option<&str>, out_of_range: OutOfRange) -> Self {
+        SamplingValidator {
+            engine: engine.map(|e| e.to_string()),
+            caps: SamplingCaps::for_engine(engine),
+            out_of_range,
+            banned: HashMap::new(),
+            default_seed: None,
+        }
+    }
+
+    /// Validator for `engine`, with the [`OutOfRange`] behavior and the default seed from the
+    /// environment
+    pub fn from_env(engine: Option<&str>) -> Result<Self> {
+        Ok(SamplingValidator::new(engine, OutOfRange::from_env()?)
+            .with_default_seed(default_seed_from_env()?))
+    }
+
+    /// Sample the requests without a seed with `seed`, if the engine takes one
+    pub fn with_default_seed(mut self, seed: Option<i64>) -> Self {
+        self.default_seed = seed;
+        self
+    }
+
+    /// Ban the words of the [`BANNED_WORDS_ENV`] file, if it is set
+    pub fn with_banned_words_from_env(self, tokenizer: &dyn Tokenizer) -> Result<Self> {
+        let path = match std::env::var(BANNED_WORDS_ENV) {
+            Ok(path) if !path.is_empty() => path,
+            _ => return Ok(self),
+        };
+        let words = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed reading banned words from {path}"))?;
+        self.with_banned_words(
+            words.lines().map(str::trim).filter(|w| !w.is_empty()),
+            tokenizer,
+        )
+    }
+
+    /// Never generate these words. A word is banned by a logit bias on its token, with or
+    /// without a leading space, so the engine must support `logit_bias`. A word that is more
+    /// than one token either way can't be banned like that, it is skipped with a warning.
+    pub fn with_banned_words<'a>(
+        mut self,
+        words: impl IntoIterator<Item = &'a str>,
+        tokenizer: &dyn Tokenizer,
+    ) -> Result<Self> {
+        for word in words {
+            if !self.caps.logit_bias {
+                anyhow::bail!(
+                    "Banned words need logit_bias, which {} does not support",
+                    self.engine.as_deref().unwrap_or("this engine")
+                );
+            }
+            let mut banned = false;
+            for text in [word.to_string(), format!(" {word}")] {
+                if let [token_id] = tokenizer.encode(&text)?.token_ids[..] {
+                    self.banned.insert(token_id, BANNED);
+                    banned = true;
+                }
+            }
+            if !banned {
+                tracing::warn!(
+                    word,
+                    "Not banning a word of more than one token, only single tokens can be banned"
+                );
+            }
+        }
+        Ok(self)
+    }
+
+    /// Drop the options the engine doesn't support and clamp or reject out of range values.
+    /// Returns a warning for each change made.
+    pub fn validate(&self, options: &mut SamplingOptions) -> Result<Vec<String>> {
+        let mut warnings = vec![];
+        let caps = &self.caps;
+        self.check(
+            "temperature",
+            &mut options.temperature,
+            caps.temperature,
+            &mut warnings,
+        )?;
+        self.check("top_p", &mut options.top_p, caps.top_p, &mut warnings)?;
+        if options.top_k == Some(-1) && caps.top_k.is_some() {
+            // All tokens, nothing to check
+        } else {
+            self.check("top_k", &mut options.top_k, caps.top_k, &mut warnings)?;
+        }
+        self.check("min_p", &mut options.min_p, caps.min_p, &mut warnings)?;
+        self.check(
+            "frequency_penalty",
+            &mut options.frequency_penalty,
+            caps.frequency_penalty,
+            &mut warnings,
+        )?;
+        self.check(
+            "presence_penalty",
+            &mut options.presence_penalty,
+            caps.presence_penalty,
+            &mut warnings,
+        )?;
+        self.check(
+            "repetition_penalty",
+            &mut options.repetition_penalty,
+            caps.repetition_penalty,
+            &mut warnings,
+        )?;
+        if options.seed.is_none() && caps.seed.is_some() {
+            options.seed = self.default_seed;
+        }
+        self.check("seed", &mut options.seed, caps.seed, &mut warnings)?;
+        if let Some(logit_bias) = options.logit_bias.as_mut() {
+            if !caps.logit_bias {
+                let engine = self.engine.as_deref().unwrap_or("this engine");
+                return Err(RequestError::invalid_request(format!(
+                    "logit_bias is not supported by {engine}"
+                ))
+                .into());
+            }
+            for (token_id, bias) in logit_bias.iter_mut() {
+                let mut value = Some(*bias);
+                self.check(
+                    &format!("logit_bias of token {token_id}"),
+                    &mut value,
+                    Some(LOGIT_BIAS_RANGE),
+                    &mut warnings,
+                )?;
+                *bias = value.unwrap_or(*bias);
+            }
+        }
+        if !self.banned.is_empty() {
+            options
+                .logit_bias
+                .get_or_insert_default()
+                .extend(&self.banned);
+        }
+        Ok(warnings)
+    }
+
+    fn check<T>(
+        &self,
+        name: &str,
+        value: &mut Option<T>,
+        range: Option<(T, T)>,
+        warnings: &mut Vec<String>,
+    ) -> Result<()>
+    where
+        T: PartialOrd + Display + Copy,
+    {
+        let Some(v) = *value else {
+            return Ok(());
+        };
+        let Some(range) = range else {
+            let engine = self.engine.as_deref().unwrap_or("this engine");
+            warnings.push(format!(
+                "{name} is not supported by {engine} and was ignored"
+            ));
+            *value = None;
+            return Ok(());
+        };
+        if let Err(err) = validate_range(Some(v), &range) {
+            match self.out_of_range {
+                OutOfRange::Reject => anyhow::bail!("Error validating {name}: {err}"),
+                OutOfRange::Clamp => {
+                    let clamped = if v < range.0 { range.0 } else { range.1 };
+                    warnings.push(format!(
+                        "{name} {v} is out of range [{}, {}], clamped to {clamped}",
+                        range.0, range.1
+                    ));
+                    *value = Some(clamped);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn validate_range<T>(value: Option<T>, range: &(T, T)) -> Result<Option<T>>
+where
+    T: PartialOrd + Display,
+{
+    if value.is_none() {
+        return Ok(None);
+    }
+    let value = value.unwrap();
+    if value < range.0 || value > range.1 {
+        anyhow::bail!("Value {} is out of range [{}, {}]", value, range.0, range.1);
+    }
+    Ok(Some(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_range() {
+        assert_eq!(validate_range(Some(0.5), &(0.0, 1.0)).unwrap(), Some(0.5));
+        assert_eq!(validate_range(Some(0.0), &(0.0, 1.0)).unwrap(), Some(0.0));
+        assert_eq!(validate_range(Some(1.0), &(1.0, 1.0)).unwrap(), Some(1.0));
+        assert_eq!(validate_range(Some(1_i32), &(1, 1)).unwrap(), Some(1));
+        assert_eq!(
+            validate_range(Some(1.1), &(0.0, 1.0))
+                .unwrap_err()
+                .to_string(),
+            "Value 1.1 is out of range [0, 1]"
+        );
+        assert_eq!(
+            validate_range(Some(-0.1), &(0.0, 1.0))
+                .unwrap_err()
+                .to_string(),
+            "Value -0.1 is out of range [0, 1]"
+        );
+    }
+
+    #[test]
+    fn test_sampling_validator() {
+        let options = SamplingOptions {
+            temperature: Some(3.0),
+            top_k: Some(-1),
+            seed: Some(42),
+            ..Default::default()
+        };
+
+        let reject = SamplingValidator::default();
+        let err = reject.validate(&mut options.clone()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Error validating temperature: Value 3 is out of range [0, 2]"
+        );
+
+        let clamp = SamplingValidator::new(Some("vllm"), OutOfRange::Clamp);
+        let mut clamped = options.clone();
+        let warnings = clamp.validate(&mut clamped).unwrap();
+        assert_eq!(clamped.temperature, Some(2.0));
+        assert_eq!(clamped.top_k, Some(-1));
+        assert_eq!(
+            warnings,
+            vec!["temperature 3 is out of range [0, 2], clamped to 2".to_string()]
+        );
+
+        let greedy = SamplingValidator::new(Some("llamacpp"), OutOfRange::Reject);
+        let mut ignored = options;
+        let warnings = greedy.validate(&mut ignored).unwrap();
+        assert_eq!(ignored.temperature, None);
+        assert_eq!(ignored.top_k, None);
+        assert_eq!(ignored.seed, None);
+        assert_eq!(warnings.len(), 3);
+        assert_eq!(
+            warnings[0],
+            "temperature is not supported by llamacpp and was ignored"
+        );
+    }
+
+    #[test]
+    fn test_default_seed() {
+        let vllm =
+            SamplingValidator::new(Some("vllm"), OutOfRange::Reject).with_default_seed(Some(7));
+        let mut options = SamplingOptions::default();
+        vllm.validate(&mut options).unwrap();
+        assert_eq!(options.seed, Some(7));
+        options.seed = Some(42);
+        vllm.validate(&mut options).unwrap();
+        assert_eq!(options.seed, Some(42));
+
+        let greedy =
+            SamplingValidator::new(Some("llamacpp"), OutOfRange::Reject).with_default_seed(Some(7));
+        let mut options = SamplingOptions::default();
+        assert!(greedy.validate(&mut options).unwrap().is_empty());
+        assert_eq!(options.seed, None);
+    }
+
+    #[test]
+    fn test_logit_bias() {
+        let options = SamplingOptions {
+            logit_bias: Some(HashMap::from([(5, 150.0), (6, -1.0)])),
+            ..Default::default()
+        };
+
+        let err = SamplingValidator::new(Some("sglang"), OutOfRange::Clamp)
+            .validate(&mut options.clone())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "HTTP Error 400: logit_bias is not supported by sglang"
+        );
+
+        let mut clamped = options;
+        let warnings = SamplingValidator::new(Some("llamacpp"), OutOfRange::Clamp)
+            .validate(&mut clamped)
+            .unwrap();
+        assert_eq!(
+            clamped.logit_bias,
+            Some(HashMap::from([(5, 100.0), (6, -1.0)]))
+        );
+        assert_eq!(warnings.len(), 1);
+
+        let tokenizer = crate::tokenizers::HuggingFaceTokenizer::from_file(
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("tests/data/sample-models/TinyLlama_v1.1/tokenizer.json")
+                .to_str()
+                .unwrap(),
+        )
+        .unwrap();
+        let validator = SamplingValidator::new(Some("llamacpp"), OutOfRange::Reject)
+            .with_banned_words(["hello"], &tokenizer)
+            .unwrap();
+        let mut banned = SamplingOptions::default();
+        validator.validate(&mut banned).unwrap();
+        let banned = banned.logit_bias.unwrap();
+        assert!(!banned.is_empty());
+        assert!(banned.values().all(|bias| *bias == BANNED));
+
+        assert!(SamplingValidator::new(Some("sglang"), OutOfRange::Reject)
+            .with_banned_words(["hello"], &tokenizer)
+            .is_err());
+        // Skipped, the other words are still banned
+        let validator = SamplingValidator::default()
+            .with_banned_words(["antidisestablishmentarianism"], &tokenizer)
+            .unwrap();
+        let mut skipped = SamplingOptions::default();
+        validator.validate(&mut skipped).unwrap();
+        assert_eq!(skipped.logit_bias, None);
+        let validator = SamplingValidator::new(Some("llamacpp"), OutOfRange::Reject)
+            .with_banned_words(["antidisestablishmentarianism", "hello"], &tokenizer)
+            .unwrap();
+        let mut mixed = SamplingOptions::default();
+        validator.validate(&mut mixed).unwrap();
+        assert_eq!(mixed.logit_bias.unwrap().len(), banned.len());
+    }
+}
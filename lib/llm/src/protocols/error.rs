@@ -0,0 +1,208 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Errors of the request path
+//!
+//! The pre-processor, the router and the engines fail a request with a [`RequestError`] of some
+//! [`ErrorKind`]. The kind sets the HTTP status and the `code` of the error response, so that
+//! clients and retriers can tell bad input (don't retry) from overload (retry later) from a
+//! failed engine (retry, maybe elsewhere).
+//!
+//! As text, the error starts with its kind in brackets, e.g. `[engine_failure] llama_decode
+//! failed`. That is how the kind survives where errors become strings: error events in the
+//! response stream, the finish reason of an engine output, the trip back from a remote worker.
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// What went wrong with a request, as clients need to tell apart. The names are the `code` of
+/// error responses and don't change.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// The request is malformed, or asks for something the model or engine can't do
+    InvalidRequest,
+
+    /// The prompt, or the prompt and the completion, are longer than the model takes
+    ContextLengthExceeded,
+
+    /// The content filter rejected the prompt
+    ContentFilter,
+
+    /// We don't know who is calling: no API key, or an unknown one
+    Unauthenticated,
+
+    /// The caller may not do this
+    Forbidden,
+
+    /// No such model, or other resource
+    NotFound,
+
+    /// Too many requests, from this caller or for this model. Retry later.
+    Overloaded,
+
+    /// Nothing can serve the request at the moment, such as a model without workers
+    Unavailable,
+
+    /// The request ran past its deadline
+    Timeout,
+
+    /// The engine failed while generating. Another try, perhaps on another worker, may work.
+    EngineFailure,
+
+    /// A bug or a misconfiguration of the service
+    Internal,
+}
+
+impl ErrorKind {
+    pub const ALL: [ErrorKind; 11] = [
+        ErrorKind::InvalidRequest,
+        ErrorKind::ContextLengthExceeded,
+        ErrorKind::ContentFilter,
+        ErrorKind::Unauthenticated,
+        ErrorKind::Forbidden,
+        ErrorKind::NotFound,
+        ErrorKind::Overloaded,
+        ErrorKind::Unavailable,
+        ErrorKind::Timeout,
+        ErrorKind::EngineFailure,
+        ErrorKind::Internal,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorKind::InvalidRequest => "invalid_request",
+            ErrorKind::ContextLengthExceeded => "context_length_exceeded",
+            ErrorKind::ContentFilter => "content_filter",
+            ErrorKind::Unauthenticated => "unauthenticated",
+            ErrorKind::Forbidden => "forbidden",
+            ErrorKind::NotFound => "not_found",
+            ErrorKind::Overloaded => "overloaded",
+            ErrorKind::Unavailable => "unavailable",
+            ErrorKind::Timeout => "timeout",
+            ErrorKind::EngineFailure => "engine_failure",
+            ErrorKind::Internal => "internal",
+        }
+    }
+
+    /// The HTTP status of the error response
+    pub fn status(&self) -> u16 {
+        match self {
+            ErrorKind::InvalidRequest
+            | ErrorKind::ContextLengthExceeded
+            | ErrorKind::ContentFilter => 400,
+            ErrorKind::Unauthenticated => 401,
+            ErrorKind::Forbidden => 403,
+            ErrorKind::NotFound => 404,
+            ErrorKind::Overloaded => 429,
+            ErrorKind::Unavailable => 503,
+            ErrorKind::Timeout => 504,
+            ErrorKind::EngineFailure | ErrorKind::Internal => 500,
+        }
+    }
+
+    /// The kind of an error response with this status
+    pub fn from_status(status: u16) -> ErrorKind {
+        match status {
+            401 => ErrorKind::Unauthenticated,
+            403 => ErrorKind::Forbidden,
+            404 => ErrorKind::NotFound,
+            429 => ErrorKind::Overloaded,
+            503 => ErrorKind::Unavailable,
+            504 => ErrorKind::Timeout,
+            400..=499 => ErrorKind::InvalidRequest,
+            _ => ErrorKind::Internal,
+        }
+    }
+
+    /// Whether the same request may succeed later
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            ErrorKind::Overloaded
+                | ErrorKind::Unavailable
+                | ErrorKind::Timeout
+                | ErrorKind::EngineFailure
+        )
+    }
+}
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for ErrorKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ErrorKind::ALL
+            .into_iter()
+            .find(|kind| kind.as_str() == s)
+            .ok_or_else(|| format!("unknown error kind '{s}'"))
+    }
+}
+
+/// A failed request, see [`ErrorKind`]
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("[{kind}] {message}")]
+pub struct RequestError {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl RequestError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        RequestError {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    pub fn invalid_request(message: impl Into<String>) -> Self {
+        RequestError::new(ErrorKind::InvalidRequest, message)
+    }
+
+    pub fn engine_failure(message: impl Into<String>) -> Self {
+        RequestError::new(ErrorKind::EngineFailure, message)
+    }
+
+    /// Back from the error as text. None if the message doesn't start with a kind.
+    pub fn from_event_message(message: &str) -> Option<RequestError> {
+        let (kind, message) = message.strip_prefix('[')?.split_once("] ")?;
+        Some(RequestError::new(kind.parse().ok()?, message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_message() {
+        let err = RequestError::new(ErrorKind::Overloaded, "Model m is busy");
+        assert_eq!(err.to_string(), "[overloaded] Model m is busy");
+        assert_eq!(
+            RequestError::from_event_message(&err.to_string()),
+            Some(err)
+        );
+        assert_eq!(RequestError::from_event_message("[nonsense] oops"), None);
+        assert_eq!(
+            RequestError::from_event_message("llama_decode failed"),
+            None
+        );
+    }
+}
@@ -15,19 +15,20 @@
 
 pub mod chat_completions;
 pub mod completions;
+pub mod embeddings;
 pub mod models;
 pub mod nvext;
+pub mod tokenize;
+pub mod transcriptions;
 
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use std::{
-    fmt::Display,
-    ops::{Add, Div, Mul, Sub},
-};
+use std::collections::HashMap;
+use std::ops::{Add, Div, Mul, Sub};
 
 use super::{
     common::{self, SamplingOptionsProvider, StopConditionsProvider},
-    ContentProvider,
+    ContentProvider, TokenIdType,
 };
 
 /// Minimum allowed value for OpenAI's `temperature` sampling option
@@ -66,6 +67,15 @@ pub const MAX_PRESENCE_PENALTY: f32 = 2.0;
 /// Allowed range of values for OpenAI's `presence_penalty` sampling option
 pub const PRESENCE_PENALTY_RANGE: (f32, f32) = (MIN_PRESENCE_PENALTY, MAX_PRESENCE_PENALTY);
 
+/// Allowed range of values for each bias of OpenAI's `logit_bias` sampling option
+pub const LOGIT_BIAS_RANGE: (f32, f32) = (-100.0, 100.0);
+
+/// Maximum of OpenAI's `top_logprobs` chat completion option
+pub const MAX_TOP_LOGPROBS: u8 = 20;
+
+/// Maximum of OpenAI's `logprobs` completion option
+pub const MAX_COMPLETION_LOGPROBS: u8 = 5;
+
 /// Usage statistics for the completion request
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct CompletionUsage {
@@ -140,6 +150,18 @@ trait OpenAISamplingOptionsProvider {
 
     fn get_presence_penalty(&self) -> Option<f32>;
 
+    fn get_seed(&self) -> Option<i64> {
+        None
+    }
+
+    /// Output constraints from the request's `response_format`
+    fn get_guided_decoding(&self) -> Option<common::GuidedDecodingOptions> {
+        None
+    }
+
+    /// `logit_bias` as sent, token ids as strings
+    fn get_logit_bias(&self) -> Option<&HashMap<String, serde_json::Value>>;
+
     fn nvext(&self) -> Option<&nvext::NvExt>;
 }
 
@@ -154,23 +176,19 @@ trait OpenAIStopConditionsProvider {
 }
 
 impl<T: OpenAISamplingOptionsProvider> SamplingOptionsProvider for T {
+    /// The options as requested. The pre-processor checks them against the engine, see
+    /// [`common::sampling`].
     fn extract_sampling_options(&self) -> Result<common::SamplingOptions> {
-        // let result = self.validate();
-        // if let Err(e) = result {
-        //     return Err(format!("Error validating sampling options: {}", e));
-        // }
-
-        let mut temperature = validate_range(self.get_temperature(), &TEMPERATURE_RANGE)
-            .map_err(|e| anyhow::anyhow!("Error validating temperature: {}", e))?;
-        let mut top_p = validate_range(self.get_top_p(), &TOP_P_RANGE)
-            .map_err(|e| anyhow::anyhow!("Error validating top_p: {}", e))?;
-        let frequency_penalty =
-            validate_range(self.get_frequency_penalty(), &FREQUENCY_PENALTY_RANGE)
-                .map_err(|e| anyhow::anyhow!("Error validating frequency_penalty: {}", e))?;
-        let presence_penalty = validate_range(self.get_presence_penalty(), &PRESENCE_PENALTY_RANGE)
-            .map_err(|e| anyhow::anyhow!("Error validating presence_penalty: {}", e))?;
+        let mut temperature = self.get_temperature();
+        let mut top_p = self.get_top_p();
+        let mut top_k = None;
+        let mut repetition_penalty = None;
 
         if let Some(nvext) = self.nvext() {
+            top_k = nvext
+                .top_k
+                .map(|k| k.clamp(i32::MIN as i64, i32::MAX as i64) as i32);
+            repetition_penalty = nvext.repetition_penalty.map(|p| p as f32);
             let greedy = nvext.greed_sampling.unwrap_or(false);
             if greedy {
                 top_p = None;
@@ -181,20 +199,41 @@ impl<T: OpenAISamplingOptionsProvider> SamplingOptionsProvider for T {
         Ok(common::SamplingOptions {
             n: None,
             best_of: None,
-            frequency_penalty,
-            presence_penalty,
-            repetition_penalty: None,
+            frequency_penalty: self.get_frequency_penalty(),
+            presence_penalty: self.get_presence_penalty(),
+            repetition_penalty,
             temperature,
             top_p,
-            top_k: None,
+            top_k,
             min_p: None,
-            seed: None,
+            seed: self.get_seed(),
             use_beam_search: None,
             length_penalty: None,
+            guided_decoding: self.get_guided_decoding(),
+            logit_bias: self.get_logit_bias().map(parse_logit_bias).transpose()?,
         })
     }
 }
 
+/// The token ids and biases of an OpenAI `logit_bias`. The range of the biases is checked
+/// with the other sampling options.
+fn parse_logit_bias(
+    logit_bias: &HashMap<String, serde_json::Value>,
+) -> Result<HashMap<TokenIdType, f32>> {
+    logit_bias
+        .iter()
+        .map(|(token, bias)| {
+            let Ok(token_id) = token.parse::<TokenIdType>() else {
+                anyhow::bail!("logit_bias key '{token}' is not a token id");
+            };
+            let Some(bias) = bias.as_f64() else {
+                anyhow::bail!("logit_bias of token {token_id} is not a number: {bias}");
+            };
+            Ok((token_id, bias as f32))
+        })
+        .collect()
+}
+
 impl<T: OpenAIStopConditionsProvider> StopConditionsProvider for T {
     fn extract_stop_conditions(&self) -> Result<common::StopConditions> {
         let max_tokens = self.get_max_tokens();
@@ -208,16 +247,18 @@ impl<T: OpenAIStopConditionsProvider> StopConditionsProvider for T {
         }
 
         let mut ignore_eos = None;
+        let mut stop_token_ids_hidden = None;
 
         if let Some(nvext) = self.nvext() {
             ignore_eos = nvext.ignore_eos;
+            stop_token_ids_hidden = nvext.stop_token_ids.clone();
         }
 
         Ok(common::StopConditions {
             max_tokens,
             min_tokens,
             stop,
-            stop_token_ids_hidden: None,
+            stop_token_ids_hidden,
             ignore_eos,
         })
     }
@@ -260,21 +301,6 @@ pub struct GenericCompletionResponse<C>
     // TODO() - add NvResponseExtention
 }
 
-// todo - move to common location
-fn validate_range<T>(value: Option<T>, range: &(T, T)) -> Result<Option<T>>
-where
-    T: PartialOrd + Display,
-{
-    if value.is_none() {
-        return Ok(None);
-    }
-    let value = value.unwrap();
-    if value < range.0 || value > range.1 {
-        anyhow::bail!("Value {} is out of range [{}, {}]", value, range.0, range.1);
-    }
-    Ok(Some(value))
-}
-
 // todo - move to common location
 /// scale value in `src` range to `dst` range
 pub fn scale_value<T>(value: &T, src: &(T, T), dst: &(T, T)) -> Result<T>
@@ -313,26 +339,6 @@ mod tests {
 
     use super::*;
 
-    #[test]
-    fn test_validate_range() {
-        assert_eq!(validate_range(Some(0.5), &(0.0, 1.0)).unwrap(), Some(0.5));
-        assert_eq!(validate_range(Some(0.0), &(0.0, 1.0)).unwrap(), Some(0.0));
-        assert_eq!(validate_range(Some(1.0), &(1.0, 1.0)).unwrap(), Some(1.0));
-        assert_eq!(validate_range(Some(1_i32), &(1, 1)).unwrap(), Some(1));
-        assert_eq!(
-            validate_range(Some(1.1), &(0.0, 1.0))
-                .unwrap_err()
-                .to_string(),
-            "Value 1.1 is out of range [0, 1]"
-        );
-        assert_eq!(
-            validate_range(Some(-0.1), &(0.0, 1.0))
-                .unwrap_err()
-                .to_string(),
-            "Value -0.1 is out of range [0, 1]"
-        );
-    }
-
     #[test]
     fn test_scaled_value() {
         assert_eq!(scale_value(&0.5, &(0.0, 1.0), &(0.0, 2.0)).unwrap(), 1.0);
@@ -13,10 +13,15 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
+
+use super::common::{GuidedDecodingOptions, OutputOptions, OutputOptionsProvider};
 use super::nvext::NvExt;
 use super::nvext::NvExtProvider;
+use super::nvext::NvResponseExt;
 use super::OpenAISamplingOptionsProvider;
 use super::OpenAIStopConditionsProvider;
+use super::MAX_TOP_LOGPROBS;
 use dynamo_runtime::protocols::annotated::AnnotationsProvider;
 use serde::{Deserialize, Serialize};
 use validator::Validate;
@@ -49,10 +54,14 @@ pub struct NvCreateChatCompletionRequest {
 /// # Fields
 /// - `inner`: The base OpenAI unary chat completion response, embedded
 ///   using `serde(flatten)`.
+/// - `nvext`: The optional NVIDIA extension field. See [`NvResponseExt`].
 #[derive(Serialize, Deserialize, Validate, Debug, Clone)]
 pub struct NvCreateChatCompletionResponse {
     #[serde(flatten)]
     pub inner: async_openai::types::CreateChatCompletionResponse,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nvext: Option<NvResponseExt>,
 }
 
 /// A response structure for streamed chat completions, embedding OpenAI's
@@ -61,10 +70,15 @@ pub struct NvCreateChatCompletionResponse {
 /// # Fields
 /// - `inner`: The base OpenAI streaming chat completion response, embedded
 ///   using `serde(flatten)`.
+/// - `nvext`: The optional NVIDIA extension field. Warnings are on the first chunk, timings
+///   on a last chunk of their own. See [`NvResponseExt`].
 #[derive(Serialize, Deserialize, Validate, Debug, Clone)]
 pub struct NvCreateChatCompletionStreamResponse {
     #[serde(flatten)]
     pub inner: async_openai::types::CreateChatCompletionStreamResponse,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nvext: Option<NvResponseExt>,
 }
 
 /// Implements `NvExtProvider` for `NvCreateChatCompletionRequest`,
@@ -130,6 +144,31 @@ impl OpenAISamplingOptionsProvider for NvCreateChatCompletionRequest {
         self.inner.presence_penalty
     }
 
+    /// Retrieves the seed for deterministic sampling, if set.
+    fn get_seed(&self) -> Option<i64> {
+        self.inner.seed
+    }
+
+    /// Converts `response_format` to guided decoding constraints. `json_object`, or a
+    /// `json_schema` without a schema, allows any JSON object.
+    fn get_guided_decoding(&self) -> Option<GuidedDecodingOptions> {
+        use async_openai::types::ResponseFormat;
+        let any_object = || serde_json::json!({"type": "object"});
+        let json = match self.inner.response_format.as_ref()? {
+            ResponseFormat::Text => return None,
+            ResponseFormat::JsonObject => any_object(),
+            ResponseFormat::JsonSchema { json_schema } => {
+                json_schema.schema.clone().unwrap_or_else(any_object)
+            }
+        };
+        Some(GuidedDecodingOptions { json: Some(json) })
+    }
+
+    /// Retrieves the per-token logit biases, if set.
+    fn get_logit_bias(&self) -> Option<&HashMap<String, serde_json::Value>> {
+        self.inner.logit_bias.as_ref()
+    }
+
     /// Returns a reference to the optional `NvExt` extension, if available.
     fn nvext(&self) -> Option<&NvExt> {
         self.nvext.as_ref()
@@ -173,3 +212,25 @@ impl OpenAIStopConditionsProvider for NvCreateChatCompletionRequest {
         self.nvext.as_ref()
     }
 }
+
+impl OutputOptionsProvider for NvCreateChatCompletionRequest {
+    /// `logprobs: true` asks for the log probability of each token, `top_logprobs` for that
+    /// many alternatives at each position as well.
+    fn extract_output_options(&self) -> anyhow::Result<OutputOptions> {
+        let top_logprobs = self.inner.top_logprobs.unwrap_or(0);
+        if top_logprobs > MAX_TOP_LOGPROBS {
+            anyhow::bail!("top_logprobs must be at most {MAX_TOP_LOGPROBS}");
+        }
+        let logprobs = match self.inner.logprobs {
+            Some(true) => Some(top_logprobs.into()),
+            _ if self.inner.top_logprobs.is_some() => {
+                anyhow::bail!("top_logprobs needs logprobs to be true")
+            }
+            _ => None,
+        };
+        Ok(OutputOptions {
+            logprobs,
+            ..Default::default()
+        })
+    }
+}
@@ -13,7 +13,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::{NvCreateChatCompletionResponse, NvCreateChatCompletionStreamResponse};
+use super::{NvCreateChatCompletionResponse, NvCreateChatCompletionStreamResponse, NvResponseExt};
 use crate::protocols::{
     codec::{Message, SseCodecError},
     convert_sse_stream, Annotated,
@@ -45,6 +45,8 @@ pub struct DeltaAggregator {
     error: Option<String>,
     /// Optional service tier information for the response.
     service_tier: Option<async_openai::types::ServiceTierResponse>,
+    /// NVIDIA extensions sent with the chunks.
+    nvext: Option<NvResponseExt>,
 }
 
 /// Represents the accumulated state of a single chat choice during streaming aggregation.
@@ -53,6 +55,8 @@ struct DeltaChoice {
     index: u32,
     /// The accumulated text content for the choice.
     text: String,
+    /// The accumulated tool calls, in the order of their index.
+    tool_calls: Vec<async_openai::types::ChatCompletionMessageToolCall>,
     /// The role associated with this message (e.g., `system`, `user`, `assistant`).
     role: Option<async_openai::types::Role>,
     /// The reason the completion was finished (if applicable).
@@ -80,6 +84,7 @@ impl DeltaAggregator {
             choices: HashMap::new(),
             error: None,
             service_tier: None,
+            nvext: None,
         }
     }
 
@@ -121,6 +126,12 @@ impl DeltaAggregator {
                     if let Some(system_fingerprint) = delta.inner.system_fingerprint {
                         aggregator.system_fingerprint = Some(system_fingerprint);
                     }
+                    if let Some(nvext) = delta.nvext {
+                        aggregator
+                            .nvext
+                            .get_or_insert_with(Default::default)
+                            .merge(nvext);
+                    }
 
                     // Aggregate choices incrementally.
                     for choice in delta.inner.choices {
@@ -131,9 +142,10 @@ impl DeltaAggregator {
                                 .or_insert(DeltaChoice {
                                     index: choice.index,
                                     text: "".to_string(),
+                                    tool_calls: Vec::new(),
                                     role: choice.delta.role,
                                     finish_reason: None,
-                                    logprobs: choice.logprobs,
+                                    logprobs: None,
                                 });
 
                         // Append content if available.
@@ -141,6 +153,23 @@ impl DeltaAggregator {
                             state_choice.text.push_str(content);
                         }
 
+                        // Tool calls are streamed as a first chunk with the id and name, and
+                        // optionally more chunks with the rest of the arguments.
+                        for chunk in choice.delta.tool_calls.into_iter().flatten() {
+                            state_choice.apply_tool_call_chunk(chunk);
+                        }
+
+                        // Each chunk has the log probabilities of its own tokens.
+                        if let Some(logprobs) = choice.logprobs {
+                            match state_choice.logprobs.as_mut() {
+                                Some(all) => all
+                                    .content
+                                    .get_or_insert_with(Vec::new)
+                                    .extend(logprobs.content.into_iter().flatten()),
+                                None => state_choice.logprobs = Some(logprobs),
+                            }
+                        }
+
                         // Update finish reason if provided.
                         if let Some(finish_reason) = choice.finish_reason {
                             state_choice.finish_reason = Some(finish_reason);
@@ -179,12 +208,47 @@ impl DeltaAggregator {
             service_tier: aggregator.service_tier,
         };
 
-        let response = NvCreateChatCompletionResponse { inner };
+        let response = NvCreateChatCompletionResponse {
+            inner,
+            nvext: aggregator.nvext,
+        };
 
         Ok(response)
     }
 }
 
+impl DeltaChoice {
+    fn apply_tool_call_chunk(
+        &mut self,
+        chunk: async_openai::types::ChatCompletionMessageToolCallChunk,
+    ) {
+        let index = chunk.index as usize;
+        while self.tool_calls.len() <= index {
+            self.tool_calls
+                .push(async_openai::types::ChatCompletionMessageToolCall {
+                    id: String::new(),
+                    r#type: async_openai::types::ChatCompletionToolType::Function,
+                    function: async_openai::types::FunctionCall {
+                        name: String::new(),
+                        arguments: String::new(),
+                    },
+                });
+        }
+        let tool_call = &mut self.tool_calls[index];
+        if let Some(id) = chunk.id {
+            tool_call.id = id;
+        }
+        if let Some(function) = chunk.function {
+            if let Some(name) = function.name {
+                tool_call.function.name.push_str(&name);
+            }
+            if let Some(arguments) = function.arguments {
+                tool_call.function.arguments.push_str(&arguments);
+            }
+        }
+    }
+}
+
 #[allow(deprecated)]
 impl From<DeltaChoice> for async_openai::types::ChatChoice {
     /// Converts a [`DeltaChoice`] into an [`async_openai::types::ChatChoice`].
@@ -195,8 +259,17 @@ impl From<DeltaChoice> for async_openai::types::ChatChoice {
         async_openai::types::ChatChoice {
             message: async_openai::types::ChatCompletionResponseMessage {
                 role: delta.role.expect("delta should have a Role"),
-                content: Some(delta.text),
-                tool_calls: None,
+                // Like OpenAI, a message with tool calls and no text has no content
+                content: if delta.text.is_empty() && !delta.tool_calls.is_empty() {
+                    None
+                } else {
+                    Some(delta.text)
+                },
+                tool_calls: if delta.tool_calls.is_empty() {
+                    None
+                } else {
+                    Some(delta.tool_calls)
+                },
                 refusal: None,
                 function_call: None,
                 audio: None,
@@ -278,7 +351,7 @@ mod tests {
             object: "chat.completion".to_string(),
         };
 
-        let data = NvCreateChatCompletionStreamResponse { inner };
+        let data = NvCreateChatCompletionStreamResponse { inner, nvext: None };
 
         Annotated {
             data: Some(data),
@@ -420,7 +493,10 @@ mod tests {
             object: "chat.completion".to_string(),
         };
 
-        let data = NvCreateChatCompletionStreamResponse { inner: delta };
+        let data = NvCreateChatCompletionStreamResponse {
+            inner: delta,
+            nvext: None,
+        };
 
         // Wrap it in Annotated and create a stream
         let annotated_delta = Annotated {
@@ -459,4 +535,63 @@ mod tests {
         );
         assert_eq!(choice1.message.role, async_openai::types::Role::Assistant);
     }
+
+    #[tokio::test]
+    async fn test_tool_calls() {
+        use crate::protocols::common::{llm_backend::BackendOutput, FinishReason};
+        use crate::protocols::openai::{
+            chat_completions::delta::{DeltaGenerator, DeltaGeneratorOptions},
+            DeltaGeneratorExt,
+        };
+
+        let options = DeltaGeneratorOptions {
+            enable_tool_calls: true,
+            ..Default::default()
+        };
+        let mut generator = DeltaGenerator::new("test-model".to_string(), options);
+        let chunks = [
+            "<tool_call>\n{\"name\": \"get_weather\", ",
+            "\"arguments\": {\"city\": \"Paris\"}}\n</tool_call>",
+        ];
+        let mut deltas = Vec::new();
+        for (i, chunk) in chunks.iter().enumerate() {
+            let output = BackendOutput {
+                token_ids: vec![],
+                tokens: vec![],
+                text: Some(chunk.to_string()),
+                cum_log_probs: None,
+                log_probs: None,
+                logprobs: None,
+                finish_reason: (i == chunks.len() - 1).then_some(FinishReason::EoS),
+                spec_decode: None,
+            };
+            let data = generator.choice_from_postprocessor(output).unwrap();
+            deltas.push(Annotated {
+                data: Some(data),
+                id: None,
+                event: None,
+                comment: None,
+            });
+        }
+
+        // The call is only complete in the last chunk
+        assert!(deltas[0].data.as_ref().unwrap().inner.choices[0]
+            .delta
+            .tool_calls
+            .is_none());
+
+        let response = DeltaAggregator::apply(Box::pin(stream::iter(deltas)))
+            .await
+            .unwrap();
+        let choice = &response.inner.choices[0];
+        assert_eq!(
+            choice.finish_reason,
+            Some(async_openai::types::FinishReason::ToolCalls)
+        );
+        assert!(choice.message.content.is_none());
+        let tool_calls = choice.message.tool_calls.as_ref().unwrap();
+        assert_eq!(tool_calls.len(), 1);
+        assert_eq!(tool_calls[0].function.name, "get_weather");
+        assert_eq!(tool_calls[0].function.arguments, r#"{"city":"Paris"}"#);
+    }
 }
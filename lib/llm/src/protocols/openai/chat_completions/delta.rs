@@ -13,7 +13,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::{NvCreateChatCompletionRequest, NvCreateChatCompletionStreamResponse};
+use super::{NvCreateChatCompletionRequest, NvCreateChatCompletionStreamResponse, NvResponseExt};
+use crate::preprocessor::tools::{ToolCallParser, ToolCallResponse};
 use crate::protocols::common;
 
 /// Provides a method for generating a [`DeltaGenerator`] from a chat completion request.
@@ -23,9 +24,19 @@ impl NvCreateChatCompletionRequest {
     /// # Returns
     /// * [`DeltaGenerator`] configured with model name and response options.
     pub fn response_generator(&self) -> DeltaGenerator {
+        let has_tools = self
+            .inner
+            .tools
+            .as_ref()
+            .is_some_and(|tools| !tools.is_empty());
         let options = DeltaGeneratorOptions {
             enable_usage: true,
             enable_logprobs: self.inner.logprobs.unwrap_or(false),
+            enable_tool_calls: has_tools
+                && !matches!(
+                    self.inner.tool_choice,
+                    Some(async_openai::types::ChatCompletionToolChoiceOption::None)
+                ),
         };
 
         DeltaGenerator::new(self.inner.model.clone(), options)
@@ -39,6 +50,8 @@ pub struct DeltaGeneratorOptions {
     pub enable_usage: bool,
     /// Determines whether log probabilities should be included in the response.
     pub enable_logprobs: bool,
+    /// Determines whether tool calls in the model output are returned as `tool_calls`.
+    pub enable_tool_calls: bool,
 }
 
 /// Generates incremental chat completion responses in a streaming fashion.
@@ -62,6 +75,12 @@ pub struct DeltaGenerator {
     msg_counter: u64,
     /// Configuration options for response generation.
     options: DeltaGeneratorOptions,
+    /// Extracts tool calls from the generated text, if enabled.
+    tool_parser: Option<ToolCallParser>,
+    /// Warnings about the request, sent in the `nvext` of the first chunk.
+    warnings: Vec<String>,
+    /// The seed the request is sampled with, sent in the `nvext` of the first chunk.
+    seed: Option<i64>,
 }
 
 impl DeltaGenerator {
@@ -98,6 +117,9 @@ impl DeltaGenerator {
             service_tier: None,
             usage,
             msg_counter: 0,
+            tool_parser: options.enable_tool_calls.then(ToolCallParser::new),
+            warnings: vec![],
+            seed: None,
             options,
         }
     }
@@ -110,6 +132,22 @@ impl DeltaGenerator {
         self.usage.prompt_tokens = isl;
     }
 
+    /// Sets the warnings about the request, such as sampling options that were changed.
+    ///
+    /// # Arguments
+    /// * `warnings` - Messages for the client, returned in the first chunk.
+    pub fn set_warnings(&mut self, warnings: Vec<String>) {
+        self.warnings = warnings;
+    }
+
+    /// Sets the seed the request is sampled with.
+    ///
+    /// # Arguments
+    /// * `seed` - The seed of the sampling options sent to the engine, if any.
+    pub fn set_seed(&mut self, seed: Option<i64>) {
+        self.seed = seed;
+    }
+
     /// Creates a choice within a chat completion response.
     ///
     /// # Arguments
@@ -120,7 +158,6 @@ impl DeltaGenerator {
     ///
     /// # Returns
     /// * An [`async_openai::types::CreateChatCompletionStreamResponse`] instance representing the choice.
-    #[allow(deprecated)]
     pub fn create_choice(
         &self,
         index: u32,
@@ -128,7 +165,29 @@ impl DeltaGenerator {
         finish_reason: Option<async_openai::types::FinishReason>,
         logprobs: Option<async_openai::types::ChatChoiceLogprobs>,
     ) -> async_openai::types::CreateChatCompletionStreamResponse {
-        // TODO: Update for tool calling
+        self.create_choice_with_tool_calls(index, text, None, finish_reason, logprobs)
+    }
+
+    /// Creates a choice within a chat completion response, carrying tool call deltas.
+    ///
+    /// # Arguments
+    /// * `index` - The index of the choice in the completion response.
+    /// * `text` - The text content for the response.
+    /// * `tool_calls` - Tool call deltas for the response.
+    /// * `finish_reason` - The reason why the response finished (e.g., stop, length, etc.).
+    /// * `logprobs` - Optional log probabilities of the generated tokens.
+    ///
+    /// # Returns
+    /// * An [`async_openai::types::CreateChatCompletionStreamResponse`] instance representing the choice.
+    #[allow(deprecated)]
+    pub fn create_choice_with_tool_calls(
+        &self,
+        index: u32,
+        text: Option<String>,
+        tool_calls: Option<Vec<async_openai::types::ChatCompletionMessageToolCallChunk>>,
+        finish_reason: Option<async_openai::types::FinishReason>,
+        logprobs: Option<async_openai::types::ChatChoiceLogprobs>,
+    ) -> async_openai::types::CreateChatCompletionStreamResponse {
         let delta = async_openai::types::ChatCompletionStreamResponseDelta {
             role: if self.msg_counter == 0 {
                 Some(async_openai::types::Role::Assistant)
@@ -136,7 +195,7 @@ impl DeltaGenerator {
                 None
             },
             content: text,
-            tool_calls: None,
+            tool_calls,
             function_call: None,
             refusal: None,
         };
@@ -187,29 +246,119 @@ impl crate::protocols::openai::DeltaGeneratorExt<NvCreateChatCompletionStreamRes
         // Aggregate token usage if enabled.
         if self.options.enable_usage {
             self.usage.completion_tokens += delta.token_ids.len() as u32;
+            self.usage.total_tokens = self.usage.prompt_tokens + self.usage.completion_tokens;
         }
 
-        // TODO: Implement log probabilities aggregation.
-        let logprobs = None;
+        let logprobs = match delta.logprobs {
+            Some(logprobs) if self.options.enable_logprobs => Some(chat_logprobs(logprobs)),
+            _ => None,
+        };
 
         // Map backend finish reasons to OpenAI's finish reasons.
-        let finish_reason = match delta.finish_reason {
+        let mut finish_reason = match delta.finish_reason {
             Some(common::FinishReason::EoS) => Some(async_openai::types::FinishReason::Stop),
             Some(common::FinishReason::Stop) => Some(async_openai::types::FinishReason::Stop),
             Some(common::FinishReason::Length) => Some(async_openai::types::FinishReason::Length),
             Some(common::FinishReason::Cancelled) => Some(async_openai::types::FinishReason::Stop),
+            Some(common::FinishReason::ContentFilter) => {
+                Some(async_openai::types::FinishReason::ContentFilter)
+            }
             Some(common::FinishReason::Error(err_msg)) => {
                 return Err(anyhow::anyhow!(err_msg));
             }
             None => None,
         };
 
+        // Split tool calls out of the text.
+        let (text, tool_calls) = match self.tool_parser.as_mut() {
+            Some(parser) => {
+                let first_index = parser.tool_call_count();
+                let mut parsed = parser.push(delta.text.as_deref().unwrap_or_default());
+                if finish_reason.is_some() {
+                    let rest = parser.finish();
+                    if let Some(content) = rest.content {
+                        parsed
+                            .content
+                            .get_or_insert_with(String::new)
+                            .push_str(&content);
+                    }
+                    parsed.tool_calls.extend(rest.tool_calls);
+                    if parser.tool_call_count() > 0
+                        && finish_reason == Some(async_openai::types::FinishReason::Stop)
+                    {
+                        finish_reason = Some(async_openai::types::FinishReason::ToolCalls);
+                    }
+                }
+                let tool_calls = (!parsed.tool_calls.is_empty())
+                    .then(|| tool_call_chunks(first_index, parsed.tool_calls));
+                (parsed.content, tool_calls)
+            }
+            None => (delta.text, None),
+        };
+
         // Create the streaming response.
         let index = 0;
-        let stream_response = self.create_choice(index, delta.text, finish_reason, logprobs);
+        let stream_response =
+            self.create_choice_with_tool_calls(index, text, tool_calls, finish_reason, logprobs);
 
         Ok(NvCreateChatCompletionStreamResponse {
             inner: stream_response,
+            nvext: NvResponseExt::first_chunk(std::mem::take(&mut self.warnings), self.seed.take()),
+        })
+    }
+}
+
+/// The log probabilities of the tokens of a chunk, in the chat format
+fn chat_logprobs(
+    positions: Vec<common::llm_backend::PositionLogProbs>,
+) -> async_openai::types::ChatChoiceLogprobs {
+    let content = positions
+        .into_iter()
+        .map(|position| {
+            let token = position.token.token.unwrap_or_default();
+            async_openai::types::ChatCompletionTokenLogprob {
+                bytes: Some(token.as_bytes().to_vec()),
+                token,
+                logprob: position.token.logprob as f32,
+                top_logprobs: position
+                    .top
+                    .into_iter()
+                    .map(|top| {
+                        let token = top.token.unwrap_or_default();
+                        async_openai::types::TopLogprobs {
+                            bytes: Some(token.as_bytes().to_vec()),
+                            token,
+                            logprob: top.logprob as f32,
+                        }
+                    })
+                    .collect(),
+            }
         })
+        .collect();
+    async_openai::types::ChatChoiceLogprobs {
+        content: Some(content),
+        refusal: None,
     }
 }
+
+/// Tool calls as stream deltas. Each call is complete, so it is sent in a single chunk.
+fn tool_call_chunks(
+    first_index: usize,
+    calls: Vec<ToolCallResponse>,
+) -> Vec<async_openai::types::ChatCompletionMessageToolCallChunk> {
+    calls
+        .into_iter()
+        .enumerate()
+        .map(
+            |(i, call)| async_openai::types::ChatCompletionMessageToolCallChunk {
+                index: (first_index + i) as u32,
+                id: Some(call.id),
+                r#type: Some(async_openai::types::ChatCompletionToolType::Function),
+                function: Some(async_openai::types::FunctionCallStream {
+                    name: Some(call.function.name),
+                    arguments: Some(call.function.arguments),
+                }),
+            },
+        )
+        .collect()
+}
@@ -26,9 +26,13 @@ pub use aggregator::DeltaAggregator;
 pub use delta::DeltaGenerator;
 
 use super::{
-    common::{self, SamplingOptionsProvider, StopConditionsProvider},
-    nvext::{NvExt, NvExtProvider},
+    common::{
+        self, sampling::SamplingValidator, OutputOptionsProvider, SamplingOptionsProvider,
+        StopConditionsProvider,
+    },
+    nvext::{NvExt, NvExtProvider, NvResponseExt},
     CompletionUsage, ContentProvider, OpenAISamplingOptionsProvider, OpenAIStopConditionsProvider,
+    MAX_COMPLETION_LOGPROBS,
 };
 
 use dynamo_runtime::protocols::annotated::AnnotationsProvider;
@@ -75,8 +79,10 @@ pub struct CompletionResponse {
     /// The optional nature of this field will be relaxed when it is supported.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub system_fingerprint: Option<String>,
-    // TODO(ryan)
-    // pub nvext: Option<NimResponseExt>,
+
+    /// NVIDIA extensions, see [`NvResponseExt`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nvext: Option<NvResponseExt>,
 }
 
 /// Legacy OpenAI CompletionResponse Choice component
@@ -118,6 +124,16 @@ pub struct LogprobResult {
     pub text_offset: Vec<i32>,
 }
 
+impl LogprobResult {
+    /// Append the tokens of a later chunk
+    pub fn extend(&mut self, other: LogprobResult) {
+        self.tokens.extend(other.tokens);
+        self.token_logprobs.extend(other.token_logprobs);
+        self.top_logprobs.extend(other.top_logprobs);
+        self.text_offset.extend(other.text_offset);
+    }
+}
+
 pub fn prompt_to_string(prompt: &async_openai::types::Prompt) -> String {
     match prompt {
         async_openai::types::Prompt::String(s) => s.clone(),
@@ -147,15 +163,20 @@ impl NvExtProvider for CompletionRequest {
     }
 
     fn raw_prompt(&self) -> Option<String> {
-        if let Some(nvext) = self.nvext.as_ref() {
-            if let Some(use_raw_prompt) = nvext.use_raw_prompt {
-                if use_raw_prompt {
-                    return Some(prompt_to_string(&self.inner.prompt));
-                }
-            }
+        if self.use_raw_prompt() {
+            return Some(prompt_to_string(&self.inner.prompt));
         }
         None
     }
+
+    /// A completions prompt is plain text, eval harnesses expect it to reach the model
+    /// untouched. Set `nvext.use_raw_prompt` to false to apply the chat template.
+    fn use_raw_prompt(&self) -> bool {
+        self.nvext
+            .as_ref()
+            .and_then(|ext| ext.use_raw_prompt)
+            .unwrap_or(true)
+    }
 }
 
 impl AnnotationsProvider for CompletionRequest {
@@ -191,6 +212,14 @@ impl OpenAISamplingOptionsProvider for CompletionRequest {
         self.inner.presence_penalty
     }
 
+    fn get_seed(&self) -> Option<i64> {
+        self.inner.seed
+    }
+
+    fn get_logit_bias(&self) -> Option<&HashMap<String, serde_json::Value>> {
+        self.inner.logit_bias.as_ref()
+    }
+
     fn nvext(&self) -> Option<&NvExt> {
         self.nvext.as_ref()
     }
@@ -206,7 +235,10 @@ impl OpenAIStopConditionsProvider for CompletionRequest {
     }
 
     fn get_stop(&self) -> Option<Vec<String>> {
-        None
+        match self.inner.stop.as_ref()? {
+            async_openai::types::Stop::String(s) => Some(vec![s.clone()]),
+            async_openai::types::Stop::StringArray(v) => Some(v.clone()),
+        }
     }
 
     fn nvext(&self) -> Option<&NvExt> {
@@ -214,6 +246,21 @@ impl OpenAIStopConditionsProvider for CompletionRequest {
     }
 }
 
+impl OutputOptionsProvider for CompletionRequest {
+    /// `logprobs: n` asks for the log probability of each token and of the `n` most likely
+    /// tokens at its position
+    fn extract_output_options(&self) -> anyhow::Result<common::OutputOptions> {
+        let logprobs = self.inner.logprobs;
+        if logprobs.is_some_and(|n| n > MAX_COMPLETION_LOGPROBS) {
+            anyhow::bail!("logprobs must be at most {MAX_COMPLETION_LOGPROBS}");
+        }
+        Ok(common::OutputOptions {
+            logprobs: logprobs.map(u32::from),
+            ..Default::default()
+        })
+    }
+}
+
 #[derive(Builder)]
 pub struct ResponseFactory {
     #[builder(setter(into))]
@@ -250,6 +297,7 @@ impl ResponseFactory {
             choices: vec![choice],
             system_fingerprint: self.system_fingerprint.clone(),
             usage,
+            nvext: None,
         }
     }
 }
@@ -288,9 +336,12 @@ impl TryFrom<CompletionRequest> for common::CompletionRequest {
             .extract_stop_conditions()
             .map_err(|e| anyhow::anyhow!("Failed to extract stop conditions: {}", e))?;
 
-        let sampling_options = request
+        let mut sampling_options = request
             .extract_sampling_options()
             .map_err(|e| anyhow::anyhow!("Failed to extract sampling options: {}", e))?;
+        SamplingValidator::default()
+            .validate(&mut sampling_options)
+            .map_err(|e| anyhow::anyhow!("Failed to extract sampling options: {}", e))?;
 
         let prompt = common::PromptType::Completion(common::CompletionContext {
             prompt: prompt_to_string(&request.inner.prompt),
@@ -326,6 +377,7 @@ impl TryFrom<common::StreamingCompletionResponse> for CompletionChoice {
                     return Err(anyhow::anyhow!("finish_reason::error = {}", err_msg));
                 }
                 Some(common::FinishReason::Cancelled) => Some("cancelled".to_string()),
+                Some(common::FinishReason::ContentFilter) => Some("content_filter".to_string()),
                 None => None,
             },
         };
@@ -19,6 +19,7 @@ use anyhow::Result;
 use futures::StreamExt;
 
 use super::{CompletionChoice, CompletionResponse, CompletionUsage, LogprobResult};
+use crate::protocols::openai::nvext::NvResponseExt;
 use crate::protocols::{
     codec::{Message, SseCodecError},
     common::FinishReason,
@@ -32,6 +33,7 @@ pub struct DeltaAggregator {
     created: u64,
     usage: Option<CompletionUsage>,
     system_fingerprint: Option<String>,
+    nvext: Option<NvResponseExt>,
     choices: HashMap<u64, DeltaChoice>,
     error: Option<String>,
 }
@@ -57,6 +59,7 @@ impl DeltaAggregator {
             created: 0,
             usage: None,
             system_fingerprint: None,
+            nvext: None,
             choices: HashMap::new(),
             error: None,
         }
@@ -92,6 +95,12 @@ impl DeltaAggregator {
                     if let Some(system_fingerprint) = delta.system_fingerprint {
                         aggregator.system_fingerprint = Some(system_fingerprint);
                     }
+                    if let Some(nvext) = delta.nvext {
+                        aggregator
+                            .nvext
+                            .get_or_insert_with(Default::default)
+                            .merge(nvext);
+                    }
 
                     // handle the choices
                     for choice in delta.choices {
@@ -103,12 +112,17 @@ impl DeltaAggregator {
                                     index: choice.index,
                                     text: "".to_string(),
                                     finish_reason: None,
-                                    logprobs: choice.logprobs,
+                                    logprobs: None,
                                 });
 
                         state_choice.text.push_str(&choice.text);
 
-                        // todo - handle logprobs
+                        if let Some(logprobs) = choice.logprobs {
+                            match state_choice.logprobs.as_mut() {
+                                Some(all) => all.extend(logprobs),
+                                None => state_choice.logprobs = Some(logprobs),
+                            }
+                        }
 
                         if let Some(finish_reason) = choice.finish_reason {
                             let reason = FinishReason::from_str(&finish_reason).ok();
@@ -143,6 +157,7 @@ impl DeltaAggregator {
             model: aggregator.model,
             object: "text_completion".to_string(),
             system_fingerprint: aggregator.system_fingerprint,
+            nvext: aggregator.nvext,
             choices,
         })
     }
@@ -195,6 +210,7 @@ mod tests {
                 created: 1234567890,
                 usage: None,
                 system_fingerprint: None,
+                nvext: None,
                 choices: vec![CompletionChoice {
                     index,
                     text: text.to_string(),
@@ -296,6 +312,7 @@ mod tests {
                 created: 1234567890,
                 usage: None,
                 system_fingerprint: None,
+                nvext: None,
                 choices: vec![
                     CompletionChoice {
                         index: 0,
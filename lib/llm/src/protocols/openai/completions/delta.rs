@@ -13,8 +13,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::{CompletionChoice, CompletionRequest, CompletionResponse};
+use std::collections::HashMap;
+
+use super::{CompletionChoice, CompletionRequest, CompletionResponse, LogprobResult};
 use crate::protocols::common;
+use crate::protocols::openai::nvext::NvResponseExt;
 use crate::protocols::openai::CompletionUsage;
 
 impl CompletionRequest {
@@ -23,7 +26,7 @@ impl CompletionRequest {
     pub fn response_generator(&self) -> DeltaGenerator {
         let options = DeltaGeneratorOptions {
             enable_usage: true,
-            enable_logprobs: false,
+            enable_logprobs: self.inner.logprobs.is_some(),
         };
 
         DeltaGenerator::new(self.inner.model.clone(), options)
@@ -44,6 +47,12 @@ pub struct DeltaGenerator {
     model: String,
     system_fingerprint: Option<String>,
     usage: CompletionUsage,
+    /// Sent in the `nvext` of the first response
+    warnings: Vec<String>,
+    /// The seed the request is sampled with, also sent in the first response
+    seed: Option<i64>,
+    /// Characters of text generated so far, for the `text_offset` of the logprobs
+    text_offset: i32,
 
     options: DeltaGeneratorOptions,
 }
@@ -62,6 +71,9 @@ impl DeltaGenerator {
             model,
             system_fingerprint: None,
             usage: CompletionUsage::default(),
+            warnings: vec![],
+            seed: None,
+            text_offset: 0,
             options,
         }
     }
@@ -70,6 +82,16 @@ impl DeltaGenerator {
         self.usage.prompt_tokens = isl;
     }
 
+    /// Warnings about the request, such as sampling options that were changed
+    pub fn set_warnings(&mut self, warnings: Vec<String>) {
+        self.warnings = warnings;
+    }
+
+    /// The seed of the sampling options sent to the engine
+    pub fn set_seed(&mut self, seed: Option<i64>) {
+        self.seed = seed;
+    }
+
     pub fn create_choice(
         &self,
         index: u64,
@@ -95,10 +117,41 @@ impl DeltaGenerator {
             } else {
                 None
             },
+            nvext: None,
         }
     }
 }
 
+impl DeltaGenerator {
+    /// The log probabilities of the tokens of a chunk, in the legacy completions format
+    fn logprob_result(
+        &mut self,
+        positions: Vec<common::llm_backend::PositionLogProbs>,
+    ) -> LogprobResult {
+        let mut result = LogprobResult {
+            tokens: Vec::with_capacity(positions.len()),
+            token_logprobs: Vec::with_capacity(positions.len()),
+            top_logprobs: Vec::with_capacity(positions.len()),
+            text_offset: Vec::with_capacity(positions.len()),
+        };
+        for position in positions {
+            let token = position.token.token.unwrap_or_default();
+            result.text_offset.push(self.text_offset);
+            self.text_offset += token.chars().count() as i32;
+            result.token_logprobs.push(position.token.logprob as f32);
+            result.top_logprobs.push(
+                position
+                    .top
+                    .into_iter()
+                    .map(|top| (top.token.unwrap_or_default(), top.logprob as f32))
+                    .collect::<HashMap<_, _>>(),
+            );
+            result.tokens.push(token);
+        }
+        result
+    }
+}
+
 impl crate::protocols::openai::DeltaGeneratorExt<CompletionResponse> for DeltaGenerator {
     fn choice_from_postprocessor(
         &mut self,
@@ -107,15 +160,20 @@ impl crate::protocols::openai::DeltaGeneratorExt<CompletionResponse> for DeltaGe
         // aggregate usage
         if self.options.enable_usage {
             self.usage.completion_tokens += delta.token_ids.len() as i32;
+            self.usage.total_tokens = self.usage.prompt_tokens + self.usage.completion_tokens;
         }
 
-        // todo logprobs
+        let logprobs = match delta.logprobs {
+            Some(logprobs) if self.options.enable_logprobs => Some(self.logprob_result(logprobs)),
+            _ => None,
+        };
 
         let finish_reason = match delta.finish_reason {
             Some(common::FinishReason::EoS) => Some("stop".to_string()),
             Some(common::FinishReason::Stop) => Some("stop".to_string()),
             Some(common::FinishReason::Length) => Some("length".to_string()),
             Some(common::FinishReason::Cancelled) => Some("cancelled".to_string()),
+            Some(common::FinishReason::ContentFilter) => Some("content_filter".to_string()),
             Some(common::FinishReason::Error(err_msg)) => {
                 return Err(anyhow::anyhow!(err_msg));
             }
@@ -124,6 +182,10 @@ impl crate::protocols::openai::DeltaGeneratorExt<CompletionResponse> for DeltaGe
 
         // create choice
         let index = 0;
-        Ok(self.create_choice(index, delta.text, finish_reason))
+        let mut response = self.create_choice(index, delta.text, finish_reason);
+        response.choices[0].logprobs = logprobs;
+        response.nvext =
+            NvResponseExt::first_chunk(std::mem::take(&mut self.warnings), self.seed.take());
+        Ok(response)
     }
 }
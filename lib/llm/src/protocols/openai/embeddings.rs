@@ -0,0 +1,121 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use super::nvext::NvExt;
+use dynamo_runtime::engine::DataStream;
+use dynamo_runtime::protocols::annotated::Annotated;
+use futures::StreamExt;
+
+/// A request structure for creating embeddings, extending OpenAI's
+/// `CreateEmbeddingRequest` with [`NvExt`] extensions.
+#[derive(Serialize, Deserialize, Validate, Debug, Clone)]
+pub struct NvCreateEmbeddingRequest {
+    #[serde(flatten)]
+    pub inner: async_openai::types::CreateEmbeddingRequest,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nvext: Option<NvExt>,
+}
+
+/// A response structure for embeddings, embedding OpenAI's `CreateEmbeddingResponse`.
+///
+/// Embeddings are not streamed, but engines are [`ServerStreamingEngine`]s so that they can go
+/// over the network like everything else. An embedding engine returns a stream with a single
+/// response in it.
+///
+/// [`ServerStreamingEngine`]: dynamo_runtime::pipeline::ServerStreamingEngine
+#[derive(Serialize, Deserialize, Validate, Debug, Clone)]
+pub struct NvCreateEmbeddingResponse {
+    #[serde(flatten)]
+    pub inner: async_openai::types::CreateEmbeddingResponse,
+}
+
+impl NvCreateEmbeddingResponse {
+    /// Take the response out of an engine's output stream. If the engine sent more than one,
+    /// the data of all of them is merged, in order.
+    pub async fn from_annotated_stream(
+        stream: DataStream<Annotated<NvCreateEmbeddingResponse>>,
+    ) -> Result<NvCreateEmbeddingResponse, String> {
+        let mut stream = stream;
+        let mut response: Option<NvCreateEmbeddingResponse> = None;
+        while let Some(annotated) = stream.next().await {
+            if annotated.event.as_deref() == Some("error") {
+                return Err(annotated
+                    .comment
+                    .map(|c| c.join(" -- "))
+                    .unwrap_or_else(|| "unspecified error".to_string()));
+            }
+            let Some(next) = annotated.data else {
+                continue;
+            };
+            match response.as_mut() {
+                None => response = Some(next),
+                Some(r) => {
+                    r.inner.data.extend(next.inner.data);
+                    r.inner.usage.prompt_tokens += next.inner.usage.prompt_tokens;
+                    r.inner.usage.total_tokens += next.inner.usage.total_tokens;
+                }
+            }
+        }
+        response.ok_or_else(|| "Engine did not return an embedding response".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(index: u32, embedding: Vec<f32>) -> NvCreateEmbeddingResponse {
+        serde_json::from_value(serde_json::json!({
+            "object": "list",
+            "model": "bert",
+            "data": [{"index": index, "object": "embedding", "embedding": embedding}],
+            "usage": {"prompt_tokens": 2, "total_tokens": 2}
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_request_deserialize() {
+        let request: NvCreateEmbeddingRequest =
+            serde_json::from_str(r#"{"model": "bert", "input": ["hello", "world"]}"#).unwrap();
+        assert_eq!(request.inner.model, "bert");
+        assert!(request.nvext.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_from_annotated_stream() {
+        let stream = futures::stream::iter(vec![
+            Annotated::from_data(response(0, vec![0.1, 0.2])),
+            Annotated::from_data(response(1, vec![0.3, 0.4])),
+        ]);
+        let out = NvCreateEmbeddingResponse::from_annotated_stream(Box::pin(stream))
+            .await
+            .unwrap();
+        assert_eq!(out.inner.data.len(), 2);
+        assert_eq!(out.inner.data[1].embedding, vec![0.3, 0.4]);
+        assert_eq!(out.inner.usage.total_tokens, 4);
+
+        let empty = futures::stream::iter(Vec::<Annotated<NvCreateEmbeddingResponse>>::new());
+        assert!(
+            NvCreateEmbeddingResponse::from_annotated_stream(Box::pin(empty))
+                .await
+                .is_err()
+        );
+    }
+}
@@ -17,9 +17,20 @@ use derive_builder::Builder;
 use serde::{Deserialize, Serialize};
 use validator::{Validate, ValidationError};
 
+use crate::lora::LoraAdapter;
+use crate::protocols::TokenIdType;
+
 pub trait NvExtProvider {
     fn nvext(&self) -> Option<&NvExt>;
     fn raw_prompt(&self) -> Option<String>;
+
+    /// Whether the preprocessor should skip the prompt template and tokenize
+    /// [`NvExtProvider::raw_prompt`] directly. Follows `nvext.use_raw_prompt`, default false.
+    fn use_raw_prompt(&self) -> bool {
+        self.nvext()
+            .and_then(|ext| ext.use_raw_prompt)
+            .unwrap_or(false)
+    }
 }
 
 /// NVIDIA LLM extensions to the OpenAI API
@@ -31,6 +42,12 @@ pub struct NvExt {
     #[builder(default, setter(strip_option))]
     pub ignore_eos: Option<bool>,
 
+    /// Token ids that end generation, besides the model's end of sequence tokens. Like the
+    /// `stop` strings, they are not part of the output.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default, setter(strip_option))]
+    pub stop_token_ids: Option<Vec<TokenIdType>>,
+
     #[builder(default, setter(strip_option))] // NIM LLM might default to -1
     #[validate(custom(function = "validate_top_k"))]
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -55,12 +72,185 @@ pub struct NvExt {
     #[builder(default, setter(strip_option))]
     pub use_raw_prompt: Option<bool>,
 
+    /// Code after the cursor, for fill-in-the-middle on chat completions. The last message
+    /// is the code before it. The completions API has its own `suffix` field.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default, setter(into, strip_option))]
+    pub suffix: Option<String>,
+
     /// Annotations
     /// User requests triggers which result in the request issue back out-of-band information in the SSE
     /// stream using the `event:` field.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     #[builder(default, setter(strip_option))]
     pub annotations: Option<Vec<String>>,
+
+    /// If true, the response carries how long each stage of the request took in
+    /// `nvext.timings`. See [`Timings`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default, setter(strip_option))]
+    pub timings: Option<bool>,
+
+    /// When streaming, send the chunks that arrive within this many milliseconds of each other
+    /// together. `0` sends each chunk at once. Overrides the server's setting, see
+    /// [`crate::http::service::coalesce`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default, setter(strip_option))]
+    pub stream_coalesce_ms: Option<u64>,
+
+    /// Most chunks sent together, with `stream_coalesce_ms`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default, setter(strip_option))]
+    #[validate(range(min = 1))]
+    pub stream_coalesce_max: Option<u32>,
+
+    /// Scheduling class of the request. When a model's requests have to queue, higher
+    /// priorities are admitted first. The `x-dynamo-priority` header sets it too, for clients
+    /// that can't change the body; this field takes precedence.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default, setter(strip_option))]
+    pub priority: Option<Priority>,
+
+    /// Name of a prompt preset, whose system prompt and few-shot examples go before the
+    /// messages of a chat request. See [`crate::presets`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default, setter(into, strip_option))]
+    pub preset: Option<String>,
+
+    /// Id of a conversation the server keeps: the earlier turns of the session go before the
+    /// messages of a chat request, and the reply is added to it. See
+    /// [`crate::http::service::conversations`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default, setter(into, strip_option))]
+    pub session_id: Option<String>,
+
+    /// The adapter of a `<model>:<adapter>` request, set by the HTTP service. Never read from
+    /// clients, who pick adapters by model name. See [`crate::lora`].
+    #[serde(skip)]
+    #[builder(default, setter(strip_option))]
+    pub lora: Option<LoraAdapter>,
+}
+
+/// Scheduling class of a request, see [`NvExt::priority`]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    /// Interactive traffic
+    High,
+    #[default]
+    Normal,
+    /// Batch traffic, which can wait
+    Low,
+}
+
+impl Priority {
+    /// From highest to lowest
+    pub const ALL: [Priority; 3] = [Priority::High, Priority::Normal, Priority::Low];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Priority::High => "high",
+            Priority::Normal => "normal",
+            Priority::Low => "low",
+        }
+    }
+}
+
+impl std::str::FromStr for Priority {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Priority::ALL
+            .into_iter()
+            .find(|priority| priority.as_str().eq_ignore_ascii_case(s.trim()))
+            .ok_or_else(|| format!("unknown priority '{s}', expected high, normal or low"))
+    }
+}
+
+impl std::fmt::Display for Priority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// NVIDIA extensions to the OpenAI responses
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct NvResponseExt {
+    /// Changes made to the request, such as sampling options the engine does not support.
+    /// See [`crate::protocols::common::sampling`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+
+    /// Where the time went, when the request asked with `nvext.timings`. Streams carry it
+    /// in a last chunk of its own, without choices.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timings: Option<Timings>,
+
+    /// The seed the response was sampled with, the request's or the default one. Sent in the
+    /// first chunk. None if the engine does not take a seed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+}
+
+impl NvResponseExt {
+    /// The extension carrying `warnings`, or None if there are none
+    pub fn from_warnings(warnings: Vec<String>) -> Option<Self> {
+        (!warnings.is_empty()).then_some(NvResponseExt {
+            warnings,
+            ..Default::default()
+        })
+    }
+
+    /// The extension of the first chunk of a response, or None if it has nothing to say
+    pub fn first_chunk(warnings: Vec<String>, seed: Option<i64>) -> Option<Self> {
+        let mut ext = NvResponseExt::from_warnings(warnings);
+        if seed.is_some() {
+            ext.get_or_insert_with(Default::default).seed = seed;
+        }
+        ext
+    }
+
+    /// Add the extension of a later chunk of the same response
+    pub fn merge(&mut self, other: NvResponseExt) {
+        self.warnings.extend(other.warnings);
+        self.seed = self.seed.or(other.seed);
+        if other.timings.is_some() {
+            self.timings = other.timings;
+        }
+    }
+
+    /// The extension carrying `timings`
+    pub fn from_timings(timings: Timings) -> Self {
+        NvResponseExt {
+            timings: Some(timings),
+            ..Default::default()
+        }
+    }
+}
+
+/// Milliseconds a request spent in each stage, as seen by the HTTP service. The stages
+/// add up to roughly the whole request.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct Timings {
+    /// From the request arriving until the engine accepted it, less `tokenize` and `transfer`.
+    /// Routing, and waiting for the worker to take the request.
+    pub queue_ms: f64,
+
+    /// Applying the prompt template and tokenizing the prompt
+    pub tokenize_ms: f64,
+
+    /// Sending the request to the worker until its response stream connected
+    pub transfer_ms: f64,
+
+    /// From the engine accepting the request to the first token
+    pub prefill_ms: f64,
+
+    /// From the first token to the last
+    pub decode_ms: f64,
+
+    /// Turning the generated tokens back into text. Happens while decoding, so it is part
+    /// of `decode_ms` too.
+    pub detokenize_ms: f64,
 }
 
 impl Default for NvExt {
@@ -136,6 +326,15 @@ mod tests {
         assert!(nv_ext.validate().is_ok());
     }
 
+    #[test]
+    fn test_priority() {
+        assert_eq!("High".parse::<Priority>(), Ok(Priority::High));
+        assert!("urgent".parse::<Priority>().is_err());
+        let nv_ext: NvExt = serde_json::from_str(r#"{"priority": "low"}"#).unwrap();
+        assert_eq!(nv_ext.priority, Some(Priority::Low));
+        assert_eq!(NvExt::default().priority, None);
+    }
+
     // Test invalid `top_k` validation using proptest
     proptest! {
         #[test]
@@ -0,0 +1,153 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Dry-run tokenization for `/v1/tokenize` and `/v1/count_tokens`, and the reverse for
+//! `/v1/detokenize`. Not part of the OpenAI API.
+//!
+//! The prompt is built and tokenized exactly as for a generation request, so clients can budget
+//! their prompts against the context window with the server's own chat template and tokenizer.
+//! Detokenizing turns the token ids seen in raw engine traffic back into text.
+
+use async_openai::types::{
+    ChatCompletionRequestMessage, ChatCompletionTool, CreateChatCompletionRequest,
+};
+use serde::{Deserialize, Serialize};
+
+use super::chat_completions::NvCreateChatCompletionRequest;
+use crate::protocols::TokenIdType;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TokenizeRequest {
+    pub model: String,
+
+    /// Chat messages, rendered with the model's chat template. Exactly one of `messages` and
+    /// `prompt` must be set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub messages: Option<Vec<ChatCompletionRequestMessage>>,
+
+    /// Tools passed to the chat template along with `messages`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<ChatCompletionTool>>,
+
+    /// Text tokenized as-is, without the chat template
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+
+    /// Include the token ids in the response. Defaults to true for `/v1/tokenize` and false
+    /// for `/v1/count_tokens`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub return_token_ids: Option<bool>,
+}
+
+/// What to tokenize
+pub enum TokenizeInput {
+    Chat(Box<NvCreateChatCompletionRequest>),
+    Prompt(String),
+}
+
+impl TokenizeRequest {
+    /// Split the request into its input. Errors if it has neither or both of `messages` and
+    /// `prompt`.
+    pub fn input(self) -> Result<TokenizeInput, String> {
+        match (self.messages, self.prompt) {
+            (Some(messages), None) => {
+                let inner = CreateChatCompletionRequest {
+                    model: self.model,
+                    messages,
+                    tools: self.tools,
+                    ..Default::default()
+                };
+                Ok(TokenizeInput::Chat(Box::new(
+                    NvCreateChatCompletionRequest { inner, nvext: None },
+                )))
+            }
+            (None, Some(prompt)) => Ok(TokenizeInput::Prompt(prompt)),
+            (Some(_), Some(_)) => Err("Set one of 'messages' and 'prompt', not both".to_string()),
+            (None, None) => Err("One of 'messages' or 'prompt' is required".to_string()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TokenizeResponse {
+    pub model: String,
+
+    /// Number of prompt tokens
+    pub count: usize,
+
+    /// Context window of the model. The prompt and completion together must fit in it.
+    pub max_model_len: usize,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_ids: Option<Vec<TokenIdType>>,
+
+    /// The prompt after the chat template was applied
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub formatted_prompt: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DetokenizeRequest {
+    pub model: String,
+
+    pub token_ids: Vec<TokenIdType>,
+
+    /// Leave special tokens such as BOS and EOS out of the text. Defaults to false, so that
+    /// the text shows everything the engine saw.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skip_special_tokens: Option<bool>,
+
+    /// Also return the text of each token on its own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub return_tokens: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DetokenizeResponse {
+    pub model: String,
+
+    pub text: String,
+
+    /// The text of each token, in the same order as the request's `token_ids`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokens: Option<Vec<String>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_input() {
+        let request: TokenizeRequest = serde_json::from_value(serde_json::json!({
+            "model": "m",
+            "messages": [{"role": "user", "content": "Hello"}],
+        }))
+        .unwrap();
+        let TokenizeInput::Chat(chat) = request.input().unwrap() else {
+            panic!("Expected chat input");
+        };
+        assert_eq!(chat.inner.model, "m");
+        assert_eq!(chat.inner.messages.len(), 1);
+
+        let request: TokenizeRequest = serde_json::from_value(serde_json::json!({
+            "model": "m",
+            "prompt": "Hello",
+            "messages": [{"role": "user", "content": "Hello"}],
+        }))
+        .unwrap();
+        assert!(request.input().is_err());
+    }
+}
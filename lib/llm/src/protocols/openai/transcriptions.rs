@@ -0,0 +1,214 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use base64::Engine as _;
+use serde::{Deserialize, Serialize};
+
+use crate::audio::Pcm;
+use dynamo_runtime::engine::DataStream;
+use dynamo_runtime::protocols::annotated::Annotated;
+use futures::StreamExt;
+
+/// A speech-to-text request, built by the HTTP service from an OpenAI
+/// `/v1/audio/transcriptions` multipart upload.
+///
+/// The audio is already decoded: `pcm` is base64 of little-endian `f32` samples, mono, at
+/// `sample_rate` (always 16 kHz today). In Python that is
+/// `numpy.frombuffer(base64.b64decode(request["pcm"]), dtype="<f4")`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NvCreateTranscriptionRequest {
+    pub model: String,
+
+    pub sample_rate: u32,
+
+    pub pcm: String,
+
+    /// ISO-639-1 language of the audio, if the client knows it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+
+    /// Text to guide the model's style or continue a previous segment
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+
+    /// The engine only needs to fill in `segments` when this is
+    /// [`TranscriptionFormat::VerboseJson`]
+    #[serde(default)]
+    pub response_format: TranscriptionFormat,
+}
+
+impl NvCreateTranscriptionRequest {
+    pub fn new(model: String, pcm: &Pcm) -> Self {
+        let bytes: Vec<u8> = pcm.samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        NvCreateTranscriptionRequest {
+            model,
+            sample_rate: pcm.sample_rate,
+            pcm: base64::engine::general_purpose::STANDARD.encode(bytes),
+            language: None,
+            prompt: None,
+            temperature: None,
+            response_format: TranscriptionFormat::default(),
+        }
+    }
+
+    /// The decoded samples, for Rust engines
+    pub fn samples(&self) -> anyhow::Result<Vec<f32>> {
+        let bytes = base64::engine::general_purpose::STANDARD.decode(&self.pcm)?;
+        if bytes.len() % 4 != 0 {
+            anyhow::bail!("PCM length {} is not a multiple of 4", bytes.len());
+        }
+        Ok(bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect())
+    }
+}
+
+/// The `response_format` values we can produce. OpenAI's `srt` and `vtt` are not supported.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TranscriptionFormat {
+    #[default]
+    Json,
+    Text,
+    VerboseJson,
+}
+
+impl std::str::FromStr for TranscriptionFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(TranscriptionFormat::Json),
+            "text" => Ok(TranscriptionFormat::Text),
+            "verbose_json" => Ok(TranscriptionFormat::VerboseJson),
+            other => Err(format!(
+                "Unsupported response_format '{other}', expected json, text or verbose_json"
+            )),
+        }
+    }
+}
+
+/// A timed piece of the transcript
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TranscriptionSegment {
+    pub id: u32,
+    pub start: f32,
+    pub end: f32,
+    pub text: String,
+}
+
+/// A transcription, in the shape of OpenAI's `verbose_json` response.
+///
+/// Engines may stream the transcript in pieces, each carrying the next part of `text` and
+/// of `segments`. The HTTP service folds them with [`Self::from_annotated_stream`].
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct NvCreateTranscriptionResponse {
+    pub text: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+
+    /// Length of the audio in seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub segments: Option<Vec<TranscriptionSegment>>,
+}
+
+impl NvCreateTranscriptionResponse {
+    /// Join the pieces of an engine's output stream into one transcript
+    pub async fn from_annotated_stream(
+        stream: DataStream<Annotated<NvCreateTranscriptionResponse>>,
+    ) -> Result<NvCreateTranscriptionResponse, String> {
+        let mut stream = stream;
+        let mut response: Option<NvCreateTranscriptionResponse> = None;
+        while let Some(annotated) = stream.next().await {
+            if annotated.event.as_deref() == Some("error") {
+                return Err(annotated
+                    .comment
+                    .map(|c| c.join(" -- "))
+                    .unwrap_or_else(|| "unspecified error".to_string()));
+            }
+            let Some(next) = annotated.data else {
+                continue;
+            };
+            match response.as_mut() {
+                None => response = Some(next),
+                Some(r) => {
+                    r.text.push_str(&next.text);
+                    r.language = next.language.or(r.language.take());
+                    r.duration = next.duration.or(r.duration);
+                    if let Some(segments) = next.segments {
+                        r.segments.get_or_insert_with(Vec::new).extend(segments);
+                    }
+                }
+            }
+        }
+        response.ok_or_else(|| "Engine did not return a transcription".to_string())
+    }
+
+    /// The `json` format only has the text
+    pub fn into_json(self) -> serde_json::Value {
+        serde_json::json!({ "text": self.text })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pcm_round_trip() {
+        let pcm = Pcm {
+            sample_rate: 16_000,
+            samples: vec![0.0, 0.5, -1.0],
+        };
+        let request = NvCreateTranscriptionRequest::new("whisper".to_string(), &pcm);
+        let request: NvCreateTranscriptionRequest =
+            serde_json::from_str(&serde_json::to_string(&request).unwrap()).unwrap();
+        assert_eq!(request.samples().unwrap(), pcm.samples);
+        assert_eq!(request.response_format, TranscriptionFormat::Json);
+    }
+
+    #[tokio::test]
+    async fn test_from_annotated_stream() {
+        let piece = |text: &str, start: f32| NvCreateTranscriptionResponse {
+            text: text.to_string(),
+            language: Some("en".to_string()),
+            duration: None,
+            segments: Some(vec![TranscriptionSegment {
+                id: start as u32,
+                start,
+                end: start + 1.0,
+                text: text.to_string(),
+            }]),
+        };
+        let stream = futures::stream::iter(vec![
+            Annotated::from_data(piece("Hello", 0.0)),
+            Annotated::from_data(piece(" world", 1.0)),
+        ]);
+        let out = NvCreateTranscriptionResponse::from_annotated_stream(Box::pin(stream))
+            .await
+            .unwrap();
+        assert_eq!(out.text, "Hello world");
+        assert_eq!(out.language.as_deref(), Some("en"));
+        assert_eq!(out.segments.unwrap().len(), 2);
+    }
+}
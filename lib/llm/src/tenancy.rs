@@ -0,0 +1,275 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Namespaces as tenants
+//!
+//! One frontend can serve the models of several namespaces. Each API key with a `namespace`
+//! (see [`crate::auth`]) only sees the models registered in that namespace, and the tokens
+//! of its responses count against the namespace's quota. The namespaces are loaded from a
+//! JSON file:
+//! ```json
+//! {
+//!   "team-a": {"token_quota": 5000000, "quota_period_secs": 86400},
+//!   "team-b": {}
+//! }
+//! ```
+//! `token_quota` is the number of prompt and generated tokens the namespace may use per
+//! `quota_period_secs`, or in total without a period. Requests are admitted while some of the
+//! quota is left, so the last response can go over it. A namespace without a quota is not
+//! limited. Usage is kept in memory, it starts again from zero when the frontend restarts.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::Context as _;
+use serde::{Deserialize, Serialize};
+
+/// Limits of a namespace
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NamespaceConfig {
+    /// Tokens the namespace may use per period. Unlimited if None.
+    #[serde(default)]
+    pub token_quota: Option<u64>,
+
+    /// Length of a quota period. The quota never resets if None.
+    #[serde(default)]
+    pub quota_period_secs: Option<u64>,
+}
+
+/// Why a request was turned away
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum TenancyError {
+    #[error("Namespace '{0}' is not served here.")]
+    UnknownNamespace(String),
+
+    #[error("Namespace '{namespace}' used its quota of {quota} tokens.")]
+    QuotaExceeded {
+        namespace: String,
+        quota: u64,
+        /// Until the next period, if the quota resets
+        resets_in: Option<Duration>,
+    },
+}
+
+/// Usage of a namespace, as the admin endpoint reports it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NamespaceUsage {
+    pub namespace: String,
+    /// Tokens used in the current period
+    pub used_tokens: u64,
+    pub token_quota: Option<u64>,
+    pub quota_period_secs: Option<u64>,
+    /// Seconds until the quota resets
+    pub resets_in_secs: Option<u64>,
+}
+
+struct Account {
+    config: NamespaceConfig,
+    used: u64,
+    period_start: Instant,
+}
+
+impl Account {
+    /// Start a new period if the current one is over
+    fn roll(&mut self, now: Instant) {
+        let Some(period) = self.period() else {
+            return;
+        };
+        let elapsed = now.duration_since(self.period_start);
+        if elapsed >= period {
+            let periods = (elapsed.as_secs_f64() / period.as_secs_f64()).floor() as u32;
+            self.period_start += period * periods;
+            self.used = 0;
+        }
+    }
+
+    fn period(&self) -> Option<Duration> {
+        self.config
+            .quota_period_secs
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs)
+    }
+
+    fn resets_in(&self, now: Instant) -> Option<Duration> {
+        self.period()
+            .map(|period| (self.period_start + period).saturating_duration_since(now))
+    }
+}
+
+/// The namespaces a frontend serves and the tokens each has used
+pub struct Tenants {
+    accounts: Mutex<HashMap<String, Account>>,
+}
+
+impl Tenants {
+    pub fn new(namespaces: HashMap<String, NamespaceConfig>) -> Self {
+        let now = Instant::now();
+        let accounts = namespaces
+            .into_iter()
+            .map(|(namespace, config)| {
+                let account = Account {
+                    config,
+                    used: 0,
+                    period_start: now,
+                };
+                (namespace, account)
+            })
+            .collect();
+        Tenants {
+            accounts: Mutex::new(accounts),
+        }
+    }
+
+    /// Load the namespaces from a JSON file, see the module docs for the format
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path).with_context(|| path.display().to_string())?;
+        let namespaces: HashMap<String, NamespaceConfig> =
+            serde_json::from_str(&contents).with_context(|| path.display().to_string())?;
+        if namespaces.is_empty() {
+            anyhow::bail!("{}: no namespaces", path.display());
+        }
+        Ok(Tenants::new(namespaces))
+    }
+
+    /// The namespaces, sorted
+    pub fn namespaces(&self) -> Vec<String> {
+        let mut namespaces: Vec<_> = self.accounts.lock().unwrap().keys().cloned().collect();
+        namespaces.sort();
+        namespaces
+    }
+
+    pub fn contains(&self, namespace: &str) -> bool {
+        self.accounts.lock().unwrap().contains_key(namespace)
+    }
+
+    /// Whether `namespace` may start another request
+    pub fn check(&self, namespace: &str) -> Result<(), TenancyError> {
+        let now = Instant::now();
+        let mut accounts = self.accounts.lock().unwrap();
+        let account = accounts
+            .get_mut(namespace)
+            .ok_or_else(|| TenancyError::UnknownNamespace(namespace.to_string()))?;
+        account.roll(now);
+        match account.config.token_quota {
+            Some(quota) if account.used >= quota => Err(TenancyError::QuotaExceeded {
+                namespace: namespace.to_string(),
+                quota,
+                resets_in: account.resets_in(now),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Count the prompt and generated `tokens` of a response against the namespace's quota
+    pub fn charge(&self, namespace: &str, tokens: u64) {
+        let mut accounts = self.accounts.lock().unwrap();
+        if let Some(account) = accounts.get_mut(namespace) {
+            account.roll(Instant::now());
+            account.used += tokens;
+        }
+    }
+
+    /// Usage of every namespace, sorted by name
+    pub fn usage(&self) -> Vec<NamespaceUsage> {
+        let now = Instant::now();
+        let mut accounts = self.accounts.lock().unwrap();
+        let mut usage: Vec<_> = accounts
+            .iter_mut()
+            .map(|(namespace, account)| {
+                account.roll(now);
+                NamespaceUsage {
+                    namespace: namespace.clone(),
+                    used_tokens: account.used,
+                    token_quota: account.config.token_quota,
+                    quota_period_secs: account.config.quota_period_secs,
+                    resets_in_secs: account.resets_in(now).map(|d| d.as_secs()),
+                }
+            })
+            .collect();
+        usage.sort_by(|a, b| a.namespace.cmp(&b.namespace));
+        usage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quota() {
+        let tenants = Tenants::new(HashMap::from([
+            (
+                "a".to_string(),
+                NamespaceConfig {
+                    token_quota: Some(100),
+                    quota_period_secs: None,
+                },
+            ),
+            ("b".to_string(), NamespaceConfig::default()),
+        ]));
+
+        assert!(tenants.check("a").is_ok());
+        tenants.charge("a", 60);
+        assert!(tenants.check("a").is_ok());
+        // The last response may go over
+        tenants.charge("a", 60);
+        assert!(matches!(
+            tenants.check("a"),
+            Err(TenancyError::QuotaExceeded {
+                quota: 100,
+                resets_in: None,
+                ..
+            })
+        ));
+
+        tenants.charge("b", 1_000_000);
+        assert!(tenants.check("b").is_ok());
+        assert_eq!(
+            tenants.check("c"),
+            Err(TenancyError::UnknownNamespace("c".to_string()))
+        );
+
+        let usage = tenants.usage();
+        assert_eq!(usage[0].namespace, "a");
+        assert_eq!(usage[0].used_tokens, 120);
+        assert_eq!(usage[1].token_quota, None);
+    }
+
+    #[test]
+    fn test_quota_period() {
+        let mut account = Account {
+            config: NamespaceConfig {
+                token_quota: Some(10),
+                quota_period_secs: Some(60),
+            },
+            used: 10,
+            period_start: Instant::now(),
+        };
+        let start = account.period_start;
+        account.roll(start + Duration::from_secs(59));
+        assert_eq!(account.used, 10);
+        assert_eq!(
+            account.resets_in(start + Duration::from_secs(59)),
+            Some(Duration::from_secs(1))
+        );
+
+        account.roll(start + Duration::from_secs(150));
+        assert_eq!(account.used, 0);
+        assert_eq!(account.period_start, start + Duration::from_secs(120));
+    }
+}
@@ -14,10 +14,14 @@
 // limitations under the License.
 
 pub mod hf;
+pub mod pool;
 
 #[cfg(feature = "sentencepiece")]
 pub mod sp;
 
+#[cfg(feature = "tiktoken")]
+pub mod tiktoken;
+
 // TODO: Add tokenizer benchmarks
 // TODO: Enable README.md as a module doc
 // #[doc = include_str!("../README.md")]
@@ -34,12 +38,17 @@ pub use hf::HuggingFaceTokenizer;
 #[cfg(feature = "sentencepiece")]
 pub use sp::SentencePieceTokenizer;
 
+#[cfg(feature = "tiktoken")]
+pub use tiktoken::TiktokenTokenizer;
+
 /// Represents the type of tokenizer being used
 #[derive(Debug)]
 pub enum TokenizerType {
     HuggingFace(String),
     #[cfg(feature = "sentencepiece")]
     SentencePiece(String),
+    #[cfg(feature = "tiktoken")]
+    Tiktoken(String),
 }
 
 /// character offsets in the original text
@@ -65,6 +74,9 @@ pub mod traits {
     }
 
     pub trait Tokenizer: Encoder + Decoder {
+        /// Id of a token of the vocabulary, special tokens included
+        fn token_to_id(&self, token: &str) -> Option<TokenIdType>;
+
         // fn get_vocab_size(&self) -> usize;
         // fn make_unique_clone(&self) -> Box<dyn Tokenizer>;
     }
@@ -121,6 +133,7 @@ where
 /// Supported file types are:
 /// - json: HuggingFace tokenizer
 /// - model: SentencePiece tokenizer
+/// - tiktoken: tiktoken BPE ranks
 pub fn create_tokenizer_from_file(file_path: &str) -> Result<Arc<dyn traits::Tokenizer>> {
     let path = Path::new(file_path);
     let extension = path
@@ -146,45 +159,49 @@ pub fn create_tokenizer_from_file(file_path: &str) -> Result<Arc<dyn traits::Tok
                 ))
             }
         }
+        "tiktoken" => {
+            #[cfg(feature = "tiktoken")]
+            {
+                let tokenizer = TiktokenTokenizer::from_file(file_path)?;
+                Ok(Arc::new(tokenizer))
+            }
+            #[cfg(not(feature = "tiktoken"))]
+            {
+                Err(Error::msg("tiktoken tokenizer not supported".to_string()))
+            }
+        }
         _ => Err(Error::msg("Unsupported file type".to_string())),
     }
 }
 
+/// Longest a character can take to decode: four UTF-8 bytes, each a byte-fallback token
+const MAX_HELD_TOKENS: usize = 4;
+
 /// DecodeStream will keep the state necessary to produce individual chunks of
 /// strings given an input stream of token_ids.
 ///
 /// This is necessary because decoding in general cannot achieve that since strings
 /// depend on surrounding ids to provide a valid string. Typically stripping extra spaces.
+///
+/// A character can also be split over several tokens, one per byte with `byte_fallback`
+/// tokenizers or as parts of a byte-level BPE token. Such tokens decode to `�` until the
+/// character is complete, so they are held back, and the chunks never end part way through a
+/// character. Engines returning token ids all go through here, see [`crate::backend`].
 pub struct DecodeStream {
     /// The tokenizer used to decode token_ids
     tokenizer: Arc<dyn traits::Tokenizer>,
 
     skip_special_tokens: bool,
-    /// A temporary buffer of the necessary token_ids needed
-    /// to produce valid string chunks.
-    /// This typically contains 3 parts:
-    ///  - read
-    ///  - prefix
-    ///  - rest
-    ///
-    /// Read is the bit necessary to surround the prefix
-    /// so decoding the whole ids produces a valid prefix.
-    /// Prefix is the previously produced string, kept around to trim off of
-    /// the next valid chunk
+
+    /// The ids of the last chunk returned, which give the next ids the context to decode as
+    /// they would in the whole text (a leading space, ...), followed by the ids not yet
+    /// returned
     ids: Vec<u32>,
 
-    /// The previously returned chunk that needs to be discarded from the
-    /// decoding of the current ids to produce the next chunk
+    /// The text of `ids[..read_index]`, trimmed off the decoding of `ids` to get the next chunk
     prefix: String,
 
-    /// The index within the ids corresponding to the prefix so we can drain
-    /// correctlyk
-    prefix_index: usize,
-
-    /// We need to keep 2 prefixes.
-    /// Prefix is the second one that was already emitted to discard the part
-    /// of the text of all the ids
-    /// read is the prefix kept only for starting side effects of the prefix
+    /// Where the ids not yet returned start
     read_index: usize,
 }
 
@@ -195,40 +212,68 @@ impl DecodeStream {
             skip_special_tokens,
             ids: Vec::new(),
             prefix: "".to_string(),
-            prefix_index: 0,
             read_index: 0,
         }
     }
 
     /// Step appends a token_id to the internal state and tries to produce a text chunk.
     ///
-    /// The method only fails if the internal state is corrupted.
+    /// The method only fails if the tokenizer cannot decode the ids.
     ///
     /// Returning `None` means the given id is not enough to produce a chunk.
     /// This typically happens with `byte_fallback` options where some tokens do not
     /// represent valid UTF-8, and only follow-up token_ids will help produce
-    /// a valid chunk.
+    /// a valid chunk. Bytes still not a character after four tokens never will be, and are
+    /// returned as the `�` the tokenizer made of them.
     pub fn step(&mut self, id: u32) -> Result<Option<String>> {
         self.ids.push(id);
-        let string = self
+        let text = self
             .tokenizer
             .decode(self.ids.as_slice(), self.skip_special_tokens)?;
+        let held = self.ids.len() - self.read_index;
+        if text.ends_with('\u{FFFD}') && held < MAX_HELD_TOKENS {
+            return Ok(None);
+        }
+        self.emit(text)
+    }
 
-        if string.len() > self.prefix.len() && !string.ends_with('�') {
-            if !(string.starts_with(&self.prefix)) {
-                anyhow::bail!("Detokenizer failure: invalid prefix");
-            }
-            let new_text = &string[self.prefix.len()..].to_string();
-            let new_prefix_index = self.ids.len() - self.prefix_index;
-            self.prefix = self
-                .tokenizer
-                .decode(self.ids.as_slice(), self.skip_special_tokens)?;
-            self.read_index = self.prefix_index;
-            self.prefix_index = new_prefix_index;
-            Ok(Some(new_text.to_string()))
+    /// The text of the ids held back at the end of the stream, if any
+    pub fn flush(&mut self) -> Result<Option<String>> {
+        if self.read_index == self.ids.len() {
+            return Ok(None);
+        }
+        let text = self
+            .tokenizer
+            .decode(self.ids.as_slice(), self.skip_special_tokens)?;
+        self.emit(text)
+    }
+
+    /// Return what `text`, the decoding of all of `ids`, adds to the prefix, and make the
+    /// ids not yet returned the next prefix
+    fn emit(&mut self, text: String) -> Result<Option<String>> {
+        let start = if text.starts_with(&self.prefix) {
+            self.prefix.len()
         } else {
-            Ok(None)
+            // More context changed how the prefix decodes, e.g. a space tokenizers drop at the
+            // start of a text. It was already returned, so skip as many bytes.
+            let mut start = self.prefix.len().min(text.len());
+            while !text.is_char_boundary(start) {
+                start += 1;
+            }
+            start
+        };
+        if start >= text.len() {
+            // Nothing yet, e.g. a skipped special token
+            return Ok(None);
         }
+        let new_text = text[start..].to_string();
+
+        self.ids.drain(..self.read_index);
+        self.read_index = self.ids.len();
+        self.prefix = self
+            .tokenizer
+            .decode(self.ids.as_slice(), self.skip_special_tokens)?;
+        Ok(Some(new_text))
     }
 }
 
@@ -0,0 +1,180 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The threads that tokenize prompts
+//!
+//! Encoding a long prompt takes milliseconds of CPU. Done on a tokio worker, it holds up every
+//! other task on that worker, such as the responses streaming to other clients. The
+//! pre-processor sends that work to a [`TokenizerPool`] instead and awaits the result.
+//!
+//! The pool has `DYN_TOKENIZER_THREADS` threads, by default half the CPUs. At most
+//! `DYN_TOKENIZER_QUEUE` more jobs wait for a thread, 256 by default; after that callers wait
+//! for a place in the queue without blocking their tokio worker.
+//!
+//! The streaming detokenization of the engine's output stays where it is, each step only
+//! decodes a few tokens.
+
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, LazyLock};
+
+use anyhow::Context as _;
+use tokio::sync::{oneshot, Semaphore};
+
+/// How many threads tokenize
+pub const TOKENIZER_THREADS_ENV: &str = "DYN_TOKENIZER_THREADS";
+
+/// How many jobs can wait for one of those threads
+pub const TOKENIZER_QUEUE_ENV: &str = "DYN_TOKENIZER_QUEUE";
+
+const DEFAULT_QUEUE: usize = 256;
+
+static GLOBAL: LazyLock<TokenizerPool> = LazyLock::new(|| {
+    TokenizerPool::from_env()
+        .or_else(|err| {
+            tracing::warn!("Tokenizer pool: {err:#}. Using the defaults.");
+            TokenizerPool::new(default_threads(), DEFAULT_QUEUE)
+        })
+        .expect("Failed to start the tokenizer threads")
+});
+
+pub struct TokenizerPool {
+    pool: rayon::ThreadPool,
+    /// A permit for each thread and each place in the queue
+    slots: Arc<Semaphore>,
+}
+
+impl TokenizerPool {
+    pub fn new(threads: usize, queue: usize) -> anyhow::Result<Self> {
+        if threads == 0 {
+            anyhow::bail!("The tokenizer pool needs at least one thread");
+        }
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|idx| format!("tokenizer-{idx}"))
+            .build()?;
+        Ok(TokenizerPool {
+            pool,
+            slots: Arc::new(Semaphore::new(threads + queue)),
+        })
+    }
+
+    pub fn from_env() -> anyhow::Result<Self> {
+        let threads = match std::env::var(TOKENIZER_THREADS_ENV) {
+            Ok(val) => val
+                .trim()
+                .parse()
+                .with_context(|| format!("{TOKENIZER_THREADS_ENV}='{val}'"))?,
+            Err(_) => default_threads(),
+        };
+        let queue = match std::env::var(TOKENIZER_QUEUE_ENV) {
+            Ok(val) => val
+                .trim()
+                .parse()
+                .with_context(|| format!("{TOKENIZER_QUEUE_ENV}='{val}'"))?,
+            Err(_) => DEFAULT_QUEUE,
+        };
+        Self::new(threads, queue)
+    }
+
+    /// The pool every pre-processor shares, started the first time it is used
+    pub fn global() -> &'static TokenizerPool {
+        &GLOBAL
+    }
+
+    pub fn threads(&self) -> usize {
+        self.pool.current_num_threads()
+    }
+
+    /// Run `f` on one of the pool's threads. Waits for a place in the queue if it is full.
+    pub async fn run<T, F>(&self, f: F) -> anyhow::Result<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let permit = self.slots.clone().acquire_owned().await?;
+        let (tx, rx) = oneshot::channel();
+        self.pool.spawn(move || {
+            let result = std::panic::catch_unwind(AssertUnwindSafe(f));
+            drop(permit);
+            // The caller went away, nobody wants the result
+            let _ = tx.send(result);
+        });
+        match rx.await? {
+            Ok(result) => Ok(result),
+            Err(_) => anyhow::bail!("Tokenizing panicked"),
+        }
+    }
+}
+
+/// Half the CPUs, leaving the rest to tokio and the engine
+fn default_threads() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| (n.get() / 2).max(1))
+        .unwrap_or(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_run() {
+        let pool = TokenizerPool::new(2, 1).unwrap();
+        assert_eq!(pool.threads(), 2);
+        let name = pool
+            .run(|| std::thread::current().name().map(str::to_string))
+            .await
+            .unwrap();
+        assert!(name.unwrap().starts_with("tokenizer-"));
+
+        assert!(pool.run(|| panic!("bad tokenizer")).await.is_err());
+        // The pool survives it
+        assert_eq!(pool.run(|| 1 + 1).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_bounded() {
+        let pool = Arc::new(TokenizerPool::new(1, 1).unwrap());
+        let running = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        let jobs: Vec<_> = (0..8)
+            .map(|_| {
+                let pool = pool.clone();
+                let running = running.clone();
+                let most = most.clone();
+                tokio::spawn(async move {
+                    pool.run(move || {
+                        let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                        most.fetch_max(now, Ordering::SeqCst);
+                        std::thread::sleep(std::time::Duration::from_millis(5));
+                        running.fetch_sub(1, Ordering::SeqCst);
+                    })
+                    .await
+                })
+            })
+            .collect();
+        for job in jobs {
+            job.await.unwrap().unwrap();
+        }
+        assert_eq!(most.load(Ordering::SeqCst), 1);
+        assert_eq!(pool.slots.available_permits(), 2);
+    }
+
+    #[test]
+    fn test_no_threads() {
+        assert!(TokenizerPool::new(0, 8).is_err());
+    }
+}
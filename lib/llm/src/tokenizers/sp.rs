@@ -106,4 +106,8 @@ impl Decoder for SentencePieceTokenizer {
 }
 
 /// Implement the Tokenizer trait for SentencePieceTokenizer
-impl Tokenizer for SentencePieceTokenizer {}
+impl Tokenizer for SentencePieceTokenizer {
+    fn token_to_id(&self, token: &str) -> Option<TokenIdType> {
+        self.spp.piece_to_id(token).ok().flatten()
+    }
+}
@@ -0,0 +1,164 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use base64::Engine as _;
+use tiktoken_rs::CoreBPE;
+
+use super::{
+    traits::{Decoder, Encoder, Tokenizer},
+    Encoding, Error, Result, TokenIdType,
+};
+
+/// Pre-tokenizer split of cl100k_base, which the models shipping a tiktoken file build on
+const CL100K_PATTERN: &str = r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+";
+
+/// A tokenizer from a tiktoken BPE file: one base64 token and its rank per line.
+pub struct TiktokenTokenizer {
+    bpe: CoreBPE,
+    encoder: HashMap<Vec<u8>, TokenIdType>,
+    /// Bytes of each token, special tokens included
+    decoder: HashMap<TokenIdType, Vec<u8>>,
+    special: HashMap<String, TokenIdType>,
+    special_ids: HashSet<TokenIdType>,
+}
+
+impl TiktokenTokenizer {
+    /// Load the ranks in `path`. The special tokens come from the `added_tokens_decoder` of a
+    /// `tokenizer_config.json` next to it, if there is one.
+    pub fn from_file(path: &str) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| Error::msg(format!("Error loading tokenizer: {}", err)))?;
+        let mut encoder = HashMap::new();
+        for (n, line) in contents.lines().enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+            let parsed = line.split_once(' ').and_then(|(token, rank)| {
+                let token = base64::engine::general_purpose::STANDARD
+                    .decode(token)
+                    .ok()?;
+                Some((token, rank.parse::<TokenIdType>().ok()?))
+            });
+            let Some((token, rank)) = parsed else {
+                return Err(Error::msg(format!(
+                    "Error loading tokenizer: {path} line {} is not a base64 token and a rank",
+                    n + 1
+                )));
+            };
+            encoder.insert(token, rank);
+        }
+        let special = Self::special_tokens(path)?;
+        Self::new(encoder, special, CL100K_PATTERN)
+    }
+
+    pub fn new(
+        encoder: HashMap<Vec<u8>, TokenIdType>,
+        special: HashMap<String, TokenIdType>,
+        pattern: &str,
+    ) -> Result<Self> {
+        let mut decoder: HashMap<TokenIdType, Vec<u8>> = encoder
+            .iter()
+            .map(|(token, rank)| (*rank, token.clone()))
+            .collect();
+        decoder.extend(
+            special
+                .iter()
+                .map(|(token, rank)| (*rank, token.as_bytes().to_vec())),
+        );
+        let bpe = CoreBPE::new(encoder.clone(), special.clone(), pattern)
+            .map_err(|err| Error::msg(format!("Error loading tokenizer: {}", err)))?;
+        Ok(TiktokenTokenizer {
+            bpe,
+            encoder,
+            decoder,
+            special_ids: special.values().copied().collect(),
+            special,
+        })
+    }
+
+    fn special_tokens(path: &str) -> Result<HashMap<String, TokenIdType>> {
+        let config = Path::new(path).with_file_name("tokenizer_config.json");
+        if !config.exists() {
+            return Ok(HashMap::new());
+        }
+        let config: serde_json::Value = std::fs::read_to_string(&config)
+            .map_err(Error::from)
+            .and_then(|contents| Ok(serde_json::from_str(&contents)?))
+            .map_err(|err| Error::msg(format!("Error loading {}: {}", config.display(), err)))?;
+        let Some(added) = config["added_tokens_decoder"].as_object() else {
+            return Ok(HashMap::new());
+        };
+        Ok(added
+            .iter()
+            .filter_map(|(id, token)| {
+                Some((token["content"].as_str()?.to_string(), id.parse().ok()?))
+            })
+            .collect())
+    }
+}
+
+impl Encoder for TiktokenTokenizer {
+    fn encode(&self, input: &str) -> Result<Encoding> {
+        let token_ids = self.bpe.encode_with_special_tokens(input);
+
+        let mut tokens = Vec::with_capacity(token_ids.len());
+        let mut spans = Vec::with_capacity(token_ids.len());
+        let mut start = 0;
+        for id in &token_ids {
+            let bytes = self.decoder.get(id).map(Vec::as_slice).unwrap_or_default();
+            tokens.push(String::from_utf8_lossy(bytes).into_owned());
+            spans.push((start, start + bytes.len()));
+            start += bytes.len();
+        }
+
+        Ok(Encoding {
+            token_ids,
+            tokens,
+            spans,
+        })
+    }
+}
+
+impl Decoder for TiktokenTokenizer {
+    /// Bytes that are not valid UTF-8 on their own, such as part of a character, decode to
+    /// U+FFFD like with the other tokenizers.
+    fn decode(&self, token_ids: &[TokenIdType], skip_special_tokens: bool) -> Result<String> {
+        let mut bytes = Vec::new();
+        for id in token_ids {
+            let Some(token) = self.decoder.get(id) else {
+                return Err(Error::msg(format!(
+                    "Error decoding input: unknown token id {id}"
+                )));
+            };
+            if skip_special_tokens && self.special_ids.contains(id) {
+                continue;
+            }
+            bytes.extend_from_slice(token);
+        }
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+}
+
+impl Tokenizer for TiktokenTokenizer {
+    fn token_to_id(&self, token: &str) -> Option<TokenIdType> {
+        self.special
+            .get(token)
+            .or_else(|| self.encoder.get(token.as_bytes()))
+            .copied()
+    }
+}
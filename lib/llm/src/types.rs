@@ -52,4 +52,30 @@ pub mod openai {
             Annotated<NvCreateChatCompletionStreamResponse>,
         >;
     }
+
+    pub mod embeddings {
+        use super::*;
+
+        pub use protocols::openai::embeddings::{
+            NvCreateEmbeddingRequest, NvCreateEmbeddingResponse,
+        };
+
+        /// A [`ServerStreamingEngine`] implementation for the OpenAI Embeddings API
+        pub type OpenAIEmbeddingsStreamingEngine =
+            ServerStreamingEngine<NvCreateEmbeddingRequest, Annotated<NvCreateEmbeddingResponse>>;
+    }
+
+    pub mod transcriptions {
+        use super::*;
+
+        pub use protocols::openai::transcriptions::{
+            NvCreateTranscriptionRequest, NvCreateTranscriptionResponse,
+        };
+
+        /// A [`ServerStreamingEngine`] implementation for the OpenAI Audio Transcriptions API
+        pub type OpenAITranscriptionsStreamingEngine = ServerStreamingEngine<
+            NvCreateTranscriptionRequest,
+            Annotated<NvCreateTranscriptionResponse>,
+        >;
+    }
 }
@@ -13,8 +13,14 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use async_openai::types::CreateCompletionRequestArgs;
 use dynamo_llm::backend::Backend;
+use dynamo_llm::engines::{make_engine_full, StreamingEngineAdapter};
 use dynamo_llm::model_card::model::ModelDeploymentCard;
+use dynamo_llm::protocols::openai::completions::CompletionRequest;
+use dynamo_runtime::engine::AsyncEngine;
+use dynamo_runtime::pipeline::Context;
+use futures::StreamExt;
 
 #[tokio::test]
 async fn test_sequence_factory() {
@@ -32,3 +38,28 @@ async fn test_sequence_factory() {
     let output = decode_stream.step(1).unwrap();
     assert_eq!(output, None);
 }
+
+#[tokio::test]
+async fn test_stop_full_engine() {
+    // The echo engine does not apply stop strings itself
+    let engine = StreamingEngineAdapter::new(make_engine_full());
+    let inner = CreateCompletionRequestArgs::default()
+        .model("echo")
+        .prompt("one two three")
+        .stop(vec!["three", "o t"])
+        .build()
+        .unwrap();
+    let request = CompletionRequest { inner, nvext: None };
+
+    let mut stream = engine.generate(Context::new(request)).await.unwrap();
+    let mut text = String::new();
+    let mut finish_reason = None;
+    while let Some(item) = stream.next().await {
+        for choice in item.data.unwrap().choices {
+            text.push_str(&choice.text);
+            finish_reason = choice.finish_reason.or(finish_reason);
+        }
+    }
+    assert_eq!(text, "one tw");
+    assert_eq!(finish_reason.as_deref(), Some("stop"));
+}
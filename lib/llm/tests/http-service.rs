@@ -15,12 +15,17 @@
 
 use anyhow::Error;
 use async_stream::stream;
+use dynamo_llm::auth::{ApiKey, ApiKeys};
+use dynamo_llm::engines::{make_engine_embeddings, make_engine_full, StreamingEngineAdapter};
 use dynamo_llm::http::service::{
     error::HttpError,
+    idempotency::IdempotencyConfig,
     metrics::{Endpoint, RequestType, Status},
-    service_v2::HttpService,
+    model_admin::{LoadedModel, ModelLoader},
+    service_v2::{HttpService, ReloadConfig},
     Metrics,
 };
+use dynamo_llm::model_card::ModelDeploymentCard;
 use dynamo_llm::protocols::{
     openai::{
         chat_completions::{NvCreateChatCompletionRequest, NvCreateChatCompletionStreamResponse},
@@ -29,6 +34,7 @@ use dynamo_llm::protocols::{
     Annotated,
 };
 use dynamo_runtime::{
+    drain::Drain,
     pipeline::{
         async_trait, AsyncEngine, AsyncEngineContextProvider, ManyOut, ResponseStream, SingleIn,
     },
@@ -69,6 +75,7 @@ impl
 
                 let output = NvCreateChatCompletionStreamResponse {
                     inner,
+                    nvext: None,
                 };
 
                 yield Annotated::from_data(output);
@@ -79,6 +86,100 @@ impl
     }
 }
 
+/// Generates until the request is stopped
+struct UntilStoppedEngine {}
+
+#[async_trait]
+impl
+    AsyncEngine<
+        SingleIn<NvCreateChatCompletionRequest>,
+        ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
+        Error,
+    > for UntilStoppedEngine
+{
+    async fn generate(
+        &self,
+        request: SingleIn<NvCreateChatCompletionRequest>,
+    ) -> Result<ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>, Error> {
+        let (request, context) = request.transfer(());
+        let ctx = context.context();
+        let generator = request.response_generator();
+
+        let request_ctx = ctx.clone();
+        let stream = stream! {
+            let mut i = 0;
+            while !request_ctx.is_stopped() {
+                let inner = generator.create_choice(i, Some(format!("choice {i}")), None, None);
+                let output = NvCreateChatCompletionStreamResponse { inner, nvext: None };
+                yield Annotated::from_data(output);
+                i += 1;
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        };
+
+        Ok(ResponseStream::new(Box::pin(stream), ctx))
+    }
+}
+
+/// Answers with its tag, and again once `gate` has a permit for it
+struct GatedEngine {
+    tag: String,
+    gate: Arc<tokio::sync::Semaphore>,
+}
+
+#[async_trait]
+impl
+    AsyncEngine<
+        SingleIn<NvCreateChatCompletionRequest>,
+        ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
+        Error,
+    > for GatedEngine
+{
+    async fn generate(
+        &self,
+        request: SingleIn<NvCreateChatCompletionRequest>,
+    ) -> Result<ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>, Error> {
+        let (request, context) = request.transfer(());
+        let ctx = context.context();
+        let generator = request.response_generator();
+        let tag = self.tag.clone();
+        let gate = self.gate.clone();
+
+        let stream = stream! {
+            for _ in 0..2 {
+                let inner = generator.create_choice(0, Some(tag.clone()), None, None);
+                yield Annotated::from_data(NvCreateChatCompletionStreamResponse { inner, nvext: None });
+                gate.acquire().await.unwrap().forget();
+            }
+        };
+
+        Ok(ResponseStream::new(Box::pin(stream), ctx))
+    }
+}
+
+/// Loads a [`GatedEngine`] tagged with the path it is asked for
+struct GatedLoader {
+    gate: Arc<tokio::sync::Semaphore>,
+}
+
+#[async_trait]
+impl ModelLoader for GatedLoader {
+    async fn load(&self, path: &str, name: Option<&str>) -> anyhow::Result<LoadedModel> {
+        Ok(LoadedModel {
+            card: ModelDeploymentCard::with_name_only(name.unwrap_or(path)),
+            chat: Arc::new(GatedEngine {
+                tag: path.to_string(),
+                gate: self.gate.clone(),
+            }),
+            completions: Arc::new(StreamingEngineAdapter::new(make_engine_full())),
+        })
+    }
+
+    async fn unload(&self, _name: &str) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
 struct AlwaysFailEngine {}
 
 #[async_trait]
@@ -138,6 +239,9 @@ fn compute_index(endpoint: &Endpoint, request_type: &RequestType, status: &Statu
     let endpoint = match endpoint {
         Endpoint::Completions => 0,
         Endpoint::ChatCompletions => 1,
+        Endpoint::Embeddings | Endpoint::Transcriptions => {
+            unreachable!("only the completions endpoints are counted")
+        }
     };
 
     let request_type = match request_type {
@@ -314,6 +418,12 @@ async fn test_http_service() {
     }
 
     assert!(found, "The expected bucket was not found");
+
+    // Token latencies are the pre-processor's, the HTTP service doesn't repeat them
+    let families = registry.gather();
+    assert!(!families
+        .iter()
+        .any(|m| m.get_name().ends_with("_time_to_first_token_seconds")));
     // ==== ChatCompletions / Stream / Success ====
 
     // ==== ChatCompletions / Unary / Success ====
@@ -460,6 +570,819 @@ async fn test_http_service() {
     assert!(response.status().is_success(), "{:?}", response);
     println!("{}", response.text().await.unwrap());
 
+    // =========== Models come and go ===========
+    let client = &client;
+    let list_models = move || async move {
+        let response = client
+            .get("http://localhost:8989/v1/models")
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success(), "{:?}", response);
+        response.json::<serde_json::Value>().await.unwrap()
+    };
+    let models = list_models().await;
+    let ids: Vec<_> = models["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|model| model["id"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(ids, vec!["bar", "foo"]);
+    assert_eq!(models["data"][0]["modality"], "text->text");
+
+    assert!(manager.remove_model("bar"));
+    assert!(!manager.remove_model("bar"));
+    let models = list_models().await;
+    assert_eq!(models["data"].as_array().unwrap().len(), 1);
+    assert_eq!(models["data"][0]["id"], "foo");
+
     cancel_token.cancel();
     task.await.unwrap().unwrap();
 }
+
+#[tokio::test]
+async fn test_cancel_request() {
+    let service = HttpService::builder().port(8990).build().unwrap();
+    let manager = service.model_manager().clone();
+    let token = CancellationToken::new();
+    let task = tokio::spawn({
+        let token = token.clone();
+        async move { service.run(token).await }
+    });
+    manager
+        .add_chat_completions_model("forever", Arc::new(UntilStoppedEngine {}))
+        .unwrap();
+
+    let request = serde_json::json!({
+        "model": "forever",
+        "messages": [{"role": "user", "content": "hi"}],
+        "stream": true,
+    });
+    let client = reqwest::Client::new();
+    let response = client
+        .post("http://localhost:8990/v1/chat/completions")
+        .json(&request)
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success(), "{:?}", response);
+    let request_id = response.headers()["x-request-id"]
+        .to_str()
+        .unwrap()
+        .to_string();
+
+    let cancel_url = format!("http://localhost:8990/v1/requests/{request_id}/cancel");
+    let cancelled = client.post(&cancel_url).send().await.unwrap();
+    assert_eq!(cancelled.status(), StatusCode::OK);
+
+    // The stream ends instead of going on forever
+    let body = tokio::time::timeout(std::time::Duration::from_secs(5), response.text())
+        .await
+        .expect("stream did not end after cancel")
+        .unwrap();
+    assert!(body.contains("choice 0"));
+
+    let again = client.post(&cancel_url).send().await.unwrap();
+    assert_eq!(again.status(), StatusCode::NOT_FOUND);
+
+    token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_drain() {
+    let drain = Arc::new(Drain::default());
+    let service = HttpService::builder()
+        .port(8991)
+        .drain(Some(drain.clone()))
+        .build()
+        .unwrap();
+    let manager = service.model_manager().clone();
+    let token = CancellationToken::new();
+    let task = tokio::spawn({
+        let token = token.clone();
+        async move { service.run(token).await }
+    });
+    manager
+        .add_chat_completions_model("forever", Arc::new(UntilStoppedEngine {}))
+        .unwrap();
+
+    let request = serde_json::json!({
+        "model": "forever",
+        "messages": [{"role": "user", "content": "hi"}],
+        "stream": true,
+    });
+    let client = reqwest::Client::new();
+    let url = "http://localhost:8991/v1/chat/completions";
+    let running = client.post(url).json(&request).send().await.unwrap();
+    assert!(running.status().is_success(), "{:?}", running);
+    assert_eq!(drain.in_flight(), 1);
+
+    drain.start();
+    let rejected = client.post(url).json(&request).send().await.unwrap();
+    assert_eq!(rejected.status(), StatusCode::SERVICE_UNAVAILABLE);
+    // Scrapers still get through
+    let metrics = client
+        .get("http://localhost:8991/metrics")
+        .send()
+        .await
+        .unwrap();
+    assert!(metrics.status().is_success());
+
+    // The running stream isn't cut, it is done when its body is
+    assert_eq!(
+        drain.wait_idle(std::time::Duration::from_millis(50)).await,
+        1
+    );
+    let request_id = running.headers()["x-request-id"]
+        .to_str()
+        .unwrap()
+        .to_string();
+    client
+        .post(format!(
+            "http://localhost:8991/v1/requests/{request_id}/cancel"
+        ))
+        .send()
+        .await
+        .unwrap();
+    running.text().await.unwrap();
+    assert_eq!(drain.wait_idle(std::time::Duration::from_secs(5)).await, 0);
+
+    token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_probes() {
+    let drain = Arc::new(Drain::default());
+    let service = HttpService::builder()
+        .port(8993)
+        .drain(Some(drain.clone()))
+        .build()
+        .unwrap();
+    let manager = service.model_manager().clone();
+    let token = CancellationToken::new();
+    let task = tokio::spawn({
+        let token = token.clone();
+        async move { service.run(token).await }
+    });
+    // Let the service bind
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let client = reqwest::Client::new();
+    let get = |path: &str| client.get(format!("http://localhost:8993{path}")).send();
+    assert_eq!(get("/live").await.unwrap().status(), StatusCode::OK);
+    assert_eq!(
+        get("/ready").await.unwrap().status(),
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+
+    manager
+        .add_chat_completions_model("foo", Arc::new(CounterEngine {}))
+        .unwrap();
+    assert_eq!(get("/ready").await.unwrap().status(), StatusCode::OK);
+    let health: serde_json::Value = get("/health").await.unwrap().json().await.unwrap();
+    assert_eq!(health["status"], "ready");
+    assert_eq!(health["models"], serde_json::json!(["foo"]));
+
+    drain.start();
+    let health = get("/health").await.unwrap();
+    assert_eq!(health.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let health: serde_json::Value = health.json().await.unwrap();
+    assert_eq!(health["status"], "draining");
+    assert_eq!(get("/live").await.unwrap().status(), StatusCode::OK);
+
+    token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_reload() {
+    let service = HttpService::builder().port(8992).build().unwrap();
+    let manager = service.model_manager().clone();
+    let token = CancellationToken::new();
+    let task = tokio::spawn({
+        let service = service.clone();
+        let token = token.clone();
+        async move { service.run(token).await }
+    });
+    manager
+        .add_chat_completions_model("forever", Arc::new(UntilStoppedEngine {}))
+        .unwrap();
+
+    let chat = |model: &str| {
+        serde_json::json!({
+            "model": model,
+            "messages": [{"role": "user", "content": "hi"}],
+            "stream": true,
+        })
+    };
+    let client = reqwest::Client::new();
+    let url = "http://localhost:8992/v1/chat/completions";
+    let running = client
+        .post(url)
+        .json(&chat("forever"))
+        .send()
+        .await
+        .unwrap();
+    assert!(running.status().is_success(), "{:?}", running);
+
+    let mut api_keys = ApiKeys::default();
+    let api_key = ApiKey {
+        name: "a".to_string(),
+        models: None,
+        namespace: None,
+    };
+    api_keys.insert("sk-a", api_key).unwrap();
+    let reload = ReloadConfig {
+        api_keys: Some(Arc::new(api_keys)),
+        model_aliases: [("gpt".to_string(), vec!["forever".parse().unwrap()])]
+            .into_iter()
+            .collect(),
+        ..Default::default()
+    };
+    service.reload(reload).unwrap();
+
+    let unauthenticated = client
+        .post(url)
+        .json(&chat("forever"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(unauthenticated.status(), StatusCode::UNAUTHORIZED);
+    let aliased = client
+        .post(url)
+        .bearer_auth("sk-a")
+        .json(&chat("gpt"))
+        .send()
+        .await
+        .unwrap();
+    assert!(aliased.status().is_success(), "{:?}", aliased);
+    let models: serde_json::Value = client
+        .get("http://localhost:8992/v1/models")
+        .bearer_auth("sk-a")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let ids: Vec<&str> = models["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|model| model["id"].as_str().unwrap())
+        .collect();
+    assert_eq!(ids, ["forever", "gpt"]);
+
+    // A key namespace needs tenants, the failed reload changes nothing
+    let mut api_keys = ApiKeys::default();
+    let api_key = ApiKey {
+        name: "b".to_string(),
+        models: None,
+        namespace: Some("team-b".to_string()),
+    };
+    api_keys.insert("sk-b", api_key).unwrap();
+    let reload = ReloadConfig {
+        api_keys: Some(Arc::new(api_keys)),
+        ..Default::default()
+    };
+    assert!(service.reload(reload).is_err());
+
+    // The stream started before the reload goes on, and can still be cancelled with the key
+    for response in [running, aliased] {
+        let request_id = response.headers()["x-request-id"]
+            .to_str()
+            .unwrap()
+            .to_string();
+        client
+            .post(format!(
+                "http://localhost:8992/v1/requests/{request_id}/cancel"
+            ))
+            .bearer_auth("sk-a")
+            .send()
+            .await
+            .unwrap();
+        let body = tokio::time::timeout(std::time::Duration::from_secs(5), response.text())
+            .await
+            .expect("stream did not end after cancel")
+            .unwrap();
+        assert!(body.contains("choice 0"));
+    }
+
+    token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+/// Uploads `contents` to `POST /v1/files` as a batch input, returns the file id
+async fn upload_batch_file(client: &reqwest::Client, base: &str, contents: &str) -> String {
+    let boundary = "dynamo-test-boundary";
+    let body = format!(
+        "--{boundary}\r\nContent-Disposition: form-data; name=\"purpose\"\r\n\r\nbatch\r\n\
+         --{boundary}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"requests.jsonl\"\r\n\
+         Content-Type: application/jsonl\r\n\r\n{contents}\r\n--{boundary}--\r\n"
+    );
+    let response = client
+        .post(format!("{base}/v1/files"))
+        .header(
+            "content-type",
+            format!("multipart/form-data; boundary={boundary}"),
+        )
+        .body(body)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let file: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(file["purpose"], "batch");
+    file["id"].as_str().unwrap().to_string()
+}
+
+/// Polls `GET /v1/batches/{batch_id}` until the batch is `status`
+async fn wait_for_batch(
+    client: &reqwest::Client,
+    base: &str,
+    batch_id: &str,
+    status: &str,
+) -> serde_json::Value {
+    let url = format!("{base}/v1/batches/{batch_id}");
+    for _ in 0..100 {
+        let batch: serde_json::Value = client.get(&url).send().await.unwrap().json().await.unwrap();
+        if batch["status"] == status {
+            return batch;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    panic!("batch {batch_id} never got {status}");
+}
+
+#[tokio::test]
+async fn test_batches() {
+    let batch_dir = tempfile::tempdir().unwrap();
+    let service = HttpService::builder()
+        .port(8994)
+        .enable_batches_endpoints(true)
+        .batch_dir(Some(batch_dir.path().to_path_buf()))
+        .batch_concurrency(Some(2))
+        .build()
+        .unwrap();
+    let manager = service.model_manager().clone();
+    let token = CancellationToken::new();
+    let task = tokio::spawn({
+        let token = token.clone();
+        async move { service.run(token).await }
+    });
+    manager
+        .add_chat_completions_model(
+            "echo",
+            Arc::new(StreamingEngineAdapter::new(make_engine_full())),
+        )
+        .unwrap();
+    manager
+        .add_chat_completions_model("forever", Arc::new(UntilStoppedEngine {}))
+        .unwrap();
+    // Let the service bind
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let client = reqwest::Client::new();
+    let base = "http://localhost:8994";
+    let line = |custom_id: &str, model: &str| {
+        serde_json::json!({
+            "custom_id": custom_id,
+            "method": "POST",
+            "url": "/v1/chat/completions",
+            "body": {"model": model, "messages": [{"role": "user", "content": "Hi"}]},
+        })
+        .to_string()
+    };
+
+    // One line the echo engine answers, one for a model nobody serves
+    let contents = format!("{}\n{}\n", line("ok", "echo"), line("missing", "nope"));
+    let input_file_id = upload_batch_file(&client, base, &contents).await;
+    let create = serde_json::json!({
+        "input_file_id": input_file_id,
+        "endpoint": "/v1/chat/completions",
+    });
+    let batch: serde_json::Value = client
+        .post(format!("{base}/v1/batches"))
+        .json(&create)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let batch_id = batch["id"].as_str().unwrap().to_string();
+
+    let batch = wait_for_batch(&client, base, &batch_id, "completed").await;
+    assert_eq!(
+        batch["request_counts"],
+        serde_json::json!({"total": 2, "completed": 1, "failed": 1})
+    );
+    let content = |file_id: &serde_json::Value| {
+        let url = format!("{base}/v1/files/{}/content", file_id.as_str().unwrap());
+        let client = client.clone();
+        async move { client.get(url).send().await.unwrap().text().await.unwrap() }
+    };
+    let output = content(&batch["output_file_id"]).await;
+    let output: serde_json::Value = serde_json::from_str(output.trim()).unwrap();
+    assert_eq!(output["custom_id"], "ok");
+    assert_eq!(output["response"]["status_code"], 200);
+    assert_eq!(
+        output["response"]["body"]["choices"][0]["message"]["content"],
+        "Hi"
+    );
+    let errors = content(&batch["error_file_id"]).await;
+    let errors: serde_json::Value = serde_json::from_str(errors.trim()).unwrap();
+    assert_eq!(errors["custom_id"], "missing");
+    assert_eq!(errors["error"]["code"], "model_not_found");
+
+    // A batch that can't finish on its own is cancelled
+    let input_file_id = upload_batch_file(&client, base, &line("stuck", "forever")).await;
+    let create = serde_json::json!({
+        "input_file_id": input_file_id,
+        "endpoint": "/v1/chat/completions",
+    });
+    let batch: serde_json::Value = client
+        .post(format!("{base}/v1/batches"))
+        .json(&create)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let batch_id = batch["id"].as_str().unwrap().to_string();
+    wait_for_batch(&client, base, &batch_id, "in_progress").await;
+    let cancel_url = format!("{base}/v1/batches/{batch_id}/cancel");
+    let cancelling: serde_json::Value = client
+        .post(&cancel_url)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(cancelling["status"], "cancelling");
+    let batch = wait_for_batch(&client, base, &batch_id, "cancelled").await;
+    assert_eq!(batch["request_counts"]["completed"], 0);
+    assert!(batch["error_file_id"].is_null());
+
+    let unknown = client
+        .post(format!("{base}/v1/batches/batch_unknown/cancel"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(unknown.status(), StatusCode::NOT_FOUND);
+
+    token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+/// A client sending `api_key` with every request
+fn client_with_key(api_key: &str) -> reqwest::Client {
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert(
+        reqwest::header::AUTHORIZATION,
+        format!("Bearer {api_key}").parse().unwrap(),
+    );
+    reqwest::Client::builder()
+        .default_headers(headers)
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_batch_owners() {
+    let batch_dir = tempfile::tempdir().unwrap();
+    let mut api_keys = ApiKeys::default();
+    let key_a = ApiKey {
+        name: "a".to_string(),
+        models: Some(vec!["echo".to_string()]),
+        namespace: None,
+    };
+    api_keys.insert("sk-a", key_a).unwrap();
+    let key_b = ApiKey {
+        name: "b".to_string(),
+        models: None,
+        namespace: None,
+    };
+    api_keys.insert("sk-b", key_b).unwrap();
+    let service = HttpService::builder()
+        .port(8997)
+        .enable_batches_endpoints(true)
+        .batch_dir(Some(batch_dir.path().to_path_buf()))
+        .api_keys(Some(Arc::new(api_keys)))
+        .build()
+        .unwrap();
+    let manager = service.model_manager().clone();
+    let token = CancellationToken::new();
+    let task = tokio::spawn({
+        let token = token.clone();
+        async move { service.run(token).await }
+    });
+    manager
+        .add_chat_completions_model(
+            "echo",
+            Arc::new(StreamingEngineAdapter::new(make_engine_full())),
+        )
+        .unwrap();
+    manager
+        .add_chat_completions_model("forever", Arc::new(UntilStoppedEngine {}))
+        .unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let base = "http://localhost:8997";
+    let a = client_with_key("sk-a");
+    let b = client_with_key("sk-b");
+    let line = |custom_id: &str, model: &str| {
+        serde_json::json!({
+            "custom_id": custom_id,
+            "url": "/v1/chat/completions",
+            "body": {"model": model, "messages": [{"role": "user", "content": "Hi"}]},
+        })
+        .to_string()
+    };
+    let files_on_disk = || std::fs::read_dir(batch_dir.path()).unwrap().count();
+
+    // Key a may use echo but not forever
+    let contents = format!("{}\n{}\n", line("ok", "echo"), line("denied", "forever"));
+    let input_file_id = upload_batch_file(&a, base, &contents).await;
+    let file_url = format!("{base}/v1/files/{input_file_id}");
+    let create = serde_json::json!({
+        "input_file_id": input_file_id,
+        "endpoint": "/v1/chat/completions",
+    });
+
+    // Nobody else sees the file or can batch it
+    for url in [file_url.clone(), format!("{file_url}/content")] {
+        let response = b.get(&url).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+    let response = b
+        .post(format!("{base}/v1/batches"))
+        .json(&create)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let batch: serde_json::Value = a
+        .post(format!("{base}/v1/batches"))
+        .json(&create)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let batch_id = batch["id"].as_str().unwrap().to_string();
+    let batch = wait_for_batch(&a, base, &batch_id, "completed").await;
+    assert_eq!(
+        batch["request_counts"],
+        serde_json::json!({"total": 2, "completed": 1, "failed": 1})
+    );
+    let error_url = format!(
+        "{base}/v1/files/{}/content",
+        batch["error_file_id"].as_str().unwrap()
+    );
+    let errors = a.get(error_url).send().await.unwrap().text().await.unwrap();
+    let errors: serde_json::Value = serde_json::from_str(errors.trim()).unwrap();
+    assert_eq!(errors["custom_id"], "denied");
+    assert_eq!(errors["error"]["code"], "forbidden");
+
+    // Nor the batch
+    let batch_url = format!("{base}/v1/batches/{batch_id}");
+    let response = b.get(&batch_url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = b.post(format!("{batch_url}/cancel")).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = b.delete(&batch_url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // Deleting the batch deletes its output and error files, deleting the input the last one
+    assert_eq!(files_on_disk(), 3);
+    let deleted: serde_json::Value = a
+        .delete(&batch_url)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(deleted["deleted"], true);
+    assert_eq!(files_on_disk(), 1);
+    let response = a.get(&batch_url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = a.delete(&file_url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(files_on_disk(), 0);
+    let response = a.get(&file_url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_idempotency_per_client() {
+    let service = HttpService::builder()
+        .port(8998)
+        .idempotency(Some(IdempotencyConfig::new(
+            std::time::Duration::from_secs(60),
+        )))
+        .build()
+        .unwrap();
+    let manager = service.model_manager().clone();
+    let token = CancellationToken::new();
+    let task = tokio::spawn({
+        let token = token.clone();
+        async move { service.run(token).await }
+    });
+    manager
+        .add_chat_completions_model(
+            "echo",
+            Arc::new(StreamingEngineAdapter::new(make_engine_full())),
+        )
+        .unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    // Without API keys, two clients are told apart by their address
+    let client_at = |ip: [u8; 4]| {
+        reqwest::Client::builder()
+            .local_address(std::net::IpAddr::from(ip))
+            .build()
+            .unwrap()
+    };
+    let request = serde_json::json!({
+        "model": "echo",
+        "messages": [{"role": "user", "content": "Hi"}],
+    });
+    let send = |client: reqwest::Client| {
+        let request = request.clone();
+        async move {
+            client
+                .post("http://127.0.0.1:8998/v1/chat/completions")
+                .header("Idempotency-Key", "retry-1")
+                .json(&request)
+                .send()
+                .await
+                .unwrap()
+        }
+    };
+    let request_id = |response: &reqwest::Response| response.headers()["x-request-id"].clone();
+
+    let first = send(client_at([127, 0, 0, 1])).await;
+    assert_eq!(first.status(), StatusCode::OK);
+    let retry = send(client_at([127, 0, 0, 1])).await;
+    assert_eq!(retry.headers()["idempotent-replayed"], "true");
+    assert_eq!(request_id(&retry), request_id(&first));
+
+    let other = send(client_at([127, 0, 0, 2])).await;
+    assert_eq!(other.status(), StatusCode::OK);
+    assert!(other.headers().get("idempotent-replayed").is_none());
+    assert_ne!(request_id(&other), request_id(&first));
+
+    token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_embeddings() {
+    let service = HttpService::builder().port(8995).build().unwrap();
+    let manager = service.model_manager().clone();
+    let token = CancellationToken::new();
+    let task = tokio::spawn({
+        let token = token.clone();
+        async move { service.run(token).await }
+    });
+    manager
+        .add_embeddings_model("echo", make_engine_embeddings())
+        .unwrap();
+    manager
+        .add_chat_completions_model(
+            "chat",
+            Arc::new(StreamingEngineAdapter::new(make_engine_full())),
+        )
+        .unwrap();
+    // Let the service bind
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let client = reqwest::Client::new();
+    let url = "http://localhost:8995/v1/embeddings";
+    let embed = |body: serde_json::Value| client.post(url).json(&body).send();
+
+    let response = embed(serde_json::json!({"model": "echo", "input": ["hello", "hello", "bye"]}))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let data = body["data"].as_array().unwrap();
+    assert_eq!(data.len(), 3);
+    assert_eq!(data[2]["index"], 2);
+    assert_eq!(data[0]["embedding"].as_array().unwrap().len(), 16);
+    assert_eq!(data[0]["embedding"], data[1]["embedding"]);
+    assert_ne!(data[0]["embedding"], data[2]["embedding"]);
+    assert_eq!(body["usage"]["prompt_tokens"], 13);
+
+    let response = embed(serde_json::json!({"model": "echo", "input": "hi", "dimensions": 4}))
+        .await
+        .unwrap();
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["data"][0]["embedding"].as_array().unwrap().len(), 4);
+
+    let response =
+        embed(serde_json::json!({"model": "echo", "input": "hi", "encoding_format": "base64"}))
+            .await
+            .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // A chat model doesn't do embeddings, and nobody serves this one
+    let response = embed(serde_json::json!({"model": "chat", "input": "hi"}))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = embed(serde_json::json!({"model": "nope", "input": "hi"}))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_model_admin_swap() {
+    let gate = Arc::new(tokio::sync::Semaphore::new(0));
+    let service = HttpService::builder()
+        .port(8996)
+        .model_loader(Some(Arc::new(GatedLoader { gate: gate.clone() })))
+        .build()
+        .unwrap();
+    let token = CancellationToken::new();
+    let task = tokio::spawn({
+        let token = token.clone();
+        async move { service.run(token).await }
+    });
+    // Let the service bind
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let client = reqwest::Client::new();
+    let base = "http://localhost:8996";
+    let load = |path: &str| {
+        client
+            .post(format!("{base}/admin/models/load"))
+            .json(&serde_json::json!({"path": path, "name": "m"}))
+            .send()
+    };
+    let chat = || {
+        client
+            .post(format!("{base}/v1/chat/completions"))
+            .json(&serde_json::json!({
+                "model": "m",
+                "messages": [{"role": "user", "content": "Hi"}],
+                "stream": true,
+            }))
+            .send()
+    };
+
+    let response = load("v1").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["replaced"], false);
+
+    // Streams its first chunk, then waits on the gate
+    let in_flight = chat().await.unwrap();
+    assert_eq!(in_flight.status(), StatusCode::OK);
+
+    let response = load("v2").await.unwrap();
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["replaced"], true);
+
+    // New requests go to the new engine while the old one still streams
+    let swapped = chat().await.unwrap();
+    gate.add_permits(4);
+    let old = in_flight.text().await.unwrap();
+    assert_eq!(old.matches("v1").count(), 2, "{old}");
+    assert!(!old.contains("v2"), "{old}");
+    let new = swapped.text().await.unwrap();
+    assert_eq!(new.matches("v2").count(), 2, "{new}");
+
+    // Unloading lets the running request finish
+    let in_flight = chat().await.unwrap();
+    let response = client
+        .post(format!("{base}/admin/models/unload"))
+        .json(&serde_json::json!({"name": "m"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    gate.add_permits(2);
+    let text = in_flight.text().await.unwrap();
+    assert_eq!(text.matches("v2").count(), 2, "{text}");
+    assert_eq!(chat().await.unwrap().status(), StatusCode::NOT_FOUND);
+
+    token.cancel();
+    task.await.unwrap().unwrap();
+}
@@ -29,6 +29,18 @@ async fn test_model_info_from_hf_like_local_repo() {
     assert_eq!(info.vocab_size(), 32000);
 }
 
+#[tokio::test]
+async fn test_stop_token_ids_from_generation_config() {
+    let mdc = ModelDeploymentCard::load(HF_PATH).await.unwrap();
+    assert_eq!(mdc.stop_token_ids, vec![2]);
+
+    // config.json only has <|eot_id|>, generation_config.json adds <|end_of_text|>
+    let mdc = ModelDeploymentCard::load("tests/data/sample-models/mock-llama-3.1-8b-instruct")
+        .await
+        .unwrap();
+    assert_eq!(mdc.stop_token_ids, vec![128001, 128009]);
+}
+
 #[tokio::test]
 async fn test_model_info_from_non_existent_local_repo() {
     let path = "tests/data/sample-models/this-model-does-not-exist";
@@ -43,6 +55,8 @@ async fn test_tokenizer_from_hf_like_local_repo() {
     match mdc.tokenizer.unwrap() {
         TokenizerKind::HfTokenizerJson(_) => (),
         TokenizerKind::GGUF(_) => (),
+        TokenizerKind::SentencePiece(_) => (),
+        TokenizerKind::Tiktoken(_) => (),
     }
 }
 
@@ -66,3 +80,99 @@ async fn test_missing_required_files() {
     // Should fail because config.json is missing
     assert!(err.contains("unable to extract"));
 }
+
+#[tokio::test]
+async fn test_vocab_checksum() {
+    let mdc = ModelDeploymentCard::load(HF_PATH).await.unwrap();
+    let checksum = mdc.vocab_checksum().unwrap();
+
+    // Same files elsewhere, under another name
+    let temp_dir = tempdir().unwrap();
+    for file in ["config.json", "tokenizer.json", "tokenizer_config.json"] {
+        std::fs::copy(format!("{HF_PATH}/{file}"), temp_dir.path().join(file)).unwrap();
+    }
+    let mut copy = ModelDeploymentCard::load(temp_dir.path()).await.unwrap();
+    copy.set_name("copy");
+    assert_eq!(copy.vocab_checksum().unwrap(), checksum);
+
+    // Another template
+    std::fs::write(temp_dir.path().join("tokenizer_config.json"), "{}").unwrap();
+    assert_ne!(copy.vocab_checksum().unwrap(), checksum);
+
+    assert!(ModelDeploymentCard::with_name_only("blank")
+        .vocab_checksum()
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_tokenizer_override() {
+    // A model without a tokenizer of its own
+    let temp_dir = tempdir().unwrap();
+    std::fs::copy(
+        format!("{HF_PATH}/config.json"),
+        temp_dir.path().join("config.json"),
+    )
+    .unwrap();
+    assert!(ModelDeploymentCard::load(temp_dir.path()).await.is_err());
+
+    let tokenizer = std::path::PathBuf::from(format!("{HF_PATH}/tokenizer.json"));
+    let mdc = ModelDeploymentCard::load_with_tokenizer(temp_dir.path(), Some(&tokenizer))
+        .await
+        .unwrap();
+    match mdc.tokenizer.as_ref().unwrap() {
+        TokenizerKind::HfTokenizerJson(file) => assert!(file.ends_with("tokenizer.json")),
+        other => panic!("Expected a tokenizer.json, got {other:?}"),
+    }
+    assert!(mdc.tokenizer().is_ok());
+}
+
+#[test]
+fn test_tokenizer_kind_from_file() {
+    let temp_dir = tempdir().unwrap();
+    let write = |name: &str, contents: &[u8]| {
+        let path = temp_dir.path().join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    };
+
+    let tiktoken = write("tokenizer.model", b"IQ== 0\nIg== 1\n");
+    assert!(matches!(
+        TokenizerKind::from_file(&tiktoken).unwrap(),
+        TokenizerKind::Tiktoken(_)
+    ));
+    let sentencepiece = std::path::PathBuf::from(format!("{HF_PATH}/tokenizer.model"));
+    assert!(matches!(
+        TokenizerKind::from_file(&sentencepiece).unwrap(),
+        TokenizerKind::SentencePiece(_)
+    ));
+    let hf = write("tokenizer.json", b"{}");
+    assert!(matches!(
+        TokenizerKind::from_file(&hf).unwrap(),
+        TokenizerKind::HfTokenizerJson(_)
+    ));
+    assert!(TokenizerKind::from_file(&write("vocab.txt", b"")).is_err());
+    assert!(TokenizerKind::from_file(&temp_dir.path().join("missing.json")).is_err());
+}
+
+#[tokio::test]
+async fn test_tokenizer_without_tokenizer_json() {
+    // Only the SentencePiece model
+    let temp_dir = tempdir().unwrap();
+    for file in ["config.json", "tokenizer.model"] {
+        std::fs::copy(format!("{HF_PATH}/{file}"), temp_dir.path().join(file)).unwrap();
+    }
+    let mdc = ModelDeploymentCard::load(temp_dir.path()).await.unwrap();
+    assert!(matches!(
+        mdc.tokenizer.as_ref().unwrap(),
+        TokenizerKind::SentencePiece(_)
+    ));
+
+    // Only a tiktoken vocabulary
+    std::fs::remove_file(temp_dir.path().join("tokenizer.model")).unwrap();
+    std::fs::write(temp_dir.path().join("qwen.tiktoken"), b"IQ== 0\nIg== 1\n").unwrap();
+    let mdc = ModelDeploymentCard::load(temp_dir.path()).await.unwrap();
+    match mdc.tokenizer.as_ref().unwrap() {
+        TokenizerKind::Tiktoken(file) => assert!(file.ends_with("qwen.tiktoken")),
+        other => panic!("Expected a tiktoken file, got {other:?}"),
+    }
+}
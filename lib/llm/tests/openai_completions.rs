@@ -13,8 +13,20 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
+
 use async_openai::types::CreateCompletionRequestArgs;
-use dynamo_llm::protocols::openai::{self, completions::CompletionRequest};
+use dynamo_llm::preprocessor::prompt::OAIChatLikeRequest;
+use dynamo_llm::protocols::common::llm_backend::{BackendOutput, PositionLogProbs, TokenLogProb};
+use dynamo_llm::protocols::common::{
+    OutputOptionsProvider, SamplingOptionsProvider, StopConditionsProvider,
+};
+use dynamo_llm::protocols::openai::{
+    self,
+    completions::CompletionRequest,
+    nvext::{NvExt, NvExtProvider},
+    DeltaGeneratorExt,
+};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -73,6 +85,119 @@ fn valid_samples() {
         });
     }
 }
+#[test]
+fn token_prompt_and_stop() {
+    let inner = CreateCompletionRequestArgs::default()
+        .model("gpt-3.5-turbo")
+        .prompt(vec![1_u16, 2, 3])
+        .stop(vec!["\n".to_string()])
+        .build()
+        .unwrap();
+    let request = CompletionRequest { inner, nvext: None };
+    assert_eq!(request.prompt_token_ids().unwrap(), Some(vec![1, 2, 3]));
+
+    let stop_conditions = request.extract_stop_conditions().unwrap();
+    assert_eq!(stop_conditions.stop, Some(vec!["\n".to_string()]));
+
+    let inner = CreateCompletionRequestArgs::default()
+        .model("gpt-3.5-turbo")
+        .prompt(vec![vec![1_u16], vec![2]])
+        .build()
+        .unwrap();
+    let request = CompletionRequest { inner, nvext: None };
+    assert!(request.prompt_token_ids().is_err());
+}
+
+#[test]
+fn raw_prompt_by_default() {
+    let inner = CreateCompletionRequestArgs::default()
+        .model("gpt-3.5-turbo")
+        .prompt("Once upon a time")
+        .build()
+        .unwrap();
+    let mut request = CompletionRequest { inner, nvext: None };
+    assert!(request.use_raw_prompt());
+    assert_eq!(request.raw_prompt().as_deref(), Some("Once upon a time"));
+    assert_eq!(request.prompt_token_ids().unwrap(), None);
+
+    request.nvext = Some(NvExt::builder().use_raw_prompt(false).build().unwrap());
+    assert!(!request.use_raw_prompt());
+    assert_eq!(request.raw_prompt(), None);
+}
+
+#[test]
+fn logit_bias() {
+    let inner = CreateCompletionRequestArgs::default()
+        .model("gpt-3.5-turbo")
+        .prompt("Once upon a time")
+        .logit_bias(HashMap::from([
+            ("50256".to_string(), serde_json::json!(-100)),
+            ("11".to_string(), serde_json::json!(2.5)),
+        ]))
+        .build()
+        .unwrap();
+    let mut request = CompletionRequest { inner, nvext: None };
+    let sampling = request.extract_sampling_options().unwrap();
+    assert_eq!(
+        sampling.logit_bias,
+        Some(HashMap::from([(50256, -100.0), (11, 2.5)]))
+    );
+
+    request.inner.logit_bias = Some(HashMap::from([("hello".to_string(), serde_json::json!(1))]));
+    let err = request.extract_sampling_options().unwrap_err();
+    assert_eq!(err.to_string(), "logit_bias key 'hello' is not a token id");
+}
+
+#[test]
+fn logprobs() {
+    let inner = CreateCompletionRequestArgs::default()
+        .model("gpt-3.5-turbo")
+        .prompt("Once upon a time")
+        .logprobs(1)
+        .build()
+        .unwrap();
+    let mut request = CompletionRequest { inner, nvext: None };
+    assert_eq!(request.extract_output_options().unwrap().logprobs, Some(1));
+
+    let logprob = |token_id, token: &str, logprob| TokenLogProb {
+        token_id,
+        token: Some(token.to_string()),
+        logprob,
+    };
+    let mut generator = request.response_generator();
+    let mut chunk = |token_id, token: &str| {
+        let output = BackendOutput {
+            token_ids: vec![token_id],
+            tokens: vec![Some(token.to_string())],
+            text: Some(token.to_string()),
+            cum_log_probs: None,
+            log_probs: None,
+            logprobs: Some(vec![PositionLogProbs {
+                token: logprob(token_id, token, -0.5),
+                top: vec![logprob(token_id, token, -0.5)],
+            }]),
+            finish_reason: None,
+            spec_decode: None,
+        };
+        generator.choice_from_postprocessor(output).unwrap().choices[0]
+            .logprobs
+            .clone()
+            .unwrap()
+    };
+    let first = chunk(11, " there");
+    let second = chunk(12, " was");
+    assert_eq!(first.tokens, vec![" there"]);
+    assert_eq!(first.text_offset, vec![0]);
+    assert_eq!(second.text_offset, vec![6]);
+    assert_eq!(
+        second.top_logprobs[0],
+        HashMap::from([(" was".to_string(), -0.5)])
+    );
+
+    request.inner.logprobs = Some(6);
+    assert!(request.extract_output_options().is_err());
+}
+
 #[allow(clippy::vec_init_then_push)]
 fn build_samples() -> Result<Vec<CompletionSample>, String> {
     let mut samples = Vec::new();
@@ -17,6 +17,7 @@ use anyhow::Ok;
 
 use dynamo_llm::model_card::model::{ModelDeploymentCard, PromptContextMixin};
 use dynamo_llm::preprocessor::prompt::PromptFormatter;
+use dynamo_llm::preprocessor::{media::MediaError, OpenAIPreprocessor};
 use dynamo_llm::protocols::openai::chat_completions::NvCreateChatCompletionRequest;
 use serde::{Deserialize, Serialize};
 
@@ -468,3 +469,119 @@ async fn test_multi_turn_with_continuation() {
       insta::assert_snapshot!(formatted_prompt);
     });
 }
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_tokenize_request_matches_preprocess() {
+    let mdc = ModelDeploymentCard::load("tests/data/sample-models/mock-llama-3.1-8b-instruct")
+        .await
+        .unwrap();
+    let preprocessor = OpenAIPreprocessor::new(mdc).await.unwrap();
+
+    let request: NvCreateChatCompletionRequest = serde_json::from_value(serde_json::json!({
+        "model": "mock-llama-3.1-8b-instruct",
+        "messages": [{"role": "user", "content": "What is deep learning?"}],
+    }))
+    .unwrap();
+
+    let (formatted_prompt, token_ids) = preprocessor.tokenize_request(&request).unwrap();
+    assert!(formatted_prompt.unwrap().contains("What is deep learning?"));
+
+    let (backend_input, _, _) = preprocessor.preprocess_request(&request).unwrap();
+    assert_eq!(token_ids, backend_input.token_ids);
+    assert_eq!(preprocessor.context_length(), 8192);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_prefix_tokens_reused() {
+    let mdc = ModelDeploymentCard::load("tests/data/sample-models/mock-llama-3.1-8b-instruct")
+        .await
+        .unwrap();
+    let preprocessor = OpenAIPreprocessor::new(mdc).await.unwrap();
+
+    // Long enough to be cached, the second and third requests reuse its tokens
+    let system = "You answer questions about geography, briefly. ".repeat(100);
+    for question in [
+        "What is the capital of France?",
+        "What is the capital of Peru?",
+        " and Chile?",
+    ] {
+        let request: NvCreateChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "mock-llama-3.1-8b-instruct",
+            "messages": [
+                {"role": "system", "content": system},
+                {"role": "user", "content": question},
+            ],
+        }))
+        .unwrap();
+        let (formatted_prompt, token_ids) = preprocessor.tokenize_request(&request).unwrap();
+        let whole = preprocessor.tokenize(&formatted_prompt.unwrap()).unwrap();
+        assert_eq!(token_ids, whole.token_ids);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_detokenize() {
+    let mdc = ModelDeploymentCard::load("tests/data/sample-models/mock-llama-3.1-8b-instruct")
+        .await
+        .unwrap();
+    let preprocessor = OpenAIPreprocessor::new(mdc).await.unwrap();
+
+    let encoding = preprocessor.tokenize("The capital of France").unwrap();
+    let text = preprocessor.detokenize(&encoding.token_ids, true).unwrap();
+    assert_eq!(text.trim(), "The capital of France");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_image_needs_multimodal_model() {
+    let mdc = ModelDeploymentCard::load("tests/data/sample-models/mock-llama-3.1-8b-instruct")
+        .await
+        .unwrap();
+    let preprocessor = OpenAIPreprocessor::new(mdc).await.unwrap();
+
+    let request: NvCreateChatCompletionRequest = serde_json::from_value(serde_json::json!({
+        "model": "mock-llama-3.1-8b-instruct",
+        "messages": [{"role": "user", "content": [
+            {"type": "text", "text": "What is in this image?"},
+            {"type": "image_url", "image_url": {"url": "https://example.com/cat.png"}},
+        ]}],
+    }))
+    .unwrap();
+
+    let err = preprocessor.tokenize_request(&request).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<MediaError>(),
+        Some(MediaError::ModalityNotSupported { .. })
+    ));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_chat_template_override() {
+    // TinyLlama's tokenizer_config.json has no chat template
+    let mut mdc = ModelDeploymentCard::load("tests/data/sample-models/TinyLlama_v1.1")
+        .await
+        .unwrap();
+    assert!(!mdc.has_chat_template());
+    assert!(PromptFormatter::from_mdc(mdc.clone()).await.is_err());
+
+    let mut template = tempfile::NamedTempFile::new().unwrap();
+    std::io::Write::write_all(
+        &mut template,
+        b"{% for m in messages %}<{{ m.role }}>{{ m.content }}\n{% endfor %}\
+          {% if add_generation_prompt %}<assistant>{% endif %}",
+    )
+    .unwrap();
+    mdc.set_chat_template(template.path()).unwrap();
+    assert!(mdc.has_chat_template());
+
+    let preprocessor = OpenAIPreprocessor::new(mdc).await.unwrap();
+    let request: NvCreateChatCompletionRequest = serde_json::from_value(serde_json::json!({
+        "model": "TinyLlama_v1.1",
+        "messages": [{"role": "user", "content": "What is deep learning?"}],
+    }))
+    .unwrap();
+    let (formatted_prompt, _) = preprocessor.tokenize_request(&request).unwrap();
+    assert_eq!(
+        formatted_prompt.unwrap(),
+        "<user>What is deep learning?\n<assistant>"
+    );
+}
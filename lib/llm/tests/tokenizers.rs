@@ -25,7 +25,7 @@
 //! in a hashmap. We will then use these hashes to test that the tokenizer is working correctly. This
 //! will detect if upstream dependency changes result in different/new behavior.
 
-use dynamo_llm::tokenizers::traits::{Decoder, Encoder};
+use dynamo_llm::tokenizers::traits::{Decoder, Encoder, Tokenizer as _};
 use dynamo_llm::tokenizers::*;
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -143,3 +143,30 @@ fn test_sequence() {
     }
     assert_eq!(output, TEST_PROMPTS[0]);
 }
+
+#[test]
+fn test_decode_stream_multi_byte() {
+    let tokenizer = Arc::new(HuggingFaceTokenizer::from_file(TINYLLAMA_TOKENIZER_PATH).unwrap());
+
+    // TinyLlama has no token for most of these, they are one byte-fallback token per byte
+    let prompt = "Grüße aus 東京 🌍🚀 café";
+    let encoding = tokenizer.encode(prompt).unwrap();
+    let mut decoder = DecodeStream::new(tokenizer.clone(), false);
+    let mut output = String::new();
+    for token_id in encoding.token_ids {
+        if let Some(text) = decoder.step(token_id).unwrap() {
+            assert!(!text.contains('\u{FFFD}'), "split character in {text:?}");
+            output.push_str(&text);
+        }
+    }
+    assert_eq!(decoder.flush().unwrap(), None);
+    assert_eq!(output, prompt);
+
+    // A stream that ends part way through a character
+    let mut decoder = DecodeStream::new(tokenizer.clone(), false);
+    let first_bytes = ["<0xF0>", "<0x9F>"].map(|token| tokenizer.token_to_id(token).unwrap());
+    for token_id in first_bytes {
+        assert_eq!(decoder.step(token_id).unwrap(), None);
+    }
+    assert!(decoder.flush().unwrap().unwrap().contains('\u{FFFD}'));
+}
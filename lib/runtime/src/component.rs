@@ -65,6 +65,7 @@ mod client;
 #[allow(clippy::module_inception)]
 mod component;
 mod endpoint;
+pub mod health;
 mod namespace;
 mod registry;
 pub mod service;
@@ -95,12 +96,123 @@ pub struct ComponentEndpointInfo {
     pub namespace: String,
     pub lease_id: i64,
     pub transport: TransportType,
+    /// Zone the worker runs in, from `DYN_ZONE`. Used by client routing hints.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zone: Option<String>,
+    /// Capacity of the worker relative to the others serving the endpoint, from
+    /// `DYN_WORKER_WEIGHT`. A worker of weight 4 gets four times the requests of one of weight 1.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weight: Option<u32>,
+    /// How busy the worker's GPUs are, for workers running a GPU telemetry collector.
+    /// Updated in place while the worker runs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gpu_load: Option<GpuLoad>,
+    /// What the worker's hardware can do. Without a `weight`, routers weight the workers by it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<WorkerCapabilities>,
 }
 
 impl ComponentEndpointInfo {
     pub fn id(&self) -> i64 {
         self.lease_id
     }
+
+    /// Routing weight, 1 unless the worker said otherwise
+    pub fn weight(&self) -> u32 {
+        self.weight.unwrap_or(1).max(1)
+    }
+
+    /// How close the worker is to running out of GPU, from 0 to 1, 0 if it doesn't say
+    pub fn gpu_pressure(&self) -> f32 {
+        self.gpu_load.as_ref().map_or(0.0, GpuLoad::pressure)
+    }
+}
+
+/// Environment variable with the GPU model a worker advertises, e.g. `H100`
+pub const WORKER_GPU_MODEL_ENV: &str = "DYN_WORKER_GPU_MODEL";
+
+/// Environment variable with the GPU memory of a worker in GiB, all its GPUs together
+pub const WORKER_VRAM_GIB_ENV: &str = "DYN_WORKER_VRAM_GIB";
+
+/// Environment variable with the tokens per second a worker generates, as measured for it
+pub const WORKER_TOKENS_PER_SEC_ENV: &str = "DYN_WORKER_TOKENS_PER_SEC";
+
+/// The hardware of a worker and how fast it is, for routers to send more requests to the
+/// bigger workers of a pool that mixes GPUs
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct WorkerCapabilities {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gpu_model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gpu_count: Option<u32>,
+    /// Memory of all the worker's GPUs together
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vram_bytes: Option<u64>,
+    /// Output tokens per second of one request, measured on the worker
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokens_per_sec: Option<f32>,
+}
+
+impl WorkerCapabilities {
+    /// What [`WORKER_GPU_MODEL_ENV`], [`WORKER_VRAM_GIB_ENV`] and
+    /// [`WORKER_TOKENS_PER_SEC_ENV`] say, for workers whose capabilities aren't detected or
+    /// were measured elsewhere
+    pub fn from_env() -> Self {
+        fn positive(name: &str) -> Option<f64> {
+            let val = std::env::var(name).ok()?;
+            match val.trim().parse::<f64>() {
+                Ok(n) if n > 0.0 => Some(n),
+                _ => {
+                    tracing::warn!("Invalid {name} '{val}', expected a positive number");
+                    None
+                }
+            }
+        }
+        WorkerCapabilities {
+            gpu_model: std::env::var(WORKER_GPU_MODEL_ENV)
+                .ok()
+                .filter(|model| !model.is_empty()),
+            gpu_count: None,
+            vram_bytes: positive(WORKER_VRAM_GIB_ENV).map(|gib| (gib * (1u64 << 30) as f64) as u64),
+            tokens_per_sec: positive(WORKER_TOKENS_PER_SEC_ENV).map(|n| n as f32),
+        }
+    }
+
+    /// Each of our values, else the one of `other`
+    pub fn or(self, other: WorkerCapabilities) -> Self {
+        WorkerCapabilities {
+            gpu_model: self.gpu_model.or(other.gpu_model),
+            gpu_count: self.gpu_count.or(other.gpu_count),
+            vram_bytes: self.vram_bytes.or(other.vram_bytes),
+            tokens_per_sec: self.tokens_per_sec.or(other.tokens_per_sec),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == WorkerCapabilities::default()
+    }
+}
+
+/// Utilization of a worker's GPUs, as its GPU telemetry collector last sampled it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct GpuLoad {
+    /// Mean compute utilization of the worker's GPUs, from 0 to 1
+    pub utilization: f32,
+    /// Fraction of the memory of the worker's GPUs in use
+    pub memory_used: f32,
+    /// Fraction of the engine's KV cache in use, for engines that report it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kv_cache_usage: Option<f32>,
+}
+
+impl GpuLoad {
+    /// The KV cache use when known, it is what a busy engine runs out of first, else the
+    /// compute utilization
+    pub fn pressure(&self) -> f32 {
+        self.kv_cache_usage
+            .unwrap_or(self.utilization)
+            .clamp(0.0, 1.0)
+    }
 }
 
 /// A [Component] a discoverable entity in the distributed runtime.
@@ -187,8 +299,8 @@ impl Component {
 
     pub async fn scrape_stats(&self, timeout: Duration) -> Result<ServiceSet> {
         let service_name = self.service_name();
-        let service_client = self.drt().service_client();
-        service_client
+        self.drt()
+            .service_discovery()
             .collect_services(&service_name, timeout)
             .await
     }
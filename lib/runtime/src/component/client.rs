@@ -18,12 +18,12 @@ use crate::pipeline::{
     SingleIn,
 };
 use rand::Rng;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::{
     atomic::{AtomicU64, Ordering},
     Arc,
 };
-use tokio::{net::unix::pipe::Receiver, sync::Mutex};
+use tokio::sync::Mutex;
 
 use crate::{pipeline::async_trait, transports::etcd::WatchEvent};
 
@@ -54,6 +54,8 @@ pub struct Client {
     pub endpoint: Endpoint,
     // These are the remotes I know about
     pub endpoints: EndpointSource,
+    // Remotes never to route to, by instance id
+    excluded: Arc<tokio::sync::watch::Sender<HashSet<i64>>>,
 }
 
 #[derive(Clone, Debug)]
@@ -68,6 +70,7 @@ impl Client {
         Ok(Client {
             endpoint,
             endpoints: EndpointSource::Static,
+            excluded: Arc::new(tokio::sync::watch::Sender::new(HashSet::new())),
         })
     }
 
@@ -84,15 +87,36 @@ impl Client {
         let (prefix, _watcher, mut kv_event_rx) = prefix_watcher.dissolve();
 
         let (watch_tx, watch_rx) = tokio::sync::watch::channel(vec![]);
+        let (excluded_tx, mut excluded_rx) = tokio::sync::watch::channel(HashSet::new());
 
         let secondary = endpoint.component.drt.runtime.secondary().clone();
 
+        // probe the workers in the background, the watcher leaves out those not answering
+        let (probe_tx, mut probe_rx) = tokio::sync::mpsc::channel(1);
+        if let Some(interval) = health::interval_from_env() {
+            let component = endpoint.component.clone();
+            secondary.spawn(async move {
+                let mut ticker = tokio::time::interval(interval);
+                ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+                while !probe_tx.is_closed() {
+                    ticker.tick().await;
+                    match health::probe(&component).await {
+                        Ok(answered) => {
+                            let _ = probe_tx.send(answered).await;
+                        }
+                        Err(err) => tracing::debug!(%err, "Failed probing workers"),
+                    }
+                }
+            });
+        }
+        let mut liveness = health::Liveness::new(endpoint.path());
+
         // this task should be included in the registry
         // currently this is created once per client, but this object/task should only be instantiated
         // once per worker/instance
         secondary.spawn(async move {
             tracing::debug!("Starting endpoint watcher for prefix: {}", prefix);
-            let mut map = HashMap::new();
+            let mut map: HashMap<String, ComponentEndpointInfo> = HashMap::new();
 
             loop {
                 let kv_event = tokio::select! {
@@ -100,9 +124,16 @@ impl Client {
                         tracing::debug!("all watchers have closed; shutting down endpoint watcher for prefix: {}", prefix);
                         break;
                     }
+                    Some(answered) = probe_rx.recv() => {
+                        if !liveness.observe(map.values(), &answered) {
+                            continue;
+                        }
+                        None
+                    }
+                    Ok(()) = excluded_rx.changed() => None,
                     kv_event = kv_event_rx.recv() => {
                         match kv_event {
-                            Some(kv_event) => kv_event,
+                            Some(kv_event) => Some(kv_event),
                             None => {
                                 tracing::debug!("watch stream has closed; shutting down endpoint watcher for prefix: {}", prefix);
                                 break;
@@ -112,28 +143,44 @@ impl Client {
                 };
 
                 match kv_event {
-                    WatchEvent::Put(kv) => {
+                    Some(WatchEvent::Put(kv)) => {
                         let key = String::from_utf8(kv.key().to_vec());
                         let val = serde_json::from_slice::<ComponentEndpointInfo>(kv.value());
                         if let (Ok(key), Ok(val)) = (key, val) {
+                            // Workers rewrite their key to advertise their load, that
+                            // doesn't make a stale one live again
+                            if !map.contains_key(&key) {
+                                liveness.register(val.id());
+                            }
                             map.insert(key.clone(), val);
                         } else {
                             tracing::error!("Unable to parse put endpoint event; shutting down endpoint watcher for prefix: {}", prefix);
                             break;
                         }
                     }
-                    WatchEvent::Delete(kv) => {
+                    Some(WatchEvent::Delete(kv)) => {
                         match String::from_utf8(kv.key().to_vec()) {
-                            Ok(key) => { map.remove(&key); }
+                            Ok(key) => {
+                                if let Some(val) = map.remove(&key) {
+                                    liveness.forget(val.id());
+                                }
+                            }
                             Err(_) => {
                                 tracing::error!("Unable to parse delete endpoint event; shutting down endpoint watcher for prefix: {}", prefix);
                                 break;
                             }
                         }
                     }
+                    // a worker became live or stale, or was excluded
+                    None => {}
                 }
 
-                let endpoints: Vec<ComponentEndpointInfo> = map.values().cloned().collect();
+                let excluded = excluded_rx.borrow_and_update().clone();
+                let endpoints: Vec<ComponentEndpointInfo> = map
+                    .values()
+                    .filter(|ep| liveness.is_live(ep.id()) && !excluded.contains(&ep.id()))
+                    .cloned()
+                    .collect();
 
                 if watch_tx.send(endpoints).is_err() {
                     tracing::debug!("Unable to send watch updates; shutting down endpoint watcher for prefix: {}", prefix);
@@ -149,6 +196,7 @@ impl Client {
         Ok(Client {
             endpoint,
             endpoints: EndpointSource::Dynamic(watch_rx),
+            excluded: Arc::new(excluded_tx),
         })
     }
 
@@ -190,6 +238,26 @@ impl Client {
         Ok(endpoints)
     }
 
+    /// Stop routing to the instance `instance_id`, for good, even while it stays registered.
+    /// For a worker that can't serve our requests correctly.
+    pub fn exclude(&self, instance_id: i64) {
+        self.excluded.send_modify(|excluded| {
+            excluded.insert(instance_id);
+        });
+    }
+
+    /// Route to `instance_id` again after [`Client::exclude`]. Returns false if it wasn't
+    /// excluded.
+    pub fn include(&self, instance_id: i64) -> bool {
+        self.excluded
+            .send_if_modified(|excluded| excluded.remove(&instance_id))
+    }
+
+    /// The instances we don't route to, whether they are still registered or not
+    pub fn excluded(&self) -> HashSet<i64> {
+        self.excluded.borrow().clone()
+    }
+
     /// Is this component know at startup and not discovered via etcd?
     pub fn is_static(&self) -> bool {
         matches!(self.endpoints, EndpointSource::Static)
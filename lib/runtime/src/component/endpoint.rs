@@ -14,8 +14,12 @@
 // limitations under the License.
 
 use derive_getters::Dissolve;
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
 
 use super::*;
+use crate::drain::Drain;
+use crate::transports::etcd;
 
 pub use async_nats::service::endpoint::Stats as EndpointStats;
 
@@ -40,6 +44,16 @@ pub struct EndpointConfig {
     #[educe(Debug(ignore))]
     #[builder(default, private)]
     _stats_handler: Option<EndpointStatsHandler>,
+
+    /// GPU load to advertise with the endpoint, its registration is rewritten as it changes
+    #[educe(Debug(ignore))]
+    #[builder(default, setter(strip_option))]
+    gpu_load: Option<watch::Receiver<Option<GpuLoad>>>,
+
+    /// Hardware detected on the worker, to advertise with the endpoint. The `DYN_WORKER_*`
+    /// environment variables override it, see [`WorkerCapabilities::from_env`].
+    #[builder(default, setter(strip_option))]
+    capabilities: Option<WorkerCapabilities>,
 }
 
 impl EndpointConfigBuilder {
@@ -55,7 +69,8 @@ impl EndpointConfigBuilder {
     }
 
     pub async fn start(self) -> Result<()> {
-        let (endpoint, lease, handler, stats_handler) = self.build_internal()?.dissolve();
+        let (endpoint, lease, handler, stats_handler, gpu_load, capabilities) =
+            self.build_internal()?.dissolve();
         let lease = lease.or(endpoint.drt().primary_lease());
         let lease_id = lease.as_ref().map(|l| l.id()).unwrap_or(0);
 
@@ -106,6 +121,7 @@ impl EndpointConfigBuilder {
         let push_endpoint = PushEndpoint::builder()
             .service_handler(handler)
             .cancellation_token(cancel_token.clone())
+            .drain(endpoint.drt().runtime().drain())
             .build()
             .map_err(|e| anyhow::anyhow!("Failed to build push endpoint: {e}"))?;
 
@@ -121,22 +137,106 @@ impl EndpointConfigBuilder {
             namespace: endpoint.component.namespace.name.clone(),
             lease_id,
             transport: TransportType::NatsTcp(endpoint.subject_to(lease_id)),
+            zone: std::env::var("DYN_ZONE").ok().filter(|z| !z.is_empty()),
+            weight: weight_from_env(),
+            gpu_load: gpu_load.as_ref().and_then(|load| *load.borrow()),
+            capabilities: Some(WorkerCapabilities::from_env().or(capabilities.unwrap_or_default()))
+                .filter(|capabilities| !capabilities.is_empty()),
         };
 
-        let info = serde_json::to_vec_pretty(&info)?;
+        let value = serde_json::to_vec_pretty(&info)?;
 
         if let Some(etcd_client) = &endpoint.component.drt.etcd_client {
             if let Err(e) = etcd_client
-                .kv_create(endpoint.etcd_path_with_id(lease_id), info, Some(lease_id))
+                .kv_create(endpoint.etcd_path_with_id(lease_id), value, Some(lease_id))
                 .await
             {
                 tracing::error!("Failed to register discoverable service: {:?}", e);
                 cancel_token.cancel();
                 return Err(error!("Failed to register discoverable service"));
             }
+            // Workers register once their engine is up
+            crate::readiness::set_ready(true);
+
+            if let Some(gpu_load) = gpu_load {
+                tokio::spawn(advertise_gpu_load(
+                    etcd_client.clone(),
+                    endpoint.etcd_path_with_id(lease_id),
+                    info,
+                    gpu_load,
+                    endpoint.drt().runtime().drain(),
+                    cancel_token.clone(),
+                ));
+            }
+
+            // Deregister as soon as we drain, so routers stop sending us requests
+            let etcd_client = etcd_client.clone();
+            let etcd_path = endpoint.etcd_path_with_id(lease_id);
+            let drain = endpoint.drt().runtime().drain();
+            tokio::spawn(async move {
+                tokio::select! {
+                    _ = drain.draining() => {
+                        crate::readiness::set_ready(false);
+                        if let Err(e) = etcd_client.kv_delete(etcd_path.as_str(), None).await {
+                            tracing::warn!("Failed to deregister {etcd_path}: {:?}", e);
+                        }
+                    }
+                    _ = cancel_token.cancelled() => {}
+                }
+            });
         }
         task.await??;
 
         Ok(())
     }
 }
+
+/// Rewrite the registration `info` of the endpoint at `etcd_path` each time its GPU load
+/// changes, until it is deregistered
+async fn advertise_gpu_load(
+    etcd_client: etcd::Client,
+    etcd_path: String,
+    mut info: ComponentEndpointInfo,
+    mut gpu_load: watch::Receiver<Option<GpuLoad>>,
+    drain: Arc<Drain>,
+    cancel_token: CancellationToken,
+) {
+    loop {
+        tokio::select! {
+            changed = gpu_load.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+            }
+            // Writing the key again would register us again
+            _ = drain.draining() => break,
+            _ = cancel_token.cancelled() => break,
+        }
+        info.gpu_load = *gpu_load.borrow_and_update();
+        let value = match serde_json::to_vec_pretty(&info) {
+            Ok(value) => value,
+            Err(err) => {
+                tracing::warn!(%err, "Failed to serialize the registration of {etcd_path}");
+                continue;
+            }
+        };
+        if let Err(err) = etcd_client
+            .kv_put(&etcd_path, value, Some(info.lease_id))
+            .await
+        {
+            tracing::debug!(%err, "Failed to advertise the GPU load of {etcd_path}");
+        }
+    }
+}
+
+/// The worker's routing weight from `DYN_WORKER_WEIGHT`, see [`ComponentEndpointInfo::weight`]
+fn weight_from_env() -> Option<u32> {
+    let val = std::env::var("DYN_WORKER_WEIGHT").ok()?;
+    match val.trim().parse::<u32>() {
+        Ok(weight) if weight > 0 => Some(weight),
+        _ => {
+            tracing::warn!("Invalid DYN_WORKER_WEIGHT '{val}', expected a positive integer");
+            None
+        }
+    }
+}
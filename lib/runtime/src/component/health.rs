@@ -0,0 +1,202 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Health probes of the workers behind a [`Client`](super::Client).
+//!
+//! A worker that dies is deregistered once its etcd lease expires. One that is still holding
+//! its lease but cannot be reached over NATS would keep getting requests, so the client also
+//! asks the workers for their stats every [`HEALTH_CHECK_INTERVAL_ENV`] seconds. A worker that
+//! misses [`MISSED_PROBES`] probes in a row is left out of routing until it answers again.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use prometheus::{IntGaugeVec, Opts};
+
+use super::{Component, ComponentEndpointInfo, TransportType};
+
+/// Environment variable with the seconds between health probes. 0 turns them off.
+pub const HEALTH_CHECK_INTERVAL_ENV: &str = "DYN_HEALTH_CHECK_INTERVAL_SECS";
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long workers have to answer a probe
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A worker missing this many probes in a row is not routed to
+pub const MISSED_PROBES: u32 = 3;
+
+/// Whether each registered worker is routed to, by endpoint
+static WORKER_LIVE: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    crate::metrics::register(
+        IntGaugeVec::new(
+            Opts::new(
+                "dynamo_worker_live",
+                "1 if the worker answers health probes and gets requests, 0 if it is left out",
+            ),
+            &["endpoint", "worker"],
+        )
+        .unwrap(),
+    )
+});
+
+/// Time between probes from [`HEALTH_CHECK_INTERVAL_ENV`], None if they are off
+pub(crate) fn interval_from_env() -> Option<Duration> {
+    let Ok(val) = std::env::var(HEALTH_CHECK_INTERVAL_ENV) else {
+        return Some(DEFAULT_INTERVAL);
+    };
+    match val.trim().parse::<u64>() {
+        Ok(0) => None,
+        Ok(secs) => Some(Duration::from_secs(secs)),
+        Err(err) => {
+            tracing::warn!(%err, "Invalid {HEALTH_CHECK_INTERVAL_ENV} '{val}', using the default");
+            Some(DEFAULT_INTERVAL)
+        }
+    }
+}
+
+/// Ask the workers of `component` for their stats, and return the subjects of the
+/// endpoints that answered
+pub(crate) async fn probe(component: &Component) -> anyhow::Result<HashSet<String>> {
+    let services = component.scrape_stats(PROBE_TIMEOUT).await?;
+    Ok(services
+        .into_endpoints()
+        .map(|endpoint| endpoint.subject)
+        .collect())
+}
+
+fn subject(info: &ComponentEndpointInfo) -> &str {
+    match &info.transport {
+        TransportType::NatsTcp(subject) => subject,
+    }
+}
+
+/// Probes each worker of an endpoint missed in a row
+pub(crate) struct Liveness {
+    endpoint: String,
+    missed: HashMap<i64, u32>,
+}
+
+impl Liveness {
+    pub(crate) fn new(endpoint: String) -> Self {
+        Liveness {
+            endpoint,
+            missed: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn is_live(&self, worker_id: i64) -> bool {
+        self.missed.get(&worker_id).copied().unwrap_or(0) < MISSED_PROBES
+    }
+
+    /// A worker registered, it is live until it misses probes
+    pub(crate) fn register(&mut self, worker_id: i64) {
+        self.missed.insert(worker_id, 0);
+        self.set_metric(worker_id, true);
+    }
+
+    /// A worker deregistered
+    pub(crate) fn forget(&mut self, worker_id: i64) {
+        self.missed.remove(&worker_id);
+        let _ = WORKER_LIVE.remove_label_values(&[&self.endpoint, &worker_id.to_string()]);
+    }
+
+    /// Count a probe of the `registered` workers, of which those with a subject in `answered`
+    /// answered. Returns whether any worker became live or stale.
+    pub(crate) fn observe<'a>(
+        &mut self,
+        registered: impl IntoIterator<Item = &'a ComponentEndpointInfo>,
+        answered: &HashSet<String>,
+    ) -> bool {
+        let mut changed = false;
+        for info in registered {
+            let id = info.id();
+            let was_live = self.is_live(id);
+            let missed = self.missed.entry(id).or_default();
+            if answered.contains(subject(info)) {
+                *missed = 0;
+            } else {
+                *missed = missed.saturating_add(1);
+            }
+            let live = self.is_live(id);
+            if live != was_live {
+                changed = true;
+                if live {
+                    tracing::info!(worker_id = id, endpoint = %self.endpoint, "Worker is back");
+                } else {
+                    tracing::warn!(
+                        worker_id = id,
+                        endpoint = %self.endpoint,
+                        "Worker missed {MISSED_PROBES} health probes, not routing to it"
+                    );
+                }
+                self.set_metric(id, live);
+            }
+        }
+        changed
+    }
+
+    fn set_metric(&self, worker_id: i64, live: bool) {
+        WORKER_LIVE
+            .with_label_values(&[&self.endpoint, &worker_id.to_string()])
+            .set(live as i64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn worker(id: i64) -> ComponentEndpointInfo {
+        ComponentEndpointInfo {
+            component: "backend".to_string(),
+            endpoint: "generate".to_string(),
+            namespace: "test".to_string(),
+            lease_id: id,
+            transport: TransportType::NatsTcp(format!("test.backend.generate-{id:x}")),
+            zone: None,
+            weight: None,
+            gpu_load: None,
+            capabilities: None,
+        }
+    }
+
+    #[test]
+    fn test_liveness() {
+        let workers = vec![worker(1), worker(2)];
+        let mut liveness = Liveness::new("test/backend/generate".to_string());
+        for w in &workers {
+            liveness.register(w.id());
+        }
+        let only_first: HashSet<String> = [subject(&workers[0]).to_string()].into();
+
+        for _ in 1..MISSED_PROBES {
+            assert!(!liveness.observe(&workers, &only_first));
+        }
+        assert!(liveness.is_live(2));
+        assert!(liveness.observe(&workers, &only_first));
+        assert!(liveness.is_live(1));
+        assert!(!liveness.is_live(2));
+
+        // One answer brings it back
+        let both: HashSet<String> = workers.iter().map(|w| subject(w).to_string()).collect();
+        assert!(liveness.observe(&workers, &both));
+        assert!(liveness.is_live(2));
+
+        liveness.forget(2);
+        assert!(liveness.is_live(2));
+    }
+}
@@ -17,7 +17,7 @@ use super::Result;
 use derive_builder::Builder;
 use figment::{
     providers::{Env, Format, Serialized, Toml},
-    Figment,
+    Figment, Source,
 };
 use serde::{Deserialize, Serialize};
 use validator::Validate;
@@ -33,11 +33,18 @@ impl WorkerConfig {
     /// Panics on invalid configuration.
     pub fn from_settings() -> Self {
         // All calls should be global and thread safe.
+        Self::figment().extract().unwrap() // safety: Called on startup, so panic is reasonable
+    }
+
+    /// The settings that differ from the defaults, and where they come from
+    pub fn non_default_settings() -> Vec<ConfigSetting> {
+        non_default_settings(&Self::figment(), &Self::default(), "DYN_WORKER_")
+    }
+
+    fn figment() -> Figment {
         Figment::new()
             .merge(Serialized::defaults(Self::default()))
             .merge(Env::prefixed("DYN_WORKER_"))
-            .extract()
-            .unwrap() // safety: Called on startup, so panic is reasonable
     }
 }
 
@@ -107,6 +114,11 @@ impl RuntimeConfig {
         Ok(config)
     }
 
+    /// The settings that differ from the defaults, and where they come from
+    pub fn non_default_settings() -> Vec<ConfigSetting> {
+        non_default_settings(&Self::figment(), &Self::default(), "DYN_RUNTIME_")
+    }
+
     pub fn single_threaded() -> Self {
         RuntimeConfig {
             num_worker_threads: 1,
@@ -142,6 +154,105 @@ impl RuntimeConfigBuilder {
     }
 }
 
+/// Where a setting was taken from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfigSource {
+    Cli,
+    Env,
+    File,
+}
+
+impl std::fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            ConfigSource::Cli => "cli",
+            ConfigSource::Env => "env",
+            ConfigSource::File => "file",
+        };
+        f.write_str(s)
+    }
+}
+
+/// A setting that is not at its default value
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ConfigSetting {
+    /// Flag or field name, e.g. `--http-port` or `num_worker_threads`
+    pub key: String,
+    pub value: String,
+    pub source: ConfigSource,
+    /// The environment variable or file it was read from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+}
+
+/// Compare the configuration `figment` extracts to `default`, field by field. A changed field
+/// comes either from a file or from an environment variable starting with `env_prefix`.
+fn non_default_settings<T: Serialize + serde::de::DeserializeOwned>(
+    figment: &Figment,
+    default: &T,
+    env_prefix: &str,
+) -> Vec<ConfigSetting> {
+    let Ok(config) = figment.extract::<T>() else {
+        // from_settings reports the error
+        return vec![];
+    };
+    let (Ok(serde_json::Value::Object(config)), Ok(serde_json::Value::Object(default))) =
+        (serde_json::to_value(config), serde_json::to_value(default))
+    else {
+        return vec![];
+    };
+    config
+        .into_iter()
+        .filter(|(key, value)| default.get(key) != Some(value))
+        .map(|(key, value)| {
+            let (source, origin) = match figment.find_metadata(&key).and_then(|m| m.source.as_ref())
+            {
+                Some(Source::File(path)) => (ConfigSource::File, path.display().to_string()),
+                _ => (
+                    ConfigSource::Env,
+                    format!("{env_prefix}{}", key.to_uppercase()),
+                ),
+            };
+            let value = match value {
+                serde_json::Value::String(s) => s,
+                other => other.to_string(),
+            };
+            ConfigSetting {
+                key,
+                value,
+                source,
+                origin: Some(origin),
+            }
+        })
+        .collect()
+}
+
+/// Print the settings that differ from the defaults, so operators can check at a glance what
+/// a process runs with, and log them as one JSON array in the `config` field.
+pub fn log_settings(process: &str, settings: &[ConfigSetting]) {
+    let json = serde_json::to_string(settings).unwrap_or_default();
+    tracing::info!(config = %json, "{process} non-default configuration");
+
+    if settings.is_empty() {
+        eprintln!("{process}: default configuration");
+        return;
+    }
+    let width = settings.iter().map(|s| s.key.len()).max().unwrap_or(0);
+    let mut banner = format!("{process}: non-default configuration\n");
+    for setting in settings {
+        let from = match &setting.origin {
+            Some(origin) => format!("{} {origin}", setting.source),
+            None => setting.source.to_string(),
+        };
+        banner.push_str(&format!(
+            "  {:<width$}  {}  ({from})\n",
+            setting.key, setting.value
+        ));
+    }
+    eprint!("{banner}");
+}
+
 /// Check if an environment variable is truthy
 pub fn env_is_truthy(env: &str) -> bool {
     match std::env::var(env) {
@@ -217,6 +328,28 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_runtime_config_non_default_settings() {
+        temp_env::with_vars(
+            vec![
+                ("DYN_RUNTIME_NUM_WORKER_THREADS", Some("24")),
+                ("DYN_RUNTIME_MAX_BLOCKING_THREADS", None::<&str>),
+            ],
+            || {
+                let settings = RuntimeConfig::non_default_settings();
+                assert_eq!(
+                    settings,
+                    vec![ConfigSetting {
+                        key: "num_worker_threads".to_string(),
+                        value: "24".to_string(),
+                        source: ConfigSource::Env,
+                        origin: Some("DYN_RUNTIME_NUM_WORKER_THREADS".to_string()),
+                    }]
+                );
+            },
+        )
+    }
+
     #[test]
     fn test_runtime_config_rejects_invalid_thread_count() -> Result<()> {
         temp_env::with_vars(
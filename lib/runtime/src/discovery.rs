@@ -13,10 +13,23 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::{transports::etcd, Result};
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use crate::{service::ServiceSet, transports::etcd, Result};
 
 pub use etcd::Lease;
 
+/// Finds the live instances of a service, with their stats. This is the stats side of
+/// discovery, which instances exist is read from their etcd registrations.
+#[async_trait]
+pub trait ServiceDiscovery: Send + Sync {
+    /// The instances of `service_name` and the stats of their endpoints. Instances that
+    /// don't answer within `timeout` are left out.
+    async fn collect_services(&self, service_name: &str, timeout: Duration) -> Result<ServiceSet>;
+}
+
 pub struct DiscoveryClient {
     namespace: String,
     etcd_client: etcd::Client,
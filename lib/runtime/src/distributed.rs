@@ -16,7 +16,7 @@
 pub use crate::component::Component;
 use crate::{
     component::{self, ComponentBuilder, Namespace},
-    discovery::DiscoveryClient,
+    discovery::{DiscoveryClient, ServiceDiscovery},
     service::ServiceClient,
     transports::{etcd, nats, tcp},
     ErrorContext,
@@ -132,6 +132,10 @@ impl DistributedRuntime {
         ServiceClient::new(self.nats_client.clone())
     }
 
+    pub(crate) fn service_discovery(&self) -> Box<dyn ServiceDiscovery> {
+        Box::new(self.service_client())
+    }
+
     pub async fn tcp_server(&self) -> Result<Arc<tcp::server::TcpStreamServer>> {
         Ok(self
             .tcp_server
@@ -0,0 +1,131 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Draining before shutdown
+//!
+//! On a shutdown signal the [`crate::Worker`] first drains: services stop taking new requests
+//! (the HTTP service answers 503, endpoints deregister from etcd and stop listening) and the
+//! requests already running are given until [`DYN_DRAIN_TIMEOUT`] to finish. Only then is the
+//! runtime cancelled, which stops everything else.
+//!
+//! Each running request holds an [`InFlight`] guard, so we know when they are all done.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+
+/// Environment variable with the longest time in seconds to wait for running requests on
+/// shutdown
+pub const DYN_DRAIN_TIMEOUT: &str = "DYN_DRAIN_TIMEOUT";
+
+/// Default drain timeout in seconds
+pub const DEFAULT_DRAIN_TIMEOUT: u64 = 30;
+
+#[derive(Debug, Default)]
+pub struct Drain {
+    token: CancellationToken,
+    in_flight: AtomicUsize,
+    idle: Notify,
+}
+
+impl Drain {
+    /// Stop taking new requests
+    pub fn start(&self) {
+        self.token.cancel();
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Wait for the drain to start
+    pub async fn draining(&self) {
+        self.token.cancelled().await
+    }
+
+    /// Count a request as running until the guard is dropped
+    pub fn track(self: &Arc<Self>) -> InFlight {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlight(self.clone())
+    }
+
+    /// Number of requests running
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Wait at most `timeout` for the running requests to finish. Returns how many are left.
+    pub async fn wait_idle(&self, timeout: Duration) -> usize {
+        let idle = async {
+            loop {
+                let notified = self.idle.notified();
+                if self.in_flight() == 0 {
+                    return;
+                }
+                notified.await;
+            }
+        };
+        let _ = tokio::time::timeout(timeout, idle).await;
+        self.in_flight()
+    }
+}
+
+/// Drain timeout from [`DYN_DRAIN_TIMEOUT`]
+pub fn timeout_from_env() -> Duration {
+    let secs = std::env::var(DYN_DRAIN_TIMEOUT)
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_DRAIN_TIMEOUT);
+    Duration::from_secs(secs)
+}
+
+/// A running request, see [`Drain::track`]
+#[derive(Debug)]
+pub struct InFlight(Arc<Drain>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if self.0.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_wait_idle() {
+        let drain = Arc::new(Drain::default());
+        assert_eq!(drain.wait_idle(Duration::from_secs(1)).await, 0);
+
+        let first = drain.track();
+        let second = drain.track();
+        assert_eq!(drain.wait_idle(Duration::from_millis(10)).await, 2);
+
+        drain.start();
+        assert!(drain.is_draining());
+        tokio::spawn(async move {
+            drop(first);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            drop(second);
+        });
+        assert_eq!(drain.wait_idle(Duration::from_secs(5)).await, 0);
+    }
+}
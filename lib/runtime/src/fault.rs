@@ -0,0 +1,288 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Fault injection, for testing how the distributed runtime copes with failures
+//!
+//! Built with the `fault-injection` feature, a process reads a fault schedule from
+//! [`FAULTS_ENV`]: a JSON list of rules, inline or in a file. Each rule names a
+//! [`FaultPoint`] and what to do there:
+//!
+//! ```json
+//! [
+//!   {"point": "worker_request", "action": "crash", "after": 5},
+//!   {"point": "nats_request", "action": "delay", "ms": 2000, "target": "generate"},
+//!   {"point": "block_transfer", "action": "drop", "probability": 0.1},
+//!   {"point": "response_chunk", "action": "delay", "ms": 50, "after_secs": 30, "times": 100}
+//! ]
+//! ```
+//!
+//! The first rule crashes the worker on its sixth request, which tests the router's retries
+//! and the lease expiring. Rules are checked in order, the first one that fires wins. Without
+//! the feature the hooks compile to nothing and the variable is ignored.
+
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+/// The fault schedule: JSON, or the path of a JSON file
+pub const FAULTS_ENV: &str = "DYN_FAULTS";
+
+/// Where a fault can be injected
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FaultPoint {
+    /// A worker receiving a request. `drop` fails the request. The target is empty.
+    WorkerRequest,
+    /// A worker sending a response chunk. `drop` ends the response stream. The target is
+    /// empty.
+    ResponseChunk,
+    /// A client publishing a request on NATS. `drop` fails the request. The target is the
+    /// subject.
+    NatsRequest,
+    /// A KV block transfer. `drop` fails the transfer. The target is the transfer strategy,
+    /// e.g. `NixlWrite`.
+    BlockTransfer,
+}
+
+/// What to do at a [`FaultPoint`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum FaultAction {
+    /// Abort the process, without any cleanup, as a crash would
+    Crash,
+    /// Wait this long, then carry on
+    Delay { ms: u64 },
+    /// Fail what is being done, as the point says
+    Drop,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FaultRule {
+    pub point: FaultPoint,
+
+    #[serde(flatten)]
+    pub action: FaultAction,
+
+    /// Only where the point's target contains this
+    #[serde(default)]
+    pub target: Option<String>,
+
+    /// Let this many matching hits through first
+    #[serde(default)]
+    pub after: u64,
+
+    /// Only this many seconds after the schedule was loaded
+    #[serde(default)]
+    pub after_secs: f64,
+
+    /// Fire at most this many times, no limit if None
+    #[serde(default)]
+    pub times: Option<u64>,
+
+    /// Chance of firing on each hit past `after`
+    #[serde(default = "always")]
+    pub probability: f64,
+}
+
+fn always() -> f64 {
+    1.0
+}
+
+/// A fault schedule and how far it got
+pub struct FaultSchedule {
+    rules: Vec<(FaultRule, RuleState)>,
+    loaded: Instant,
+}
+
+#[derive(Default)]
+struct RuleState {
+    hits: AtomicU64,
+    fired: AtomicU64,
+}
+
+/// Returned by [`inject`] when a fault drops what was being done
+#[derive(Debug, thiserror::Error)]
+#[error("Injected fault at {point:?} {target}")]
+pub struct InjectedFault {
+    pub point: FaultPoint,
+    pub target: String,
+}
+
+impl FaultSchedule {
+    pub fn new(rules: Vec<FaultRule>) -> Self {
+        FaultSchedule {
+            rules: rules
+                .into_iter()
+                .map(|rule| (rule, RuleState::default()))
+                .collect(),
+            loaded: Instant::now(),
+        }
+    }
+
+    /// From [`FAULTS_ENV`], None if it is not set
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(val) = std::env::var(FAULTS_ENV) else {
+            return Ok(None);
+        };
+        let json = if val.trim_start().starts_with('[') {
+            val
+        } else {
+            std::fs::read_to_string(Path::new(&val))
+                .map_err(|err| anyhow::anyhow!("{FAULTS_ENV} {val}: {err}"))?
+        };
+        let rules: Vec<FaultRule> = serde_json::from_str(&json)
+            .map_err(|err| anyhow::anyhow!("Invalid {FAULTS_ENV}: {err}"))?;
+        Ok(Some(FaultSchedule::new(rules)))
+    }
+
+    /// The process's schedule. A schedule that doesn't parse is a broken test, so it panics.
+    pub fn global() -> Option<&'static FaultSchedule> {
+        static SCHEDULE: LazyLock<Option<FaultSchedule>> = LazyLock::new(|| {
+            let schedule = FaultSchedule::from_env().unwrap_or_else(|err| panic!("{err}"));
+            if let Some(schedule) = &schedule {
+                tracing::warn!("Injecting faults, {} rules", schedule.rules.len());
+            }
+            schedule
+        });
+        SCHEDULE.as_ref()
+    }
+
+    /// The fault to inject at `point`, if a rule fires
+    pub fn check(&self, point: FaultPoint, target: &str) -> Option<FaultAction> {
+        let elapsed = self.loaded.elapsed().as_secs_f64();
+        for (rule, state) in &self.rules {
+            if rule.point != point
+                || rule.after_secs > elapsed
+                || rule
+                    .target
+                    .as_deref()
+                    .is_some_and(|wanted| !target.contains(wanted))
+            {
+                continue;
+            }
+            if state.hits.fetch_add(1, Ordering::Relaxed) < rule.after {
+                continue;
+            }
+            if rule.probability < 1.0 && rand::random::<f64>() >= rule.probability {
+                continue;
+            }
+            let fired = state.fired.fetch_add(1, Ordering::Relaxed);
+            if rule.times.is_some_and(|times| fired >= times) {
+                continue;
+            }
+            return Some(rule.action);
+        }
+        None
+    }
+}
+
+/// The fault to inject at `point`. Always None without the `fault-injection` feature.
+#[inline]
+pub fn check(point: FaultPoint, target: &str) -> Option<FaultAction> {
+    #[cfg(feature = "fault-injection")]
+    {
+        let action = FaultSchedule::global()?.check(point, target);
+        if let Some(action) = action {
+            tracing::warn!(?point, target, ?action, "Injecting fault");
+        }
+        action
+    }
+    #[cfg(not(feature = "fault-injection"))]
+    {
+        let _ = (point, target);
+        None
+    }
+}
+
+/// Inject the fault due at `point`: crash, wait, or return the error to fail with
+pub async fn inject(point: FaultPoint, target: &str) -> Result<(), InjectedFault> {
+    match check(point, target) {
+        None => Ok(()),
+        Some(FaultAction::Crash) => std::process::abort(),
+        Some(FaultAction::Delay { ms }) => {
+            tokio::time::sleep(Duration::from_millis(ms)).await;
+            Ok(())
+        }
+        Some(FaultAction::Drop) => Err(InjectedFault {
+            point,
+            target: target.to_string(),
+        }),
+    }
+}
+
+/// [`inject`] for synchronous code, a delay blocks the thread
+pub fn inject_blocking(point: FaultPoint, target: &str) -> Result<(), InjectedFault> {
+    match check(point, target) {
+        None => Ok(()),
+        Some(FaultAction::Crash) => std::process::abort(),
+        Some(FaultAction::Delay { ms }) => {
+            std::thread::sleep(Duration::from_millis(ms));
+            Ok(())
+        }
+        Some(FaultAction::Drop) => Err(InjectedFault {
+            point,
+            target: target.to_string(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let rules: Vec<FaultRule> = serde_json::from_str(
+            r#"[
+                {"point": "nats_request", "action": "delay", "ms": 10, "target": "generate", "after": 1, "times": 2},
+                {"point": "nats_request", "action": "drop"},
+                {"point": "worker_request", "action": "crash", "after_secs": 3600}
+            ]"#,
+        )
+        .unwrap();
+        let schedule = FaultSchedule::new(rules);
+        let delay = Some(FaultAction::Delay { ms: 10 });
+
+        // The first hit is let through by the delay, the drop catches it
+        assert_eq!(
+            schedule.check(FaultPoint::NatsRequest, "ns.backend.generate-1"),
+            Some(FaultAction::Drop)
+        );
+        assert_eq!(
+            schedule.check(FaultPoint::NatsRequest, "ns.backend.generate-1"),
+            delay
+        );
+        assert_eq!(
+            schedule.check(FaultPoint::NatsRequest, "ns.backend.generate-1"),
+            delay
+        );
+        // Fired its two times
+        assert_eq!(
+            schedule.check(FaultPoint::NatsRequest, "ns.backend.generate-1"),
+            Some(FaultAction::Drop)
+        );
+        // Not a target of the delay
+        assert_eq!(
+            schedule.check(FaultPoint::NatsRequest, "ns.backend.embed-1"),
+            Some(FaultAction::Drop)
+        );
+        // Not yet
+        assert_eq!(schedule.check(FaultPoint::WorkerRequest, ""), None);
+        assert_eq!(schedule.check(FaultPoint::BlockTransfer, "NixlWrite"), None);
+    }
+}
@@ -26,20 +26,25 @@ pub use anyhow::{
 
 use async_once_cell::OnceCell;
 
-mod config;
+pub mod config;
 pub use config::RuntimeConfig;
 
 pub mod component;
 pub mod discovery;
+pub mod drain;
 pub mod engine;
+pub mod fault;
 pub mod logging;
+pub mod metrics;
 pub mod pipeline;
 pub mod prelude;
 pub mod protocols;
+pub mod readiness;
 pub mod runnable;
 pub mod runtime;
 pub mod service;
 pub mod slug;
+pub mod telemetry;
 pub mod traits;
 pub mod transports;
 pub mod utils;
@@ -64,6 +69,7 @@ pub struct Runtime {
     primary: RuntimeType,
     secondary: RuntimeType,
     cancellation_token: CancellationToken,
+    drain: Arc<drain::Drain>,
 }
 
 /// Distributed [Runtime] which provides access to shared resources across the cluster, this includes
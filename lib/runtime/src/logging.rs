@@ -25,6 +25,9 @@
 //!
 //! To use local timezone for logging timestamps, set the `DYN_LOG_USE_LOCAL_TZ` environment variable to `1`.
 //!
+//! Spans are also exported to an OpenTelemetry collector when `OTEL_EXPORTER_OTLP_ENDPOINT` is
+//! set, see [`crate::telemetry`].
+//!
 //! Filters can be configured using the `DYN_LOG` environment variable or by setting the `filters`
 //! key in the TOML configuration file. Filters are comma-separated key-value pairs where the key
 //! is the crate or module name and the value is the log level. The default log level is `info`.
@@ -127,14 +130,20 @@ pub fn init() {
                 .event_format(CustomJsonFormatter::new())
                 .with_writer(std::io::stderr)
                 .with_filter(filter_layer);
-            tracing_subscriber::registry().with(l).init();
+            tracing_subscriber::registry()
+                .with(crate::telemetry::layer())
+                .with(l)
+                .init();
         } else {
             let l = fmt::layer()
                 .with_ansi(!crate::config::disable_ansi_logging())
                 .event_format(fmt::format().compact().with_timer(TimeFormatter::new()))
                 .with_writer(std::io::stderr)
                 .with_filter(filter_layer);
-            tracing_subscriber::registry().with(l).init();
+            tracing_subscriber::registry()
+                .with(crate::telemetry::layer())
+                .with(l)
+                .init();
         };
     });
 }
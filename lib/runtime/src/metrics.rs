@@ -0,0 +1,160 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Process wide Prometheus metrics
+//!
+//! Any module can put its metrics in the process wide [`registry`], usually by creating them
+//! in a `LazyLock` with [`register`] so that they are registered exactly once. Everything
+//! registered here is exposed by the HTTP service's `/metrics` route, and by [`serve`] for
+//! processes that don't run the HTTP service. [`serve`] also answers the liveness and
+//! readiness probes of those processes.
+
+use std::sync::LazyLock;
+
+use prometheus::{core::Collector, Encoder, Registry, TextEncoder};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::sync::CancellationToken;
+
+pub use prometheus;
+
+static REGISTRY: LazyLock<Registry> = LazyLock::new(Registry::new);
+
+/// The process wide registry
+pub fn registry() -> &'static Registry {
+    &REGISTRY
+}
+
+/// Register a collector with the process wide registry and return it.
+///
+/// A collector that cannot be registered, for example because its name is already taken,
+/// is logged and returned anyway. It keeps working, it just isn't exported.
+pub fn register<C: Collector + Clone + 'static>(collector: C) -> C {
+    if let Err(err) = REGISTRY.register(Box::new(collector.clone())) {
+        tracing::error!(%err, "Failed to register metric");
+    }
+    collector
+}
+
+/// The process wide metrics, and those of the `extra` registries, in the Prometheus text
+/// format
+pub fn encode_text(extra: &[&Registry]) -> anyhow::Result<String> {
+    let mut families = REGISTRY.gather();
+    for registry in extra {
+        families.extend(registry.gather());
+    }
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&families, &mut buffer)?;
+    Ok(String::from_utf8(buffer)?)
+}
+
+/// Serve `GET /metrics`, and `/live`, `/ready` and `/health` for probes, on
+/// `0.0.0.0:<port>` until cancelled. `/ready` and `/health` are the [`crate::readiness`] of
+/// the process.
+pub async fn serve(port: u16, cancel_token: CancellationToken) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(("0.0.0.0", port)).await?;
+    tracing::info!(port, "Serving metrics on http://0.0.0.0:{port}/metrics");
+    serve_listener(listener, cancel_token).await
+}
+
+async fn serve_listener(
+    listener: TcpListener,
+    cancel_token: CancellationToken,
+) -> anyhow::Result<()> {
+    loop {
+        let (mut socket, _) = tokio::select! {
+            _ = cancel_token.cancelled() => return Ok(()),
+            accepted = listener.accept() => accepted?,
+        };
+        tokio::spawn(async move {
+            if let Err(err) = respond(&mut socket).await {
+                tracing::debug!(%err, "Failed to serve metrics request");
+            }
+        });
+    }
+}
+
+/// A minimal HTTP/1.1 responder, scrapers and probes only need a few routes
+async fn respond(socket: &mut TcpStream) -> anyhow::Result<()> {
+    let mut buf = [0u8; 1024];
+    let n = socket.read(&mut buf).await?;
+    let request = String::from_utf8_lossy(&buf[..n]);
+    let path = request
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .unwrap_or_default();
+    let path = path.split_once('?').map_or(path, |(path, _)| path);
+
+    let (status, body) = match path {
+        "/metrics" => ("200 OK", encode_text(&[])?),
+        "/live" => ("200 OK", "live\n".to_string()),
+        "/ready" | "/health" if crate::readiness::is_ready() => ("200 OK", "ready\n".to_string()),
+        "/ready" | "/health" => ("503 Service Unavailable", "not ready\n".to_string()),
+        _ => ("404 Not Found", "Not Found\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    socket.write_all(response.as_bytes()).await?;
+    socket.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_serve() {
+        let counter = register(
+            prometheus::IntCounter::new("dynamo_test_serve_total", "Test counter").unwrap(),
+        );
+        counter.inc_by(3);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let cancel_token = CancellationToken::new();
+        let server = tokio::spawn(serve_listener(listener, cancel_token.clone()));
+
+        let mut socket = TcpStream::connect(addr).await.unwrap();
+        socket
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        socket.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("dynamo_test_serve_total 3"));
+
+        let get = |path: &'static str| async move {
+            let mut socket = TcpStream::connect(addr).await.unwrap();
+            let request = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n");
+            socket.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            socket.read_to_string(&mut response).await.unwrap();
+            response
+        };
+        assert!(get("/live").await.starts_with("HTTP/1.1 200 OK"));
+        assert!(get("/ready").await.starts_with("HTTP/1.1 503"));
+        crate::readiness::set_ready(true);
+        assert!(get("/ready").await.starts_with("HTTP/1.1 200 OK"));
+        crate::readiness::set_ready(false);
+
+        cancel_token.cancel();
+        server.await.unwrap().unwrap();
+    }
+}
@@ -27,8 +27,10 @@ pub mod context;
 pub mod error;
 pub mod network;
 pub use network::egress::addressed_router::{AddressedPushRouter, AddressedRequest};
-pub use network::egress::push_router::{PushRouter, RouterMode};
+pub use network::egress::push_router::{PushRouter, RouterMode, SharedRouterMode};
+pub use network::egress::routing_hints::{RoutingHintPolicy, RoutingHints};
 pub mod registry;
+pub mod timings;
 
 pub use crate::engine::{
     self as engine, async_trait, AsyncEngine, AsyncEngineContext, AsyncEngineContextProvider, Data,
@@ -118,6 +118,17 @@ impl<T: Send + Sync + 'static> Context<T> {
         self.transfer(())
     }
 
+    /// A Context for `current` with the same controller as this one, so stopping or killing
+    /// either stops both. Only the shared objects of the registry are carried over.
+    pub fn fork<U: Send + Sync + 'static>(&self, current: U) -> Context<U> {
+        Context {
+            current,
+            controller: self.controller.clone(),
+            registry: self.registry.clone_shared(),
+            stages: self.stages.clone(),
+        }
+    }
+
     pub fn stages(&self) -> &Vec<String> {
         &self.stages
     }
@@ -20,12 +20,15 @@ pub mod egress;
 pub mod ingress;
 pub mod tcp;
 
-use std::sync::{Arc, OnceLock};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, OnceLock,
+};
 
 use anyhow::Result;
 use async_trait::async_trait;
 use bytes::Bytes;
-use codec::{TwoPartCodec, TwoPartMessage, TwoPartMessageType};
+use codec::{PayloadEncoding, TwoPartCodec, TwoPartMessage, TwoPartMessageType};
 use derive_builder::Builder;
 use futures::StreamExt;
 // io::Cursor, TryStreamExt
@@ -61,6 +64,50 @@ pub enum ControlMessage {
     Sentinel,
 }
 
+/// Header of a response data frame. The frames of a stream are numbered from 0, so the
+/// receiver can tell a dropped, repeated or reordered response from a good stream.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+struct DataHeader {
+    seq: u64,
+}
+
+/// Checks the data frames of a response stream arrive in order, each exactly once
+#[derive(Debug, Default)]
+struct SequenceCheck {
+    next: u64,
+    /// Whether the sender numbers its frames, known from the first one. Workers from before
+    /// numbering send frames without a header, which are not checked.
+    numbered: Option<bool>,
+}
+
+impl SequenceCheck {
+    /// Check the header of the next data frame
+    fn check(&mut self, header: &[u8]) -> Result<(), String> {
+        let seq = if header.is_empty() {
+            None
+        } else {
+            let header: DataHeader = serde_json::from_slice(header)
+                .map_err(|err| format!("Invalid response header: {err}"))?;
+            Some(header.seq)
+        };
+        match (*self.numbered.get_or_insert(seq.is_some()), seq) {
+            (false, None) => Ok(()),
+            (true, Some(seq)) if seq == self.next => {
+                self.next += 1;
+                Ok(())
+            }
+            (true, Some(seq)) => Err(format!(
+                "Response {seq} out of sequence, expected response {}",
+                self.next
+            )),
+            (true, None) => Err(format!("Response {} has no sequence number", self.next)),
+            (false, Some(seq)) => Err(format!(
+                "Response with sequence number {seq} in an unnumbered stream"
+            )),
+        }
+    }
+}
+
 /// This is the first message in a `ResponseStream`. This is not a message that gets process
 /// by the general pipeline, but is a control message that is awaited before the
 /// [`AsyncEngine::generate`] method is allowed to return.
@@ -146,11 +193,18 @@ pub trait ResponseService {
 pub struct StreamSender {
     tx: tokio::sync::mpsc::Sender<TwoPartMessage>,
     prologue: Option<ResponseStreamPrologue>,
+    /// Sequence number of the next data frame
+    next_seq: AtomicU64,
 }
 
 impl StreamSender {
     pub async fn send(&self, data: Bytes) -> Result<()> {
-        Ok(self.tx.send(TwoPartMessage::from_data(data)).await?)
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+        let header = serde_json::to_vec(&DataHeader { seq })?;
+        Ok(self
+            .tx
+            .send(TwoPartMessage::new(header.into(), data))
+            .await?)
     }
 
     pub async fn send_control(&self, control: ControlMessage) -> Result<()> {
@@ -183,8 +237,10 @@ impl StreamSender {
     }
 }
 
+/// Receives the data of a response stream, or an error if the stream broke off: responses
+/// lost, repeated or out of order, or the sender went away before the end.
 pub struct StreamReceiver {
-    rx: tokio::sync::mpsc::Receiver<Bytes>,
+    rx: tokio::sync::mpsc::Receiver<Result<Bytes, String>>,
 }
 
 /// Connection Info is encoded as JSON and then again serialized has part of the Transport
@@ -274,6 +330,10 @@ struct RequestControlMessage {
     request_type: RequestType,
     response_type: ResponseType,
     connection_info: ConnectionInfo,
+    /// How the request and its responses are encoded. Left out for JSON, which is all that
+    /// older workers know.
+    #[serde(default, skip_serializing_if = "PayloadEncoding::is_json")]
+    encoding: PayloadEncoding,
 }
 
 pub struct Ingress<Req: PipelineIO, Resp: PipelineIO> {
@@ -323,3 +383,39 @@ impl<Req: PipelineIO, Resp: PipelineIO> Ingress<Req, Resp> {
 pub trait PushWorkHandler: Send + Sync {
     async fn handle_payload(&self, payload: Bytes) -> Result<(), PipelineError>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(seq: u64) -> Vec<u8> {
+        serde_json::to_vec(&DataHeader { seq }).unwrap()
+    }
+
+    #[test]
+    fn test_sequence_check() {
+        let mut check = SequenceCheck::default();
+        assert!(check.check(&header(0)).is_ok());
+        assert!(check.check(&header(1)).is_ok());
+        // Lost
+        assert_eq!(
+            check.check(&header(3)).unwrap_err(),
+            "Response 3 out of sequence, expected response 2"
+        );
+
+        let mut check = SequenceCheck::default();
+        assert!(check.check(&header(0)).is_ok());
+        // Repeated
+        assert!(check.check(&header(0)).is_err());
+
+        // Unnumbered, from an older worker
+        let mut check = SequenceCheck::default();
+        assert!(check.check(b"").is_ok());
+        assert!(check.check(b"").is_ok());
+        assert!(check.check(&header(2)).is_err());
+
+        let mut check = SequenceCheck::default();
+        assert!(check.check(&header(0)).is_ok());
+        assert!(check.check(b"").is_err());
+    }
+}
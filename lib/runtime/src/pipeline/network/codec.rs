@@ -25,8 +25,10 @@ use tokio_util::{
     codec::{Decoder, Encoder},
 };
 
+mod payload;
 mod two_part;
 
+pub use payload::{PayloadEncoding, PAYLOAD_ENCODING_ENV};
 pub use two_part::{TwoPartCodec, TwoPartMessage, TwoPartMessageType};
 
 // // Custom codec that reads a u64 length header and the message of that length
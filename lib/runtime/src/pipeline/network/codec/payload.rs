@@ -0,0 +1,159 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! How requests and responses are encoded on the wire
+//!
+//! The requester picks the encoding of each request, from [`PAYLOAD_ENCODING_ENV`], and names
+//! it in the request's control message. The worker decodes the request with it and encodes
+//! every response of the stream the same way. JSON is the default and is what workers from
+//! before this was negotiable expect, so a requester only switches to MessagePack once all
+//! its workers understand it.
+//!
+//! MessagePack is a smaller and faster to parse encoding of the same data model, so the
+//! request and response types need nothing more than their serde derives.
+
+use std::sync::LazyLock;
+
+use bytes::Bytes;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::pipeline::PipelineError;
+
+/// `json` or `msgpack`, the encoding of the requests we send and their responses
+pub const PAYLOAD_ENCODING_ENV: &str = "DYN_PAYLOAD_ENCODING";
+
+static FROM_ENV: LazyLock<PayloadEncoding> = LazyLock::new(|| {
+    let Ok(val) = std::env::var(PAYLOAD_ENCODING_ENV) else {
+        return PayloadEncoding::default();
+    };
+    val.parse().unwrap_or_else(|err| {
+        tracing::warn!("Invalid {PAYLOAD_ENCODING_ENV}: {err}. Using json.");
+        PayloadEncoding::default()
+    })
+});
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadEncoding {
+    #[default]
+    Json,
+    Msgpack,
+}
+
+impl PayloadEncoding {
+    /// The encoding of the requests this process sends, from [`PAYLOAD_ENCODING_ENV`], JSON if
+    /// unset. Read once.
+    pub fn from_env() -> Self {
+        *FROM_ENV
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PayloadEncoding::Json => "json",
+            PayloadEncoding::Msgpack => "msgpack",
+        }
+    }
+
+    pub fn is_json(&self) -> bool {
+        *self == PayloadEncoding::Json
+    }
+
+    pub fn encode<T: Serialize>(&self, value: &T) -> Result<Bytes, PipelineError> {
+        let buf = match self {
+            PayloadEncoding::Json => serde_json::to_vec(value)
+                .map_err(|err| PipelineError::SerializationError(err.to_string()))?,
+            // Structs as maps, so that optional and flattened fields work as they do in JSON
+            PayloadEncoding::Msgpack => rmp_serde::to_vec_named(value)
+                .map_err(|err| PipelineError::SerializationError(err.to_string()))?,
+        };
+        // Takes over the Vec's buffer, no copy
+        Ok(Bytes::from(buf))
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T, PipelineError> {
+        match self {
+            PayloadEncoding::Json => serde_json::from_slice(data)
+                .map_err(|err| PipelineError::DeserializationError(err.to_string())),
+            PayloadEncoding::Msgpack => rmp_serde::from_slice(data)
+                .map_err(|err| PipelineError::DeserializationError(err.to_string())),
+        }
+    }
+
+    /// The payload for a log message: JSON as it is, MessagePack by size
+    pub fn describe(&self, data: &[u8]) -> String {
+        match self {
+            PayloadEncoding::Json => String::from_utf8_lossy(data).into_owned(),
+            PayloadEncoding::Msgpack => format!("<{} bytes of msgpack>", data.len()),
+        }
+    }
+}
+
+impl std::fmt::Display for PayloadEncoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for PayloadEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "json" => Ok(PayloadEncoding::Json),
+            "msgpack" | "messagepack" => Ok(PayloadEncoding::Msgpack),
+            _ => Err(format!(
+                "unknown payload encoding '{s}', expected json or msgpack"
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Request {
+        token_ids: Vec<u32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        max_tokens: Option<u32>,
+        #[serde(flatten)]
+        extra: HashMap<String, serde_json::Value>,
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let request = Request {
+            token_ids: (0..4096).collect(),
+            max_tokens: None,
+            extra: HashMap::from([("temperature".to_string(), serde_json::json!(0.5))]),
+        };
+        let json = PayloadEncoding::Json.encode(&request).unwrap();
+        let msgpack = PayloadEncoding::Msgpack.encode(&request).unwrap();
+        assert!(msgpack.len() < json.len());
+        assert_eq!(
+            PayloadEncoding::Json.decode::<Request>(&json).unwrap(),
+            request
+        );
+        assert_eq!(
+            PayloadEncoding::Msgpack
+                .decode::<Request>(&msgpack)
+                .unwrap(),
+            request
+        );
+        assert!(PayloadEncoding::Json.decode::<Request>(&msgpack).is_err());
+    }
+}
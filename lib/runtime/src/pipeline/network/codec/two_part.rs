@@ -15,7 +15,7 @@
 
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 use tokio_util::codec::{Decoder, Encoder};
-use xxhash_rust::xxh3::xxh3_64;
+use xxhash_rust::xxh3::{xxh3_64, Xxh3};
 
 use crate::pipeline::error::TwoPartCodecError;
 
@@ -31,22 +31,47 @@ impl TwoPartCodec {
 
     /// Encodes a `TwoPartMessage` into `Bytes`, enforcing `max_message_size`.
     pub fn encode_message(&self, msg: TwoPartMessage) -> Result<Bytes, TwoPartCodecError> {
-        let mut buf = BytesMut::new();
+        let mut buf = BytesMut::with_capacity(24 + msg.header.len() + msg.data.len());
         let mut codec = self.clone();
         codec.encode(msg, &mut buf)?;
         Ok(buf.freeze())
     }
 
-    /// Decodes a `TwoPartMessage` from `Bytes`, enforcing `max_message_size`.
+    /// Decodes a `TwoPartMessage` from `Bytes`, enforcing `max_message_size`. The header and
+    /// data are slices of `data`, not copies.
     pub fn decode_message(&self, data: Bytes) -> Result<TwoPartMessage, TwoPartCodecError> {
-        let mut buf = BytesMut::from(&data[..]);
-        let mut codec = self.clone();
-        match codec.decode(&mut buf)? {
-            Some(msg) => Ok(msg),
-            None => Err(TwoPartCodecError::InvalidMessage(
+        if data.len() < 24 {
+            return Err(TwoPartCodecError::InvalidMessage(
                 "No message decoded".to_string(),
-            )),
+            ));
         }
+        let mut cursor = &data[..];
+        let header_len = cursor.get_u64() as usize;
+        let body_len = cursor.get_u64() as usize;
+        let checksum = cursor.get_u64();
+
+        let total_len = 24usize
+            .checked_add(header_len)
+            .and_then(|len| len.checked_add(body_len))
+            .ok_or_else(|| TwoPartCodecError::InvalidMessage("Invalid lengths".to_string()))?;
+        if let Some(max_size) = self.max_message_size {
+            if total_len > max_size {
+                return Err(TwoPartCodecError::MessageTooLarge(total_len, max_size));
+            }
+        }
+        if data.len() < total_len {
+            return Err(TwoPartCodecError::InvalidMessage(
+                "No message decoded".to_string(),
+            ));
+        }
+        if xxh3_64(&data[24..total_len]) != checksum {
+            return Err(TwoPartCodecError::ChecksumMismatch);
+        }
+
+        Ok(TwoPartMessage {
+            header: data.slice(24..24 + header_len),
+            data: data.slice(24 + header_len..total_len),
+        })
     }
 }
 
@@ -117,13 +142,14 @@ impl Encoder<TwoPartMessage> for TwoPartCodec {
             }
         }
 
-        // Compute checksum of the data
-        let mut data_to_hash = BytesMut::with_capacity(header_len + body_len);
-        data_to_hash.extend_from_slice(&item.header);
-        data_to_hash.extend_from_slice(&item.data);
-        let checksum = xxh3_64(&data_to_hash);
+        // Compute checksum of the header and data together, without copying them together
+        let mut hasher = Xxh3::new();
+        hasher.update(&item.header);
+        hasher.update(&item.data);
+        let checksum = hasher.digest();
 
         // Write header and body sizes and checksum
+        dst.reserve(total_len);
         dst.put_u64(header_len as u64);
         dst.put_u64(body_len as u64);
         dst.put_u64(checksum);
@@ -248,6 +274,20 @@ mod tests {
         assert_eq!(decoded.data, data);
     }
 
+    /// The decoded header and data are views of the encoded bytes
+    #[test]
+    fn test_decode_without_copying() {
+        let message = TwoPartMessage::from_parts(Bytes::from("header"), Bytes::from(vec![7; 4096]));
+        let codec = TwoPartCodec::new(None);
+        let encoded = codec.encode_message(message).unwrap();
+        let range = encoded.as_ptr_range();
+
+        let decoded = codec.decode_message(encoded.clone()).unwrap();
+        assert!(range.contains(&decoded.header.as_ptr()));
+        assert!(range.contains(&decoded.data.as_ptr()));
+        assert_eq!(decoded.data.len(), 4096);
+    }
+
     /// Test encoding and decoding of a message with only header.
     #[test]
     fn test_message_with_only_header() {
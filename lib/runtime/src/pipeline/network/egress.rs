@@ -15,5 +15,9 @@
 
 pub mod addressed_router;
 pub mod push_router;
+pub mod queue;
+pub mod retry;
+pub mod routing_hints;
+pub mod sticky;
 
 use super::*;
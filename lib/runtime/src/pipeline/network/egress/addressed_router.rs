@@ -15,8 +15,10 @@
 
 use async_nats::client::Client;
 use tracing as log;
+use tracing::Instrument;
 
 use super::*;
+use crate::pipeline::timings::{StageTimings, STAGE_TIMINGS_CONTEXT_KEY, TRANSFER_STAGE};
 use crate::Result;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -39,6 +41,10 @@ struct RequestControlMessage {
     request_type: RequestType,
     response_type: ResponseType,
     connection_info: ConnectionInfo,
+    /// How the request and its responses are encoded. Left out for JSON, which is all that
+    /// older workers know.
+    #[serde(default, skip_serializing_if = "PayloadEncoding::is_json")]
+    encoding: PayloadEncoding,
 }
 
 pub struct AddressedRequest<T> {
@@ -76,6 +82,25 @@ impl AddressedPushRouter {
     }
 }
 
+/// Responses, or why the response stream broke off
+pub(crate) type CheckedStream<U> =
+    std::pin::Pin<Box<dyn futures::Stream<Item = Result<U, String>> + Send>>;
+
+/// The response standing in for the rest of a broken stream: an error annotation, if `U` is
+/// annotated. Otherwise the stream just ends there.
+pub(crate) fn broken_stream_response<U>(err: &str) -> Option<U>
+where
+    U: for<'de> Deserialize<'de>,
+{
+    log::error!(%err, "Response stream broke off");
+    let annotated = crate::protocols::annotated::Annotated::<()>::from_error(format!(
+        "Response stream broke off: {err}"
+    ));
+    serde_json::to_value(annotated)
+        .and_then(serde_json::from_value)
+        .ok()
+}
+
 #[async_trait]
 impl<T, U> AsyncEngine<SingleIn<AddressedRequest<T>>, ManyOut<U>, Error> for AddressedPushRouter
 where
@@ -83,11 +108,38 @@ where
     U: Data + for<'de> Deserialize<'de>,
 {
     async fn generate(&self, request: SingleIn<AddressedRequest<T>>) -> Result<ManyOut<U>, Error> {
+        let (stream, engine_ctx) = self.generate_checked(request).await?;
+        let stream = stream.filter_map(|response| async move {
+            match response {
+                Ok(response) => Some(response),
+                Err(err) => broken_stream_response(&err),
+            }
+        });
+        Ok(ResponseStream::new(Box::pin(stream), engine_ctx))
+    }
+}
+
+impl AddressedPushRouter {
+    /// Send the request, like `generate`, keeping a broken response stream as an error item
+    pub(crate) async fn generate_checked<T, U>(
+        &self,
+        request: SingleIn<AddressedRequest<T>>,
+    ) -> Result<(CheckedStream<U>, Arc<dyn AsyncEngineContext>), Error>
+    where
+        T: Data + Serialize,
+        U: Data + for<'de> Deserialize<'de>,
+    {
         let request_id = request.context().id().to_string();
         let (addressed_request, context) = request.transfer(());
         let (request, address) = addressed_request.into_parts();
         let engine_ctx = context.context();
 
+        // time from here until the response stream is connected, if anyone asked
+        let _transfer = context
+            .get::<StageTimings>(STAGE_TIMINGS_CONTEXT_KEY)
+            .ok()
+            .map(|timings| timings.span(TRANSFER_STAGE));
+
         // registration options for the data plane in a singe in / many out configuration
         let options = StreamOptions::builder()
             .context(engine_ctx.clone())
@@ -115,18 +167,20 @@ where
         // used to issue the request on the
         // todo -- this object should be automatically created by the register call, and achieved by to the two into_parts()
         // calls. all the information here is provided by the [`StreamOptions`] object and/or the dataplane object
+        let encoding = PayloadEncoding::from_env();
         let control_message = RequestControlMessage {
             id: engine_ctx.id().to_string(),
             request_type: RequestType::SingleIn,
             response_type: ResponseType::ManyOut,
             connection_info,
+            encoding,
         };
 
         // next build the two part message where we package the connection info and the request into
         // a single Vec<u8> that can be sent over the wire.
         // --- package this up in the WorkQueuePublisher ---
         let ctrl = serde_json::to_vec(&control_message)?;
-        let data = serde_json::to_vec(&request)?;
+        let data = encoding.encode(&request)?;
 
         log::trace!(
             request_id,
@@ -135,7 +189,7 @@ where
             data.len()
         );
 
-        let msg = TwoPartMessage::from_parts(ctrl.into(), data.into());
+        let msg = TwoPartMessage::from_parts(ctrl.into(), data);
 
         // the request plane / work queue should provide a two part message codec that can be used
         // or it should take a two part message directly
@@ -147,11 +201,19 @@ where
 
         log::trace!(request_id, "enqueueing two-part message to nats");
 
+        // the worker continues the trace from the headers
+        let span = log::info_span!("publish", request_id, subject = %address);
+        let mut headers = crate::telemetry::NatsHeaders(async_nats::HeaderMap::new());
+        crate::telemetry::inject_context(&span, &mut headers);
+
+        crate::fault::inject(crate::fault::FaultPoint::NatsRequest, address.as_str()).await?;
+
         // we might need to add a timeout on this if there is no subscriber to the subject; however, I think nats
         // will handle this for us
         let _response = self
             .req_transport
-            .request(address.to_string(), buffer)
+            .request_with_headers(address.to_string(), headers.0, buffer)
+            .instrument(span)
             .await?;
 
         log::trace!(request_id, "awaiting transport handshake");
@@ -162,17 +224,21 @@ where
 
         let stream = tokio_stream::wrappers::ReceiverStream::new(response_stream.rx);
 
-        let stream = stream.filter_map(|msg| async move {
-            match serde_json::from_slice::<U>(&msg) {
-                Ok(r) => Some(r),
+        let stream = stream.filter_map(move |msg| async move {
+            let msg = match msg {
+                Ok(msg) => msg,
+                Err(err) => return Some(Err(err)),
+            };
+            match encoding.decode::<U>(&msg) {
+                Ok(r) => Some(Ok(r)),
                 Err(err) => {
-                    let json_str = String::from_utf8_lossy(&msg);
-                    log::warn!(%err, %json_str, "Failed deserializing JSON to response");
+                    let payload = encoding.describe(&msg);
+                    log::warn!(%err, %payload, "Failed deserializing response");
                     None
                 }
             }
         });
 
-        Ok(ResponseStream::new(Box::pin(stream), engine_ctx))
+        Ok((Box::pin(stream), engine_ctx))
     }
 }
@@ -14,23 +14,110 @@
 // limitations under the License.
 
 use async_trait::async_trait;
+use futures::StreamExt;
+use prometheus::{IntCounterVec, IntGauge, IntGaugeVec, Opts};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::HashMap,
     marker::PhantomData,
     sync::{
         atomic::{AtomicU64, Ordering},
-        Arc,
+        Arc, LazyLock, Mutex,
     },
+    time::Duration,
 };
 
+use super::addressed_router::broken_stream_response;
+use super::queue::{ordered_sessions_from_env, SessionQueues};
+use super::retry::{self, AttemptKind, RetryPolicy};
+use super::routing_hints::{
+    RoutingHintPolicy, RoutingHints, PINNED_WORKER_CONTEXT_KEY, ROUTING_HINTS_CONTEXT_KEY,
+};
+use super::sticky::{sticky_session_ttl_from_env, StickySessions};
 use crate::{
-    component::{Client, Endpoint, EndpointSource},
-    engine::{AsyncEngine, Data},
+    component::{Client, ComponentEndpointInfo, Endpoint, EndpointSource, WorkerCapabilities},
+    engine::{AsyncEngine, AsyncEngineContextProvider, Data, ResponseStream},
     pipeline::{AddressedPushRouter, AddressedRequest, Error, ManyOut, SingleIn},
     traits::DistributedRuntimeProvider,
 };
 
+/// Requests sent to a worker whose response stream has not finished yet, by endpoint
+static QUEUE_DEPTH: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    crate::metrics::register(
+        IntGaugeVec::new(
+            Opts::new(
+                "dynamo_router_queue_depth",
+                "Requests routed to a worker that have not finished streaming back",
+            ),
+            &["endpoint"],
+        )
+        .unwrap(),
+    )
+});
+
+/// Requests sent again after a failure, or hedged, by endpoint
+static EXTRA_ATTEMPTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    crate::metrics::register(
+        IntCounterVec::new(
+            Opts::new(
+                "dynamo_router_extra_attempts_total",
+                "Requests sent to another worker, after a failure (retry) or a slow start (hedge)",
+            ),
+            &["endpoint", "kind"],
+        )
+        .unwrap(),
+    )
+});
+
+/// Decrements the queue depth when the response stream is dropped
+struct QueueDepthGuard(IntGauge);
+
+impl Drop for QueueDepthGuard {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+/// Requests in flight from this process, by worker subject. Shared by all routers, a worker
+/// serving several models is as busy as all of them together.
+static OUTSTANDING: LazyLock<Mutex<HashMap<String, usize>>> = LazyLock::new(Default::default);
+
+fn outstanding(subject: &str) -> usize {
+    OUTSTANDING
+        .lock()
+        .unwrap()
+        .get(subject)
+        .copied()
+        .unwrap_or(0)
+}
+
+/// Counts a request as outstanding on its worker until the response stream is dropped
+struct OutstandingGuard(String);
+
+impl OutstandingGuard {
+    fn new(subject: &str) -> Self {
+        *OUTSTANDING
+            .lock()
+            .unwrap()
+            .entry(subject.to_string())
+            .or_default() += 1;
+        OutstandingGuard(subject.to_string())
+    }
+}
+
+impl Drop for OutstandingGuard {
+    fn drop(&mut self) {
+        let mut outstanding = OUTSTANDING.lock().unwrap();
+        if let Some(count) = outstanding.get_mut(&self.0) {
+            *count -= 1;
+            if *count == 0 {
+                outstanding.remove(&self.0);
+            }
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct PushRouter<T, U>
 where
@@ -42,11 +129,23 @@ where
     pub client: Client,
 
     /// How we choose which endpoint to send traffic to.
-    router_mode: RouterMode,
+    router_mode: SharedRouterMode,
 
     /// Number of round robin requests handled. Used to decide which server is next.
     round_robin_counter: Arc<AtomicU64>,
 
+    /// What to do with routing hints the client attached to the request
+    hint_policy: RoutingHintPolicy,
+
+    /// When set, the requests of a session wait for the previous one to finish streaming back
+    sessions: Option<Arc<SessionQueues>>,
+
+    /// When set, the requests of a session go to the worker that served the previous one
+    sticky: Option<Arc<StickySessions>>,
+
+    /// Whether to retry failed requests on another worker, and hedge slow ones
+    retry: RetryPolicy,
+
     /// The next step in the chain. PushRouter (this object) picks an endpoint,
     /// addresses it, then passes it to AddressedPushRouter which does the network traffic.
     addressed: Arc<AddressedPushRouter>,
@@ -62,12 +161,36 @@ pub enum RouterMode {
     #[default]
     Random,
     RoundRobin,
+    /// The endpoint with the fewest requests in flight from this process
+    LeastOutstanding,
+    /// The less busy of two random endpoints. Close to least outstanding, without every
+    /// frontend piling onto the same idle worker.
+    PowerOfTwo,
     //KV,
     //
     // Always and only go to the given endpoint ID. Used by Python bindings.
     Direct(i64),
 }
 
+/// A [`RouterMode`] shared by several routers, which can be changed while they serve requests.
+/// Requests already routed are not affected.
+#[derive(Default, Debug, Clone)]
+pub struct SharedRouterMode(Arc<Mutex<RouterMode>>);
+
+impl SharedRouterMode {
+    pub fn new(router_mode: RouterMode) -> Self {
+        SharedRouterMode(Arc::new(Mutex::new(router_mode)))
+    }
+
+    pub fn get(&self) -> RouterMode {
+        *self.0.lock().unwrap()
+    }
+
+    pub fn set(&self, router_mode: RouterMode) {
+        *self.0.lock().unwrap() = router_mode;
+    }
+}
+
 async fn addressed_router(endpoint: &Endpoint) -> anyhow::Result<Arc<AddressedPushRouter>> {
     AddressedPushRouter::new(
         endpoint.drt().nats_client.client().clone(),
@@ -85,57 +208,78 @@ where
         Ok(PushRouter {
             client,
             addressed,
-            router_mode,
+            router_mode: SharedRouterMode::new(router_mode),
             round_robin_counter: Arc::new(AtomicU64::new(0)),
+            hint_policy: RoutingHintPolicy::from_env(),
+            sessions: ordered_sessions_from_env().then(Default::default),
+            sticky: sticky_session_ttl_from_env().map(|ttl| Arc::new(StickySessions::new(ttl))),
+            retry: RetryPolicy::from_env(),
             _phantom: PhantomData,
         })
     }
 
-    /// Issue a request to the next available endpoint in a round-robin fashion
-    pub async fn round_robin(&self, request: SingleIn<T>) -> anyhow::Result<ManyOut<U>> {
-        let counter = self.round_robin_counter.fetch_add(1, Ordering::Relaxed);
+    /// Follow `router_mode` instead of the mode we were created with, so it can be changed
+    /// for all the routers sharing it at once
+    pub fn with_shared_router_mode(mut self, router_mode: SharedRouterMode) -> Self {
+        self.router_mode = router_mode;
+        self
+    }
 
-        let endpoint_id = {
-            let endpoints = self.client.endpoints();
-            let count = endpoints.len();
-            if count == 0 {
-                return Err(anyhow::anyhow!(
-                    "no endpoints found for endpoint {:?}",
-                    self.client.endpoint.etcd_path()
-                ));
-            }
-            let offset = counter % count as u64;
-            endpoints[offset as usize].id()
-        };
+    /// Override the routing hint policy, which defaults to the `DYN_ROUTING_HINTS` env var.
+    pub fn with_hint_policy(mut self, hint_policy: RoutingHintPolicy) -> Self {
+        self.hint_policy = hint_policy;
+        self
+    }
+
+    /// What this router does with client routing hints
+    pub fn hint_policy(&self) -> RoutingHintPolicy {
+        self.hint_policy
+    }
+
+    /// Generate the requests of a session one at a time, in submit order. Defaults to the
+    /// `DYN_ORDERED_SESSIONS` env var.
+    pub fn with_ordered_sessions(mut self, ordered: bool) -> Self {
+        self.sessions = ordered.then(Default::default);
+        self
+    }
+
+    /// Send the requests of a session to the same worker while the session is used at least
+    /// every `ttl`. None turns it off. Defaults to the `DYN_STICKY_SESSION_TTL_SECS` env var.
+    pub fn with_sticky_sessions(mut self, ttl: Option<Duration>) -> Self {
+        self.sticky = ttl.map(|ttl| Arc::new(StickySessions::new(ttl)));
+        self
+    }
+
+    /// The sessions pinned to workers, None if sessions are not sticky
+    pub fn sticky_sessions(&self) -> Option<Arc<StickySessions>> {
+        self.sticky.clone()
+    }
+
+    /// Override the retry and hedging policy, which defaults to the `DYN_ROUTER_RETRIES` and
+    /// `DYN_ROUTER_HEDGE_AFTER_MS` env vars. Only hedge endpoints whose requests are
+    /// idempotent.
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Issue a request to the next available endpoint in a round-robin fashion. Each endpoint
+    /// gets as many turns as its weight.
+    pub async fn round_robin(&self, request: SingleIn<T>) -> anyhow::Result<ManyOut<U>> {
+        let endpoint_id = self.choose_any(RouterMode::RoundRobin)?;
         tracing::trace!("round robin router selected {endpoint_id}");
 
         let subject = self.client.endpoint.subject_to(endpoint_id);
-        let request = request.map(|req| AddressedRequest::new(req, subject));
-
-        self.addressed.generate(request).await
+        self.send(request, subject).await
     }
 
-    /// Issue a request to a random endpoint
+    /// Issue a request to a random endpoint, in proportion to their weights
     pub async fn random(&self, request: SingleIn<T>) -> anyhow::Result<ManyOut<U>> {
-        let endpoint_id = {
-            let endpoints = self.client.endpoints();
-            let count = endpoints.len();
-            if count == 0 {
-                return Err(anyhow::anyhow!(
-                    "no endpoints found for endpoint {:?}",
-                    self.client.endpoint.etcd_path()
-                ));
-            }
-            let counter = rand::rng().random::<u64>();
-            let offset = counter % count as u64;
-            endpoints[offset as usize].id()
-        };
+        let endpoint_id = self.choose_any(RouterMode::Random)?;
         tracing::trace!("random router selected {endpoint_id}");
 
         let subject = self.client.endpoint.subject_to(endpoint_id);
-        let request = request.map(|req| AddressedRequest::new(req, subject));
-
-        self.addressed.generate(request).await
+        self.send(request, subject).await
     }
 
     /// Issue a request to a specific endpoint
@@ -158,20 +302,205 @@ where
         }
 
         let subject = self.client.endpoint.subject_to(endpoint_id);
-        let request = request.map(|req| AddressedRequest::new(req, subject));
+        self.send(request, subject).await
+    }
+
+    /// Issue a request to one of the endpoints matching the client's routing hints, using
+    /// our router mode to pick between them.
+    pub async fn hinted(
+        &self,
+        request: SingleIn<T>,
+        hints: &RoutingHints,
+    ) -> anyhow::Result<ManyOut<U>> {
+        let endpoint_id = {
+            let endpoints = hints.filter(self.client.endpoints(), self.hint_policy)?;
+            let count = endpoints.len();
+            if count == 0 {
+                return Err(anyhow::anyhow!(
+                    "no endpoints found for endpoint {:?}",
+                    self.client.endpoint.etcd_path()
+                ));
+            }
+            let endpoints: Vec<_> = endpoints.iter().collect();
+            self.choose(&endpoints, self.router_mode.get())
+        };
+        tracing::trace!(?hints, "hinted router selected {endpoint_id}");
+
+        let subject = self.client.endpoint.subject_to(endpoint_id);
+        self.send(request, subject).await
+    }
+
+    /// Issue a request to the endpoint with the fewest requests in flight for its weight
+    pub async fn least_outstanding(&self, request: SingleIn<T>) -> anyhow::Result<ManyOut<U>> {
+        let endpoint_id = self.choose_any(RouterMode::LeastOutstanding)?;
+        tracing::trace!("least outstanding router selected {endpoint_id}");
+
+        let subject = self.client.endpoint.subject_to(endpoint_id);
+        self.send(request, subject).await
+    }
+
+    /// Issue a request to the less busy of two random endpoints
+    pub async fn power_of_two(&self, request: SingleIn<T>) -> anyhow::Result<ManyOut<U>> {
+        let endpoint_id = self.choose_any(RouterMode::PowerOfTwo)?;
+        tracing::trace!("power of two router selected {endpoint_id}");
 
-        self.addressed.generate(request).await
+        let subject = self.client.endpoint.subject_to(endpoint_id);
+        self.send(request, subject).await
     }
 
     pub async fn r#static(&self, request: SingleIn<T>) -> anyhow::Result<ManyOut<U>> {
         let subject = self.client.endpoint.subject();
         tracing::debug!("static got subject: {subject}");
-        let request = request.map(|req| AddressedRequest::new(req, subject));
         tracing::debug!("router generate");
-        self.addressed.generate(request).await
+        self.send(request, subject).await
+    }
+
+    /// Choose one of all the endpoints with `mode`
+    fn choose_any(&self, mode: RouterMode) -> anyhow::Result<i64> {
+        let endpoints = self.client.endpoints();
+        if endpoints.is_empty() {
+            anyhow::bail!(
+                "no endpoints found for endpoint {:?}",
+                self.client.endpoint.etcd_path()
+            );
+        }
+        let endpoints: Vec<_> = endpoints.iter().collect();
+        Ok(self.choose(&endpoints, mode))
+    }
+
+    /// Choose one of `endpoints`, which must not be empty, with `mode`
+    fn choose(&self, endpoints: &[&ComponentEndpointInfo], mode: RouterMode) -> i64 {
+        let load = |i: usize| outstanding(&self.client.endpoint.subject_to(endpoints[i].id()));
+        let weights = routing_weights(endpoints);
+        let weight = |i: usize| weights[i];
+        let pressure = |i: usize| endpoints[i].gpu_pressure();
+        let chosen = pick(
+            mode,
+            endpoints.len(),
+            &self.round_robin_counter,
+            load,
+            weight,
+            pressure,
+        );
+        endpoints[chosen].id()
+    }
+
+    /// Address the request to `subject` and send it. It counts as outstanding on that worker
+    /// until its response stream is dropped.
+    async fn send(&self, request: SingleIn<T>, subject: String) -> anyhow::Result<ManyOut<U>> {
+        let guard = OutstandingGuard::new(&subject);
+        let request = request.map(|req| AddressedRequest::new(req, subject));
+        let stream: ManyOut<U> = self.addressed.generate(request).await?;
+        let context = stream.context();
+        let stream = stream.map(move |item| {
+            let _ = &guard;
+            item
+        });
+        Ok(ResponseStream::new(Box::pin(stream), context))
+    }
+}
+
+/// Which of `count` endpoints to use. `counter` is the round robin position, `load(i)` the
+/// requests in flight on endpoint `i`, `weight(i)` its capacity relative to the others and
+/// `pressure(i)` how busy its GPUs are, from 0 to 1.
+fn pick(
+    mode: RouterMode,
+    count: usize,
+    counter: &AtomicU64,
+    load: impl Fn(usize) -> usize,
+    weight: impl Fn(usize) -> u64,
+    pressure: impl Fn(usize) -> f32,
+) -> usize {
+    // Whether endpoint `a` is less loaded than `b` for its weight. When they are even, whether
+    // its GPUs are less busy.
+    let less_busy = |a: usize, b: usize| {
+        let (a_load, b_load) = (load(a) as u64 * weight(b), load(b) as u64 * weight(a));
+        a_load < b_load || (a_load == b_load && pressure(a) < pressure(b))
+    };
+    let total = || (0..count).map(&weight).sum::<u64>();
+    match mode {
+        RouterMode::RoundRobin => {
+            let turn = counter.fetch_add(1, Ordering::Relaxed) % total();
+            by_weight(turn, count, &weight)
+        }
+        RouterMode::LeastOutstanding => {
+            // Ties go round robin, so an idle pool still spreads the requests
+            let start = (counter.fetch_add(1, Ordering::Relaxed) % count as u64) as usize;
+            (0..count)
+                .map(|i| (start + i) % count)
+                .reduce(|best, i| if less_busy(i, best) { i } else { best })
+                .unwrap()
+        }
+        RouterMode::PowerOfTwo => {
+            let mut rng = rand::rng();
+            let first = rng.random_range(0..count);
+            if count == 1 {
+                return first;
+            }
+            let second = (first + rng.random_range(1..count)) % count;
+            if less_busy(second, first) {
+                second
+            } else {
+                first
+            }
+        }
+        RouterMode::Random | RouterMode::Direct(_) => {
+            by_weight(rand::rng().random_range(0..total()), count, &weight)
+        }
     }
 }
 
+/// Weights of `endpoints` relative to each other. If a worker set its weight, every worker
+/// gets the weight it set, 1 if none. Otherwise when they all advertise their tokens per second,
+/// or else all their GPU memory, they are weighted in proportion to it, to a tenth of the
+/// smallest.
+fn routing_weights(endpoints: &[&ComponentEndpointInfo]) -> Vec<u64> {
+    let explicit = || endpoints.iter().map(|ep| ep.weight() as u64).collect();
+    if endpoints.iter().any(|ep| ep.weight.is_some()) {
+        return explicit();
+    }
+    let capacity = |measure: fn(&WorkerCapabilities) -> Option<f64>| -> Option<Vec<f64>> {
+        endpoints
+            .iter()
+            .map(|ep| {
+                ep.capabilities
+                    .as_ref()
+                    .and_then(measure)
+                    .filter(|c| *c > 0.0)
+            })
+            .collect()
+    };
+    let by_speed = capacity(|c| c.tokens_per_sec.map(f64::from));
+    let Some(capacities) = by_speed.or_else(|| capacity(|c| c.vram_bytes.map(|b| b as f64))) else {
+        return explicit();
+    };
+    let smallest = capacities.iter().copied().fold(f64::INFINITY, f64::min);
+    capacities
+        .iter()
+        .map(|c| ((c / smallest * 10.0).round() as u64).max(1))
+        .collect()
+}
+
+/// The endpoint at position `offset` when each endpoint takes up `weight(i)` places in a row
+fn by_weight(mut offset: u64, count: usize, weight: impl Fn(usize) -> u64) -> usize {
+    for i in 0..count {
+        let weight = weight(i);
+        if offset < weight {
+            return i;
+        }
+        offset -= weight;
+    }
+    count - 1
+}
+
+/// The worker a privileged client pinned the request to, see [`PINNED_WORKER_CONTEXT_KEY`]
+fn pinned_worker<T: Data>(request: &SingleIn<T>) -> Option<i64> {
+    request
+        .get::<i64>(PINNED_WORKER_CONTEXT_KEY)
+        .ok()
+        .map(|worker| *worker)
+}
+
 #[async_trait]
 impl<T, U> AsyncEngine<SingleIn<T>, ManyOut<U>, Error> for PushRouter<T, U>
 where
@@ -179,13 +508,410 @@ where
     U: Data + for<'de> Deserialize<'de>,
 {
     async fn generate(&self, request: SingleIn<T>) -> Result<ManyOut<U>, Error> {
+        let session = match &self.sessions {
+            Some(sessions) => {
+                let hints = request.get::<RoutingHints>(ROUTING_HINTS_CONTEXT_KEY).ok();
+                match hints.and_then(|hints| hints.session.clone()) {
+                    Some(session) => {
+                        tracing::trace!(%session, "waiting for the session's previous requests");
+                        Some(sessions.enter(&session).await)
+                    }
+                    None => None,
+                }
+            }
+            None => None,
+        };
+
+        let gauge = QUEUE_DEPTH.with_label_values(&[&self.client.endpoint.path()]);
+        gauge.inc();
+        let guard = QueueDepthGuard(gauge);
+
+        let stream = match pinned_worker(&request) {
+            // The client wants this worker's answer, not any worker's
+            Some(worker) => {
+                tracing::trace!("request pinned to {worker:x}");
+                self.direct(request, worker).await?
+            }
+            None if self.retry.is_enabled() => self.route_with_retries(request).await?,
+            None => self.route(request).await?,
+        };
+        let context = stream.context();
+        let stream = stream.map(move |item| {
+            let _ = (&guard, &session);
+            item
+        });
+        Ok(ResponseStream::new(Box::pin(stream), context))
+    }
+}
+
+impl<T, U> PushRouter<T, U>
+where
+    T: Data + Serialize,
+    U: Data + for<'de> Deserialize<'de>,
+{
+    async fn route(&self, request: SingleIn<T>) -> Result<ManyOut<U>, Error> {
         match &self.client.endpoints {
             EndpointSource::Static => self.r#static(request).await,
-            EndpointSource::Dynamic(_) => match self.router_mode {
-                RouterMode::Random => self.random(request).await,
-                RouterMode::RoundRobin => self.round_robin(request).await,
-                RouterMode::Direct(endpoint_id) => self.direct(request, endpoint_id).await,
-            },
+            EndpointSource::Dynamic(_) => {
+                if let Some(session) = self.sticky_session(&request) {
+                    let hints = self.routing_hints(&request);
+                    let (_, subject) = self.select(hints.as_deref(), Some(&session), &[])?;
+                    tracing::trace!(%session, "sticky router selected {subject}");
+                    return self.send(request, subject).await;
+                }
+                if let Some(hints) = self.routing_hints(&request) {
+                    return self.hinted(request, &hints).await;
+                }
+                match self.router_mode.get() {
+                    RouterMode::Random => self.random(request).await,
+                    RouterMode::RoundRobin => self.round_robin(request).await,
+                    RouterMode::LeastOutstanding => self.least_outstanding(request).await,
+                    RouterMode::PowerOfTwo => self.power_of_two(request).await,
+                    RouterMode::Direct(endpoint_id) => self.direct(request, endpoint_id).await,
+                }
+            }
+        }
+    }
+
+    /// The request's routing hints, if we follow them
+    fn routing_hints(&self, request: &SingleIn<T>) -> Option<Arc<RoutingHints>> {
+        match self.router_mode.get() {
+            RouterMode::Direct(_) => None,
+            _ if self.hint_policy == RoutingHintPolicy::Ignore => None,
+            _ => request
+                .get::<RoutingHints>(ROUTING_HINTS_CONTEXT_KEY)
+                .ok()
+                .filter(|hints| !hints.is_empty()),
+        }
+    }
+
+    /// The request's session, if we keep sessions on their worker
+    fn sticky_session(&self, request: &SingleIn<T>) -> Option<String> {
+        self.sticky.as_ref()?;
+        if let RouterMode::Direct(_) = self.router_mode.get() {
+            return None;
+        }
+        request
+            .get::<RoutingHints>(ROUTING_HINTS_CONTEXT_KEY)
+            .ok()?
+            .session
+            .clone()
+    }
+
+    /// Pick the endpoint for an attempt like `route` does, preferring those not in `tried`.
+    /// A sticky `session` stays on its worker if it is a candidate, else moves to the one we
+    /// pick. Returns its id, None for a static endpoint, and its subject.
+    fn select(
+        &self,
+        hints: Option<&RoutingHints>,
+        session: Option<&str>,
+        tried: &[i64],
+    ) -> anyhow::Result<(Option<i64>, String)> {
+        if let EndpointSource::Static = self.client.endpoints {
+            return Ok((None, self.client.endpoint.subject()));
+        }
+        let endpoint_id = match self.router_mode.get() {
+            RouterMode::Direct(endpoint_id) => {
+                if !self
+                    .client
+                    .endpoints()
+                    .iter()
+                    .any(|ep| ep.id() == endpoint_id)
+                {
+                    anyhow::bail!(
+                        "endpoint_id={} not found for endpoint {:?}",
+                        endpoint_id,
+                        self.client.endpoint.etcd_path()
+                    );
+                }
+                endpoint_id
+            }
+            _ => {
+                let endpoints = match hints {
+                    Some(hints) => hints.filter(self.client.endpoints(), self.hint_policy)?,
+                    None => self.client.endpoints(),
+                };
+                let untried: Vec<_> = endpoints
+                    .iter()
+                    .filter(|ep| !tried.contains(&ep.id()))
+                    .collect();
+                let candidates = if untried.is_empty() {
+                    endpoints.iter().collect()
+                } else {
+                    untried
+                };
+                if candidates.is_empty() {
+                    anyhow::bail!(
+                        "no endpoints found for endpoint {:?}",
+                        self.client.endpoint.etcd_path()
+                    );
+                }
+                match (&self.sticky, session) {
+                    (Some(sticky), Some(session)) => {
+                        let pinned = sticky.get(session);
+                        match pinned.filter(|id| candidates.iter().any(|ep| ep.id() == *id)) {
+                            Some(endpoint_id) => endpoint_id,
+                            None => {
+                                let endpoint_id = self.choose(&candidates, self.router_mode.get());
+                                if let Some(previous) = pinned {
+                                    tracing::debug!(
+                                        session,
+                                        "moving session from worker {previous} to {endpoint_id}"
+                                    );
+                                }
+                                sticky.pin(session, endpoint_id);
+                                endpoint_id
+                            }
+                        }
+                    }
+                    _ => self.choose(&candidates, self.router_mode.get()),
+                }
+            }
+        };
+        Ok((
+            Some(endpoint_id),
+            self.client.endpoint.subject_to(endpoint_id),
+        ))
+    }
+
+    /// Route like `route`, retrying on another worker and hedging as our [`RetryPolicy`]
+    /// says. Only the first response stream to produce a response reaches the caller.
+    async fn route_with_retries(&self, request: SingleIn<T>) -> Result<ManyOut<U>, Error> {
+        let hints = self.routing_hints(&request);
+        let session = self.sticky_session(&request);
+        // Serialized once, every attempt sends the same request
+        let (body, context) = request.into_parts();
+        let body = serde_json::to_value(&body)?;
+        let engine_ctx = context.context();
+        let endpoint = self.client.endpoint.path();
+
+        let mut tried = vec![];
+        let attempt = |kind: AttemptKind| -> anyhow::Result<Option<_>> {
+            let (endpoint_id, subject) =
+                self.select(hints.as_deref(), session.as_deref(), &tried)?;
+            if let Some(endpoint_id) = endpoint_id {
+                // A copy on the same worker would not be any faster
+                if kind == AttemptKind::Hedge && tried.contains(&endpoint_id) {
+                    return Ok(None);
+                }
+                tried.push(endpoint_id);
+            }
+            if kind != AttemptKind::First {
+                EXTRA_ATTEMPTS
+                    .with_label_values(&[endpoint.as_str(), kind.as_str()])
+                    .inc();
+            }
+            tracing::trace!(kind = kind.as_str(), "retrying router selected {subject}");
+
+            let guard = OutstandingGuard::new(&subject);
+            let request = context.fork(AddressedRequest::new(body.clone(), subject.clone()));
+            let addressed = self.addressed.clone();
+            Ok(Some(async move {
+                let (mut stream, _) = addressed.generate_checked::<_, U>(request).await?;
+                match stream.next().await {
+                    Some(Ok(first)) => Ok((first, stream, guard)),
+                    Some(Err(err)) => Err(anyhow::anyhow!("{subject}: {err}")),
+                    None => Err(anyhow::anyhow!(
+                        "{subject} closed the response stream without responding"
+                    )),
+                }
+            }))
+        };
+        let (first, stream, guard) =
+            retry::run(self.retry, || engine_ctx.is_stopped(), attempt).await?;
+
+        // From here on a broken stream fails the request
+        let stream = stream.filter_map(|response| async move {
+            match response {
+                Ok(response) => Some(response),
+                Err(err) => broken_stream_response(&err),
+            }
+        });
+        let stream = stream.map(move |item| {
+            let _ = &guard;
+            item
+        });
+        let stream = futures::stream::once(async move { first }).chain(stream);
+        Ok(ResponseStream::new(Box::pin(stream), engine_ctx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pick() {
+        let counter = AtomicU64::new(0);
+        let idle_gpus = |_: usize| 0.0;
+        let even = |_: usize| 1;
+        let load = |i: usize| [3, 1, 0, 1][i];
+        assert_eq!(
+            pick(
+                RouterMode::LeastOutstanding,
+                4,
+                &counter,
+                load,
+                even,
+                idle_gpus
+            ),
+            2
+        );
+        assert_eq!(
+            pick(
+                RouterMode::LeastOutstanding,
+                4,
+                &counter,
+                load,
+                even,
+                idle_gpus
+            ),
+            2
+        );
+
+        // Ties are spread
+        let idle = |_: usize| 0;
+        let picked: Vec<usize> = (0..4)
+            .map(|_| {
+                pick(
+                    RouterMode::LeastOutstanding,
+                    4,
+                    &counter,
+                    idle,
+                    even,
+                    idle_gpus,
+                )
+            })
+            .collect();
+        assert_eq!(picked, vec![2, 3, 0, 1]);
+
+        // Never the busiest of two
+        let load = |i: usize| [5, 0][i];
+        for _ in 0..20 {
+            assert_eq!(
+                pick(RouterMode::PowerOfTwo, 2, &counter, load, even, idle_gpus),
+                1
+            );
+        }
+        assert_eq!(
+            pick(RouterMode::PowerOfTwo, 1, &counter, load, even, idle_gpus),
+            0
+        );
+    }
+
+    #[test]
+    fn test_pick_weighted() {
+        let counter = AtomicU64::new(0);
+        let idle_gpus = |_: usize| 0.0;
+        let weight = |i: usize| [3, 1][i];
+        let idle = |_: usize| 0;
+        let picked: Vec<usize> = (0..8)
+            .map(|_| pick(RouterMode::RoundRobin, 2, &counter, idle, weight, idle_gpus))
+            .collect();
+        assert_eq!(picked, vec![0, 0, 0, 1, 0, 0, 0, 1]);
+
+        let mut picked = [0; 2];
+        for _ in 0..4000 {
+            picked[pick(RouterMode::Random, 2, &counter, idle, weight, idle_gpus)] += 1;
+        }
+        assert!((2700..3300).contains(&picked[0]), "{picked:?}");
+
+        // Four requests on a worker of weight 3 is less than two on one of weight 1
+        let load = |i: usize| [4, 2][i];
+        assert_eq!(
+            pick(
+                RouterMode::LeastOutstanding,
+                2,
+                &counter,
+                load,
+                weight,
+                idle_gpus
+            ),
+            0
+        );
+        assert_eq!(
+            pick(RouterMode::PowerOfTwo, 2, &counter, load, weight, idle_gpus),
+            0
+        );
+    }
+
+    #[test]
+    fn test_routing_weights() {
+        let worker = |weight: Option<u32>, tokens_per_sec: Option<f32>, vram_gib: u64| {
+            ComponentEndpointInfo {
+                component: "backend".to_string(),
+                endpoint: "generate".to_string(),
+                namespace: "test".to_string(),
+                lease_id: 1,
+                transport: crate::component::TransportType::NatsTcp("test".to_string()),
+                zone: None,
+                weight,
+                gpu_load: None,
+                capabilities: Some(WorkerCapabilities {
+                    vram_bytes: Some(vram_gib << 30),
+                    tokens_per_sec,
+                    ..Default::default()
+                }),
+            }
+        };
+        let weights = |workers: &[ComponentEndpointInfo]| {
+            routing_weights(&workers.iter().collect::<Vec<_>>())
+        };
+
+        // By speed when all measured it, else by memory
+        let h100 = worker(None, Some(120.0), 80);
+        let a10 = worker(None, Some(40.0), 24);
+        assert_eq!(weights(&[h100.clone(), a10.clone()]), vec![30, 10]);
+        let unmeasured = worker(None, None, 48);
+        assert_eq!(
+            weights(&[h100.clone(), a10.clone(), unmeasured]),
+            vec![33, 10, 20]
+        );
+
+        // Weights set by the workers win
+        let weighted = worker(Some(2), None, 24);
+        assert_eq!(weights(&[h100, a10, weighted]), vec![1, 1, 2]);
+    }
+
+    #[test]
+    fn test_pick_gpu_pressure() {
+        let counter = AtomicU64::new(0);
+        let even = |_: usize| 1;
+        let idle = |_: usize| 0;
+        let pressure = |i: usize| [0.9, 0.2, 0.5][i];
+        // Even on requests, the emptiest GPUs win
+        for _ in 0..3 {
+            assert_eq!(
+                pick(
+                    RouterMode::LeastOutstanding,
+                    3,
+                    &counter,
+                    idle,
+                    even,
+                    pressure
+                ),
+                1
+            );
+        }
+        // Requests in flight still come first
+        let load = |i: usize| [0, 1, 1][i];
+        assert_eq!(
+            pick(
+                RouterMode::LeastOutstanding,
+                3,
+                &counter,
+                load,
+                even,
+                pressure
+            ),
+            0
+        );
+        let pressure = |i: usize| [0.9, 0.2][i];
+        for _ in 0..20 {
+            assert_eq!(
+                pick(RouterMode::PowerOfTwo, 2, &counter, idle, even, pressure),
+                1
+            );
         }
     }
 }
@@ -12,3 +12,119 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
+
+//! Ordered processing of the requests of a session.
+//!
+//! With several workers on an endpoint, two requests of the same session can be generated at
+//! the same time on different workers, and finish in any order. Consumers keeping per-session
+//! state need them one after the other. When ordering is on, the [`super::push_router::PushRouter`]
+//! holds back a session's request until the response stream of the previous one is done.
+//!
+//! The session is the `session=<id>` routing hint. Requests without one are not held back.
+//! Order is kept between requests going through the same router, so clients of a session must
+//! all use the same frontend.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+
+/// Environment variable turning on ordered processing of sessions: `1` or `true`.
+pub const ORDERED_SESSIONS_ENV: &str = "DYN_ORDERED_SESSIONS";
+
+pub fn ordered_sessions_from_env() -> bool {
+    std::env::var(ORDERED_SESSIONS_ENV)
+        .map(|val| matches!(val.trim().to_lowercase().as_str(), "1" | "true"))
+        .unwrap_or(false)
+}
+
+/// One lock per session with a request in flight or waiting. Tokio's mutex is fair, waiters
+/// get it in the order they asked, which is the order the requests were submitted.
+#[derive(Default)]
+pub struct SessionQueues {
+    sessions: Mutex<HashMap<String, Arc<AsyncMutex<()>>>>,
+}
+
+impl SessionQueues {
+    /// Wait for the previous requests of `session` to finish. The session is ours until the
+    /// guard is dropped.
+    pub async fn enter(self: &Arc<Self>, session: &str) -> SessionGuard {
+        let lock = self
+            .sessions
+            .lock()
+            .unwrap()
+            .entry(session.to_string())
+            .or_default()
+            .clone();
+        let guard = lock.lock_owned().await;
+        SessionGuard {
+            queues: self.clone(),
+            session: session.to_string(),
+            guard,
+        }
+    }
+
+    /// Number of sessions with a request in flight or waiting
+    pub fn len(&self) -> usize {
+        self.sessions.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Lets the next request of the session go when dropped
+pub struct SessionGuard {
+    queues: Arc<SessionQueues>,
+    session: String,
+    guard: OwnedMutexGuard<()>,
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        let mut sessions = self.queues.sessions.lock().unwrap();
+        // Only the map's and our guard's references: nobody is waiting. Waiters take theirs
+        // with the map locked, so none can show up before we remove it.
+        if Arc::strong_count(OwnedMutexGuard::mutex(&self.guard)) == 2 {
+            sessions.remove(&self.session);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_session_order() {
+        let queues = Arc::new(SessionQueues::default());
+        let order = Arc::new(Mutex::new(vec![]));
+
+        let first = queues.enter("a").await;
+        // Other sessions are not held back
+        let other = queues.enter("b").await;
+        drop(other);
+
+        let mut waiting = vec![];
+        for i in 1..=3 {
+            let queues = queues.clone();
+            let order = order.clone();
+            waiting.push(tokio::spawn(async move {
+                let _guard = queues.enter("a").await;
+                order.lock().unwrap().push(i);
+            }));
+            // Make sure they queue up in this order
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(order.lock().unwrap().is_empty());
+
+        drop(first);
+        for handle in waiting {
+            handle.await.unwrap();
+        }
+        assert_eq!(*order.lock().unwrap(), vec![1, 2, 3]);
+        assert!(queues.is_empty());
+    }
+}
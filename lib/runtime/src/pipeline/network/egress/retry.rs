@@ -0,0 +1,254 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Retries and hedged requests.
+//!
+//! A request fails over to another worker when the one it was sent to could not take it: the
+//! worker is gone, the NATS request timed out, or the response stream closed before the first
+//! response. Once a response came back the request stays with that worker, so the client never
+//! sees two streams or a response twice.
+//!
+//! Hedging sends a copy of the request to a second worker when the first has not responded
+//! within a threshold. Whichever responds first is kept, the other is cancelled. The losing
+//! worker may have done part of the work, so only hedge idempotent requests such as prefill.
+
+use std::future::Future;
+use std::time::Duration;
+
+use futures::stream::{FuturesUnordered, StreamExt};
+
+/// Environment variable with how many other workers to try after a failure. Defaults to 0.
+pub const RETRIES_ENV: &str = "DYN_ROUTER_RETRIES";
+
+/// Environment variable with the milliseconds after which to hedge. Unset, no hedging.
+pub const HEDGE_AFTER_ENV: &str = "DYN_ROUTER_HEDGE_AFTER_MS";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// How many more attempts to make after failed ones
+    pub max_retries: u32,
+
+    /// Send a copy of the request to another worker if the first has not responded in this
+    /// long. Only for idempotent requests.
+    pub hedge_after: Option<Duration>,
+}
+
+impl RetryPolicy {
+    /// Read the policy from [`RETRIES_ENV`] and [`HEDGE_AFTER_ENV`], ignoring invalid values.
+    pub fn from_env() -> Self {
+        RetryPolicy {
+            max_retries: env_number(RETRIES_ENV).unwrap_or(0) as u32,
+            hedge_after: env_number(HEDGE_AFTER_ENV).map(Duration::from_millis),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.max_retries > 0 || self.hedge_after.is_some()
+    }
+}
+
+fn env_number(name: &str) -> Option<u64> {
+    let val = std::env::var(name).ok()?;
+    match val.trim().parse() {
+        Ok(n) => Some(n),
+        Err(err) => {
+            tracing::warn!(%err, "Invalid {name} '{val}', ignoring it");
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AttemptKind {
+    First,
+    Retry,
+    Hedge,
+}
+
+impl AttemptKind {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            AttemptKind::First => "first",
+            AttemptKind::Retry => "retry",
+            AttemptKind::Hedge => "hedge",
+        }
+    }
+}
+
+/// Start attempts following `policy` until one succeeds, and return its result. Attempts
+/// still running then are dropped. `attempt` returns None when there is nowhere to send
+/// that attempt, and `is_stopped` says the client gave up, after which nothing is retried.
+pub(crate) async fn run<R, F, Fut>(
+    policy: RetryPolicy,
+    is_stopped: impl Fn() -> bool,
+    mut attempt: F,
+) -> anyhow::Result<R>
+where
+    F: FnMut(AttemptKind) -> anyhow::Result<Option<Fut>>,
+    Fut: Future<Output = anyhow::Result<R>>,
+{
+    let mut running = FuturesUnordered::new();
+    match attempt(AttemptKind::First)? {
+        Some(first) => running.push(first),
+        None => anyhow::bail!("No worker to send the request to"),
+    }
+
+    let hedge = async {
+        match policy.hedge_after {
+            Some(after) => tokio::time::sleep(after).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(hedge);
+    let mut hedged = false;
+    let mut retries = 0;
+
+    loop {
+        tokio::select! {
+            Some(result) = running.next() => {
+                let err = match result {
+                    Ok(response) => return Ok(response),
+                    Err(err) => err,
+                };
+                if is_stopped() || retries >= policy.max_retries {
+                    if running.is_empty() {
+                        return Err(err);
+                    }
+                    // A hedge is still running
+                    tracing::debug!(%err, "Request failed, waiting on the other attempt");
+                    continue;
+                }
+                retries += 1;
+                tracing::debug!(%err, retries, "Request failed, retrying");
+                match attempt(AttemptKind::Retry) {
+                    Ok(Some(retry)) => running.push(retry),
+                    Ok(None) | Err(_) if !running.is_empty() => {}
+                    Ok(None) => return Err(err),
+                    Err(retry_err) => return Err(retry_err),
+                }
+            }
+            _ = &mut hedge, if !hedged => {
+                hedged = true;
+                if is_stopped() {
+                    continue;
+                }
+                match attempt(AttemptKind::Hedge) {
+                    Ok(Some(copy)) => running.push(copy),
+                    Ok(None) => tracing::trace!("No other worker to hedge the request on"),
+                    Err(err) => tracing::debug!(%err, "Failed hedging the request"),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex};
+
+    /// Resolves to its index in the order attempts were started
+    type TestAttempt = Pin<Box<dyn Future<Output = anyhow::Result<usize>>>>;
+
+    /// Attempts that fail or succeed after a delay, in the order they are started
+    fn attempts(
+        plan: Vec<(u64, bool)>,
+        started: Arc<Mutex<Vec<AttemptKind>>>,
+    ) -> impl FnMut(AttemptKind) -> anyhow::Result<Option<TestAttempt>> {
+        move |kind| {
+            let mut started = started.lock().unwrap();
+            let n = started.len();
+            started.push(kind);
+            let Some(&(delay, ok)) = plan.get(n) else {
+                return Ok(None);
+            };
+            Ok(Some(Box::pin(async move {
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                if ok {
+                    Ok(n)
+                } else {
+                    anyhow::bail!("attempt {n} failed")
+                }
+            })))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retries() {
+        let policy = RetryPolicy {
+            max_retries: 2,
+            hedge_after: None,
+        };
+
+        let started = Arc::new(Mutex::new(vec![]));
+        let plan = vec![(10, false), (10, false), (10, true)];
+        let winner = run(policy, || false, attempts(plan, started.clone())).await;
+        assert_eq!(winner.unwrap(), 2);
+        assert_eq!(
+            *started.lock().unwrap(),
+            vec![AttemptKind::First, AttemptKind::Retry, AttemptKind::Retry]
+        );
+
+        // Out of retries
+        let started = Arc::new(Mutex::new(vec![]));
+        let plan = vec![(10, false), (10, false), (10, false), (10, true)];
+        let err = run(policy, || false, attempts(plan, started.clone())).await;
+        assert_eq!(err.unwrap_err().to_string(), "attempt 2 failed");
+
+        // The client went away, no retry
+        let started = Arc::new(Mutex::new(vec![]));
+        let plan = vec![(10, false), (10, true)];
+        assert!(run(policy, || true, attempts(plan, started.clone()))
+            .await
+            .is_err());
+        assert_eq!(started.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_hedging() {
+        let policy = RetryPolicy {
+            max_retries: 0,
+            hedge_after: Some(Duration::from_millis(50)),
+        };
+
+        // The hedge answers first
+        let started = Arc::new(Mutex::new(vec![]));
+        let plan = vec![(1000, true), (10, true)];
+        let winner = run(policy, || false, attempts(plan, started.clone())).await;
+        assert_eq!(winner.unwrap(), 1);
+        assert_eq!(
+            *started.lock().unwrap(),
+            vec![AttemptKind::First, AttemptKind::Hedge]
+        );
+
+        // Fast enough, no hedge
+        let started = Arc::new(Mutex::new(vec![]));
+        let plan = vec![(10, true), (10, true)];
+        assert_eq!(
+            run(policy, || false, attempts(plan, started.clone()))
+                .await
+                .unwrap(),
+            0
+        );
+        assert_eq!(started.lock().unwrap().len(), 1);
+
+        // The first fails after hedging, the hedge still wins
+        let started = Arc::new(Mutex::new(vec![]));
+        let plan = vec![(100, false), (200, true)];
+        let winner = run(policy, || false, attempts(plan, started.clone())).await;
+        assert_eq!(winner.unwrap(), 1);
+    }
+}
@@ -0,0 +1,401 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Client supplied routing hints.
+//!
+//! A client can ask for a particular worker, zone or routing preference with a header like:
+//!
+//! ```text
+//! x-dynamo-routing: worker=7587889712474989333, zone=us-east!, prefer=cache
+//! ```
+//!
+//! `session=<id>` is not a constraint on the worker but names the session the request belongs
+//! to, see [`super::queue`].
+//!
+//! A hint ending in `!` is a hard constraint: if it cannot be met the request fails instead of
+//! being routed elsewhere. Everything else is a soft preference. Whether hints are honored at
+//! all is decided by the [`RoutingHintPolicy`] of the [`super::push_router::PushRouter`].
+//!
+//! The HTTP service parses the header and stores the [`RoutingHints`] in the request
+//! [`crate::pipeline::Context`] under [`ROUTING_HINTS_CONTEXT_KEY`], where the router finds it.
+//!
+//! Privileged clients can also pin a request to a worker with [`PINNED_WORKER_HEADER`], to
+//! debug it or compare two workers. Unlike a `worker=` hint this is not up to the policy: the
+//! router sends the request to that worker or fails it, and doesn't retry it elsewhere.
+
+use std::str::FromStr;
+
+use crate::component::ComponentEndpointInfo;
+
+/// HTTP header clients use to send routing hints
+pub const ROUTING_HINTS_HEADER: &str = "x-dynamo-routing";
+
+/// Key of the [`RoutingHints`] in the request context registry
+pub const ROUTING_HINTS_CONTEXT_KEY: &str = "routing_hints";
+
+/// HTTP header with the worker (endpoint lease id) a request is pinned to, decimal or hex
+/// with a `0x` prefix
+pub const PINNED_WORKER_HEADER: &str = "x-dynamo-worker";
+
+/// Key of the worker id a request is pinned to in the request context registry
+pub const PINNED_WORKER_CONTEXT_KEY: &str = "pinned_worker";
+
+/// Environment variable selecting the [`RoutingHintPolicy`]: `ignore`, `soft` or `enforce`.
+pub const ROUTING_HINTS_POLICY_ENV: &str = "DYN_ROUTING_HINTS";
+
+/// What the router does with client routing hints
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RoutingHintPolicy {
+    /// Route as if there were no hints
+    Ignore,
+
+    /// Honor hints when they can be met, otherwise route normally. Hard constraints are
+    /// treated as soft.
+    #[default]
+    Soft,
+
+    /// Like `Soft`, but a hard constraint that cannot be met fails the request.
+    Enforce,
+}
+
+impl RoutingHintPolicy {
+    /// Read the policy from [`ROUTING_HINTS_POLICY_ENV`], defaulting to `Soft`.
+    pub fn from_env() -> Self {
+        match std::env::var(ROUTING_HINTS_POLICY_ENV) {
+            Ok(val) => val.parse().unwrap_or_else(|err| {
+                tracing::warn!(%err, "Invalid {ROUTING_HINTS_POLICY_ENV}, using 'soft'");
+                RoutingHintPolicy::Soft
+            }),
+            Err(_) => RoutingHintPolicy::default(),
+        }
+    }
+}
+
+impl FromStr for RoutingHintPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "ignore" => Ok(RoutingHintPolicy::Ignore),
+            "soft" => Ok(RoutingHintPolicy::Soft),
+            "enforce" => Ok(RoutingHintPolicy::Enforce),
+            other => anyhow::bail!(
+                "Unknown routing hint policy '{other}'. Expected ignore, soft or enforce."
+            ),
+        }
+    }
+}
+
+/// What a `prefer=` hint asks the router to favor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preference {
+    /// The worker most likely to have the prompt prefix in its KV cache. Only the KV router
+    /// tracks that, other routers ignore it.
+    Cache,
+}
+
+impl FromStr for Preference {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "cache" => Ok(Preference::Cache),
+            other => anyhow::bail!("Unknown routing preference '{other}'. Expected cache."),
+        }
+    }
+}
+
+impl std::fmt::Display for Preference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Preference::Cache => write!(f, "cache"),
+        }
+    }
+}
+
+/// A single hint value and whether the client asked for it to be a hard constraint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hint<T> {
+    pub value: T,
+    pub hard: bool,
+}
+
+/// Routing hints for a single request
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RoutingHints {
+    /// Routing preference, `cache` to prefer the worker most likely to have the prompt prefix
+    /// cached. Only the KV router can honor it.
+    pub prefer: Option<Hint<Preference>>,
+
+    /// Send the request to this worker (endpoint lease id). Decimal, or hex with a `0x` prefix.
+    pub worker: Option<Hint<i64>>,
+
+    /// Send the request to a worker that registered with this zone (`DYN_ZONE`).
+    pub zone: Option<Hint<String>>,
+
+    /// Session the request belongs to. Routers with ordered sessions on generate the requests
+    /// of a session one at a time, in the order they came in.
+    pub session: Option<String>,
+}
+
+impl RoutingHints {
+    pub fn is_empty(&self) -> bool {
+        self.prefer.is_none()
+            && self.worker.is_none()
+            && self.zone.is_none()
+            && self.session.is_none()
+    }
+
+    /// Narrow `endpoints` down to those matching the hints.
+    ///
+    /// Hints that match no endpoint are dropped, unless they are hard and the policy is
+    /// [`RoutingHintPolicy::Enforce`], in which case this returns an error. A router that
+    /// doesn't track worker state can't meet a preference, so a hard one fails when enforced.
+    pub fn filter(
+        &self,
+        endpoints: Vec<ComponentEndpointInfo>,
+        policy: RoutingHintPolicy,
+    ) -> anyhow::Result<Vec<ComponentEndpointInfo>> {
+        if policy == RoutingHintPolicy::Ignore {
+            return Ok(endpoints);
+        }
+        if let Some(prefer) = &self.prefer {
+            if prefer.hard && policy == RoutingHintPolicy::Enforce {
+                anyhow::bail!(
+                    "Required routing hint prefer={} needs the KV router",
+                    prefer.value
+                );
+            }
+            tracing::trace!(prefer = %prefer.value, "routing preference not used by router");
+        }
+        self.filter_placement(endpoints, policy)
+    }
+
+    /// Like [`RoutingHints::filter`] but only for the `worker` and `zone` hints, for routers
+    /// that handle `prefer` themselves.
+    pub fn filter_placement(
+        &self,
+        endpoints: Vec<ComponentEndpointInfo>,
+        policy: RoutingHintPolicy,
+    ) -> anyhow::Result<Vec<ComponentEndpointInfo>> {
+        if policy == RoutingHintPolicy::Ignore {
+            return Ok(endpoints);
+        }
+        let mut endpoints = endpoints;
+
+        if let Some(worker) = &self.worker {
+            endpoints = apply(endpoints, worker, policy, "worker", |ep| {
+                ep.id() == worker.value
+            })?;
+        }
+        if let Some(zone) = &self.zone {
+            endpoints = apply(endpoints, zone, policy, "zone", |ep| {
+                ep.zone.as_deref() == Some(zone.value.as_str())
+            })?;
+        }
+        Ok(endpoints)
+    }
+
+    /// Whether the client named the worker or zone to run the request on
+    pub fn has_placement(&self) -> bool {
+        self.worker.is_some() || self.zone.is_some()
+    }
+}
+
+fn apply<T: std::fmt::Display>(
+    endpoints: Vec<ComponentEndpointInfo>,
+    hint: &Hint<T>,
+    policy: RoutingHintPolicy,
+    name: &str,
+    matches: impl Fn(&ComponentEndpointInfo) -> bool,
+) -> anyhow::Result<Vec<ComponentEndpointInfo>> {
+    let matching: Vec<_> = endpoints.iter().filter(|ep| matches(ep)).cloned().collect();
+    if !matching.is_empty() {
+        return Ok(matching);
+    }
+    if hint.hard && policy == RoutingHintPolicy::Enforce {
+        anyhow::bail!(
+            "No endpoint satisfies required routing hint {name}={}",
+            hint.value
+        );
+    }
+    tracing::debug!(
+        "No endpoint matches routing hint {name}={}, ignoring",
+        hint.value
+    );
+    Ok(endpoints)
+}
+
+impl FromStr for RoutingHints {
+    type Err = anyhow::Error;
+
+    /// Parse `key=value` pairs separated by `,` or `;`. A trailing `!` on the value makes it hard.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut hints = RoutingHints::default();
+        for part in s.split([',', ';']).map(str::trim).filter(|p| !p.is_empty()) {
+            let Some((key, value)) = part.split_once('=') else {
+                anyhow::bail!("Invalid routing hint '{part}', expected key=value");
+            };
+            let value = value.trim();
+            let (value, hard) = match value.strip_suffix('!') {
+                Some(v) => (v.trim(), true),
+                None => (value, false),
+            };
+            if value.is_empty() {
+                anyhow::bail!("Invalid routing hint '{part}', missing value");
+            }
+            match key.trim().to_lowercase().as_str() {
+                "prefer" => {
+                    hints.prefer = Some(Hint {
+                        value: value.parse()?,
+                        hard,
+                    })
+                }
+                "worker" => {
+                    hints.worker = Some(Hint {
+                        value: parse_worker_id(value)?,
+                        hard,
+                    });
+                }
+                "zone" => {
+                    hints.zone = Some(Hint {
+                        value: value.to_string(),
+                        hard,
+                    })
+                }
+                "session" => {
+                    if hard {
+                        anyhow::bail!("Invalid routing hint '{part}', a session cannot be hard");
+                    }
+                    hints.session = Some(value.to_string());
+                }
+                other => anyhow::bail!("Unknown routing hint '{other}'"),
+            }
+        }
+        Ok(hints)
+    }
+}
+
+/// A worker id as clients write it, decimal or hex with a `0x` prefix
+pub fn parse_worker_id(value: &str) -> anyhow::Result<i64> {
+    let value = value.trim();
+    match value.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => value.parse(),
+    }
+    .map_err(|err| anyhow::anyhow!("Invalid worker id '{value}': {err}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::component::TransportType;
+
+    fn endpoint(lease_id: i64, zone: Option<&str>) -> ComponentEndpointInfo {
+        ComponentEndpointInfo {
+            component: "c".to_string(),
+            endpoint: "e".to_string(),
+            namespace: "ns".to_string(),
+            lease_id,
+            transport: TransportType::NatsTcp(format!("ns.c.e-{lease_id:x}")),
+            zone: zone.map(|z| z.to_string()),
+            weight: None,
+            gpu_load: None,
+            capabilities: None,
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let hints: RoutingHints = "worker=0x10!, zone=us-east; prefer=cache".parse().unwrap();
+        assert_eq!(
+            hints.worker,
+            Some(Hint {
+                value: 16,
+                hard: true
+            })
+        );
+        assert_eq!(hints.zone.unwrap().value, "us-east");
+        assert_eq!(hints.prefer.unwrap().value, Preference::Cache);
+        assert!("prefer=fastest".parse::<RoutingHints>().is_err());
+
+        assert!("".parse::<RoutingHints>().unwrap().is_empty());
+        assert!("worker".parse::<RoutingHints>().is_err());
+        assert!("worker=abc".parse::<RoutingHints>().is_err());
+        assert!("color=blue".parse::<RoutingHints>().is_err());
+
+        let hints: RoutingHints = "session=chat-42".parse().unwrap();
+        assert_eq!(hints.session.as_deref(), Some("chat-42"));
+        assert!(!hints.is_empty());
+        assert!("session=chat-42!".parse::<RoutingHints>().is_err());
+    }
+
+    #[test]
+    fn test_filter() {
+        let endpoints = vec![
+            endpoint(1, Some("us-east")),
+            endpoint(2, Some("us-west")),
+            endpoint(3, None),
+        ];
+
+        let hints: RoutingHints = "zone=us-west".parse().unwrap();
+        let out = hints
+            .filter(endpoints.clone(), RoutingHintPolicy::Soft)
+            .unwrap();
+        assert_eq!(out.iter().map(|ep| ep.id()).collect::<Vec<_>>(), vec![2]);
+
+        let out = hints
+            .filter(endpoints.clone(), RoutingHintPolicy::Ignore)
+            .unwrap();
+        assert_eq!(out.len(), 3);
+
+        // Soft hint that can't be met falls back to all endpoints
+        let hints: RoutingHints = "worker=42".parse().unwrap();
+        let out = hints
+            .filter(endpoints.clone(), RoutingHintPolicy::Enforce)
+            .unwrap();
+        assert_eq!(out.len(), 3);
+
+        // Hard hint only fails when enforced
+        let hints: RoutingHints = "worker=42!".parse().unwrap();
+        assert!(hints
+            .filter(endpoints.clone(), RoutingHintPolicy::Enforce)
+            .is_err());
+        assert_eq!(
+            hints
+                .filter(endpoints.clone(), RoutingHintPolicy::Soft)
+                .unwrap()
+                .len(),
+            3
+        );
+
+        // Only the KV router can prefer cached workers, a required preference fails elsewhere
+        let hints: RoutingHints = "prefer=cache!, zone=us-east".parse().unwrap();
+        assert!(hints
+            .filter(endpoints.clone(), RoutingHintPolicy::Enforce)
+            .is_err());
+        assert_eq!(
+            hints
+                .filter(endpoints.clone(), RoutingHintPolicy::Soft)
+                .unwrap()
+                .len(),
+            1
+        );
+        let out = hints
+            .filter_placement(endpoints, RoutingHintPolicy::Enforce)
+            .unwrap();
+        assert_eq!(out.iter().map(|ep| ep.id()).collect::<Vec<_>>(), vec![1]);
+    }
+}
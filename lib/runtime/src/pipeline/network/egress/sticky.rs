@@ -0,0 +1,191 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Sticky sessions.
+//!
+//! The turns of a chat share a growing prompt prefix. Sending them all to the same worker lets
+//! it reuse the KV blocks it computed for the previous turn. With sticky sessions on, the
+//! [`super::push_router::PushRouter`] remembers the worker it picked for a session and sends
+//! the following requests of that session there, for as long as the worker is around and the
+//! session keeps being used within the TTL.
+//!
+//! When the pinned worker goes away, or fails a request that is retried, the session moves to
+//! the worker the router picks instead. That worker has to compute the prefix again.
+//!
+//! The session is the `session=<id>` routing hint, see [`super::routing_hints`].
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Environment variable with how many seconds a session stays pinned to its worker after its
+/// last request. Unset or 0, sessions are not sticky.
+pub const STICKY_SESSION_TTL_ENV: &str = "DYN_STICKY_SESSION_TTL_SECS";
+
+pub fn sticky_session_ttl_from_env() -> Option<Duration> {
+    let val = std::env::var(STICKY_SESSION_TTL_ENV).ok()?;
+    match val.trim().parse::<u64>() {
+        Ok(0) => None,
+        Ok(secs) => Some(Duration::from_secs(secs)),
+        Err(err) => {
+            tracing::warn!(%err, "Invalid {STICKY_SESSION_TTL_ENV} '{val}', ignoring it");
+            None
+        }
+    }
+}
+
+/// The worker each session is pinned to
+pub struct StickySessions {
+    ttl: Duration,
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    sessions: HashMap<String, Pinned>,
+    last_sweep: Instant,
+}
+
+struct Pinned {
+    worker: i64,
+    last_used: Instant,
+}
+
+impl StickySessions {
+    pub fn new(ttl: Duration) -> Self {
+        StickySessions {
+            ttl,
+            inner: Mutex::new(Inner {
+                sessions: HashMap::new(),
+                last_sweep: Instant::now(),
+            }),
+        }
+    }
+
+    /// The worker `session` is pinned to, unless it expired. Counts as a use of the session.
+    pub fn get(&self, session: &str) -> Option<i64> {
+        self.get_at(session, Instant::now())
+    }
+
+    /// Pin `session` to `worker`, replacing the worker it had
+    pub fn pin(&self, session: &str, worker: i64) {
+        self.pin_at(session, worker, Instant::now())
+    }
+
+    /// Number of pinned sessions, including expired ones not swept yet
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().sessions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The sessions that haven't expired and their worker, by session id
+    pub fn list(&self) -> Vec<(String, i64)> {
+        self.list_at(Instant::now())
+    }
+
+    /// Unpin `session`, its next request goes to the worker the router picks. Returns false
+    /// if it wasn't pinned.
+    pub fn remove(&self, session: &str) -> bool {
+        self.inner
+            .lock()
+            .unwrap()
+            .sessions
+            .remove(session)
+            .is_some()
+    }
+
+    fn list_at(&self, now: Instant) -> Vec<(String, i64)> {
+        let inner = self.inner.lock().unwrap();
+        let mut sessions: Vec<(String, i64)> = inner
+            .sessions
+            .iter()
+            .filter(|(_, pinned)| now.duration_since(pinned.last_used) <= self.ttl)
+            .map(|(session, pinned)| (session.clone(), pinned.worker))
+            .collect();
+        sessions.sort();
+        sessions
+    }
+
+    fn get_at(&self, session: &str, now: Instant) -> Option<i64> {
+        let mut inner = self.inner.lock().unwrap();
+        let pinned = inner.sessions.get_mut(session)?;
+        if now.duration_since(pinned.last_used) > self.ttl {
+            inner.sessions.remove(session);
+            return None;
+        }
+        pinned.last_used = now;
+        Some(pinned.worker)
+    }
+
+    fn pin_at(&self, session: &str, worker: i64, now: Instant) {
+        let mut inner = self.inner.lock().unwrap();
+        // Sessions that are never used again would otherwise stay forever
+        if now.duration_since(inner.last_sweep) > self.ttl {
+            let ttl = self.ttl;
+            inner
+                .sessions
+                .retain(|_, pinned| now.duration_since(pinned.last_used) <= ttl);
+            inner.last_sweep = now;
+        }
+        inner.sessions.insert(
+            session.to_string(),
+            Pinned {
+                worker,
+                last_used: now,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sticky_sessions() {
+        let ttl = Duration::from_secs(10);
+        let sessions = StickySessions::new(ttl);
+        let start = Instant::now();
+
+        assert_eq!(sessions.get_at("a", start), None);
+        sessions.pin_at("a", 1, start);
+        sessions.pin_at("b", 2, start);
+        assert_eq!(sessions.get_at("a", start + ttl), Some(1));
+
+        // Using a session keeps it alive, the other one expires
+        assert_eq!(sessions.get_at("a", start + ttl * 2), Some(1));
+        assert_eq!(sessions.get_at("b", start + ttl * 2), None);
+
+        // Moving to another worker
+        sessions.pin_at("a", 3, start + ttl * 2);
+        assert_eq!(sessions.get_at("a", start + ttl * 2), Some(3));
+
+        // Pinning sweeps the sessions nobody asked about again
+        sessions.pin_at("c", 4, start + ttl * 4);
+        assert_eq!(sessions.len(), 1);
+
+        // Listing leaves out the expired ones, removing unpins
+        sessions.pin_at("d", 5, start + ttl * 5);
+        assert_eq!(
+            sessions.list_at(start + ttl * 6),
+            vec![("d".to_string(), 5)]
+        );
+        assert!(sessions.remove("d"));
+        assert!(!sessions.remove("d"));
+        assert_eq!(sessions.get_at("d", start + ttl * 6), None);
+    }
+}
@@ -20,12 +20,18 @@ use anyhow::Result;
 use async_nats::service::endpoint::Endpoint;
 use derive_builder::Builder;
 use tokio::sync::Notify;
+
+use crate::drain::Drain;
 use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
 
 #[derive(Builder)]
 pub struct PushEndpoint {
     pub service_handler: Arc<dyn PushWorkHandler>,
     pub cancellation_token: CancellationToken,
+    /// Stop taking requests when this drain starts
+    #[builder(default)]
+    pub drain: Arc<Drain>,
 }
 
 /// version of crate
@@ -51,6 +57,15 @@ impl PushEndpoint {
                     req
                 }
 
+                // stop taking requests, the running ones finish below
+                _ = self.drain.draining() => {
+                    tracing::info!("Draining service");
+                    if let Err(e) = endpoint.stop().await {
+                        tracing::warn!("Failed to stop NATS service: {:?}", e);
+                    }
+                    break;
+                }
+
                 // process shutdown
                 _ = self.cancellation_token.cancelled() => {
                     tracing::info!("Shutting down service");
@@ -73,24 +88,35 @@ impl PushEndpoint {
                 // increment the inflight counter
                 inflight.fetch_add(1, Ordering::SeqCst);
                 let inflight_clone = inflight.clone();
+                let running = self.drain.track();
                 let notify_clone = notify.clone();
 
-                tokio::spawn(async move {
-                    tracing::trace!(worker_id, "handling new request");
-                    let result = ingress.handle_payload(req.message.payload).await;
-                    match result {
-                        Ok(_) => {
-                            tracing::trace!(worker_id, "request handled successfully");
-                        }
-                        Err(e) => {
-                            tracing::warn!("Failed to handle request: {:?}", e);
+                // continue the trace of the client that published the request
+                let span = tracing::info_span!("handle_request", subject = %req.message.subject);
+                if let Some(headers) = req.message.headers.clone() {
+                    crate::telemetry::set_parent(&span, &crate::telemetry::NatsHeaders(headers));
+                }
+
+                tokio::spawn(
+                    async move {
+                        tracing::trace!(worker_id, "handling new request");
+                        let result = ingress.handle_payload(req.message.payload).await;
+                        match result {
+                            Ok(_) => {
+                                tracing::trace!(worker_id, "request handled successfully");
+                            }
+                            Err(e) => {
+                                tracing::warn!("Failed to handle request: {:?}", e);
+                            }
                         }
-                    }
 
-                    // decrease the inflight counter
-                    inflight_clone.fetch_sub(1, Ordering::SeqCst);
-                    notify_clone.notify_one();
-                });
+                        // decrease the inflight counter
+                        inflight_clone.fetch_sub(1, Ordering::SeqCst);
+                        drop(running);
+                        notify_clone.notify_one();
+                    }
+                    .instrument(span),
+                );
             } else {
                 break;
             }
@@ -14,7 +14,9 @@
 // limitations under the License.
 
 use super::*;
+use crate::fault::{self, FaultPoint};
 use serde::{Deserialize, Serialize};
+use tracing::Instrument;
 
 #[async_trait]
 impl<T: Data, U: Data> PushWorkHandler for Ingress<SingleIn<T>, ManyOut<U>>
@@ -46,7 +48,7 @@ where
                         ));
                     }
                 };
-                let request: T = serde_json::from_slice(&data)?;
+                let request: T = control_msg.encoding.decode(&data)?;
                 (control_msg, request)
             }
             _ => {
@@ -57,6 +59,7 @@ where
         // extend request with context
         tracing::trace!("received control message: {:?}", control_msg);
         tracing::trace!("received request: {:?}", request);
+        let encoding = control_msg.encoding;
         let request: context::Context<T> = Context::with_id(request, control_msg.id);
 
         // todo - eventually have a handler class which will returned an abstracted object, but for now,
@@ -71,12 +74,18 @@ where
             PipelineError::Generic(format!("Failed to create response stream: {:?}", e,))
         })?;
 
+        if let Err(err) = fault::inject(FaultPoint::WorkerRequest, "").await {
+            let _result = publisher.send_prologue(Some(err.to_string())).await;
+            return Err(PipelineError::Generic(err.to_string()));
+        }
+
         tracing::trace!("calling generate");
         let stream = self
             .segment
             .get()
             .expect("segment not set")
             .generate(request)
+            .instrument(tracing::info_span!("generate"))
             .await
             .map_err(PipelineError::GenerateError);
 
@@ -97,11 +106,30 @@ where
 
         let context = stream.context();
 
-        while let Some(resp) = stream.next().await {
+        loop {
+            // The requester sends a Kill control message when it no longer wants the
+            // responses. Stop is left to the engine, which may still send what it has.
+            let resp = tokio::select! {
+                biased;
+                _ = context.killed() => {
+                    tracing::debug!("Request {} killed, dropping the response stream", context.id());
+                    break;
+                }
+                resp = stream.next() => match resp {
+                    Some(resp) => resp,
+                    None => break,
+                },
+            };
+            if let Err(err) = fault::inject(FaultPoint::ResponseChunk, "").await {
+                tracing::warn!("{err}, ending the response stream for {}", context.id());
+                context.stop_generating();
+                break;
+            }
             tracing::trace!("Sending response: {:?}", resp);
-            let resp_bytes = serde_json::to_vec(&resp)
+            let resp_bytes = encoding
+                .encode(&resp)
                 .expect("fatal error: invalid response object - this should never happen");
-            if (publisher.send(resp_bytes.into()).await).is_err() {
+            if (publisher.send(resp_bytes).await).is_err() {
                 tracing::error!("Failed to publish response for stream {}", context.id());
                 context.stop_generating();
                 break;
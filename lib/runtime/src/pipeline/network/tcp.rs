@@ -34,6 +34,7 @@ pub mod server;
 
 use super::ControlMessage;
 use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncWrite};
 
 #[allow(unused_imports)]
 use super::{
@@ -43,6 +44,14 @@ use super::{
 
 const TCP_TRANSPORT: &str = "tcp_server";
 
+/// A connection between two nodes, over TLS if
+/// [`TransportTls`](crate::transports::tls::TransportTls) is configured
+pub(crate) type Connection = Box<dyn AsyncConnection>;
+
+pub(crate) trait AsyncConnection: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> AsyncConnection for T {}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TcpStreamConnectionInfo {
     pub address: String,
@@ -171,7 +180,7 @@ mod tests {
 
         println!("Client sent message");
 
-        let data = recv_stream.unwrap().rx.recv().await.unwrap();
+        let data = recv_stream.unwrap().rx.recv().await.unwrap().unwrap();
 
         println!("Server received message");
 
@@ -24,13 +24,14 @@ use tokio::{
 };
 use tokio_util::codec::{FramedRead, FramedWrite};
 
-use super::{CallHomeHandshake, ControlMessage, TcpStreamConnectionInfo};
+use super::{CallHomeHandshake, Connection, ControlMessage, TcpStreamConnectionInfo};
 use crate::engine::AsyncEngineContext;
 use crate::pipeline::network::{
     codec::{TwoPartCodec, TwoPartMessage},
     tcp::StreamType,
     ConnectionInfo, ResponseStreamPrologue, StreamSender,
 };
+use crate::transports::tls::TransportTls;
 use crate::{error, ErrorContext, Result}; // Import SinkExt to use the `send` method
 
 #[allow(dead_code)]
@@ -96,6 +97,14 @@ impl TcpClient {
         }
 
         let stream = TcpClient::connect(&info.address).await?;
+        let stream: Connection = match TransportTls::global()? {
+            Some(tls) => Box::new(
+                tls.connect(stream)
+                    .await
+                    .with_context(|| format!("TLS handshake with {} failed", info.address))?,
+            ),
+            None => Box::new(stream),
+        };
         let (read_half, write_half) = tokio::io::split(stream);
 
         let framed_reader = FramedRead::new(read_half, TwoPartCodec::default());
@@ -196,6 +205,7 @@ impl TcpClient {
         let stream_sender = StreamSender {
             tx: bytes_tx,
             prologue,
+            next_seq: Default::default(),
         };
 
         Ok(stream_sender)
@@ -203,10 +213,10 @@ impl TcpClient {
 }
 
 async fn handle_reader(
-    framed_reader: FramedRead<tokio::io::ReadHalf<tokio::net::TcpStream>, TwoPartCodec>,
+    framed_reader: FramedRead<tokio::io::ReadHalf<Connection>, TwoPartCodec>,
     context: Arc<dyn AsyncEngineContext>,
     alive_tx: tokio::sync::oneshot::Sender<()>,
-) -> FramedRead<tokio::io::ReadHalf<tokio::net::TcpStream>, TwoPartCodec> {
+) -> FramedRead<tokio::io::ReadHalf<Connection>, TwoPartCodec> {
     let mut framed_reader = framed_reader;
     let mut alive_tx = alive_tx;
     loop {
@@ -263,11 +273,11 @@ async fn handle_reader(
 }
 
 async fn handle_writer(
-    mut framed_writer: FramedWrite<tokio::io::WriteHalf<tokio::net::TcpStream>, TwoPartCodec>,
+    mut framed_writer: FramedWrite<tokio::io::WriteHalf<Connection>, TwoPartCodec>,
     mut bytes_rx: tokio::sync::mpsc::Receiver<TwoPartMessage>,
     alive_rx: tokio::sync::oneshot::Receiver<()>,
     context: Arc<dyn AsyncEngineContext>,
-) -> Result<FramedWrite<tokio::io::WriteHalf<tokio::net::TcpStream>, TwoPartCodec>> {
+) -> Result<FramedWrite<tokio::io::WriteHalf<Connection>, TwoPartCodec>> {
     loop {
         let msg = tokio::select! {
             biased;
@@ -18,7 +18,6 @@ use socket2::{Domain, SockAddr, Socket, Type};
 use std::{
     collections::HashMap,
     net::{SocketAddr, TcpListener},
-    os::fd::{AsFd, FromRawFd},
     sync::Arc,
 };
 use tokio::sync::Mutex;
@@ -36,18 +35,19 @@ use tokio::{
 use tokio_util::codec::{FramedRead, FramedWrite};
 
 use super::{
-    CallHomeHandshake, ControlMessage, PendingConnections, RegisteredStream, StreamOptions,
-    StreamReceiver, StreamSender, TcpStreamConnectionInfo, TwoPartCodec,
+    CallHomeHandshake, Connection, ControlMessage, PendingConnections, RegisteredStream,
+    StreamOptions, StreamReceiver, StreamSender, TcpStreamConnectionInfo, TwoPartCodec,
 };
 use crate::engine::AsyncEngineContext;
 use crate::pipeline::{
     network::{
         codec::{TwoPartMessage, TwoPartMessageType},
         tcp::StreamType,
-        ResponseService, ResponseStreamPrologue,
+        ResponseService, ResponseStreamPrologue, SequenceCheck,
     },
     PipelineError,
 };
+use crate::transports::tls::TransportTls;
 use crate::{error, ErrorContext, Result};
 
 #[allow(dead_code)]
@@ -139,6 +139,9 @@ impl TcpStreamServer {
             None => local_ip().unwrap().to_string(),
         };
 
+        // A broken TLS configuration fails here, not on the first request
+        TransportTls::global().map_err(|e| PipelineError::Generic(format!("{e:#}")))?;
+
         let state = Arc::new(Mutex::new(State::default()));
 
         let local_port = Self::start(local_ip.clone(), options.port, state.clone())
@@ -156,6 +159,11 @@ impl TcpStreamServer {
         }))
     }
 
+    /// The address the server listens on, which other hosts reach this one at
+    pub fn local_ip(&self) -> &str {
+        &self.local_ip
+    }
+
     #[allow(clippy::await_holding_lock)]
     async fn start(local_ip: String, local_port: u16, state: Arc<Mutex<State>>) -> Result<u16> {
         let addr = format!("{}:{}", local_ip, local_port);
@@ -340,6 +348,20 @@ async fn tcp_listener(
     // #[instrument(level = "trace"), skip(state)]
     // todo - clone before spawn and trace process_stream
     async fn handle_connection(stream: tokio::net::TcpStream, state: Arc<Mutex<State>>) {
+        let stream: Connection = match TransportTls::global() {
+            Ok(Some(tls)) => match tls.accept(stream).await {
+                Ok(stream) => Box::new(stream),
+                Err(e) => {
+                    tracing::warn!("TLS handshake failed: {}", e);
+                    return;
+                }
+            },
+            Ok(None) => Box::new(stream),
+            Err(e) => {
+                tracing::warn!("failed to handle tcp connection: {:#}", e);
+                return;
+            }
+        };
         let result = process_stream(stream, state).await;
         match result {
             Ok(_) => tracing::trace!("successfully processed tcp connection"),
@@ -353,7 +375,7 @@ async fn tcp_listener(
 
     /// This method is responsible for the internal tcp stream handshake
     /// The handshake will specialize the stream as a request/sender or response/receiver stream
-    async fn process_stream(stream: tokio::net::TcpStream, state: Arc<Mutex<State>>) -> Result<()> {
+    async fn process_stream(stream: Connection, state: Arc<Mutex<State>>) -> Result<()> {
         // split the socket in to a reader and writer
         let (read_half, write_half) = tokio::io::split(stream);
 
@@ -398,8 +420,8 @@ async fn tcp_listener(
     async fn process_response_stream(
         subject: String,
         state: Arc<Mutex<State>>,
-        mut reader: FramedRead<tokio::io::ReadHalf<tokio::net::TcpStream>, TwoPartCodec>,
-        writer: FramedWrite<tokio::io::WriteHalf<tokio::net::TcpStream>, TwoPartCodec>,
+        mut reader: FramedRead<tokio::io::ReadHalf<Connection>, TwoPartCodec>,
+        writer: FramedWrite<tokio::io::WriteHalf<Connection>, TwoPartCodec>,
     ) -> Result<()> {
         let response_stream = state
             .lock().await
@@ -480,13 +502,14 @@ async fn tcp_listener(
     }
 
     async fn network_receive_handler(
-        mut framed_reader: FramedRead<tokio::io::ReadHalf<tokio::net::TcpStream>, TwoPartCodec>,
-        response_tx: mpsc::Sender<Bytes>,
+        mut framed_reader: FramedRead<tokio::io::ReadHalf<Connection>, TwoPartCodec>,
+        response_tx: mpsc::Sender<Result<Bytes, String>>,
         control_tx: mpsc::Sender<ControlMessage>,
         context: Arc<dyn AsyncEngineContext>,
     ) {
         // loop over reading the tcp stream and checking if the writer is closed
         let mut can_stop = true;
+        let mut sequence = SequenceCheck::default();
         loop {
             tokio::select! {
                 biased;
@@ -515,11 +538,10 @@ async fn tcp_listener(
                             let (header, data) = msg.into_parts();
 
                             // received a control message
-                            if !header.is_empty() {
+                            if data.is_empty() {
                                 match process_control_message(header) {
                                     Ok(ControlAction::Continue) => {}
                                     Ok(ControlAction::Shutdown) => {
-                                        assert!(data.is_empty(), "received sentinel message with data; this should never happen");
                                         tracing::trace!("received sentinel message; shutting down");
                                         break;
                                     }
@@ -528,27 +550,34 @@ async fn tcp_listener(
                                         panic!("{:?}", e);
                                     }
                                 }
+                                continue;
                             }
 
-                            if !data.is_empty() {
-                                if let Err(err) = response_tx.send(data).await {
-                                    tracing::debug!("forwarding body/data message to response channel failed: {}", err);
-                                    control_tx.send(ControlMessage::Kill).await.expect("the control channel should not be closed");
-                                    break;
-                                };
+                            // a data message, the header has its sequence number
+                            if let Err(err) = sequence.check(&header) {
+                                tracing::warn!(%err, "response stream is broken; failing the request");
+                                let _ = response_tx.send(Err(err)).await;
+                                control_tx.send(ControlMessage::Kill).await.expect("the control channel should not be closed");
+                                break;
                             }
+                            if let Err(err) = response_tx.send(Ok(data)).await {
+                                tracing::debug!("forwarding body/data message to response channel failed: {}", err);
+                                control_tx.send(ControlMessage::Kill).await.expect("the control channel should not be closed");
+                                break;
+                            };
                         }
                         Some(Err(_)) => {
                             // TODO(#171) - address fatal errors
                             panic!("invalid message issued over socket; this should never happen");
                         }
                         None => {
-                            // this is allowed but we try to avoid it
-                            // the logic is that the client will tell us when its is done and the server
-                            // will close the connection naturally when the sentinel message is received
-                            // the client closing early represents a transport error outside the control of the
-                            // transport library
-                            tracing::trace!("tcp stream was closed by client");
+                            // the client tells us when it is done with a sentinel message, and the server
+                            // closes the connection when it gets it. the client closing first means it went
+                            // away mid-stream, e.g. the worker died, and the responses we have are partial.
+                            tracing::debug!("tcp stream was closed by client before the sentinel");
+                            let _ = response_tx
+                                .send(Err("The worker closed the response stream before the end".to_string()))
+                                .await;
                             break;
                         }
                     }
@@ -559,7 +588,7 @@ async fn tcp_listener(
     }
 
     async fn network_send_handler(
-        socket_tx: FramedWrite<tokio::io::WriteHalf<tokio::net::TcpStream>, TwoPartCodec>,
+        socket_tx: FramedWrite<tokio::io::WriteHalf<Connection>, TwoPartCodec>,
         control_rx: mpsc::Receiver<ControlMessage>,
     ) {
         let mut socket_tx = socket_tx;
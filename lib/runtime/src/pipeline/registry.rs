@@ -86,6 +86,14 @@ impl Registry {
         }
     }
 
+    /// A registry holding the same shared objects as this one, and no unique objects.
+    pub fn clone_shared(&self) -> Self {
+        Registry {
+            shared_storage: self.shared_storage.clone(),
+            unique_storage: HashMap::new(),
+        }
+    }
+
     /// Check if a unique object exists in the registry by key.
     pub fn contains_unique(&self, key: &str) -> bool {
         self.unique_storage.contains_key(key)
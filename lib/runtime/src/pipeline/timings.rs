@@ -0,0 +1,96 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Time spent in each stage of one request.
+//!
+//! Whoever wants the breakdown, usually the HTTP service, stores a [`StageTimings`] in the
+//! request [`super::Context`] under [`STAGE_TIMINGS_CONTEXT_KEY`]. Stages of the pipeline that
+//! find it there time their work with [`StageTimings::span`]. When nobody asked, nothing is
+//! timed.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Key of the [`StageTimings`] in the request context registry
+pub const STAGE_TIMINGS_CONTEXT_KEY: &str = "stage_timings";
+
+/// Sending the request to the worker, until its response stream is connected
+pub const TRANSFER_STAGE: &str = "transfer";
+
+#[derive(Debug, Default)]
+pub struct StageTimings {
+    stages: Mutex<HashMap<&'static str, Duration>>,
+}
+
+impl StageTimings {
+    /// Add `took` to the time spent in `stage`. A stage can run more than once per request,
+    /// decoding each response for example.
+    pub fn record(&self, stage: &'static str, took: Duration) {
+        *self.stages.lock().unwrap().entry(stage).or_default() += took;
+    }
+
+    /// Time spent in `stage` so far, zero if it never ran
+    pub fn get(&self, stage: &str) -> Duration {
+        self.stages
+            .lock()
+            .unwrap()
+            .get(stage)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Time `stage` until the returned span is dropped
+    pub fn span(self: &Arc<Self>, stage: &'static str) -> StageSpan {
+        StageSpan {
+            timings: self.clone(),
+            stage,
+            start: Instant::now(),
+        }
+    }
+}
+
+/// Records the time since it was created into its [`StageTimings`] when dropped
+pub struct StageSpan {
+    timings: Arc<StageTimings>,
+    stage: &'static str,
+    start: Instant,
+}
+
+impl Drop for StageSpan {
+    fn drop(&mut self) {
+        self.timings.record(self.stage, self.start.elapsed());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stage_timings() {
+        let timings = Arc::new(StageTimings::default());
+        timings.record("decode", Duration::from_millis(2));
+        timings.record("decode", Duration::from_millis(3));
+        assert_eq!(timings.get("decode"), Duration::from_millis(5));
+        assert_eq!(timings.get("prefill"), Duration::ZERO);
+
+        {
+            let _span = timings.span(TRANSFER_STAGE);
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(timings.get(TRANSFER_STAGE) >= Duration::from_millis(10));
+    }
+}
@@ -0,0 +1,35 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Whether this process is ready for traffic
+//!
+//! A process is live as soon as it answers. It is ready once it has something to serve: for a
+//! worker, when its model is loaded, its engine built and its endpoint registered. It stops
+//! being ready when it starts draining. [`crate::metrics::serve`] answers `/ready` with this,
+//! for processes that don't run the HTTP service.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static READY: AtomicBool = AtomicBool::new(false);
+
+pub fn set_ready(ready: bool) {
+    if READY.swap(ready, Ordering::Relaxed) != ready {
+        tracing::debug!(ready, "Readiness changed");
+    }
+}
+
+pub fn is_ready() -> bool {
+    READY.load(Ordering::Relaxed)
+}
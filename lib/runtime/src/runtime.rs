@@ -27,6 +27,7 @@
 
 use super::{error, Result, Runtime, RuntimeType};
 use crate::config::{self, RuntimeConfig};
+use crate::drain::Drain;
 
 use futures::Future;
 use once_cell::sync::OnceCell;
@@ -56,6 +57,7 @@ impl Runtime {
             primary: runtime,
             secondary,
             cancellation_token,
+            drain: Arc::new(Drain::default()),
         })
     }
 
@@ -111,6 +113,11 @@ impl Runtime {
         self.cancellation_token.child_token()
     }
 
+    /// The [`Drain`] services check to stop taking requests before the [`Runtime`] shuts down
+    pub fn drain(&self) -> Arc<Drain> {
+        self.drain.clone()
+    }
+
     /// Shuts down the [`Runtime`] instance
     pub fn shutdown(&self) {
         self.cancellation_token.cancel();
@@ -19,10 +19,11 @@
 // we will want to associate the components cancellation token with the
 // component's "service state"
 
-use crate::{error, transports::nats, utils::stream, Result};
+use crate::{discovery::ServiceDiscovery, error, transports::nats, utils::stream, Result};
 
 use async_nats::Message;
 use async_stream::try_stream;
+use async_trait::async_trait;
 use bytes::Bytes;
 use derive_getters::Dissolve;
 use futures::stream::{StreamExt, TryStreamExt};
@@ -144,6 +145,13 @@ impl ServiceClient {
     }
 }
 
+#[async_trait]
+impl ServiceDiscovery for ServiceClient {
+    async fn collect_services(&self, service_name: &str, timeout: Duration) -> Result<ServiceSet> {
+        ServiceClient::collect_services(self, service_name, timeout).await
+    }
+}
+
 impl ServiceSet {
     pub fn into_endpoints(self) -> impl Iterator<Item = EndpointInfo> {
         self.services
@@ -0,0 +1,135 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! OpenTelemetry tracing
+//!
+//! Export is off unless one of the standard `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT` or
+//! `OTEL_EXPORTER_OTLP_ENDPOINT` environment variables is set. Spans are then sent over OTLP
+//! (HTTP, protobuf) by the layer [`crate::logging::init`] installs. The service name is
+//! `OTEL_SERVICE_NAME`, or the name of the executable.
+//!
+//! The trace context travels between processes as W3C `traceparent` headers: on NATS
+//! requests with [`inject`] and [`set_parent`], and on HTTP requests.
+
+use std::sync::OnceLock;
+
+use async_nats::HeaderMap;
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::{Layer, Registry};
+
+pub use opentelemetry::propagation::{Extractor, Injector};
+
+const ENDPOINT_ENVS: [&str; 2] = [
+    "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT",
+    "OTEL_EXPORTER_OTLP_ENDPOINT",
+];
+
+static PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
+/// Is an OTLP endpoint configured?
+pub fn is_enabled() -> bool {
+    ENDPOINT_ENVS
+        .iter()
+        .any(|env| std::env::var(env).is_ok_and(|v| !v.is_empty()))
+}
+
+/// The layer exporting spans, if enabled. Only spans at `info` and above are exported.
+pub(crate) fn layer() -> Option<impl Layer<Registry> + Send + Sync> {
+    if !is_enabled() {
+        return None;
+    }
+    let exporter = match opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .build()
+    {
+        Ok(exporter) => exporter,
+        Err(err) => {
+            // Logging isn't initialized yet
+            eprintln!("Failed creating the OTLP span exporter, tracing is disabled: {err}");
+            return None;
+        }
+    };
+    let service_name = std::env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| {
+        std::env::current_exe()
+            .ok()
+            .and_then(|exe| Some(exe.file_name()?.to_string_lossy().into_owned()))
+            .unwrap_or_else(|| "dynamo".to_string())
+    });
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name(service_name).build())
+        .build();
+    let tracer = provider.tracer("dynamo");
+    let _ = PROVIDER.set(provider);
+
+    Some(
+        tracing_opentelemetry::layer()
+            .with_tracer(tracer)
+            .with_filter(LevelFilter::INFO),
+    )
+}
+
+/// Send the spans that are still buffered. Called when the [`crate::Worker`] exits.
+pub fn shutdown() {
+    if let Some(provider) = PROVIDER.get() {
+        if let Err(err) = provider.shutdown() {
+            tracing::warn!(%err, "Failed flushing OpenTelemetry spans");
+        }
+    }
+}
+
+/// Write the trace context of `span` to `carrier`
+pub fn inject_context(span: &Span, carrier: &mut dyn Injector) {
+    TraceContextPropagator::new().inject_context(&span.context(), carrier);
+}
+
+/// Make `span` a child of the trace context in `carrier`, if it has one
+pub fn set_parent(span: &Span, carrier: &dyn Extractor) {
+    let context = TraceContextPropagator::new().extract(carrier);
+    span.set_parent(context);
+}
+
+/// The trace context of the current span as NATS headers
+pub fn inject() -> HeaderMap {
+    let mut headers = NatsHeaders(HeaderMap::new());
+    inject_context(&Span::current(), &mut headers);
+    headers.0
+}
+
+/// Adapts NATS headers to the OpenTelemetry propagator
+pub struct NatsHeaders(pub HeaderMap);
+
+impl Injector for NatsHeaders {
+    fn set(&mut self, key: &str, value: String) {
+        self.0.insert(key, value.as_str());
+    }
+}
+
+impl Extractor for NatsHeaders {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(|value| value.as_str())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.iter().map(|(name, _)| name.as_ref()).collect()
+    }
+}
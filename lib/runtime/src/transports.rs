@@ -18,7 +18,9 @@
 //!
 //! These are the low-level building blocks for the distributed system.
 
+pub mod control_plane;
 pub mod etcd;
 pub mod nats;
 pub mod tcp;
+pub mod tls;
 pub mod zmq;
@@ -0,0 +1,114 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reachability of the control plane
+//!
+//! When etcd or NATS go away we don't fail requests: routers keep using the workers they last
+//! knew about, and local engines don't need either. The etcd watchers and the lease keep alive
+//! reconnect in the background, and the watchers reconcile what changed meanwhile. What is
+//! down is exported as the `dynamo_control_plane_up` gauge.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use prometheus::{IntGaugeVec, Opts};
+
+/// First wait before reconnecting, doubled on each failure
+pub(crate) const RECONNECT_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Longest wait between reconnect attempts
+pub(crate) const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Service {
+    Etcd,
+    Nats,
+}
+
+impl Service {
+    fn name(self) -> &'static str {
+        match self {
+            Service::Etcd => "etcd",
+            Service::Nats => "nats",
+        }
+    }
+
+    fn state(self) -> &'static AtomicBool {
+        static ETCD: AtomicBool = AtomicBool::new(true);
+        static NATS: AtomicBool = AtomicBool::new(true);
+        match self {
+            Service::Etcd => &ETCD,
+            Service::Nats => &NATS,
+        }
+    }
+}
+
+static CONTROL_PLANE_UP: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    crate::metrics::register(
+        IntGaugeVec::new(
+            Opts::new(
+                "dynamo_control_plane_up",
+                "1 if the control plane service is reachable, 0 during an outage",
+            ),
+            &["service"],
+        )
+        .unwrap(),
+    )
+});
+
+/// Record whether `service` is reachable. Only changes are logged, many tasks report the
+/// same outage.
+pub fn set_reachable(service: Service, reachable: bool) {
+    CONTROL_PLANE_UP
+        .with_label_values(&[service.name()])
+        .set(reachable as i64);
+    if service.state().swap(reachable, Ordering::Relaxed) == reachable {
+        return;
+    }
+    if reachable {
+        tracing::info!("{} is reachable again", service.name());
+    } else {
+        tracing::warn!(
+            "{} is unreachable, serving with the last known state until it is back",
+            service.name()
+        );
+    }
+}
+
+pub fn is_reachable(service: Service) -> bool {
+    service.state().load(Ordering::Relaxed)
+}
+
+/// The wait after `backoff`
+pub(crate) fn next_backoff(backoff: Duration) -> Duration {
+    (backoff * 2).min(MAX_RECONNECT_BACKOFF)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reachable() {
+        set_reachable(Service::Nats, false);
+        assert!(!is_reachable(Service::Nats));
+        assert_eq!(CONTROL_PLANE_UP.with_label_values(&["nats"]).get(), 0);
+        set_reachable(Service::Nats, true);
+        assert!(is_reachable(Service::Nats));
+
+        assert_eq!(next_backoff(Duration::from_secs(8)), MAX_RECONNECT_BACKOFF);
+    }
+}
@@ -13,6 +13,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use super::control_plane;
 use crate::{error, CancellationToken, ErrorContext, Result, Runtime};
 
 use async_nats::jetstream::kv;
@@ -26,7 +27,7 @@ use validator::Validate;
 
 use etcd_client::{
     Certificate, Compare, CompareOp, DeleteOptions, GetOptions, Identity, PutOptions, PutResponse,
-    TlsOptions, Txn, TxnOp, TxnOpResponse, WatchOptions, Watcher,
+    TlsOptions, Txn, TxnOp, TxnOpResponse, WatchOptions, WatchStream, Watcher,
 };
 
 pub use etcd_client::{ConnectOptions, KeyValue, LeaseClient};
@@ -113,7 +114,7 @@ impl Client {
         let lease_id = if config.attach_lease {
             let lease_client = client.lease_client();
 
-            let lease = create_lease(lease_client, 10, token)
+            let lease = create_lease(lease_client, config.lease_ttl, token)
                 .await
                 .context("creating primary lease")?;
 
@@ -121,6 +122,7 @@ impl Client {
         } else {
             0
         };
+        control_plane::set_reachable(control_plane::Service::Etcd, true);
 
         Ok(Client {
             client,
@@ -306,15 +308,100 @@ impl Client {
         Ok(get_response.take_kvs())
     }
 
+    /// The keys under `prefix` followed by their changes.
+    ///
+    /// If the watch breaks, for example because etcd is down, the receiver keeps what it has
+    /// and we reconnect in the background. Once back, it gets a `Put` for every key that
+    /// changed and a `Delete` for every key that went away in the meantime.
     pub async fn kv_get_and_watch_prefix(
         &self,
         prefix: impl AsRef<str> + std::fmt::Display,
     ) -> Result<PrefixWatcher> {
+        let prefix = prefix.as_ref().to_string();
+        let (kvs, watcher, watch_stream) = self.get_and_watch(&prefix).await?;
+        tracing::trace!("initial kv count: {:?}", kvs.len());
+
+        let (tx, rx) = mpsc::channel(32);
+
+        let client = self.clone();
+        let task_prefix = prefix.clone();
+        self.runtime.secondary().spawn(async move {
+            let prefix = task_prefix;
+            // What the receiver knows, to tell it what changed while we were disconnected
+            let mut known: HashMap<Vec<u8>, KeyValue> = HashMap::new();
+            for kv in kvs {
+                known.insert(kv.key().to_vec(), kv.clone());
+                if tx.send(WatchEvent::Put(kv)).await.is_err() {
+                    // receiver is closed
+                    return;
+                }
+            }
+
+            let mut watch_stream = watch_stream;
+            // The first watcher is in the PrefixWatcher, later ones must live as long as their
+            // stream
+            let mut _watcher = None;
+            loop {
+                if !forward_events(&mut watch_stream, &tx, &mut known).await {
+                    // receiver is closed
+                    return;
+                }
+                control_plane::set_reachable(control_plane::Service::Etcd, false);
+                tracing::debug!(prefix, "etcd watch lost, reconnecting");
+
+                let mut backoff = control_plane::RECONNECT_BACKOFF;
+                let (kvs, watcher, stream) = loop {
+                    tokio::select! {
+                        _ = tx.closed() => return,
+                        _ = tokio::time::sleep(backoff) => {}
+                    }
+                    match client.get_and_watch(&prefix).await {
+                        Ok(watch) => break watch,
+                        Err(err) => {
+                            tracing::trace!(prefix, %err, "etcd watch reconnect failed");
+                            backoff = control_plane::next_backoff(backoff);
+                        }
+                    }
+                };
+                control_plane::set_reachable(control_plane::Service::Etcd, true);
+                _watcher = Some(watcher);
+                watch_stream = stream;
+
+                let current: HashMap<Vec<u8>, KeyValue> =
+                    kvs.into_iter().map(|kv| (kv.key().to_vec(), kv)).collect();
+                for (key, kv) in &known {
+                    if !current.contains_key(key)
+                        && tx.send(WatchEvent::Delete(kv.clone())).await.is_err()
+                    {
+                        return;
+                    }
+                }
+                for (key, kv) in &current {
+                    let unchanged = known
+                        .get(key)
+                        .is_some_and(|old| old.mod_revision() == kv.mod_revision());
+                    if !unchanged && tx.send(WatchEvent::Put(kv.clone())).await.is_err() {
+                        return;
+                    }
+                }
+                tracing::debug!(prefix, count = current.len(), "etcd watch reconnected");
+                known = current;
+            }
+        });
+        Ok(PrefixWatcher {
+            prefix,
+            watcher,
+            rx,
+        })
+    }
+
+    /// The keys under `prefix`, and a watch of what happens to them after
+    async fn get_and_watch(&self, prefix: &str) -> Result<(Vec<KeyValue>, Watcher, WatchStream)> {
         let mut kv_client = self.client.kv_client();
         let mut watch_client = self.client.watch_client();
 
         let mut get_response = kv_client
-            .get(prefix.as_ref(), Some(GetOptions::new().with_prefix()))
+            .get(prefix, Some(GetOptions::new().with_prefix()))
             .await?;
 
         let start_revision = get_response
@@ -325,9 +412,9 @@ impl Client {
         tracing::trace!("{prefix}: start_revision: {start_revision}");
         let start_revision = start_revision + 1;
 
-        let (watcher, mut watch_stream) = watch_client
+        let (watcher, watch_stream) = watch_client
             .watch(
-                prefix.as_ref(),
+                prefix,
                 Some(
                     WatchOptions::new()
                         .with_prefix()
@@ -337,51 +424,42 @@ impl Client {
             )
             .await?;
 
-        let kvs = get_response.take_kvs();
-        tracing::trace!("initial kv count: {:?}", kvs.len());
-
-        let (tx, rx) = mpsc::channel(32);
+        Ok((get_response.take_kvs(), watcher, watch_stream))
+    }
+}
 
-        self.runtime.secondary().spawn(async move {
-            for kv in kvs {
-                if tx.send(WatchEvent::Put(kv)).await.is_err() {
-                    // receiver is closed
-                    break;
+/// Send the watch events on until the watch breaks. Returns false if the receiver is gone.
+async fn forward_events(
+    watch_stream: &mut WatchStream,
+    tx: &mpsc::Sender<WatchEvent>,
+    known: &mut HashMap<Vec<u8>, KeyValue>,
+) -> bool {
+    while let Some(Ok(response)) = watch_stream.next().await {
+        if response.canceled() {
+            tracing::debug!(reason = response.cancel_reason(), "etcd watch canceled");
+            break;
+        }
+        for event in response.events() {
+            let Some(kv) = event.kv() else {
+                continue;
+            };
+            let event = match event.event_type() {
+                etcd_client::EventType::Put => {
+                    known.insert(kv.key().to_vec(), kv.clone());
+                    WatchEvent::Put(kv.clone())
                 }
-            }
-
-            while let Some(Ok(response)) = watch_stream.next().await {
-                for event in response.events() {
-                    match event.event_type() {
-                        etcd_client::EventType::Put => {
-                            if let Some(kv) = event.kv() {
-                                if let Err(err) = tx.send(WatchEvent::Put(kv.clone())).await {
-                                    tracing::error!(
-                                        "kv watcher error forwarding WatchEvent::Put: {err}"
-                                    );
-                                    // receiver is closed
-                                    break;
-                                }
-                            }
-                        }
-                        etcd_client::EventType::Delete => {
-                            if let Some(kv) = event.kv() {
-                                if tx.send(WatchEvent::Delete(kv.clone())).await.is_err() {
-                                    // receiver is closed
-                                    break;
-                                }
-                            }
-                        }
-                    }
+                etcd_client::EventType::Delete => {
+                    known.remove(kv.key());
+                    WatchEvent::Delete(kv.clone())
                 }
+            };
+            if let Err(err) = tx.send(event).await {
+                tracing::error!("kv watcher error forwarding {:?}", err.0);
+                return false;
             }
-        });
-        Ok(PrefixWatcher {
-            prefix: prefix.as_ref().to_string(),
-            watcher,
-            rx,
-        })
+        }
     }
+    true
 }
 
 #[derive(Dissolve)]
@@ -397,6 +475,11 @@ pub enum WatchEvent {
     Delete(KeyValue),
 }
 
+/// Environment variable with the TTL in seconds of the primary lease
+pub const LEASE_TTL_ENV: &str = "DYN_LEASE_TTL";
+
+const DEFAULT_LEASE_TTL: i64 = 10;
+
 /// ETCD client configuration options
 #[derive(Debug, Clone, Builder, Validate)]
 pub struct ClientOptions {
@@ -409,6 +492,11 @@ pub struct ClientOptions {
     /// If true, the client will attach a lease to the primary [`CancellationToken`].
     #[builder(default = "true")]
     pub attach_lease: bool,
+
+    /// Seconds the primary lease, and with it our registrations, outlive us if we stop
+    /// keeping it alive. Defaults to [`LEASE_TTL_ENV`], or 10.
+    #[builder(default = "default_lease_ttl()")]
+    pub lease_ttl: i64,
 }
 
 impl Default for ClientOptions {
@@ -440,6 +528,20 @@ impl Default for ClientOptions {
             etcd_url: default_servers(),
             etcd_connect_options: connect_options,
             attach_lease: true,
+            lease_ttl: default_lease_ttl(),
+        }
+    }
+}
+
+fn default_lease_ttl() -> i64 {
+    let Ok(val) = std::env::var(LEASE_TTL_ENV) else {
+        return DEFAULT_LEASE_TTL;
+    };
+    match val.trim().parse() {
+        Ok(ttl) if ttl > 0 => ttl,
+        _ => {
+            tracing::warn!("Invalid {LEASE_TTL_ENV} '{val}', using {DEFAULT_LEASE_TTL}");
+            DEFAULT_LEASE_TTL
         }
     }
 }
@@ -15,6 +15,8 @@
 
 use super::*;
 
+use etcd_client::{LeaseKeepAliveStream, LeaseKeeper};
+
 /// Create a [`Lease`] with a given time-to-live (TTL) attached to the [`CancellationToken`].
 pub async fn create_lease(
     mut lease_client: LeaseClient,
@@ -58,7 +60,8 @@ pub async fn revoke_lease(mut lease_client: LeaseClient, lease_id: i64) -> Resul
 /// Task to keep leases alive.
 ///
 /// If this task returns an error, the cancellation token will be invoked on the runtime.
-/// If
+/// Losing etcd is not an error: we keep reconnecting for as long as it takes. Only when etcd
+/// tells us the lease is gone, its keys with it, do we give up.
 pub async fn keep_alive(
     client: LeaseClient,
     lease_id: i64,
@@ -70,29 +73,44 @@ pub async fn keep_alive(
 
     let mut client = client;
     let (mut heartbeat_sender, mut heartbeat_receiver) = client.keep_alive(lease_id).await?;
+    let mut connected = true;
 
     loop {
         // if the deadline is exceeded, then we have failed to issue a heartbeat in time
-        // we maybe be permanently disconnected from the etcd server, so we are now officially done
-        if deadline < std::time::Instant::now() {
-            return Err(error!("failed to issue heartbeat in time"));
+        // etcd may have expired the lease, we'll know when we reach it again
+        if connected && deadline < std::time::Instant::now() {
+            tracing::warn!(lease_id, "failed to issue heartbeat in time");
+            control_plane::set_reachable(control_plane::Service::Etcd, false);
+            connected = false;
         }
 
         tokio::select! {
             biased;
 
             status = heartbeat_receiver.message() => {
-                if let Some(resp) = status? {
-                    tracing::trace!(lease_id, "keep alive response received: {:?}", resp);
-
-                    // update ttl and deadline
-                    ttl = resp.ttl();
-                    deadline = create_deadline(ttl)?;
-
-                    if resp.ttl() == 0 {
-                        return Err(error!("lease expired or revoked"));
+                match status {
+                    Ok(Some(resp)) => {
+                        tracing::trace!(lease_id, "keep alive response received: {:?}", resp);
+
+                        if resp.ttl() == 0 {
+                            return Err(error!("lease expired or revoked"));
+                        }
+
+                        // update ttl and deadline
+                        ttl = resp.ttl();
+                        deadline = create_deadline(ttl)?;
+                        if !connected {
+                            control_plane::set_reachable(control_plane::Service::Etcd, true);
+                            connected = true;
+                        }
+                    }
+                    Ok(None) | Err(_) => {
+                        tracing::debug!(lease_id, ?status, "keep alive stream lost, reconnecting");
+                        let Some(stream) = reconnect(&mut client, lease_id, &token).await else {
+                            return Ok(());
+                        };
+                        (heartbeat_sender, heartbeat_receiver) = stream;
                     }
-
                 }
             }
 
@@ -105,13 +123,13 @@ pub async fn keep_alive(
             _ = tokio::time::sleep(tokio::time::Duration::from_secs(ttl as u64 / 2)) => {
                 tracing::trace!(lease_id, "sending keep alive");
 
-                // if we get a error issuing the heartbeat, set the ttl to 0
-                // this will allow us to poll the response stream once and the cancellation token once, then
-                // immediately try to tick the heartbeat
-                // this will repeat until either the heartbeat is reestablished or the deadline is exceeded
+                // if we get a error issuing the heartbeat, the stream is broken: reconnect
                 if let Err(e) = heartbeat_sender.keep_alive().await {
                     tracing::warn!(lease_id, "keep alive failed: {:?}", e);
-                    ttl = 0;
+                    let Some(stream) = reconnect(&mut client, lease_id, &token).await else {
+                        return Ok(());
+                    };
+                    (heartbeat_sender, heartbeat_receiver) = stream;
                 }
             }
 
@@ -119,6 +137,35 @@ pub async fn keep_alive(
     }
 }
 
+/// Open a new keep alive stream, retrying until etcd is back. None if cancelled first.
+async fn reconnect(
+    client: &mut LeaseClient,
+    lease_id: i64,
+    token: &CancellationToken,
+) -> Option<(LeaseKeeper, LeaseKeepAliveStream)> {
+    let mut backoff = control_plane::RECONNECT_BACKOFF;
+    loop {
+        tokio::select! {
+            _ = token.cancelled() => return None,
+            _ = tokio::time::sleep(backoff) => {}
+        }
+        match client.keep_alive(lease_id).await {
+            Ok((mut sender, receiver)) => {
+                // the first heartbeat tells us whether the lease survived
+                if let Err(err) = sender.keep_alive().await {
+                    tracing::trace!(lease_id, %err, "keep alive after reconnect failed");
+                }
+                return Some((sender, receiver));
+            }
+            Err(err) => {
+                tracing::trace!(lease_id, %err, "keep alive reconnect failed");
+                control_plane::set_reachable(control_plane::Service::Etcd, false);
+                backoff = control_plane::next_backoff(backoff);
+            }
+        }
+    }
+}
+
 /// Create a deadline for a given time-to-live (TTL).
 fn create_deadline(ttl: i64) -> Result<std::time::Instant> {
     if ttl <= 0 {
@@ -28,6 +28,13 @@
 //! - `NATS_AUTH_CREDENTIALS_FILE`: the path to the credentials file
 //!
 //! Note: `NATS_AUTH_USERNAME` and `NATS_AUTH_PASSWORD` must be used together.
+//!
+//! To encrypt the connection, which a `tls://` server also does:
+//!
+//! - `NATS_TLS_CA`: PEM file of the CAs the server's certificate is signed by
+//! - `NATS_TLS_CERT` and `NATS_TLS_KEY`: PEM files of our certificate and its key, for a
+//!   server that asks clients for one
+use super::control_plane;
 use crate::Result;
 
 use async_nats::{client, jetstream, Subscriber};
@@ -218,6 +225,9 @@ pub struct ClientOptions {
 
     #[builder(default)]
     auth: NatsAuth,
+
+    #[builder(default)]
+    tls: NatsTls,
 }
 
 fn default_server() -> String {
@@ -229,10 +239,12 @@ fn default_server() -> String {
 }
 
 fn validate_nats_server(server: &str) -> Result<(), ValidationError> {
-    if server.starts_with("nats://") {
+    if server.starts_with("nats://") || server.starts_with("tls://") {
         Ok(())
     } else {
-        Err(ValidationError::new("server must start with 'nats://'"))
+        Err(ValidationError::new(
+            "server must start with 'nats://' or 'tls://'",
+        ))
     }
 }
 
@@ -243,6 +255,11 @@ impl ClientOptions {
         ClientOptionsBuilder::default()
     }
 
+    /// The server URL, `NATS_SERVER` unless set with the builder
+    pub fn server(&self) -> &str {
+        &self.server
+    }
+
     /// Validate the config and attempt to connection to the NATS server
     pub async fn connect(self) -> Result<Client> {
         self.validate()?;
@@ -258,7 +275,22 @@ impl ClientOptions {
             }
         };
 
-        let client = client.connect(self.server).await?;
+        let client = self.tls.apply(client);
+        let client = client
+            .event_callback(|event| async move {
+                match event {
+                    async_nats::Event::Connected => {
+                        control_plane::set_reachable(control_plane::Service::Nats, true)
+                    }
+                    async_nats::Event::Disconnected => {
+                        control_plane::set_reachable(control_plane::Service::Nats, false)
+                    }
+                    _ => {}
+                }
+            })
+            .connect(self.server)
+            .await?;
+        control_plane::set_reachable(control_plane::Service::Nats, true);
         let js_ctx = jetstream::new(client.clone());
 
         Ok(Client { client, js_ctx })
@@ -270,6 +302,7 @@ impl Default for ClientOptions {
         ClientOptions {
             server: default_server(),
             auth: NatsAuth::default(),
+            tls: NatsTls::default(),
         }
     }
 }
@@ -320,6 +353,45 @@ impl Default for NatsAuth {
     }
 }
 
+/// TLS for the connection to NATS
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct NatsTls {
+    /// PEM file of the CAs the server's certificate is signed by. Encrypts the connection.
+    pub ca: Option<PathBuf>,
+
+    /// PEM files of the client certificate and its key
+    pub client_cert: Option<(PathBuf, PathBuf)>,
+}
+
+impl NatsTls {
+    pub fn from_env() -> Self {
+        let var = |name| std::env::var_os(name).filter(|val| !val.is_empty());
+        NatsTls {
+            ca: var("NATS_TLS_CA").map(PathBuf::from),
+            client_cert: var("NATS_TLS_CERT")
+                .zip(var("NATS_TLS_KEY"))
+                .map(|(cert, key)| (PathBuf::from(cert), PathBuf::from(key))),
+        }
+    }
+
+    fn apply(self, options: async_nats::ConnectOptions) -> async_nats::ConnectOptions {
+        let mut options = options;
+        if let Some(ca) = self.ca {
+            options = options.add_root_certificates(ca).require_tls(true);
+        }
+        if let Some((cert, key)) = self.client_cert {
+            options = options.add_client_certificate(cert, key).require_tls(true);
+        }
+        options
+    }
+}
+
+impl Default for NatsTls {
+    fn default() -> Self {
+        NatsTls::from_env()
+    }
+}
+
 /// Is this file name / url in the NATS object store?
 /// Checks the name only, does not go to the store.
 pub fn is_nats_url(s: &str) -> bool {
@@ -535,6 +607,19 @@ mod tests {
             assert_eq!(opts.server, "nats://localhost:6222");
             assert_eq!(opts.auth, NatsAuth::Token("token".to_string()));
 
+            Ok(())
+        });
+        Jail::expect_with(|jail| {
+            jail.set_env("NATS_SERVER", "tls://nats.example.com:4222");
+            jail.set_env("NATS_TLS_CA", "/etc/nats/ca.pem");
+            jail.set_env("NATS_TLS_CERT", "/etc/nats/cert.pem");
+
+            let opts = ClientOptions::builder().build().unwrap();
+            assert!(opts.validate().is_ok());
+            assert_eq!(opts.tls.ca, Some(PathBuf::from("/etc/nats/ca.pem")));
+            // The certificate is no use without its key
+            assert_eq!(opts.tls.client_cert, None);
+
             Ok(())
         });
     }
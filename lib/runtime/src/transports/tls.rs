@@ -0,0 +1,181 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! TLS for the TCP connections between nodes
+//!
+//! Response streams, and anything else one node sends another over plain TCP, are encrypted
+//! when [`CERT_ENV`], [`KEY_ENV`] and [`CA_ENV`] are set. Each is a PEM file, or the PEM itself.
+//! Both ends of a connection show their certificate, and each checks the other's was signed by
+//! the CA, so every node needs a certificate of that CA. Connections check the certificate is
+//! for [`SERVER_NAME_ENV`], `dynamo` by default, rather than for each node's address, so one
+//! certificate with that name can be shared by all the nodes.
+//!
+//! Every node must be configured the same way: a node with TLS can't talk to one without.
+//! The KV block manager checks for it too, and refuses to move KV blocks between workers
+//! with NIXL, which would send them unencrypted.
+
+use std::io::BufReader;
+use std::sync::{Arc, LazyLock};
+
+use anyhow::Context as _;
+use tokio::net::TcpStream;
+use tokio_rustls::{
+    rustls::{
+        crypto::ring,
+        pki_types::{CertificateDer, PrivateKeyDer, ServerName},
+        server::WebPkiClientVerifier,
+        ClientConfig, RootCertStore, ServerConfig,
+    },
+    TlsAcceptor, TlsConnector,
+};
+
+/// This node's certificate chain, its certificate first
+pub const CERT_ENV: &str = "DYN_TRANSPORT_TLS_CERT";
+
+/// The private key of the certificate
+pub const KEY_ENV: &str = "DYN_TRANSPORT_TLS_KEY";
+
+/// The certificates of the CAs every node's certificate is signed by
+pub const CA_ENV: &str = "DYN_TRANSPORT_TLS_CA";
+
+/// The name the certificates are for
+pub const SERVER_NAME_ENV: &str = "DYN_TRANSPORT_TLS_SERVER_NAME";
+
+const DEFAULT_SERVER_NAME: &str = "dynamo";
+
+/// Accepts and makes TLS connections
+pub struct TransportTls {
+    acceptor: TlsAcceptor,
+    connector: TlsConnector,
+    server_name: ServerName<'static>,
+}
+
+impl TransportTls {
+    /// From the environment, None if TLS is not configured
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let var = |name| std::env::var(name).ok().filter(|val| !val.is_empty());
+        let (cert, key, ca) = match (var(CERT_ENV), var(KEY_ENV), var(CA_ENV)) {
+            (None, None, None) => return Ok(None),
+            (Some(cert), Some(key), Some(ca)) => (cert, key, ca),
+            _ => anyhow::bail!("Transport TLS needs all of {CERT_ENV}, {KEY_ENV} and {CA_ENV}"),
+        };
+        let server_name = var(SERVER_NAME_ENV).unwrap_or_else(|| DEFAULT_SERVER_NAME.to_string());
+        let server_name = ServerName::try_from(server_name)
+            .with_context(|| format!("Invalid {SERVER_NAME_ENV}"))?;
+        let certs = load_certs(CERT_ENV, &cert)?;
+        let key = load_key(KEY_ENV, &key)?;
+        let mut roots = RootCertStore::empty();
+        for ca_cert in load_certs(CA_ENV, &ca)? {
+            roots.add(ca_cert).context(CA_ENV)?;
+        }
+        let roots = Arc::new(roots);
+
+        let provider = Arc::new(ring::default_provider());
+        let verifier =
+            WebPkiClientVerifier::builder_with_provider(roots.clone(), provider.clone()).build()?;
+        let server = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(certs.clone(), key.clone_key())
+            .context(CERT_ENV)?;
+        let client = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots)
+            .with_client_auth_cert(certs, key)
+            .context(CERT_ENV)?;
+
+        Ok(Some(TransportTls {
+            acceptor: TlsAcceptor::from(Arc::new(server)),
+            connector: TlsConnector::from(Arc::new(client)),
+            server_name,
+        }))
+    }
+
+    /// The process's configuration, read from the environment once
+    pub fn global() -> anyhow::Result<Option<&'static TransportTls>> {
+        static TLS: LazyLock<Result<Option<TransportTls>, String>> =
+            LazyLock::new(|| TransportTls::from_env().map_err(|err| format!("{err:#}")));
+        match &*TLS {
+            Ok(tls) => Ok(tls.as_ref()),
+            Err(err) => Err(anyhow::anyhow!("{err}")),
+        }
+    }
+
+    /// Finish accepting a connection with the TLS handshake
+    pub async fn accept(
+        &self,
+        stream: TcpStream,
+    ) -> std::io::Result<tokio_rustls::server::TlsStream<TcpStream>> {
+        self.acceptor.accept(stream).await
+    }
+
+    /// Finish making a connection with the TLS handshake
+    pub async fn connect(
+        &self,
+        stream: TcpStream,
+    ) -> std::io::Result<tokio_rustls::client::TlsStream<TcpStream>> {
+        self.connector
+            .connect(self.server_name.clone(), stream)
+            .await
+    }
+}
+
+/// The PEM in `val`, or in the file it names
+fn read_pem(name: &str, val: &str) -> anyhow::Result<Vec<u8>> {
+    if val.trim_start().starts_with("-----BEGIN") {
+        return Ok(val.as_bytes().to_vec());
+    }
+    std::fs::read(val).with_context(|| format!("{name} {val}"))
+}
+
+fn load_certs(name: &str, val: &str) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    let pem = read_pem(name, val)?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(pem.as_slice()))
+        .collect::<Result<Vec<_>, _>>()
+        .context(name.to_string())?;
+    if certs.is_empty() {
+        anyhow::bail!("No certificate found in {name}");
+    }
+    Ok(certs)
+}
+
+fn load_key(name: &str, val: &str) -> anyhow::Result<PrivateKeyDer<'static>> {
+    let pem = read_pem(name, val)?;
+    rustls_pemfile::private_key(&mut BufReader::new(pem.as_slice()))
+        .context(name.to_string())?
+        .ok_or_else(|| anyhow::anyhow!("No private key found in {name}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_env() {
+        temp_env::with_vars_unset([CERT_ENV, KEY_ENV, CA_ENV], || {
+            assert!(TransportTls::from_env().unwrap().is_none());
+        });
+        temp_env::with_vars([(CERT_ENV, Some("/nonexistent/cert.pem"))], || {
+            let err = TransportTls::from_env().err().unwrap();
+            assert!(err.to_string().starts_with("Transport TLS needs all of"));
+        });
+
+        // The PEM itself, or a file of it
+        let pem = "-----BEGIN CERTIFICATE-----\n";
+        assert_eq!(read_pem(CA_ENV, pem).unwrap(), pem.as_bytes());
+        let err = read_pem(CA_ENV, "/nonexistent/ca.pem").unwrap_err();
+        assert_eq!(err.to_string(), format!("{CA_ENV} /nonexistent/ca.pem"));
+    }
+}
@@ -34,7 +34,7 @@ use bytes::Bytes;
 use derive_getters::Dissolve;
 use futures::TryStreamExt;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, os::fd::FromRawFd, sync::Arc, time::Duration, vec::IntoIter};
+use std::{collections::HashMap, sync::Arc, time::Duration, vec::IntoIter};
 use tokio::{
     sync::{mpsc, Mutex},
     task::{JoinError, JoinHandle},
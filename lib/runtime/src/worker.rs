@@ -23,20 +23,27 @@
 //! The [Worker::execute] method is designed to be called once from main and will block
 //! the calling thread until the application completes or is canceled. The method initialized
 //! the signal handler used to trap `SIGINT` and `SIGTERM` signals and trigger a graceful shutdown.
+//! On Windows those are Ctrl+C and the console window closing.
 //!
-//! On termination, the user application is given a graceful shutdown period of controlled by
-//! the [DYN_WORKER_GRACEFUL_SHUTDOWN_TIMEOUT] environment variable. If the application does not
+//! On termination, running requests are first drained (see [crate::drain]) for up to
+//! [crate::drain::DYN_DRAIN_TIMEOUT] seconds. Then the user application is given a graceful
+//! shutdown period of controlled by the [DYN_WORKER_GRACEFUL_SHUTDOWN_TIMEOUT] environment
+//! variable. If the application does not
 //! shutdown in time, the worker will terminate the application with an exit code of 911.
 //!
 //! The default values of [DYN_WORKER_GRACEFUL_SHUTDOWN_TIMEOUT] differ between the development
 //! and release builds. In development, the default is [DEFAULT_GRACEFUL_SHUTDOWN_TIMEOUT_DEBUG] and
 //! in release, the default is [DEFAULT_GRACEFUL_SHUTDOWN_TIMEOUT_RELEASE].
 
+use super::drain::{self, Drain};
 use super::{error, CancellationToken, Result, Runtime, RuntimeConfig};
 
 use futures::Future;
 use once_cell::sync::OnceCell;
-use std::{sync::Mutex, time::Duration};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 use tokio::{signal, task::JoinHandle};
 
 static RT: OnceCell<tokio::runtime::Runtime> = OnceCell::new();
@@ -46,6 +53,7 @@ const SHUTDOWN_MESSAGE: &str =
     "Application received shutdown signal; attempting to gracefully shutdown";
 const SHUTDOWN_TIMEOUT_MESSAGE: &str =
     "Use DYN_WORKER_GRACEFUL_SHUTDOWN_TIMEOUT to control the graceful shutdown timeout";
+const DRAIN_TIMEOUT_MESSAGE: &str = "Use DYN_DRAIN_TIMEOUT to wait longer for them";
 
 /// Environment variable to control the graceful shutdown timeout
 pub const DYN_WORKER_GRACEFUL_SHUTDOWN_TIMEOUT: &str = "DYN_WORKER_GRACEFUL_SHUTDOWN_TIMEOUT";
@@ -119,7 +127,11 @@ impl Worker {
 
         INIT.set(Mutex::new(Some(secondary.spawn(async move {
             // start signal handler
-            tokio::spawn(signal_handler(runtime.cancellation_token.clone()));
+            tokio::spawn(signal_handler(
+                runtime.cancellation_token.clone(),
+                runtime.drain(),
+                drain::timeout_from_env(),
+            ));
 
             let cancel_token = runtime.child_token();
             let (mut app_tx, app_rx) = tokio::sync::oneshot::channel::<()>();
@@ -172,36 +184,64 @@ impl Worker {
             .take()
             .expect("Application initialized; but another thread is awaiting it; Worker.execute() can only be called once");
 
-        secondary.block_on(task)??;
+        let result = secondary.block_on(task);
+        crate::telemetry::shutdown();
+        result??;
         local_runtime.shutdown();
         Ok(())
     }
 }
 
-/// Catch signals and trigger a shutdown
-async fn signal_handler(cancel_token: CancellationToken) -> Result<()> {
-    let ctrl_c = async {
-        signal::ctrl_c().await?;
-        anyhow::Ok(())
-    };
-
-    let sigterm = async {
-        signal::unix::signal(signal::unix::SignalKind::terminate())?
-            .recv()
-            .await;
-        anyhow::Ok(())
-    };
+/// Catch signals and trigger a shutdown. We drain first: services stop taking requests, and
+/// we wait up to `drain_timeout` for the running ones. A second signal skips the wait.
+async fn signal_handler(
+    cancel_token: CancellationToken,
+    drain: Arc<Drain>,
+    drain_timeout: Duration,
+) -> Result<()> {
+    let mut terminate = Terminate::new()?;
 
     tokio::select! {
-        _ = ctrl_c => {
+        _ = signal::ctrl_c() => {
             tracing::info!("Ctrl+C received, starting graceful shutdown");
         },
-        _ = sigterm => {
-            tracing::info!("SIGTERM received, starting graceful shutdown");
+        _ = terminate.recv() => {
+            tracing::info!("{} received, starting graceful shutdown", Terminate::NAME);
         },
         _ = cancel_token.cancelled() => {
             tracing::debug!("CancellationToken triggered; shutting down");
+            return Ok(());
+        },
+    }
+
+    // stop taking requests, let the running ones finish
+    drain.start();
+    if drain.in_flight() > 0 {
+        tracing::info!(
+            "Waiting up to {}s for {} running requests to finish",
+            drain_timeout.as_secs(),
+            drain.in_flight()
+        );
+    }
+    tokio::select! {
+        left = drain.wait_idle(drain_timeout) => {
+            if left > 0 {
+                tracing::warn!(
+                    "{left} requests still running after {}s; {DRAIN_TIMEOUT_MESSAGE}",
+                    drain_timeout.as_secs()
+                );
+            }
+        },
+        _ = signal::ctrl_c() => {
+            tracing::info!("Ctrl+C received again, not waiting for running requests");
+        },
+        _ = terminate.recv() => {
+            tracing::info!(
+                "{} received again, not waiting for running requests",
+                Terminate::NAME
+            );
         },
+        _ = cancel_token.cancelled() => {},
     }
 
     // trigger a shutdown
@@ -209,3 +249,36 @@ async fn signal_handler(cancel_token: CancellationToken) -> Result<()> {
 
     Ok(())
 }
+
+/// How the OS asks a process to stop: SIGTERM, or on Windows its console closing
+#[cfg(unix)]
+struct Terminate(signal::unix::Signal);
+
+#[cfg(unix)]
+impl Terminate {
+    const NAME: &'static str = "SIGTERM";
+
+    fn new() -> std::io::Result<Self> {
+        signal::unix::signal(signal::unix::SignalKind::terminate()).map(Terminate)
+    }
+
+    async fn recv(&mut self) -> Option<()> {
+        self.0.recv().await
+    }
+}
+
+#[cfg(windows)]
+struct Terminate(signal::windows::CtrlClose);
+
+#[cfg(windows)]
+impl Terminate {
+    const NAME: &'static str = "Console close";
+
+    fn new() -> std::io::Result<Self> {
+        signal::windows::ctrl_close().map(Terminate)
+    }
+
+    async fn recv(&mut self) -> Option<()> {
+        self.0.recv().await
+    }
+}